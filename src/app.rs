@@ -1,30 +1,49 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
 
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, bail, Context, Result};
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
 
+use crate::autotag::{self, AutoTagRule};
+use crate::bulk::{self, BulkChange, BulkEdit};
+use crate::bundle;
 use crate::clipboard;
-use crate::config::ConfigStore;
-use crate::model::{Config, Host};
+use crate::config::{self, ConfigStore, MigrationReport, SaveOutcome};
+use crate::debuglog;
+use crate::export;
+use crate::failure::{self, Diagnosis};
+use crate::freeze;
+use crate::hostkey;
+use crate::hosttime;
+use crate::model::{
+    format_vars, is_var_name, parse_vars, Config, FieldChange, Host, HostRole, IpFamily, KeyAction,
+    KeyMap,
+};
+use crate::net::{self, LocalResolver, PortProbe, PortProber, Resolution};
+use crate::overlay;
+use crate::preflight::{self, KeyFile, PortState};
+use crate::privacy::Masker;
+use crate::problems::{self, Problem, ProblemKind};
+use crate::program;
+use crate::series;
 use crate::ssh;
-
-#[derive(Clone, Copy, Debug)]
-pub enum StatusKind {
-    Info,
-    Warn,
-    Error,
-}
-
-pub struct StatusLine {
-    pub text: String,
-    pub kind: StatusKind,
-}
+use crate::ssh_config;
+use crate::state::{AppState, HostOrder, SearchScope, StateStore};
+use crate::status::{StatusKind, StatusLine, StatusManager};
+use crate::text;
+use crate::watch::{self, Watcher};
 
 #[derive(Clone, Copy, Debug)]
 pub enum FormKind {
@@ -34,30 +53,293 @@ pub enum FormKind {
 
 #[derive(Clone, Debug)]
 pub enum ConfirmKind {
-    Connect { extra_cmd: String },
-    Delete,
+    /// `request_tty` starts from the host's setting and can be changed for
+    /// this connection only.
+    Connect {
+        extra_cmd: String,
+        request_tty: Option<bool>,
+        /// Set by the first Enter inside the host's freeze window; the
+        /// second one connects.
+        freeze_armed: bool,
+    },
+    ConnectAs {
+        user: String,
+    },
+    Rename {
+        name: String,
+    },
+    /// `dependents` are the hosts that use this one as their bastion; the
+    /// deletion clears their `bastion` too.
+    Delete {
+        dependents: Vec<String>,
+    },
+    /// Undo would restore a snapshot from before the config was last read
+    /// from disk; `changes` lists what it would revert.
+    UndoPastReload {
+        changes: Vec<String>,
+    },
+    /// `Y` on a host with an IPv4 address: whether the copy should get the
+    /// next address too, before it opens in the Add form.
+    SeriesAddress {
+        next: Box<Host>,
+        address: String,
+    },
+    /// Hosts others use as their bastion but whose role was never set:
+    /// whether to mark them as jump hosts. Asked once; either answer is
+    /// saved.
+    SuggestJumpHosts {
+        ids: Vec<String>,
+    },
+    /// Privacy mode is on but the action hands out the real values.
+    Unmasked(UnmaskedAction),
+    /// Plain Enter on a host whose stored `remote_command` would run.
+    RemoteCommand {
+        command: String,
+    },
+    /// `b` on a host behind several jump hosts: which one to connect to.
+    /// `hops` are canonical ids in the order ssh visits them.
+    PickBastion {
+        hops: Vec<String>,
+        selected: usize,
+    },
+}
+
+/// Actions that ignore privacy mode, confirmed first while it is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnmaskedAction {
+    CopyConnection,
+    ExportBundle,
+}
+
+/// An undo snapshot, tagged with the config generation it was taken in.
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub config: Config,
+    pub generation: u64,
+}
+
+/// An edit to the host list. Every one goes through `App::apply_change`,
+/// so nothing reaches the file without passing validation first.
+#[derive(Clone, Debug)]
+pub enum Change {
+    Add(Host),
+    /// Replaces the host at `index`; a new name is carried into bastion
+    /// references (see `Config::rename_host`).
+    Edit {
+        index: usize,
+        host: Host,
+    },
+    Remove(usize),
+    /// Swaps in a whole new host list (bulk edits).
+    ReplaceHosts(Vec<Host>),
+    Rename {
+        index: usize,
+        name: String,
+    },
+    SetRoles {
+        ids: Vec<String>,
+        role: HostRole,
+    },
+    /// Sets the config-wide `default_key`; `None` leaves keys to ssh.
+    SetDefaultKey(Option<String>),
+    /// Swaps in a whole config read from its JSON copy (`I`); the
+    /// session's overlay hosts stay.
+    Import(Config),
+    /// Goes back to an undo snapshot; the caller takes it off the history.
+    Restore(Config),
+}
+
+impl Change {
+    fn apply(self, config: &mut Config) -> Result<()> {
+        match self {
+            Change::Add(host) => config.hosts.push(host),
+            Change::Edit { index, host } => {
+                if config.hosts[index].name != host.name {
+                    config.rename_host(index, &host.name)?;
+                }
+                config.hosts[index] = host;
+            }
+            Change::Remove(index) => {
+                config.hosts.remove(index);
+            }
+            Change::ReplaceHosts(hosts) => config.hosts = hosts,
+            Change::Rename { index, name } => {
+                config.rename_host(index, &name)?;
+            }
+            Change::SetRoles { ids, role } => {
+                for host in &mut config.hosts {
+                    if ids.contains(&host.id()) {
+                        host.role = Some(role);
+                    }
+                }
+            }
+            Change::SetDefaultKey(key) => config.default_key = key,
+            Change::Import(mut imported) => {
+                let profile = config.active_profile.clone();
+                config.switch_profile(None)?;
+                let overlays = config.hosts.iter().filter(|h| h.overlay.is_some());
+                imported.hosts.extend(overlays.cloned());
+                // Stays on the profile shown when the import has it too.
+                let _ = imported.switch_profile(profile.as_deref());
+                *config = imported;
+            }
+            Change::Restore(mut snapshot) => {
+                // Connection times aren't edits; going back keeps them.
+                for host in &mut snapshot.hosts {
+                    let id = host.id();
+                    if let Some(current) = config.hosts.iter().find(|h| h.id() == id) {
+                        host.last_connected = current.last_connected;
+                        host.connect_count = current.connect_count;
+                    }
+                }
+                *config = snapshot;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How the last session to a host failed, kept until one ends cleanly.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LastError {
+    pub message: String,
+    /// What ssh's stderr says went wrong, when it could be read.
+    pub diagnosis: Option<Diagnosis>,
 }
 
 #[derive(Clone, Debug)]
 pub struct FormField {
     pub label: &'static str,
     pub value: String,
+    /// Byte offset into `value`, always on a grapheme boundary.
     pub cursor: usize,
 }
 
+impl FormField {
+    fn insert(&mut self, c: char) {
+        self.value.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Removes the whole grapheme before the cursor, accents included.
+    fn delete_back(&mut self) {
+        let start = text::prev_boundary(&self.value, self.cursor);
+        self.value.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+}
+
 const FIELD_SSH_COMMAND: &str = "SSH command";
 const FIELD_NAME: &str = "Name";
+const FIELD_GROUP: &str = "Group";
 const FIELD_HOST: &str = "Host / IP";
 const FIELD_USER: &str = "User";
 const FIELD_PORT: &str = "Port";
 const FIELD_KEYS: &str = "SSH keys";
 const FIELD_BASTION: &str = "Bastion";
+const FIELD_PROXY_COMMAND: &str = "Proxy command";
+const FIELD_ROLE: &str = "Role";
 const FIELD_TAGS: &str = "Tags (comma)";
 const FIELD_OPTIONS: &str = "Options";
+const FIELD_LOCAL_FORWARDS: &str = "Local fwd (-L)";
+const FIELD_REMOTE_FORWARDS: &str = "Remote fwd (-R)";
 const FIELD_REMOTE_COMMAND: &str = "Remote command";
+const FIELD_COMMAND_PRESETS: &str = "Command presets";
+const FIELD_VARS: &str = "Vars (k=v)";
+const FIELD_SET_ENV: &str = "Env (SetEnv)";
+const FIELD_REQUEST_TTY: &str = "Request TTY";
+const FIELD_IP_FAMILY: &str = "IP family";
 const FIELD_PREFER_PUBLIC_KEY: &str = "Prefer publickey";
+const FIELD_MULTIPLEX: &str = "Multiplex";
+const FIELD_FORWARD_AGENT: &str = "Forward agent";
+const FIELD_FORWARD_X11: &str = "Forward X11";
+const FIELD_COMMAND_TEMPLATE: &str = "Command template";
+const FIELD_MAINTENANCE: &str = "Maintenance";
+const FIELD_FREEZE_WINDOW: &str = "Freeze window";
+const FIELD_TIMEZONE: &str = "Timezone";
+const FIELD_QUIET_HOURS: &str = "Quiet hours";
+const FIELD_PINNED_FINGERPRINT: &str = "Pinned fingerprint";
+const FIELD_PINNED_HOSTKEY: &str = "Pinned host key";
 const FIELD_DESCRIPTION: &str = "Description";
 
+/// Saving an edit that changes more fields than this, or the address, asks
+/// for a review of the changes first.
+const REVIEW_CHANGES_OVER: usize = 3;
+
+/// Pushes `entry`, dropping the oldest beyond `limit` (the config's
+/// `history_limit`). Steps kept under a higher limit only go once
+/// something new is pushed.
+fn push_capped(stack: &mut Vec<HistoryEntry>, entry: HistoryEntry, limit: usize) {
+    stack.push(entry);
+    let excess = stack.len().saturating_sub(limit);
+    stack.drain(..excess);
+}
+
+/// What restoring `snapshot` over `current` would do, one line per host.
+fn undo_changes(current: &Config, snapshot: &Config) -> Vec<String> {
+    let mut changes = Vec::new();
+    for host in &current.hosts {
+        let id = host.id();
+        match snapshot.hosts.iter().find(|h| h.id() == id) {
+            None => changes.push(format!("removes {id}")),
+            Some(old) => {
+                let fields: Vec<&str> = host.diff(old).iter().map(|c| c.field).collect();
+                if !fields.is_empty() {
+                    changes.push(format!("{id}: reverts {}", fields.join(", ")));
+                }
+            }
+        }
+    }
+    for host in &snapshot.hosts {
+        let id = host.id();
+        if !current.hosts.iter().any(|h| h.id() == id) {
+            changes.push(format!("restores {id}"));
+        }
+    }
+    if changes.is_empty() && current != snapshot {
+        changes.push("reverts top-level settings".into());
+    }
+    changes
+}
+
+/// The `Host::diff` key of the value a form field edits.
+pub fn field_diff_key(label: &str) -> Option<&'static str> {
+    Some(match label {
+        FIELD_NAME => "name",
+        FIELD_GROUP => "group",
+        FIELD_HOST => "host",
+        FIELD_USER => "user",
+        FIELD_PORT => "port",
+        FIELD_KEYS => "key_paths",
+        FIELD_BASTION => "bastion",
+        FIELD_PROXY_COMMAND => "proxy_command",
+        FIELD_ROLE => "role",
+        FIELD_TAGS => "tags",
+        FIELD_OPTIONS => "options",
+        FIELD_LOCAL_FORWARDS => "local_forwards",
+        FIELD_REMOTE_FORWARDS => "remote_forwards",
+        FIELD_REMOTE_COMMAND => "remote_command",
+        FIELD_COMMAND_PRESETS => "command_presets",
+        FIELD_VARS => "vars",
+        FIELD_SET_ENV => "set_env",
+        FIELD_REQUEST_TTY => "request_tty",
+        FIELD_IP_FAMILY => "ip_family",
+        FIELD_PREFER_PUBLIC_KEY => "prefer_public_key_auth",
+        FIELD_MULTIPLEX => "multiplex",
+        FIELD_FORWARD_AGENT => "forward_agent",
+        FIELD_FORWARD_X11 => "forward_x11",
+        FIELD_COMMAND_TEMPLATE => "command_template",
+        FIELD_MAINTENANCE => "maintenance",
+        FIELD_FREEZE_WINDOW => "freeze_window",
+        FIELD_TIMEZONE => "timezone",
+        FIELD_QUIET_HOURS => "quiet_hours",
+        FIELD_PINNED_FINGERPRINT => "pinned_fingerprint",
+        FIELD_PINNED_HOSTKEY => "pinned_hostkey",
+        FIELD_DESCRIPTION => "description",
+        _ => return None,
+    })
+}
+
 #[derive(Clone, Debug)]
 pub struct BastionDropdownState {
     pub search_filter: String,
@@ -85,13 +367,13 @@ impl BastionDropdownState {
                 .hosts
                 .iter()
                 .enumerate()
-                .filter(|(_, h)| self.exclude_host.as_deref() != Some(&h.name))
+                .filter(|(_, h)| self.exclude_host != Some(h.id()))
                 .map(|(i, _)| i)
                 .collect();
         } else {
             let mut scored: Vec<(i64, usize)> = Vec::new();
             for (i, host) in config.hosts.iter().enumerate() {
-                if self.exclude_host.as_deref() == Some(&host.name) {
+                if self.exclude_host == Some(host.id()) {
                     continue;
                 }
                 let haystack = format!(
@@ -105,9 +387,12 @@ impl BastionDropdownState {
                     scored.push((score, i));
                 }
             }
-            scored.sort_by(|a, b| b.0.cmp(&a.0));
+            scored.sort_by_key(|entry| std::cmp::Reverse(entry.0));
             self.filtered_indices = scored.into_iter().map(|(_, i)| i).collect();
         }
+        // Jump hosts first, keeping config or score order within each part.
+        self.filtered_indices
+            .sort_by_key(|&i| !config.hosts[i].is_jump_host());
         // Reset selection to top when filter changes
         self.selected = 0;
         if self.selected >= self.filtered_indices.len() {
@@ -158,6 +443,7 @@ impl KeySelectorState {
         }
     }
 
+    #[cfg(test)]
     fn current_selected(&self) -> bool {
         self.available_keys
             .get(self.selected)
@@ -185,24 +471,54 @@ pub struct FormState {
     pub index: usize,
     pub bastion_dropdown: Option<BastionDropdownState>,
     pub key_selector: Option<KeySelectorState>,
-    editing_host_name: Option<String>,
+    /// Problems found in a pasted ssh_config block, shown under the command.
+    pub paste_warnings: Vec<String>,
+    /// Fields the user edited by hand in the Add form. The SSH command no
+    /// longer fills them until they are cleared again.
+    pub manual: BTreeSet<&'static str>,
+    /// SSH command text as of the last sync, so unchanged text isn't parsed
+    /// again.
+    synced_command: String,
+    /// The spec last applied to the fields; an identical parse result leaves
+    /// them alone.
+    applied_spec: Option<SshSpec>,
+    /// The host as it was when the Edit form opened.
+    original: Option<Host>,
+    /// Changes awaiting confirmation before an edit is saved.
+    pub review: Option<Vec<FieldChange>>,
+    /// Tags the `[[auto_tags]]` rules give the Add form's host as of the
+    /// last `sync_auto_tags`.
+    pub auto_tags: Vec<String>,
+    /// Why the last save failed; cleared once the form is edited.
+    pub error: Option<FormError>,
+    editing_host_id: Option<String>,
+}
+
+/// A failed save as the form shows it: the field at fault is marked, and
+/// an error with no field (a bastion cycle, a duplicate name) gets a line
+/// of its own in the form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormError {
+    pub field: Option<&'static str>,
+    pub message: String,
+}
+
+/// An error from `build_host` that comes from one field's value.
+#[derive(Debug, thiserror::Error)]
+#[error("{error}")]
+pub struct FieldError {
+    pub field: &'static str,
+    error: anyhow::Error,
+}
+
+/// Tags an error with the form field it came from.
+fn in_field(field: &'static str) -> impl FnOnce(anyhow::Error) -> anyhow::Error {
+    move |error| FieldError { field, error }.into()
 }
 
 impl FormState {
     pub fn new(kind: FormKind, host: Option<&Host>, config: &Config) -> Self {
-        let blank = Host {
-            name: "".into(),
-            address: "".into(),
-            user: None,
-            port: None,
-            key_paths: Vec::new(),
-            tags: Vec::new(),
-            options: Vec::new(),
-            remote_command: None,
-            description: None,
-            bastion: None,
-            prefer_public_key_auth: false,
-        };
+        let blank = Host::default();
         let h = host.unwrap_or(&blank);
         let mut fields = Vec::new();
 
@@ -221,6 +537,7 @@ impl FormState {
         }
 
         let name = h.name.clone();
+        let group = h.group.clone().unwrap_or_default();
         let host_addr = h.address.clone();
         let user = h.user.clone().unwrap_or_default();
         let port = h.port.map(|p| p.to_string()).unwrap_or_default();
@@ -230,6 +547,8 @@ impl FormState {
             h.key_paths.join(", ")
         };
         let bastion = h.bastion.clone().unwrap_or_default();
+        let proxy_command = h.proxy_command.clone().unwrap_or_default();
+        let role = h.role.map(|r| r.label().to_string()).unwrap_or_default();
         let tags = if h.tags.is_empty() {
             "".into()
         } else {
@@ -240,9 +559,26 @@ impl FormState {
         } else {
             h.options.join(" ")
         };
+        let local_forwards = h.local_forwards.join(", ");
+        let command_presets = h.command_presets.join(", ");
+        let set_env = format_set_env(&h.set_env);
+        let remote_forwards = h.remote_forwards.join(", ");
         let remote = h.remote_command.clone().unwrap_or_default();
+        let vars = format_vars(&h.vars);
+        let maintenance = h.maintenance.clone().unwrap_or_default();
+        let freeze_window = h.freeze_window.clone().unwrap_or_default();
+        let timezone = h.timezone.clone().unwrap_or_default();
+        let quiet_hours = h.quiet_hours.clone().unwrap_or_default();
+        let pinned_fingerprint = h.pinned_fingerprint.clone().unwrap_or_default();
+        let pinned_hostkey = h.pinned_hostkey.clone().unwrap_or_default();
         let desc = h.description.clone().unwrap_or_default();
+        let request_tty = tty_field_value(h.request_tty);
+        let ip_family = family_field_value(h.ip_family);
         let prefer_public_key = bool_field_value(h.prefer_public_key_auth);
+        let multiplex = bool_field_value(h.multiplex);
+        let forward_agent = bool_field_value(h.forward_agent);
+        let forward_x11 = bool_field_value(h.forward_x11);
+        let template = h.command_template.clone().unwrap_or_default();
 
         fields.extend([
             FormField {
@@ -250,6 +586,11 @@ impl FormState {
                 value: name.clone(),
                 cursor: name.len(),
             },
+            FormField {
+                label: FIELD_GROUP,
+                value: group.clone(),
+                cursor: group.len(),
+            },
             FormField {
                 label: FIELD_HOST,
                 value: host_addr.clone(),
@@ -275,6 +616,16 @@ impl FormState {
                 value: bastion.clone(),
                 cursor: bastion.len(),
             },
+            FormField {
+                label: FIELD_PROXY_COMMAND,
+                value: proxy_command.clone(),
+                cursor: proxy_command.len(),
+            },
+            FormField {
+                label: FIELD_ROLE,
+                value: role.clone(),
+                cursor: role.len(),
+            },
             FormField {
                 label: FIELD_TAGS,
                 value: tags.clone(),
@@ -285,16 +636,101 @@ impl FormState {
                 value: options.clone(),
                 cursor: options.len(),
             },
+            FormField {
+                label: FIELD_LOCAL_FORWARDS,
+                value: local_forwards.clone(),
+                cursor: local_forwards.len(),
+            },
+            FormField {
+                label: FIELD_REMOTE_FORWARDS,
+                value: remote_forwards.clone(),
+                cursor: remote_forwards.len(),
+            },
             FormField {
                 label: FIELD_REMOTE_COMMAND,
                 value: remote.clone(),
                 cursor: remote.len(),
             },
+            FormField {
+                label: FIELD_COMMAND_PRESETS,
+                value: command_presets.clone(),
+                cursor: command_presets.len(),
+            },
+            FormField {
+                label: FIELD_VARS,
+                value: vars.clone(),
+                cursor: vars.len(),
+            },
+            FormField {
+                label: FIELD_SET_ENV,
+                value: set_env.clone(),
+                cursor: set_env.len(),
+            },
+            FormField {
+                label: FIELD_REQUEST_TTY,
+                value: request_tty.clone(),
+                cursor: request_tty.len(),
+            },
+            FormField {
+                label: FIELD_IP_FAMILY,
+                value: ip_family.clone(),
+                cursor: ip_family.len(),
+            },
             FormField {
                 label: FIELD_PREFER_PUBLIC_KEY,
                 value: prefer_public_key.clone(),
                 cursor: prefer_public_key.len(),
             },
+            FormField {
+                label: FIELD_MULTIPLEX,
+                value: multiplex.clone(),
+                cursor: multiplex.len(),
+            },
+            FormField {
+                label: FIELD_FORWARD_AGENT,
+                value: forward_agent.clone(),
+                cursor: forward_agent.len(),
+            },
+            FormField {
+                label: FIELD_FORWARD_X11,
+                value: forward_x11.clone(),
+                cursor: forward_x11.len(),
+            },
+            FormField {
+                label: FIELD_COMMAND_TEMPLATE,
+                value: template.clone(),
+                cursor: template.len(),
+            },
+            FormField {
+                label: FIELD_MAINTENANCE,
+                value: maintenance.clone(),
+                cursor: maintenance.len(),
+            },
+            FormField {
+                label: FIELD_FREEZE_WINDOW,
+                value: freeze_window.clone(),
+                cursor: freeze_window.len(),
+            },
+            FormField {
+                label: FIELD_TIMEZONE,
+                value: timezone.clone(),
+                cursor: timezone.len(),
+            },
+            FormField {
+                label: FIELD_QUIET_HOURS,
+                value: quiet_hours.clone(),
+                cursor: quiet_hours.len(),
+            },
+            FormField {
+                label: FIELD_PINNED_FINGERPRINT,
+                value: pinned_fingerprint.clone(),
+                cursor: pinned_fingerprint.len(),
+            },
+            FormField {
+                label: FIELD_PINNED_HOSTKEY,
+                value: pinned_hostkey.clone(),
+                cursor: pinned_hostkey.len(),
+            },
             FormField {
                 label: FIELD_DESCRIPTION,
                 value: desc.clone(),
@@ -304,21 +740,85 @@ impl FormState {
 
         Self {
             kind,
-            fields,
             index: 0,
             bastion_dropdown: None,
             key_selector: None,
-            editing_host_name: host.map(|h| h.name.clone()),
+            paste_warnings: Vec::new(),
+            manual: BTreeSet::new(),
+            synced_command: fields
+                .first()
+                .filter(|f| f.label == FIELD_SSH_COMMAND)
+                .map(|f| f.value.clone())
+                .unwrap_or_default(),
+            applied_spec: None,
+            original: host.filter(|_| matches!(kind, FormKind::Edit)).cloned(),
+            review: None,
+            auto_tags: Vec::new(),
+            error: None,
+            fields,
+            editing_host_id: host.map(Host::id),
+        }
+    }
+
+    /// Keeps the Add form's tags in step with the `[[auto_tags]]` rules: a
+    /// tag a rule starts giving is added, one it stops giving is taken out
+    /// again, and one the user deleted stays deleted.
+    pub fn sync_auto_tags(&mut self, rules: &[AutoTagRule]) {
+        if !matches!(self.kind, FormKind::Add) || rules.is_empty() {
+            return;
+        }
+        let value = |label| self.field(label).map_or("", |f| f.value.trim());
+        let probe = Host {
+            name: value(FIELD_NAME).to_string(),
+            address: value(FIELD_HOST).to_string(),
+            user: non_empty(value(FIELD_USER)),
+            port: value(FIELD_PORT).parse().ok(),
+            ..Default::default()
+        };
+        let given = autotag::tags_for(rules, &probe);
+        if given == self.auto_tags {
+            return;
         }
+        let mut tags: Vec<String> = value(FIELD_TAGS)
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty() && (given.contains(t) || !self.auto_tags.contains(t)))
+            .collect();
+        for tag in &given {
+            if !self.auto_tags.contains(tag) && !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        self.auto_tags = given;
+        self.set_field_value(FIELD_TAGS, tags.join(", "));
     }
 
+    /// Handles a key in the form. Changes to the SSH command are picked up by
+    /// the next [`FormState::sync_command_field`], once per frame.
     pub fn handle_input(&mut self, key: KeyEvent, config: &Config) {
+        let before = self.values();
+        self.edit(key, config);
+        self.mark_manual(&before);
+        self.clear_error(&before);
+    }
+
+    fn edit(&mut self, key: KeyEvent, config: &Config) {
         let bastion_field_idx = self.field_index(FIELD_BASTION);
         let keys_field_idx = self.field_index(FIELD_KEYS);
-        let prefer_public_key_idx = self.field_index(FIELD_PREFER_PUBLIC_KEY);
         let is_bastion_field = Some(self.index) == bastion_field_idx;
         let is_keys_field = Some(self.index) == keys_field_idx;
-        let is_prefer_public_key_field = Some(self.index) == prefer_public_key_idx;
+        let bool_field = [
+            FIELD_PREFER_PUBLIC_KEY,
+            FIELD_MULTIPLEX,
+            FIELD_FORWARD_AGENT,
+            FIELD_FORWARD_X11,
+        ]
+        .into_iter()
+        .find(|label| Some(self.index) == self.field_index(label));
+        let is_request_tty_field = Some(self.index) == self.field_index(FIELD_REQUEST_TTY);
+        let is_ip_family_field = Some(self.index) == self.field_index(FIELD_IP_FAMILY);
+        let is_role_field = Some(self.index) == self.field_index(FIELD_ROLE);
+        let is_port_field = Some(self.index) == self.field_index(FIELD_PORT);
 
         if is_keys_field && self.key_selector.is_some() {
             match key.code {
@@ -386,7 +886,7 @@ impl FormState {
                         .as_ref()
                         .and_then(|dropdown| dropdown.filtered_indices.get(dropdown.selected))
                         .and_then(|idx| config.hosts.get(*idx))
-                        .map(|host| host.name.clone());
+                        .map(|host| config.display_name(host));
                     if let Some(host_name) = selected_host {
                         self.set_field_value(FIELD_BASTION, host_name);
                     }
@@ -417,10 +917,7 @@ impl FormState {
                     let mut filter = None;
                     if let Some(idx) = bastion_field_idx {
                         if let Some(f) = self.fields.get_mut(idx) {
-                            if f.cursor > 0 {
-                                f.value.remove(f.cursor - 1);
-                                f.cursor -= 1;
-                            }
+                            f.delete_back();
                             filter = Some(f.value.clone());
                         }
                     }
@@ -440,8 +937,7 @@ impl FormState {
                         let mut filter = None;
                         if let Some(idx) = bastion_field_idx {
                             if let Some(f) = self.fields.get_mut(idx) {
-                                f.value.insert(f.cursor, c);
-                                f.cursor += 1;
+                                f.insert(c);
                                 filter = Some(f.value.clone());
                             }
                         }
@@ -500,35 +996,51 @@ impl FormState {
                     }
                     return;
                 }
-                if is_prefer_public_key_field {
-                    self.toggle_bool_field(FIELD_PREFER_PUBLIC_KEY);
+                if let Some(label) = bool_field {
+                    self.toggle_bool_field(label);
+                    return;
+                }
+                if is_role_field {
+                    let next = match self.field(FIELD_ROLE).map(|f| HostRole::parse(&f.value)) {
+                        Some(Ok(Some(HostRole::Bastion))) => HostRole::Normal,
+                        _ => HostRole::Bastion,
+                    };
+                    self.set_field_value(FIELD_ROLE, next.label().to_string());
+                    return;
+                }
+                if is_request_tty_field {
+                    let current = self
+                        .field(FIELD_REQUEST_TTY)
+                        .and_then(|f| parse_tty_field(&f.value).ok())
+                        .flatten();
+                    self.set_field_value(FIELD_REQUEST_TTY, tty_field_value(next_tty(current)));
+                    return;
+                }
+                if is_ip_family_field {
+                    let current = self
+                        .field(FIELD_IP_FAMILY)
+                        .and_then(|f| IpFamily::parse(&f.value).ok())
+                        .flatten();
+                    self.set_field_value(FIELD_IP_FAMILY, family_field_value(next_family(current)));
                     return;
                 }
                 if let Some(f) = self.fields.get_mut(self.index) {
-                    f.value.insert(f.cursor, ' ');
-                    f.cursor += 1;
+                    f.insert(' ');
                 }
             }
             KeyCode::Left => {
                 if let Some(f) = self.fields.get_mut(self.index) {
-                    if f.cursor > 0 {
-                        f.cursor -= 1;
-                    }
+                    f.cursor = text::prev_boundary(&f.value, f.cursor);
                 }
             }
             KeyCode::Right => {
                 if let Some(f) = self.fields.get_mut(self.index) {
-                    if f.cursor < f.value.len() {
-                        f.cursor += 1;
-                    }
+                    f.cursor = text::next_boundary(&f.value, f.cursor);
                 }
             }
             KeyCode::Backspace => {
                 if let Some(f) = self.fields.get_mut(self.index) {
-                    if f.cursor > 0 {
-                        f.value.remove(f.cursor - 1);
-                        f.cursor -= 1;
-                    }
+                    f.delete_back();
                 }
                 if is_bastion_field {
                     let filter = self.field(FIELD_BASTION).map(|f| f.value.clone());
@@ -544,17 +1056,40 @@ impl FormState {
                 if c == ' ' {
                     return;
                 }
-                if is_prefer_public_key_field {
+                if is_request_tty_field {
+                    let setting = match c.to_ascii_lowercase() {
+                        'y' => Some(true),
+                        'n' => Some(false),
+                        'a' => None,
+                        _ => return,
+                    };
+                    self.set_field_value(FIELD_REQUEST_TTY, tty_field_value(setting));
+                    return;
+                }
+                if is_ip_family_field {
+                    let family = match c.to_ascii_lowercase() {
+                        '4' => Some(IpFamily::V4),
+                        '6' => Some(IpFamily::V6),
+                        'a' => None,
+                        _ => return,
+                    };
+                    self.set_field_value(FIELD_IP_FAMILY, family_field_value(family));
+                    return;
+                }
+                if let Some(label) = bool_field {
                     if c.eq_ignore_ascii_case(&'y') {
-                        self.set_field_value(FIELD_PREFER_PUBLIC_KEY, bool_field_value(true));
+                        self.set_field_value(label, bool_field_value(true));
                     } else if c.eq_ignore_ascii_case(&'n') {
-                        self.set_field_value(FIELD_PREFER_PUBLIC_KEY, bool_field_value(false));
+                        self.set_field_value(label, bool_field_value(false));
                     }
                     return;
                 }
+                // Too large a port still gets typed; the form shows it in red.
+                if is_port_field && !c.is_ascii_digit() {
+                    return;
+                }
                 if let Some(f) = self.fields.get_mut(self.index) {
-                    f.value.insert(f.cursor, c);
-                    f.cursor += 1;
+                    f.insert(c);
                 }
                 if is_bastion_field {
                     let filter = self.field(FIELD_BASTION).map(|f| f.value.clone());
@@ -571,15 +1106,153 @@ impl FormState {
         if let Some(f) = self.fields.get_mut(self.index) {
             f.cursor = f.cursor.min(f.value.len());
         }
-        if matches!(self.kind, FormKind::Add) && self.index == 0 {
-            if let Some(cmd_field) = self.fields.first() {
-                if let Some(spec) =
-                    non_empty(&cmd_field.value).and_then(|s| parse_ssh_spec(&s).ok())
-                {
-                    self.apply_spec(&spec);
+    }
+
+    fn values(&self) -> Vec<String> {
+        self.fields.iter().map(|f| f.value.clone()).collect()
+    }
+
+    /// Drops the save error once the field it names, or for an error with
+    /// no field any field, differs from `before`.
+    fn clear_error(&mut self, before: &[String]) {
+        let Some(error) = &self.error else {
+            return;
+        };
+        let edited = self.fields.iter().zip(before).any(|(field, old)| {
+            &field.value != old && error.field.is_none_or(|f| f == field.label)
+        });
+        if edited {
+            self.error = None;
+        }
+    }
+
+    /// Records a failed save, moving to the field it names with the cursor
+    /// at the end of its value.
+    fn fail(&mut self, err: &anyhow::Error) {
+        let field = err.downcast_ref::<FieldError>().map(|e| e.field);
+        if let Some(index) = field.and_then(|label| self.field_index(label)) {
+            self.close_inline_overlays();
+            self.index = index;
+            let field = &mut self.fields[index];
+            field.cursor = field.value.len();
+        }
+        self.error = Some(FormError {
+            field,
+            message: err.to_string(),
+        });
+    }
+
+    /// Marks structured fields whose value changed since `before` as edited
+    /// by hand; clearing a field hands it back to the SSH command.
+    fn mark_manual(&mut self, before: &[String]) {
+        if !matches!(self.kind, FormKind::Add) {
+            return;
+        }
+        for (field, old) in self.fields.iter().zip(before) {
+            if field.label == FIELD_SSH_COMMAND || &field.value == old {
+                continue;
+            }
+            if field.value.trim().is_empty() {
+                self.manual.remove(field.label);
+            } else {
+                self.manual.insert(field.label);
+            }
+        }
+    }
+
+    /// Inserts pasted text into the active field. Only the SSH command field
+    /// keeps line breaks, so it can take a whole ssh_config stanza.
+    pub fn paste(&mut self, text: &str, config: &Config) {
+        let before = self.values();
+        self.insert_paste(text, config);
+        self.mark_manual(&before);
+        self.sync_command_field();
+        self.clear_error(&before);
+    }
+
+    fn insert_paste(&mut self, text: &str, config: &Config) {
+        let toggles = [
+            self.field_index(FIELD_REQUEST_TTY),
+            self.field_index(FIELD_IP_FAMILY),
+            self.field_index(FIELD_PREFER_PUBLIC_KEY),
+            self.field_index(FIELD_MULTIPLEX),
+            self.field_index(FIELD_FORWARD_AGENT),
+            self.field_index(FIELD_FORWARD_X11),
+        ];
+        if toggles.contains(&Some(self.index)) {
+            return;
+        }
+        let multi_line = (matches!(self.kind, FormKind::Add) && self.index == 0)
+            || Some(self.index) == self.field_index(FIELD_DESCRIPTION);
+        let text = if multi_line {
+            text.replace("\r\n", "\n")
+        } else {
+            text.split_whitespace().collect::<Vec<_>>().join(" ")
+        };
+        if let Some(f) = self.fields.get_mut(self.index) {
+            let at = f.cursor.min(f.value.len());
+            f.value.insert_str(at, &text);
+            f.cursor = at + text.len();
+        }
+        if Some(self.index) == self.field_index(FIELD_BASTION) {
+            let filter = self.field(FIELD_BASTION).map(|f| f.value.clone());
+            if let (Some(dropdown), Some(filter)) = (self.bastion_dropdown.as_mut(), filter) {
+                dropdown.search_filter = filter;
+                dropdown.rebuild_filter(config);
+            }
+        }
+    }
+
+    /// Fills the fields from the Add form's SSH command, which holds either
+    /// an ssh command line or a pasted ssh_config stanza. A stanza is
+    /// replaced by the equivalent command once its fields are taken. Does
+    /// nothing unless the text changed since the last call, and returns
+    /// whether it was parsed.
+    pub fn sync_command_field(&mut self) -> bool {
+        if !matches!(self.kind, FormKind::Add) {
+            return false;
+        }
+        let Some(text) = self.field(FIELD_SSH_COMMAND).map(|f| f.value.clone()) else {
+            return false;
+        };
+        if text == self.synced_command {
+            return false;
+        }
+        self.synced_command = text.clone();
+        if ssh_config::looks_like_config(&text) {
+            if let Some(pasted) = spec_from_ssh_config(&text) {
+                self.apply_spec(&pasted.spec);
+                if let Some(name) = pasted.name {
+                    // `sshdb`'s own ssh_config export names blocks by id.
+                    match name.split_once('/') {
+                        Some((group, name)) => {
+                            self.fill(FIELD_GROUP, group.to_string());
+                            self.fill(FIELD_NAME, name.to_string());
+                        }
+                        None => self.fill(FIELD_NAME, name),
+                    }
                 }
+                let command = if pasted.spec.address.is_empty() {
+                    String::new()
+                } else {
+                    ssh_command_for(&pasted.spec)
+                };
+                self.set_field_value(FIELD_SSH_COMMAND, command.clone());
+                self.synced_command = command;
+                self.applied_spec = Some(pasted.spec);
+                self.paste_warnings = pasted.warnings;
+            }
+            return true;
+        }
+        self.paste_warnings.clear();
+        let spec = non_empty(&text).and_then(|s| parse_ssh_spec(&s).ok());
+        if let Some(spec) = spec {
+            if self.applied_spec.as_ref() != Some(&spec) {
+                self.apply_spec(&spec);
+                self.applied_spec = Some(spec);
             }
         }
+        true
     }
 
     fn next(&mut self) {
@@ -604,6 +1277,16 @@ impl FormState {
         }
     }
 
+    /// Shift+Enter (or Alt+Enter, for terminals that don't report Shift)
+    /// starts a new line in the Description field instead of saving.
+    fn takes_newline(&self, key: &KeyEvent) -> bool {
+        key.code == KeyCode::Enter
+            && key
+                .modifiers
+                .intersects(KeyModifiers::SHIFT | KeyModifiers::ALT)
+            && Some(self.index) == self.field_index(FIELD_DESCRIPTION)
+    }
+
     fn field_index(&self, label: &'static str) -> Option<usize> {
         self.fields.iter().position(|field| field.label == label)
     }
@@ -618,7 +1301,7 @@ impl FormState {
     }
 
     fn open_bastion_dropdown(&mut self, config: &Config) {
-        let mut dropdown = BastionDropdownState::new(config, self.editing_host_name.as_deref());
+        let mut dropdown = BastionDropdownState::new(config, self.editing_host_id.as_deref());
         if let Some(f) = self.field(FIELD_BASTION) {
             dropdown.search_filter = f.value.clone();
             dropdown.rebuild_filter(config);
@@ -637,38 +1320,33 @@ impl FormState {
     }
 
     pub fn build_host(&self) -> Result<Host> {
-        let cmd_idx = if matches!(self.kind, FormKind::Add) {
-            Some(0)
-        } else {
-            None
-        };
-        let mut idx = if cmd_idx.is_some() { 1 } else { 0 };
-        let name_field = self.fields[idx].value.trim();
-        idx += 1;
-        let host_field = self.fields[idx].value.trim();
-        idx += 1;
-        let user_field = self.fields[idx].value.trim();
-        idx += 1;
-        let port_field = self.fields[idx].value.trim();
-        idx += 1;
-        let keys_field = self.fields[idx].value.trim();
-        idx += 1;
-        let bastion_field = self.fields[idx].value.trim();
-        idx += 1;
-        let tags_field = self.fields[idx].value.trim();
-        idx += 1;
-        let options_field = self.fields[idx].value.trim();
-        idx += 1;
-        let remote_field = self.fields[idx].value.trim();
-        idx += 1;
-        let prefer_public_key_field = self.fields[idx].value.trim();
-        idx += 1;
-        let desc_field = self.fields[idx].value.trim();
-
-        let raw_spec = cmd_idx
-            .and_then(|i| non_empty(&self.fields[i].value))
+        let value = |label: &'static str| self.field(label).map(|f| f.value.trim()).unwrap_or("");
+        let name_field = value(FIELD_NAME);
+        let group_field = value(FIELD_GROUP);
+        let host_field = value(FIELD_HOST);
+        let user_field = value(FIELD_USER);
+        let port_field = value(FIELD_PORT);
+        let keys_field = value(FIELD_KEYS);
+        let bastion_field = value(FIELD_BASTION);
+        let tags_field = value(FIELD_TAGS);
+        let options_field = value(FIELD_OPTIONS);
+        let remote_field = value(FIELD_REMOTE_COMMAND);
+        let prefer_public_key_field = value(FIELD_PREFER_PUBLIC_KEY);
+        let request_tty =
+            parse_tty_field(value(FIELD_REQUEST_TTY)).map_err(in_field(FIELD_REQUEST_TTY))?;
+        let ip_family =
+            IpFamily::parse(value(FIELD_IP_FAMILY)).map_err(in_field(FIELD_IP_FAMILY))?;
+        let template_field = value(FIELD_COMMAND_TEMPLATE);
+        let maintenance_field = value(FIELD_MAINTENANCE);
+        let freeze_field = value(FIELD_FREEZE_WINDOW);
+        let desc_field = value(FIELD_DESCRIPTION);
+
+        let raw_spec = self
+            .field(FIELD_SSH_COMMAND)
+            .and_then(|f| non_empty(&f.value))
             .map(|s| parse_ssh_spec(&s))
-            .transpose()?;
+            .transpose()
+            .map_err(in_field(FIELD_SSH_COMMAND))?;
 
         let host_str = if !host_field.is_empty() {
             host_field.to_string()
@@ -687,14 +1365,31 @@ impl FormState {
         };
 
         if name.is_empty() || host_str.is_empty() {
-            return Err(anyhow!("name and host cannot be empty"));
+            let field = if self.field(FIELD_SSH_COMMAND).is_some() && host_field.is_empty() {
+                FIELD_SSH_COMMAND
+            } else {
+                FIELD_HOST
+            };
+            return Err(in_field(field)(anyhow!("name and host cannot be empty")));
         }
+        if name.contains('/') || group_field.contains('/') {
+            let field = if name.contains('/') {
+                FIELD_NAME
+            } else {
+                FIELD_GROUP
+            };
+            return Err(in_field(field)(anyhow!(
+                "name and group cannot contain '/'"
+            )));
+        }
+        let group = non_empty(group_field);
 
         let user = non_empty(user_field).or_else(|| raw_spec.as_ref().and_then(|s| s.user.clone()));
         let port = non_empty(port_field)
             .map(|p| p.parse::<u16>())
             .transpose()
-            .context("port must be numeric")?
+            .context("port must be numeric")
+            .map_err(in_field(FIELD_PORT))?
             .or_else(|| raw_spec.as_ref().and_then(|s| s.port));
         let key_paths = if keys_field.is_empty() {
             raw_spec
@@ -705,6 +1400,13 @@ impl FormState {
             parse_key_paths(keys_field)
         };
         let bastion = non_empty(bastion_field);
+        let proxy_command = non_empty(value(FIELD_PROXY_COMMAND));
+        if bastion.is_some() && proxy_command.is_some() {
+            return Err(in_field(FIELD_PROXY_COMMAND)(anyhow!(
+                "set either a bastion or a proxy command, not both"
+            )));
+        }
+        let role = HostRole::parse(value(FIELD_ROLE)).map_err(in_field(FIELD_ROLE))?;
         let tags = non_empty(tags_field)
             .map(|s| {
                 s.split(',')
@@ -721,7 +1423,14 @@ impl FormState {
                     .collect()
             })
             .unwrap_or_default();
+        let local_forwards =
+            parse_forwards(value(FIELD_LOCAL_FORWARDS)).map_err(in_field(FIELD_LOCAL_FORWARDS))?;
+        let remote_forwards = parse_forwards(value(FIELD_REMOTE_FORWARDS))
+            .map_err(in_field(FIELD_REMOTE_FORWARDS))?;
         let remote_command = non_empty(remote_field);
+        let command_presets = parse_presets(value(FIELD_COMMAND_PRESETS));
+        let vars = parse_vars(value(FIELD_VARS)).map_err(in_field(FIELD_VARS))?;
+        let (set_env, _) = parse_set_env(value(FIELD_SET_ENV));
         let prefer_public_key_auth = if prefer_public_key_field.is_empty() {
             raw_spec
                 .as_ref()
@@ -730,21 +1439,92 @@ impl FormState {
         } else {
             parse_bool_field(prefer_public_key_field)
         };
+        let multiplex = parse_bool_field(value(FIELD_MULTIPLEX));
+        let forward_agent = parse_bool_field(value(FIELD_FORWARD_AGENT));
+        let forward_x11 = parse_bool_field(value(FIELD_FORWARD_X11));
+        let command_template = non_empty(template_field);
+        if let Some(template) = &command_template {
+            ssh::shell_split(template)
+                .context("command template")
+                .map_err(in_field(FIELD_COMMAND_TEMPLATE))?;
+        }
+        let maintenance = non_empty(maintenance_field);
+        let freeze_window = non_empty(freeze_field);
+        if let Some(window) = &freeze_window {
+            freeze::parse(window).map_err(in_field(FIELD_FREEZE_WINDOW))?;
+        }
+        let timezone = non_empty(value(FIELD_TIMEZONE));
+        if let Some(zone) = &timezone {
+            hosttime::parse_zone(zone).map_err(in_field(FIELD_TIMEZONE))?;
+        }
+        let quiet_hours = non_empty(value(FIELD_QUIET_HOURS));
+        if let Some(hours) = &quiet_hours {
+            freeze::parse(hours)
+                .context("quiet hours")
+                .map_err(in_field(FIELD_QUIET_HOURS))?;
+        }
         let description = non_empty(desc_field);
 
-        Ok(Host {
+        let host = Host {
             name: name.to_string(),
+            group,
             address: host_str,
             user,
             port,
             key_paths,
             tags,
             options,
+            local_forwards,
+            remote_forwards,
             remote_command,
+            command_presets,
+            vars,
+            set_env,
             bastion,
+            proxy_command,
+            role,
             prefer_public_key_auth,
+            multiplex,
+            forward_agent,
+            forward_x11,
+            request_tty,
+            ip_family,
+            command_template,
+            connect_with: None,
+            alias_ok: false,
+            protected: false,
+            pinned: false,
+            password_command: None,
+            maintenance,
+            freeze_window,
+            timezone,
+            quiet_hours,
+            pinned_fingerprint: non_empty(value(FIELD_PINNED_FINGERPRINT)),
+            pinned_hostkey: non_empty(value(FIELD_PINNED_HOSTKEY)),
             description,
-        })
+            last_connected: None,
+            connect_count: 0,
+            overlay: None,
+        };
+        hostkey::validate(&host)?;
+        Ok(host)
+    }
+
+    /// What saving the Edit form would change; empty for the Add form or
+    /// while the fields don't make a valid host.
+    pub fn changes(&self) -> Vec<FieldChange> {
+        let (Some(original), Ok(mut host)) = (&self.original, self.build_host()) else {
+            return Vec::new();
+        };
+        // Not form fields; `save_host` carries them over.
+        host.alias_ok = original.alias_ok;
+        host.protected = original.protected;
+        host.pinned = original.pinned;
+        host.password_command = original.password_command.clone();
+        host.connect_with = original.connect_with.clone();
+        host.last_connected = original.last_connected;
+        host.connect_count = original.connect_count;
+        original.diff(&host)
     }
 
     fn set_field_value(&mut self, label: &str, value: String) {
@@ -762,54 +1542,72 @@ impl FormState {
         self.set_field_value(label, bool_field_value(!enabled));
     }
 
+    /// Sets a field from the SSH command unless the user edited it by hand.
+    fn fill(&mut self, label: &'static str, value: String) {
+        if !self.manual.contains(label) {
+            self.set_field_value(label, value);
+        }
+    }
+
     fn apply_spec(&mut self, spec: &SshSpec) {
-        self.set_field_value(FIELD_HOST, spec.address.clone());
+        self.fill(FIELD_HOST, spec.address.clone());
         if let Some(user) = &spec.user {
-            self.set_field_value(FIELD_USER, user.clone());
-            if self
-                .fields
-                .iter()
-                .find(|f| f.label == FIELD_NAME)
-                .map(|f| f.value.trim().is_empty())
-                .unwrap_or(false)
+            self.fill(FIELD_USER, user.clone());
+            if !spec.address.is_empty()
+                && self
+                    .fields
+                    .iter()
+                    .find(|f| f.label == FIELD_NAME)
+                    .map(|f| f.value.trim().is_empty())
+                    .unwrap_or(false)
             {
-                self.set_field_value(FIELD_NAME, format!("{user}@{}", spec.address));
+                self.fill(FIELD_NAME, format!("{user}@{}", spec.address));
             }
         } else {
-            self.set_field_value(FIELD_USER, "".into());
+            self.fill(FIELD_USER, "".into());
         }
 
         if let Some(port) = spec.port {
-            self.set_field_value(FIELD_PORT, port.to_string());
+            self.fill(FIELD_PORT, port.to_string());
         } else {
-            self.set_field_value(FIELD_PORT, "".into());
+            self.fill(FIELD_PORT, "".into());
         }
 
         if spec.key_paths.is_empty() {
-            self.set_field_value(FIELD_KEYS, "".into());
+            self.fill(FIELD_KEYS, "".into());
         } else {
-            self.set_field_value(FIELD_KEYS, spec.key_paths.join(", "));
+            self.fill(FIELD_KEYS, spec.key_paths.join(", "));
         }
 
         if !spec.options.is_empty() {
-            self.set_field_value(FIELD_OPTIONS, spec.options.join(" "));
+            self.fill(FIELD_OPTIONS, spec.options.join(" "));
         } else {
-            self.set_field_value(FIELD_OPTIONS, "".into());
+            self.fill(FIELD_OPTIONS, "".into());
         }
+        self.fill(FIELD_LOCAL_FORWARDS, spec.local_forwards.join(", "));
+        self.fill(FIELD_REMOTE_FORWARDS, spec.remote_forwards.join(", "));
         if let Some(bastion) = &spec.bastion {
-            self.set_field_value(FIELD_BASTION, bastion.clone());
+            self.fill(FIELD_BASTION, bastion.clone());
         } else {
-            self.set_field_value(FIELD_BASTION, "".into());
+            self.fill(FIELD_BASTION, "".into());
         }
+        self.fill(
+            FIELD_PROXY_COMMAND,
+            spec.proxy_command.clone().unwrap_or_default(),
+        );
+        self.fill(FIELD_SET_ENV, format_set_env(&spec.set_env));
         if let Some(remote) = &spec.remote_command {
-            self.set_field_value(FIELD_REMOTE_COMMAND, remote.clone());
+            self.fill(FIELD_REMOTE_COMMAND, remote.clone());
         } else {
-            self.set_field_value(FIELD_REMOTE_COMMAND, "".into());
+            self.fill(FIELD_REMOTE_COMMAND, "".into());
         }
-        self.set_field_value(
+        self.fill(
             FIELD_PREFER_PUBLIC_KEY,
             bool_field_value(spec.prefer_public_key_auth),
         );
+        self.fill(FIELD_IP_FAMILY, family_field_value(spec.ip_family));
+        self.fill(FIELD_FORWARD_AGENT, bool_field_value(spec.forward_agent));
+        self.fill(FIELD_FORWARD_X11, bool_field_value(spec.forward_x11));
     }
 }
 
@@ -830,6 +1628,70 @@ fn parse_key_paths(input: &str) -> Vec<String> {
         .collect()
 }
 
+/// Comma-separated `-L`/`-R` specs such as `8080:localhost:80`.
+fn parse_forwards(input: &str) -> Result<Vec<String>> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(|spec| {
+            if spec.contains(char::is_whitespace) {
+                bail!("forward '{spec}' contains spaces; separate forwards with commas");
+            }
+            Ok(spec.to_string())
+        })
+        .collect()
+}
+
+/// Whitespace-separated `KEY=value` pairs, and the words that aren't one.
+/// A value with spaces is quoted, as `format_set_env` writes it.
+fn parse_set_env(input: &str) -> (Vec<String>, Vec<String>) {
+    split_set_env(input)
+        .into_iter()
+        .partition(|var| var.split_once('=').is_some_and(|(key, _)| !key.is_empty()))
+}
+
+/// The Env field for `vars`, quoting the ones with spaces so
+/// `parse_set_env` reads them back whole.
+fn format_set_env(vars: &[String]) -> String {
+    vars.iter()
+        .map(|var| ssh::shell_quote(var))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits a SetEnv value into its variables, honouring quotes; unbalanced
+/// ones fall back to plain words rather than losing the line.
+fn split_set_env(value: &str) -> Vec<String> {
+    ssh::shell_split(value).unwrap_or_else(|_| value.split_whitespace().map(String::from).collect())
+}
+
+/// Command presets separated by commas or newlines.
+fn parse_presets(input: &str) -> Vec<String> {
+    input
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|command| !command.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// The preset after (or before) the one `current` holds, wrapping around;
+/// from a typed command, the first (or last) preset.
+fn cycle_preset(presets: &[String], current: &str, forward: bool) -> Option<String> {
+    let len = presets.len();
+    if len == 0 {
+        return None;
+    }
+    let next = match presets.iter().position(|p| p == current.trim()) {
+        Some(i) if forward => (i + 1) % len,
+        Some(i) => (i + len - 1) % len,
+        None if forward => 0,
+        None => len - 1,
+    };
+    Some(presets[next].clone())
+}
+
 fn parse_bool_field(input: &str) -> bool {
     matches!(
         input.trim().to_ascii_lowercase().as_str(),
@@ -841,40 +1703,308 @@ fn bool_field_value(enabled: bool) -> String {
     if enabled { "yes" } else { "no" }.to_string()
 }
 
-#[derive(Debug, Clone)]
-struct SshSpec {
-    address: String,
-    user: Option<String>,
-    port: Option<u16>,
-    key_paths: Vec<String>,
-    options: Vec<String>,
-    bastion: Option<String>,
-    prefer_public_key_auth: bool,
-    remote_command: Option<String>,
+fn tty_field_value(setting: Option<bool>) -> String {
+    match setting {
+        None => "auto",
+        Some(true) => "yes (-t)",
+        Some(false) => "no (-T)",
+    }
+    .to_string()
 }
 
-fn parse_ssh_spec(input: &str) -> Result<SshSpec> {
-    let mut user = None;
-    let mut port = None;
-    let mut key_paths = Vec::new();
-    let mut bastion = None;
-    let mut prefer_public_key_auth = false;
-    let mut options = Vec::new();
-    let tokens: Vec<&str> = input.split_whitespace().collect();
-    let mut i = 0usize;
-    if tokens.first() == Some(&"ssh") {
-        i += 1;
+fn parse_tty_field(input: &str) -> Result<Option<bool>> {
+    match input.split_whitespace().next().unwrap_or("") {
+        "-t" => return Ok(Some(true)),
+        "-T" => return Ok(Some(false)),
+        _ => {}
+    }
+    match input.trim().to_ascii_lowercase().as_str() {
+        "" | "auto" => Ok(None),
+        "yes" | "yes (-t)" | "true" | "on" => Ok(Some(true)),
+        "no" | "no (-t)" | "false" | "off" => Ok(Some(false)),
+        _ => bail!("request TTY must be auto, yes or no"),
     }
+}
 
-    let mut target = None;
-    // First pass: find the target (hostname)
-    while i < tokens.len() {
-        let token = tokens[i];
-        if parse_ssh_option(
-            &tokens,
-            &mut i,
-            &mut port,
-            &mut key_paths,
+fn family_field_value(family: Option<IpFamily>) -> String {
+    match family {
+        None => "any".to_string(),
+        Some(family) => format!("{} ({})", family.label(), family.flag()),
+    }
+}
+
+/// Cycles any → IPv4 → IPv6 → any.
+fn next_family(family: Option<IpFamily>) -> Option<IpFamily> {
+    match family {
+        None => Some(IpFamily::V4),
+        Some(IpFamily::V4) => Some(IpFamily::V6),
+        Some(IpFamily::V6) => None,
+    }
+}
+
+/// Cycles auto → yes → no → auto.
+pub fn next_tty(setting: Option<bool>) -> Option<bool> {
+    match setting {
+        None => Some(true),
+        Some(true) => Some(false),
+        Some(false) => None,
+    }
+}
+
+/// What an ssh_config stanza pasted into the Add form maps to.
+struct PastedStanza {
+    spec: SshSpec,
+    /// From the `Host` pattern, unless it is a wildcard.
+    name: Option<String>,
+    warnings: Vec<String>,
+}
+
+/// Directives that make no sense as a `-o` option.
+const SKIPPED_DIRECTIVES: [&str; 2] = ["match", "include"];
+
+/// Maps the first `Host` block of `text` onto the form's fields; unknown
+/// directives become `-o Key=Value` options.
+fn spec_from_ssh_config(text: &str) -> Option<PastedStanza> {
+    let parsed = ssh_config::parse(text);
+    let named = parsed
+        .stanzas
+        .iter()
+        .filter(|s| !s.patterns.is_empty())
+        .count();
+    let stanza = parsed
+        .stanzas
+        .iter()
+        .find(|s| !s.patterns.is_empty())
+        .or_else(|| parsed.stanzas.first())?;
+    let mut warnings = Vec::new();
+    let pattern = stanza.patterns.first().cloned();
+    if named > 1 {
+        warnings.push(format!(
+            "Pasted {named} Host blocks; using the first ({}).",
+            pattern.as_deref().unwrap_or_default()
+        ));
+    }
+    if stanza.patterns.len() > 1 {
+        warnings.push(format!(
+            "Host line lists {} patterns; using '{}'.",
+            stanza.patterns.len(),
+            pattern.as_deref().unwrap_or_default()
+        ));
+    }
+    let name = pattern.filter(|p| {
+        let wildcard = p.contains(['*', '?', '!']);
+        if wildcard {
+            warnings.push(format!(
+                "Host pattern '{p}' is a wildcard; not used as name."
+            ));
+        }
+        !wildcard
+    });
+
+    let mut spec = SshSpec {
+        address: String::new(),
+        user: None,
+        port: None,
+        key_paths: Vec::new(),
+        options: Vec::new(),
+        local_forwards: Vec::new(),
+        remote_forwards: Vec::new(),
+        bastion: None,
+        proxy_command: None,
+        set_env: Vec::new(),
+        prefer_public_key_auth: false,
+        forward_agent: false,
+        forward_x11: false,
+        remote_command: None,
+        ip_family: None,
+    };
+    // Like ssh, the first value of a directive wins; IdentityFile and the
+    // forwards add up.
+    let mut seen = HashSet::new();
+    for (keyword, value) in &stanza.directives {
+        let key = keyword.to_ascii_lowercase();
+        let adds_up = ["identityfile", "localforward", "remoteforward"].contains(&key.as_str());
+        if !adds_up && !seen.insert(key.clone()) {
+            continue;
+        }
+        match key.as_str() {
+            "localforward" | "remoteforward" => {
+                // `[bind:]port host:hostport` is `-L [bind:]port:host:hostport`.
+                let forward = value.split_whitespace().collect::<Vec<_>>().join(":");
+                if key == "localforward" {
+                    spec.local_forwards.push(forward);
+                } else {
+                    spec.remote_forwards.push(forward);
+                }
+            }
+            "identityfile" => spec
+                .key_paths
+                .extend(single_word(keyword, value, &mut warnings)),
+            "hostname" => {
+                spec.address = single_word(keyword, value, &mut warnings).unwrap_or_default()
+            }
+            "user" => spec.user = single_word(keyword, value, &mut warnings),
+            "port" => match value.parse::<u16>() {
+                Ok(port) => spec.port = Some(port),
+                Err(_) => warnings.push(format!("Skipped Port: '{value}' is not a port.")),
+            },
+            "addressfamily" => match IpFamily::parse(value) {
+                Ok(family) => spec.ip_family = family,
+                Err(_) => warnings.push(format!(
+                    "Skipped AddressFamily: '{value}' is not any, inet or inet6."
+                )),
+            },
+            "proxyjump" if value.eq_ignore_ascii_case("none") => {}
+            "proxyjump" => spec.bastion = single_word(keyword, value, &mut warnings),
+            "proxycommand" if value.eq_ignore_ascii_case("none") => {}
+            // The command has a field of its own, so spaces are fine.
+            "proxycommand" => spec.proxy_command = Some(value.clone()),
+            "forwardagent" => spec.forward_agent = value.eq_ignore_ascii_case("yes"),
+            "forwardx11" => spec.forward_x11 = value.eq_ignore_ascii_case("yes"),
+            "setenv" => spec.set_env.extend(split_set_env(value)),
+            _ if SKIPPED_DIRECTIVES.contains(&key.as_str()) => {
+                warnings.push(format!("Skipped {keyword}: not supported in a host entry."));
+            }
+            _ => {
+                let Some(value) = single_word(keyword, value, &mut warnings) else {
+                    continue;
+                };
+                let option = format!("{keyword}={value}");
+                if is_preferred_public_key_option(&option) {
+                    spec.prefer_public_key_auth = true;
+                } else {
+                    spec.options.extend(["-o".to_string(), option]);
+                }
+            }
+        }
+    }
+    if spec.address.is_empty() {
+        match &name {
+            Some(name) => spec.address = name.clone(),
+            None => warnings.push("No HostName in the pasted block.".into()),
+        }
+    }
+    if !parsed.skipped.is_empty() {
+        warnings.push(format!(
+            "Ignored {} unrecognised line(s).",
+            parsed.skipped.len()
+        ));
+    }
+    Some(PastedStanza {
+        spec,
+        name,
+        warnings,
+    })
+}
+
+/// Values end up in whitespace-separated fields, so spaces can't survive.
+fn single_word(keyword: &str, value: &str, warnings: &mut Vec<String>) -> Option<String> {
+    if value.contains(char::is_whitespace) {
+        warnings.push(format!("Skipped {keyword}: value contains spaces."));
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// The ssh command line `parse_ssh_spec` reads back as `spec`.
+fn ssh_command_for(spec: &SshSpec) -> String {
+    let mut parts = vec!["ssh".to_string()];
+    if let Some(port) = spec.port {
+        parts.extend(["-p".to_string(), port.to_string()]);
+    }
+    if let Some(family) = spec.ip_family {
+        parts.push(family.flag().to_string());
+    }
+    for key in &spec.key_paths {
+        parts.extend(["-i".to_string(), key.clone()]);
+    }
+    if let Some(bastion) = &spec.bastion {
+        parts.extend(["-J".to_string(), bastion.clone()]);
+    }
+    if let Some(command) = &spec.proxy_command {
+        parts.extend([
+            "-o".to_string(),
+            ssh::shell_quote(&format!("ProxyCommand={command}")),
+        ]);
+    }
+    if !spec.set_env.is_empty() {
+        parts.extend([
+            "-o".to_string(),
+            ssh::shell_quote(&format!("SetEnv={}", spec.set_env.join(" "))),
+        ]);
+    }
+    if spec.forward_agent {
+        parts.push("-A".into());
+    }
+    if spec.forward_x11 {
+        parts.push("-X".into());
+    }
+    if spec.prefer_public_key_auth {
+        parts.extend([
+            "-o".to_string(),
+            "PreferredAuthentications=publickey".into(),
+        ]);
+    }
+    parts.extend(spec.options.iter().cloned());
+    for forward in &spec.local_forwards {
+        parts.extend(["-L".to_string(), forward.clone()]);
+    }
+    for forward in &spec.remote_forwards {
+        parts.extend(["-R".to_string(), forward.clone()]);
+    }
+    parts.push(match &spec.user {
+        Some(user) => format!("{user}@{}", spec.address),
+        None => spec.address.clone(),
+    });
+    parts.join(" ")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SshSpec {
+    address: String,
+    user: Option<String>,
+    port: Option<u16>,
+    key_paths: Vec<String>,
+    options: Vec<String>,
+    local_forwards: Vec<String>,
+    remote_forwards: Vec<String>,
+    bastion: Option<String>,
+    proxy_command: Option<String>,
+    set_env: Vec<String>,
+    prefer_public_key_auth: bool,
+    forward_agent: bool,
+    forward_x11: bool,
+    remote_command: Option<String>,
+    ip_family: Option<IpFamily>,
+}
+
+fn parse_ssh_spec(input: &str) -> Result<SshSpec> {
+    let mut user = None;
+    let mut port = None;
+    let mut key_paths = Vec::new();
+    let mut bastion = None;
+    let mut prefer_public_key_auth = false;
+    let mut options = Vec::new();
+    // Quotes are honoured so `-o 'ProxyCommand=nc %h %p'` stays one word;
+    // unbalanced ones fall back to plain whitespace.
+    let words = ssh::shell_split(input)
+        .unwrap_or_else(|_| input.split_whitespace().map(String::from).collect());
+    let tokens: Vec<&str> = words.iter().map(String::as_str).collect();
+    let mut i = 0usize;
+    if tokens.first() == Some(&"ssh") {
+        i += 1;
+    }
+
+    let mut target = None;
+    // First pass: find the target (hostname)
+    while i < tokens.len() {
+        let token = tokens[i];
+        if parse_ssh_option(
+            &tokens,
+            &mut i,
+            &mut port,
+            &mut key_paths,
             &mut bastion,
             &mut prefer_public_key_auth,
             &mut options,
@@ -910,6 +2040,34 @@ fn parse_ssh_spec(input: &str) -> Result<SshSpec> {
         break;
     }
 
+    // `-4` and `-6` are kept apart from the other flags; the last one wins.
+    // `-A` and `-X` have toggles of their own; the trusted `-Y` stays an
+    // option, as the toggle would make it `-X`.
+    let mut ip_family = None;
+    let (mut forward_agent, mut forward_x11) = (false, false);
+    options.retain(|option| match option.as_str() {
+        "-4" | "-6" => {
+            ip_family = IpFamily::parse(option).ok().flatten();
+            false
+        }
+        "-A" => {
+            forward_agent = true;
+            false
+        }
+        "-X" => {
+            forward_x11 = true;
+            false
+        }
+        _ => true,
+    });
+    let (local_forwards, remote_forwards) = take_forwards(&mut options);
+    // The last ProxyCommand wins, as it has a field of its own.
+    let proxy_command = take_option(&mut options, "ProxyCommand").pop();
+    let set_env = take_option(&mut options, "SetEnv")
+        .iter()
+        .flat_map(|vars| split_set_env(vars))
+        .collect();
+
     let mut addr = target.clone();
     if let Some((u, h)) = target.split_once('@') {
         user = Some(u.to_string());
@@ -922,8 +2080,15 @@ fn parse_ssh_spec(input: &str) -> Result<SshSpec> {
         port,
         key_paths,
         options,
+        local_forwards,
+        remote_forwards,
         bastion,
+        proxy_command,
+        set_env,
         prefer_public_key_auth,
+        forward_agent,
+        forward_x11,
+        ip_family,
         remote_command: if let Some(start) = remote_start {
             Some(tokens[start..].join(" "))
         } else {
@@ -932,6 +2097,73 @@ fn parse_ssh_spec(input: &str) -> Result<SshSpec> {
     })
 }
 
+/// Moves `-L`/`-R` forwards (`-L spec` or `-Lspec`) out of `options`, as
+/// `(local, remote)`. A flag missing its spec stays an option.
+fn take_forwards(options: &mut Vec<String>) -> (Vec<String>, Vec<String>) {
+    let (mut local, mut remote) = (Vec::new(), Vec::new());
+    let mut kept = Vec::new();
+    let mut args = std::mem::take(options).into_iter().peekable();
+    while let Some(arg) = args.next() {
+        let forwards = match arg.get(..2) {
+            Some("-L") => &mut local,
+            Some("-R") => &mut remote,
+            _ => {
+                kept.push(arg);
+                continue;
+            }
+        };
+        match &arg[2..] {
+            "" => match args.next_if(|next| !next.starts_with('-')) {
+                Some(spec) => forwards.push(spec),
+                None => kept.push(arg),
+            },
+            spec => forwards.push(spec.to_string()),
+        }
+    }
+    *options = kept;
+    (local, remote)
+}
+
+/// Moves every `-o Keyword=…` (or `-oKeyword=…`) for `keyword` out of
+/// `options` and returns their values in order, for options that have a
+/// field of their own.
+fn take_option(options: &mut Vec<String>, keyword: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut kept = Vec::new();
+    let mut args = std::mem::take(options).into_iter().peekable();
+    while let Some(arg) = args.next() {
+        let found = match arg.as_str() {
+            "-o" => args
+                .next_if(|next| option_value(next, keyword).is_some())
+                .and_then(|next| option_value(&next, keyword)),
+            other => other
+                .strip_prefix("-o")
+                .and_then(|option| option_value(option, keyword)),
+        };
+        match found {
+            Some(found) => values.push(found),
+            None => kept.push(arg),
+        }
+    }
+    *options = kept;
+    values
+}
+
+/// The value of a `Keyword=…` or `Keyword …` option.
+fn option_value(option: &str, keyword: &str) -> Option<String> {
+    let written = option.get(..keyword.len())?;
+    if !written.eq_ignore_ascii_case(keyword) {
+        return None;
+    }
+    let rest = &option[keyword.len()..];
+    if !rest.starts_with(['=', ' ', '\t']) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let value = rest.strip_prefix('=').unwrap_or(rest).trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
 fn parse_ssh_option(
     tokens: &[&str],
     i: &mut usize,
@@ -987,6 +2219,11 @@ fn parse_ssh_option(
             }
             true
         }
+        // Flags without a value; the target may follow directly.
+        "-4" | "-6" | "-A" | "-X" | "-Y" => {
+            options.push(token.to_string());
+            true
+        }
         other if other.starts_with('-') => {
             options.push(other.to_string());
             if let Some(next) = generic_ssh_option_arg(tokens, *i) {
@@ -1021,6 +2258,33 @@ fn is_preferred_public_key_option(option: &str) -> bool {
         .eq_ignore_ascii_case("PreferredAuthentications=publickey")
 }
 
+/// Tab completion for the connect-as prompt: the next known user sharing the
+/// typed prefix, cycling when the input already is a known user.
+fn complete_user(known: &[String], input: &str) -> String {
+    if let Some(pos) = known.iter().position(|u| u == input) {
+        return known[(pos + 1) % known.len()].clone();
+    }
+    known
+        .iter()
+        .find(|u| u.starts_with(input))
+        .cloned()
+        .unwrap_or_else(|| input.to_string())
+}
+
+pub(crate) fn search_haystack(host: &Host, scope: SearchScope) -> String {
+    let mut parts = vec![host.id()];
+    if matches!(scope, SearchScope::Targets | SearchScope::Everything) {
+        parts.push(host.address.clone());
+        parts.extend(host.user.clone());
+        parts.extend(host.tags.iter().cloned());
+    }
+    if matches!(scope, SearchScope::Everything) {
+        parts.extend(host.description.clone());
+        parts.extend(host.remote_command.clone());
+    }
+    parts.join(" ")
+}
+
 fn discover_ssh_keys() -> Vec<String> {
     let Some(home) = std::env::var_os("HOME").map(PathBuf::from) else {
         return Vec::new();
@@ -1062,16 +2326,171 @@ pub enum Mode {
     Form,
     Confirm,
     QuickConnect,
+    Watch,
+    /// Search box and results only; see `handle_launcher`.
+    Launcher,
+    /// List of `problems::find` results; see `handle_problems`.
+    Problems,
+    /// How the selected host's command is put together; see `ssh::explain`.
+    Explain,
+    /// The selected host's whole command, one argument per line; see
+    /// `ssh::command_lines`.
+    FullCommand,
+    /// Export or import a migration bundle; see `handle_bundle`.
+    Bundle,
+    /// One field change for every marked host; see `handle_bulk`.
+    Bulk,
+    /// Which hosts each `[[auto_tags]]` rule matches, for writing them.
+    Rules,
+    /// Picking a tag the list must carry; see `handle_tag_filter`.
+    TagFilter,
+    /// Paths for copying a file to the selected host; see `handle_transfer`.
+    Transfer,
+    /// Config-wide settings; see `handle_settings`.
+    Settings,
+}
+
+/// The `O` settings modal, as edited so far.
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    /// A key path, `agent`, or empty for ssh's own defaults.
+    pub default_key: String,
+    /// What Tab swaps back in after switching to `agent`.
+    other_key: String,
+}
+
+/// Typed text narrowing the known tags, and the highlighted one.
+#[derive(Clone, Debug, Default)]
+pub struct TagPicker {
+    pub query: String,
+    pub selected: usize,
+}
+
+/// One host's key files as last looked up on disk; see `App::missing_keys`.
+struct KeyFileCheck {
+    /// The expanded paths looked up.
+    paths: Vec<String>,
+    /// The configured paths among them that weren't there.
+    missing: Vec<String>,
+}
+
+/// A running `H` sweep: the hosts whose answer hasn't been recorded yet.
+struct HealthSweep {
+    /// Canonical id, address and port of each.
+    pending: Vec<(String, String, u16)>,
+    total: usize,
+    /// Hosts left out because they are reached through something else.
+    skipped: usize,
+}
+
+/// What ssh reaches `host` through, when a port probe from here would say
+/// nothing about it.
+fn reached_through(host: &Host) -> Option<String> {
+    match (&host.bastion, &host.proxy_command, &host.command_template) {
+        (Some(bastion), _, _) => Some(format!("bastion {bastion}")),
+        (_, Some(_), _) => Some("its proxy command".to_string()),
+        (_, _, Some(_)) => Some("its command template".to_string()),
+        _ => None,
+    }
+}
+
+/// What a search scores against, kept while a search is typed so that each
+/// keystroke only scores; `rebuild_filter` drops it.
+#[derive(Default)]
+struct SearchCache {
+    scope: Option<SearchScope>,
+    /// `search_haystack` of each host, by config index.
+    haystacks: Vec<String>,
+    /// The last search text and the hosts it matched, in config order.
+    last: Option<(String, Vec<usize>)>,
+}
+
+impl SearchCache {
+    fn prepare(&mut self, hosts: &[Host], scope: SearchScope) {
+        if self.scope != Some(scope) {
+            *self = Self {
+                scope: Some(scope),
+                haystacks: hosts.iter().map(|h| search_haystack(h, scope)).collect(),
+                last: None,
+            };
+        }
+    }
+}
+
+/// The `f` prompt: a local file and where it goes on the selected host.
+#[derive(Clone, Debug, Default)]
+pub struct Transfer {
+    pub local: String,
+    /// Empty copies into the remote home directory.
+    pub remote: String,
+    /// Whether typing goes to `remote` rather than `local`.
+    pub editing_remote: bool,
+}
+
+impl Transfer {
+    fn editing(&mut self) -> &mut String {
+        if self.editing_remote {
+            &mut self.remote
+        } else {
+            &mut self.local
+        }
+    }
+}
+
+/// One row of the drawn host list: a host, or a heading over the hosts
+/// below it that can't be selected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListRow {
+    /// Index into `config.hosts`.
+    Host(usize),
+    /// The jump hosts divider, with the number of jump hosts.
+    JumpHosts(usize),
+    /// A group of the `z` view: the first tag of its hosts (`None` for the
+    /// untagged ones) and how many are listed.
+    Tag(Option<String>, usize),
+}
+
+/// Per-connection changes to the selected host; never saved.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOverrides {
+    pub user: Option<String>,
+    /// `Some(setting)` replaces the host's `request_tty`.
+    pub request_tty: Option<Option<bool>>,
+    /// Leaves out the host's stored `remote_command`.
+    pub skip_remote_command: bool,
+    /// Connects for real even with dry-run on (`G`).
+    pub bypass_dry_run: bool,
 }
 
 pub enum AppAction {
     Quit,
-    RunSsh(std::process::Command),
+    RunSsh(ssh::SshCommand),
+}
+
+/// Results delivered from worker threads, drained by `App::tick`.
+pub enum BackgroundEvent {
+    StaleSocketsRemoved(usize),
+    ControlConnectionsClosed { closed: usize, failed: usize },
+}
+
+/// Selected host (by id) per view, so moving between the full list and a
+/// search lands back where the user was in each.
+#[derive(Clone, Debug, Default)]
+struct SelectionMemory {
+    /// Filter the list was last built for.
+    filter: String,
+    /// Selection in the unfiltered list when it was last left.
+    list: Option<String>,
+    /// Most recent non-empty filter and its selection.
+    search: Option<(String, String)>,
+    /// Set when the user moves the selection within a search; clearing the
+    /// search then keeps that host instead of the pre-search one.
+    picked: bool,
 }
 
 pub struct App {
     pub mode: Mode,
-    pub status: Option<StatusLine>,
+    pub status: StatusManager,
     pub filter: String,
     pub filtered_indices: Vec<usize>,
     pub selected: usize,
@@ -1082,22 +2501,281 @@ pub struct App {
     pub quick_cursor: usize,
     pub show_help: bool,
     pub show_about: bool,
+    pub show_preview_bar: bool,
+    /// Host index and command preview for the footer strip.
+    preview_cache: RefCell<Option<(usize, String)>>,
+    /// Key file lookups for the details pane, by host id.
+    key_files: RefCell<BTreeMap<String, KeyFileCheck>>,
+    search_cache: SearchCache,
+    /// Masks addresses and key paths on screen (`P`); see `privacy`.
+    pub privacy: bool,
+    masker: Masker,
+    pub watch: Option<Watcher>,
     pub matcher: SkimMatcherV2,
     pub config: Config,
     pub config_path: PathBuf,
-    pub history: Vec<Config>,
+    pub history: Vec<HistoryEntry>,
+    /// What undo went back from, newest last; emptied by any new change
+    /// and by a reload.
+    pub redo_stack: Vec<HistoryEntry>,
+    /// Bumped each time the config is read from disk again (`r`, bundle
+    /// import), so undo can tell which snapshots predate the file on disk.
+    pub generation: u64,
+    pub search_scope: SearchScope,
+    /// Ctrl+R in search: the search text is a regex instead of a fuzzy
+    /// pattern.
+    pub search_regex: bool,
+    /// The last search regex that compiled; an invalid one keeps its
+    /// results showing.
+    last_regex: Option<regex::Regex>,
+    /// The regex typed last didn't compile, and its warning is showing.
+    regex_invalid: bool,
+    /// Order of the unfiltered list (`s`).
+    pub host_order: HostOrder,
+    selection_memory: SelectionMemory,
+    /// Highlighted row in the problems panel.
+    pub problem_selected: usize,
+    /// A bundle read for import with the changes it would make, waiting for
+    /// `y` in the bundle menu.
+    pub bundle_import: Option<(bundle::Bundle, Vec<String>)>,
+    /// First visible line of the explain report or the full command.
+    pub explain_scroll: u16,
+    /// Lines of the details pane scrolled off by PageDown, and the host
+    /// they were scrolled on; another host starts at the top.
+    pub details_scroll: (String, u16),
+    /// How far the details pane can scroll before its last line leaves the
+    /// pane, as last drawn; 0 before that.
+    details_max_scroll: Cell<u16>,
+    /// First row of the host list on screen; `move_selection` keeps the
+    /// selection inside the window it starts.
+    pub list_offset: usize,
+    /// Rows the host list had for hosts when last drawn; 0 before that.
+    list_height: Cell<usize>,
+    /// Why the config location can't be written; edits then stay in memory
+    /// until `W` moves to a writable copy.
+    pub read_only: Option<String>,
+    /// Canonical ids of the hosts marked with Space for a bulk edit.
+    pub marked: HashSet<String>,
+    /// Whether the jump hosts section at the bottom of the unfiltered list
+    /// is open (`J`).
+    pub jump_hosts_expanded: bool,
+    /// Lists the hosts under a heading per first tag (`z`), jump hosts
+    /// included.
+    pub grouped: bool,
+    /// Canonical ids of the hosts in the workset (`w`): an ad-hoc set for
+    /// the session that never touches their tags or the config.
+    pub workset: BTreeSet<String>,
+    /// Show only workset hosts (`v`).
+    pub workset_only: bool,
+    /// Id of the host `j`/`k` last moved to; saved in the state file.
+    last_selected: Option<String>,
+    /// Tags a listed host must all carry (`t`), apart from the search text;
+    /// Esc clears them.
+    pub tag_filter: Vec<String>,
+    /// The `t` picker, open in `Mode::TagFilter`.
+    pub tag_picker: Option<TagPicker>,
+    /// The `f` prompt, open in `Mode::Transfer`.
+    pub transfer: Option<Transfer>,
+    /// The `O` modal, open in `Mode::Settings`.
+    pub settings: Option<Settings>,
+    /// Canonical id of the host `l` picked as a bastion, until `l` on
+    /// another host links them or Esc drops it.
+    pub pending_bastion: Option<String>,
+    /// List-view keys, from the config's `[keys]` over the defaults.
+    keymap: KeyMap,
+    pub bulk: Option<BulkEdit>,
+    /// `--overlay` files, merged over the config on load and reload.
+    overlays: Vec<PathBuf>,
+    /// Main-config hosts an overlay host replaced, with their indices.
+    shadowed: Vec<(usize, Host)>,
+    pub resolver: LocalResolver,
+    /// Port probes behind the pre-flight checklist and `o`.
+    pub probes: PortProber,
+    /// The `o` check still running: host name, address and port, so its
+    /// answer can go to the status line.
+    ping: Option<(String, String, u16)>,
+    /// Whether each host answered the last health sweep (`H`), by
+    /// canonical id; only for this session.
+    pub health: HashMap<String, bool>,
+    /// The `H` sweep still running.
+    sweep: Option<HealthSweep>,
+    /// Show only the hosts the sweep found reachable (`Some(true)`) or
+    /// unreachable (`Some(false)`); `A` cycles it.
+    pub health_filter: Option<bool>,
+    /// How the ssh binary is looked up; tests swap in a fake `PATH`.
+    pub find_program: fn(&str) -> Result<PathBuf, program::Unavailable>,
+    /// Why the ssh binary can't be run, from the last check. Connecting is
+    /// refused while set; dry-run and editing keep working.
+    pub ssh_unavailable: Option<String>,
+    /// Per host id, for this run only.
+    pub last_errors: BTreeMap<String, LastError>,
+    /// Said when the session being started ends cleanly, in place of the
+    /// usual message; see `take_session_note`.
+    session_note: Option<String>,
+    /// Fixed time for scripted sessions; `None` uses the system clock.
+    clock: Option<SystemTime>,
+    background_tx: Sender<BackgroundEvent>,
+    background_rx: Receiver<BackgroundEvent>,
     store: ConfigStore,
+    state: StateStore,
 }
 
 impl App {
     pub fn new(store: ConfigStore) -> Result<Self> {
-        let config = store
-            .load_or_init()
+        let read_only = store.check_writable().err().map(|err| err.to_string());
+        let (config, migration) = Self::load_config(&store, read_only.is_some())
             .with_context(|| "failed to open sshdb config")?;
+        let mut app = Self::with_config(config, store);
+        app.status.set(match &read_only {
+            Some(reason) => StatusLine {
+                text: format!(
+                    "Read-only: {reason}. Changes won't be saved; press {} to continue from a writable copy.",
+                    app.key(KeyAction::WritableCopy)
+                ),
+                kind: StatusKind::Warn,
+            },
+            None => StatusLine {
+                text: match migration {
+                    Some(report) => report.summary(),
+                    None => format!(
+                        "Loaded config. Dry-run is OFF; press {} to toggle.",
+                        app.key(KeyAction::DryRun)
+                    ),
+                },
+                kind: StatusKind::Info,
+            },
+        });
+        if read_only.is_none() {
+            // Shown over "Loaded config." so a typo in [keys] is noticed.
+            app.rebuild_keymap();
+        }
+        app.read_only = read_only;
+        Ok(app)
+    }
+
+    /// A read-only location is loaded without creating or migrating the
+    /// file; a missing one then starts empty.
+    fn load_config(
+        store: &ConfigStore,
+        read_only: bool,
+    ) -> Result<(Config, Option<MigrationReport>)> {
+        let loaded = if !read_only {
+            store.load_or_init()
+        } else if !store.path().exists() {
+            Ok((Config::default(), None))
+        } else {
+            store.load_read_only().map(|config| (config, None))
+        };
+        let path = store.path().display();
+        match &loaded {
+            Ok((config, migration)) => debuglog::info(
+                "config",
+                "loaded",
+                &[
+                    ("path", &path),
+                    ("hosts", &config.hosts.len()),
+                    ("read_only", &read_only),
+                    (
+                        "migrated_from",
+                        &migration.as_ref().map_or(0, |report| report.from),
+                    ),
+                ],
+            ),
+            Err(err) => debuglog::error(
+                "config",
+                "load failed",
+                &[("path", &path), ("error", &format!("{err:#}"))],
+            ),
+        }
+        loaded
+    }
+
+    /// Merges the `--overlay` files over the loaded config, in order, and
+    /// keeps them for `r` to apply again. Overlay hosts are never saved.
+    pub fn load_overlays(&mut self, paths: &[PathBuf]) -> Result<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.overlays = paths.to_vec();
+        let replaced = self.apply_overlays()?;
+        let count = self
+            .config
+            .hosts
+            .iter()
+            .filter(|h| h.overlay.is_some())
+            .count();
+        self.status.set(if replaced.is_empty() {
+            StatusLine {
+                text: format!(
+                    "Loaded {count} overlay host(s) for this session; they are not saved."
+                ),
+                kind: StatusKind::Info,
+            }
+        } else {
+            StatusLine {
+                text: format!(
+                    "Overlay hosts replace {} for this session; the config keeps its own.",
+                    replaced.join(", ")
+                ),
+                kind: StatusKind::Warn,
+            }
+        });
+        Ok(())
+    }
+
+    /// Returns the ids of the hosts the overlays replaced.
+    fn apply_overlays(&mut self) -> Result<Vec<String>> {
+        self.shadowed.clear();
+        if self.overlays.is_empty() {
+            self.rebuild_filter();
+            return Ok(Vec::new());
+        }
+        let mut replaced = Vec::new();
+        for path in &self.overlays {
+            let hosts = overlay::read(path)?;
+            let source = path.display().to_string();
+            replaced.extend(overlay::merge(
+                &mut self.config,
+                &source,
+                hosts,
+                &mut self.shadowed,
+            ));
+        }
+        Self::validate_ids(&self.config).context("invalid overlay hosts")?;
+        Self::validate_bastions(&self.config).context("invalid overlay hosts")?;
+        self.rebuild_filter();
+        Ok(replaced)
+    }
+
+    /// Builds an app around an already loaded config. Edits are saved through
+    /// `store`; pair it with `ConfigStore::in_memory` to stay off the disk.
+    pub fn with_config(config: Config, store: ConfigStore) -> Self {
         let config_path = store.path().to_path_buf();
+        let state = if store.is_in_memory() {
+            StateStore::in_memory()
+        } else {
+            StateStore::beside(&config_path)
+        };
+        let saved_state = state.load().unwrap_or_default();
+        // Hosts removed since the workset was saved are dropped from it.
+        let workset = if config.persist_workset {
+            let ids: HashSet<String> = config.hosts.iter().map(Host::id).collect();
+            saved_state
+                .workset
+                .into_iter()
+                .filter(|id| ids.contains(id))
+                .collect()
+        } else {
+            BTreeSet::new()
+        };
+        let (background_tx, background_rx) = mpsc::channel();
         let mut app = Self {
-            mode: Mode::Normal,
-            status: None,
+            mode: if config.launcher {
+                Mode::Launcher
+            } else {
+                Mode::Normal
+            },
+            status: StatusManager::default(),
             filter: String::new(),
             filtered_indices: Vec::new(),
             selected: 0,
@@ -1108,154 +2786,698 @@ impl App {
             quick_cursor: 0,
             show_help: false,
             show_about: false,
+            show_preview_bar: config.show_preview_bar,
+            preview_cache: RefCell::new(None),
+            key_files: RefCell::new(BTreeMap::new()),
+            search_cache: SearchCache::default(),
+            privacy: config.privacy_mode,
+            masker: Masker::new(&config, config.privacy_pseudonyms),
+            watch: None,
             matcher: SkimMatcherV2::default(),
             config,
             config_path,
             history: Vec::new(),
+            redo_stack: Vec::new(),
+            generation: 0,
+            search_scope: saved_state.search_scope,
+            search_regex: false,
+            last_regex: None,
+            regex_invalid: false,
+            host_order: saved_state.host_order,
+            last_selected: saved_state.last_selected,
+            selection_memory: SelectionMemory::default(),
+            problem_selected: 0,
+            bundle_import: None,
+            marked: HashSet::new(),
+            jump_hosts_expanded: false,
+            grouped: false,
+            workset,
+            workset_only: false,
+            tag_filter: Vec::new(),
+            tag_picker: None,
+            transfer: None,
+            settings: None,
+            pending_bastion: None,
+            keymap: KeyMap::default(),
+            bulk: None,
+            overlays: Vec::new(),
+            shadowed: Vec::new(),
+            explain_scroll: 0,
+            details_scroll: (String::new(), 0),
+            details_max_scroll: Cell::new(0),
+            list_offset: 0,
+            list_height: Cell::new(0),
+            read_only: None,
+            resolver: LocalResolver::default(),
+            probes: PortProber::default(),
+            ping: None,
+            health: HashMap::new(),
+            sweep: None,
+            health_filter: None,
+            find_program: program::find_on_path,
+            ssh_unavailable: None,
+            last_errors: BTreeMap::new(),
+            session_note: None,
+            clock: None,
+            background_tx,
+            background_rx,
             store,
+            state,
         };
         app.rebuild_filter();
-        app.status = Some(StatusLine {
-            text: "Loaded config. Dry-run is OFF; press C to toggle.".into(),
-            kind: StatusKind::Info,
+        // A host removed since, or now hidden, leaves the selection at the top.
+        let remembered = app.last_selected.as_deref().and_then(|id| {
+            app.filtered_indices
+                .iter()
+                .position(|&i| app.config.hosts[i].id() == id)
         });
-        Ok(app)
+        app.selected = remembered.unwrap_or(0);
+        app.rebuild_keymap();
+        app
     }
 
-    pub fn on_event(&mut self, event: Event) -> Result<Option<AppAction>> {
-        match event {
-            Event::Key(key) if key.kind == KeyEventKind::Press => self.on_key(key),
-            _ => Ok(None),
+    /// The keys that run `action` now, for status hints.
+    fn key(&self, action: KeyAction) -> String {
+        self.keymap.label(action)
+    }
+
+    /// Reads `[keys]` again; entries it can't use are named in a warning
+    /// and keep their default keys.
+    fn rebuild_keymap(&mut self) {
+        let (keymap, warnings) = KeyMap::new(&self.config.keys);
+        self.keymap = keymap;
+        if !warnings.is_empty() {
+            self.status.set(StatusLine {
+                text: format!("Ignored in [keys]: {}.", warnings.join("; ")),
+                kind: StatusKind::Warn,
+            });
         }
     }
 
-    fn on_key(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
-        if self.show_about {
-            if matches!(
-                key.code,
-                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('a')
-            ) {
-                self.show_about = false;
-            }
-            return Ok(None);
+    /// Per-frame housekeeping: collects background results and schedules new ones.
+    pub fn tick(&mut self) {
+        self.resolver.poll();
+        self.probes.poll();
+        self.report_ping();
+        self.report_sweep();
+        if let Some(form) = self.form.as_mut() {
+            form.sync_command_field();
+            form.sync_auto_tags(&self.config.auto_tags);
         }
-        if key.modifiers.contains(KeyModifiers::CONTROL) {
-            if let KeyCode::Char('c') = key.code {
-                return Ok(Some(AppAction::Quit));
-            }
+        if let Some(watcher) = self.watch.as_mut() {
+            watcher.tick(Instant::now());
         }
-        if self.show_help {
-            match key.code {
-                KeyCode::Esc | KeyCode::Char('?') | KeyCode::Char('h') => {
-                    self.show_help = false;
-                }
-                _ => {}
+        while let Ok(event) = self.background_rx.try_recv() {
+            self.on_background(event);
+        }
+        // The launcher never shows warnings or problems, so skip the lookups.
+        if matches!(self.mode, Mode::Launcher) {
+            return;
+        }
+        for host in &self.config.hosts {
+            if problems::alias_candidate(host) && net::needs_lookup(&host.address) {
+                self.resolver.request(&host.address);
             }
-            return Ok(None);
         }
-        match self.mode.clone() {
-            Mode::Normal => self.handle_normal(key),
-            Mode::Search => self.handle_search(key),
-            Mode::Form => self.handle_form(key),
-            Mode::Confirm => self.handle_confirm(key),
-            Mode::QuickConnect => self.handle_quickconnect(key),
+        if !self.config.warn_local_addresses && !self.config.show_resolved_addresses {
+            return;
+        }
+        if let Some(address) = self.current_host().map(|h| h.address.clone()) {
+            self.resolver.request(&address);
         }
     }
 
-    fn handle_normal(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
-        match key.code {
-            KeyCode::Char('q') => return Ok(Some(AppAction::Quit)),
-            KeyCode::Char('?') | KeyCode::Char('h') => {
-                self.show_help = true;
-            }
-            KeyCode::Char('a') => {
-                self.show_about = true;
-            }
-            KeyCode::Char('/') => {
-                self.mode = Mode::Search;
-                self.status = Some(StatusLine {
-                    text: "Search: type to filter, Enter to apply.".into(),
-                    kind: StatusKind::Info,
-                });
+    fn on_background(&mut self, event: BackgroundEvent) {
+        self.status.set(match event {
+            BackgroundEvent::StaleSocketsRemoved(count) => StatusLine {
+                text: format!("Removed {count} stale ssh control socket(s)."),
+                kind: StatusKind::Info,
+            },
+            BackgroundEvent::ControlConnectionsClosed { closed, failed } => StatusLine {
+                text: format!("Closed {closed} control connection(s), {failed} failed."),
+                kind: if failed == 0 {
+                    StatusKind::Info
+                } else {
+                    StatusKind::Warn
+                },
+            },
+        });
+    }
+
+    /// Deferred startup work, run off the UI thread after the first frame.
+    pub fn start_housekeeping(&self) {
+        let tx = self.background_tx.clone();
+        thread::spawn(move || {
+            debuglog::debug("task", "housekeeping started", &[]);
+            let Some(dir) = ssh::control_socket_dir() else {
+                return;
+            };
+            let sockets = ssh::find_control_sockets(&dir);
+            let removed = ssh::remove_stale_sockets(&sockets, ssh::control_master_alive);
+            debuglog::debug(
+                "task",
+                "housekeeping finished",
+                &[("sockets", &sockets.len()), ("removed", &removed)],
+            );
+            if removed > 0 {
+                let _ = tx.send(BackgroundEvent::StaleSocketsRemoved(removed));
             }
-            KeyCode::Char('g') => {
-                self.mode = Mode::QuickConnect;
-                self.quick_input = Some(String::new());
-                self.quick_cursor = 0;
-                self.status = Some(StatusLine {
-                    text: "Quick connect: paste ssh user@host string, Enter to connect.".into(),
-                    kind: StatusKind::Info,
+        });
+    }
+
+    fn close_control_connections(&mut self) {
+        let sockets = ssh::control_socket_dir()
+            .map(|dir| ssh::find_control_sockets(&dir))
+            .unwrap_or_default();
+        if sockets.is_empty() {
+            self.status.set(StatusLine {
+                text: "No ssh control connections to close.".into(),
+                kind: StatusKind::Info,
+            });
+            return;
+        }
+        self.status.set(StatusLine {
+            text: format!("Closing {} control connection(s)…", sockets.len()),
+            kind: StatusKind::Info,
+        });
+        let tx = self.background_tx.clone();
+        thread::spawn(move || {
+            debuglog::debug(
+                "task",
+                "closing control connections",
+                &[("sockets", &sockets.len())],
+            );
+            let (closed, failed) = ssh::close_control_sockets(&sockets, ssh::control_master_exit);
+            debuglog::debug(
+                "task",
+                "control connections closed",
+                &[("closed", &closed), ("failed", &failed)],
+            );
+            let _ = tx.send(BackgroundEvent::ControlConnectionsClosed { closed, failed });
+        });
+    }
+
+    /// Warning shown before connecting when a host points back at this machine.
+    pub fn local_address_warning(&self, host: &Host) -> Option<String> {
+        if !self.config.warn_local_addresses {
+            return None;
+        }
+        self.resolver.local_kind(&host.address).map(|kind| {
+            format!(
+                "{} is a {} address; this will not reach a remote machine",
+                host.address,
+                kind.label()
+            )
+        })
+    }
+
+    /// The selected host's name lookup for the details pane, once `tick`
+    /// asked for it; `None` for IP literals or with the display turned off.
+    pub fn resolution(&self, host: &Host) -> Option<Resolution<'_>> {
+        if !self.config.show_resolved_addresses || net::is_ip_literal(&host.address) {
+            return None;
+        }
+        self.resolver.resolution(&host.address)
+    }
+
+    /// Warning when `ip_family` asks for an address family the host has no
+    /// address in: a literal of the other family, or a name without the
+    /// matching record here. Bastion hosts are resolved by the bastion, so
+    /// they are not checked.
+    pub fn ip_family_warning(&self, host: &Host) -> Option<String> {
+        let family = host.ip_family?;
+        if host.command_template.is_some() || host.bastion.is_some() {
+            return None;
+        }
+        let literal = host
+            .address
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        if let Ok(ip) = literal.parse::<IpAddr>() {
+            return (!family.matches(ip)).then(|| {
+                format!(
+                    "{} is not an {} address; ssh {} will fail",
+                    host.address,
+                    family.label(),
+                    family.flag()
+                )
+            });
+        }
+        let Some(Resolution::Resolved(addresses)) = self.resolver.resolution(&host.address) else {
+            return None;
+        };
+        (!addresses.iter().any(|ip| family.matches(*ip))).then(|| {
+            format!(
+                "{} has no {} ({}) record; ssh {} will fail",
+                host.address,
+                family.record(),
+                family.label(),
+                family.flag()
+            )
+        })
+    }
+
+    pub fn on_event(&mut self, event: Event) -> Result<Option<AppAction>> {
+        let before = self.mode.clone();
+        let (key, result) = match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press => {
+                (format!("{:?}", key.code), self.on_key(key))
+            }
+            Event::Paste(text) => ("paste".to_string(), self.on_paste(&text)),
+            _ => return Ok(None),
+        };
+        self.log_outcome(&before, &key, &result);
+        result
+    }
+
+    /// Debug log: the mode a key switched to, the action it asked for, or
+    /// the error that ends the session.
+    fn log_outcome(&self, before: &Mode, key: &str, result: &Result<Option<AppAction>>) {
+        if std::mem::discriminant(before) != std::mem::discriminant(&self.mode) {
+            debuglog::debug(
+                "app",
+                "mode changed",
+                &[
+                    ("from", &format!("{before:?}")),
+                    ("to", &format!("{:?}", self.mode)),
+                    ("key", &key),
+                ],
+            );
+        }
+        match result {
+            Ok(None) => {}
+            Ok(Some(AppAction::Quit)) => debuglog::info("app", "quit", &[("key", &key)]),
+            Ok(Some(AppAction::RunSsh(cmd))) => {
+                debuglog::info("app", "connect", &[("host", &cmd.host_id()), ("key", &key)])
+            }
+            Err(err) => debuglog::error(
+                "app",
+                "key handling failed",
+                &[("error", &format!("{err:#}")), ("key", &key)],
+            ),
+        }
+    }
+
+    /// Bracketed paste. The Add form takes the text whole; other text inputs
+    /// get its first line typed in; everywhere else it is ignored so pasted
+    /// letters can't trigger commands.
+    fn on_paste(&mut self, text: &str) -> Result<Option<AppAction>> {
+        if self.show_about || self.show_help {
+            return Ok(None);
+        }
+        let typed = match self.mode {
+            Mode::Form => {
+                if let Some(form) = self.form.as_mut() {
+                    form.paste(text, &self.config);
+                }
+                return Ok(None);
+            }
+            Mode::Search | Mode::Launcher | Mode::QuickConnect | Mode::TagFilter => true,
+            Mode::Confirm => !matches!(
+                self.confirm,
+                Some(
+                    ConfirmKind::Delete { .. }
+                        | ConfirmKind::UndoPastReload { .. }
+                        | ConfirmKind::SeriesAddress { .. }
+                        | ConfirmKind::SuggestJumpHosts { .. }
+                        | ConfirmKind::Unmasked(_)
+                        | ConfirmKind::RemoteCommand { .. }
+                        | ConfirmKind::PickBastion { .. }
+                )
+            ),
+            Mode::Normal
+            | Mode::Watch
+            | Mode::Problems
+            | Mode::Explain
+            | Mode::FullCommand
+            | Mode::Bundle
+            | Mode::Rules => false,
+            Mode::Bulk => {
+                if let Some(bulk) = self.bulk.as_mut() {
+                    bulk.value.push_str(text.lines().next().unwrap_or(""));
+                }
+                return Ok(None);
+            }
+            Mode::Settings => {
+                if let Some(settings) = self.settings.as_mut() {
+                    settings
+                        .default_key
+                        .push_str(text.lines().next().unwrap_or("").trim());
+                }
+                return Ok(None);
+            }
+            Mode::Transfer => {
+                if let Some(transfer) = self.transfer.as_mut() {
+                    transfer
+                        .editing()
+                        .push_str(text.lines().next().unwrap_or("").trim());
+                }
+                return Ok(None);
+            }
+        };
+        if typed {
+            let line = text.lines().map(str::trim).find(|l| !l.is_empty());
+            for c in line.unwrap_or_default().chars() {
+                self.on_key(KeyEvent::from(KeyCode::Char(c)))?;
+            }
+        }
+        Ok(None)
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
+        if self.show_about {
+            if matches!(
+                key.code,
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('a')
+            ) {
+                self.show_about = false;
+            }
+            return Ok(None);
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            if let KeyCode::Char('c') = key.code {
+                return Ok(Some(AppAction::Quit));
+            }
+        }
+        if self.show_help {
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('?') | KeyCode::Char('h') => {
+                    self.show_help = false;
+                }
+                _ => {}
+            }
+            return Ok(None);
+        }
+        match self.mode.clone() {
+            Mode::Normal => self.handle_normal(key),
+            Mode::Search => self.handle_search(key),
+            Mode::Form => self.handle_form(key),
+            Mode::Confirm => self.handle_confirm(key),
+            Mode::QuickConnect => self.handle_quickconnect(key),
+            Mode::Watch => self.handle_watch(key),
+            Mode::Problems => self.handle_problems(key),
+            Mode::Explain => self.handle_explain(key),
+            Mode::FullCommand if key.code == KeyCode::Char('V') => {
+                self.mode = Mode::Normal;
+                Ok(None)
+            }
+            Mode::FullCommand => self.handle_explain(key),
+            Mode::Rules => {
+                if matches!(
+                    key.code,
+                    KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('T')
+                ) {
+                    self.mode = Mode::Normal;
+                }
+                Ok(None)
+            }
+            Mode::Bundle => self.handle_bundle(key),
+            Mode::Bulk => self.handle_bulk(key),
+            Mode::Launcher => self.handle_launcher(key),
+            Mode::TagFilter => {
+                self.handle_tag_filter(key);
+                Ok(None)
+            }
+            Mode::Transfer => self.handle_transfer(key),
+            Mode::Settings => self.handle_settings(key),
+        }
+    }
+
+    /// Typing filters, Enter connects to the highlighted (by default the top)
+    /// result, Alt+Enter switches to the full view on it, Esc quits.
+    fn handle_launcher(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
+        match key.code {
+            KeyCode::Esc => return Ok(Some(AppAction::Quit)),
+            KeyCode::Enter if key.modifiers.contains(KeyModifiers::ALT) => {
+                let current = self.current_index();
+                self.mode = Mode::Normal;
+                self.filter.clear();
+                self.rebuild_filter();
+                if let Some(pos) =
+                    current.and_then(|idx| self.filtered_indices.iter().position(|i| *i == idx))
+                {
+                    self.selected = pos;
+                }
+            }
+            KeyCode::Enter if self.enter_needs_confirm() => self.open_connect_confirm(),
+            KeyCode::Enter => return self.connect_selected(),
+            KeyCode::Down => self.move_selection(1),
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Char(c)
+                if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT =>
+            {
+                self.filter.push(c);
+                self.refilter();
+                self.selected = 0;
+            }
+            KeyCode::Backspace => {
+                self.filter.pop();
+                self.refilter();
+                self.selected = 0;
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn handle_normal(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
+        let action = match key.code {
+            KeyCode::Down => Some(KeyAction::Down),
+            KeyCode::Up => Some(KeyAction::Up),
+            _ => self.keymap.action(&key),
+        };
+        match action {
+            Some(KeyAction::Quit) => return Ok(Some(AppAction::Quit)),
+            Some(KeyAction::Help) => {
+                self.show_help = true;
+            }
+            Some(KeyAction::About) => {
+                self.show_about = true;
+            }
+            Some(KeyAction::Search) => {
+                self.mode = Mode::Search;
+                // An active filter is kept, with typing going on after it.
+                let text = if self.filter.is_empty() {
+                    "Search: type to filter, Enter to apply, Esc to clear."
+                } else {
+                    "Search: type to extend, Ctrl+U to start over, Esc to clear."
+                };
+                self.status.set(StatusLine {
+                    text: text.into(),
+                    kind: StatusKind::Info,
+                });
+            }
+            Some(KeyAction::QuickConnect) => {
+                self.mode = Mode::QuickConnect;
+                self.quick_input = Some(String::new());
+                self.quick_cursor = 0;
+                self.status.set(StatusLine {
+                    text: "Quick connect: paste ssh user@host string, Enter to connect.".into(),
+                    kind: StatusKind::Info,
                 });
             }
-            KeyCode::Char('j') | KeyCode::Down => self.move_selection(1),
-            KeyCode::Char('k') | KeyCode::Up => self.move_selection(-1),
-            KeyCode::Char('n') => {
+            Some(KeyAction::Down) => self.move_selection(1),
+            Some(KeyAction::Up) => self.move_selection(-1),
+            Some(KeyAction::New) => {
                 self.form = Some(FormState::new(FormKind::Add, None, &self.config));
                 self.mode = Mode::Form;
-                self.status = Some(StatusLine {
+                self.status.set(StatusLine {
                     text: "New host: paste ssh command or fill fields; Tab to move, Enter to save."
                         .into(),
                     kind: StatusKind::Info,
                 });
             }
-            KeyCode::Char('u') => {
-                if self.undo()? {
-                    self.status = Some(StatusLine {
-                        text: "Undid last change.".into(),
-                        kind: StatusKind::Info,
-                    });
-                } else {
-                    self.status = Some(StatusLine {
-                        text: "Nothing to undo.".into(),
-                        kind: StatusKind::Warn,
-                    });
+            Some(KeyAction::Undo) => self.undo()?,
+            Some(KeyAction::Redo) => self.redo()?,
+            Some(KeyAction::Duplicate) => {
+                if let Some(host) = self.current_host().cloned() {
+                    self.duplicate_host(host)?;
                 }
             }
-            KeyCode::Char('y') => {
+            Some(KeyAction::DuplicateNext) => {
                 if let Some(host) = self.current_host().cloned() {
-                    self.duplicate_host(host)?;
+                    self.duplicate_next(host)?;
                 }
             }
-            KeyCode::Char('e') => {
+            Some(KeyAction::Edit) => {
                 if let Some(host) = self.current_host().cloned() {
                     self.form = Some(FormState::new(FormKind::Edit, Some(&host), &self.config));
                     self.mode = Mode::Form;
                 } else {
-                    self.status = Some(StatusLine {
+                    self.status.set(StatusLine {
                         text: "No host selected to edit.".into(),
                         kind: StatusKind::Warn,
                     });
                 }
             }
-            KeyCode::Char('d') => {
-                if self.current_host().is_some() {
+            Some(KeyAction::Delete) if self.current_host().is_some() => {
+                let dependents = self
+                    .current_host()
+                    .map(|host| self.config.bastion_referrers(&host.id()))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|i| self.config.display_name(&self.config.hosts[i]))
+                    .collect();
+                self.mode = Mode::Confirm;
+                self.confirm = Some(ConfirmKind::Delete { dependents });
+            }
+            Some(KeyAction::ConnectCommand) => self.open_connect_confirm(),
+            Some(KeyAction::ConnectBastion) => return self.connect_bastion(),
+            Some(KeyAction::ConnectAs) => {
+                if let Some(host) = self.current_host() {
+                    let user = host.user.clone().unwrap_or_default();
                     self.mode = Mode::Confirm;
-                    self.confirm = Some(ConfirmKind::Delete);
+                    self.confirm = Some(ConfirmKind::ConnectAs { user });
                 }
             }
-            KeyCode::Char('c') => {
-                if self.current_host().is_some() {
+            Some(KeyAction::Rename) => {
+                if let Some(host) = self.current_host() {
+                    let name = host.name.clone();
                     self.mode = Mode::Confirm;
-                    self.confirm = Some(ConfirmKind::Connect {
-                        extra_cmd: String::new(),
+                    self.confirm = Some(ConfirmKind::Rename { name });
+                }
+            }
+            Some(KeyAction::Problems) => self.open_problems(),
+            Some(KeyAction::Bundle) => self.mode = Mode::Bundle,
+            Some(KeyAction::ExportSshConfig) => self.export_ssh_config(),
+            Some(KeyAction::ExportJson) => self.export_json(),
+            Some(KeyAction::ImportJson) => self.import_json(),
+            Some(KeyAction::SwitchProfile) => self.next_profile(),
+            Some(KeyAction::SortRecent) => self.toggle_host_order(),
+            Some(KeyAction::JumpHosts) => self.toggle_jump_hosts(),
+            Some(KeyAction::GroupByTag) => self.toggle_grouped(),
+            Some(KeyAction::WorksetClear) => {
+                self.clear_workset();
+            }
+            Some(KeyAction::Workset) => self.toggle_workset(),
+            Some(KeyAction::WorksetView) => self.toggle_workset_view(),
+            Some(KeyAction::Mark) => {
+                if let Some(id) = self.current_host().map(Host::id) {
+                    if !self.marked.remove(&id) {
+                        self.marked.insert(id);
+                    }
+                    self.move_selection(1);
+                }
+            }
+            Some(KeyAction::BulkEdit) => {
+                if self.marked.is_empty() {
+                    self.status.set(StatusLine {
+                        text: format!(
+                            "Mark hosts with {} first, then {} edits them together.",
+                            self.key(KeyAction::Mark),
+                            self.key(KeyAction::BulkEdit)
+                        ),
+                        kind: StatusKind::Warn,
+                    });
+                } else {
+                    self.bulk = Some(BulkEdit::default());
+                    self.mode = Mode::Bulk;
+                }
+            }
+            Some(KeyAction::WritableCopy) => {
+                if let Err(err) = self.continue_from_writable_copy() {
+                    self.status.set(StatusLine {
+                        text: format!("{err:#}"),
+                        kind: StatusKind::Error,
                     });
                 }
             }
-            KeyCode::Char('x') => {
+            Some(KeyAction::Explain) if self.current_host().is_some() => {
+                self.explain_scroll = 0;
+                self.mode = Mode::Explain;
+            }
+            Some(KeyAction::FullCommand) if self.current_host().is_some() => {
+                self.explain_scroll = 0;
+                self.mode = Mode::FullCommand;
+            }
+            Some(KeyAction::CopyConnection) if self.privacy && self.current_host().is_some() => {
+                self.mode = Mode::Confirm;
+                self.confirm = Some(ConfirmKind::Unmasked(UnmaskedAction::CopyConnection));
+            }
+            Some(KeyAction::CopyConnection) => {
                 self.copy_current_connection_string();
             }
-            KeyCode::Enter => {
-                if self.current_host().is_some() {
-                    return self.connect(None);
+            Some(KeyAction::Ping) => self.ping_current(),
+            Some(KeyAction::HealthSweep) => self.health_sweep(),
+            Some(KeyAction::HealthFilter) => self.cycle_health_filter(),
+            Some(KeyAction::LinkBastion) => self.link_bastion(),
+            Some(KeyAction::MoveHostUp) => self.move_host(-1),
+            Some(KeyAction::MoveHostDown) => self.move_host(1),
+            Some(KeyAction::Pin) => self.toggle_pin(),
+            Some(KeyAction::Privacy) => self.toggle_privacy(),
+            Some(KeyAction::AutoTagRules) => self.mode = Mode::Rules,
+            Some(KeyAction::CloseControl) => {
+                self.close_control_connections();
+            }
+            None if matches!(key.code, KeyCode::PageDown | KeyCode::PageUp) => {
+                if let Some(id) = self.current_host().map(Host::id) {
+                    let scroll = match &self.details_scroll {
+                        (scrolled, lines) if *scrolled == id => *lines,
+                        _ => 0,
+                    };
+                    let scroll = if key.code == KeyCode::PageDown {
+                        scroll.saturating_add(5)
+                    } else {
+                        scroll.saturating_sub(5)
+                    };
+                    let scroll = scroll.min(self.details_max_scroll.get());
+                    self.details_scroll = (id, scroll);
                 }
             }
-            KeyCode::Char('r') => {
+            None if key.code == KeyCode::Esc && self.pending_bastion.is_some() => {
+                self.pending_bastion = None;
+                self.status.set(StatusLine {
+                    text: "No bastion picked.".into(),
+                    kind: StatusKind::Info,
+                });
+            }
+            None if key.code == KeyCode::Esc && !self.tag_filter.is_empty() => {
+                self.tag_filter.clear();
+                self.rebuild_keeping_selection();
+                self.status.set(StatusLine {
+                    text: "Tag filter cleared.".into(),
+                    kind: StatusKind::Info,
+                });
+            }
+            None if key.code == KeyCode::Esc => {
+                self.status.dismiss();
+            }
+            Some(KeyAction::TagFilter) => self.open_tag_filter(),
+            Some(KeyAction::PreviewBar) => {
+                self.show_preview_bar = !self.show_preview_bar;
+                self.status.set(StatusLine {
+                    text: format!(
+                        "Preview bar {}.",
+                        if self.show_preview_bar { "on" } else { "off" }
+                    ),
+                    kind: StatusKind::Info,
+                });
+            }
+            Some(KeyAction::Connect) if self.enter_needs_confirm() => self.open_connect_confirm(),
+            Some(KeyAction::Connect) if self.current_host().is_some() => {
+                return self.connect_selected();
+            }
+            Some(KeyAction::ConnectForce) => return self.connect_force(),
+            Some(KeyAction::CopyFile) if self.current_host().is_some() => {
+                self.transfer = Some(Transfer::default());
+                self.mode = Mode::Transfer;
+            }
+            Some(KeyAction::Settings) => {
+                let default_key = self.config.default_key.clone().unwrap_or_default();
+                self.settings = Some(Settings {
+                    default_key,
+                    other_key: String::new(),
+                });
+                self.mode = Mode::Settings;
+            }
+            Some(KeyAction::Reload) => {
                 self.reload_config()?;
             }
-            KeyCode::Char('C') => {
+            Some(KeyAction::DryRun) => {
                 self.dry_run = !self.dry_run;
                 let state = if self.dry_run { "ON" } else { "OFF" };
-                self.status = Some(StatusLine {
+                self.status.set(StatusLine {
                     text: format!("Dry-run toggled {state}."),
                     kind: StatusKind::Info,
                 });
@@ -1276,20 +3498,34 @@ impl App {
         match key.code {
             KeyCode::Esc => {
                 self.mode = Mode::Normal;
-                self.status = None;
+                self.status.clear();
+                self.filter.clear();
+                self.rebuild_filter();
             }
             KeyCode::Enter => {
                 self.mode = Mode::Normal;
             }
-            KeyCode::Char(c) => {
-                if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT {
-                    self.filter.push(c);
-                    self.rebuild_filter();
-                }
+            KeyCode::Tab => {
+                self.cycle_search_scope();
+            }
+            KeyCode::Char('u') if key.modifiers == KeyModifiers::CONTROL => {
+                self.filter.clear();
+                self.rebuild_filter();
+            }
+            KeyCode::Char('r') if key.modifiers == KeyModifiers::CONTROL => {
+                self.search_regex = !self.search_regex;
+                self.last_regex = None;
+                self.rebuild_filter();
+            }
+            KeyCode::Char(c)
+                if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT =>
+            {
+                self.filter.push(c);
+                self.refilter();
             }
             KeyCode::Backspace => {
                 self.filter.pop();
-                self.rebuild_filter();
+                self.refilter();
             }
             _ => {}
         }
@@ -1297,61 +3533,115 @@ impl App {
     }
 
     fn handle_form(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
-        if let Some(form) = self.form.as_mut() {
-            let active_bastion = form.field_index(FIELD_BASTION) == Some(form.index);
-            let active_keys = form.field_index(FIELD_KEYS) == Some(form.index);
-            let overlay_open = (active_bastion && form.bastion_dropdown.is_some())
-                || (active_keys && form.key_selector.is_some());
-            if overlay_open && matches!(key.code, KeyCode::Enter | KeyCode::Esc) {
-                form.handle_input(key, &self.config);
-                return Ok(None);
+        let Some(form) = self.form.as_mut() else {
+            self.mode = Mode::Normal;
+            return Ok(None);
+        };
+        if form.review.is_some() {
+            match key.code {
+                KeyCode::Enter | KeyCode::Char('y') => self.submit_form(true),
+                KeyCode::Esc | KeyCode::Char('n') => form.review = None,
+                _ => {}
             }
+            return Ok(None);
+        }
+        let active_bastion = form.field_index(FIELD_BASTION) == Some(form.index);
+        let active_keys = form.field_index(FIELD_KEYS) == Some(form.index);
+        let overlay_open = (active_bastion && form.bastion_dropdown.is_some())
+            || (active_keys && form.key_selector.is_some());
+        if overlay_open && matches!(key.code, KeyCode::Enter | KeyCode::Esc) {
+            form.handle_input(key, &self.config);
+            return Ok(None);
+        }
 
-            match key.code {
-                KeyCode::Esc => {
-                    self.mode = Mode::Normal;
-                    self.form = None;
-                }
-                KeyCode::Enter => {
-                    if !overlay_open {
-                        match form.build_host() {
-                            Ok(host) => {
-                                let action = form.kind;
-                                match self.save_host(action, host) {
-                                    Ok(_) => {
-                                        self.form = None;
-                                        self.mode = Mode::Normal;
-                                    }
-                                    Err(e) => {
-                                        self.status = Some(StatusLine {
-                                            text: e.to_string(),
-                                            kind: StatusKind::Error,
-                                        });
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                self.status = Some(StatusLine {
-                                    text: e.to_string(),
-                                    kind: StatusKind::Error,
-                                });
-                            }
-                        }
-                    }
+        match key.code {
+            KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                self.form = None;
+            }
+            KeyCode::Enter if form.takes_newline(&key) => {
+                let before = form.values();
+                if let Some(f) = form.fields.get_mut(form.index) {
+                    f.insert('\n');
                 }
-                _ => {
-                    form.handle_input(key, &self.config);
+                form.mark_manual(&before);
+            }
+            KeyCode::Enter => {
+                if !overlay_open {
+                    self.submit_form(false);
                 }
             }
-        } else {
-            self.mode = Mode::Normal;
+            _ => {
+                form.handle_input(key, &self.config);
+            }
         }
         Ok(None)
     }
 
+    /// Saves the form's host. An edit that changes the address or more than
+    /// a few fields is held for review first, unless already `reviewed`.
+    fn submit_form(&mut self, reviewed: bool) {
+        let Some(form) = self.form.as_mut() else {
+            return;
+        };
+        form.review = None;
+        form.sync_command_field();
+        form.sync_auto_tags(&self.config.auto_tags);
+        let host = match form.build_host() {
+            Ok(host) => host,
+            Err(e) => {
+                form.fail(&e);
+                self.status.set(StatusLine {
+                    text: e.to_string(),
+                    kind: StatusKind::Error,
+                });
+                return;
+            }
+        };
+        if !reviewed {
+            let changes = form.changes();
+            if changes.len() > REVIEW_CHANGES_OVER || changes.iter().any(|c| c.field == "host") {
+                form.review = Some(changes);
+                return;
+            }
+        }
+        let kind = form.kind;
+        let (_, dropped_env) = parse_set_env(form.field(FIELD_SET_ENV).map_or("", |f| &f.value));
+        match self.save_host(kind, host) {
+            Ok(_) => {
+                if !dropped_env.is_empty() {
+                    let saved = self
+                        .status
+                        .current()
+                        .map(|s| s.text.clone())
+                        .unwrap_or_default();
+                    self.status.set(StatusLine {
+                        text: format!(
+                            "{saved} Left out of Env, not KEY=value: {}.",
+                            dropped_env.join(" ")
+                        ),
+                        kind: StatusKind::Warn,
+                    });
+                }
+                self.form = None;
+                self.mode = Mode::Normal;
+                self.suggest_jump_hosts();
+            }
+            Err(e) => {
+                if let Some(form) = self.form.as_mut() {
+                    form.fail(&e);
+                }
+                self.status.set(StatusLine {
+                    text: e.to_string(),
+                    kind: StatusKind::Error,
+                });
+            }
+        }
+    }
+
     fn handle_confirm(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
         match self.confirm.clone() {
-            Some(ConfirmKind::Delete) => match key.code {
+            Some(ConfirmKind::Delete { .. }) => match key.code {
                 KeyCode::Esc | KeyCode::Char('n') => {
                     self.mode = Mode::Normal;
                     self.confirm = None;
@@ -1363,11 +3653,28 @@ impl App {
                 }
                 _ => {}
             },
-            Some(ConfirmKind::Connect { mut extra_cmd }) => match key.code {
+            Some(ConfirmKind::Connect {
+                mut extra_cmd,
+                mut request_tty,
+                freeze_armed,
+            }) => match key.code {
                 KeyCode::Esc => {
                     self.mode = Mode::Normal;
                     self.confirm = None;
                 }
+                KeyCode::Enter if !freeze_armed && self.current_freeze().is_some() => {
+                    self.status.set(StatusLine {
+                        text:
+                            "Inside the change freeze window; press Enter again to connect anyway."
+                                .into(),
+                        kind: StatusKind::Warn,
+                    });
+                    self.confirm = Some(ConfirmKind::Connect {
+                        extra_cmd,
+                        request_tty,
+                        freeze_armed: true,
+                    });
+                }
                 KeyCode::Enter => {
                     let extra = if extra_cmd.trim().is_empty() {
                         None
@@ -1376,27 +3683,269 @@ impl App {
                     };
                     self.confirm = None;
                     self.mode = Mode::Normal;
-                    return self.connect(extra);
+                    return self.connect_with(
+                        extra,
+                        ConnectOverrides {
+                            request_tty: Some(request_tty),
+                            ..Default::default()
+                        },
+                    );
                 }
-                KeyCode::Backspace => {
-                    extra_cmd.pop();
-                    self.confirm = Some(ConfirmKind::Connect { extra_cmd });
+                KeyCode::Char('t') if key.modifiers == KeyModifiers::CONTROL => {
+                    request_tty = next_tty(request_tty);
+                    self.confirm = Some(ConfirmKind::Connect {
+                        extra_cmd,
+                        request_tty,
+                        freeze_armed,
+                    });
                 }
-                KeyCode::Char(c) => {
-                    if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT {
-                        extra_cmd.push(c);
-                        self.confirm = Some(ConfirmKind::Connect { extra_cmd });
+                KeyCode::Tab => {
+                    self.confirm = None;
+                    self.mode = Mode::Normal;
+                    self.start_watch(extra_cmd.trim())?;
+                }
+                KeyCode::Up | KeyCode::Down => {
+                    let presets = self
+                        .current_host()
+                        .map(|h| h.command_presets.as_slice())
+                        .unwrap_or_default();
+                    if let Some(preset) =
+                        cycle_preset(presets, &extra_cmd, key.code == KeyCode::Down)
+                    {
+                        extra_cmd = preset;
                     }
+                    self.confirm = Some(ConfirmKind::Connect {
+                        extra_cmd,
+                        request_tty,
+                        freeze_armed,
+                    });
                 }
-                _ => {}
-            },
-            None => {
-                self.mode = Mode::Normal;
+                KeyCode::Backspace => {
+                    extra_cmd.pop();
+                    self.confirm = Some(ConfirmKind::Connect {
+                        extra_cmd,
+                        request_tty,
+                        freeze_armed,
+                    });
+                }
+                KeyCode::Char(c)
+                    if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT =>
+                {
+                    extra_cmd.push(c);
+                    self.confirm = Some(ConfirmKind::Connect {
+                        extra_cmd,
+                        request_tty,
+                        freeze_armed,
+                    });
+                }
+                _ => {}
+            },
+            Some(ConfirmKind::ConnectAs { mut user }) => match key.code {
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                }
+                KeyCode::Enter => {
+                    let user = user.trim().to_string();
+                    if user.is_empty() {
+                        self.status.set(StatusLine {
+                            text: "Enter a user to connect as.".into(),
+                            kind: StatusKind::Warn,
+                        });
+                        return Ok(None);
+                    }
+                    self.confirm = None;
+                    self.mode = Mode::Normal;
+                    return self.connect_as(None, Some(user));
+                }
+                KeyCode::Tab => {
+                    user = complete_user(&self.known_users(), &user);
+                    self.confirm = Some(ConfirmKind::ConnectAs { user });
+                }
+                KeyCode::Backspace => {
+                    user.pop();
+                    self.confirm = Some(ConfirmKind::ConnectAs { user });
+                }
+                KeyCode::Char(c)
+                    if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT =>
+                {
+                    user.push(c);
+                    self.confirm = Some(ConfirmKind::ConnectAs { user });
+                }
+                _ => {}
+            },
+            Some(ConfirmKind::Rename { mut name }) => match key.code {
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                }
+                KeyCode::Enter => {
+                    // On error the prompt stays open so the name can be fixed.
+                    if let Err(err) = self.rename_current(&name) {
+                        self.status.set(StatusLine {
+                            text: err.to_string(),
+                            kind: StatusKind::Error,
+                        });
+                        return Ok(None);
+                    }
+                    self.confirm = None;
+                    self.mode = Mode::Normal;
+                }
+                KeyCode::Backspace => {
+                    name.pop();
+                    self.confirm = Some(ConfirmKind::Rename { name });
+                }
+                KeyCode::Char(c)
+                    if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT =>
+                {
+                    name.push(c);
+                    self.confirm = Some(ConfirmKind::Rename { name });
+                }
+                _ => {}
+            },
+            Some(ConfirmKind::SeriesAddress { mut next, address }) => match key.code {
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                }
+                KeyCode::Enter | KeyCode::Char('y') | KeyCode::Char('n') => {
+                    if key.code != KeyCode::Char('n') {
+                        next.address = address;
+                    }
+                    self.confirm = None;
+                    self.open_series_form(&next);
+                }
+                _ => {}
+            },
+            Some(ConfirmKind::SuggestJumpHosts { ids }) => match key.code {
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                }
+                KeyCode::Enter | KeyCode::Char('y') | KeyCode::Char('n') => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                    let role = if key.code == KeyCode::Char('n') {
+                        HostRole::Normal
+                    } else {
+                        HostRole::Bastion
+                    };
+                    self.set_roles(&ids, role)?;
+                }
+                _ => {}
+            },
+            Some(ConfirmKind::PickBastion { hops, selected }) => {
+                let pick = match key.code {
+                    KeyCode::Esc => {
+                        self.mode = Mode::Normal;
+                        self.confirm = None;
+                        None
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        let selected = selected.checked_sub(1).unwrap_or(hops.len() - 1);
+                        self.confirm = Some(ConfirmKind::PickBastion { hops, selected });
+                        None
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        let selected = (selected + 1) % hops.len();
+                        self.confirm = Some(ConfirmKind::PickBastion { hops, selected });
+                        None
+                    }
+                    KeyCode::Enter => hops.get(selected).cloned(),
+                    KeyCode::Char(c) => c
+                        .to_digit(10)
+                        .and_then(|n| hops.get((n as usize).checked_sub(1)?))
+                        .cloned(),
+                    _ => None,
+                };
+                if let Some(id) = pick {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                    return self.connect_to_bastion(&id);
+                }
+            }
+            Some(ConfirmKind::RemoteCommand { .. }) => match key.code {
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                }
+                KeyCode::Enter | KeyCode::Char('e') => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                    return self.connect_with(
+                        None,
+                        ConnectOverrides {
+                            skip_remote_command: key.code == KeyCode::Char('e'),
+                            ..Default::default()
+                        },
+                    );
+                }
+                _ => {}
+            },
+            Some(ConfirmKind::Unmasked(action)) => match key.code {
+                KeyCode::Esc | KeyCode::Char('n') => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                }
+                KeyCode::Enter | KeyCode::Char('y') => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                    match action {
+                        UnmaskedAction::CopyConnection => self.copy_current_connection_string(),
+                        UnmaskedAction::ExportBundle => {
+                            let status = match self.export_bundle() {
+                                Ok(text) => StatusLine {
+                                    text,
+                                    kind: StatusKind::Info,
+                                },
+                                Err(err) => StatusLine {
+                                    text: format!("Bundle: {err:#}"),
+                                    kind: StatusKind::Error,
+                                },
+                            };
+                            self.status.set(status);
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Some(ConfirmKind::UndoPastReload { .. }) => match key.code {
+                KeyCode::Esc | KeyCode::Char('n') => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                }
+                KeyCode::Enter | KeyCode::Char('y') => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                    self.apply_undo()?;
+                }
+                KeyCode::Char('c') => {
+                    self.mode = Mode::Normal;
+                    self.confirm = None;
+                    self.drop_stale_history();
+                }
+                _ => {}
+            },
+            None => {
+                self.mode = Mode::Normal;
             }
         }
         Ok(None)
     }
 
+    /// Every distinct user configured on any host, for completion.
+    pub fn known_users(&self) -> Vec<String> {
+        let mut users: Vec<String> = self
+            .config
+            .hosts
+            .iter()
+            .filter_map(|h| h.user.clone())
+            .collect();
+        users.sort();
+        users.dedup();
+        users
+    }
+
     fn handle_quickconnect(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
         match key.code {
             KeyCode::Esc => {
@@ -1406,10 +3955,9 @@ impl App {
             }
             KeyCode::Backspace => {
                 if let Some(buf) = self.quick_input.as_mut() {
-                    if self.quick_cursor > 0 {
-                        buf.remove(self.quick_cursor - 1);
-                        self.quick_cursor -= 1;
-                    }
+                    let start = text::prev_boundary(buf, self.quick_cursor);
+                    buf.replace_range(start..self.quick_cursor, "");
+                    self.quick_cursor = start;
                 }
             }
             KeyCode::Enter => {
@@ -1425,20 +3973,18 @@ impl App {
                 if let Some(buf) = self.quick_input.as_mut() {
                     if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT {
                         buf.insert(self.quick_cursor, c);
-                        self.quick_cursor += 1;
+                        self.quick_cursor += c.len_utf8();
                     }
                 }
             }
             KeyCode::Left => {
-                if self.quick_cursor > 0 {
-                    self.quick_cursor -= 1;
+                if let Some(buf) = self.quick_input.as_ref() {
+                    self.quick_cursor = text::prev_boundary(buf, self.quick_cursor);
                 }
             }
             KeyCode::Right => {
                 if let Some(buf) = self.quick_input.as_ref() {
-                    if self.quick_cursor < buf.len() {
-                        self.quick_cursor += 1;
-                    }
+                    self.quick_cursor = text::next_boundary(buf, self.quick_cursor);
                 }
             }
             _ => {}
@@ -1454,6 +4000,22 @@ impl App {
         let len = self.filtered_indices.len() as isize;
         let new = (self.selected as isize + delta).rem_euclid(len);
         self.selected = new as usize;
+        self.list_offset = self.list_window(self.list_height.get());
+        if !self.filter.is_empty() {
+            self.selection_memory.picked = true;
+        }
+    }
+
+    /// Saves the selected host for the next start, if it changed. Called
+    /// when the selection has settled (on quit and before connecting), not
+    /// on every move, so `j`/`k` never wait on the state file.
+    pub fn remember_selection(&mut self) -> Result<()> {
+        let id = self.current_host().map(Host::id);
+        if id.is_none() || id == self.last_selected {
+            return Ok(());
+        }
+        self.last_selected = id;
+        self.save_state()
     }
 
     pub fn current_host(&self) -> Option<&Host> {
@@ -1462,397 +4024,5255 @@ impl App {
             .and_then(|idx| self.config.hosts.get(*idx))
     }
 
+    pub fn set_filter(&mut self, filter: &str) {
+        self.filter = filter.to_string();
+        self.rebuild_filter();
+    }
+
     fn rebuild_filter(&mut self) {
+        self.search_cache = SearchCache::default();
+        self.key_files.get_mut().clear();
+        self.refilter();
+    }
+
+    /// `rebuild_filter` for a changed search text only: the config is as it
+    /// was, so the search haystacks are reused.
+    fn refilter(&mut self) {
+        self.preview_cache.get_mut().take();
+        self.masker = Masker::new(&self.config, self.config.privacy_pseudonyms);
+        let left_view = (self.filter != self.selection_memory.filter).then(|| self.leave_view());
         if self.filter.is_empty() {
-            self.filtered_indices = (0..self.config.hosts.len()).collect();
+            self.last_regex = None;
+            let hosts = &self.config.hosts;
+            let (mut jump, mut normal): (Vec<usize>, Vec<usize>) =
+                (0..hosts.len()).partition(|&i| hosts[i].is_jump_host());
+            if self.host_order == HostOrder::Recent {
+                // `None` sorts below any time, so never-connected hosts
+                // come last, in config order.
+                let recency = |&i: &usize| std::cmp::Reverse(hosts[i].last_connected);
+                normal.sort_by_key(recency);
+                jump.sort_by_key(recency);
+            }
+            // Stable, so pinned hosts keep their order among themselves.
+            normal.sort_by_key(|&i| !hosts[i].pinned);
+            jump.sort_by_key(|&i| !hosts[i].pinned);
+            self.filtered_indices = normal;
+            if self.jump_hosts_expanded
+                || self.grouped
+                || self.workset_only
+                || self.health_filter.is_some()
+                || !self.tag_filter.is_empty()
+            {
+                self.filtered_indices.extend(jump);
+            }
+        } else if self.search_regex {
+            self.filtered_indices = self.regex_matches();
         } else {
+            let cache = &mut self.search_cache;
+            cache.prepare(&self.config.hosts, self.search_scope);
+            // Every character of a search has to match, so a longer one can
+            // only match among the hosts the shorter one did.
+            let candidates = match cache.last.take() {
+                Some((last, matched)) if self.filter.starts_with(&last) => matched,
+                _ => (0..self.config.hosts.len()).collect(),
+            };
             let mut scored: Vec<(i64, usize)> = Vec::new();
-            for (i, host) in self.config.hosts.iter().enumerate() {
-                let haystack = format!(
-                    "{} {} {} {}",
-                    host.name,
-                    host.address,
-                    host.tags.join(" "),
-                    host.description.clone().unwrap_or_default()
-                );
-                if let Some(score) = self.matcher.fuzzy_match(&haystack, &self.filter) {
+            for i in candidates {
+                if let Some(score) = self.matcher.fuzzy_match(&cache.haystacks[i], &self.filter) {
                     scored.push((score, i));
                 }
             }
-            scored.sort_by(|a, b| b.0.cmp(&a.0));
+            cache.last = Some((
+                self.filter.clone(),
+                scored.iter().map(|(_, i)| *i).collect(),
+            ));
+            scored.sort_by_key(|entry| std::cmp::Reverse(entry.0));
             self.filtered_indices = scored.into_iter().map(|(_, i)| i).collect();
         }
+        if self.workset_only {
+            let hosts = &self.config.hosts;
+            let workset = &self.workset;
+            self.filtered_indices
+                .retain(|i| workset.contains(&hosts[*i].id()));
+        }
+        if let Some(reachable) = self.health_filter {
+            let hosts = &self.config.hosts;
+            let health = &self.health;
+            self.filtered_indices
+                .retain(|i| health.get(&hosts[*i].id()) == Some(&reachable));
+        }
+        if !self.tag_filter.is_empty() {
+            let hosts = &self.config.hosts;
+            let wanted = &self.tag_filter;
+            self.filtered_indices
+                .retain(|i| wanted.iter().all(|tag| hosts[*i].tags.contains(tag)));
+        }
+        if self.grouped {
+            // Stable, so each group keeps the order (or search ranking) the
+            // hosts had; the untagged go last.
+            let hosts = &self.config.hosts;
+            self.filtered_indices.sort_by_key(|&i| {
+                let first = hosts[i].tags.first();
+                (first.is_none(), first)
+            });
+        }
         if self.selected >= self.filtered_indices.len() {
             self.selected = self.filtered_indices.len().saturating_sub(1);
         }
+        if let Some(previous) = left_view {
+            self.enter_view(previous);
+        }
     }
 
-    fn save_host(&mut self, kind: FormKind, host: Host) -> Result<()> {
-        let mut validation_config = self.config.clone();
-        match kind {
-            FormKind::Add => validation_config.hosts.push(host.clone()),
-            FormKind::Edit => {
-                if let Some(idx) = self.current_index() {
-                    validation_config.hosts[idx] = host.clone();
-                } else {
-                    self.status = Some(StatusLine {
-                        text: "No host selected to edit.".into(),
-                        kind: StatusKind::Warn,
-                    });
-                    return Ok(());
+    /// Hosts whose search haystack the regex search text matches, in config
+    /// order; case matters only when the text has an uppercase letter, as
+    /// in a fuzzy search. Text that doesn't compile (often just half
+    /// typed) warns and keeps matching the last regex that did.
+    fn regex_matches(&mut self) -> Vec<usize> {
+        let regex = regex::RegexBuilder::new(&self.filter)
+            .case_insensitive(!self.filter.chars().any(char::is_uppercase))
+            .build();
+        let regex = match regex {
+            Ok(regex) => {
+                if std::mem::take(&mut self.regex_invalid) {
+                    self.status.clear();
+                }
+                self.last_regex = Some(regex.clone());
+                regex
+            }
+            Err(err) => {
+                let reason = err.to_string();
+                let reason = reason.lines().last().unwrap_or_default();
+                self.status.set(StatusLine {
+                    text: format!(
+                        "Not a valid regex ({}); showing the last results.",
+                        reason.trim_start_matches("error: ")
+                    ),
+                    kind: StatusKind::Warn,
+                });
+                self.regex_invalid = true;
+                match &self.last_regex {
+                    Some(regex) => regex.clone(),
+                    None => return (0..self.config.hosts.len()).collect(),
                 }
             }
+        };
+        let cache = &mut self.search_cache;
+        cache.prepare(&self.config.hosts, self.search_scope);
+        cache.last = None;
+        (0..self.config.hosts.len())
+            .filter(|&i| regex.is_match(&cache.haystacks[i]))
+            .collect()
+    }
+
+    /// Records the selection of the view being left and returns it.
+    fn leave_view(&mut self) -> Option<String> {
+        let current = self.current_host().map(Host::id);
+        let memory = &mut self.selection_memory;
+        if memory.filter.is_empty() {
+            memory.list = current.clone();
+            memory.picked = false;
+        } else if let Some(id) = &current {
+            memory.search = Some((memory.filter.clone(), id.clone()));
+        }
+        memory.filter = self.filter.clone();
+        current
+    }
+
+    /// Restores the remembered selection for the view now shown. Back on the
+    /// full list that is the pre-search host unless one was picked in the
+    /// results.
+    fn enter_view(&mut self, previous: Option<String>) {
+        let memory = &self.selection_memory;
+        let target = if self.filter.is_empty() {
+            if memory.picked {
+                previous
+            } else {
+                memory.list.clone()
+            }
+        } else {
+            memory
+                .search
+                .as_ref()
+                .filter(|(filter, _)| *filter == self.filter)
+                .map(|(_, id)| id.clone())
+        };
+        if let Some(pos) = target.and_then(|id| {
+            self.filtered_indices
+                .iter()
+                .position(|i| self.config.hosts[*i].id() == id)
+        }) {
+            self.selected = pos;
+        }
+    }
+
+    fn toggle_host_order(&mut self) {
+        self.host_order = self.host_order.toggle();
+        self.rebuild_keeping_selection();
+        let text = match self.host_order {
+            HostOrder::Config => "Hosts in config order.",
+            HostOrder::Recent => "Hosts by last connection, most recent first.",
+        };
+        self.status.set(match self.save_state() {
+            Ok(()) => StatusLine {
+                text: text.into(),
+                kind: StatusKind::Info,
+            },
+            Err(err) => StatusLine {
+                text: format!("{text} Not saved: {err:#}"),
+                kind: StatusKind::Warn,
+            },
+        });
+    }
+
+    fn rebuild_keeping_selection(&mut self) {
+        let current = self.current_index();
+        self.rebuild_filter();
+        if let Some(pos) =
+            current.and_then(|idx| self.filtered_indices.iter().position(|i| *i == idx))
+        {
+            self.selected = pos;
+        }
+    }
+
+    /// Notes that a session to `id` is starting. Written to the config
+    /// straight away, outside the undo history; a read-only config only
+    /// keeps it for the session.
+    fn record_connection(&mut self, id: &str) -> Result<()> {
+        let now = self.unix_now();
+        let Some(host) = self.config.hosts.iter_mut().find(|h| h.id() == id) else {
+            return Ok(());
+        };
+        host.last_connected = Some(now);
+        host.connect_count = host.connect_count.saturating_add(1);
+        if self.host_order == HostOrder::Recent && self.filter.is_empty() {
+            self.rebuild_keeping_selection();
+        }
+        if self.read_only.is_some() {
+            return Ok(());
+        }
+        self.save_config()
+    }
+
+    fn cycle_search_scope(&mut self) {
+        self.search_scope = self.search_scope.next();
+        self.rebuild_filter();
+        self.status.set(match self.save_state() {
+            Ok(()) => StatusLine {
+                text: format!("Search scope: {}.", self.search_scope.label()),
+                kind: StatusKind::Info,
+            },
+            Err(err) => StatusLine {
+                text: format!("Search scope changed but not saved: {err}"),
+                kind: StatusKind::Warn,
+            },
+        });
+    }
+
+    /// The config as it is written: the default profile's hosts at the
+    /// top level and no overlay hosts.
+    fn persisted(&self) -> Config {
+        overlay::persisted(&self.config.with_default_profile(), &self.shadowed)
+    }
+
+    /// Persists the host database; a lossy rewrite replaces the status line
+    /// with a warning so it isn't missed.
+    fn save_config(&mut self) -> Result<()> {
+        if let Some(reason) = &self.read_only {
+            self.status.set(StatusLine {
+                text: format!(
+                    "Not saved: {reason}. Press {} to continue from a writable copy.",
+                    self.key(KeyAction::WritableCopy)
+                ),
+                kind: StatusKind::Warn,
+            });
+            return Ok(());
+        }
+        let persisted = self.persisted();
+        let saved = self.store.save(&persisted);
+        let path = self.store.path().display();
+        match &saved {
+            Ok(outcome) => debuglog::info(
+                "config",
+                "saved",
+                &[
+                    ("path", &path),
+                    ("hosts", &persisted.hosts.len()),
+                    ("outcome", &format!("{outcome:?}")),
+                ],
+            ),
+            Err(err) => debuglog::error(
+                "config",
+                "save failed",
+                &[("path", &path), ("error", &format!("{err:#}"))],
+            ),
+        }
+        if let SaveOutcome::Rewritten(warning) = saved? {
+            self.status.set(StatusLine {
+                text: warning,
+                kind: StatusKind::Warn,
+            });
         }
-        Self::validate_bastions(&validation_config)?;
+        Ok(())
+    }
+
+    fn save_state(&self) -> Result<()> {
+        self.state.save(&AppState {
+            search_scope: self.search_scope,
+            host_order: self.host_order,
+            last_selected: self.last_selected.clone(),
+            workset: if self.config.persist_workset {
+                self.workset.iter().cloned().collect()
+            } else {
+                Vec::new()
+            },
+        })
+    }
 
+    fn save_host(&mut self, kind: FormKind, mut host: Host) -> Result<()> {
+        let editing = match kind {
+            FormKind::Add => None,
+            FormKind::Edit => self.current_index(),
+        };
+        self.check_name_free(&host, editing)
+            .map_err(in_field(FIELD_NAME))?;
         match kind {
             FormKind::Add => {
-                self.push_history();
-                self.config.hosts.push(host.clone());
-                self.status = Some(StatusLine {
+                self.status.set(StatusLine {
                     text: format!("Added host {}.", host.name),
                     kind: StatusKind::Info,
                 });
+                self.apply_change(Change::Add(host))
             }
             FormKind::Edit => {
-                if let Some(idx) = self.current_index() {
-                    self.push_history();
-                    self.config.hosts[idx] = host.clone();
-                    self.status = Some(StatusLine {
-                        text: format!("Updated host {}.", host.name),
-                        kind: StatusKind::Info,
-                    });
-                } else {
-                    self.status = Some(StatusLine {
+                let Some(index) = self.current_index() else {
+                    self.status.set(StatusLine {
                         text: "No host selected to edit.".into(),
                         kind: StatusKind::Warn,
                     });
                     return Ok(());
-                }
+                };
+                // The form has no field for it; an acknowledged alias
+                // stays acknowledged until the address changes.
+                let previous = &self.config.hosts[index];
+                let previous_id = previous.id();
+                host.alias_ok = previous.alias_ok && previous.address == host.address;
+                host.protected = previous.protected;
+                host.pinned = previous.pinned;
+                host.password_command = previous.password_command.clone();
+                host.connect_with = previous.connect_with.clone();
+                host.last_connected = previous.last_connected;
+                host.connect_count = previous.connect_count;
+                host.overlay = previous.overlay.clone();
+                let renamed = if previous.name != host.name {
+                    Some(self.config.clone().rename_host(index, &host.name)?)
+                } else {
+                    None
+                };
+                self.status.set(StatusLine {
+                    text: match (renamed, &host.overlay) {
+                        (_, Some(source)) => format!(
+                            "Updated overlay host {} for this session; {source} is not changed.",
+                            host.name
+                        ),
+                        (Some(report), None) => format!("Updated host. {}", report.summary()),
+                        (None, None) => format!("Updated host {}.", host.name),
+                    },
+                    kind: StatusKind::Info,
+                });
+                let id = host.id();
+                self.apply_change(Change::Edit { index, host })?;
+                self.follow_workset(&previous_id, &id)
             }
         }
-        self.store.save(&self.config)?;
-        self.rebuild_filter();
-        Ok(())
     }
 
-    fn validate_bastions(config: &Config) -> Result<()> {
-        for host in &config.hosts {
-            if let Some(bastion_name) = &host.bastion {
-                if bastion_name == &host.name {
-                    bail!("Host '{}' cannot use itself as bastion.", host.name);
-                }
-
-                let mut seen: Vec<String> = vec![host.name.clone()];
-                let mut current = bastion_name.as_str();
-                loop {
-                    if seen.iter().any(|h| h == current) {
-                        bail!(
-                            "Circular bastion reference detected involving '{}'.",
-                            current
-                        );
-                    }
-                    let Some(bastion) = config.find_host(current) else {
-                        break;
-                    };
-                    seen.push(current.to_string());
-                    let Some(next) = &bastion.bastion else { break };
-                    current = next;
-                }
-            }
+    /// Fails when a host other than the one at `editing` already has the
+    /// id `host` would save under, so the form can point at the name
+    /// rather than only failing the whole config later.
+    fn check_name_free(&self, host: &Host, editing: Option<usize>) -> Result<()> {
+        let id = host.id();
+        let taken = self
+            .config
+            .hosts
+            .iter()
+            .enumerate()
+            .any(|(i, other)| Some(i) != editing && other.id() == id);
+        if !taken {
+            return Ok(());
+        }
+        match &host.group {
+            Some(group) => bail!(
+                "A host named '{}' already exists in group '{group}'.",
+                host.name
+            ),
+            None => bail!("A host named '{}' already exists.", host.name),
         }
-        Ok(())
-    }
-
-    fn current_index(&self) -> Option<usize> {
-        self.filtered_indices.get(self.selected).cloned()
     }
 
-    fn delete_current(&mut self) -> Result<()> {
-        if let Some(idx) = self.current_index() {
-            let removed_name = self.config.hosts.get(idx).map(|h| h.name.clone());
-            self.push_history();
-            if let Some(name) = removed_name {
-                self.status = Some(StatusLine {
-                    text: format!("Removed {}.", name),
-                    kind: StatusKind::Warn,
-                });
-            }
-            self.config.hosts.remove(idx);
-            self.store.save(&self.config)?;
+    /// Keeps a renamed (or regrouped) host in the workset.
+    fn follow_workset(&mut self, old_id: &str, new_id: &str) -> Result<()> {
+        if old_id == new_id || !self.workset.remove(old_id) {
+            return Ok(());
+        }
+        self.workset.insert(new_id.to_string());
+        if self.workset_only {
             self.rebuild_filter();
-            if self.selected >= self.filtered_indices.len() {
-                self.selected = self.filtered_indices.len().saturating_sub(1);
-            }
+        }
+        if self.config.persist_workset {
+            self.save_state()?;
         }
         Ok(())
     }
 
-    fn duplicate_host(&mut self, host: Host) -> Result<()> {
-        let base = format!("{}-copy", host.name);
-        let name = self.unique_name(&base);
-        let mut new_host = host.clone();
-        new_host.name = name.clone();
-        self.push_history();
-        self.config.hosts.push(new_host);
-        self.store.save(&self.config)?;
-        self.rebuild_filter();
-        if let Some(pos) = self
-            .filtered_indices
-            .iter()
-            .position(|i| self.config.hosts.get(*i).map(|h| &h.name) == Some(&name))
-        {
+    /// Asks whether to mark hosts used as bastions as jump hosts, when some
+    /// have no role yet and nothing else is on screen. Overlay hosts are
+    /// left out since their role could not be saved.
+    pub fn suggest_jump_hosts(&mut self) {
+        if !matches!(self.mode, Mode::Normal) || self.confirm.is_some() {
+            return;
+        }
+        let ids: Vec<String> = self
+            .config
+            .jump_host_candidates()
+            .into_iter()
+            .map(|i| &self.config.hosts[i])
+            .filter(|host| host.overlay.is_none())
+            .map(Host::id)
+            .collect();
+        if !ids.is_empty() {
+            self.mode = Mode::Confirm;
+            self.confirm = Some(ConfirmKind::SuggestJumpHosts { ids });
+        }
+    }
+
+    fn set_roles(&mut self, ids: &[String], role: HostRole) -> Result<()> {
+        self.status.set(StatusLine {
+            text: match role {
+                HostRole::Bastion => format!(
+                    "Marked {} as jump host{}; {} shows them.",
+                    ids.join(", "),
+                    if ids.len() == 1 { "" } else { "s" },
+                    self.key(KeyAction::JumpHosts)
+                ),
+                HostRole::Normal => format!("Kept {} as normal hosts.", ids.join(", ")),
+            },
+            kind: StatusKind::Info,
+        });
+        self.apply_change(Change::SetRoles {
+            ids: ids.to_vec(),
+            role,
+        })
+    }
+
+    /// Adds the selected host to the workset or takes it out.
+    fn toggle_workset(&mut self) {
+        let Some(id) = self.current_host().map(Host::id) else {
+            return;
+        };
+        let added = self.workset.insert(id.clone());
+        if !added {
+            self.workset.remove(&id);
+        }
+        let text = format!(
+            "{} {id} {} the workset ({} hosts).",
+            if added { "Added" } else { "Removed" },
+            if added { "to" } else { "from" },
+            self.workset.len()
+        );
+        if self.workset_only {
+            self.workset_only = !self.workset.is_empty();
+            self.rebuild_filter();
+        }
+        self.workset_changed(text);
+    }
+
+    /// `t`: opens the tag picker, unless no host has a tag.
+    fn open_tag_filter(&mut self) {
+        if self.config.tags().is_empty() {
+            self.status.set(StatusLine {
+                text: "No host has tags to filter by.".into(),
+                kind: StatusKind::Warn,
+            });
+            return;
+        }
+        self.tag_picker = Some(TagPicker::default());
+        self.mode = Mode::TagFilter;
+    }
+
+    /// Known tags containing the picker's text, ignoring case.
+    pub fn tag_choices(&self) -> Vec<String> {
+        let query = self
+            .tag_picker
+            .as_ref()
+            .map(|picker| picker.query.to_lowercase())
+            .unwrap_or_default();
+        let mut tags = self.config.tags();
+        tags.retain(|tag| tag.to_lowercase().contains(&query));
+        tags
+    }
+
+    /// Typing narrows the tags, Enter adds the highlighted one to the tag
+    /// filter (or takes it out again if already there), Esc closes.
+    fn handle_tag_filter(&mut self, key: KeyEvent) {
+        let choices = self.tag_choices().len();
+        let Some(picker) = self.tag_picker.as_mut() else {
+            self.mode = Mode::Normal;
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => {
+                self.tag_picker = None;
+                self.mode = Mode::Normal;
+            }
+            KeyCode::Up => picker.selected = picker.selected.saturating_sub(1),
+            KeyCode::Down => {
+                picker.selected = (picker.selected + 1).min(choices.saturating_sub(1));
+            }
+            KeyCode::Backspace => {
+                picker.query.pop();
+                picker.selected = 0;
+            }
+            KeyCode::Char(c)
+                if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT =>
+            {
+                picker.query.push(c);
+                picker.selected = 0;
+            }
+            KeyCode::Enter => {
+                let selected = picker.selected;
+                let Some(tag) = self.tag_choices().into_iter().nth(selected) else {
+                    return;
+                };
+                self.tag_picker = None;
+                self.mode = Mode::Normal;
+                if let Some(pos) = self.tag_filter.iter().position(|t| *t == tag) {
+                    self.tag_filter.remove(pos);
+                } else {
+                    self.tag_filter.push(tag);
+                }
+                self.rebuild_keeping_selection();
+                self.status.set(StatusLine {
+                    text: if self.tag_filter.is_empty() {
+                        "Tag filter cleared.".into()
+                    } else {
+                        format!(
+                            "Showing hosts tagged {} ({}); {} adds a tag, Esc clears.",
+                            self.tag_filter.join(" + "),
+                            self.filtered_indices.len(),
+                            self.key(KeyAction::TagFilter)
+                        )
+                    },
+                    kind: StatusKind::Info,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Shows only the workset hosts, or everything again.
+    fn toggle_workset_view(&mut self) {
+        if self.workset.is_empty() && !self.workset_only {
+            self.status.set(StatusLine {
+                text: format!(
+                    "The workset is empty; {} adds the selected host.",
+                    self.key(KeyAction::Workset)
+                ),
+                kind: StatusKind::Warn,
+            });
+            return;
+        }
+        self.workset_only = !self.workset_only;
+        let current = self.current_index();
+        self.rebuild_filter();
+        if let Some(pos) = current.and_then(|c| self.filtered_indices.iter().position(|i| *i == c))
+        {
+            self.selected = pos;
+        }
+        self.status.set(StatusLine {
+            text: if self.workset_only {
+                format!(
+                    "Showing the workset ({} hosts); {} shows all.",
+                    self.workset.len(),
+                    self.key(KeyAction::WorksetView)
+                )
+            } else {
+                "Showing all hosts.".into()
+            },
+            kind: StatusKind::Info,
+        });
+    }
+
+    fn clear_workset(&mut self) {
+        if self.workset.is_empty() {
+            return;
+        }
+        let count = self.workset.len();
+        self.workset.clear();
+        self.workset_only = false;
+        self.rebuild_filter();
+        self.workset_changed(format!("Cleared the workset ({count} hosts)."));
+    }
+
+    /// Reports a workset change, saving it first with `persist_workset`.
+    fn workset_changed(&mut self, text: String) {
+        let saved = if self.config.persist_workset {
+            self.save_state()
+        } else {
+            Ok(())
+        };
+        self.status.set(match saved {
+            Ok(()) => StatusLine {
+                text,
+                kind: StatusKind::Info,
+            },
+            Err(err) => StatusLine {
+                text: format!("{text} Not saved: {err:#}"),
+                kind: StatusKind::Warn,
+            },
+        });
+    }
+
+    /// Turns the `z` grouping by tag on or off, keeping the selected host
+    /// selected.
+    fn toggle_grouped(&mut self) {
+        self.grouped = !self.grouped;
+        let current = self.current_index();
+        self.rebuild_filter();
+        if let Some(pos) = current.and_then(|c| self.filtered_indices.iter().position(|i| *i == c))
+        {
+            self.selected = pos;
+        }
+        self.status.set(StatusLine {
+            text: if self.grouped {
+                "Grouped by first tag.".into()
+            } else {
+                "Grouping by tag off.".into()
+            },
+            kind: StatusKind::Info,
+        });
+    }
+
+    /// Opens or closes the jump hosts section, keeping the selected host
+    /// selected when it is still listed.
+    fn toggle_jump_hosts(&mut self) {
+        self.jump_hosts_expanded = !self.jump_hosts_expanded;
+        let current = self.current_index();
+        self.rebuild_filter();
+        if let Some(pos) = current.and_then(|c| self.filtered_indices.iter().position(|i| *i == c))
+        {
+            self.selected = pos;
+        }
+        if self.filter.is_empty() {
+            let count = self
+                .config
+                .hosts
+                .iter()
+                .filter(|h| h.is_jump_host())
+                .count();
+            self.status.set(StatusLine {
+                text: format!(
+                    "Jump hosts ({count}) {}.",
+                    if self.jump_hosts_expanded {
+                        "shown"
+                    } else {
+                        "hidden"
+                    }
+                ),
+                kind: StatusKind::Info,
+            });
+        }
+    }
+
+    /// Where the list headings go: each one's position in
+    /// `filtered_indices` and its row. Headings come before the host at
+    /// their position, so that host is one row further down.
+    pub fn list_headings(&self) -> Vec<(usize, ListRow)> {
+        if !self.grouped {
+            return self
+                .jump_section()
+                .map(|(start, count)| (start, ListRow::JumpHosts(count)))
+                .into_iter()
+                .collect();
+        }
+        let hosts = &self.config.hosts;
+        let first_tag = |i: usize| hosts[i].tags.first();
+        let mut headings: Vec<(usize, ListRow)> = Vec::new();
+        for (pos, &i) in self.filtered_indices.iter().enumerate() {
+            match headings.last_mut() {
+                Some((_, ListRow::Tag(tag, count))) if tag.as_ref() == first_tag(i) => *count += 1,
+                _ => headings.push((pos, ListRow::Tag(first_tag(i).cloned(), 1))),
+            }
+        }
+        headings
+    }
+
+    /// What row `row` of the drawn list shows, given `list_headings`.
+    pub fn list_row(&self, headings: &[(usize, ListRow)], row: usize) -> ListRow {
+        let mut pos = row;
+        for (n, (start, heading)) in headings.iter().enumerate() {
+            match (start + n).cmp(&row) {
+                std::cmp::Ordering::Equal => return heading.clone(),
+                std::cmp::Ordering::Less => pos = row - n - 1,
+                std::cmp::Ordering::Greater => break,
+            }
+        }
+        ListRow::Host(self.filtered_indices[pos])
+    }
+
+    /// Row of the selection in the drawn list, where each heading is a row
+    /// of its own.
+    pub fn selected_row(&self) -> usize {
+        let above = self
+            .list_headings()
+            .iter()
+            .filter(|(start, _)| self.selected >= *start)
+            .count();
+        self.selected + above
+    }
+
+    /// Called by the list as it draws, so `move_selection` scrolls by the
+    /// rows actually on screen.
+    pub fn set_list_height(&self, rows: usize) {
+        self.list_height.set(rows);
+    }
+
+    /// The first row to show in a list `height` rows tall: `list_offset`,
+    /// moved just enough to show the selection and not to leave rows empty
+    /// below the last host. Other jumps of the selection (a search, undo)
+    /// are caught up here when drawing.
+    pub fn list_window(&self, height: usize) -> usize {
+        let row = self.selected_row();
+        if height == 0 {
+            return self.list_offset.min(row);
+        }
+        let total = self.filtered_indices.len() + self.list_headings().len();
+        self.list_offset
+            .clamp((row + 1).saturating_sub(height), row)
+            .min(total.saturating_sub(height))
+    }
+
+    /// The jump hosts section of the unfiltered list: the position in
+    /// `filtered_indices` where it starts and how many hosts it holds.
+    /// `None` while searching, grouping by tag or showing the workset, which
+    /// mix every host.
+    pub fn jump_section(&self) -> Option<(usize, usize)> {
+        if !self.filter.is_empty()
+            || self.grouped
+            || self.workset_only
+            || !self.tag_filter.is_empty()
+        {
+            return None;
+        }
+        let count = self
+            .config
+            .hosts
+            .iter()
+            .filter(|h| h.is_jump_host())
+            .count();
+        let start = self.filtered_indices.len() - if self.jump_hosts_expanded { count } else { 0 };
+        (count > 0).then_some((start, count))
+    }
+
+    /// Renames the selected host; see `Config::rename_host`.
+    fn rename_current(&mut self, new_name: &str) -> Result<()> {
+        let Some(idx) = self.current_index() else {
+            return Ok(());
+        };
+        let report = self.config.clone().rename_host(idx, new_name)?;
+        if report.old_id == report.new_id {
+            return Ok(());
+        }
+        self.status.set(StatusLine {
+            text: report.summary(),
+            kind: StatusKind::Info,
+        });
+        self.apply_change(Change::Rename {
+            index: idx,
+            name: new_name.to_string(),
+        })?;
+        self.follow_workset(&report.old_id, &report.new_id)?;
+        if let Some(pos) = self.filtered_indices.iter().position(|i| *i == idx) {
             self.selected = pos;
         }
-        self.status = Some(StatusLine {
+        Ok(())
+    }
+
+    /// Applies `change` to a copy of the config and validates the result;
+    /// only then records undo history, takes the copy, saves and refreshes
+    /// the list. Callers set their status line first, so a save warning
+    /// can still replace it.
+    pub fn apply_change(&mut self, change: Change) -> Result<()> {
+        let restore = matches!(change, Change::Restore(_));
+        let mut config = self.config.clone();
+        change.apply(&mut config)?;
+        Self::validate(&config, &self.config)?;
+        if !restore {
+            self.push_history();
+        }
+        // An import, or undoing one, can bring other `[keys]`.
+        let keys_changed = config.keys != self.config.keys;
+        self.config = config;
+        if keys_changed {
+            self.rebuild_keymap();
+        }
+        self.save_config()?;
+        self.rebuild_filter();
+        Ok(())
+    }
+
+    /// Rules a config must pass before it is saved: unique ids and sound
+    /// bastion chains everywhere, plus the per-host checks for hosts that
+    /// differ from `current` (others may predate a rule and are left to
+    /// the problems panel).
+    fn validate(config: &Config, current: &Config) -> Result<()> {
+        Self::validate_ids(config)?;
+        Self::validate_bastions(config)?;
+        for host in &config.hosts {
+            if !current.hosts.contains(host) {
+                Self::validate_host(host)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_host(host: &Host) -> Result<()> {
+        if host.name.trim().is_empty() || host.address.trim().is_empty() {
+            bail!("Name and host cannot be empty.");
+        }
+        if host.name.contains('/') || host.group.as_deref().is_some_and(|g| g.contains('/')) {
+            bail!("Name and group of '{}' cannot contain '/'.", host.name);
+        }
+        if let Some(name) = host.vars.keys().find(|name| !is_var_name(name)) {
+            bail!("'{name}' is not a variable name (on '{}').", host.name);
+        }
+        if let Some(template) = &host.command_template {
+            ssh::shell_split(template)
+                .with_context(|| format!("command template of '{}'", host.name))?;
+        }
+        if let Some(window) = &host.freeze_window {
+            freeze::parse(window).with_context(|| format!("freeze window of '{}'", host.name))?;
+        }
+        if let Some(zone) = &host.timezone {
+            hosttime::parse_zone(zone).with_context(|| format!("timezone of '{}'", host.name))?;
+        }
+        if let Some(hours) = &host.quiet_hours {
+            freeze::parse(hours).with_context(|| format!("quiet hours of '{}'", host.name))?;
+        }
+        hostkey::validate(host)
+    }
+
+    fn validate_ids(config: &Config) -> Result<()> {
+        let mut seen = HashSet::new();
+        for host in &config.hosts {
+            if !seen.insert(host.id()) {
+                match &host.group {
+                    Some(group) => bail!(
+                        "A host named '{}' already exists in group '{group}'.",
+                        host.name
+                    ),
+                    None => bail!("A host named '{}' already exists.", host.name),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows every bastion chain; references resolve relative to the group
+    /// of the host that wrote them.
+    fn validate_bastions(config: &Config) -> Result<()> {
+        for host in &config.hosts {
+            let Some(bastion_name) = &host.bastion else {
+                continue;
+            };
+            let mut seen: Vec<String> = vec![host.id()];
+            let mut from = host;
+            let mut current = bastion_name.as_str();
+            let mut depth = 0;
+            loop {
+                depth += 1;
+                if depth > config.max_bastion_depth {
+                    bail!(
+                        "Host '{}' goes through more than {} bastions (max_bastion_depth).",
+                        host.id(),
+                        config.max_bastion_depth
+                    );
+                }
+                let bastion = config
+                    .resolve_host(current, from.group.as_deref())
+                    .map_err(|err| anyhow!("Bastion of '{}': {err}.", from.id()))?;
+                let Some(bastion) = bastion else {
+                    break;
+                };
+                let id = bastion.id();
+                if seen.len() == 1 && id == seen[0] {
+                    bail!("Host '{}' cannot use itself as bastion.", id);
+                }
+                if seen.contains(&id) {
+                    bail!("Circular bastion reference detected involving '{}'.", id);
+                }
+                seen.push(id);
+                let Some(next) = &bastion.bastion else { break };
+                from = bastion;
+                current = next;
+            }
+        }
+        Ok(())
+    }
+
+    fn current_index(&self) -> Option<usize> {
+        self.filtered_indices.get(self.selected).cloned()
+    }
+
+    /// Removes the selected host. Hosts that used it as their bastion
+    /// connect directly afterwards, in the same undo step, rather than
+    /// keeping a reference that would only fail at connect time.
+    fn delete_current(&mut self) -> Result<()> {
+        if let Some(idx) = self.current_index() {
+            let removed_name = self.config.hosts.get(idx).map(|h| h.name.clone());
+            let referrers = self.config.bastion_referrers(&self.config.hosts[idx].id());
+            if let Some(name) = removed_name {
+                let mut text = format!("Removed {}.", name);
+                if !referrers.is_empty() {
+                    let names: Vec<String> = referrers
+                        .iter()
+                        .map(|&i| self.config.display_name(&self.config.hosts[i]))
+                        .collect();
+                    text = format!(
+                        "Removed {name}; cleared the bastion of {}.",
+                        names.join(", ")
+                    );
+                }
+                self.status.set(StatusLine {
+                    text,
+                    kind: StatusKind::Warn,
+                });
+            }
+            if referrers.is_empty() {
+                self.apply_change(Change::Remove(idx))?;
+            } else {
+                let mut hosts = self.config.hosts.clone();
+                for i in referrers {
+                    hosts[i].bastion = None;
+                }
+                hosts.remove(idx);
+                self.apply_change(Change::ReplaceHosts(hosts))?;
+            }
+            if self.selected >= self.filtered_indices.len() {
+                self.selected = self.filtered_indices.len().saturating_sub(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// `Y`: opens the Add form with the next host of `host`'s numbered
+    /// series (`web-02` → `web-03`, skipping names already taken). The same
+    /// number in a hostname address follows along; an IPv4 address only
+    /// after a prompt. Names without a trailing number duplicate like `y`.
+    fn duplicate_next(&mut self, host: Host) -> Result<()> {
+        let Some(number) = series::number(&host.name) else {
+            return self.duplicate_host(host);
+        };
+        let group = host.group.as_deref();
+        let Some((step, name)) = (1..=1000)
+            .filter_map(|step| Some((step, series::increment(&host.name, step)?)))
+            .find(|(_, name)| {
+                !self
+                    .config
+                    .hosts
+                    .iter()
+                    .any(|h| &h.name == name && h.group.as_deref() == group)
+            })
+        else {
+            return self.duplicate_host(host);
+        };
+        let mut next = host.clone();
+        next.name = name;
+        if let Some(address) = series::increment_hostname(&host.address, number, step) {
+            next.address = address;
+        } else if let Some(address) = series::increment_ipv4(&host.address, step) {
+            self.confirm = Some(ConfirmKind::SeriesAddress {
+                next: Box::new(next),
+                address,
+            });
+            self.mode = Mode::Confirm;
+            return Ok(());
+        }
+        self.open_series_form(&next);
+        Ok(())
+    }
+
+    fn open_series_form(&mut self, next: &Host) {
+        self.form = Some(FormState::new(FormKind::Add, Some(next), &self.config));
+        self.mode = Mode::Form;
+        self.status.set(StatusLine {
+            text: format!(
+                "New host {} from the series: check the fields, Enter to save.",
+                next.name
+            ),
+            kind: StatusKind::Info,
+        });
+    }
+
+    fn duplicate_host(&mut self, host: Host) -> Result<()> {
+        let base = format!("{}-copy", host.name);
+        let name = self.unique_name(&base, host.group.as_deref());
+        let mut new_host = host.clone();
+        new_host.name = name.clone();
+        // A copy of an overlay host is an ordinary host and gets saved.
+        new_host.overlay = None;
+        new_host.last_connected = None;
+        new_host.connect_count = 0;
+        let id = new_host.id();
+        self.status.set(StatusLine {
             text: format!("Duplicated host to {}.", name),
             kind: StatusKind::Info,
         });
+        self.apply_change(Change::Add(new_host))?;
+        if let Some(pos) = self
+            .filtered_indices
+            .iter()
+            .position(|i| self.config.hosts.get(*i).map(Host::id) == Some(id.clone()))
+        {
+            self.selected = pos;
+        }
         Ok(())
     }
 
-    fn quick_connect(&mut self, spec: SshSpec) -> Result<Option<AppAction>> {
-        // Clear filter to ensure selection works after add/lookup.
-        self.filter.clear();
-        self.rebuild_filter();
+    /// Quick connect from a raw `ssh ...` string, as typed in the `g` prompt.
+    pub fn quick_connect_str(&mut self, input: &str) -> Result<Option<AppAction>> {
+        let spec = parse_ssh_spec(input)?;
+        self.quick_connect(spec)
+    }
+
+    fn quick_connect(&mut self, spec: SshSpec) -> Result<Option<AppAction>> {
+        // Clear filter to ensure selection works after add/lookup.
+        self.filter.clear();
+        self.rebuild_filter();
+
+        let target_idx = if let Some(idx) = self.find_host_by_spec(&spec) {
+            self.status.set(StatusLine {
+                text: "Quick connect using existing host.".into(),
+                kind: StatusKind::Info,
+            });
+            idx
+        } else {
+            let name_base = if let Some(user) = &spec.user {
+                format!("{user}@{}", spec.address)
+            } else {
+                spec.address.clone()
+            };
+            let name = self.unique_name(&name_base, None);
+            let mut host = Host {
+                name: name.clone(),
+                address: spec.address.clone(),
+                user: spec.user.clone(),
+                port: spec.port,
+                key_paths: spec.key_paths.clone(),
+                tags: Vec::new(),
+                options: spec.options.clone(),
+                local_forwards: spec.local_forwards.clone(),
+                remote_forwards: spec.remote_forwards.clone(),
+                remote_command: spec.remote_command.clone(),
+                bastion: spec.bastion.clone(),
+                proxy_command: spec.proxy_command.clone(),
+                set_env: spec.set_env.clone(),
+                prefer_public_key_auth: spec.prefer_public_key_auth,
+                forward_agent: spec.forward_agent,
+                forward_x11: spec.forward_x11,
+                description: None,
+                ..Default::default()
+            };
+            let tagged = match autotag::apply(&self.config.auto_tags, &mut host) {
+                tags if tags.is_empty() => String::new(),
+                tags => format!(" (tagged {})", tags.join(", ")),
+            };
+            self.status.set(StatusLine {
+                text: format!("Added {name}{tagged} and connecting..."),
+                kind: StatusKind::Info,
+            });
+            if let Err(err) = self.apply_change(Change::Add(host)) {
+                self.status.set(StatusLine {
+                    text: format!("Not added: {err:#}"),
+                    kind: StatusKind::Error,
+                });
+                return Ok(None);
+            }
+            self.config
+                .hosts
+                .iter()
+                .position(|h| h.group.is_none() && h.name == name)
+                .unwrap_or(0)
+        };
+
+        if let Some(pos) = self.filtered_indices.iter().position(|i| *i == target_idx) {
+            self.selected = pos;
+        }
+
+        self.connect(None)
+    }
+
+    fn find_host_by_spec(&self, spec: &SshSpec) -> Option<usize> {
+        self.config.hosts.iter().position(|h| {
+            h.address == spec.address
+                && h.user.as_deref() == spec.user.as_deref()
+                && h.port == spec.port
+                && h.key_paths == spec.key_paths
+                && h.options == spec.options
+                && h.local_forwards == spec.local_forwards
+                && h.remote_forwards == spec.remote_forwards
+                && h.bastion.as_deref() == spec.bastion.as_deref()
+                && h.proxy_command.as_deref() == spec.proxy_command.as_deref()
+                && h.prefer_public_key_auth == spec.prefer_public_key_auth
+                && h.forward_agent == spec.forward_agent
+                && h.forward_x11 == spec.forward_x11
+                && h.remote_command.as_deref() == spec.remote_command.as_deref()
+        })
+    }
+
+    /// A name based on `base` that no other host in `group` uses; hosts in
+    /// other groups may share it.
+    fn unique_name(&self, base: &str, group: Option<&str>) -> String {
+        let taken = |name: &str| {
+            self.config
+                .hosts
+                .iter()
+                .any(|h| h.name == name && h.group.as_deref() == group)
+        };
+        if !taken(base) {
+            return base.to_string();
+        }
+        let mut i = 2;
+        loop {
+            let cand = format!("{base}-{i}");
+            if !taken(&cand) {
+                return cand;
+            }
+            i += 1;
+        }
+    }
+
+    fn snapshot(&self) -> HistoryEntry {
+        HistoryEntry {
+            config: self.config.clone(),
+            generation: self.generation,
+        }
+    }
+
+    /// Records the config before a new change; whatever was undone can no
+    /// longer be redone.
+    fn push_history(&mut self) {
+        let entry = self.snapshot();
+        push_capped(&mut self.history, entry, self.config.history_limit);
+        self.redo_stack.clear();
+    }
+
+    /// Undoes the last change, unless its snapshot predates the last
+    /// reload: going back then would also discard whatever changed on disk,
+    /// so it asks first and lists what would be reverted.
+    fn undo(&mut self) -> Result<()> {
+        let Some(entry) = self.history.last() else {
+            self.status.set(StatusLine {
+                text: "Nothing to undo.".into(),
+                kind: StatusKind::Warn,
+            });
+            return Ok(());
+        };
+        if entry.generation != self.generation {
+            self.confirm = Some(ConfirmKind::UndoPastReload {
+                changes: undo_changes(&self.config, &entry.config),
+            });
+            self.mode = Mode::Confirm;
+            return Ok(());
+        }
+        self.apply_undo()
+    }
+
+    fn apply_undo(&mut self) -> Result<()> {
+        let Some(entry) = self.history.last() else {
+            return Ok(());
+        };
+        let snapshot = entry.config.clone();
+        let current = self.snapshot();
+        self.status.set(StatusLine {
+            text: "Undid last change.".into(),
+            kind: StatusKind::Info,
+        });
+        self.apply_change(Change::Restore(snapshot))?;
+        self.history.pop();
+        push_capped(&mut self.redo_stack, current, self.config.history_limit);
+        Ok(())
+    }
+
+    /// Re-applies the change the last undo went back from (`Ctrl+R`).
+    fn redo(&mut self) -> Result<()> {
+        let Some(entry) = self.redo_stack.last() else {
+            self.status.set(StatusLine {
+                text: "Nothing to redo.".into(),
+                kind: StatusKind::Warn,
+            });
+            return Ok(());
+        };
+        let snapshot = entry.config.clone();
+        let current = self.snapshot();
+        self.status.set(StatusLine {
+            text: "Redid last change.".into(),
+            kind: StatusKind::Info,
+        });
+        self.apply_change(Change::Restore(snapshot))?;
+        self.redo_stack.pop();
+        push_capped(&mut self.history, current, self.config.history_limit);
+        Ok(())
+    }
+
+    /// Writes the hosts as ssh_config to `exported_ssh_config` next to the
+    /// config file (`S`), for plain `ssh` and other tools.
+    fn export_ssh_config(&mut self) {
+        if self.store.is_in_memory() {
+            self.status.set(StatusLine {
+                text: "The config is not on disk; nowhere to write the ssh_config.".into(),
+                kind: StatusKind::Warn,
+            });
+            return;
+        }
+        let path = self.config_path.with_file_name("exported_ssh_config");
+        let skipped = self
+            .config
+            .hosts
+            .iter()
+            .filter(|host| export::unexportable(&self.config, host).is_some())
+            .count();
+        let status = match fs::write(&path, export::export_ssh_config(&self.config)) {
+            Ok(()) if skipped > 0 => StatusLine {
+                text: format!(
+                    "Exported {} host(s) as ssh_config to {}; {skipped} skipped (see the comments there).",
+                    self.config.hosts.len() - skipped,
+                    path.display()
+                ),
+                kind: StatusKind::Warn,
+            },
+            Ok(()) => StatusLine {
+                text: format!(
+                    "Exported {} host(s) as ssh_config to {}.",
+                    self.config.hosts.len(),
+                    path.display()
+                ),
+                kind: StatusKind::Info,
+            },
+            Err(err) => StatusLine {
+                text: format!("Could not write {}: {err}", path.display()),
+                kind: StatusKind::Error,
+            },
+        };
+        self.status.set(status);
+    }
+
+    /// `F`: shows the next `[[profiles]]` host set, and after the last the
+    /// default one again. Edits, undo and saving then work on its hosts.
+    fn next_profile(&mut self) {
+        if self.config.profiles.is_empty() {
+            self.status.set(StatusLine {
+                text: "No profiles in the config; add [[profiles]] tables with a name and hosts."
+                    .into(),
+                kind: StatusKind::Info,
+            });
+            return;
+        }
+        let next = self.config.next_profile().map(String::from);
+        if let Err(err) = self.show_profile(next.as_deref()) {
+            self.status.set(StatusLine {
+                text: format!("{err:#}"),
+                kind: StatusKind::Error,
+            });
+            return;
+        }
+        self.status.set(StatusLine {
+            text: format!(
+                "Profile: {} ({} host(s)).",
+                next.as_deref().unwrap_or("default"),
+                self.config.hosts.len()
+            ),
+            kind: StatusKind::Info,
+        });
+    }
+
+    fn show_profile(&mut self, name: Option<&str>) -> Result<()> {
+        self.config.switch_profile(name)?;
+        // Snapshots switch too, so undo compares and restores like for like.
+        for entry in self.history.iter_mut().chain(self.redo_stack.iter_mut()) {
+            let _ = entry.config.switch_profile(name);
+        }
+        self.marked.clear();
+        self.rebuild_filter();
+        Ok(())
+    }
+
+    /// The JSON copy `D` writes and `I` reads: the config path with a
+    /// `.json` extension.
+    fn json_path(&self) -> PathBuf {
+        self.config_path.with_extension("json")
+    }
+
+    /// Writes the config as JSON next to the config file (`D`), for tools
+    /// that sync JSON. Overlay hosts are left out, as when saving.
+    fn export_json(&mut self) {
+        if self.store.is_in_memory() {
+            self.status.set(StatusLine {
+                text: "The config is not on disk; nowhere to write the JSON copy.".into(),
+                kind: StatusKind::Warn,
+            });
+            return;
+        }
+        let path = self.json_path();
+        let persisted = self.persisted();
+        let written = ConfigStore::export_json(&persisted).and_then(|json| {
+            fs::write(&path, json).with_context(|| format!("could not write {}", path.display()))
+        });
+        self.status.set(match written {
+            Ok(()) => StatusLine {
+                text: format!(
+                    "Exported {} host(s) as JSON to {}.",
+                    persisted.hosts.len(),
+                    path.display()
+                ),
+                kind: StatusKind::Info,
+            },
+            Err(err) => StatusLine {
+                text: format!("JSON export failed: {err:#}"),
+                kind: StatusKind::Error,
+            },
+        });
+    }
+
+    /// Replaces the config with its JSON copy (`I`). It is validated like
+    /// any change, so unknown or looping bastions leave the config as it
+    /// was, and `u` undoes it.
+    fn import_json(&mut self) {
+        if self.store.is_in_memory() {
+            self.status.set(StatusLine {
+                text: "The config is not on disk; there is no JSON copy to import.".into(),
+                kind: StatusKind::Warn,
+            });
+            return;
+        }
+        let path = self.json_path();
+        let imported = fs::read_to_string(&path)
+            .with_context(|| format!("could not read {}", path.display()))
+            .and_then(|text| ConfigStore::import_json(&text))
+            .and_then(|config| {
+                // Set first, so a warning about the imported [keys] shows over it.
+                self.status.set(StatusLine {
+                    text: format!(
+                        "Imported {} host(s) from {}; {} undoes it.",
+                        config.hosts.len(),
+                        path.display(),
+                        KeyMap::new(&config.keys).0.label(KeyAction::Undo)
+                    ),
+                    kind: StatusKind::Info,
+                });
+                self.apply_change(Change::Import(config))
+            });
+        if let Err(err) = imported {
+            self.status.set(StatusLine {
+                text: format!("JSON import failed, nothing changed: {err:#}"),
+                kind: StatusKind::Error,
+            });
+        }
+    }
+
+    /// Forgets the snapshots taken before the last reload.
+    fn drop_stale_history(&mut self) {
+        let generation = self.generation;
+        let before = self.history.len();
+        self.history.retain(|entry| entry.generation == generation);
+        self.status.set(StatusLine {
+            text: format!(
+                "Dropped {} undo step(s) from before the reload.",
+                before - self.history.len()
+            ),
+            kind: StatusKind::Info,
+        });
+    }
+
+    /// `text` as the screen shows it: masked while privacy mode is on.
+    pub fn display<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.privacy {
+            self.masker.mask(text)
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+
+    fn toggle_privacy(&mut self) {
+        self.privacy = !self.privacy;
+        self.status.set(StatusLine {
+            text: if self.privacy {
+                format!(
+                    "Privacy mode on: addresses and key paths are masked on screen ({} to show).",
+                    self.key(KeyAction::Privacy)
+                )
+            } else {
+                "Privacy mode off.".into()
+            },
+            kind: StatusKind::Info,
+        });
+    }
+
+    /// Whether the frontend should ring the bell for a newly shown error.
+    pub fn take_bell(&mut self) -> bool {
+        self.status.take_bell() && self.config.bell_on_error
+    }
+
+    /// Command preview for the selected host, recomputed only when the
+    /// selection or the host list changes.
+    pub fn selected_preview(&self) -> Option<String> {
+        let idx = *self.filtered_indices.get(self.selected)?;
+        if let Some((cached, preview)) = self.preview_cache.borrow().as_ref() {
+            if *cached == idx {
+                return Some(preview.clone());
+            }
+        }
+        let host = self.config.hosts.get(idx)?;
+        let preview =
+            ssh::command_preview(host, &self.config, self.config.default_key.as_deref(), None);
+        *self.preview_cache.borrow_mut() = Some((idx, preview.clone()));
+        Some(preview)
+    }
+
+    /// The host's configured key paths (its own, else `default_key`) that
+    /// aren't on disk, as written in the config. Looked up once per host
+    /// and kept until the config changes or is reloaded; connecting is
+    /// not affected.
+    pub fn missing_keys(&self, host: &Host) -> Vec<String> {
+        let default_key = self.config.default_key.as_deref();
+        let (keys, source) = ssh::choose_keys(&host.key_paths, default_key);
+        let configured: Vec<&str> = match source {
+            ssh::KeySource::Host => host.key_paths.iter().map(String::as_str).collect(),
+            ssh::KeySource::DefaultKey => default_key.into_iter().collect(),
+            _ => return Vec::new(),
+        };
+        let id = host.id();
+        if let Some(check) = self.key_files.borrow().get(&id) {
+            if check.paths == keys {
+                return check.missing.clone();
+            }
+        }
+        let missing: Vec<String> = configured
+            .into_iter()
+            .zip(&keys)
+            .filter(|(_, path)| !std::path::Path::new(path).exists())
+            .map(|(key, _)| key.to_string())
+            .collect();
+        self.key_files.borrow_mut().insert(
+            id,
+            KeyFileCheck {
+                paths: keys,
+                missing: missing.clone(),
+            },
+        );
+        missing
+    }
+
+    /// Opens the connect modal (`c`); plain Enter lands here too for
+    /// protected hosts and while the host is inside its freeze window or
+    /// quiet hours.
+    fn open_connect_confirm(&mut self) {
+        let Some(host) = self.current_host() else {
+            return;
+        };
+        let request_tty = host.request_tty;
+        let probe = (host.is_protected()
+            && host.bastion.is_none()
+            && host.command_template.is_none()
+            && !preflight::skips(&self.config, preflight::Check::Port))
+        .then(|| (host.address.clone(), host.port.unwrap_or(22)));
+        if let Some((address, port)) = probe {
+            self.probes.request(&address, port, Instant::now());
+        }
+        self.confirm = Some(ConfirmKind::Connect {
+            extra_cmd: String::new(),
+            request_tty,
+            freeze_armed: false,
+        });
+        self.mode = Mode::Confirm;
+    }
+
+    /// Whether plain Enter goes through the connect modal instead of
+    /// connecting straight away.
+    fn enter_needs_confirm(&self) -> bool {
+        self.current_host().is_some_and(Host::is_protected)
+            || self.current_freeze().is_some()
+            || self.current_quiet_hours().is_some()
+    }
+
+    /// The pre-flight checklist for the selected host; `None` unless it is
+    /// protected.
+    pub fn preflight(&self) -> Option<Vec<preflight::CheckResult>> {
+        let host = self.current_host().filter(|host| host.is_protected())?;
+        Some(preflight::run(&self.config, host, self))
+    }
+
+    /// The selected host's `freeze_window` when it is in effect now.
+    pub fn current_freeze(&self) -> Option<&str> {
+        self.current_host()
+            .and_then(|host| self.active_freeze(host))
+    }
+
+    /// The current time, or the time `set_clock` fixed.
+    pub fn now(&self) -> SystemTime {
+        self.clock.unwrap_or_else(SystemTime::now)
+    }
+
+    /// Stops the clock at `time` (`None` restarts it), so anything that
+    /// depends on the time of day renders the same on every run.
+    pub fn set_clock(&mut self, time: Option<SystemTime>) {
+        self.clock = time;
+    }
+
+    /// `host.freeze_window` if the current time is inside it; unparsable
+    /// windows never match (the details pane shows why).
+    pub fn active_freeze<'a>(&self, host: &'a Host) -> Option<&'a str> {
+        let spec = host.freeze_window.as_deref()?;
+        let zone = freeze::Zone::parse(self.config.freeze_timezone.as_deref()).ok()?;
+        freeze::active_at_time(spec, zone, self.now())
+            .ok()?
+            .then_some(spec)
+    }
+
+    /// `host`'s wall-clock time as `03:12 (Asia/Tokyo)`; `Err` for a
+    /// `timezone` that isn't in the tz database.
+    pub fn host_local_time(&self, host: &Host) -> Option<Result<String>> {
+        let name = host.timezone.as_deref()?;
+        Some(hosttime::parse_zone(name).map(|tz| {
+            format!(
+                "{} ({})",
+                hosttime::local_time(tz, self.unix_now()),
+                tz.name()
+            )
+        }))
+    }
+
+    /// The selected host's quiet-hours warning, when it is inside them now.
+    pub fn current_quiet_hours(&self) -> Option<String> {
+        self.current_host()
+            .and_then(|host| self.quiet_hours_warning(host))
+    }
+
+    /// Why connecting to `host` now may be a bad idea, if the time is inside
+    /// its `quiet_hours`; unparsable settings never match.
+    pub fn quiet_hours_warning(&self, host: &Host) -> Option<String> {
+        let spec = host.quiet_hours.as_deref()?;
+        let tz = match host.timezone.as_deref() {
+            Some(name) => Some(hosttime::parse_zone(name).ok()?),
+            None => None,
+        };
+        hosttime::in_quiet_hours(spec, tz, self.unix_now())
+            .ok()?
+            .then(|| {
+                let clock = tz.map_or_else(String::new, |tz| {
+                    format!(
+                        " (it is {} there)",
+                        hosttime::local_time(tz, self.unix_now())
+                    )
+                });
+                format!("inside the quiet hours of {} ({spec}){clock}", host.name)
+            })
+    }
+
+    fn unix_now(&self) -> i64 {
+        self.now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64)
+    }
+
+    pub fn connect(&mut self, extra: Option<String>) -> Result<Option<AppAction>> {
+        self.connect_with(extra, ConnectOverrides::default())
+    }
+
+    /// Plain Enter: connects, unless the host's stored remote command would
+    /// run with nothing on screen saying so (`confirm_remote_command`).
+    fn connect_selected(&mut self) -> Result<Option<AppAction>> {
+        let stored = self
+            .current_host()
+            .and_then(|host| host.remote_command.clone())
+            .filter(|command| !command.trim().is_empty());
+        match stored {
+            Some(command) if self.config.confirm_remote_command => {
+                self.confirm = Some(ConfirmKind::RemoteCommand { command });
+                self.mode = Mode::Confirm;
+                Ok(None)
+            }
+            _ => self.connect(None),
+        }
+    }
+
+    /// `G`: connects to the selected host for real even with dry-run on,
+    /// without the remote command prompt. Hosts that Enter would stop for
+    /// (protected, frozen, quiet hours) are refused: their checklist only
+    /// connects with dry-run off.
+    pub fn connect_force(&mut self) -> Result<Option<AppAction>> {
+        let Some(host) = self.current_host() else {
+            return Ok(None);
+        };
+        if self.enter_needs_confirm() {
+            self.status.set(StatusLine {
+                text: format!(
+                    "{} doesn't skip the checks for {}; press {} to turn dry-run off, then {}.",
+                    self.key(KeyAction::ConnectForce),
+                    self.config.display_name(host),
+                    self.key(KeyAction::DryRun),
+                    self.key(KeyAction::Connect)
+                ),
+                kind: StatusKind::Warn,
+            });
+            return Ok(None);
+        }
+        self.connect_with(
+            None,
+            ConnectOverrides {
+                bypass_dry_run: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Connects to the selected host, optionally as a different user for this
+    /// invocation only. Bastion hops keep their own users; nothing is saved.
+    pub fn connect_as(
+        &mut self,
+        extra: Option<String>,
+        user_override: Option<String>,
+    ) -> Result<Option<AppAction>> {
+        self.connect_with(
+            extra,
+            ConnectOverrides {
+                user: user_override,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn connect_with(
+        &mut self,
+        extra: Option<String>,
+        overrides: ConnectOverrides,
+    ) -> Result<Option<AppAction>> {
+        let Some(host) = self.current_host().cloned() else {
+            self.status.set(StatusLine {
+                text: "No host selected.".into(),
+                kind: StatusKind::Warn,
+            });
+            return Ok(None);
+        };
+        self.connect_host(host, extra, overrides)
+    }
+
+    /// `b`: connects straight to the selected host's bastion with the
+    /// bastion's own settings, asking which one when the chain has several
+    /// jump hosts. The selection stays where it is.
+    fn connect_bastion(&mut self) -> Result<Option<AppAction>> {
+        let Some(host) = self.current_host().cloned() else {
+            return Ok(None);
+        };
+        let Some(reference) = host.bastion.as_deref() else {
+            self.status.set(StatusLine {
+                text: format!("{} has no bastion.", self.config.display_name(&host)),
+                kind: StatusKind::Warn,
+            });
+            return Ok(None);
+        };
+        let hops = match ssh::bastion_chain(&self.config, &host) {
+            Ok(hops) => hops,
+            Err(err) => {
+                self.status.set(StatusLine {
+                    text: format!("Bastion of {}: {err}.", host.id()),
+                    kind: StatusKind::Error,
+                });
+                return Ok(None);
+            }
+        };
+        // A hop that is no host here is a raw address on the host that
+        // names it; only the direct bastion has to be a host to connect to.
+        let ids: Vec<String> = hops.into_iter().filter_map(|hop| hop.id).collect();
+        let direct = self
+            .config
+            .resolve_host(reference, host.group.as_deref())
+            .ok()
+            .flatten();
+        if direct.is_none() {
+            let similar = self.config.similar_hosts(reference);
+            let text = if similar.is_empty() {
+                format!(
+                    "Bastion '{reference}' of {} is not a host in the list.",
+                    host.id()
+                )
+            } else {
+                format!(
+                    "Bastion '{reference}' of {} is not a host in the list (did you mean {}?).",
+                    host.id(),
+                    similar.join(", ")
+                )
+            };
+            self.status.set(StatusLine {
+                text,
+                kind: StatusKind::Error,
+            });
+            return Ok(None);
+        }
+        match ids.as_slice() {
+            [id] => {
+                let id = id.clone();
+                self.connect_to_bastion(&id)
+            }
+            _ => {
+                self.confirm = Some(ConfirmKind::PickBastion {
+                    selected: ids.len() - 1,
+                    hops: ids,
+                });
+                self.mode = Mode::Confirm;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Connects to the jump host `id` of the selected host.
+    fn connect_to_bastion(&mut self, id: &str) -> Result<Option<AppAction>> {
+        let (Some(bastion), Some(host)) = (
+            self.config.find_host(id).cloned(),
+            self.current_host().map(Host::id),
+        ) else {
+            return Ok(None);
+        };
+        let preview = ssh::command_preview(
+            &bastion,
+            &self.config,
+            self.config.default_key.as_deref(),
+            None,
+        );
+        let action = self.connect_host(bastion, None, ConnectOverrides::default())?;
+        if let Some(AppAction::RunSsh(_)) = &action {
+            self.status.set(StatusLine {
+                text: format!("Connecting to bastion {id} of {host} with: {preview}"),
+                kind: StatusKind::Info,
+            });
+            self.session_note = Some(format!("Connected to bastion {id} of {host}."));
+        }
+        Ok(action)
+    }
+
+    /// Typing fills the highlighted path, Tab switches between them, Enter
+    /// copies and Esc closes.
+    fn handle_transfer(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
+        let Some(transfer) = self.transfer.as_mut() else {
+            self.mode = Mode::Normal;
+            return Ok(None);
+        };
+        match key.code {
+            KeyCode::Esc => {
+                self.transfer = None;
+                self.mode = Mode::Normal;
+            }
+            KeyCode::Tab | KeyCode::BackTab | KeyCode::Up | KeyCode::Down => {
+                transfer.editing_remote = !transfer.editing_remote;
+            }
+            KeyCode::Backspace => {
+                transfer.editing().pop();
+            }
+            KeyCode::Char(c)
+                if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT =>
+            {
+                transfer.editing().push(c);
+            }
+            KeyCode::Enter => return self.start_transfer(),
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn handle_settings(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
+        let Some(settings) = self.settings.as_mut() else {
+            self.mode = Mode::Normal;
+            return Ok(None);
+        };
+        match key.code {
+            KeyCode::Esc => {
+                self.settings = None;
+                self.mode = Mode::Normal;
+            }
+            KeyCode::Tab | KeyCode::BackTab => {
+                if settings.default_key.trim() == "agent" {
+                    settings.default_key = std::mem::take(&mut settings.other_key);
+                } else {
+                    settings.other_key =
+                        std::mem::replace(&mut settings.default_key, "agent".into());
+                }
+            }
+            KeyCode::Backspace => {
+                settings.default_key.pop();
+            }
+            KeyCode::Char(c)
+                if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT =>
+            {
+                settings.default_key.push(c);
+            }
+            KeyCode::Enter => {
+                let key = non_empty(&settings.default_key);
+                self.settings = None;
+                self.mode = Mode::Normal;
+                if key == self.config.default_key {
+                    return Ok(None);
+                }
+                self.status.set(StatusLine {
+                    text: match &key {
+                        Some(key) => format!("Default key set to {key}."),
+                        None => "Default key cleared; ssh picks keys itself.".into(),
+                    },
+                    kind: StatusKind::Info,
+                });
+                if let Err(err) = self.apply_change(Change::SetDefaultKey(key)) {
+                    self.status.set(StatusLine {
+                        text: format!("Default key not changed: {err:#}"),
+                        kind: StatusKind::Error,
+                    });
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// The scp command the `f` prompt would run, quoted for display.
+    pub fn transfer_preview(&self) -> Option<String> {
+        let (host, transfer) = (self.current_host()?, self.transfer.as_ref()?);
+        Some(ssh::scp_preview(
+            host,
+            &self.config,
+            self.config.default_key.as_deref(),
+            transfer.local.trim(),
+            transfer.remote.trim(),
+        ))
+    }
+
+    /// Copies the prompt's file to the selected host with scp, or shows
+    /// the command in dry-run, like connecting does.
+    fn start_transfer(&mut self) -> Result<Option<AppAction>> {
+        let (Some(host), Some(transfer)) = (self.current_host().cloned(), self.transfer.clone())
+        else {
+            return Ok(None);
+        };
+        let (local, remote) = (transfer.local.trim(), transfer.remote.trim());
+        if local.is_empty() {
+            self.status.set(StatusLine {
+                text: "Type the local file to copy first.".into(),
+                kind: StatusKind::Warn,
+            });
+            return Ok(None);
+        }
+        let default_key = self.config.default_key.as_deref();
+        if let Err(err) = ssh::build_scp_argv(&host, &self.config, default_key, local, remote) {
+            self.status.set(StatusLine {
+                text: format!("Not copying to {}: {err}", host.name),
+                kind: StatusKind::Error,
+            });
+            return Ok(None);
+        }
+        let preview = ssh::scp_preview(&host, &self.config, default_key, local, remote);
+        self.transfer = None;
+        self.mode = Mode::Normal;
+        if self.dry_run {
+            self.status.set(StatusLine {
+                text: format!("Dry-run: {preview}"),
+                kind: StatusKind::Info,
+            });
+            return Ok(None);
+        }
+        if let Err(unavailable) = (self.find_program)(ssh::SCP) {
+            self.status.set(StatusLine {
+                text: format!(
+                    "{} Install an OpenSSH client; dry-run (C) still works.",
+                    unavailable.message(ssh::SCP)
+                ),
+                kind: StatusKind::Error,
+            });
+            return Ok(None);
+        }
+        let cmd = ssh::build_scp_command(&host, &self.config, default_key, local, remote)?;
+        self.watch = None;
+        self.status.set(StatusLine {
+            text: format!("Copying with: {preview}"),
+            kind: StatusKind::Info,
+        });
+        let remote = if remote.is_empty() { "~" } else { remote };
+        self.session_note = Some(format!("Copied {local} to {}:{remote}.", host.id()));
+        Ok(Some(AppAction::RunSsh(cmd)))
+    }
+
+    /// What to say once the session just run ended cleanly, when it was
+    /// not to the selected host itself.
+    pub fn take_session_note(&mut self) -> Option<String> {
+        self.session_note.take()
+    }
+
+    fn connect_host(
+        &mut self,
+        mut host: Host,
+        extra: Option<String>,
+        overrides: ConnectOverrides,
+    ) -> Result<Option<AppAction>> {
+        let mut as_user = overrides
+            .user
+            .as_ref()
+            .map(|user| format!(" as {user}"))
+            .unwrap_or_default();
+        if overrides.skip_remote_command && host.remote_command.take().is_some() {
+            as_user.push_str(" without its remote command");
+        }
+        if let Some(user) = overrides.user {
+            host.user = Some(user);
+        }
+        if let Some(request_tty) = overrides.request_tty {
+            host.request_tty = request_tty;
+        }
+
+        if !self.can_build_command(&host, extra.as_deref()) {
+            return Ok(None);
+        }
+        let preview = ssh::command_preview(
+            &host,
+            &self.config,
+            self.config.default_key.as_deref(),
+            extra.as_deref(),
+        );
+
+        if self.dry_run && !overrides.bypass_dry_run {
+            self.status.set(StatusLine {
+                text: format!("Dry-run{as_user}: {preview}"),
+                kind: StatusKind::Info,
+            });
+            return Ok(None);
+        }
+        if host.command_template.is_none() && !self.check_ssh_binary() {
+            return Ok(None);
+        }
+        let connecting = if self.dry_run {
+            "Forcing real connection (dry-run bypassed)"
+        } else {
+            "Connecting"
+        };
+
+        let cmd = ssh::build_command(
+            &host,
+            &self.config,
+            self.config.default_key.as_deref(),
+            extra.as_deref(),
+        )?;
+        // The interactive session needs the terminal; a watch would keep
+        // spawning ssh processes behind it.
+        self.watch = None;
+        self.status.set(StatusLine {
+            text: format!("{connecting}{as_user} with: {preview}"),
+            kind: StatusKind::Info,
+        });
+        if let Err(err) = self.record_connection(&host.id()) {
+            self.status.set(StatusLine {
+                text: format!(
+                    "{connecting}{as_user} with: {preview} (connection time not saved: {err:#})"
+                ),
+                kind: StatusKind::Warn,
+            });
+        }
+        Ok(Some(AppAction::RunSsh(cmd)))
+    }
+
+    /// Records how a session to `host_id` ended: a failure becomes the
+    /// host's last error, naming the hop that broke when ssh's stderr said
+    /// so, and a clean end clears it. Returns the failure message.
+    pub fn session_ended(&mut self, host_id: &str, result: &Result<()>) -> Option<String> {
+        let Err(err) = result else {
+            self.last_errors.remove(host_id);
+            return None;
+        };
+        let diagnosis = err
+            .downcast_ref::<ssh::SessionFailed>()
+            .and_then(|failed| failed.diagnosis.clone());
+        let message = match &diagnosis {
+            Some(
+                diagnosis @ Diagnosis {
+                    at: failure::At::Hop { .. },
+                    ..
+                },
+            ) => format!("ssh to {host_id} {diagnosis}"),
+            Some(diagnosis) => format!("ssh to {host_id} failed: {diagnosis}"),
+            None => format!("ssh failed: {err}"),
+        };
+        self.last_errors.insert(
+            host_id.to_string(),
+            LastError {
+                message: message.clone(),
+                diagnosis,
+            },
+        );
+        Some(message)
+    }
+
+    /// Whether the command for `host` can be put together; if not (an unset
+    /// `{{var}}`, a bastion loop, ...) says why as an Error instead.
+    fn can_build_command(&mut self, host: &Host, extra: Option<&str>) -> bool {
+        let default_key = self.config.default_key.as_deref();
+        match ssh::build_argv(host, &self.config, default_key, extra) {
+            Ok(_) => true,
+            Err(err) => {
+                self.status.set(StatusLine {
+                    text: format!("Not connecting to {}: {err}", host.name),
+                    kind: StatusKind::Error,
+                });
+                false
+            }
+        }
+    }
+
+    /// Re-runs `command` (or the host's saved remote command) on the watch
+    /// interval in non-interactive ssh and switches to the watch pane.
+    fn start_watch(&mut self, command: &str) -> Result<()> {
+        let Some(mut host) = self.current_host().cloned() else {
+            return Ok(());
+        };
+        let command = if command.is_empty() {
+            host.remote_command.clone().unwrap_or_default()
+        } else {
+            command.to_string()
+        };
+        if command.trim().is_empty() {
+            self.status.set(StatusLine {
+                text: "Watch needs a remote command; type one before pressing Tab.".into(),
+                kind: StatusKind::Warn,
+            });
+            return Ok(());
+        }
+        // Never stop for a password prompt in the background.
+        host.options.push("-oBatchMode=yes".into());
+        if !self.can_build_command(&host, Some(&command)) {
+            return Ok(());
+        }
+        let interval = Duration::from_secs(self.config.watch_interval_secs.max(1));
+        let default_key = self.config.default_key.as_deref();
+        if self.dry_run {
+            let preview = ssh::command_preview(&host, &self.config, default_key, Some(&command));
+            self.status.set(StatusLine {
+                text: format!(
+                    "Dry-run: would run every {}s: {preview}",
+                    interval.as_secs()
+                ),
+                kind: StatusKind::Info,
+            });
+            return Ok(());
+        }
+        if host.command_template.is_none() && !self.check_ssh_binary() {
+            return Ok(());
+        }
+        let default_key = self.config.default_key.as_deref();
+        let (cmd, known_hosts) =
+            ssh::build_command(&host, &self.config, default_key, Some(&command))?.into_parts();
+        let program = cmd.get_program().to_string_lossy().into_owned();
+        let args = cmd
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        self.watch = Some(Watcher::new(
+            format!("{}: {command}", host.display_label()),
+            interval,
+            watch::command_runner(program, args, known_hosts),
+        ));
+        self.mode = Mode::Watch;
+        self.status.set(StatusLine {
+            text: format!(
+                "Watching every {}s. j/k browse runs, +/- interval, Enter connects, Esc stops.",
+                interval.as_secs()
+            ),
+            kind: StatusKind::Info,
+        });
+        Ok(())
+    }
+
+    fn handle_watch(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
+        let Some(watcher) = self.watch.as_mut() else {
+            self.mode = Mode::Normal;
+            return Ok(None);
+        };
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => {
+                self.watch = None;
+                self.mode = Mode::Normal;
+                self.status.set(StatusLine {
+                    text: "Stopped watching.".into(),
+                    kind: StatusKind::Info,
+                });
+            }
+            KeyCode::Char('k') | KeyCode::Up => watcher.older(),
+            KeyCode::Char('j') | KeyCode::Down => watcher.newer(),
+            KeyCode::Char('+') => watcher.interval += Duration::from_secs(1),
+            KeyCode::Char('-') if watcher.interval > Duration::from_secs(1) => {
+                watcher.interval -= Duration::from_secs(1);
+            }
+            KeyCode::Enter => {
+                self.mode = Mode::Normal;
+                return self.connect(None);
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    pub fn problems(&self) -> Vec<Problem> {
+        let mut problems = problems::find(&self.config, |address| self.resolver.classify(address));
+        if let Some(message) = &self.ssh_unavailable {
+            problems.insert(
+                0,
+                Problem {
+                    index: None,
+                    kind: ProblemKind::SshUnavailable,
+                    message: message.clone(),
+                },
+            );
+        }
+        problems
+    }
+
+    /// Looks for the ssh binary again (`PATH` or `ssh_binary` may have
+    /// changed since the last check). When it can't be run, says why on the
+    /// status bar and returns false.
+    pub fn check_ssh_binary(&mut self) -> bool {
+        let binary = self.config.ssh_binary();
+        self.ssh_unavailable = (self.find_program)(binary).err().map(|unavailable| {
+            format!(
+                "{} Install an OpenSSH client or set ssh_binary in the config; dry-run (C) still works.",
+                unavailable.message(binary)
+            )
+        });
+        match &self.ssh_unavailable {
+            Some(message) => {
+                self.status.set(StatusLine {
+                    text: message.clone(),
+                    kind: StatusKind::Error,
+                });
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn open_problems(&mut self) {
+        if self.problems().is_empty() {
+            self.status.set(StatusLine {
+                text: "No problems found.".into(),
+                kind: StatusKind::Info,
+            });
+            return;
+        }
+        self.problem_selected = 0;
+        self.mode = Mode::Problems;
+    }
+
+    /// Up/Down pick a problem, Enter shows its host, `a` acknowledges an
+    /// ssh_config alias, Esc closes.
+    fn handle_problems(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            self.mode = Mode::Normal;
+            return Ok(None);
+        }
+        self.problem_selected = self.problem_selected.min(problems.len() - 1);
+        let problem = &problems[self.problem_selected];
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('!') => {
+                self.mode = Mode::Normal;
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.problem_selected = self.problem_selected.saturating_sub(1);
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.problem_selected = (self.problem_selected + 1).min(problems.len() - 1);
+            }
+            KeyCode::Enter => {
+                if let Some(index) = problem.index {
+                    self.mode = Mode::Normal;
+                    self.show_host(index);
+                }
+            }
+            KeyCode::Char('a') if problem.kind == ProblemKind::SshConfigAlias => {
+                let Some(index) = problem.index else {
+                    return Ok(None);
+                };
+                let mut host = self.config.hosts[index].clone();
+                host.alias_ok = true;
+                let text = format!("Marked '{}' as an intended ssh_config alias.", host.address);
+                self.status.set(StatusLine {
+                    text,
+                    kind: StatusKind::Info,
+                });
+                self.apply_change(Change::Edit { index, host })?;
+                if problems.len() == 1 {
+                    self.mode = Mode::Normal;
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn export_bundle(&self) -> Result<String> {
+        let path = bundle::default_path();
+        let manifest = bundle::export(&self.store, &path, true)?;
+        Ok(format!(
+            "Wrote {} ({} hosts: {}).",
+            path.display(),
+            manifest.hosts,
+            manifest.files.join(", ")
+        ))
+    }
+
+    /// `e` exports a bundle to `bundle::default_path()`, `i` reads one from
+    /// there and lists what importing would change, `y` then applies it.
+    fn handle_bundle(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
+        let path = bundle::default_path();
+        let result = match (key.code, self.bundle_import.take()) {
+            (KeyCode::Esc | KeyCode::Char('q'), _) => Ok(None),
+            (KeyCode::Char('e'), None) if self.privacy => {
+                self.mode = Mode::Confirm;
+                self.confirm = Some(ConfirmKind::Unmasked(UnmaskedAction::ExportBundle));
+                return Ok(None);
+            }
+            (KeyCode::Char('e'), None) => self.export_bundle().map(Some),
+            (KeyCode::Char('i'), None) => match bundle::read(&path) {
+                Ok(bundle) => {
+                    let plan = bundle::plan(&bundle, &self.store);
+                    self.bundle_import = Some((bundle, plan));
+                    return Ok(None);
+                }
+                Err(err) => Err(err),
+            },
+            (KeyCode::Char('y'), Some((bundle, _))) => match &self.read_only {
+                Some(reason) => Err(anyhow!("not imported: {reason}")),
+                // The bundle replaces the file wholesale, so it gets the
+                // same checks as any other change before it is written.
+                None => Self::validate(bundle.config(), &self.config)
+                    .and_then(|()| bundle::apply(&bundle, &self.store))
+                    .and_then(|()| self.reload_config())
+                    .map(|()| Some(format!("Imported {}.", path.display()))),
+            },
+            (_, pending) => {
+                self.bundle_import = pending;
+                return Ok(None);
+            }
+        };
+        self.mode = Mode::Normal;
+        match result {
+            Ok(Some(text)) => self.status.set(StatusLine {
+                text,
+                kind: StatusKind::Info,
+            }),
+            Ok(None) => {}
+            Err(err) => self.status.set(StatusLine {
+                text: format!("Bundle: {err:#}"),
+                kind: StatusKind::Error,
+            }),
+        }
+        Ok(None)
+    }
+
+    /// The marked hosts after the pending bulk edit, with the hosts it
+    /// changes. The whole resulting config is validated.
+    fn bulk_result(&self) -> Result<(Vec<Host>, Vec<BulkChange>)> {
+        let edit = self.bulk.clone().unwrap_or_default();
+        let (hosts, changes) =
+            bulk::apply(&self.config.hosts, &self.marked, edit.field(), &edit.value)?;
+        let mut config = self.config.clone();
+        config.hosts = hosts;
+        Self::validate_ids(&config)?;
+        Self::validate_bastions(&config)?;
+        Ok((config.hosts, changes))
+    }
+
+    /// What the bulk-edit modal previews.
+    pub fn bulk_preview(&self) -> Result<Vec<BulkChange>> {
+        self.bulk_result().map(|(_, changes)| changes)
+    }
+
+    fn handle_bulk(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
+        let Some(edit) = self.bulk.as_mut() else {
+            self.mode = Mode::Normal;
+            return Ok(None);
+        };
+        match key.code {
+            KeyCode::Esc => {
+                self.bulk = None;
+                self.mode = Mode::Normal;
+            }
+            KeyCode::Tab | KeyCode::Down => edit.cycle(1),
+            KeyCode::BackTab | KeyCode::Up => edit.cycle(-1),
+            KeyCode::Backspace => {
+                edit.value.pop();
+            }
+            KeyCode::Char(c)
+                if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT =>
+            {
+                edit.value.push(c);
+            }
+            KeyCode::Enter => self.apply_bulk()?,
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Applies the bulk edit as one undo step and one save.
+    fn apply_bulk(&mut self) -> Result<()> {
+        let (hosts, changes) = match self.bulk_result() {
+            Ok(result) => result,
+            Err(err) => {
+                self.status.set(StatusLine {
+                    text: format!("Bulk edit: {err:#}"),
+                    kind: StatusKind::Error,
+                });
+                return Ok(());
+            }
+        };
+        if changes.is_empty() {
+            self.status.set(StatusLine {
+                text: "Bulk edit: nothing to change on the marked hosts.".into(),
+                kind: StatusKind::Warn,
+            });
+            return Ok(());
+        }
+        let field = self.bulk.take().unwrap_or_default().field();
+        self.mode = Mode::Normal;
+        self.status.set(StatusLine {
+            text: format!(
+                "Bulk edit: changed {} on {} host(s).",
+                field.label(),
+                changes.len()
+            ),
+            kind: StatusKind::Info,
+        });
+        if let Err(err) = self.apply_change(Change::ReplaceHosts(hosts)) {
+            self.status.set(StatusLine {
+                text: format!("Bulk edit: {err:#}"),
+                kind: StatusKind::Error,
+            });
+        }
+        Ok(())
+    }
+
+    /// How far the details pane of the selected host is scrolled.
+    pub fn details_offset(&self) -> u16 {
+        match (&self.details_scroll, self.current_host()) {
+            ((id, lines), Some(host)) if *id == host.id() => {
+                (*lines).min(self.details_max_scroll.get())
+            }
+            _ => 0,
+        }
+    }
+
+    /// Called by the details pane as it draws, so PageDown stops at its
+    /// last line.
+    pub fn set_details_max_scroll(&self, lines: u16) {
+        self.details_max_scroll.set(lines);
+    }
+
+    /// Why the selected host connects the way it does.
+    pub fn current_explanation(&self) -> Option<ssh::Explanation> {
+        self.current_host()
+            .map(|host| ssh::explain(host, &self.config, self.config.default_key.as_deref(), None))
+    }
+
+    /// The selected host's command for the `V` modal, one argument per line.
+    pub fn current_command_lines(&self) -> Option<Result<Vec<String>>> {
+        self.current_host().map(|host| {
+            ssh::command_lines(host, &self.config, self.config.default_key.as_deref(), None)
+        })
+    }
+
+    fn handle_explain(&mut self, key: KeyEvent) -> Result<Option<AppAction>> {
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('i') => {
+                self.mode = Mode::Normal;
+            }
+            KeyCode::Char('j') | KeyCode::Down => {
+                self.explain_scroll = self.explain_scroll.saturating_add(1);
+            }
+            KeyCode::Char('k') | KeyCode::Up => {
+                self.explain_scroll = self.explain_scroll.saturating_sub(1);
+            }
+            KeyCode::PageDown => self.explain_scroll = self.explain_scroll.saturating_add(10),
+            KeyCode::PageUp => self.explain_scroll = self.explain_scroll.saturating_sub(10),
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Selects the host at `index`, clearing the filter if it hides it.
+    fn show_host(&mut self, index: usize) {
+        if !self.filtered_indices.contains(&index) {
+            self.filter.clear();
+            self.rebuild_filter();
+        }
+        if let Some(pos) = self.filtered_indices.iter().position(|i| *i == index) {
+            self.selected = pos;
+        }
+    }
+
+    fn current_connection_string(&self) -> Option<String> {
+        self.current_host().map(|host| {
+            ssh::command_preview(host, &self.config, self.config.default_key.as_deref(), None)
+        })
+    }
+
+    /// `l`: picks the selected host as a bastion, then, on a second host,
+    /// makes that host connect through it. The change is validated like
+    /// an edit, so a cycle is refused and nothing is saved.
+    fn link_bastion(&mut self) {
+        let Some(index) = self.current_index() else {
+            return;
+        };
+        let target = &self.config.hosts[index];
+        let Some(bastion_id) = self.pending_bastion.clone() else {
+            self.pending_bastion = Some(target.id());
+            self.status.set(StatusLine {
+                text: format!(
+                    "Bastion: {}. Select the host to reach through it and press {}; Esc cancels.",
+                    self.config.display_name(target),
+                    self.key(KeyAction::LinkBastion)
+                ),
+                kind: StatusKind::Info,
+            });
+            return;
+        };
+        if bastion_id == target.id() {
+            self.pending_bastion = None;
+            self.status.set(StatusLine {
+                text: "No bastion picked.".into(),
+                kind: StatusKind::Info,
+            });
+            return;
+        }
+        self.pending_bastion = None;
+        let mut host = target.clone();
+        if host.proxy_command.is_some() {
+            self.status.set(StatusLine {
+                text: format!(
+                    "{} connects through its proxy command; a bastion can't be added too.",
+                    host.name
+                ),
+                kind: StatusKind::Error,
+            });
+            return;
+        }
+        // The short name when it finds the bastion from the host's group.
+        let short = bastion_id.rsplit('/').next().unwrap_or(&bastion_id);
+        let reference = match self.config.resolve_host(short, host.group.as_deref()) {
+            Ok(Some(found)) if found.id() == bastion_id => short.to_string(),
+            _ => bastion_id.clone(),
+        };
+        host.bastion = Some(reference.clone());
+        let name = host.name.clone();
+        let text = format!("{name} now connects through {reference}.");
+        match self.apply_change(Change::Edit { index, host }) {
+            Ok(()) => {
+                self.status.set(StatusLine {
+                    text,
+                    kind: StatusKind::Info,
+                });
+                self.suggest_jump_hosts();
+            }
+            Err(err) => self.status.set(StatusLine {
+                text: format!("Bastion not set: {err:#}"),
+                kind: StatusKind::Error,
+            }),
+        }
+    }
+
+    /// `*`: pins the selected host above the others, or unpins it. Saved
+    /// like an edit, so `u` takes it back.
+    fn toggle_pin(&mut self) {
+        let Some(index) = self.current_index() else {
+            return;
+        };
+        let mut host = self.config.hosts[index].clone();
+        host.pinned = !host.pinned;
+        let name = self.config.display_name(&host);
+        let text = if host.pinned {
+            format!("Pinned {name} to the top of the list.")
+        } else {
+            format!("Unpinned {name}.")
+        };
+        let id = host.id();
+        match self.apply_change(Change::Edit { index, host }) {
+            Ok(()) => {
+                if let Some(pos) = self
+                    .filtered_indices
+                    .iter()
+                    .position(|&i| self.config.hosts[i].id() == id)
+                {
+                    self.selected = pos;
+                }
+                self.status.set(StatusLine {
+                    text,
+                    kind: StatusKind::Info,
+                });
+            }
+            Err(err) => self.status.set(StatusLine {
+                text: format!("Not pinned: {err:#}"),
+                kind: StatusKind::Error,
+            }),
+        }
+    }
+
+    /// `Alt+k`/`Alt+j`: swaps the selected host with the one listed above
+    /// or below it in `config.hosts`, saved and undoable like an edit. Only
+    /// the plain list in config order has an order to change: a search,
+    /// recency or tag groups would move the host somewhere else.
+    fn move_host(&mut self, delta: isize) {
+        let Some(index) = self.current_index() else {
+            return;
+        };
+        let refusal = if !self.filter.is_empty() {
+            Some("Clear the search to move hosts; a filtered list has no order of its own.".into())
+        } else if self.host_order == HostOrder::Recent {
+            Some(format!(
+                "Hosts are listed by last connection; press {} for config order to move them.",
+                self.key(KeyAction::SortRecent)
+            ))
+        } else if self.grouped {
+            Some(format!(
+                "Hosts are grouped by tag; press {} to ungroup them and move them.",
+                self.key(KeyAction::GroupByTag)
+            ))
+        } else if !self.tag_filter.is_empty() || self.workset_only || self.health_filter.is_some() {
+            Some(format!(
+                "Only some hosts are listed; show them all ({}, {} or {}) to move them.",
+                self.key(KeyAction::TagFilter),
+                self.key(KeyAction::WorksetView),
+                self.key(KeyAction::HealthFilter)
+            ))
+        } else {
+            None
+        };
+        if let Some(text) = refusal {
+            self.status.set(StatusLine {
+                text,
+                kind: StatusKind::Warn,
+            });
+            return;
+        }
+        let name = self.config.display_name(&self.config.hosts[index]);
+        // Jump hosts are listed after the rest and pinned hosts before it,
+        // so a host only trades places with a neighbour from its own section.
+        let section = |host: &Host| (host.is_jump_host(), host.pinned);
+        let neighbour = self
+            .selected
+            .checked_add_signed(delta)
+            .and_then(|pos| self.filtered_indices.get(pos).copied())
+            .filter(|&i| section(&self.config.hosts[i]) == section(&self.config.hosts[index]));
+        let Some(neighbour) = neighbour else {
+            self.status.set(StatusLine {
+                text: format!(
+                    "{name} is already at the {}.",
+                    if delta < 0 { "top" } else { "bottom" }
+                ),
+                kind: StatusKind::Info,
+            });
+            return;
+        };
+        let mut hosts = self.config.hosts.clone();
+        hosts.swap(index, neighbour);
+        match self.apply_change(Change::ReplaceHosts(hosts)) {
+            Ok(()) => {
+                if let Some(pos) = self.filtered_indices.iter().position(|&i| i == neighbour) {
+                    self.selected = pos;
+                }
+                self.status.set(StatusLine {
+                    text: format!("Moved {name} {}.", if delta < 0 { "up" } else { "down" }),
+                    kind: StatusKind::Info,
+                });
+            }
+            Err(err) => self.status.set(StatusLine {
+                text: format!("Host not moved: {err:#}"),
+                kind: StatusKind::Error,
+            }),
+        }
+    }
+
+    /// `o`: checks off the UI thread whether the selected host's ssh port
+    /// accepts a TCP connection, even when an earlier answer is fresh.
+    /// Hosts reached through something else aren't probed from here.
+    fn ping_current(&mut self) {
+        let Some(host) = self.current_host() else {
+            return;
+        };
+        let name = self.config.display_name(host);
+        if let Some(through) = reached_through(host) {
+            self.status.set(StatusLine {
+                text: format!("Not checked: {name} is reached through {through}."),
+                kind: StatusKind::Warn,
+            });
+            return;
+        }
+        let address = host.address.clone();
+        let port = host.port.unwrap_or(22);
+        self.probes.recheck(&address, port, Instant::now());
+        self.status.set(StatusLine {
+            text: format!("Checking {name} on port {port}…"),
+            kind: StatusKind::Info,
+        });
+        self.ping = Some((name, address, port));
+    }
+
+    /// Puts the answer to the running `o` check on the status line once it
+    /// is in.
+    fn report_ping(&mut self) {
+        let Some((name, address, port)) = &self.ping else {
+            return;
+        };
+        let status = match self.probes.status(address, *port, Instant::now()) {
+            Some((PortProbe::Pending, _)) => return,
+            Some((PortProbe::Open(took), _)) => StatusLine {
+                text: format!("{name}: reachable ({}ms).", took.as_millis()),
+                kind: StatusKind::Info,
+            },
+            Some((PortProbe::Closed(reason), _)) => StatusLine {
+                text: format!("{name}: unreachable ({reason})."),
+                kind: StatusKind::Warn,
+            },
+            None => return,
+        };
+        self.status.set(status);
+        self.ping = None;
+    }
+
+    /// `H`: checks the ssh port of every host that can be probed from here,
+    /// a bounded number at a time, and records which answered in `health`.
+    fn health_sweep(&mut self) {
+        let now = Instant::now();
+        let mut pending = Vec::new();
+        let mut skipped = 0;
+        for host in &self.config.hosts {
+            if reached_through(host).is_some() {
+                skipped += 1;
+                continue;
+            }
+            let port = host.port.unwrap_or(22);
+            self.probes.recheck(&host.address, port, now);
+            pending.push((host.id(), host.address.clone(), port));
+        }
+        if pending.is_empty() {
+            self.status.set(StatusLine {
+                text: "No host to check: all are reached through a bastion or proxy.".into(),
+                kind: StatusKind::Warn,
+            });
+            return;
+        }
+        self.health.clear();
+        self.status.set(StatusLine {
+            text: format!("Health sweep: 0/{} checked…", pending.len()),
+            kind: StatusKind::Info,
+        });
+        self.sweep = Some(HealthSweep {
+            total: pending.len(),
+            pending,
+            skipped,
+        });
+        if self.health_filter.is_some() {
+            self.rebuild_filter();
+        }
+    }
+
+    /// Records the sweep answers that came in and keeps its progress on the
+    /// status line.
+    fn report_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+        let now = Instant::now();
+        let before = sweep.pending.len();
+        let health = &mut self.health;
+        let probes = &self.probes;
+        sweep.pending.retain(|(id, address, port)| {
+            let reachable = match probes.status(address, *port, now) {
+                Some((PortProbe::Pending, _)) => return true,
+                Some((PortProbe::Open(_), _)) => true,
+                Some((PortProbe::Closed(_), _)) | None => false,
+            };
+            health.insert(id.clone(), reachable);
+            false
+        });
+        if sweep.pending.len() == before {
+            return;
+        }
+        let down = self.health.values().filter(|up| !**up).count();
+        let status = if sweep.pending.is_empty() {
+            let mut text = format!(
+                "Health sweep: {} reachable, {down} unreachable",
+                self.health.len() - down
+            );
+            if sweep.skipped > 0 {
+                text.push_str(&format!(
+                    "; {} behind a bastion or proxy not checked",
+                    sweep.skipped
+                ));
+            }
+            text.push_str(&format!(
+                ". {} filters by it.",
+                self.key(KeyAction::HealthFilter)
+            ));
+            self.sweep = None;
+            StatusLine {
+                text,
+                kind: if down > 0 {
+                    StatusKind::Warn
+                } else {
+                    StatusKind::Info
+                },
+            }
+        } else {
+            StatusLine {
+                text: format!(
+                    "Health sweep: {}/{} checked, {down} unreachable…",
+                    sweep.total - sweep.pending.len(),
+                    sweep.total
+                ),
+                kind: StatusKind::Info,
+            }
+        };
+        self.status.set(status);
+        if self.health_filter.is_some() {
+            let current = self.current_index();
+            self.rebuild_filter();
+            if let Some(pos) =
+                current.and_then(|c| self.filtered_indices.iter().position(|i| *i == c))
+            {
+                self.selected = pos;
+            }
+        }
+    }
+
+    /// `A`: all hosts → only reachable → only unreachable → all again, by
+    /// the last health sweep.
+    fn cycle_health_filter(&mut self) {
+        if self.health.is_empty() && self.health_filter.is_none() {
+            self.status.set(StatusLine {
+                text: format!(
+                    "No health sweep yet; {} checks every host.",
+                    self.key(KeyAction::HealthSweep)
+                ),
+                kind: StatusKind::Warn,
+            });
+            return;
+        }
+        self.health_filter = match self.health_filter {
+            None => Some(true),
+            Some(true) => Some(false),
+            Some(false) => None,
+        };
+        let current = self.current_index();
+        self.rebuild_filter();
+        if let Some(pos) = current.and_then(|c| self.filtered_indices.iter().position(|i| *i == c))
+        {
+            self.selected = pos;
+        }
+        let key = self.key(KeyAction::HealthFilter);
+        let text = match self.health_filter {
+            Some(true) => format!("Showing reachable hosts; {key} shows the unreachable ones."),
+            Some(false) => format!("Showing unreachable hosts; {key} shows all."),
+            None => "Showing all hosts.".into(),
+        };
+        self.status.set(StatusLine {
+            text,
+            kind: StatusKind::Info,
+        });
+    }
+
+    /// The last port check of `host`, from `o` or the pre-flight checklist,
+    /// with its age; `None` when there is none or it is stale.
+    pub fn reachability(&self, host: &Host) -> Option<(&PortProbe, Duration)> {
+        self.probes
+            .status(&host.address, host.port.unwrap_or(22), Instant::now())
+    }
+
+    fn copy_current_connection_string(&mut self) {
+        let Some(command) = self.current_connection_string() else {
+            self.status.set(StatusLine {
+                text: "No host selected.".into(),
+                kind: StatusKind::Warn,
+            });
+            return;
+        };
+
+        match clipboard::copy_text(&command) {
+            Ok(()) => {
+                self.status.set(StatusLine {
+                    text: "Copied ssh command to clipboard.".into(),
+                    kind: StatusKind::Info,
+                });
+            }
+            Err(err) if err.is::<clipboard::NoClipboard>() => {
+                self.status.set(StatusLine {
+                    text: format!("Not copied: {err}."),
+                    kind: StatusKind::Warn,
+                });
+            }
+            Err(err) => {
+                self.status.set(StatusLine {
+                    text: format!("Clipboard copy failed: {err}"),
+                    kind: StatusKind::Error,
+                });
+            }
+        }
+    }
+
+    /// Saves the current config at the first writable fallback location
+    /// without an existing config there, and continues from it.
+    fn continue_from_writable_copy(&mut self) -> Result<()> {
+        if self.read_only.is_none() {
+            self.status.set(StatusLine {
+                text: format!("{} is writable; nothing to do.", self.config_path.display()),
+                kind: StatusKind::Info,
+            });
+            return Ok(());
+        }
+        let mut tried = Vec::new();
+        for path in config::fallback_paths() {
+            if path == self.config_path || path.exists() {
+                continue;
+            }
+            let store = ConfigStore::at(path.clone());
+            if let Err(err) = store.check_writable() {
+                tried.push(err.to_string());
+                continue;
+            }
+            store.save(&self.persisted())?;
+            self.state = StateStore::beside(&path);
+            self.store = store;
+            self.config_path = path.clone();
+            self.read_only = None;
+            self.status.set(StatusLine {
+                text: format!(
+                    "Continuing from {0}; set {1}={0} or pass --config to keep using it.",
+                    path.display(),
+                    config::CONFIG_ENV
+                ),
+                kind: StatusKind::Info,
+            });
+            return Ok(());
+        }
+        anyhow::bail!(
+            "no writable location without an existing config ({}); set {} to one",
+            if tried.is_empty() {
+                "all fallbacks already have a config".to_string()
+            } else {
+                tried.join("; ")
+            },
+            config::CONFIG_ENV
+        )
+    }
+
+    fn reload_config(&mut self) -> Result<()> {
+        let (config, migration) = Self::load_config(&self.store, self.read_only.is_some())
+            .with_context(|| "failed to reload config")?;
+        let profile = self.config.active_profile.take();
+        self.config = config;
+        self.show_preview_bar = self.config.show_preview_bar;
+        self.generation += 1;
+        // Redoing would silently overwrite what changed on disk.
+        self.redo_stack.clear();
+        if self.config.clear_undo_on_reload {
+            self.history.clear();
+        }
+        self.apply_overlays()?;
+        // A profile removed from the file leaves the default one showing.
+        if let Some(profile) = profile {
+            let _ = self.show_profile(Some(&profile));
+        }
+        self.status.set(StatusLine {
+            text: match migration {
+                Some(report) => report.summary(),
+                None if self.overlays.is_empty() => "Reloaded config.".into(),
+                None => "Reloaded config and overlays.".into(),
+            },
+            kind: StatusKind::Info,
+        });
+        self.rebuild_keymap();
+        Ok(())
+    }
+
+    /// The help overlay's rows, with the keys `[keys]` binds now.
+    pub fn help_entries(&self) -> Vec<(String, &'static str)> {
+        let entries = [
+            (HelpKey::Action(KeyAction::Search), "search"),
+            (HelpKey::Fixed("Tab (in search)"), "cycle search scope"),
+            (
+                HelpKey::Fixed("Ctrl+R (in search)"),
+                "switch between fuzzy and regex search",
+            ),
+            (HelpKey::Action(KeyAction::Connect), "connect"),
+            (
+                HelpKey::Action(KeyAction::ConnectForce),
+                "connect for real even in dry-run",
+            ),
+            (
+                HelpKey::Action(KeyAction::ConnectCommand),
+                "connect with remote command",
+            ),
+            (
+                HelpKey::Action(KeyAction::ConnectBastion),
+                "connect to the host's bastion",
+            ),
+            (
+                HelpKey::Action(KeyAction::CopyFile),
+                "copy a file to the host with scp",
+            ),
+            (
+                HelpKey::Action(KeyAction::Settings),
+                "settings: the default key",
+            ),
+            (
+                HelpKey::Fixed("Tab (in c)"),
+                "watch: re-run the command on an interval",
+            ),
+            (
+                HelpKey::Action(KeyAction::ConnectAs),
+                "connect once as another user",
+            ),
+            (
+                HelpKey::Action(KeyAction::CopyConnection),
+                "copy the ssh command to the clipboard",
+            ),
+            (
+                HelpKey::Action(KeyAction::Ping),
+                "check the host's ssh port is reachable",
+            ),
+            (
+                HelpKey::Action(KeyAction::HealthSweep),
+                "health sweep: check every host's ssh port",
+            ),
+            (
+                HelpKey::Action(KeyAction::HealthFilter),
+                "show only reachable / unreachable hosts",
+            ),
+            (
+                HelpKey::Action(KeyAction::LinkBastion),
+                "pick a bastion, then l on another host to connect through it",
+            ),
+            (
+                HelpKey::Action(KeyAction::Pin),
+                "pin the host to the top of the list",
+            ),
+            (
+                HelpKey::Pair(KeyAction::MoveHostUp, KeyAction::MoveHostDown),
+                "move the host up or down in the config",
+            ),
+            (
+                HelpKey::Action(KeyAction::CloseControl),
+                "close ssh control connections",
+            ),
+            (HelpKey::Fixed("Esc"), "dismiss error message"),
+            (
+                HelpKey::Action(KeyAction::PreviewBar),
+                "toggle command preview bar",
+            ),
+            (
+                HelpKey::Action(KeyAction::QuickConnect),
+                "quick connect (ssh string)",
+            ),
+            (HelpKey::Action(KeyAction::New), "new host"),
+            (HelpKey::Action(KeyAction::Edit), "edit host"),
+            (HelpKey::Action(KeyAction::Delete), "delete host"),
+            (HelpKey::Action(KeyAction::Duplicate), "duplicate host"),
+            (
+                HelpKey::Action(KeyAction::DuplicateNext),
+                "next host in a numbered series (web-02 → web-03)",
+            ),
+            (
+                HelpKey::Action(KeyAction::Rename),
+                "rename host (bastion references follow)",
+            ),
+            (
+                HelpKey::Action(KeyAction::Problems),
+                "list problems (a: acknowledge an ssh_config alias)",
+            ),
+            (
+                HelpKey::Action(KeyAction::Explain),
+                "explain how the connection is built",
+            ),
+            (
+                HelpKey::Action(KeyAction::FullCommand),
+                "show the full command, one argument per line",
+            ),
+            (
+                HelpKey::Action(KeyAction::WritableCopy),
+                "read-only config: continue from a writable copy",
+            ),
+            (
+                HelpKey::Action(KeyAction::SortRecent),
+                "sort by last connection / config order",
+            ),
+            (
+                HelpKey::Action(KeyAction::ExportSshConfig),
+                "export the hosts as ssh_config next to the config file",
+            ),
+            (
+                HelpKey::Action(KeyAction::Bundle),
+                "export or import a bundle for another machine",
+            ),
+            (
+                HelpKey::Action(KeyAction::SwitchProfile),
+                "switch to the next profile (host set)",
+            ),
+            (
+                HelpKey::Action(KeyAction::ExportJson),
+                "export the config as JSON next to the config file",
+            ),
+            (
+                HelpKey::Action(KeyAction::ImportJson),
+                "replace the config with that JSON copy (undo reverts it)",
+            ),
+            (
+                HelpKey::Action(KeyAction::JumpHosts),
+                "show or hide the jump hosts section",
+            ),
+            (
+                HelpKey::Action(KeyAction::GroupByTag),
+                "group the hosts by first tag",
+            ),
+            (
+                HelpKey::Action(KeyAction::Workset),
+                "add to or remove from the workset",
+            ),
+            (
+                HelpKey::Action(KeyAction::WorksetView),
+                "show only the workset",
+            ),
+            (
+                HelpKey::Action(KeyAction::WorksetClear),
+                "clear the workset",
+            ),
+            (
+                HelpKey::Action(KeyAction::TagFilter),
+                "show only hosts with a tag (again to add more; Esc clears)",
+            ),
+            (
+                HelpKey::Action(KeyAction::Mark),
+                "mark host for a bulk edit",
+            ),
+            (
+                HelpKey::Action(KeyAction::BulkEdit),
+                "bulk edit: change one field on all marked hosts",
+            ),
+            (HelpKey::Action(KeyAction::Undo), "undo last change"),
+            (
+                HelpKey::Action(KeyAction::Redo),
+                "redo the last undone change",
+            ),
+            (HelpKey::Action(KeyAction::Reload), "reload config"),
+            (HelpKey::Selection, "move selection"),
+            (HelpKey::Fixed("PgDn/PgUp"), "scroll the details pane"),
+            (HelpKey::Action(KeyAction::DryRun), "toggle dry-run"),
+            (
+                HelpKey::Action(KeyAction::Privacy),
+                "privacy mode: mask addresses and key paths on screen",
+            ),
+            (
+                HelpKey::Action(KeyAction::AutoTagRules),
+                "test auto_tags rules against the hosts",
+            ),
+            (HelpKey::Action(KeyAction::Help), "show help"),
+            (HelpKey::Action(KeyAction::About), "about/credits"),
+            (HelpKey::Action(KeyAction::Quit), "quit"),
+            (HelpKey::Fixed("Ctrl+C"), "quit immediately"),
+            (HelpKey::Fixed("Esc"), "cancel modal/help"),
+        ];
+        entries
+            .into_iter()
+            .map(|(key, text)| {
+                let key = match key {
+                    HelpKey::Action(action) => self.keymap.label(action),
+                    HelpKey::Pair(first, second) => {
+                        format!("{}/{}", self.keymap.label(first), self.keymap.label(second))
+                    }
+                    HelpKey::Selection => format!(
+                        "{}/{} or arrows",
+                        self.keymap.label(KeyAction::Down),
+                        self.keymap.label(KeyAction::Up)
+                    ),
+                    HelpKey::Fixed(key) => key.to_string(),
+                };
+                (key, text)
+            })
+            .collect()
+    }
+}
+
+/// Where a help row's key comes from: the keymap for list actions, fixed
+/// text for keys that only mean something inside a mode.
+enum HelpKey {
+    Action(KeyAction),
+    Pair(KeyAction, KeyAction),
+    /// `down`/`up`, which the arrows run too.
+    Selection,
+    Fixed(&'static str),
+}
+
+/// The checklist's view of the app: cached probes and the clock, plus
+/// file metadata, which is local and quick.
+impl preflight::Providers for App {
+    fn key_file(&self, path: &str) -> KeyFile {
+        match fs::metadata(ssh::expand_tilde(path)) {
+            Err(_) => KeyFile::Missing,
+            #[cfg(unix)]
+            Ok(meta) => {
+                use std::os::unix::fs::PermissionsExt;
+                KeyFile::Found {
+                    mode: Some(meta.permissions().mode()),
+                }
+            }
+            #[cfg(not(unix))]
+            Ok(_) => KeyFile::Found { mode: None },
+        }
+    }
+
+    fn port(&self, address: &str, port: u16) -> PortState {
+        match self.probes.status(address, port, Instant::now()) {
+            None => PortState::Unknown,
+            Some((PortProbe::Pending, _)) => PortState::Pending,
+            Some((PortProbe::Open(_), age)) => PortState::Open { age },
+            Some((PortProbe::Closed(reason), age)) => PortState::Closed {
+                reason: reason.clone(),
+                age,
+            },
+        }
+    }
+
+    fn active_window(&self, host: &Host) -> Option<String> {
+        self.active_freeze(host)
+            .map(|spec| format!("inside the freeze window ({spec})"))
+            .or_else(|| self.quiet_hours_warning(host))
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::model::Profile;
+    use tempfile::tempdir;
+
+    pub(crate) fn test_app() -> App {
+        with_fake_ssh(App::with_config(Config::sample(), ConfigStore::in_memory()))
+    }
+
+    /// Tests connect whether or not the machine running them has ssh, and
+    /// never probe a real port.
+    fn with_fake_ssh(mut app: App) -> App {
+        app.find_program = |program| Ok(PathBuf::from("/usr/bin").join(program));
+        app.probes = PortProber::with_probe(|_, _| PortProbe::Closed("connection refused".into()));
+        app
+    }
+
+    #[test]
+    fn settings_modal_edits_the_default_key() {
+        let (_dir, mut app) = app_on_disk();
+        app.config.hosts[0].key_paths.clear();
+        app.config.default_key = Some("~/.ssh/id_work".into());
+        press(&mut app, 'O');
+        assert!(matches!(app.mode, Mode::Settings));
+        assert_eq!(app.settings.as_ref().unwrap().default_key, "~/.ssh/id_work");
+
+        // Tab swaps in "agent" and back.
+        app.on_key(KeyEvent::from(KeyCode::Tab)).unwrap();
+        assert_eq!(app.settings.as_ref().unwrap().default_key, "agent");
+        app.on_key(KeyEvent::from(KeyCode::Tab)).unwrap();
+        assert_eq!(app.settings.as_ref().unwrap().default_key, "~/.ssh/id_work");
+        app.on_key(KeyEvent::from(KeyCode::Tab)).unwrap();
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.config.default_key.as_deref(), Some("agent"));
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "Default key set to agent."
+        );
+        assert!(!app.selected_preview().unwrap().contains("id_work"));
+        assert_eq!(
+            app.store.load_read_only().unwrap().default_key.as_deref(),
+            Some("agent")
+        );
+
+        // Emptied, the key is left to ssh; u brings it back.
+        press(&mut app, 'O');
+        for _ in 0.."agent".len() {
+            app.on_key(KeyEvent::from(KeyCode::Backspace)).unwrap();
+        }
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert_eq!(app.config.default_key, None);
+        press(&mut app, 'u');
+        assert_eq!(app.config.default_key.as_deref(), Some("agent"));
+
+        // Esc leaves it alone.
+        press(&mut app, 'O');
+        press(&mut app, 'x');
+        app.on_key(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert_eq!(app.config.default_key.as_deref(), Some("agent"));
+    }
+
+    #[test]
+    fn ctrl_r_in_search_matches_a_regex_and_keeps_results_while_invalid() {
+        let mut app = test_app();
+        press(&mut app, '/');
+        let ctrl_r = KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL);
+        app.on_key(ctrl_r).unwrap();
+        assert!(app.search_regex);
+        type_keys(&mut app, "^(prod|staging)-");
+        assert_eq!(names(&app), ["prod-web", "staging-db"]);
+
+        // Half-typed: the last valid results stay, with a warning.
+        type_keys(&mut app, "(w");
+        assert_eq!(names(&app), ["prod-web", "staging-db"]);
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Warn);
+        assert!(
+            status.text.starts_with("Not a valid regex ("),
+            "{}",
+            status.text
+        );
+        type_keys(&mut app, ")");
+        assert_eq!(names(&app), ["prod-web"]);
+        assert!(app.status.current().is_none());
+
+        // Back to fuzzy, where the same text matches nothing.
+        app.on_key(ctrl_r).unwrap();
+        assert!(!app.search_regex);
+        assert!(names(&app).is_empty());
+    }
+
+    #[test]
+    fn typed_search_ranks_like_a_fresh_one() {
+        let mut app = test_app();
+        for i in 0..60 {
+            app.config.hosts.push(Host {
+                name: format!("{}-{i}", ["web", "db", "cache"][i % 3]),
+                address: format!("10.{}.0.{i}", i % 7),
+                tags: vec![["eu", "us", "web"][i % 3].into()],
+                ..Default::default()
+            });
+        }
+        app.rebuild_filter();
+        press(&mut app, '/');
+        for (key, expected) in [
+            (KeyCode::Char('w'), "w"),
+            (KeyCode::Char('e'), "we"),
+            (KeyCode::Char('1'), "we1"),
+            (KeyCode::Backspace, "we"),
+            (KeyCode::Char('b'), "web"),
+            (KeyCode::Char('2'), "web2"),
+        ] {
+            app.on_key(KeyEvent::from(key)).unwrap();
+            assert_eq!(app.filter, expected);
+            let typed = app.filtered_indices.clone();
+            app.set_filter(expected);
+            assert_eq!(typed, app.filtered_indices, "{expected}");
+        }
+
+        // An edit is searched by its new values straight away.
+        let index = app.filtered_indices[0];
+        let mut host = app.config.hosts[index].clone();
+        host.name = "renamed".into();
+        app.apply_change(Change::Edit { index, host }).unwrap();
+        app.on_key(KeyEvent::from(KeyCode::Backspace)).unwrap();
+        app.on_key(KeyEvent::from(KeyCode::Char('2'))).unwrap();
+        assert!(!app.filtered_indices.contains(&index));
+    }
+
+    #[test]
+    fn filters_hosts_with_search() {
+        let mut app = test_app();
+        app.filter = "prod".into();
+        app.rebuild_filter();
+        assert!(!app.filtered_indices.is_empty());
+        let first = app.filtered_indices[0];
+        assert_eq!(app.config.hosts[first].name, "prod-web");
+    }
+
+    #[test]
+    fn description_matches_only_in_widest_scope() {
+        let mut app = test_app();
+        app.filter = "Payment frontend".into();
+
+        for scope in [SearchScope::Names, SearchScope::Targets] {
+            app.search_scope = scope;
+            app.rebuild_filter();
+            assert!(app.filtered_indices.is_empty(), "{scope:?} matched notes");
+        }
+
+        app.search_scope = SearchScope::Everything;
+        app.rebuild_filter();
+        assert_eq!(app.current_host().unwrap().name, "prod-web");
+    }
+
+    #[test]
+    fn search_scope_cycles_and_persists() {
+        let mut app = test_app();
+        app.mode = Mode::Search;
+        app.handle_search(KeyEvent::from(KeyCode::Tab)).unwrap();
+        assert_eq!(app.search_scope, SearchScope::Everything);
+        assert_eq!(
+            app.state.load().unwrap().search_scope,
+            SearchScope::Everything
+        );
+    }
+
+    #[test]
+    fn warns_about_loopback_address_unless_disabled() {
+        let mut app = test_app();
+        let mut host = app.config.hosts[0].clone();
+        assert!(app.local_address_warning(&host).is_none());
+
+        host.address = "127.0.0.1".into();
+        let warning = app.local_address_warning(&host).unwrap();
+        assert!(warning.contains("loopback"));
+
+        app.config.warn_local_addresses = false;
+        assert!(app.local_address_warning(&host).is_none());
+    }
+
+    #[test]
+    fn form_round_trips_command_template() {
+        let config = Config::sample();
+        let mut host = config.hosts[0].clone();
+        host.command_template = Some("tsh ssh {user}@{address}".into());
+        let form = FormState::new(FormKind::Edit, Some(&host), &config);
+        assert_eq!(form.build_host().unwrap(), host);
+    }
+
+    #[test]
+    fn form_round_trips_request_tty() {
+        let config = Config::sample();
+        let mut host = config.hosts[0].clone();
+        for setting in [None, Some(true), Some(false)] {
+            host.request_tty = setting;
+            let form = FormState::new(FormKind::Edit, Some(&host), &config);
+            assert_eq!(form.build_host().unwrap(), host);
+        }
+        assert_eq!(parse_tty_field("-T").unwrap(), Some(false));
+        assert!(parse_tty_field("maybe").is_err());
+    }
+
+    #[test]
+    fn ctrl_t_cycles_tty_for_one_connect() {
+        let mut app = test_app();
+        app.dry_run = true;
+        app.handle_normal(KeyEvent::from(KeyCode::Char('c')))
+            .unwrap();
+        let ctrl_t = KeyEvent::new(KeyCode::Char('t'), KeyModifiers::CONTROL);
+        app.handle_confirm(ctrl_t).unwrap();
+        assert!(matches!(
+            app.confirm,
+            Some(ConfirmKind::Connect {
+                request_tty: Some(true),
+                ..
+            })
+        ));
+        app.handle_confirm(KeyEvent::from(KeyCode::Enter)).unwrap();
+        let status = app.status.current().unwrap().text.clone();
+        assert!(status.contains(" -t "), "{status}");
+        assert_eq!(app.current_host().unwrap().request_tty, None);
+    }
+
+    #[test]
+    fn privacy_mode_masks_only_the_screen() {
+        let mut app = test_app();
+        app.dry_run = true;
+        app.handle_normal(KeyEvent::from(KeyCode::Char('P')))
+            .unwrap();
+        assert!(app.privacy);
+        assert_eq!(app.display("deploy@52.14.33.10"), "deploy@52.x.x.x");
+
+        // The dry run reports the command that would really run.
+        select(&mut app, "prod-web");
+        app.handle_normal(KeyEvent::from(KeyCode::Char('c')))
+            .unwrap();
+        app.handle_confirm(KeyEvent::from(KeyCode::Enter)).unwrap();
+        let status = app.status.current().unwrap().text.clone();
+        assert!(status.contains("deploy@52.14.33.10"), "{status}");
+        assert!(status.contains("/prod_id_ed25519"), "{status}");
+        assert_eq!(app.config.hosts[0].address, "52.14.33.10");
+
+        // Copying hands out real values, so it asks first.
+        app.handle_normal(KeyEvent::from(KeyCode::Char('x')))
+            .unwrap();
+        assert!(matches!(
+            app.confirm,
+            Some(ConfirmKind::Unmasked(UnmaskedAction::CopyConnection))
+        ));
+        app.handle_confirm(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(app.confirm.is_none());
+
+        app.mode = Mode::Bundle;
+        app.handle_bundle(KeyEvent::from(KeyCode::Char('e')))
+            .unwrap();
+        assert!(matches!(
+            app.confirm,
+            Some(ConfirmKind::Unmasked(UnmaskedAction::ExportBundle))
+        ));
+
+        app.handle_normal(KeyEvent::from(KeyCode::Char('P')))
+            .unwrap();
+        assert_eq!(app.display("deploy@52.14.33.10"), "deploy@52.14.33.10");
+    }
+
+    #[test]
+    fn freeze_window_routes_enter_through_a_double_confirm() {
+        let mut app = test_app();
+        app.dry_run = true;
+        app.config.hosts[0].maintenance = Some("Fri 18:00–Sun 24:00 UTC: change freeze".into());
+        app.config.hosts[0].freeze_window = Some("Mon-Sun".into());
+        select(&mut app, "prod-web");
+
+        app.handle_normal(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.mode, Mode::Confirm));
+        app.handle_confirm(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(
+            app.confirm,
+            Some(ConfirmKind::Connect {
+                freeze_armed: true,
+                ..
+            })
+        ));
+        assert!(app.status.current().unwrap().text.contains("freeze"));
+        app.handle_confirm(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+        assert!(app.status.current().unwrap().text.contains("deploy@"));
+
+        // Outside the window Enter connects straight away.
+        app.config.hosts[0].freeze_window = Some("Mon 00:00-Mon 00:01".into());
+        app.config.freeze_timezone = Some("UTC".into());
+        if app.current_freeze().is_none() {
+            app.handle_normal(KeyEvent::from(KeyCode::Enter)).unwrap();
+            assert!(matches!(app.mode, Mode::Normal));
+        }
+
+        let host = app.config.hosts[0].clone();
+        let mut form = FormState::new(FormKind::Edit, Some(&host), &app.config);
+        assert_eq!(form.build_host().unwrap(), host);
+        form.set_field_value(FIELD_FREEZE_WINDOW, "Someday 10:00-11:00".into());
+        assert!(form.build_host().is_err());
+    }
+
+    #[test]
+    fn quiet_hours_warn_on_connect_in_the_hosts_zone() {
+        // Sunday 2024-10-27 01:00 UTC: 02:00 in Berlin, 10:00 in Tokyo.
+        let sunday = SystemTime::UNIX_EPOCH + Duration::from_secs(1_729_990_800);
+        let mut app = test_app();
+        app.dry_run = true;
+        app.set_clock(Some(sunday));
+        app.config.hosts[0].timezone = Some("Asia/Tokyo".into());
+        app.config.hosts[0].quiet_hours = Some("Sat-Sun".into());
+        select(&mut app, "prod-web");
+        let host = app.current_host().unwrap().clone();
+        assert_eq!(
+            app.host_local_time(&host).unwrap().unwrap(),
+            "10:00 (Asia/Tokyo)"
+        );
+
+        app.handle_normal(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.confirm, Some(ConfirmKind::Connect { .. })));
+        let warning = app.current_quiet_hours().unwrap();
+        assert!(warning.contains("(it is 10:00 there)"), "{warning}");
+        // A note, not a gate: one Enter connects.
+        app.handle_confirm(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+
+        app.config.hosts[0].quiet_hours = Some("Mon-Fri 22:00-07:00".into());
+        assert!(app.current_quiet_hours().is_none());
+        app.handle_normal(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(app.confirm.is_none());
+
+        let host = app.config.hosts[0].clone();
+        let mut form = FormState::new(FormKind::Edit, Some(&host), &app.config);
+        assert_eq!(form.build_host().unwrap(), host);
+        form.set_field_value(FIELD_TIMEZONE, "Asia/Tokio".into());
+        let err = form.build_host().unwrap_err().to_string();
+        assert!(err.contains("did you mean Asia/Tokyo"), "{err}");
+        form.set_field_value(FIELD_TIMEZONE, String::new());
+        form.set_field_value(FIELD_QUIET_HOURS, "Someday".into());
+        assert!(form.build_host().is_err());
+    }
+
+    #[test]
+    fn protected_hosts_connect_through_the_preflight_checklist() {
+        let mut app = test_app();
+        app.dry_run = true;
+        select(&mut app, "prod-web");
+        assert!(app.preflight().is_none());
+        app.handle_normal(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(app.confirm.is_none(), "unprotected hosts connect on Enter");
+
+        app.config.hosts[0].tags.push("prod".into());
+        app.handle_normal(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.confirm, Some(ConfirmKind::Connect { .. })));
+        let checks = app.preflight().unwrap();
+        let names: Vec<&str> = checks.iter().map(|c| c.check.name()).collect();
+        assert_eq!(names, ["bastion", "keys", "port", "window", "dry_run"]);
+        let port = &checks[2];
+        assert!(
+            matches!(port.outcome, preflight::Outcome::Unknown),
+            "the probe is only started: {port:?}"
+        );
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while app.preflight().unwrap()[2].outcome == preflight::Outcome::Unknown {
+            assert!(Instant::now() < deadline, "probe never finished");
+            thread::sleep(Duration::from_millis(5));
+            app.tick();
+        }
+        let port = &app.preflight().unwrap()[2];
+        assert_eq!(port.outcome, preflight::Outcome::Fail);
+        assert!(port
+            .detail
+            .starts_with("52.14.33.10:22 unreachable: connection refused"));
+
+        // A failing check informs; it doesn't block.
+        app.handle_confirm(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+
+        app.config.preflight_skip = vec!["port".into(), "keys".into()];
+        app.config.hosts[0].tags.pop();
+        app.config.hosts[0].protected = true;
+        let names: Vec<&str> = app
+            .preflight()
+            .unwrap()
+            .iter()
+            .map(|c| c.check.name())
+            .collect();
+        assert_eq!(names, ["bastion", "window", "dry_run"]);
+
+        let host = app.config.hosts[0].clone();
+        let form = FormState::new(FormKind::Edit, Some(&host), &app.config);
+        assert!(form.changes().is_empty(), "protected is kept on edit");
+    }
+
+    fn launcher_app() -> App {
+        let mut config = Config::sample();
+        config.launcher = true;
+        with_fake_ssh(App::with_config(config, ConfigStore::in_memory()))
+    }
+
+    fn type_keys(app: &mut App, text: &str) {
+        for c in text.chars() {
+            assert!(app
+                .on_key(KeyEvent::from(KeyCode::Char(c)))
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    fn ssh_args(action: Option<AppAction>) -> Vec<String> {
+        let Some(AppAction::RunSsh(cmd)) = action else {
+            panic!("expected an ssh command");
+        };
+        cmd.get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn ssh_host_id(action: Option<AppAction>) -> String {
+        let Some(AppAction::RunSsh(cmd)) = action else {
+            panic!("expected an ssh command");
+        };
+        cmd.host_id().to_string()
+    }
+
+    #[test]
+    fn b_connects_to_the_bastion_and_keeps_the_selection() {
+        let mut app = test_app();
+        select(&mut app, "staging-db");
+        let selected = app.selected;
+        let action = app.on_key(KeyEvent::from(KeyCode::Char('b'))).unwrap();
+        assert_eq!(ssh_host_id(action), "jump-eu");
+        assert_eq!(app.selected, selected);
+        assert!(app
+            .status
+            .current()
+            .unwrap()
+            .text
+            .starts_with("Connecting to bastion jump-eu of staging-db with: ssh "));
+        assert_eq!(
+            app.take_session_note().as_deref(),
+            Some("Connected to bastion jump-eu of staging-db.")
+        );
+        assert_eq!(app.take_session_note(), None);
+
+        select(&mut app, "prod-web");
+        press(&mut app, 'b');
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "prod-web has no bastion."
+        );
+    }
+
+    #[test]
+    fn b_asks_which_hop_of_a_longer_chain() {
+        let mut app = test_app();
+        app.config.hosts.push(Host {
+            name: "edge".into(),
+            address: "198.51.100.1".into(),
+            ..Default::default()
+        });
+        let jump = app.config.find_host("jump-eu").unwrap().clone();
+        let index = app.config.hosts.iter().position(|h| *h == jump).unwrap();
+        app.config.hosts[index].bastion = Some("edge".into());
+        app.rebuild_filter();
+        select(&mut app, "staging-db");
+
+        press(&mut app, 'b');
+        assert!(matches!(
+            app.confirm,
+            Some(ConfirmKind::PickBastion { ref hops, selected: 1 }) if hops == &["edge", "jump-eu"]
+        ));
+        app.handle_confirm(KeyEvent::from(KeyCode::Up)).unwrap();
+        let action = app.handle_confirm(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert_eq!(ssh_host_id(action), "edge");
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.current_host().unwrap().name, "staging-db");
+
+        press(&mut app, 'b');
+        let action = app
+            .handle_confirm(KeyEvent::from(KeyCode::Char('2')))
+            .unwrap();
+        assert_eq!(ssh_host_id(action), "jump-eu");
+
+        press(&mut app, 'b');
+        app.handle_confirm(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+        assert!(app.confirm.is_none());
+    }
+
+    #[test]
+    fn b_on_a_dangling_bastion_suggests_a_host() {
+        let mut app = test_app();
+        select(&mut app, "staging-db");
+        let index = app.current_index().unwrap();
+        app.config.hosts[index].bastion = Some("jump-ue".into());
+        assert!(app
+            .on_key(KeyEvent::from(KeyCode::Char('b')))
+            .unwrap()
+            .is_none());
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Error);
+        assert_eq!(
+            status.text,
+            "Bastion 'jump-ue' of staging-db is not a host in the list (did you mean jump-eu?)."
+        );
+        app.status.dismiss();
+
+        app.config.hosts[index].bastion = Some("ops@192.0.2.9".into());
+        press(&mut app, 'b');
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "Bastion 'ops@192.0.2.9' of staging-db is not a host in the list."
+        );
+    }
+
+    #[test]
+    fn launcher_connects_to_top_result_on_enter() {
+        let mut app = launcher_app();
+        assert!(matches!(app.mode, Mode::Launcher));
+        type_keys(&mut app, "jum");
+        let action = app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(ssh_args(action).contains(&"ops@52.17.9.3".to_string()));
+
+        // Editing the query puts the highlight back on the top result.
+        let mut app = launcher_app();
+        type_keys(&mut app, "e");
+        app.on_key(KeyEvent::from(KeyCode::Down)).unwrap();
+        assert_eq!(app.selected, 1);
+        type_keys(&mut app, "u");
+        assert_eq!(app.selected, 0);
+        let top = app.current_host().unwrap().address.clone();
+        let action = app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(ssh_args(action).iter().any(|a| a.ends_with(&top)));
+    }
+
+    #[test]
+    fn launcher_alt_enter_opens_full_view_on_the_host() {
+        let mut app = launcher_app();
+        type_keys(&mut app, "staging");
+        let alt_enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT);
+        assert!(app.on_key(alt_enter).unwrap().is_none());
+        assert!(matches!(app.mode, Mode::Normal));
+        assert!(app.filter.is_empty());
+        assert_eq!(app.filtered_indices.len(), app.config.hosts.len());
+        assert_eq!(app.current_host().unwrap().name, "staging-db");
+    }
+
+    #[test]
+    fn launcher_escape_quits_and_letters_are_not_commands() {
+        let mut app = launcher_app();
+        type_keys(&mut app, "q?");
+        assert_eq!(app.filter, "q?");
+        assert!(!app.show_help);
+        let action = app.on_key(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(matches!(action, Some(AppAction::Quit)));
+    }
+
+    fn paste_into_add_form(app: &mut App, text: &str) -> Host {
+        app.handle_normal(KeyEvent::from(KeyCode::Char('n')))
+            .unwrap();
+        app.on_event(Event::Paste(text.into())).unwrap();
+        app.form.as_ref().unwrap().build_host().unwrap()
+    }
+
+    fn form_value(app: &App, label: &'static str) -> String {
+        app.form
+            .as_ref()
+            .unwrap()
+            .field(label)
+            .unwrap()
+            .value
+            .clone()
+    }
+
+    #[test]
+    fn pasted_ssh_config_stanza_fills_the_add_form() {
+        let mut app = test_app();
+        let host = paste_into_add_form(
+            &mut app,
+            "Host web1\r\n  HostName 10.0.0.1\r\n  User deploy\r\n  Port 2222\r\n  \
+             IdentityFile ~/.ssh/web\r\n  ProxyJump jump-eu\r\n  ServerAliveInterval 30\r\n  \
+             AddressFamily inet6\r\n",
+        );
+        assert_eq!(host.name, "web1");
+        assert_eq!(host.address, "10.0.0.1");
+        assert_eq!(host.user.as_deref(), Some("deploy"));
+        assert_eq!(host.port, Some(2222));
+        assert_eq!(host.key_paths, vec!["~/.ssh/web".to_string()]);
+        assert_eq!(host.bastion.as_deref(), Some("jump-eu"));
+        assert_eq!(host.options, vec!["-o", "ServerAliveInterval=30"]);
+        assert_eq!(host.ip_family, Some(IpFamily::V6));
+        assert_eq!(
+            form_value(&app, FIELD_SSH_COMMAND),
+            "ssh -p 2222 -6 -i ~/.ssh/web -J jump-eu -o ServerAliveInterval=30 deploy@10.0.0.1"
+        );
+        assert!(app.form.as_ref().unwrap().paste_warnings.is_empty());
+    }
+
+    #[test]
+    fn port_field_only_takes_digits() {
+        let mut app = test_app();
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.index = form.field_index(FIELD_PORT).unwrap();
+        form.set_field_value(FIELD_PORT, String::new());
+        type_keys(&mut app, "2a2-2:2");
+        assert_eq!(form_value(&app, FIELD_PORT), "2222");
+        type_keys(&mut app, "22");
+        assert_eq!(form_value(&app, FIELD_PORT), "222222");
+    }
+
+    #[test]
+    fn forwards_get_their_own_form_fields() {
+        let mut app = test_app();
+        let host = paste_into_add_form(
+            &mut app,
+            "ssh -L 8080:localhost:80 -R9000:localhost:9000 -A ops@10.0.0.7",
+        );
+        assert_eq!(host.local_forwards, ["8080:localhost:80"]);
+        assert_eq!(host.remote_forwards, ["9000:localhost:9000"]);
+        assert!(host.options.is_empty());
+        assert!(host.forward_agent);
+        assert_eq!(form_value(&app, FIELD_LOCAL_FORWARDS), "8080:localhost:80");
+
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(
+            FIELD_LOCAL_FORWARDS,
+            "8080:localhost:80, 5432:db:5432,".into(),
+        );
+        let host = form.build_host().unwrap();
+        assert_eq!(host.local_forwards, ["8080:localhost:80", "5432:db:5432"]);
+        form.set_field_value(FIELD_LOCAL_FORWARDS, "8080 localhost:80".into());
+        let err = form.build_host().unwrap_err();
+        assert!(format!("{err:#}").contains("contains spaces"), "{err:#}");
+
+        let edit = FormState::new(FormKind::Edit, Some(&host), &app.config);
+        assert_eq!(
+            edit.field(FIELD_LOCAL_FORWARDS).unwrap().value,
+            "8080:localhost:80, 5432:db:5432"
+        );
+        assert_eq!(
+            edit.build_host().unwrap().local_forwards,
+            host.local_forwards
+        );
+    }
+
+    #[test]
+    fn proxy_command_gets_its_own_form_field() {
+        let mut app = test_app();
+        let host = paste_into_add_form(
+            &mut app,
+            "ssh -o 'ProxyCommand=nc -x socks:1080 %h %p' -o ServerAliveInterval=30 ops@10.0.0.7",
+        );
+        assert_eq!(
+            host.proxy_command.as_deref(),
+            Some("nc -x socks:1080 %h %p")
+        );
+        assert_eq!(host.options, ["-o", "ServerAliveInterval=30"]);
+        assert_eq!(
+            form_value(&app, FIELD_PROXY_COMMAND),
+            "nc -x socks:1080 %h %p"
+        );
+
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_BASTION, "jump-eu".into());
+        let err = form.build_host().unwrap_err();
+        assert!(format!("{err:#}").contains("not both"), "{err:#}");
+
+        let host = paste_into_add_form(
+            &mut app,
+            "Host inner\n  HostName 10.1.0.4\n  ProxyCommand ssh -W %h:%p gw\n",
+        );
+        assert_eq!(host.proxy_command.as_deref(), Some("ssh -W %h:%p gw"));
+    }
+
+    #[test]
+    fn connect_modal_cycles_through_command_presets() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(
+            FIELD_COMMAND_PRESETS,
+            "tail -f /var/log/app.log,\ndocker ps, ,".into(),
+        );
+        let host = form.build_host().unwrap();
+        assert_eq!(
+            host.command_presets,
+            ["tail -f /var/log/app.log", "docker ps"]
+        );
+        app.config.hosts[0] = host;
+        app.form = None;
+        app.mode = Mode::Normal;
+
+        press(&mut app, 'c');
+        let extra = |app: &mut App, code: KeyCode| {
+            app.on_key(KeyEvent::from(code)).unwrap();
+            match &app.confirm {
+                Some(ConfirmKind::Connect { extra_cmd, .. }) => extra_cmd.clone(),
+                other => panic!("expected the connect modal, got {other:?}"),
+            }
+        };
+        assert_eq!(extra(&mut app, KeyCode::Down), "tail -f /var/log/app.log");
+        assert_eq!(extra(&mut app, KeyCode::Down), "docker ps");
+        assert_eq!(extra(&mut app, KeyCode::Down), "tail -f /var/log/app.log");
+        assert_eq!(extra(&mut app, KeyCode::Up), "docker ps");
+        // A typed command isn't a preset; Up starts from the last one.
+        assert_eq!(extra(&mut app, KeyCode::Char('!')), "docker ps!");
+        assert_eq!(extra(&mut app, KeyCode::Up), "docker ps");
+
+        // Without presets the arrows leave the command alone.
+        app.on_key(KeyEvent::from(KeyCode::Esc)).unwrap();
+        select(&mut app, "staging-db");
+        press(&mut app, 'c');
+        assert_eq!(extra(&mut app, KeyCode::Char('w')), "w");
+        assert_eq!(extra(&mut app, KeyCode::Down), "w");
+    }
+
+    #[test]
+    fn set_env_options_get_their_own_form_field() {
+        let mut app = test_app();
+        app.config.hosts[2].role = Some(HostRole::Normal);
+        let host = paste_into_add_form(
+            &mut app,
+            "ssh -o SetEnv=LANG=C -o 'SetEnv=A=1 B=2' -o ServerAliveInterval=30 ops@10.0.0.7",
+        );
+        assert_eq!(host.set_env, ["LANG=C", "A=1", "B=2"]);
+        assert_eq!(host.options, ["-o", "ServerAliveInterval=30"]);
+        assert_eq!(form_value(&app, FIELD_SET_ENV), "LANG=C A=1 B=2");
+
+        // Words that aren't KEY=value are left out, with a warning.
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_NAME, "app".into());
+        form.set_field_value(FIELD_SET_ENV, "LANG=C oops =x".into());
+        app.submit_form(true);
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.config.find_host("app").unwrap().set_env, ["LANG=C"]);
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Warn);
+        assert!(
+            status
+                .text
+                .ends_with("Left out of Env, not KEY=value: oops =x."),
+            "{}",
+            status.text
+        );
+
+        let host = paste_into_add_form(
+            &mut app,
+            "Host app2\n  HostName 10.1.0.4\n  SetEnv LANG=C FOO=bar\n",
+        );
+        assert_eq!(host.set_env, ["LANG=C", "FOO=bar"]);
+        assert!(host.options.is_empty());
+    }
+
+    #[test]
+    fn set_env_values_with_spaces_survive_an_edit() {
+        let mut app = test_app();
+        app.config.hosts[0].set_env = vec!["GREETING=hello world".into(), "LANG=C".into()];
+        select(&mut app, "prod-web");
+        app.handle_normal(KeyEvent::from(KeyCode::Char('e')))
+            .unwrap();
+        assert_eq!(
+            form_value(&app, FIELD_SET_ENV),
+            "'GREETING=hello world' LANG=C"
+        );
+        // Saving for another field leaves Env as it was.
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_USER, "root".into());
+        app.handle_form(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(app.form.is_none());
+        let host = app.current_host().unwrap();
+        assert_eq!(host.user.as_deref(), Some("root"));
+        assert_eq!(host.set_env, ["GREETING=hello world", "LANG=C"]);
+    }
+
+    #[test]
+    fn pasted_stanza_quirks_become_warnings() {
+        let mut app = test_app();
+        let host = paste_into_add_form(
+            &mut app,
+            "Host db db.internal\n  User root\n  User ignored\n  Port abc\n  \
+             LocalForward 5432 localhost:5432\n  SendEnv LANG LC_*\n  SetEnv A=1 B=2\n  \
+             PreferredAuthentications publickey\n\
+             Host other\n  HostName 10.9.9.9\n",
+        );
+        // No HostName: the Host pattern doubles as the address.
+        assert_eq!(host.name, "db");
+        assert_eq!(host.address, "db");
+        assert_eq!(host.user.as_deref(), Some("root"));
+        assert_eq!(host.port, None);
+        assert!(host.options.is_empty());
+        assert_eq!(host.local_forwards, ["5432:localhost:5432"]);
+        assert!(host.prefer_public_key_auth);
+        let warnings = app.form.as_ref().unwrap().paste_warnings.join("\n");
+        assert!(warnings.contains("Pasted 2 Host blocks; using the first (db)"));
+        assert!(warnings.contains("lists 2 patterns"));
+        assert!(warnings.contains("Skipped Port"));
+        assert!(warnings.contains("Skipped SendEnv"));
+        assert_eq!(host.set_env, ["A=1", "B=2"]);
+
+        // Typing over the command clears them again on the next frame.
+        app.handle_form(KeyEvent::from(KeyCode::Char('x'))).unwrap();
+        app.tick();
+        assert!(app.form.as_ref().unwrap().paste_warnings.is_empty());
+    }
+
+    #[test]
+    fn typed_command_keeps_fields_edited_by_hand() {
+        let mut app = test_app();
+        app.handle_normal(KeyEvent::from(KeyCode::Char('n')))
+            .unwrap();
+        type_keys(&mut app, "ssh -p 2200 ops@10.1.1.1");
+        // Nothing is parsed until the frame ends.
+        assert_eq!(form_value(&app, FIELD_USER), "");
+        app.tick();
+        assert_eq!(form_value(&app, FIELD_USER), "ops");
+
+        let form = app.form.as_mut().unwrap();
+        form.index = form.field_index(FIELD_USER).unwrap();
+        for _ in 0.."ops".len() {
+            app.handle_form(KeyEvent::from(KeyCode::Backspace)).unwrap();
+        }
+        type_keys(&mut app, "root");
+        assert!(app.form.as_ref().unwrap().manual.contains(FIELD_USER));
+
+        app.form.as_mut().unwrap().index = 0;
+        type_keys(&mut app, "0");
+        app.tick();
+        assert_eq!(form_value(&app, FIELD_HOST), "10.1.1.10");
+        assert_eq!(form_value(&app, FIELD_USER), "root");
+        assert_eq!(form_value(&app, FIELD_PORT), "2200");
+
+        // Clearing the field hands it back to the command.
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_SSH_COMMAND, "ssh web@10.1.1.1".into());
+        form.index = form.field_index(FIELD_USER).unwrap();
+        for _ in 0.."root".len() {
+            app.handle_form(KeyEvent::from(KeyCode::Backspace)).unwrap();
+        }
+        assert!(!app.form.as_ref().unwrap().manual.contains(FIELD_USER));
+        app.tick();
+        assert_eq!(form_value(&app, FIELD_USER), "web");
+    }
+
+    #[test]
+    fn unchanged_command_is_not_parsed_again() {
+        let config = Config::sample();
+        let mut form = FormState::new(FormKind::Add, None, &config);
+        form.set_field_value(FIELD_SSH_COMMAND, "ssh ops@10.1.1.1".into());
+        assert!(form.sync_command_field());
+        assert!(!form.sync_command_field());
+
+        // Moving the cursor changes nothing.
+        form.handle_input(KeyEvent::from(KeyCode::Left), &config);
+        assert!(!form.sync_command_field());
+
+        // A different text that parses to the same spec leaves the fields be.
+        form.set_field_value(FIELD_HOST, "10.9.9.9".into());
+        form.set_field_value(FIELD_SSH_COMMAND, "ssh  ops@10.1.1.1".into());
+        assert!(form.sync_command_field());
+        assert_eq!(form.field(FIELD_HOST).unwrap().value, "10.9.9.9");
+    }
+
+    #[test]
+    fn edits_touching_the_address_or_many_fields_are_reviewed_first() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        app.handle_normal(KeyEvent::from(KeyCode::Char('e')))
+            .unwrap();
+        let form = app.form.as_mut().unwrap();
+        assert!(form.changes().is_empty());
+        form.set_field_value(FIELD_USER, "root".into());
+        let changes = form.changes();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            (changes[0].old.as_str(), changes[0].new.as_str()),
+            ("deploy", "root")
+        );
+        // A small edit saves straight away.
+        app.handle_form(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(app.form.is_none());
+        assert_eq!(app.current_host().unwrap().user.as_deref(), Some("root"));
+
+        app.handle_normal(KeyEvent::from(KeyCode::Char('e')))
+            .unwrap();
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_HOST, "10.0.0.9".into());
+        app.handle_form(KeyEvent::from(KeyCode::Enter)).unwrap();
+        let review = app.form.as_ref().unwrap().review.clone().unwrap();
+        assert_eq!(review[0].field, "host");
+        // Esc goes back to the form without saving.
+        app.handle_form(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(app.form.as_ref().unwrap().review.is_none());
+        assert!(matches!(app.mode, Mode::Form));
+        assert_eq!(app.current_host().unwrap().address, "52.14.33.10");
+
+        app.handle_form(KeyEvent::from(KeyCode::Enter)).unwrap();
+        app.handle_form(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(app.form.is_none());
+        assert_eq!(app.current_host().unwrap().address, "10.0.0.9");
+
+        app.handle_normal(KeyEvent::from(KeyCode::Char('e')))
+            .unwrap();
+        let form = app.form.as_mut().unwrap();
+        for (label, value) in [
+            (FIELD_PORT, "2222"),
+            (FIELD_TAGS, "a,b"),
+            (FIELD_OPTIONS, "-A"),
+            (FIELD_DESCRIPTION, "moved"),
+        ] {
+            form.set_field_value(label, value.into());
+        }
+        app.handle_form(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert_eq!(app.form.as_ref().unwrap().review.as_ref().unwrap().len(), 4);
+        app.handle_form(KeyEvent::from(KeyCode::Char('y'))).unwrap();
+        assert_eq!(app.current_host().unwrap().port, Some(2222));
+    }
+
+    #[test]
+    fn wildcard_stanza_leaves_name_alone() {
+        let mut app = test_app();
+        app.handle_normal(KeyEvent::from(KeyCode::Char('n')))
+            .unwrap();
+        app.on_event(Event::Paste("Host *.prod\n  User ops\n".into()))
+            .unwrap();
+        let form = app.form.as_ref().unwrap();
+        assert_eq!(form_value(&app, FIELD_NAME), "");
+        assert_eq!(form_value(&app, FIELD_SSH_COMMAND), "");
+        assert_eq!(form_value(&app, FIELD_USER), "ops");
+        assert!(form.paste_warnings.iter().any(|w| w.contains("wildcard")));
+        assert!(form
+            .paste_warnings
+            .iter()
+            .any(|w| w.contains("No HostName")));
+    }
+
+    #[test]
+    fn pasted_ssh_command_still_parses_as_a_command() {
+        let mut app = test_app();
+        let host = paste_into_add_form(&mut app, "ssh -p 2200 ops@10.1.1.1\n");
+        assert_eq!(host.address, "10.1.1.1");
+        assert_eq!(host.port, Some(2200));
+        assert_eq!(host.name, "ops@10.1.1.1");
+    }
+
+    #[test]
+    fn paste_outside_text_inputs_is_ignored() {
+        let mut app = test_app();
+        app.on_event(Event::Paste("q".into())).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+        app.handle_normal(KeyEvent::from(KeyCode::Char('/')))
+            .unwrap();
+        app.on_event(Event::Paste("jump\nsecond line".into()))
+            .unwrap();
+        assert_eq!(app.filter, "jump");
+    }
+
+    pub(crate) fn select(app: &mut App, name: &str) {
+        app.selected = app
+            .filtered_indices
+            .iter()
+            .position(|i| app.config.hosts[*i].name == name)
+            .unwrap();
+    }
+
+    #[test]
+    fn bulk_edit_changes_marked_hosts_in_one_undo_step() {
+        let mut app = test_app();
+        let key = |c| KeyEvent::from(KeyCode::Char(c));
+        app.handle_normal(key('E')).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+        assert!(app.status.current().unwrap().text.contains("Space"));
+
+        select(&mut app, "prod-web");
+        app.handle_normal(key(' ')).unwrap();
+        select(&mut app, "jump-eu");
+        app.handle_normal(key(' ')).unwrap();
+        assert_eq!(app.marked.len(), 2);
+
+        app.handle_normal(key('E')).unwrap();
+        assert!(matches!(app.mode, Mode::Bulk));
+        type_keys(&mut app, "admin");
+        let preview = app.bulk_preview().unwrap();
+        let ids: Vec<_> = preview.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["prod-web", "jump-eu"]);
+        assert_eq!(preview[0].before, "deploy");
+
+        let history = app.history.len();
+        app.handle_bulk(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.history.len(), history + 1);
+        let users: Vec<_> = app
+            .config
+            .hosts
+            .iter()
+            .map(|h| h.user.as_deref().unwrap_or(""))
+            .collect();
+        assert_eq!(users, ["admin", "db", "admin"]);
+
+        app.handle_normal(key('u')).unwrap();
+        assert_eq!(app.config.hosts[0].user.as_deref(), Some("deploy"));
+        assert_eq!(app.config.hosts[2].user.as_deref(), Some("ops"));
+    }
+
+    #[test]
+    fn bulk_edit_validates_the_whole_config_before_writing() {
+        let mut app = test_app();
+        app.marked = ["prod-web".to_string(), "jump-eu".to_string()].into();
+        app.handle_normal(KeyEvent::from(KeyCode::Char('E')))
+            .unwrap();
+        let edit = app.bulk.as_mut().unwrap();
+        while edit.field() != bulk::BulkField::Bastion {
+            edit.cycle(1);
+        }
+        // jump-eu would become its own bastion.
+        type_keys(&mut app, "jump-eu");
+        assert!(app.bulk_preview().is_err());
+        let before = app.config.clone();
+        app.handle_bulk(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.mode, Mode::Bulk));
+        assert_eq!(app.config, before);
+        assert_eq!(app.status.current().unwrap().kind, StatusKind::Error);
+
+        app.handle_bulk(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+        assert!(app.bulk.is_none());
+    }
+
+    #[test]
+    fn problems_panel_acknowledges_ssh_config_aliases() {
+        let mut app = test_app();
+        app.config.hosts[0].address = "corp-jump".into();
+        app.handle_normal(KeyEvent::from(KeyCode::Char('!')))
+            .unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.status.current().unwrap().text, "No problems found.");
+
+        app.resolver.record("corp-jump", &[]);
+        assert_eq!(app.problems().len(), 1);
+        app.set_filter("staging");
+        app.handle_normal(KeyEvent::from(KeyCode::Char('!')))
+            .unwrap();
+        assert!(matches!(app.mode, Mode::Problems));
+        app.handle_problems(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(app.filter.is_empty());
+        assert_eq!(app.current_host().unwrap().address, "corp-jump");
+
+        app.handle_normal(KeyEvent::from(KeyCode::Char('!')))
+            .unwrap();
+        app.handle_problems(KeyEvent::from(KeyCode::Char('a')))
+            .unwrap();
+        assert!(app.config.hosts[0].alias_ok);
+        assert!(app.problems().is_empty());
+        assert!(matches!(app.mode, Mode::Normal));
+
+        // Editing keeps the acknowledgement unless the address changes.
+        app.handle_normal(KeyEvent::from(KeyCode::Char('e')))
+            .unwrap();
+        app.handle_form(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(app
+            .status
+            .current()
+            .unwrap()
+            .text
+            .starts_with("Updated host"));
+        assert!(app.config.hosts[0].alias_ok);
+    }
+
+    #[test]
+    fn capital_v_shows_the_full_command_until_pressed_again() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        press(&mut app, 'V');
+        assert!(matches!(app.mode, Mode::FullCommand));
+        let lines = app.current_command_lines().unwrap().unwrap();
+        assert_eq!(lines.join(" "), app.current_connection_string().unwrap());
+        press(&mut app, 'j');
+        assert_eq!(app.explain_scroll, 1);
+        press(&mut app, 'V');
+        assert!(matches!(app.mode, Mode::Normal));
+    }
+
+    #[test]
+    fn explain_view_opens_on_i_and_scrolls() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        app.handle_normal(KeyEvent::from(KeyCode::Char('i')))
+            .unwrap();
+        assert!(matches!(app.mode, Mode::Explain));
+        let explanation = app.current_explanation().unwrap();
+        assert_eq!(
+            explanation.argv.unwrap(),
+            ssh::build_argv(
+                app.current_host().unwrap(),
+                &app.config,
+                app.config.default_key.as_deref(),
+                None
+            )
+            .unwrap()
+        );
+        app.handle_explain(KeyEvent::from(KeyCode::Down)).unwrap();
+        app.handle_explain(KeyEvent::from(KeyCode::Down)).unwrap();
+        app.handle_explain(KeyEvent::from(KeyCode::Up)).unwrap();
+        assert_eq!(app.explain_scroll, 1);
+        app.handle_explain(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+    }
+
+    fn search(app: &mut App, query: &str) {
+        app.handle_normal(KeyEvent::from(KeyCode::Char('/')))
+            .unwrap();
+        type_keys(app, query);
+        app.handle_search(KeyEvent::from(KeyCode::Enter)).unwrap();
+    }
+
+    fn current_name(app: &App) -> &str {
+        &app.current_host().unwrap().name
+    }
+
+    #[test]
+    fn ctrl_u_empties_the_search_but_stays_in_it() {
+        let mut app = test_app();
+        search(&mut app, "prod");
+        press(&mut app, '/');
+        let ctrl_u = KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL);
+        app.on_key(ctrl_u).unwrap();
+        assert!(app.filter.is_empty());
+        assert!(matches!(app.mode, Mode::Search));
+        assert_eq!(app.filtered_indices.len(), app.config.hosts.len());
+        type_keys(&mut app, "stag");
+        assert_eq!(names(&app), ["staging-db"]);
+    }
+
+    #[test]
+    fn slash_goes_on_from_the_active_search() {
+        let mut app = test_app();
+        search(&mut app, "prod");
+        press(&mut app, '/');
+        assert_eq!(app.filter, "prod");
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "Search: type to extend, Ctrl+U to start over, Esc to clear."
+        );
+        type_keys(&mut app, "-w");
+        assert_eq!(app.filter, "prod-w");
+        assert_eq!(names(&app), ["prod-web"]);
+    }
+
+    #[test]
+    fn clearing_a_search_returns_to_the_pre_search_host() {
+        let mut app = test_app();
+        select(&mut app, "jump-eu");
+        search(&mut app, "prod");
+        assert_eq!(current_name(&app), "prod-web");
+
+        app.handle_normal(KeyEvent::from(KeyCode::Char('/')))
+            .unwrap();
+        app.handle_search(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(app.filter.is_empty());
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(current_name(&app), "jump-eu");
+    }
+
+    #[test]
+    fn host_picked_in_results_survives_clearing_the_search() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        search(&mut app, "e");
+        assert!(app.filtered_indices.len() > 1);
+        // Move off the pre-search host, wherever it ranks in the results.
+        loop {
+            app.handle_normal(KeyEvent::from(KeyCode::Char('j')))
+                .unwrap();
+            if current_name(&app) != "prod-web" {
+                break;
+            }
+        }
+        let picked = current_name(&app).to_string();
+
+        app.handle_normal(KeyEvent::from(KeyCode::Char('/')))
+            .unwrap();
+        app.handle_search(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert_eq!(current_name(&app), picked);
+    }
+
+    #[test]
+    fn toggling_between_search_and_list_restores_each_position() {
+        let mut app = test_app();
+        select(&mut app, "staging-db");
+        search(&mut app, "e");
+        app.handle_normal(KeyEvent::from(KeyCode::Char('j')))
+            .unwrap();
+        let in_results = current_name(&app).to_string();
+
+        app.handle_normal(KeyEvent::from(KeyCode::Char('/')))
+            .unwrap();
+        app.handle_search(KeyEvent::from(KeyCode::Backspace))
+            .unwrap();
+        assert_eq!(current_name(&app), in_results);
+
+        // A fresh search that is only browsed, not picked from.
+        select(&mut app, "staging-db");
+        type_keys(&mut app, "e");
+        assert_eq!(current_name(&app), in_results);
+        app.handle_search(KeyEvent::from(KeyCode::Backspace))
+            .unwrap();
+        assert_eq!(current_name(&app), "staging-db");
+    }
+
+    #[test]
+    fn rename_prompt_renames_and_repoints_bastions() {
+        let mut app = test_app();
+        select(&mut app, "jump-eu");
+        app.handle_normal(KeyEvent::from(KeyCode::Char('R')))
+            .unwrap();
+        for _ in 0.."eu".len() {
+            app.handle_confirm(KeyEvent::from(KeyCode::Backspace))
+                .unwrap();
+        }
+        type_keys(&mut app, "fra");
+        app.handle_confirm(KeyEvent::from(KeyCode::Enter)).unwrap();
+
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.current_host().unwrap().name, "jump-fra");
+        let db = app.config.find_host("staging-db").unwrap();
+        assert_eq!(db.bastion.as_deref(), Some("jump-fra"));
+        let status = &app.status.current().unwrap().text;
+        assert_eq!(
+            status,
+            "Renamed jump-eu to jump-fra; updated bastion of staging-db."
+        );
+        app.undo().unwrap();
+        assert_eq!(
+            app.config
+                .find_host("staging-db")
+                .unwrap()
+                .bastion
+                .as_deref(),
+            Some("jump-eu")
+        );
+    }
+
+    #[test]
+    fn rename_prompt_rejects_taken_names_and_stays_open() {
+        let mut app = test_app();
+        select(&mut app, "jump-eu");
+        app.handle_normal(KeyEvent::from(KeyCode::Char('R')))
+            .unwrap();
+        app.confirm = Some(ConfirmKind::Rename {
+            name: "prod-web".into(),
+        });
+        app.handle_confirm(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.mode, Mode::Confirm));
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Error);
+        assert!(status.text.contains("already exists"));
+        assert!(app.config.find_host("jump-eu").is_some());
+    }
+
+    #[test]
+    fn renaming_in_the_edit_form_repoints_bastions() {
+        let mut app = test_app();
+        select(&mut app, "jump-eu");
+        let mut host = app.current_host().unwrap().clone();
+        host.name = "jump-1".into();
+        app.save_host(FormKind::Edit, host).unwrap();
+        let db = app.config.find_host("staging-db").unwrap();
+        assert_eq!(db.bastion.as_deref(), Some("jump-1"));
+        assert!(app
+            .status
+            .current()
+            .unwrap()
+            .text
+            .contains("updated bastion of staging-db"));
+    }
+
+    #[test]
+    fn completes_known_users() {
+        let known = vec!["db".to_string(), "deploy".to_string(), "ops".to_string()];
+        assert_eq!(complete_user(&known, "de"), "deploy");
+        assert_eq!(complete_user(&known, "deploy"), "ops");
+        assert_eq!(complete_user(&known, "ops"), "db");
+        assert_eq!(complete_user(&known, "root"), "root");
+    }
+
+    #[test]
+    fn connect_as_overrides_user_for_one_invocation() {
+        let mut app = test_app();
+        app.selected = app
+            .filtered_indices
+            .iter()
+            .position(|i| app.config.hosts[*i].name == "staging-db")
+            .unwrap();
+        app.dry_run = true;
+        app.handle_normal(KeyEvent::from(KeyCode::Char('U')))
+            .unwrap();
+        assert!(matches!(
+            app.confirm,
+            Some(ConfirmKind::ConnectAs { ref user }) if user == "db"
+        ));
+        for _ in 0..2 {
+            app.handle_confirm(KeyEvent::from(KeyCode::Backspace))
+                .unwrap();
+        }
+        for c in "root".chars() {
+            app.handle_confirm(KeyEvent::from(KeyCode::Char(c)))
+                .unwrap();
+        }
+        app.handle_confirm(KeyEvent::from(KeyCode::Enter)).unwrap();
+
+        let status = app.status.current().unwrap().text.clone();
+        assert!(status.contains("as root"));
+        assert!(status.contains("root@35.12.2.4"));
+        assert!(status.contains("-J ops@52.17.9.3"), "bastion user kept");
+        assert_eq!(app.current_host().unwrap().user.as_deref(), Some("db"));
+        assert!(app.history.is_empty());
+    }
+
+    fn large_config_store(dir: &std::path::Path) -> ConfigStore {
+        let store = ConfigStore::at(dir.join("config.toml"));
+        let config = Config {
+            hosts: (0..5000)
+                .map(|i| Host {
+                    name: format!("host-{i}"),
+                    address: format!("host-{i}.example.net"),
+                    user: Some("ops".into()),
+                    tags: vec!["bulk".into()],
+                    ..Default::default()
+                })
+                .collect(),
+            ..Config::default()
+        };
+        store.save(&config).unwrap();
+        store
+    }
+
+    #[test]
+    fn opening_a_large_config_does_no_per_host_work() {
+        let dir = tempdir().unwrap();
+        let app = App::new(large_config_store(dir.path())).unwrap();
+        assert_eq!(app.filtered_indices.len(), 5000);
+        // Lookups, probes and housekeeping wait for the first frame.
+        let now = Instant::now();
+        for host in &app.config.hosts {
+            assert!(app.resolver.resolution(&host.address).is_none());
+            assert!(app.probes.status(&host.address, 22, now).is_none());
+        }
+        assert!(app.background_rx.try_recv().is_err());
+    }
+
+    /// Wall-clock budget, too noisy for CI: `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn opens_large_config_quickly() {
+        let dir = tempdir().unwrap();
+        let store = large_config_store(dir.path());
+        let started = std::time::Instant::now();
+        let app = App::new(store).unwrap();
+        let elapsed = started.elapsed();
+        assert_eq!(app.filtered_indices.len(), 5000);
+        assert!(
+            elapsed < std::time::Duration::from_secs(2),
+            "App::new took {elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn unwritable_config_opens_read_only_and_continues_from_a_copy() {
+        let _guard = crate::ENV_LOCK.lock().unwrap();
+        let home = tempdir().unwrap();
+        let old_home = std::env::var_os("HOME");
+        unsafe { std::env::set_var("HOME", home.path()) };
+
+        let blocker = home.path().join("blocker");
+        std::fs::write(&blocker, "").unwrap();
+        let mut app = App::new(ConfigStore::at(blocker.join("config.toml"))).unwrap();
+        let reason = app.read_only.clone().expect("read-only");
+        assert!(reason.contains(&blocker.display().to_string()), "{reason}");
+        let status = app.status.current().unwrap().clone();
+        assert!(matches!(status.kind, StatusKind::Warn));
+        assert!(status.text.contains("press W"), "{}", status.text);
+
+        app.config = Config::sample();
+        app.save_config().unwrap();
+        assert!(app.status.current().unwrap().text.starts_with("Not saved"));
+
+        app.on_key(KeyEvent::from(KeyCode::Char('W'))).unwrap();
+        let copy = home.path().join(".sshdb").join("config.toml");
+        let loaded = ConfigStore::at(copy.clone()).load_read_only();
+        let text = app.status.current().unwrap().text.clone();
+        unsafe {
+            match old_home {
+                Some(v) => std::env::set_var("HOME", v),
+                None => std::env::remove_var("HOME"),
+            }
+        }
+        assert!(app.read_only.is_none());
+        assert_eq!(app.config_path, copy);
+        assert!(text.contains("SSHDB_CONFIG="), "{text}");
+        assert_eq!(loaded.unwrap(), Config::sample());
+
+        app.config.hosts.pop();
+        app.save_config().unwrap();
+        assert_eq!(
+            ConfigStore::at(copy).load_read_only().unwrap().hosts.len(),
+            Config::sample().hosts.len() - 1
+        );
+    }
+
+    fn add_numbered(app: &mut App, name: &str, address: &str) {
+        app.config.hosts.push(Host {
+            name: name.into(),
+            address: address.into(),
+            user: Some("deploy".into()),
+            ..Default::default()
+        });
+        app.rebuild_filter();
+    }
+
+    #[test]
+    fn next_in_series_opens_the_add_form_with_the_number_bumped() {
+        let mut app = test_app();
+        add_numbered(&mut app, "web-02", "web-02.prod");
+        add_numbered(&mut app, "web-03", "web-03.prod");
+        select(&mut app, "web-02");
+        let hosts = app.config.hosts.len();
+        app.on_key(KeyEvent::from(KeyCode::Char('Y'))).unwrap();
+        assert!(matches!(app.mode, Mode::Form));
+        assert_eq!(form_value(&app, FIELD_NAME), "web-04");
+        assert_eq!(form_value(&app, FIELD_HOST), "web-04.prod");
+        assert_eq!(form_value(&app, FIELD_USER), "deploy");
+        assert_eq!(
+            app.config.hosts.len(),
+            hosts,
+            "nothing is saved before review"
+        );
+
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        let added = app.config.hosts.last().unwrap();
+        assert_eq!(
+            (added.name.as_str(), added.address.as_str()),
+            ("web-04", "web-04.prod")
+        );
+    }
+
+    #[test]
+    fn next_in_series_asks_before_bumping_an_ipv4_address() {
+        let mut app = test_app();
+        add_numbered(&mut app, "db9", "10.0.0.12");
+        select(&mut app, "db9");
+        app.on_key(KeyEvent::from(KeyCode::Char('Y'))).unwrap();
+        let Some(ConfirmKind::SeriesAddress { address, .. }) = app.confirm.clone() else {
+            panic!("expected the address prompt, got {:?}", app.confirm);
+        };
+        assert_eq!(address, "10.0.0.13");
+        app.on_key(KeyEvent::from(KeyCode::Char('n'))).unwrap();
+        assert_eq!(form_value(&app, FIELD_NAME), "db10");
+        assert_eq!(form_value(&app, FIELD_HOST), "10.0.0.12");
+
+        app.form = None;
+        app.mode = Mode::Normal;
+        app.on_key(KeyEvent::from(KeyCode::Char('Y'))).unwrap();
+        app.on_key(KeyEvent::from(KeyCode::Char('y'))).unwrap();
+        assert_eq!(form_value(&app, FIELD_HOST), "10.0.0.13");
+    }
+
+    #[test]
+    fn next_in_series_without_a_number_duplicates() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        app.on_key(KeyEvent::from(KeyCode::Char('Y'))).unwrap();
+        assert!(app.form.is_none());
+        assert!(app.config.hosts.iter().any(|h| h.name == "prod-web-copy"));
+    }
+
+    fn app_on_disk() -> (tempfile::TempDir, App) {
+        let dir = tempdir().unwrap();
+        let store = ConfigStore::at(dir.path().join("config.toml"));
+        store.save(&Config::sample()).unwrap();
+        let app = App::new(store).unwrap();
+        (dir, app)
+    }
+
+    pub(crate) fn press(app: &mut App, c: char) {
+        app.on_key(KeyEvent::from(KeyCode::Char(c))).unwrap();
+    }
+
+    #[test]
+    fn f_switches_profiles_and_edits_land_in_the_active_one() {
+        let dir = tempdir().unwrap();
+        let store = ConfigStore::at(dir.path().join("config.toml"));
+        let mut config = Config::sample();
+        config.profiles.push(Profile {
+            name: "work".into(),
+            hosts: vec![Host {
+                name: "ci".into(),
+                address: "10.0.0.5".into(),
+                ..Default::default()
+            }],
+        });
+        store.save(&config).unwrap();
+        let mut app = App::new(store).unwrap();
+
+        press(&mut app, 'F');
+        assert_eq!(names(&app), ["ci"]);
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "Profile: work (1 host(s))."
+        );
+        app.apply_change(Change::Add(Host {
+            name: "build".into(),
+            address: "10.0.0.6".into(),
+            ..Default::default()
+        }))
+        .unwrap();
+        let saved = app.store.load_read_only().unwrap();
+        assert_eq!(saved.hosts, Config::sample().hosts);
+        assert_eq!(saved.profiles[0].hosts.len(), 2);
+
+        // Undo and a reload both stay in the profile.
+        press(&mut app, 'u');
+        assert_eq!(names(&app), ["ci"]);
+        press(&mut app, 'r');
+        assert_eq!(names(&app), ["ci"]);
+        assert_eq!(app.config.active_profile.as_deref(), Some("work"));
+
+        press(&mut app, 'F');
+        assert_eq!(app.config.active_profile, None);
+        assert_eq!(app.config.hosts, Config::sample().hosts);
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "Profile: default (3 host(s))."
+        );
+    }
 
-        let target_idx = if let Some(idx) = self.find_host_by_spec(&spec) {
-            self.status = Some(StatusLine {
-                text: "Quick connect using existing host.".into(),
-                kind: StatusKind::Info,
-            });
-            idx
-        } else {
-            self.push_history();
-            let name_base = if let Some(user) = &spec.user {
-                format!("{user}@{}", spec.address)
-            } else {
-                spec.address.clone()
-            };
-            let name = self.unique_name(&name_base);
-            let host = Host {
-                name: name.clone(),
-                address: spec.address.clone(),
-                user: spec.user.clone(),
-                port: spec.port,
-                key_paths: spec.key_paths.clone(),
-                tags: Vec::new(),
-                options: spec.options.clone(),
-                remote_command: spec.remote_command.clone(),
-                bastion: spec.bastion.clone(),
-                prefer_public_key_auth: spec.prefer_public_key_auth,
-                description: None,
-            };
-            self.config.hosts.push(host);
-            self.store.save(&self.config)?;
-            self.rebuild_filter();
-            self.status = Some(StatusLine {
-                text: format!("Added {name} and connecting..."),
-                kind: StatusKind::Info,
-            });
-            self.config
-                .hosts
-                .iter()
-                .position(|h| h.name == name)
-                .unwrap_or(0)
+    #[test]
+    fn undo_asks_before_crossing_a_reload() {
+        let (_dir, mut app) = app_on_disk();
+        select(&mut app, "prod-web");
+        press(&mut app, 'y');
+        assert!(app.config.hosts.iter().any(|h| h.name == "prod-web-copy"));
+
+        // Someone else edits the file; `r` picks it up.
+        let mut external = app.store.load_read_only().unwrap();
+        external.hosts[1].user = Some("postgres".into());
+        app.store.save(&external).unwrap();
+        press(&mut app, 'r');
+        assert_eq!(app.generation, 1);
+
+        press(&mut app, 'u');
+        let Some(ConfirmKind::UndoPastReload { changes }) = app.confirm.clone() else {
+            panic!("expected the undo confirmation, got {:?}", app.confirm);
         };
+        assert_eq!(
+            changes,
+            ["staging-db: reverts user", "removes prod-web-copy"]
+        );
+        app.on_key(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert_eq!(app.config.hosts[1].user.as_deref(), Some("postgres"));
+        assert_eq!(app.history.len(), 1);
+
+        press(&mut app, 'u');
+        press(&mut app, 'y');
+        assert_eq!(app.config, Config::sample());
+        assert_eq!(app.store.load_read_only().unwrap(), Config::sample());
+    }
 
-        if let Some(pos) = self.filtered_indices.iter().position(|i| *i == target_idx) {
-            self.selected = pos;
-        }
+    #[test]
+    fn changes_after_a_reload_undo_without_asking() {
+        let (_dir, mut app) = app_on_disk();
+        select(&mut app, "prod-web");
+        press(&mut app, 'y');
+        press(&mut app, 'r');
+        select(&mut app, "jump-eu");
+        press(&mut app, 'y');
+        press(&mut app, 'u');
+        assert!(app.confirm.is_none());
+        assert!(!app.config.hosts.iter().any(|h| h.name == "jump-eu-copy"));
+
+        // `c` in the confirmation forgets the older steps instead.
+        press(&mut app, 'u');
+        press(&mut app, 'c');
+        assert!(app.history.is_empty());
+        assert!(app.config.hosts.iter().any(|h| h.name == "prod-web-copy"));
+    }
 
-        self.connect(None)
+    #[test]
+    fn deleting_a_bastion_clears_it_on_the_hosts_behind_it() {
+        let (dir, mut app) = app_on_disk();
+        select(&mut app, "prod-web");
+        press(&mut app, 'd');
+        let Some(ConfirmKind::Delete { dependents }) = &app.confirm else {
+            panic!("expected the delete confirm");
+        };
+        assert!(dependents.is_empty());
+        press(&mut app, 'y');
+        assert_eq!(app.status.current().unwrap().text, "Removed prod-web.");
+        assert_eq!(names(&app), ["staging-db", "jump-eu"]);
+
+        select(&mut app, "jump-eu");
+        press(&mut app, 'd');
+        let Some(ConfirmKind::Delete { dependents }) = &app.confirm else {
+            panic!("expected the delete confirm");
+        };
+        assert_eq!(dependents, &["staging-db"]);
+        press(&mut app, 'y');
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "Removed jump-eu; cleared the bastion of staging-db."
+        );
+        assert_eq!(names(&app), ["staging-db"]);
+        assert_eq!(app.config.hosts[0].bastion, None);
+        let saved = ConfigStore::at(dir.path().join("config.toml"))
+            .load_read_only()
+            .unwrap();
+        assert_eq!(saved.hosts.len(), 1);
+        assert_eq!(saved.hosts[0].bastion, None);
+
+        // One undo brings back the bastion and the reference to it.
+        press(&mut app, 'u');
+        assert_eq!(app.config.hosts.len(), 2);
+        assert_eq!(app.config.hosts[0].bastion.as_deref(), Some("jump-eu"));
     }
 
-    fn find_host_by_spec(&self, spec: &SshSpec) -> Option<usize> {
-        self.config.hosts.iter().position(|h| {
-            h.address == spec.address
-                && h.user.as_deref() == spec.user.as_deref()
-                && h.port == spec.port
-                && h.key_paths == spec.key_paths
-                && h.options == spec.options
-                && h.bastion.as_deref() == spec.bastion.as_deref()
-                && h.prefer_public_key_auth == spec.prefer_public_key_auth
-                && h.remote_command.as_deref() == spec.remote_command.as_deref()
-        })
+    #[test]
+    fn redo_reapplies_what_undo_took_back() {
+        let mut app = test_app();
+        let ctrl_r = KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL);
+        app.on_key(ctrl_r).unwrap();
+        assert_eq!(app.status.current().unwrap().text, "Nothing to redo.");
+
+        select(&mut app, "staging-db");
+        press(&mut app, 'd');
+        press(&mut app, 'y');
+        assert_eq!(app.config.hosts.len(), 2);
+        press(&mut app, 'u');
+        assert_eq!(app.config, Config::sample());
+        assert_eq!(app.redo_stack.len(), 1);
+
+        app.on_key(ctrl_r).unwrap();
+        assert_eq!(app.status.current().unwrap().text, "Redid last change.");
+        assert!(!app.config.hosts.iter().any(|h| h.name == "staging-db"));
+        assert!(app.redo_stack.is_empty());
+        // The redone change can be undone again.
+        press(&mut app, 'u');
+        assert_eq!(app.config, Config::sample());
+
+        // A new change drops what could be redone.
+        select(&mut app, "prod-web");
+        press(&mut app, 'y');
+        assert!(app.redo_stack.is_empty());
+        app.on_key(ctrl_r).unwrap();
+        assert_eq!(app.status.current().unwrap().text, "Nothing to redo.");
     }
 
-    fn unique_name(&self, base: &str) -> String {
-        if !self.config.hosts.iter().any(|h| h.name == base) {
-            return base.to_string();
+    #[test]
+    fn undo_and_redo_keep_twenty_steps_each() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        for _ in 0..25 {
+            press(&mut app, 'y');
         }
-        let mut i = 2;
-        loop {
-            let cand = format!("{base}-{i}");
-            if !self.config.hosts.iter().any(|h| h.name == cand) {
-                return cand;
-            }
-            i += 1;
+        assert_eq!(app.history.len(), 20);
+        for _ in 0..25 {
+            press(&mut app, 'u');
         }
-    }
-
-    fn push_history(&mut self) {
-        self.history.push(self.config.clone());
-        if self.history.len() > 20 {
-            self.history.remove(0);
+        assert_eq!(app.history.len(), 0);
+        assert_eq!(app.redo_stack.len(), 20);
+        assert_eq!(app.config.hosts.len(), 8);
+        let ctrl_r = KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL);
+        for _ in 0..25 {
+            app.on_key(ctrl_r).unwrap();
         }
+        assert_eq!(app.config.hosts.len(), 28);
+        assert_eq!(app.history.len(), 20);
     }
 
-    fn undo(&mut self) -> Result<bool> {
-        if let Some(prev) = self.history.pop() {
-            self.config = prev;
-            self.store.save(&self.config)?;
-            self.rebuild_filter();
-            return Ok(true);
+    #[test]
+    fn history_limit_comes_from_the_config_and_survives_reloads() {
+        let (_dir, mut app) = app_on_disk();
+        let reload_with_limit = |app: &mut App, limit| {
+            let mut config = app.config.clone();
+            config.history_limit = limit;
+            app.store.save(&config).unwrap();
+            press(app, 'r');
+        };
+        reload_with_limit(&mut app, 3);
+        select(&mut app, "prod-web");
+        for _ in 0..5 {
+            press(&mut app, 'y');
         }
-        Ok(false)
+        assert_eq!(app.history.len(), 3);
+
+        reload_with_limit(&mut app, 10);
+        assert_eq!(app.history.len(), 3);
+        press(&mut app, 'y');
+        assert_eq!(app.history.len(), 4);
+
+        // A lower limit only drops steps once there is a new one.
+        reload_with_limit(&mut app, 2);
+        assert_eq!(app.history.len(), 4);
+        press(&mut app, 'y');
+        assert_eq!(app.history.len(), 2);
     }
 
-    fn connect(&mut self, extra: Option<String>) -> Result<Option<AppAction>> {
-        let Some(host) = self.current_host().cloned() else {
-            self.status = Some(StatusLine {
-                text: "No host selected.".into(),
-                kind: StatusKind::Warn,
-            });
-            return Ok(None);
-        };
+    #[test]
+    fn json_copy_exports_and_imports_with_validation() {
+        let (dir, mut app) = app_on_disk();
+        press(&mut app, 'D');
+        let path = dir.path().join("config.json");
+        assert_eq!(
+            app.status.current().unwrap().text,
+            format!("Exported 3 host(s) as JSON to {}.", path.display())
+        );
 
-        let preview = ssh::command_preview(
-            &host,
-            &self.config,
-            self.config.default_key.as_deref(),
-            extra.as_deref(),
+        let mut edited = ConfigStore::import_json(&fs::read_to_string(&path).unwrap()).unwrap();
+        edited.hosts[0].user = Some("admin".into());
+        edited.keys.insert("undo".into(), "ctrl+z".into());
+        fs::write(&path, ConfigStore::export_json(&edited).unwrap()).unwrap();
+        press(&mut app, 'I');
+        assert_eq!(app.config.hosts[0].user.as_deref(), Some("admin"));
+        assert_eq!(
+            app.status.current().unwrap().text,
+            format!(
+                "Imported 3 host(s) from {}; Ctrl+Z undoes it.",
+                path.display()
+            )
+        );
+        // The imported [keys] apply straight away, and undoing drops them.
+        press(&mut app, 'u');
+        assert_eq!(app.config.hosts[0].user.as_deref(), Some("admin"));
+        app.on_key(KeyEvent::new(KeyCode::Char('z'), KeyModifiers::CONTROL))
+            .unwrap();
+        assert_eq!(app.config.hosts[0].user.as_deref(), Some("deploy"));
+        assert_eq!(app.key(KeyAction::Undo), "u");
+        edited.keys.clear();
+
+        // A bastion loop is refused and the config stays as it was.
+        edited.hosts[0].bastion = Some("staging-db".into());
+        edited.hosts[2].bastion = Some("prod-web".into());
+        fs::write(&path, ConfigStore::export_json(&edited).unwrap()).unwrap();
+        let before = app.config.clone();
+        press(&mut app, 'I');
+        assert_eq!(app.config, before);
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Error);
+        assert!(
+            status
+                .text
+                .starts_with("JSON import failed, nothing changed:"),
+            "{}",
+            status.text
         );
+    }
 
-        if self.dry_run {
-            self.status = Some(StatusLine {
-                text: format!("Dry-run: {preview}"),
-                kind: StatusKind::Info,
-            });
-            return Ok(None);
+    #[test]
+    fn ssh_config_export_round_trips_through_the_add_form() {
+        let (dir, mut app) = app_on_disk();
+        app.config.hosts[0].group = Some("pay".into());
+        app.config.hosts[0].options = vec!["-o".into(), "ServerAliveInterval=30".into()];
+        app.config.hosts[1].bastion = Some("jump-eu".into());
+        press(&mut app, 'S');
+        let path = dir.path().join("exported_ssh_config");
+        assert_eq!(
+            app.status.current().unwrap().text,
+            format!("Exported 3 host(s) as ssh_config to {}.", path.display())
+        );
+        let text = fs::read_to_string(&path).unwrap();
+
+        let config = app.config.clone();
+        for (host, block) in config.hosts.iter().zip(text.split("\n\n").skip(1)) {
+            let mut form = FormState::new(FormKind::Add, None, &config);
+            form.set_field_value(FIELD_SSH_COMMAND, block.to_string());
+            form.sync_command_field();
+            let imported = form.build_host().unwrap();
+            assert_eq!(imported.id(), host.id());
+            let argv = |host: &Host| {
+                ssh::build_argv(host, &config, config.default_key.as_deref(), None).unwrap()
+            };
+            assert_eq!(argv(&imported), argv(host), "{block}");
         }
 
-        let cmd = ssh::build_command(
-            &host,
-            &self.config,
-            self.config.default_key.as_deref(),
-            extra.as_deref(),
-        )?;
-        self.status = Some(StatusLine {
-            text: format!("Connecting with: {preview}"),
-            kind: StatusKind::Info,
-        });
-        Ok(Some(AppAction::RunSsh(cmd)))
+        app.config.hosts[2].command_template = Some("tsh ssh {user}@{address}".into());
+        press(&mut app, 'S');
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Warn);
+        assert!(
+            status.text.starts_with("Exported 2 host(s)"),
+            "{}",
+            status.text
+        );
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("# jump-eu not exported: uses a command template"));
     }
 
-    fn current_connection_string(&self) -> Option<String> {
-        self.current_host().map(|host| {
-            ssh::command_preview(host, &self.config, self.config.default_key.as_deref(), None)
-        })
+    #[test]
+    fn connecting_records_the_time_outside_the_undo_history() {
+        let (_dir, app) = app_on_disk();
+        let mut app = with_fake_ssh(app);
+        app.set_clock(Some(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_760_000_000),
+        ));
+        select(&mut app, "staging-db");
+        let action = app.connect(None).unwrap();
+        assert!(matches!(action, Some(AppAction::RunSsh(_))));
+        assert_eq!(app.config.hosts[1].last_connected, Some(1_760_000_000));
+        let saved = app.store.load_read_only().unwrap();
+        assert_eq!(saved.hosts[1].last_connected, Some(1_760_000_000));
+        assert_eq!(saved.hosts[1].connect_count, 1);
+        assert!(app.history.is_empty());
+        app.connect(None).unwrap();
+        assert_eq!(app.config.hosts[1].connect_count, 2);
+
+        // Dry runs connect to nothing.
+        app.dry_run = true;
+        select(&mut app, "prod-web");
+        app.connect(None).unwrap();
+        assert_eq!(app.config.hosts[0].last_connected, None);
+        assert_eq!(app.config.hosts[0].connect_count, 0);
+
+        // Undoing an unrelated edit keeps the time, and editing ignores it.
+        press(&mut app, 'y');
+        press(&mut app, 'u');
+        assert_eq!(app.config.hosts[1].last_connected, Some(1_760_000_000));
+        assert_eq!(app.config.hosts[1].connect_count, 2);
+        let host = app.config.hosts[1].clone();
+        let form = FormState::new(FormKind::Edit, Some(&host), &app.config);
+        assert!(form.changes().is_empty());
     }
 
-    fn copy_current_connection_string(&mut self) {
-        let Some(command) = self.current_connection_string() else {
-            self.status = Some(StatusLine {
-                text: "No host selected.".into(),
-                kind: StatusKind::Warn,
-            });
-            return;
+    #[test]
+    fn g_connects_for_real_through_dry_run() {
+        let mut app = test_app();
+        app.dry_run = true;
+        app.config.confirm_remote_command = true;
+        app.config.hosts[1].remote_command = Some("uptime".into());
+        select(&mut app, "staging-db");
+        let action = app.on_key(KeyEvent::from(KeyCode::Char('G'))).unwrap();
+        assert!(matches!(action, Some(AppAction::RunSsh(_))));
+        assert!(app.dry_run);
+        let status = app.status.current().unwrap();
+        assert!(status
+            .text
+            .starts_with("Forcing real connection (dry-run bypassed) with: ssh "));
+
+        // Enter still only previews.
+        assert!(app.connect(None).unwrap().is_none());
+        assert!(app.status.current().unwrap().text.starts_with("Dry-run: "));
+
+        // Protected hosts keep their checklist.
+        app.config.hosts[0].protected = true;
+        select(&mut app, "prod-web");
+        let action = app.on_key(KeyEvent::from(KeyCode::Char('G'))).unwrap();
+        assert!(action.is_none());
+        assert!(app.confirm.is_none());
+        assert_eq!(app.status.current().unwrap().kind, StatusKind::Warn);
+    }
+
+    #[test]
+    fn f_copies_a_file_with_scp_and_previews_it_in_dry_run() {
+        let mut app = with_fake_ssh(test_app());
+        app.dry_run = true;
+        select(&mut app, "staging-db");
+        press(&mut app, 'f');
+        assert!(matches!(app.mode, Mode::Transfer));
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert_eq!(app.status.current().unwrap().kind, StatusKind::Warn);
+        type_keys(&mut app, "notes.txt");
+        app.on_key(KeyEvent::from(KeyCode::Tab)).unwrap();
+        type_keys(&mut app, "/tmp/");
+        let transfer = app.transfer.clone().unwrap();
+        assert_eq!(
+            (transfer.local.as_str(), transfer.remote.as_str()),
+            ("notes.txt", "/tmp/")
+        );
+
+        let action = app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(action.is_none());
+        assert!(matches!(app.mode, Mode::Normal));
+        let status = app.status.current().unwrap().text.clone();
+        assert!(
+            status.starts_with("Dry-run: scp -J ops@52.17.9.3 -P 2222 "),
+            "{status}"
+        );
+        assert!(
+            status.ends_with(" -- notes.txt db@35.12.2.4:/tmp/"),
+            "{status}"
+        );
+
+        app.dry_run = false;
+        press(&mut app, 'f');
+        type_keys(&mut app, "notes.txt");
+        let action = app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        let Some(AppAction::RunSsh(cmd)) = action else {
+            panic!("expected scp to run");
         };
+        assert_eq!(cmd.get_program(), "scp");
+        assert_eq!(cmd.host_id(), "staging-db");
+        assert_eq!(
+            app.take_session_note().as_deref(),
+            Some("Copied notes.txt to staging-db:~.")
+        );
+    }
 
-        match clipboard::copy_text(&command) {
-            Ok(()) => {
-                self.status = Some(StatusLine {
-                    text: "Copied connection string to clipboard.".into(),
-                    kind: StatusKind::Info,
-                });
-            }
-            Err(err) => {
-                self.status = Some(StatusLine {
-                    text: format!("Clipboard copy failed: {err}"),
-                    kind: StatusKind::Error,
-                });
-            }
-        }
+    #[test]
+    fn keys_from_the_config_replace_the_defaults() {
+        let mut config = Config::sample();
+        config.keys.insert("delete".into(), "ctrl+d".into());
+        config.keys.insert("search".into(), "ctrl+".into());
+        let mut app = with_fake_ssh(App::with_config(config, ConfigStore::in_memory()));
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Warn);
+        assert!(
+            status
+                .text
+                .starts_with("Ignored in [keys]: search: 'ctrl+'"),
+            "{}",
+            status.text
+        );
+
+        press(&mut app, 'd');
+        assert!(app.confirm.is_none());
+        app.on_key(KeyEvent::new(KeyCode::Char('d'), KeyModifiers::CONTROL))
+            .unwrap();
+        assert!(matches!(app.confirm, Some(ConfirmKind::Delete { .. })));
+        app.on_key(KeyEvent::from(KeyCode::Esc)).unwrap();
+
+        // The entry that didn't parse keeps its default key.
+        press(&mut app, '/');
+        assert!(matches!(app.mode, Mode::Search));
+        app.on_key(KeyEvent::from(KeyCode::Esc)).unwrap();
+
+        // Help and status hints name the keys bound now.
+        let key_for = |app: &App, text: &str| {
+            app.help_entries()
+                .into_iter()
+                .find(|(_, t)| t.starts_with(text))
+                .map(|(key, _)| key)
+        };
+        assert_eq!(key_for(&app, "delete host").as_deref(), Some("Ctrl+D"));
+        assert_eq!(key_for(&app, "search").as_deref(), Some("/"));
+        assert_eq!(
+            key_for(&app, "move selection").as_deref(),
+            Some("j/k or arrows")
+        );
+        app.config.keys.insert("health_sweep".into(), "f5".into());
+        app.rebuild_keymap();
+        assert_eq!(key_for(&app, "health sweep").as_deref(), Some("F5"));
+        press(&mut app, 'A');
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "No health sweep yet; F5 checks every host."
+        );
     }
 
-    fn reload_config(&mut self) -> Result<()> {
-        self.config = self
-            .store
-            .load_or_init()
-            .with_context(|| "failed to reload config")?;
-        self.rebuild_filter();
-        self.status = Some(StatusLine {
-            text: "Reloaded config.".into(),
-            kind: StatusKind::Info,
+    #[test]
+    fn selection_is_remembered_across_restarts() {
+        let (dir, mut app) = app_on_disk();
+        press(&mut app, 'j');
+        press(&mut app, 'j');
+        assert_eq!(app.current_host().unwrap().name, "jump-eu");
+        // Moving alone doesn't touch the state file.
+        assert!(!dir.path().join("state.toml").exists());
+        app.remember_selection().unwrap();
+        let path = dir.path().join("config.toml");
+        let app = App::new(ConfigStore::at(path.clone())).unwrap();
+        assert_eq!(app.current_host().unwrap().name, "jump-eu");
+
+        // Gone since: back to the top.
+        let mut config = app.config.clone();
+        config.hosts.pop();
+        app.store.save(&config).unwrap();
+        let app = App::new(ConfigStore::at(path)).unwrap();
+        assert_eq!(app.selected, 0);
+        assert_eq!(app.current_host().unwrap().name, "prod-web");
+    }
+
+    #[test]
+    fn s_sorts_the_list_by_last_connection() {
+        let mut app = test_app();
+        app.config.hosts.push(Host {
+            name: "build".into(),
+            address: "10.0.0.9".into(),
+            ..Default::default()
         });
-        Ok(())
+        app.config.hosts[1].last_connected = Some(100);
+        app.config.hosts[3].last_connected = Some(200);
+        app.rebuild_filter();
+        assert_eq!(names(&app), ["prod-web", "staging-db", "jump-eu", "build"]);
+
+        select(&mut app, "jump-eu");
+        press(&mut app, 's');
+        assert_eq!(app.host_order, HostOrder::Recent);
+        assert_eq!(names(&app), ["build", "staging-db", "prod-web", "jump-eu"]);
+        assert_eq!(app.current_host().unwrap().name, "jump-eu");
+        assert_eq!(app.state.load().unwrap().host_order, HostOrder::Recent);
+
+        // A filter ranks by match, whatever the order.
+        app.set_filter("db");
+        assert_eq!(names(&app)[0], "staging-db");
+
+        app.set_filter("");
+        press(&mut app, 's');
+        assert_eq!(app.host_order, HostOrder::Config);
+        assert_eq!(names(&app), ["prod-web", "staging-db", "jump-eu", "build"]);
     }
 
-    pub fn help_entries() -> &'static [(&'static str, &'static str)] {
-        &[
-            ("/", "search"),
-            ("Enter", "connect"),
-            ("c", "connect with remote command"),
-            ("x", "copy connection string"),
-            ("g", "quick connect (ssh string)"),
-            ("n", "new host"),
-            ("e", "edit host"),
-            ("d", "delete host"),
-            ("y", "duplicate host"),
-            ("u", "undo last change"),
-            ("r", "reload config"),
-            ("j/k or arrows", "move selection"),
-            ("C", "toggle dry-run"),
-            ("?", "show help"),
-            ("a", "about/credits"),
-            ("q", "quit"),
-            ("Ctrl+C", "quit immediately"),
-            ("Esc", "cancel modal/help"),
-        ]
+    #[test]
+    fn reload_can_clear_the_undo_history() {
+        let (_dir, mut app) = app_on_disk();
+        let mut config = app.config.clone();
+        config.clear_undo_on_reload = true;
+        app.store.save(&config).unwrap();
+        press(&mut app, 'r');
+        select(&mut app, "prod-web");
+        press(&mut app, 'y');
+        assert_eq!(app.history.len(), 1);
+        press(&mut app, 'r');
+        assert!(app.history.is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
+    #[test]
+    fn overlay_hosts_are_merged_but_never_saved() {
+        let (dir, mut app) = app_on_disk();
+        let overlay = dir.path().join("project.toml");
+        fs::write(
+            &overlay,
+            "[[hosts]]\nname = \"prod-web\"\nhost = \"10.1.1.1\"\n\n[[hosts]]\nname = \"ci\"\nhost = \"10.1.1.2\"\n",
+        )
+        .unwrap();
+        app.load_overlays(std::slice::from_ref(&overlay)).unwrap();
+        let status = app.status.current().unwrap().clone();
+        assert_eq!(status.kind, StatusKind::Warn);
+        assert!(status.text.contains("replace prod-web"), "{}", status.text);
+        assert_eq!(app.config.hosts[0].address, "10.1.1.1");
+        assert_eq!(app.config.hosts.len(), Config::sample().hosts.len() + 1);
+
+        // Saving after an unrelated change writes the main hosts only.
+        select(&mut app, "ci");
+        app.on_key(KeyEvent::from(KeyCode::Char('y'))).unwrap();
+        let saved = app.store.load_read_only().unwrap();
+        let names: Vec<&str> = saved.hosts.iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(names, ["prod-web", "staging-db", "jump-eu", "ci-copy"]);
+        assert_eq!(saved.hosts[0].address, "52.14.33.10");
+
+        app.on_key(KeyEvent::from(KeyCode::Char('r'))).unwrap();
+        assert_eq!(
+            app.config.hosts[0].overlay.as_deref(),
+            Some(overlay.to_str().unwrap())
+        );
+        assert!(app.config.hosts.iter().any(|h| h.name == "ci"));
+    }
 
-    fn test_app() -> App {
-        let dir = tempdir().unwrap();
-        let store = ConfigStore::at(dir.path().join("config.toml"));
-        let config = Config::sample();
-        let mut app = App {
-            mode: Mode::Normal,
-            status: None,
-            filter: String::new(),
-            filtered_indices: Vec::new(),
-            selected: 0,
-            dry_run: false,
-            form: None,
-            confirm: None,
-            quick_input: None,
-            quick_cursor: 0,
-            show_help: false,
-            show_about: false,
-            matcher: SkimMatcherV2::default(),
-            config_path: store.path().to_path_buf(),
-            config,
-            history: Vec::new(),
-            store,
+    #[test]
+    fn bundle_menu_exports_and_imports_after_confirmation() {
+        let _guard = crate::ENV_LOCK.lock().unwrap();
+        let home = tempdir().unwrap();
+        let old_home = std::env::var_os("HOME");
+        unsafe { std::env::set_var("HOME", home.path()) };
+
+        let store = ConfigStore::at(home.path().join("cfg").join("config.toml"));
+        store.save(&Config::sample()).unwrap();
+        let mut app = App::new(store).unwrap();
+        let press = |app: &mut App, c: char| {
+            app.on_key(KeyEvent::from(KeyCode::Char(c))).unwrap();
         };
-        app.rebuild_filter();
-        app
+        press(&mut app, 'B');
+        press(&mut app, 'e');
+        let exported = app.status.current().unwrap().text.clone();
+
+        app.config.hosts.clear();
+        app.save_config().unwrap();
+        press(&mut app, 'B');
+        press(&mut app, 'i');
+        let plan = app.bundle_import.as_ref().map(|(_, plan)| plan.clone());
+        press(&mut app, 'x');
+        let still_pending = app.bundle_import.is_some();
+        let untouched = app.store.load_read_only().unwrap().hosts.len();
+        press(&mut app, 'y');
+        unsafe {
+            match old_home {
+                Some(v) => std::env::set_var("HOME", v),
+                None => std::env::remove_var("HOME"),
+            }
+        }
+
+        assert!(exported.starts_with("Wrote "), "{exported}");
+        let plan = plan.expect("plan shown");
+        assert!(plan[1].contains("0 hosts become 3"), "{plan:?}");
+        assert!(still_pending);
+        assert_eq!(untouched, 0);
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.config.hosts.len(), 3);
+        assert!(app.status.current().unwrap().text.starts_with("Imported"));
     }
 
     #[test]
-    fn filters_hosts_with_search() {
+    fn background_results_reach_status_line() {
         let mut app = test_app();
-        app.filter = "prod".into();
-        app.rebuild_filter();
-        assert!(!app.filtered_indices.is_empty());
-        let first = app.filtered_indices[0];
-        assert_eq!(app.config.hosts[first].name, "prod-web");
+        app.background_tx
+            .send(BackgroundEvent::StaleSocketsRemoved(3))
+            .unwrap();
+        app.tick();
+        assert!(app.status.current().unwrap().text.contains("3 stale"));
     }
 
     #[test]
@@ -1871,6 +9291,37 @@ mod tests {
         assert!(spec.prefer_public_key_auth);
     }
 
+    #[test]
+    fn parses_ip_family_flags() {
+        let spec = parse_ssh_spec("ssh -4 deploy@10.0.0.1").unwrap();
+        assert_eq!(spec.ip_family, Some(IpFamily::V4));
+        assert_eq!(spec.address, "10.0.0.1");
+        assert_eq!(spec.user.as_deref(), Some("deploy"));
+        assert!(spec.options.is_empty());
+
+        let spec = parse_ssh_spec("ssh -4 -p 2222 db.example.com -6").unwrap();
+        assert_eq!(spec.ip_family, Some(IpFamily::V6));
+        assert_eq!(spec.port, Some(2222));
+        assert_eq!(spec.remote_command, None);
+    }
+
+    #[test]
+    fn parses_forwarding_flags_into_their_toggles() {
+        let spec = parse_ssh_spec("ssh -A deploy@10.0.0.1 -X").unwrap();
+        assert!(spec.forward_agent);
+        assert!(spec.forward_x11);
+        assert_eq!(spec.address, "10.0.0.1");
+        assert!(spec.options.is_empty());
+        assert_eq!(spec.remote_command, None);
+        assert_eq!(ssh_command_for(&spec), "ssh -A -X deploy@10.0.0.1");
+
+        // Trusted X11 isn't downgraded to the `-X` toggle.
+        let spec = parse_ssh_spec("ssh -Y deploy@10.0.0.1").unwrap();
+        assert!(!spec.forward_x11);
+        assert_eq!(spec.options, ["-Y"]);
+        assert_eq!(ssh_command_for(&spec), "ssh -Y deploy@10.0.0.1");
+    }
+
     #[test]
     fn parses_options_after_host() {
         // Test that -p (port option) after host is parsed correctly, not as remote command
@@ -1882,8 +9333,8 @@ mod tests {
         // Test that any option after host is parsed correctly, not as remote command
         let spec = parse_ssh_spec("host -L 8080:localhost:80").unwrap();
         assert_eq!(spec.address, "host");
-        assert!(spec.options.contains(&"-L".to_string()));
-        assert!(spec.options.contains(&"8080:localhost:80".to_string()));
+        assert_eq!(spec.local_forwards, ["8080:localhost:80"]);
+        assert!(spec.options.is_empty());
         assert_eq!(spec.remote_command, None);
 
         // Test that multiple options after host are parsed correctly
@@ -1912,14 +9363,115 @@ mod tests {
     }
 
     #[test]
-    fn rejects_self_bastion() {
-        let app = test_app();
-        let mut config = app.config.clone();
-        if let Some(host) = config.hosts.first_mut() {
-            host.bastion = Some(host.name.clone());
-        }
+    fn rejects_self_bastion() {
+        let app = test_app();
+        let mut config = app.config.clone();
+        if let Some(host) = config.hosts.first_mut() {
+            host.bastion = Some(host.name.clone());
+        }
+        let err = App::validate_bastions(&config).unwrap_err();
+        assert!(err.to_string().contains("cannot use itself as bastion"));
+    }
+
+    #[test]
+    fn bastion_chains_stop_at_max_bastion_depth() {
+        // hop-0 goes through hop-1, …, up to hop-5: five bastions deep.
+        let mut config = Config {
+            hosts: (0..=5)
+                .map(|i| Host {
+                    name: format!("hop-{i}"),
+                    address: format!("10.0.0.{i}"),
+                    bastion: (i < 5).then(|| format!("hop-{}", i + 1)),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(config.max_bastion_depth, 5);
+        App::validate_bastions(&config).unwrap();
+
+        config.hosts[5].bastion = Some("gw.example.com".into());
+        let err = App::validate_bastions(&config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Host 'hop-0' goes through more than 5 bastions (max_bastion_depth)."
+        );
+
+        config.max_bastion_depth = 6;
+        App::validate_bastions(&config).unwrap();
+    }
+
+    fn grouped(group: &str, name: &str) -> Host {
+        Host {
+            name: name.into(),
+            group: Some(group.into()),
+            address: format!("{name}.{group}.internal"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ids_must_be_unique_only_within_a_group() {
+        let mut config = Config::sample();
+        config.hosts.push(grouped("team-a", "db1"));
+        config.hosts.push(grouped("team-b", "db1"));
+        App::validate_ids(&config).unwrap();
+        config.hosts.push(grouped("team-a", "db1"));
+        let err = App::validate_ids(&config).unwrap_err();
+        assert!(err.to_string().contains("already exists in group 'team-a'"));
+    }
+
+    #[test]
+    fn bastion_validation_scopes_short_names_to_the_group() {
+        let mut config = Config::sample();
+        config.hosts.push(grouped("team-a", "db1"));
+        config.hosts.push(grouped("team-b", "db1"));
+        let mut web = grouped("team-a", "web");
+        web.bastion = Some("db1".into());
+        config.hosts.push(web);
+        App::validate_bastions(&config).unwrap();
+
+        let mut db = grouped("team-a", "db1");
+        db.bastion = Some("db1".into());
+        config.hosts[3] = db;
         let err = App::validate_bastions(&config).unwrap_err();
-        assert!(err.to_string().contains("cannot use itself as bastion"));
+        assert!(err.to_string().contains("'team-a/db1' cannot use itself"));
+
+        config.hosts[3].bastion = Some("team-b/db1".into());
+        config.hosts[4].bastion = Some("team-a/db1".into());
+        let err = App::validate_bastions(&config).unwrap_err();
+        assert!(err.to_string().contains("Circular bastion reference"));
+
+        config.hosts[3].bastion = None;
+        config.hosts[4].bastion = None;
+        config.hosts[0].bastion = Some("db1".into());
+        let err = App::validate_bastions(&config).unwrap_err();
+        assert!(err.to_string().contains("Bastion of 'prod-web'"));
+        assert!(err.to_string().contains("ambiguous"));
+    }
+
+    #[test]
+    fn unique_name_is_scoped_to_the_group() {
+        let mut app = test_app();
+        app.config.hosts.push(grouped("team-a", "db1"));
+        assert_eq!(app.unique_name("db1", Some("team-b")), "db1");
+        assert_eq!(app.unique_name("db1", None), "db1");
+        assert_eq!(app.unique_name("db1", Some("team-a")), "db1-2");
+        assert_eq!(app.unique_name("prod-web", None), "prod-web-2");
+
+        app.duplicate_host(grouped("team-a", "db1")).unwrap();
+        let copy = app.current_host().unwrap();
+        assert_eq!(copy.id(), "team-a/db1-copy");
+    }
+
+    #[test]
+    fn form_round_trips_group_and_rejects_slashes() {
+        let config = Config::sample();
+        let host = grouped("team-a", "db1");
+        let mut form = FormState::new(FormKind::Edit, Some(&host), &config);
+        assert_eq!(form.build_host().unwrap(), host);
+        form.set_field_value(FIELD_GROUP, "team/a".into());
+        assert!(form.build_host().is_err());
     }
 
     #[test]
@@ -1946,6 +9498,188 @@ mod tests {
         App::validate_bastions(&config).unwrap();
     }
 
+    #[test]
+    fn quick_connect_rejects_a_bastion_cycle_before_writing() {
+        let (dir, mut app) = app_on_disk();
+        app.dry_run = true;
+        let path = dir.path().join("config.toml");
+        let before = (
+            std::fs::read_to_string(&path).unwrap(),
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+        );
+        let hosts = app.config.hosts.clone();
+
+        // The new host would be named after its own -J target.
+        let action = app
+            .quick_connect_str("ssh -J deploy@10.9.9.9 deploy@10.9.9.9")
+            .unwrap();
+        assert!(action.is_none());
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Error);
+        assert!(
+            status.text.contains("cannot use itself as bastion"),
+            "{}",
+            status.text
+        );
+        assert_eq!(app.config.hosts, hosts);
+        assert!(app.history.is_empty());
+        let after = (
+            std::fs::read_to_string(&path).unwrap(),
+            std::fs::metadata(&path).unwrap().modified().unwrap(),
+        );
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn changes_are_validated_before_history_or_save() {
+        let (dir, mut app) = app_on_disk();
+        let path = dir.path().join("config.toml");
+        let before = std::fs::read_to_string(&path).unwrap();
+
+        // jump-eu behind staging-db, which already goes through jump-eu.
+        let index = app
+            .config
+            .hosts
+            .iter()
+            .position(|h| h.name == "jump-eu")
+            .unwrap();
+        let mut host = app.config.hosts[index].clone();
+        host.bastion = Some("staging-db".into());
+        let err = app.apply_change(Change::Edit { index, host }).unwrap_err();
+        assert!(err.to_string().contains("Circular bastion"), "{err}");
+        let mut host = app.config.hosts[index].clone();
+        host.freeze_window = Some("Someday".into());
+        assert!(app.apply_change(Change::Edit { index, host }).is_err());
+        assert!(app.history.is_empty());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), before);
+
+        // Hosts that were already there aren't held to rules added later.
+        app.config.hosts[0].freeze_window = Some("Someday".into());
+        app.apply_change(Change::Remove(2)).unwrap();
+        assert_eq!(app.history.len(), 1);
+        assert_ne!(std::fs::read_to_string(&path).unwrap(), before);
+        let removed = app.config.hosts.len();
+        press(&mut app, 'u');
+        assert_eq!(app.config.hosts.len(), removed + 1);
+        assert!(app.history.is_empty());
+    }
+
+    #[test]
+    fn stored_remote_command_is_confirmed_on_plain_enter() {
+        let mut app = test_app();
+        app.dry_run = true;
+        app.config.hosts[0].remote_command = Some("reboot".into());
+        select(&mut app, "prod-web");
+        let enter = || KeyEvent::from(KeyCode::Enter);
+
+        // Esc: nothing runs.
+        app.handle_normal(enter()).unwrap();
+        assert!(matches!(
+            app.confirm,
+            Some(ConfirmKind::RemoteCommand { ref command }) if command == "reboot"
+        ));
+        app.handle_confirm(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+        assert!(app.status.current().is_none());
+
+        // Enter: connects and runs it.
+        app.handle_normal(enter()).unwrap();
+        app.handle_confirm(enter()).unwrap();
+        let status = app.status.current().unwrap().text.clone();
+        assert!(
+            status.starts_with("Dry-run: ") && status.contains("reboot"),
+            "{status}"
+        );
+
+        // e: connects without it, this once.
+        app.handle_normal(enter()).unwrap();
+        app.handle_confirm(KeyEvent::from(KeyCode::Char('e')))
+            .unwrap();
+        let status = app.status.current().unwrap().text.clone();
+        assert!(
+            status.starts_with("Dry-run without its remote command: "),
+            "{status}"
+        );
+        assert!(!status.contains("reboot"), "{status}");
+        assert_eq!(
+            app.config.hosts[0].remote_command.as_deref(),
+            Some("reboot")
+        );
+
+        // Turned off, Enter runs it straight away as before.
+        app.config.confirm_remote_command = false;
+        app.handle_normal(enter()).unwrap();
+        assert!(app.confirm.is_none());
+        assert!(app.status.current().unwrap().text.contains("reboot"));
+    }
+
+    fn lan_rule() -> AutoTagRule {
+        AutoTagRule {
+            address: Some("10.1.".into()),
+            tags: vec!["lan".into(), "eu".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quick_connect_applies_auto_tags() {
+        let mut app = test_app();
+        app.dry_run = true;
+        app.config.auto_tags = vec![
+            lan_rule(),
+            AutoTagRule {
+                user: Some("deploy".into()),
+                tags: vec!["eu".into(), "deploy".into()],
+                ..Default::default()
+            },
+        ];
+        app.quick_connect_str("ssh deploy@10.1.2.3").unwrap();
+        let host = app.config.hosts.last().unwrap();
+        assert_eq!(host.tags, ["lan", "eu", "deploy"]);
+        // Existing hosts are left alone.
+        assert_eq!(app.config.hosts[0].tags, ["web", "blue"]);
+    }
+
+    #[test]
+    fn add_form_prefills_auto_tags_the_user_can_remove() {
+        let mut app = test_app();
+        app.config.auto_tags = vec![lan_rule()];
+        press(&mut app, 'n');
+        let rules = app.config.auto_tags.clone();
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_TAGS, "db".into());
+        form.set_field_value(FIELD_SSH_COMMAND, "ssh ops@10.1.0.9".into());
+        form.sync_command_field();
+        form.sync_auto_tags(&rules);
+        assert_eq!(form_value(&app, FIELD_TAGS), "db, lan, eu");
+        assert_eq!(app.form.as_ref().unwrap().auto_tags, ["lan", "eu"]);
+
+        // A removed tag stays removed...
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_TAGS, "db, eu".into());
+        form.sync_auto_tags(&rules);
+        assert_eq!(form_value(&app, FIELD_TAGS), "db, eu");
+
+        // ...and tags go again once the rule stops matching.
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_SSH_COMMAND, "ssh ops@192.0.2.9".into());
+        form.sync_command_field();
+        form.sync_auto_tags(&rules);
+        assert_eq!(form_value(&app, FIELD_TAGS), "db");
+
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_SSH_COMMAND, "ssh ops@10.1.0.10".into());
+        form.set_field_value(FIELD_NAME, "lan-box".into());
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        let host = app
+            .config
+            .hosts
+            .iter()
+            .find(|h| h.name == "lan-box")
+            .unwrap();
+        assert_eq!(host.tags, ["db", "lan", "eu"]);
+    }
+
     #[test]
     fn quick_connect_adds_or_reuses() {
         let mut app = test_app();
@@ -1995,6 +9729,29 @@ mod tests {
         assert_eq!(selector.scroll, 2);
     }
 
+    #[test]
+    fn form_editing_steps_over_wide_and_combining_characters() {
+        let mut app = test_app();
+        let mut form = FormState::new(FormKind::Add, None, &app.config);
+        form.index = form.field_index(FIELD_NAME).unwrap();
+        app.form = Some(form);
+        app.mode = Mode::Form;
+        for c in ['東', '🚀', 'e', '\u{301}', 'x'] {
+            app.handle_form(KeyEvent::from(KeyCode::Char(c))).unwrap();
+        }
+        for code in [KeyCode::Left, KeyCode::Left, KeyCode::Backspace] {
+            app.handle_form(KeyEvent::from(code)).unwrap();
+        }
+        let field = app.form.as_ref().unwrap().field(FIELD_NAME).unwrap();
+        assert_eq!(field.value, "東e\u{301}x");
+        assert_eq!(field.cursor, "東".len());
+
+        app.handle_form(KeyEvent::from(KeyCode::Right)).unwrap();
+        app.handle_form(KeyEvent::from(KeyCode::Backspace)).unwrap();
+        let field = app.form.as_ref().unwrap().field(FIELD_NAME).unwrap();
+        assert_eq!(field.value, "東x");
+    }
+
     #[test]
     fn escape_closes_key_selector_without_closing_form() {
         let mut app = test_app();
@@ -2020,4 +9777,858 @@ mod tests {
         assert!(command.contains("deploy@52.14.33.10"));
         assert!(command.contains("prod_id_ed25519"));
     }
+
+    #[test]
+    fn watch_starts_from_connect_confirm_and_stops_for_sessions() {
+        let mut app = test_app();
+        app.confirm = Some(ConfirmKind::Connect {
+            extra_cmd: String::new(),
+            request_tty: None,
+            freeze_armed: false,
+        });
+        app.mode = Mode::Confirm;
+        app.handle_confirm(KeyEvent::from(KeyCode::Tab)).unwrap();
+        assert!(app.watch.is_none());
+        assert!(matches!(
+            app.status.current().unwrap().kind,
+            StatusKind::Warn
+        ));
+
+        app.dry_run = true;
+        app.start_watch("uptime").unwrap();
+        assert!(app.watch.is_none());
+        let text = &app.status.current().unwrap().text;
+        assert!(text.contains("every 5s") && text.contains("BatchMode=yes"));
+
+        app.dry_run = false;
+        app.watch = Some(Watcher::new(
+            "t".into(),
+            Duration::from_secs(60),
+            std::sync::Arc::new(|| Err("unused".into())),
+        ));
+        app.mode = Mode::Watch;
+        let action = app.handle_watch(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(action, Some(AppAction::RunSsh(_))));
+        assert!(app.watch.is_none());
+        assert!(matches!(app.mode, Mode::Normal));
+    }
+
+    #[test]
+    fn errors_stick_until_escape_and_ring_only_when_enabled() {
+        let mut app = test_app();
+        app.status.set(StatusLine {
+            text: "failed to save".into(),
+            kind: StatusKind::Error,
+        });
+        assert!(!app.take_bell());
+        app.handle_normal(KeyEvent::from(KeyCode::Char('C')))
+            .unwrap();
+        assert_eq!(app.status.current().unwrap().text, "failed to save");
+
+        app.handle_normal(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(app.status.current().unwrap().text.contains("Dry-run"));
+
+        app.config.bell_on_error = true;
+        app.status.set(StatusLine {
+            text: "again".into(),
+            kind: StatusKind::Error,
+        });
+        assert!(app.take_bell());
+        assert!(!app.take_bell());
+    }
+
+    fn names(app: &App) -> Vec<&str> {
+        app.filtered_indices
+            .iter()
+            .map(|i| app.config.hosts[*i].name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn tag_filter_keeps_hosts_with_every_picked_tag() {
+        let mut app = test_app();
+        app.config.hosts[1].tags.push("blue".into());
+        app.config.hosts[2].role = Some(HostRole::Bastion);
+        app.config.hosts[2].tags.push("blue".into());
+        app.rebuild_filter();
+
+        press(&mut app, 't');
+        assert!(matches!(app.mode, Mode::TagFilter));
+        type_keys(&mut app, "BL");
+        assert_eq!(app.tag_choices(), ["blue"]);
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.tag_filter, ["blue"]);
+        // Jump hosts with the tag are listed too.
+        assert_eq!(names(&app), ["prod-web", "staging-db", "jump-eu"]);
+
+        press(&mut app, 't');
+        type_keys(&mut app, "e");
+        assert_eq!(app.tag_choices(), ["blue", "green", "web"]);
+        app.on_key(KeyEvent::from(KeyCode::Down)).unwrap();
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert_eq!(app.tag_filter, ["blue", "green"]);
+        assert_eq!(names(&app), ["staging-db"]);
+
+        // The search text narrows on top, and clearing it keeps the tags.
+        app.set_filter("prod");
+        assert!(names(&app).is_empty());
+        app.set_filter("");
+        assert_eq!(names(&app), ["staging-db"]);
+
+        app.on_key(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(app.tag_filter.is_empty());
+        assert_eq!(names(&app), ["prod-web", "staging-db"]);
+
+        for host in &mut app.config.hosts {
+            host.tags.clear();
+        }
+        press(&mut app, 't');
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.status.current().unwrap().kind, StatusKind::Warn);
+    }
+
+    #[test]
+    fn jump_hosts_sit_in_a_collapsed_section_but_stay_searchable() {
+        let mut app = test_app();
+        app.config.hosts[0].role = Some(HostRole::Bastion);
+        app.rebuild_filter();
+        assert_eq!(names(&app), ["staging-db", "jump-eu"]);
+        assert_eq!(app.jump_section(), Some((2, 1)));
+
+        select(&mut app, "jump-eu");
+        press(&mut app, 'J');
+        assert_eq!(names(&app), ["staging-db", "jump-eu", "prod-web"]);
+        assert_eq!(app.jump_section(), Some((2, 1)));
+        assert_eq!(app.current_host().unwrap().name, "jump-eu");
+
+        press(&mut app, 'J');
+        app.set_filter("prod");
+        assert_eq!(names(&app)[0], "prod-web");
+        assert_eq!(app.jump_section(), None);
+        assert_eq!(app.current_host().unwrap().name, "prod-web");
+    }
+
+    #[test]
+    fn z_groups_hosts_under_their_first_tag() {
+        let mut app = test_app();
+        app.config.hosts[2].role = Some(HostRole::Bastion);
+        app.config.hosts.push(Host {
+            name: "scratch".into(),
+            address: "10.0.0.9".into(),
+            ..Default::default()
+        });
+        app.config.hosts[1].tags.push("web".into());
+        app.rebuild_filter();
+        select(&mut app, "staging-db");
+        press(&mut app, 'z');
+        assert!(app.grouped);
+        // Jump hosts join their group; staging-db shows once, under "db".
+        assert_eq!(
+            names(&app),
+            ["staging-db", "jump-eu", "prod-web", "scratch"]
+        );
+        assert_eq!(app.jump_section(), None);
+        let headings = app.list_headings();
+        assert_eq!(
+            headings,
+            [
+                (0, ListRow::Tag(Some("db".into()), 1)),
+                (1, ListRow::Tag(Some("jump".into()), 1)),
+                (2, ListRow::Tag(Some("web".into()), 1)),
+                (3, ListRow::Tag(None, 1)),
+            ]
+        );
+        assert_eq!(
+            app.list_row(&headings, 0),
+            ListRow::Tag(Some("db".into()), 1)
+        );
+        assert_eq!(app.list_row(&headings, 1), ListRow::Host(1));
+        assert_eq!(app.list_row(&headings, 7), ListRow::Host(3));
+
+        // The selection steps over the headings, from host to host.
+        assert_eq!(app.selected_row(), 1);
+        press(&mut app, 'j');
+        assert_eq!(app.current_host().unwrap().name, "jump-eu");
+        assert_eq!(app.selected_row(), 3);
+        press(&mut app, 'k');
+        press(&mut app, 'k');
+        assert_eq!(app.current_host().unwrap().name, "scratch");
+
+        press(&mut app, 'z');
+        assert_eq!(names(&app), ["prod-web", "staging-db", "scratch"]);
+        assert_eq!(app.current_host().unwrap().name, "scratch");
+    }
+
+    #[test]
+    fn bastion_dropdown_offers_jump_hosts_first() {
+        let mut config = Config::sample();
+        config.hosts[2].role = Some(HostRole::Bastion);
+        let dropdown = BastionDropdownState::new(&config, Some("prod-web"));
+        assert_eq!(dropdown.filtered_indices, [2, 1]);
+
+        let mut dropdown = BastionDropdownState::new(&config, None);
+        dropdown.search_filter = "e".into();
+        dropdown.rebuild_filter(&config);
+        assert_eq!(dropdown.filtered_indices[0], 2);
+    }
+
+    #[test]
+    fn suggests_jump_hosts_once_and_saves_either_answer() {
+        let mut app = test_app();
+        app.suggest_jump_hosts();
+        let Some(ConfirmKind::SuggestJumpHosts { ids }) = app.confirm.clone() else {
+            panic!("expected a suggestion");
+        };
+        assert_eq!(ids, ["jump-eu"]);
+        press(&mut app, 'y');
+        assert!(app.config.hosts[2].is_jump_host());
+        assert_eq!(names(&app), ["prod-web", "staging-db"]);
+        app.suggest_jump_hosts();
+        assert!(app.confirm.is_none());
+
+        let mut app = test_app();
+        app.suggest_jump_hosts();
+        press(&mut app, 'n');
+        assert_eq!(app.config.hosts[2].role, Some(HostRole::Normal));
+        app.suggest_jump_hosts();
+        assert!(app.confirm.is_none());
+
+        // Esc leaves the role unset, so it comes up again next time.
+        let mut app = test_app();
+        app.suggest_jump_hosts();
+        app.on_key(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert_eq!(app.config.hosts[2].role, None);
+        assert!(matches!(app.mode, Mode::Normal));
+    }
+
+    #[test]
+    fn role_field_toggles_and_saves() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.index = form.field_index(FIELD_ROLE).unwrap();
+        press(&mut app, ' ');
+        assert_eq!(form_value(&app, FIELD_ROLE), "bastion");
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(app.config.hosts[0].is_jump_host());
+    }
+
+    #[test]
+    fn ip_family_field_cycles_and_saves() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.index = form.field_index(FIELD_IP_FAMILY).unwrap();
+        assert_eq!(form_value(&app, FIELD_IP_FAMILY), "any");
+        press(&mut app, ' ');
+        assert_eq!(form_value(&app, FIELD_IP_FAMILY), "IPv4 (-4)");
+        press(&mut app, ' ');
+        assert_eq!(form_value(&app, FIELD_IP_FAMILY), "IPv6 (-6)");
+        press(&mut app, 'a');
+        assert_eq!(form_value(&app, FIELD_IP_FAMILY), "any");
+        press(&mut app, '4');
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert_eq!(app.config.hosts[0].ip_family, Some(IpFamily::V4));
+        assert!(app.selected_preview().unwrap().contains(" -4 "));
+    }
+
+    fn enter(app: &mut App) {
+        app.handle_form(KeyEvent::from(KeyCode::Enter)).unwrap();
+    }
+
+    #[test]
+    fn o_checks_the_ssh_port_off_the_ui_thread() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        app.config.hosts[0].port = Some(2200);
+        press(&mut app, 'o');
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "Checking prod-web on port 2200…"
+        );
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while app.ping.is_some() {
+            assert!(Instant::now() < deadline, "probe never finished");
+            std::thread::sleep(Duration::from_millis(5));
+            app.tick();
+        }
+        let status = app.status.current().unwrap();
+        assert_eq!(status.text, "prod-web: unreachable (connection refused).");
+        assert_eq!(status.kind, StatusKind::Warn);
+        assert!(app
+            .probes
+            .status("52.14.33.10", 2200, Instant::now())
+            .is_some());
+
+        // A host behind a bastion is only reachable through it.
+        select(&mut app, "staging-db");
+        press(&mut app, 'o');
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "Not checked: staging-db is reached through bastion jump-eu."
+        );
+        assert!(app.ping.is_none());
+    }
+
+    #[test]
+    fn h_sweeps_every_host_and_a_filters_by_the_answers() {
+        let mut app = test_app();
+        app.probes = PortProber::with_probe(|address, _| {
+            if address == "52.14.33.10" {
+                PortProbe::Open(Duration::from_millis(3))
+            } else {
+                PortProbe::Closed("connection refused".into())
+            }
+        });
+        press(&mut app, 'A');
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "No health sweep yet; H checks every host."
+        );
+        assert!(app.health_filter.is_none());
+
+        press(&mut app, 'H');
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "Health sweep: 0/2 checked…"
+        );
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while app.sweep.is_some() {
+            assert!(Instant::now() < deadline, "sweep never finished");
+            std::thread::sleep(Duration::from_millis(5));
+            app.tick();
+        }
+        let status = app.status.current().unwrap();
+        assert_eq!(
+            status.text,
+            "Health sweep: 1 reachable, 1 unreachable; 1 behind a bastion or proxy not checked. A filters by it."
+        );
+        assert_eq!(status.kind, StatusKind::Warn);
+        assert_eq!(app.health.get("prod-web"), Some(&true));
+        assert_eq!(app.health.get("jump-eu"), Some(&false));
+
+        press(&mut app, 'A');
+        assert_eq!(names(&app), ["prod-web"]);
+        press(&mut app, 'A');
+        assert_eq!(names(&app), ["jump-eu"]);
+        press(&mut app, 'A');
+        assert_eq!(names(&app).len(), 3);
+    }
+
+    #[test]
+    fn multiplex_field_toggles_and_saves() {
+        let mut app = test_app();
+        app.config.hosts[2].role = Some(HostRole::Normal);
+        select(&mut app, "prod-web");
+        press(&mut app, 'e');
+        assert_eq!(form_value(&app, FIELD_MULTIPLEX), "no");
+        let form = app.form.as_mut().unwrap();
+        form.index = form.field_index(FIELD_MULTIPLEX).unwrap();
+        press(&mut app, ' ');
+        assert_eq!(form_value(&app, FIELD_MULTIPLEX), "yes");
+        press(&mut app, 'x');
+        assert_eq!(form_value(&app, FIELD_MULTIPLEX), "yes");
+        enter(&mut app);
+        assert!(matches!(app.mode, Mode::Normal));
+        assert!(app.config.hosts[0].multiplex);
+    }
+
+    #[test]
+    fn shift_enter_starts_a_new_line_in_the_description() {
+        let mut app = test_app();
+        app.config.hosts[2].role = Some(HostRole::Normal);
+        select(&mut app, "prod-web");
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_DESCRIPTION, String::new());
+        form.index = form.field_index(FIELD_DESCRIPTION).unwrap();
+        type_keys(&mut app, "deploy:");
+        let shift_enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::SHIFT);
+        assert!(app.on_key(shift_enter).unwrap().is_none());
+        type_keys(&mut app, "make ship");
+        assert_eq!(form_value(&app, FIELD_DESCRIPTION), "deploy:\nmake ship");
+        enter(&mut app);
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(
+            app.config.hosts[0].description.as_deref(),
+            Some("deploy:\nmake ship")
+        );
+
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.index = form.field_index(FIELD_USER).unwrap();
+        assert!(app.on_key(shift_enter).unwrap().is_none());
+        assert!(matches!(app.mode, Mode::Normal));
+    }
+
+    #[test]
+    fn page_down_scrolls_the_details_of_the_selected_host() {
+        let mut app = test_app();
+        app.set_details_max_scroll(20);
+        select(&mut app, "prod-web");
+        app.handle_normal(KeyEvent::from(KeyCode::PageDown))
+            .unwrap();
+        app.handle_normal(KeyEvent::from(KeyCode::PageDown))
+            .unwrap();
+        app.handle_normal(KeyEvent::from(KeyCode::PageUp)).unwrap();
+        assert_eq!(app.details_offset(), 5);
+        select(&mut app, "staging-db");
+        assert_eq!(app.details_offset(), 0);
+    }
+
+    #[test]
+    fn l_links_a_host_through_the_picked_bastion() {
+        let mut app = test_app();
+        app.config.hosts[2].role = Some(HostRole::Normal);
+        select(&mut app, "jump-eu");
+        press(&mut app, 'l');
+        assert_eq!(app.pending_bastion.as_deref(), Some("jump-eu"));
+        select(&mut app, "prod-web");
+        press(&mut app, 'l');
+        assert!(app.pending_bastion.is_none());
+        assert_eq!(app.config.hosts[0].bastion.as_deref(), Some("jump-eu"));
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "prod-web now connects through jump-eu."
+        );
+
+        // A cycle is refused and leaves the config alone.
+        press(&mut app, 'l');
+        select(&mut app, "jump-eu");
+        press(&mut app, 'l');
+        assert_eq!(app.config.hosts[2].bastion, None);
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Error);
+        assert!(
+            status.text.starts_with("Bastion not set:"),
+            "{}",
+            status.text
+        );
+
+        // Esc drops a picked bastion.
+        press(&mut app, 'l');
+        app.on_key(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(app.pending_bastion.is_none());
+    }
+
+    #[test]
+    fn star_pins_a_host_above_the_others_until_searching() {
+        let (dir, mut app) = app_on_disk();
+        select(&mut app, "jump-eu");
+        press(&mut app, '*');
+        assert_eq!(names(&app), vec!["jump-eu", "prod-web", "staging-db"]);
+        assert_eq!(app.current_host().unwrap().name, "jump-eu");
+        let saved = ConfigStore::at(dir.path().join("config.toml"))
+            .load_read_only()
+            .unwrap();
+        assert!(saved.hosts[2].pinned);
+
+        // A search ranks by match, pinned or not.
+        app.set_filter("web");
+        assert_eq!(names(&app), vec!["prod-web"]);
+        app.set_filter("");
+
+        press(&mut app, 'u');
+        assert!(!app.config.hosts[2].pinned);
+        assert_eq!(names(&app), vec!["prod-web", "staging-db", "jump-eu"]);
+    }
+
+    #[test]
+    fn alt_j_and_k_move_the_host_in_the_saved_config() {
+        let (dir, mut app) = app_on_disk();
+        let alt = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::ALT);
+        select(&mut app, "prod-web");
+        app.on_key(alt('j')).unwrap();
+        assert_eq!(names(&app), vec!["staging-db", "prod-web", "jump-eu"]);
+        assert_eq!(app.current_host().unwrap().name, "prod-web");
+        let saved = ConfigStore::at(dir.path().join("config.toml"))
+            .load_read_only()
+            .unwrap();
+        assert_eq!(saved.hosts[1].name, "prod-web");
+
+        app.on_key(alt('j')).unwrap();
+        app.on_key(alt('j')).unwrap();
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "prod-web is already at the bottom."
+        );
+        assert_eq!(app.config.hosts[2].name, "prod-web");
+
+        app.on_key(alt('k')).unwrap();
+        assert_eq!(app.config.hosts[1].name, "prod-web");
+        press(&mut app, 'u');
+        press(&mut app, 'u');
+        press(&mut app, 'u');
+        assert_eq!(app.config.hosts[0].name, "prod-web");
+
+        app.set_filter("prod");
+        app.on_key(alt('j')).unwrap();
+        assert_eq!(app.status.current().unwrap().kind, StatusKind::Warn);
+        assert_eq!(app.config.hosts[0].name, "prod-web");
+
+        // With staging-db hidden, jump-eu is the neighbour on screen but
+        // not in the config.
+        app.set_filter("");
+        app.workset = ["prod-web".to_string(), "jump-eu".to_string()].into();
+        app.workset_only = true;
+        app.rebuild_filter();
+        select(&mut app, "prod-web");
+        app.on_key(alt('j')).unwrap();
+        assert_eq!(
+            app.status.current().unwrap().text,
+            "Only some hosts are listed; show them all (t, v or A) to move them."
+        );
+        assert_eq!(app.config.hosts[0].name, "prod-web");
+        app.workset_only = false;
+        app.tag_filter = vec![app.config.hosts[0].tags[0].clone()];
+        app.rebuild_filter();
+        app.on_key(alt('j')).unwrap();
+        assert_eq!(app.status.current().unwrap().kind, StatusKind::Warn);
+        assert_eq!(app.config.hosts[0].name, "prod-web");
+    }
+
+    #[test]
+    fn saving_a_taken_name_keeps_the_form_open_on_the_name() {
+        let mut app = test_app();
+        press(&mut app, 'n');
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_NAME, "prod-web".into());
+        form.set_field_value(FIELD_HOST, "10.0.0.7".into());
+        enter(&mut app);
+        assert!(matches!(app.mode, Mode::Form));
+        assert_eq!(app.config.hosts.len(), 3);
+        let form = app.form.as_ref().unwrap();
+        assert_eq!(form.fields[form.index].label, FIELD_NAME);
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Error);
+        assert_eq!(status.text, "A host named 'prod-web' already exists.");
+
+        // Editing a host may keep its own name, not take another's.
+        let mut app = test_app();
+        app.config.hosts[2].role = Some(HostRole::Normal);
+        select(&mut app, "staging-db");
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_USER, "admin".into());
+        enter(&mut app);
+        assert!(matches!(app.mode, Mode::Normal));
+        assert_eq!(app.config.hosts[1].user, Some("admin".into()));
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_NAME, "jump-eu".into());
+        enter(&mut app);
+        assert!(matches!(app.mode, Mode::Form));
+        assert!(app.config.find_host("staging-db").is_some());
+    }
+
+    #[test]
+    fn failed_save_moves_to_the_field_at_fault() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_PORT, "22x".into());
+        form.index = form.field_index(FIELD_NAME).unwrap();
+        enter(&mut app);
+        assert!(matches!(app.mode, Mode::Form));
+        let form = app.form.as_ref().unwrap();
+        assert_eq!(form.fields[form.index].label, FIELD_PORT);
+        assert_eq!(form.fields[form.index].cursor, 3);
+        assert_eq!(
+            form.error,
+            Some(FormError {
+                field: Some(FIELD_PORT),
+                message: "port must be numeric".into()
+            })
+        );
+
+        // Moving around keeps the mark; editing the field clears it.
+        app.handle_form(KeyEvent::from(KeyCode::Tab)).unwrap();
+        assert!(app.form.as_ref().unwrap().error.is_some());
+        app.handle_form(KeyEvent::from(KeyCode::BackTab)).unwrap();
+        app.handle_form(KeyEvent::from(KeyCode::Backspace)).unwrap();
+        assert_eq!(app.form.as_ref().unwrap().error, None);
+
+        for (field, value, message) in [
+            (FIELD_NAME, "web/1", "name and group cannot contain '/'"),
+            (FIELD_GROUP, "a/b", "name and group cannot contain '/'"),
+            (FIELD_HOST, "", "name and host cannot be empty"),
+            (FIELD_TIMEZONE, "Mars/Base", "unknown timezone"),
+        ] {
+            let mut app = test_app();
+            select(&mut app, "prod-web");
+            press(&mut app, 'e');
+            app.form
+                .as_mut()
+                .unwrap()
+                .set_field_value(field, value.into());
+            enter(&mut app);
+            let form = app.form.as_ref().unwrap();
+            assert_eq!(form.fields[form.index].label, field);
+            let error = form.error.as_ref().unwrap();
+            assert_eq!(error.field, Some(field));
+            assert!(error.message.starts_with(message), "{}", error.message);
+        }
+
+        // An empty Add form points at the SSH command.
+        let mut app = test_app();
+        press(&mut app, 'n');
+        app.form.as_mut().unwrap().index = 3;
+        enter(&mut app);
+        let form = app.form.as_ref().unwrap();
+        assert_eq!(form.index, 0);
+        assert_eq!(form.error.as_ref().unwrap().field, Some(FIELD_SSH_COMMAND));
+    }
+
+    #[test]
+    fn failed_save_without_a_field_stays_put_and_is_shown_in_the_form() {
+        let mut app = test_app();
+        select(&mut app, "jump-eu");
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_BASTION, "staging-db".into());
+        form.index = form.field_index(FIELD_TAGS).unwrap();
+        enter(&mut app);
+        let form = app.form.as_ref().unwrap();
+        assert_eq!(form.fields[form.index].label, FIELD_TAGS);
+        let error = form.error.as_ref().unwrap();
+        assert_eq!(error.field, None);
+        assert!(
+            error.message.contains("Circular bastion"),
+            "{}",
+            error.message
+        );
+        assert_eq!(app.status.current().unwrap().kind, StatusKind::Error);
+
+        // Any edit clears it.
+        press(&mut app, 'x');
+        assert_eq!(app.form.as_ref().unwrap().error, None);
+    }
+
+    #[test]
+    fn warns_when_the_preferred_family_has_no_address() {
+        let mut app = test_app();
+        let mut host = app.config.hosts[0].clone();
+        assert_eq!(app.ip_family_warning(&host), None);
+        host.ip_family = Some(IpFamily::V6);
+        assert_eq!(
+            app.ip_family_warning(&host).as_deref(),
+            Some("52.14.33.10 is not an IPv6 address; ssh -6 will fail")
+        );
+        host.ip_family = Some(IpFamily::V4);
+        assert_eq!(app.ip_family_warning(&host), None);
+
+        host.address = "db.example.com".into();
+        host.ip_family = Some(IpFamily::V6);
+        assert_eq!(app.ip_family_warning(&host), None, "not resolved yet");
+        app.resolver
+            .record("db.example.com", &["192.0.2.7".parse().unwrap()]);
+        assert_eq!(
+            app.ip_family_warning(&host).as_deref(),
+            Some("db.example.com has no AAAA (IPv6) record; ssh -6 will fail")
+        );
+        host.ip_family = Some(IpFamily::V4);
+        assert_eq!(app.ip_family_warning(&host), None);
+
+        // The bastion resolves the address, not this machine.
+        host.ip_family = Some(IpFamily::V6);
+        host.bastion = Some("jump-eu".into());
+        assert_eq!(app.ip_family_warning(&host), None);
+    }
+
+    #[test]
+    fn vars_field_round_trips_and_rejects_bad_pairs() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        press(&mut app, 'e');
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_VARS, "svc=api, data_dir=/srv/data".into());
+        form.set_field_value(FIELD_REMOTE_COMMAND, "ls {{data_dir}}/{{svc}}".into());
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+        let host = &app.config.hosts[0];
+        assert_eq!(host.vars["svc"], "api");
+        assert!(app
+            .selected_preview()
+            .unwrap()
+            .contains("'ls /srv/data/api'"));
+
+        // Saving asks about the unmarked jump host first.
+        app.on_key(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(app
+            .connect(Some("journalctl -u {{unit}}".into()))
+            .unwrap()
+            .is_none());
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Error);
+        assert!(status.text.contains("variable 'unit' is not set"));
+        app.status.dismiss();
+
+        press(&mut app, 'e');
+        assert_eq!(form_value(&app, FIELD_VARS), "data_dir=/srv/data,svc=api");
+        let form = app.form.as_mut().unwrap();
+        form.set_field_value(FIELD_VARS, "svc".into());
+        assert!(form
+            .build_host()
+            .unwrap_err()
+            .to_string()
+            .contains("key=value"));
+        form.set_field_value(FIELD_VARS, "a b=1".into());
+        assert!(form.build_host().is_err());
+    }
+
+    #[test]
+    fn failed_sessions_are_kept_as_the_hosts_last_error() {
+        let mut app = test_app();
+        let failed = |diagnosis| -> Result<()> {
+            Err(ssh::SessionFailed {
+                status: std::process::ExitStatus::default(),
+                diagnosis,
+            }
+            .into())
+        };
+        let timed_out = Diagnosis {
+            at: failure::At::Hop {
+                label: "jump-eu".into(),
+                bastion: true,
+            },
+            cause: failure::Cause::TimedOut,
+        };
+        assert_eq!(
+            app.session_ended("staging-db", &failed(Some(timed_out.clone())))
+                .as_deref(),
+            Some("ssh to staging-db failed at bastion jump-eu (connection timed out)")
+        );
+        assert_eq!(
+            app.last_errors["staging-db"].diagnosis.as_ref(),
+            Some(&timed_out)
+        );
+
+        let refused = Diagnosis {
+            at: failure::At::Forward {
+                from: Some("jump-eu".into()),
+                to: Some("staging-db".into()),
+            },
+            cause: failure::Cause::ConnectionRefused,
+        };
+        assert_eq!(
+            app.session_ended("staging-db", &failed(Some(refused))).as_deref(),
+            Some("ssh to staging-db failed: bastion jump-eu could not reach staging-db (connection refused)")
+        );
+        assert!(app
+            .session_ended("prod-web", &Err(anyhow!("boom")))
+            .unwrap()
+            .starts_with("ssh failed: boom"));
+        assert_eq!(app.last_errors["prod-web"].diagnosis, None);
+
+        assert_eq!(app.session_ended("staging-db", &Ok(())), None);
+        assert!(!app.last_errors.contains_key("staging-db"));
+        assert!(app.last_errors.contains_key("prod-web"));
+    }
+
+    #[test]
+    fn missing_ssh_refuses_to_connect_but_dry_run_still_works() {
+        let mut app = test_app();
+        app.find_program = |_| {
+            Err(program::Unavailable::NotFound {
+                searched: vec![PathBuf::from("/usr/local/bin"), PathBuf::from("/usr/bin")],
+            })
+        };
+        select(&mut app, "prod-web");
+        assert!(app.connect(None).unwrap().is_none());
+        let status = app.status.current().unwrap();
+        assert_eq!(status.kind, StatusKind::Error);
+        assert!(status
+            .text
+            .starts_with("'ssh' not found (searched PATH: /usr/local/bin, /usr/bin)."));
+        assert!(status.text.contains("ssh_binary"));
+        let problems = app.problems();
+        assert_eq!(problems[0].kind, ProblemKind::SshUnavailable);
+        assert_eq!(problems[0].index, None);
+
+        app.status.dismiss();
+        app.dry_run = true;
+        assert!(app.connect(None).unwrap().is_none());
+        assert!(app.status.current().unwrap().text.starts_with("Dry-run"));
+
+        // Found again (PATH fixed, or ssh_binary set): connecting works and
+        // the problem goes away.
+        app.dry_run = false;
+        app.config.ssh_binary = Some("/opt/openssh/bin/ssh".into());
+        app.find_program = |program| {
+            if program == "/opt/openssh/bin/ssh" {
+                Ok(PathBuf::from(program))
+            } else {
+                Err(program::Unavailable::NotFound { searched: vec![] })
+            }
+        };
+        let action = app.connect(None).unwrap();
+        let Some(AppAction::RunSsh(cmd)) = action else {
+            panic!("expected an ssh command");
+        };
+        assert_eq!(cmd.get_program(), "/opt/openssh/bin/ssh");
+        assert!(app.problems().is_empty());
+    }
+
+    #[test]
+    fn workset_toggles_filters_and_clears() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        press(&mut app, 'w');
+        select(&mut app, "jump-eu");
+        press(&mut app, 'w');
+        assert_eq!(app.workset.len(), 2);
+        assert_eq!(app.config.hosts, Config::sample().hosts);
+        assert!(app.status.current().unwrap().text.contains("(2 hosts)"));
+
+        press(&mut app, 'v');
+        assert_eq!(names(&app), ["prod-web", "jump-eu"]);
+        assert_eq!(app.current_host().unwrap().name, "jump-eu");
+        app.set_filter("web");
+        assert_eq!(names(&app), ["prod-web"]);
+        app.set_filter("");
+
+        // Taking the last host out of the workset shows everything again.
+        press(&mut app, 'w');
+        select(&mut app, "prod-web");
+        press(&mut app, 'w');
+        assert!(app.workset.is_empty());
+        assert!(!app.workset_only);
+        assert_eq!(names(&app).len(), 3);
+
+        press(&mut app, 'w');
+        app.on_key(KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL))
+            .unwrap();
+        assert!(app.workset.is_empty());
+        press(&mut app, 'v');
+        assert!(!app.workset_only);
+        assert_eq!(app.status.current().unwrap().kind, StatusKind::Warn);
+    }
+
+    #[test]
+    fn workset_persists_in_the_state_file_only_when_asked() {
+        let (dir, mut app) = app_on_disk();
+        select(&mut app, "staging-db");
+        press(&mut app, 'w');
+        let store = || ConfigStore::at(dir.path().join("config.toml"));
+        assert!(App::new(store()).unwrap().workset.is_empty());
+
+        app.config.persist_workset = true;
+        app.save_config().unwrap();
+        press(&mut app, 'R');
+        for _ in 0.."staging-db".len() {
+            app.on_key(KeyEvent::from(KeyCode::Backspace)).unwrap();
+        }
+        type_keys(&mut app, "staging-pg");
+        app.on_key(KeyEvent::from(KeyCode::Enter)).unwrap();
+
+        let reopened = App::new(store()).unwrap();
+        assert_eq!(reopened.workset.iter().collect::<Vec<_>>(), ["staging-pg"]);
+        let config = fs::read_to_string(dir.path().join("config.toml")).unwrap();
+        assert!(!config.contains("workset = ["));
+        let state = fs::read_to_string(dir.path().join("state.toml")).unwrap();
+        assert!(state.contains("staging-pg"));
+    }
 }
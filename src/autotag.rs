@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Auto-tagging rules (`[[auto_tags]]` in the config): tags given to hosts
+//! created by quick connect or the Add form when their address, name, user
+//! or port match. Every matching rule applies; a tag is added once.
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::model::Host;
+use crate::ssh_config::glob_match;
+
+/// One rule. Every condition it sets must hold; conditions left out don't
+/// matter.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutoTagRule {
+    /// Substring of the address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Regular expression searched for in the address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_regex: Option<String>,
+    /// Pattern for the whole host name, with `*` and `?` wildcards.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl AutoTagRule {
+    /// Why the rule can never apply: a bad regex, no condition or no tags.
+    pub fn check(&self) -> Result<()> {
+        if let Some(pattern) = &self.address_regex {
+            Regex::new(pattern).with_context(|| format!("address_regex '{pattern}'"))?;
+        }
+        if self.conditions().is_empty() {
+            bail!("no address, address_regex, name, user or port to match");
+        }
+        if self.tags.iter().all(|tag| tag.trim().is_empty()) {
+            bail!("no tags to apply");
+        }
+        Ok(())
+    }
+
+    /// Whether every condition holds for `host`; a rule that fails `check`
+    /// matches nothing.
+    pub fn matches(&self, host: &Host) -> bool {
+        if self.check().is_err() {
+            return false;
+        }
+        let regex_ok = self.address_regex.as_deref().is_none_or(|pattern| {
+            Regex::new(pattern).is_ok_and(|regex| regex.is_match(&host.address))
+        });
+        regex_ok
+            && self
+                .address
+                .as_deref()
+                .is_none_or(|part| host.address.contains(part))
+            && self
+                .name
+                .as_deref()
+                .is_none_or(|pattern| glob_match(pattern, &host.name))
+            && self
+                .user
+                .as_deref()
+                .is_none_or(|user| host.user.as_deref() == Some(user))
+            && self.port.is_none_or(|port| host.port == Some(port))
+    }
+
+    /// The conditions, e.g. `address ~ 10.1. and name web-*`.
+    pub fn describe(&self) -> String {
+        let conditions = self.conditions();
+        if conditions.is_empty() {
+            return "(no conditions)".into();
+        }
+        conditions.join(" and ")
+    }
+
+    fn conditions(&self) -> Vec<String> {
+        let mut conditions = Vec::new();
+        if let Some(part) = &self.address {
+            conditions.push(format!("address contains {part}"));
+        }
+        if let Some(pattern) = &self.address_regex {
+            conditions.push(format!("address ~ /{pattern}/"));
+        }
+        if let Some(pattern) = &self.name {
+            conditions.push(format!("name {pattern}"));
+        }
+        if let Some(user) = &self.user {
+            conditions.push(format!("user {user}"));
+        }
+        if let Some(port) = self.port {
+            conditions.push(format!("port {port}"));
+        }
+        conditions
+    }
+}
+
+/// Tags the matching rules give `host`, in rule order, each once.
+pub fn tags_for(rules: &[AutoTagRule], host: &Host) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for rule in rules.iter().filter(|rule| rule.matches(host)) {
+        for tag in rule.tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+            if !tags.iter().any(|have| have == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+    }
+    tags
+}
+
+/// Adds the `tags_for` tags `host` doesn't have yet and returns them.
+pub fn apply(rules: &[AutoTagRule], host: &mut Host) -> Vec<String> {
+    let added: Vec<String> = tags_for(rules, host)
+        .into_iter()
+        .filter(|tag| !host.tags.contains(tag))
+        .collect();
+    host.tags.extend(added.iter().cloned());
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(name: &str, address: &str, user: Option<&str>, port: Option<u16>) -> Host {
+        Host {
+            name: name.into(),
+            address: address.into(),
+            user: user.map(Into::into),
+            port,
+            ..Default::default()
+        }
+    }
+
+    fn rule(tags: &[&str]) -> AutoTagRule {
+        AutoTagRule {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn every_matching_rule_applies_once() {
+        let rules = vec![
+            AutoTagRule {
+                address: Some("10.1.".into()),
+                ..rule(&["eu", "lan"])
+            },
+            AutoTagRule {
+                name: Some("web-*".into()),
+                ..rule(&["web", "eu"])
+            },
+            AutoTagRule {
+                address_regex: Some(r"^10\.\d+\.0\.".into()),
+                user: Some("deploy".into()),
+                ..rule(&["deployable"])
+            },
+            AutoTagRule {
+                port: Some(2222),
+                ..rule(&["odd-port"])
+            },
+        ];
+        let web = host("web-1", "10.1.0.5", Some("deploy"), None);
+        assert_eq!(tags_for(&rules, &web), ["eu", "lan", "web", "deployable"]);
+        let other = host("db", "10.1.0.6", Some("root"), Some(2222));
+        assert_eq!(tags_for(&rules, &other), ["eu", "lan", "odd-port"]);
+        assert!(tags_for(&rules, &host("web", "192.0.2.1", None, None)).is_empty());
+
+        let mut tagged = web.clone();
+        tagged.tags = vec!["lan".into()];
+        assert_eq!(apply(&rules, &mut tagged), ["eu", "web", "deployable"]);
+        assert_eq!(tagged.tags, ["lan", "eu", "web", "deployable"]);
+    }
+
+    #[test]
+    fn broken_rules_are_reported_and_match_nothing() {
+        let any = host("web-1", "10.1.0.5", None, None);
+        let bad_regex = AutoTagRule {
+            address_regex: Some("10.(1".into()),
+            ..rule(&["x"])
+        };
+        let err = format!("{:#}", bad_regex.check().unwrap_err());
+        assert!(err.contains("address_regex '10.(1'"), "{err}");
+        assert!(!bad_regex.matches(&any));
+
+        let unconditional = rule(&["everything"]);
+        assert!(unconditional.check().is_err());
+        assert!(!unconditional.matches(&any));
+        assert_eq!(unconditional.describe(), "(no conditions)");
+
+        let tagless = AutoTagRule {
+            name: Some("*".into()),
+            ..rule(&[" "])
+        };
+        assert!(tagless.check().is_err());
+        assert_eq!(tagless.describe(), "name *");
+    }
+}
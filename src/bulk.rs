@@ -0,0 +1,319 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Bulk edits: one field change applied to every marked host. `apply` is a
+//! pure transform; the bulk-edit modal only collects the field and value
+//! and shows what `apply` would do.
+
+use std::collections::HashSet;
+
+use anyhow::{bail, Context, Result};
+
+use crate::model::Host;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BulkField {
+    User,
+    Port,
+    KeyPath,
+    Bastion,
+    AddTag,
+    RemoveTag,
+    AppendOptions,
+}
+
+impl BulkField {
+    /// In the order the modal cycles through them.
+    pub const ALL: [BulkField; 7] = [
+        BulkField::User,
+        BulkField::Port,
+        BulkField::KeyPath,
+        BulkField::Bastion,
+        BulkField::AddTag,
+        BulkField::RemoveTag,
+        BulkField::AppendOptions,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BulkField::User => "user",
+            BulkField::Port => "port",
+            BulkField::KeyPath => "key path",
+            BulkField::Bastion => "bastion",
+            BulkField::AddTag => "add tag",
+            BulkField::RemoveTag => "remove tag",
+            BulkField::AppendOptions => "append options",
+        }
+    }
+
+    /// What an empty value does, for the modal's hint line.
+    pub fn empty_means(self) -> &'static str {
+        match self {
+            BulkField::User | BulkField::Port | BulkField::Bastion => "empty unsets it",
+            BulkField::KeyPath => "comma-separated; empty clears the keys",
+            BulkField::AddTag | BulkField::RemoveTag => "one tag",
+            BulkField::AppendOptions => "space-separated, e.g. -o ServerAliveInterval=30",
+        }
+    }
+
+    /// The field's current value on `host`, as the preview shows it.
+    fn show(self, host: &Host) -> String {
+        match self {
+            BulkField::User => host.user.clone().unwrap_or_default(),
+            BulkField::Port => host.port.map(|p| p.to_string()).unwrap_or_default(),
+            BulkField::KeyPath => host.key_paths.join(", "),
+            BulkField::Bastion => host.bastion.clone().unwrap_or_default(),
+            BulkField::AddTag | BulkField::RemoveTag => host.tags.join(","),
+            BulkField::AppendOptions => host.options.join(" "),
+        }
+    }
+}
+
+/// The bulk-edit modal's input.
+#[derive(Clone, Debug, Default)]
+pub struct BulkEdit {
+    /// Index into `BulkField::ALL`.
+    pub field: usize,
+    pub value: String,
+}
+
+impl BulkEdit {
+    pub fn field(&self) -> BulkField {
+        BulkField::ALL[self.field % BulkField::ALL.len()]
+    }
+
+    /// Moves to the next (`step = 1`) or previous (`-1`) field.
+    pub fn cycle(&mut self, step: isize) {
+        let len = BulkField::ALL.len() as isize;
+        self.field = (self.field as isize + step).rem_euclid(len) as usize;
+    }
+}
+
+/// One host the edit changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BulkChange {
+    /// Canonical id before the edit.
+    pub id: String,
+    pub before: String,
+    pub after: String,
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Sets `field` to `value` on every host whose id is in `marked`. Returns the
+/// new host list and one entry per host that actually changed; hosts where
+/// the edit is a no-op are left out.
+pub fn apply(
+    hosts: &[Host],
+    marked: &HashSet<String>,
+    field: BulkField,
+    value: &str,
+) -> Result<(Vec<Host>, Vec<BulkChange>)> {
+    let port = match field {
+        BulkField::Port => non_empty(value)
+            .map(|p| p.parse::<u16>())
+            .transpose()
+            .context("port must be numeric")?,
+        _ => None,
+    };
+    let tag = value.trim();
+    if matches!(field, BulkField::AddTag | BulkField::RemoveTag) {
+        if tag.is_empty() {
+            bail!("type the tag to {}", field.label());
+        }
+        if tag.contains(',') || tag.contains(char::is_whitespace) {
+            bail!("one tag at a time, without commas or spaces");
+        }
+    }
+    let options: Vec<String> = value.split_whitespace().map(String::from).collect();
+    if field == BulkField::AppendOptions && options.is_empty() {
+        bail!("type the options to append");
+    }
+
+    let mut out = hosts.to_vec();
+    let mut changes = Vec::new();
+    for host in out.iter_mut().filter(|host| marked.contains(&host.id())) {
+        let before = host.clone();
+        match field {
+            BulkField::User => host.user = non_empty(value),
+            BulkField::Port => host.port = port,
+            BulkField::KeyPath => {
+                host.key_paths = value.split(',').filter_map(non_empty).collect();
+            }
+            BulkField::Bastion => host.bastion = non_empty(value),
+            BulkField::AddTag => {
+                if !host.tags.iter().any(|t| t == tag) {
+                    host.tags.push(tag.to_string());
+                }
+            }
+            BulkField::RemoveTag => host.tags.retain(|t| t != tag),
+            BulkField::AppendOptions => {
+                // Appending the same options twice is never what was meant.
+                let present = host
+                    .options
+                    .windows(options.len())
+                    .any(|window| window == options.as_slice());
+                if !present {
+                    host.options.extend(options.iter().cloned());
+                }
+            }
+        }
+        if *host != before {
+            changes.push(BulkChange {
+                id: before.id(),
+                before: field.show(&before),
+                after: field.show(host),
+            });
+        }
+    }
+    Ok((out, changes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts() -> Vec<Host> {
+        let host = |name: &str, user: Option<&str>, tags: &[&str]| Host {
+            name: name.into(),
+            address: format!("{name}.example"),
+            user: user.map(Into::into),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+        vec![
+            host("a", Some("ubuntu"), &["prod"]),
+            host("b", Some("admin"), &[]),
+            host("c", Some("ubuntu"), &["prod", "db"]),
+        ]
+    }
+
+    fn marked(ids: &[&str]) -> HashSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    fn ids(changes: &[BulkChange]) -> Vec<&str> {
+        changes.iter().map(|c| c.id.as_str()).collect()
+    }
+
+    #[test]
+    fn user_changes_only_marked_hosts_and_skips_no_ops() {
+        let hosts = hosts();
+        let (out, changes) = apply(&hosts, &marked(&["a", "b"]), BulkField::User, "admin").unwrap();
+        assert_eq!(out[0].user.as_deref(), Some("admin"));
+        assert_eq!(out[2].user.as_deref(), Some("ubuntu"));
+        // b already uses admin.
+        assert_eq!(
+            changes,
+            [BulkChange {
+                id: "a".into(),
+                before: "ubuntu".into(),
+                after: "admin".into()
+            }]
+        );
+
+        let (out, changes) = apply(&hosts, &marked(&["a", "c"]), BulkField::User, " ").unwrap();
+        assert!(out[0].user.is_none() && out[2].user.is_none());
+        assert_eq!(changes[1].after, "");
+        // The input is left alone.
+        assert_eq!(hosts[0].user.as_deref(), Some("ubuntu"));
+    }
+
+    #[test]
+    fn port_is_parsed_once_and_can_be_unset() {
+        let hosts = hosts();
+        let all = marked(&["a", "b", "c"]);
+        let (out, changes) = apply(&hosts, &all, BulkField::Port, "2222").unwrap();
+        assert!(out.iter().all(|h| h.port == Some(2222)));
+        assert_eq!(changes.len(), 3);
+        assert!(apply(&hosts, &all, BulkField::Port, "ssh").is_err());
+
+        let (_, changes) = apply(&out, &all, BulkField::Port, "").unwrap();
+        assert_eq!(
+            (changes[0].before.as_str(), changes[0].after.as_str()),
+            ("2222", "")
+        );
+        assert!(apply(&hosts, &all, BulkField::Port, "")
+            .unwrap()
+            .1
+            .is_empty());
+    }
+
+    #[test]
+    fn key_path_replaces_the_key_list() {
+        let mut hosts = hosts();
+        hosts[0].key_paths = vec!["~/.ssh/old".into()];
+        let (out, changes) = apply(
+            &hosts,
+            &marked(&["a", "b"]),
+            BulkField::KeyPath,
+            "~/.ssh/new, ~/.ssh/backup",
+        )
+        .unwrap();
+        assert_eq!(out[0].key_paths, ["~/.ssh/new", "~/.ssh/backup"]);
+        assert_eq!(out[1].key_paths, ["~/.ssh/new", "~/.ssh/backup"]);
+        assert_eq!(changes[0].before, "~/.ssh/old");
+        assert_eq!(changes[0].after, "~/.ssh/new, ~/.ssh/backup");
+    }
+
+    #[test]
+    fn bastion_is_set_or_cleared() {
+        let hosts = hosts();
+        let (out, changes) = apply(&hosts, &marked(&["b"]), BulkField::Bastion, "jump").unwrap();
+        assert_eq!(out[1].bastion.as_deref(), Some("jump"));
+        assert_eq!(ids(&changes), ["b"]);
+        let (out, _) = apply(&out, &marked(&["b"]), BulkField::Bastion, "").unwrap();
+        assert!(out[1].bastion.is_none());
+    }
+
+    #[test]
+    fn tags_are_added_and_removed_once() {
+        let hosts = hosts();
+        let all = marked(&["a", "b", "c"]);
+        let (out, changes) = apply(&hosts, &all, BulkField::AddTag, "db").unwrap();
+        assert_eq!(ids(&changes), ["a", "b"]);
+        assert_eq!(out[0].tags, ["prod", "db"]);
+        assert_eq!(out[2].tags, ["prod", "db"]);
+        assert_eq!(changes[0].after, "prod,db");
+
+        let (out, changes) = apply(&out, &all, BulkField::RemoveTag, "prod").unwrap();
+        assert_eq!(ids(&changes), ["a", "c"]);
+        assert_eq!(out[0].tags, ["db"]);
+
+        assert!(apply(&hosts, &all, BulkField::AddTag, "").is_err());
+        assert!(apply(&hosts, &all, BulkField::AddTag, "a,b").is_err());
+    }
+
+    #[test]
+    fn options_are_appended_unless_already_there() {
+        let mut hosts = hosts();
+        hosts[1].options = vec!["-o".into(), "ServerAliveInterval=30".into()];
+        let all = marked(&["a", "b"]);
+        let (out, changes) = apply(
+            &hosts,
+            &all,
+            BulkField::AppendOptions,
+            "-o ServerAliveInterval=30",
+        )
+        .unwrap();
+        assert_eq!(ids(&changes), ["a"]);
+        assert_eq!(out[0].options, ["-o", "ServerAliveInterval=30"]);
+
+        let (out, _) = apply(&out, &all, BulkField::AppendOptions, "-A").unwrap();
+        assert_eq!(out[1].options, ["-o", "ServerAliveInterval=30", "-A"]);
+        assert!(apply(&hosts, &all, BulkField::AppendOptions, " ").is_err());
+    }
+
+    #[test]
+    fn matches_grouped_hosts_by_canonical_id() {
+        let mut hosts = hosts();
+        hosts[0].group = Some("team".into());
+        let (_, changes) = apply(&hosts, &marked(&["a"]), BulkField::User, "root").unwrap();
+        assert!(changes.is_empty());
+        let (_, changes) = apply(&hosts, &marked(&["team/a"]), BulkField::User, "root").unwrap();
+        assert_eq!(ids(&changes), ["team/a"]);
+    }
+}
@@ -0,0 +1,499 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! `sshdb bundle`: the config, UI state and optionally the config backups in
+//! one `.tar.gz` with a manifest, for moving to another machine.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, ConfigStore, CURRENT_VERSION};
+use crate::model::Config;
+use crate::state::{AppState, StateStore};
+
+/// Layout version of the archive itself.
+pub const FORMAT: u8 = 1;
+const MANIFEST: &str = "manifest.toml";
+const CONFIG: &str = "config.toml";
+const STATE: &str = "state.toml";
+const BACKUPS: &str = "backups/";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BundleCommand {
+    Export { path: PathBuf, with_backups: bool },
+    Import { path: PathBuf, yes: bool },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u8,
+    pub sshdb_version: String,
+    /// Unix seconds.
+    pub created: u64,
+    pub config_version: u8,
+    pub hosts: usize,
+    pub files: Vec<String>,
+}
+
+/// A bundle read and validated in memory; nothing is written until `apply`.
+pub struct Bundle {
+    pub manifest: Manifest,
+    config_text: String,
+    config: Config,
+    /// Version `config_text` is written in; older ones migrate on apply.
+    config_version: u8,
+    state: Option<Vec<u8>>,
+    backups: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    /// The config the bundle would install.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+}
+
+/// Where the TUI reads and writes bundles.
+pub fn default_path() -> PathBuf {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("sshdb-bundle.tar.gz")
+}
+
+pub fn run(command: &BundleCommand, store: &ConfigStore) -> Result<i32> {
+    match command {
+        BundleCommand::Export { path, with_backups } => {
+            let manifest = export(store, path, *with_backups)?;
+            println!(
+                "Wrote {} ({} hosts): {}",
+                path.display(),
+                manifest.hosts,
+                manifest.files.join(", ")
+            );
+            Ok(0)
+        }
+        BundleCommand::Import { path, yes } => {
+            let bundle = read(path)?;
+            for line in plan(&bundle, store) {
+                println!("{line}");
+            }
+            if !yes && !confirm("Apply? [y/N] ")? {
+                eprintln!("sshdb bundle: nothing written");
+                return Ok(1);
+            }
+            apply(&bundle, store)?;
+            println!("Imported {}.", path.display());
+            Ok(0)
+        }
+    }
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{prompt}");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Writes the bundle for `store` to `out`. The config is copied byte for
+/// byte; the state file and backups are included when present.
+pub fn export(store: &ConfigStore, out: &Path, with_backups: bool) -> Result<Manifest> {
+    if store.is_in_memory() {
+        bail!("an in-memory config can't be bundled");
+    }
+    let config_path = store.path();
+    let config_text = fs::read_to_string(config_path)
+        .map_err(|err| anyhow::anyhow!("cannot read {}: {}", config_path.display(), err.kind()))?;
+    let (config, config_version) = config::parse_document(&config_text)?;
+
+    let mut entries = vec![(CONFIG.to_string(), config_text.into_bytes())];
+    let state = StateStore::beside(config_path);
+    if state.path().exists() {
+        entries.push((STATE.to_string(), fs::read(state.path())?));
+    }
+    if with_backups {
+        for path in backup_files(config_path)? {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            entries.push((format!("{BACKUPS}{name}"), fs::read(&path)?));
+        }
+    }
+    let manifest = Manifest {
+        format: FORMAT,
+        sshdb_version: env!("CARGO_PKG_VERSION").to_string(),
+        created: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        config_version,
+        hosts: config.hosts.len(),
+        files: entries.iter().map(|(name, _)| name.clone()).collect(),
+    };
+
+    let file = fs::File::create(out)
+        .map_err(|err| anyhow::anyhow!("cannot write {}: {}", out.display(), err.kind()))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let manifest_text = toml::to_string_pretty(&manifest)?;
+    for (name, data) in std::iter::once((MANIFEST, manifest_text.as_bytes())).chain(
+        entries
+            .iter()
+            .map(|(name, data)| (name.as_str(), data.as_slice())),
+    ) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(manifest.created);
+        archive
+            .append_data(&mut header, name, data)
+            .with_context(|| format!("failed to add {name} to the bundle"))?;
+    }
+    archive.into_inner()?.finish()?;
+    Ok(manifest)
+}
+
+/// `config.toml.bak`, `config.v1.toml.bak` and friends next to the config.
+fn backup_files(config_path: &Path) -> Result<Vec<PathBuf>> {
+    let Some(dir) = config_path.parent() else {
+        return Ok(Vec::new());
+    };
+    let stem = config_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| is_backup_name(name, &stem))
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn is_backup_name(name: &str, stem: &str) -> bool {
+    name.starts_with(&format!("{stem}.")) && name.ends_with(".bak")
+}
+
+/// Reads and validates a bundle: the manifest must be from a format this
+/// build knows and the config must parse (migrating older versions).
+pub fn read(path: &Path) -> Result<Bundle> {
+    let file = fs::File::open(path)
+        .map_err(|err| anyhow::anyhow!("cannot read {}: {}", path.display(), err.kind()))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let mut files = BTreeMap::new();
+    for entry in archive
+        .entries()
+        .with_context(|| format!("{} is not a bundle", path.display()))?
+    {
+        let mut entry = entry.with_context(|| format!("{} is damaged", path.display()))?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        files.insert(name, data);
+    }
+
+    let manifest = files
+        .remove(MANIFEST)
+        .context("not an sshdb bundle: no manifest.toml")?;
+    let manifest: Manifest = toml::from_str(std::str::from_utf8(&manifest)?)
+        .context("manifest.toml in the bundle is invalid")?;
+    if manifest.format > FORMAT {
+        bail!(
+            "bundle format {} is newer than this sshdb supports ({FORMAT}); upgrade sshdb",
+            manifest.format
+        );
+    }
+    let config_text = String::from_utf8(files.remove(CONFIG).context("bundle has no config.toml")?)
+        .context("config.toml in the bundle is not UTF-8")?;
+    let (config, config_version) =
+        config::parse_document(&config_text).context("config.toml in the bundle")?;
+    let state = files.remove(STATE);
+    if let Some(state) = &state {
+        toml::from_str::<AppState>(std::str::from_utf8(state)?)
+            .context("state.toml in the bundle is invalid")?;
+    }
+    let backups = files
+        .into_iter()
+        .filter_map(|(name, data)| {
+            let name = name.strip_prefix(BACKUPS)?;
+            let plain =
+                name.ends_with(".bak") && !name.starts_with('.') && !name.contains(['/', '\\']);
+            plain.then(|| (name.to_string(), data))
+        })
+        .collect();
+    Ok(Bundle {
+        manifest,
+        config_text,
+        config,
+        config_version,
+        state,
+        backups,
+    })
+}
+
+/// What `apply` would do, one line per file, for confirmation. The config
+/// and state are replaced; backups are merged into the config directory.
+pub fn plan(bundle: &Bundle, store: &ConfigStore) -> Vec<String> {
+    let manifest = &bundle.manifest;
+    let mut lines = vec![format!(
+        "Bundle from sshdb {}, created {}: {} hosts, config v{}.",
+        manifest.sshdb_version,
+        utc_date(manifest.created),
+        manifest.hosts,
+        manifest.config_version
+    )];
+
+    let path = store.path();
+    let migrated = if bundle.config_version < CURRENT_VERSION {
+        format!(
+            " (migrated from v{} to v{CURRENT_VERSION})",
+            bundle.config_version
+        )
+    } else {
+        String::new()
+    };
+    let ids = |config: &Config| config.hosts.iter().map(|h| h.id()).collect::<BTreeSet<_>>();
+    let incoming = ids(&bundle.config);
+    lines.push(match path.exists().then(|| store.load_read_only()) {
+        Some(Ok(current)) => {
+            let current = ids(&current);
+            format!(
+                "replace {}{migrated}: {} hosts become {} ({} new, {} dropped); the current file is kept as {}",
+                path.display(),
+                current.len(),
+                incoming.len(),
+                incoming.difference(&current).count(),
+                current.difference(&incoming).count(),
+                pre_import_backup(path).display()
+            )
+        }
+        Some(Err(_)) => format!(
+            "replace {}{migrated} (current file unreadable; kept as {})",
+            path.display(),
+            pre_import_backup(path).display()
+        ),
+        None => format!("create {}{migrated} with {} hosts", path.display(), incoming.len()),
+    });
+
+    let state = StateStore::beside(path);
+    lines.push(match (&bundle.state, state.path().exists()) {
+        (Some(_), true) => format!("replace {}", state.path().display()),
+        (Some(_), false) => format!("create {}", state.path().display()),
+        (None, _) => format!("keep {} (not in the bundle)", state.path().display()),
+    });
+    let dir = path.parent().unwrap_or(Path::new("."));
+    for (name, _) in &bundle.backups {
+        let target = dir.join(name);
+        let verb = if target.exists() { "replace" } else { "add" };
+        lines.push(format!("{verb} {}", target.display()));
+    }
+    lines
+}
+
+/// Writes the bundle over `store`'s location. The config text is written
+/// as is, then migrated in place if it is from an older version.
+pub fn apply(bundle: &Bundle, store: &ConfigStore) -> Result<()> {
+    if store.is_in_memory() {
+        bail!("can't import into an in-memory config");
+    }
+    store.check_writable()?;
+    let path = store.path();
+    if path.exists() {
+        let backup = pre_import_backup(path);
+        fs::copy(path, &backup)
+            .map_err(|err| anyhow::anyhow!("cannot write {}: {}", backup.display(), err.kind()))?;
+    }
+    store.write(&bundle.config_text)?;
+    if bundle.config_version < CURRENT_VERSION {
+        store.load_or_init()?;
+    }
+    if let Some(state) = &bundle.state {
+        let state_path = StateStore::beside(path).path().to_path_buf();
+        fs::write(&state_path, state).map_err(|err| {
+            anyhow::anyhow!("cannot write {}: {}", state_path.display(), err.kind())
+        })?;
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    for (name, data) in &bundle.backups {
+        let target = dir.join(name);
+        fs::write(&target, data)
+            .map_err(|err| anyhow::anyhow!("cannot write {}: {}", target.display(), err.kind()))?;
+    }
+    Ok(())
+}
+
+fn pre_import_backup(path: &Path) -> PathBuf {
+    path.with_extension("pre-import.toml.bak")
+}
+
+/// `YYYY-MM-DD HH:MM UTC` for unix seconds.
+fn utc_date(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    // Civil-from-days, after Howard Hinnant's date algorithms.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let minutes = secs % 86_400 / 60;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const CONFIG_TEXT: &str = "# team hosts\nversion = 2\n\n[[hosts]]\nname = \"web\"  # front\nhost = \"10.0.0.1\"\n\n[[hosts]]\nname = \"db\"\nhost = \"10.0.0.2\"\n";
+
+    fn setup(dir: &Path, config: &str) -> ConfigStore {
+        let path = dir.join("config.toml");
+        fs::write(&path, config).unwrap();
+        ConfigStore::at(path)
+    }
+
+    #[test]
+    fn round_trips_config_state_and_backups() {
+        let from = tempdir().unwrap();
+        let store = setup(from.path(), CONFIG_TEXT);
+        fs::write(from.path().join("state.toml"), "search_scope = \"names\"\n").unwrap();
+        fs::write(from.path().join("config.toml.bak"), "old").unwrap();
+        fs::write(from.path().join("notes.txt"), "not ours").unwrap();
+        let archive = from.path().join("bundle.tar.gz");
+        let manifest = export(&store, &archive, true).unwrap();
+        assert_eq!(manifest.hosts, 2);
+        assert_eq!(
+            manifest.files,
+            vec!["config.toml", "state.toml", "backups/config.toml.bak"]
+        );
+
+        let to = tempdir().unwrap();
+        let target = setup(
+            to.path(),
+            "version = 2\n\n[[hosts]]\nname = \"db\"\nhost = \"x\"\n",
+        );
+        let bundle = read(&archive).unwrap();
+        assert_eq!(bundle.manifest, manifest);
+        let plan = plan(&bundle, &target);
+        assert!(
+            plan[1].contains("1 hosts become 2 (1 new, 0 dropped)"),
+            "{plan:?}"
+        );
+        assert!(plan[2].starts_with("create "), "{plan:?}");
+        assert!(plan[3].starts_with("add "), "{plan:?}");
+        // Reading and planning write nothing.
+        assert_eq!(fs::read_dir(to.path()).unwrap().count(), 1);
+
+        apply(&bundle, &target).unwrap();
+        assert_eq!(
+            fs::read_to_string(to.path().join("config.toml")).unwrap(),
+            CONFIG_TEXT
+        );
+        assert_eq!(
+            fs::read_to_string(to.path().join("state.toml")).unwrap(),
+            "search_scope = \"names\"\n"
+        );
+        assert_eq!(
+            fs::read_to_string(to.path().join("config.toml.bak")).unwrap(),
+            "old"
+        );
+        assert!(
+            fs::read_to_string(to.path().join("config.pre-import.toml.bak"))
+                .unwrap()
+                .contains("host = \"x\"")
+        );
+        assert!(!to.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn config_only_bundles_import_and_migrate() {
+        let from = tempdir().unwrap();
+        let v1 = "[[hosts]]\nname = \"a\"\nhost = \"a\"\nkey_path = \"~/.ssh/a\"\n";
+        let store = setup(from.path(), v1);
+        let archive = from.path().join("bundle.tar.gz");
+        let manifest = export(&store, &archive, false).unwrap();
+        assert_eq!(manifest.files, vec!["config.toml"]);
+        assert_eq!(manifest.config_version, 1);
+
+        let to = tempdir().unwrap();
+        let target = ConfigStore::at(to.path().join("nested").join("config.toml"));
+        let bundle = read(&archive).unwrap();
+        let plan = plan(&bundle, &target);
+        assert!(plan[1].contains("create "), "{plan:?}");
+        assert!(plan[1].contains("migrated from v1 to v2"), "{plan:?}");
+        assert!(plan[2].starts_with("keep "), "{plan:?}");
+
+        apply(&bundle, &target).unwrap();
+        let (config, migration) = target.load_or_init().unwrap();
+        assert!(migration.is_none(), "migrated during import");
+        assert_eq!(config.hosts[0].key_paths, vec!["~/.ssh/a".to_string()]);
+        assert!(to.path().join("nested").join("config.v1.toml.bak").exists());
+    }
+
+    #[test]
+    fn rejects_foreign_and_newer_bundles() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("bundle.tar.gz");
+        let write = |entries: &[(&str, &str)]| {
+            let file = fs::File::create(&path).unwrap();
+            let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+            for (name, text) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(text.len() as u64);
+                header.set_mode(0o600);
+                archive
+                    .append_data(&mut header, name, text.as_bytes())
+                    .unwrap();
+            }
+            archive.into_inner().unwrap().finish().unwrap();
+        };
+        let manifest = |format: u8| {
+            format!(
+                "format = {format}\nsshdb_version = \"9\"\ncreated = 0\nconfig_version = 2\nhosts = 0\nfiles = []\n"
+            )
+        };
+
+        write(&[("config.toml", "version = 2\n")]);
+        assert!(read(&path).err().unwrap().to_string().contains("manifest"));
+        write(&[
+            ("manifest.toml", &manifest(FORMAT + 1)),
+            ("config.toml", ""),
+        ]);
+        assert!(read(&path).err().unwrap().to_string().contains("newer"));
+        write(&[
+            ("manifest.toml", &manifest(FORMAT)),
+            ("config.toml", "version = 99\n"),
+        ]);
+        let err = format!("{:#}", read(&path).err().unwrap());
+        assert!(err.contains("newer than this sshdb"), "{err}");
+        write(&[("manifest.toml", &manifest(FORMAT))]);
+        assert!(read(&path).err().unwrap().to_string().contains("no config"));
+    }
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(utc_date(0), "1970-01-01 00:00 UTC");
+        assert_eq!(utc_date(1_711_846_800), "2024-03-31 01:00 UTC");
+        assert_eq!(utc_date(951_782_400), "2000-02-29 00:00 UTC");
+    }
+}
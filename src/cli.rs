@@ -0,0 +1,649 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use crate::bundle::BundleCommand;
+use crate::config::ConfigStore;
+use crate::debuglog::{self, LogSettings};
+use crate::exec::{ExecArgs, DEFAULT_PARALLEL};
+use crate::export;
+use crate::model::Config;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What the process should do, decided before any terminal setup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CliAction {
+    Tui,
+    PrintVersion,
+    PrintConfigPath,
+    PrintDefaultConfig,
+    PrintHelp,
+    /// `sshdb exec …`: run a command across hosts without the TUI.
+    Exec(ExecArgs),
+    /// `sshdb bundle export|import FILE`: move the config to another machine.
+    Bundle(BundleCommand),
+    /// `sshdb connect NAME [--profile P]`: connect to one host without the
+    /// TUI.
+    Connect {
+        host: String,
+        /// `None`: the default profile.
+        profile: Option<String>,
+    },
+    /// `sshdb export [--inline-aliases] [--workset]`: print the config for
+    /// sharing.
+    Export {
+        inline_aliases: bool,
+        workset: bool,
+    },
+    /// `sshdb doctor [--collect]`: version, paths and, with `--collect`,
+    /// the end of the debug log for a bug report.
+    Doctor {
+        collect: bool,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cli {
+    pub action: CliAction,
+    /// Line-based UI without raw mode or the alternate screen.
+    pub basic: bool,
+    /// Open in the compact pick-and-connect launcher.
+    pub launcher: bool,
+    /// `--config PATH`, taking precedence over `SSHDB_CONFIG`.
+    pub config: Option<PathBuf>,
+    /// `--overlay PATH` files (repeatable), merged over the config in order.
+    pub overlays: Vec<PathBuf>,
+    /// `--log-file PATH`: write the debug log there; see `debuglog`.
+    pub log_file: Option<PathBuf>,
+    /// `--log-redact`: keep destinations and users out of the log.
+    pub log_redact: bool,
+}
+
+impl Cli {
+    pub fn parse<I, S>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut args: Vec<String> = args.into_iter().map(Into::into).collect();
+        // Options every action takes, wherever they appear.
+        // `--config=~/x.toml` reaches us with the tilde unexpanded.
+        let config = take_value(&mut args, "--config")?
+            .map(|path| PathBuf::from(crate::ssh::expand_tilde(&path)));
+        let log_file = take_value(&mut args, "--log-file")?.map(PathBuf::from);
+        let log_redact = match args.iter().position(|arg| arg == "--log-redact") {
+            Some(at) => {
+                args.remove(at);
+                true
+            }
+            None => false,
+        };
+        let command = |action| Self {
+            action,
+            basic: false,
+            launcher: false,
+            config: config.clone(),
+            overlays: Vec::new(),
+            log_file: log_file.clone(),
+            log_redact,
+        };
+        let mut args = args.into_iter().peekable();
+        if args.peek().is_some_and(|arg| arg == "exec") {
+            args.next();
+            return Ok(command(CliAction::Exec(parse_exec(args)?)));
+        }
+        if args.peek().is_some_and(|arg| arg == "bundle") {
+            args.next();
+            return Ok(command(CliAction::Bundle(parse_bundle(args)?)));
+        }
+        if args.peek().is_some_and(|arg| arg == "connect") {
+            args.next();
+            let host = args
+                .next()
+                .filter(|host| !host.starts_with('-'))
+                .context("connect needs a host name or group/name id")?;
+            let mut profile = None;
+            while let Some(arg) = args.next() {
+                if arg == "--profile" {
+                    profile = Some(args.next().context("--profile needs a profile name")?);
+                } else if let Some(name) = arg.strip_prefix("--profile=") {
+                    profile = Some(name.to_string());
+                } else {
+                    bail!("unknown connect argument '{arg}' (see --help)");
+                }
+            }
+            return Ok(command(CliAction::Connect { host, profile }));
+        }
+        if args.peek().is_some_and(|arg| arg == "doctor") {
+            args.next();
+            let mut collect = false;
+            for arg in args {
+                match arg.as_str() {
+                    "--collect" => collect = true,
+                    other => bail!("unknown doctor argument '{other}' (see --help)"),
+                }
+            }
+            return Ok(command(CliAction::Doctor { collect }));
+        }
+        if args.peek().is_some_and(|arg| arg == "export") {
+            args.next();
+            let mut inline_aliases = false;
+            let mut workset = false;
+            for arg in args {
+                match arg.as_str() {
+                    "--inline-aliases" => inline_aliases = true,
+                    "--workset" => workset = true,
+                    other => bail!("unknown export argument '{other}' (see --help)"),
+                }
+            }
+            return Ok(command(CliAction::Export {
+                inline_aliases,
+                workset,
+            }));
+        }
+        let mut action = CliAction::Tui;
+        let mut basic = false;
+        let mut launcher = false;
+        let mut overlays = Vec::new();
+        while let Some(arg) = args.next() {
+            let next = match arg.as_str() {
+                "--basic" => {
+                    basic = true;
+                    continue;
+                }
+                "--launcher" => {
+                    launcher = true;
+                    continue;
+                }
+                "--overlay" => {
+                    let Some(path) = args.next() else {
+                        bail!("--overlay needs a value");
+                    };
+                    overlays.push(PathBuf::from(path));
+                    continue;
+                }
+                "--version" | "-V" => CliAction::PrintVersion,
+                "--print-config-path" => CliAction::PrintConfigPath,
+                "--print-default-config" => CliAction::PrintDefaultConfig,
+                "--help" | "-h" => CliAction::PrintHelp,
+                other => bail!("unknown argument '{other}' (see --help)"),
+            };
+            if action != CliAction::Tui && action != next {
+                bail!("only one of --version, --print-config-path, --print-default-config may be given");
+            }
+            action = next;
+        }
+        Ok(Self {
+            action,
+            basic,
+            launcher,
+            overlays,
+            ..command(CliAction::Tui)
+        })
+    }
+
+    /// The config store every action works on.
+    pub fn store(&self) -> ConfigStore {
+        match &self.config {
+            Some(path) => ConfigStore::at(path.clone()),
+            None => ConfigStore::new(),
+        }
+    }
+
+    /// Debug log settings from `--log-file`, `--log-redact` and the
+    /// environment; `None` when logging is off.
+    pub fn log_settings(&self) -> Result<Option<LogSettings>> {
+        LogSettings::from_env(
+            self.log_file.as_deref(),
+            self.log_redact,
+            self.store().path(),
+        )
+    }
+
+    /// Text to print for informational flags; `None` means start the TUI.
+    /// Must stay free of side effects such as creating the config file.
+    pub fn info_output(&self) -> Result<Option<String>> {
+        let text = match self.action {
+            CliAction::Tui
+            | CliAction::Exec(_)
+            | CliAction::Bundle(_)
+            | CliAction::Connect { .. } => return Ok(None),
+            CliAction::PrintVersion => version_string(),
+            CliAction::PrintConfigPath => self.store().path().display().to_string(),
+            CliAction::PrintDefaultConfig => toml::to_string_pretty(&Config::default())
+                .with_context(|| "failed to serialize default config")?,
+            CliAction::PrintHelp => usage().to_string(),
+            CliAction::Export {
+                inline_aliases,
+                workset,
+            } => export::run(&self.store(), inline_aliases, workset)?,
+            CliAction::Doctor { collect } => debuglog::doctor_report(
+                self.store().path(),
+                self.log_settings()?.as_ref(),
+                collect,
+            )?,
+        };
+        Ok(Some(text))
+    }
+}
+
+/// Removes `name VALUE` or `name=VALUE` from `args` and returns the value.
+fn take_value(args: &mut Vec<String>, name: &str) -> Result<Option<String>> {
+    let joined = |arg: &String| {
+        arg.strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+            .map(String::from)
+    };
+    if let Some(at) = args.iter().position(|arg| joined(arg).is_some()) {
+        return Ok(joined(&args.remove(at)));
+    }
+    let Some(at) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    if at + 1 >= args.len() {
+        bail!("{name} needs a value");
+    }
+    let value = args.remove(at + 1);
+    args.remove(at);
+    Ok(Some(value))
+}
+
+fn parse_exec(mut args: impl Iterator<Item = String>) -> Result<ExecArgs> {
+    let mut exec = ExecArgs {
+        parallel: DEFAULT_PARALLEL,
+        ..Default::default()
+    };
+    let mut command = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().with_context(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--tag" => exec.tags.push(value()?),
+            "--host" => exec.hosts.push(value()?),
+            "--filter" => exec.filters.push(value()?),
+            "--command" => command = Some(value()?),
+            "--parallel" => {
+                let raw = value()?;
+                exec.parallel = raw.parse().ok().filter(|n| *n > 0).with_context(|| {
+                    format!("--parallel expects a positive number, got '{raw}'")
+                })?;
+            }
+            "--timeout" => {
+                let raw = value()?;
+                let secs: u64 = raw
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .with_context(|| format!("--timeout expects seconds, got '{raw}'"))?;
+                exec.timeout = Some(Duration::from_secs(secs));
+            }
+            "--dry-run" => exec.dry_run = true,
+            "--workset" => exec.workset = true,
+            other => bail!("unknown exec argument '{other}' (see --help)"),
+        }
+    }
+    exec.command = command
+        .filter(|c| !c.trim().is_empty())
+        .context("exec needs --command")?;
+    Ok(exec)
+}
+
+fn parse_bundle(mut args: impl Iterator<Item = String>) -> Result<BundleCommand> {
+    let sub = args.next().context("bundle needs 'export' or 'import'")?;
+    let mut path = None;
+    let mut flag = false;
+    let flag_name = match sub.as_str() {
+        "export" => "--with-backups",
+        "import" => "--yes",
+        other => bail!("unknown bundle command '{other}' (expected export or import)"),
+    };
+    for arg in args {
+        if arg == flag_name {
+            flag = true;
+        } else if arg.starts_with('-') || path.is_some() {
+            bail!("unknown bundle {sub} argument '{arg}' (see --help)");
+        } else {
+            path = Some(PathBuf::from(arg));
+        }
+    }
+    let path = path.with_context(|| format!("bundle {sub} needs a file"))?;
+    Ok(match sub.as_str() {
+        "export" => BundleCommand::Export {
+            path,
+            with_backups: flag,
+        },
+        _ => BundleCommand::Import { path, yes: flag },
+    })
+}
+
+pub fn version_string() -> String {
+    match option_env!("SSHDB_GIT_HASH") {
+        Some(hash) => format!("sshdb {VERSION} ({hash})"),
+        None => format!("sshdb {VERSION}"),
+    }
+}
+
+fn usage() -> &'static str {
+    "Usage: sshdb [OPTIONS]\n       \
+     sshdb connect NAME [--profile P]\n       \
+     sshdb exec --command CMD [--tag T] [--host NAME] [--filter Q] [--workset] [--parallel N] [--timeout S] [--dry-run]\n       \
+     sshdb export [--inline-aliases] [--workset]\n       \
+     sshdb bundle export FILE.tar.gz [--with-backups]\n       \
+     sshdb bundle import FILE.tar.gz [--yes]\n       \
+     sshdb doctor [--collect]\n\n\
+     Options:\n  \
+       --config PATH           use PATH as the config file (also SSHDB_CONFIG)\n  \
+       --version               print version (and git hash when known) and exit\n  \
+       --print-config-path     print the resolved config path and exit\n  \
+       --print-default-config  print a default config.toml template and exit\n  \
+       --basic                 line-based UI for terminals without full TUI support\n  \
+       --launcher              open a minimal search-and-connect view\n  \
+       --overlay PATH          merge the hosts in PATH for this session only\n                          \
+     (repeatable; later files win, nothing is saved)\n  \
+       --log-file PATH         write a debug log to PATH (also SSHDB_LOG=debug,\n                          \
+     which logs to sshdb.log next to the config)\n  \
+       --log-redact            keep hosts, users and key paths out of the log\n                          \
+     (also SSHDB_LOG_REDACT=1)\n  \
+       -h, --help              show this help\n\n\
+     connect runs ssh for the host NAME (or group/name) the way the TUI would\n\
+     and exits with ssh's status; --profile looks in profile P instead of the\n\
+     default one.\n\
+     exec runs CMD on every host matching any --tag, --host or --filter (each\n\
+     repeatable) or in the saved workset, N at a time (default 4), and exits\n\
+     non-zero if any fails.\n\
+     export prints the config as TOML; --inline-aliases replaces addresses that\n\
+     are ssh_config aliases with their HostName (and User, Port, ...) from\n\
+     ~/.ssh/config so the file works for others; --workset keeps only the saved\n\
+     workset and the bastions it needs.\n\
+     bundle export packs the config, UI state and (with --with-backups) the\n\
+     config backups into one archive; bundle import shows what it would\n\
+     replace and asks before writing (--yes skips the question).\n\
+     doctor prints the version and the config and log paths; --collect adds\n\
+     the end of the debug log, ready to paste into a bug report."
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn parses_info_flags() {
+        assert_eq!(
+            Cli::parse(Vec::<String>::new()).unwrap().action,
+            CliAction::Tui
+        );
+        assert_eq!(
+            Cli::parse(["--version"]).unwrap().action,
+            CliAction::PrintVersion
+        );
+        assert_eq!(
+            Cli::parse(["--print-config-path"]).unwrap().action,
+            CliAction::PrintConfigPath
+        );
+        assert!(Cli::parse(["--basic"]).unwrap().basic);
+        assert!(Cli::parse(["--launcher"]).unwrap().launcher);
+        assert!(Cli::parse(["--bogus"]).is_err());
+        assert!(Cli::parse(["--version", "--print-config-path"]).is_err());
+    }
+
+    #[test]
+    fn parses_bundle_commands() {
+        assert_eq!(
+            Cli::parse(["bundle", "export", "b.tar.gz", "--with-backups"])
+                .unwrap()
+                .action,
+            CliAction::Bundle(BundleCommand::Export {
+                path: "b.tar.gz".into(),
+                with_backups: true,
+            })
+        );
+        assert_eq!(
+            Cli::parse(["bundle", "import", "b.tar.gz"]).unwrap().action,
+            CliAction::Bundle(BundleCommand::Import {
+                path: "b.tar.gz".into(),
+                yes: false,
+            })
+        );
+        for bad in [
+            &["bundle"][..],
+            &["bundle", "import"],
+            &["bundle", "pack", "b"],
+            &["bundle", "import", "b", "--with-backups"],
+            &["bundle", "export", "a", "b"],
+        ] {
+            assert!(Cli::parse(bad.iter().copied()).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn config_flag_works_with_any_action() {
+        let cli = Cli::parse(["--config", "/tmp/x.toml", "--print-config-path"]).unwrap();
+        assert_eq!(cli.info_output().unwrap().unwrap(), "/tmp/x.toml");
+        let cli =
+            Cli::parse(["exec", "--command", "w", "--tag", "t", "--config", "c.toml"]).unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("c.toml")));
+        assert!(matches!(cli.action, CliAction::Exec(_)));
+        assert!(Cli::parse(["--config"]).is_err());
+
+        let cli = Cli::parse(["--config=/tmp/work.toml", "--basic"]).unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("/tmp/work.toml")));
+        assert!(cli.basic);
+        if let Ok(home) = std::env::var("HOME") {
+            let cli = Cli::parse(["--config=~/work.toml"]).unwrap();
+            assert_eq!(cli.config, Some(PathBuf::from(home).join("work.toml")));
+        }
+    }
+
+    #[test]
+    fn overlay_flag_repeats_in_order() {
+        let cli = Cli::parse(["--overlay", "a.toml", "--basic", "--overlay", "b.toml"]).unwrap();
+        assert_eq!(
+            cli.overlays,
+            [PathBuf::from("a.toml"), PathBuf::from("b.toml")]
+        );
+        assert!(cli.basic);
+        assert!(Cli::parse(Vec::<String>::new())
+            .unwrap()
+            .overlays
+            .is_empty());
+        assert!(Cli::parse(["--overlay"]).is_err());
+        assert!(Cli::parse(["exec", "--command", "w", "--overlay", "a.toml"]).is_err());
+    }
+
+    #[test]
+    fn log_flags_work_with_any_action() {
+        let cli =
+            Cli::parse(["doctor", "--log-file", "d.log", "--collect", "--log-redact"]).unwrap();
+        assert_eq!(cli.action, CliAction::Doctor { collect: true });
+        assert_eq!(cli.log_file, Some(PathBuf::from("d.log")));
+        assert!(cli.log_redact);
+        let cli = Cli::parse(["--basic", "--log-file", "t.log"]).unwrap();
+        assert!(cli.basic && !cli.log_redact);
+        assert_eq!(cli.log_file, Some(PathBuf::from("t.log")));
+        assert!(Cli::parse(["--log-file"]).is_err());
+        assert!(Cli::parse(["doctor", "--upload"]).is_err());
+    }
+
+    #[test]
+    fn parses_exec() {
+        let cli = Cli::parse([
+            "exec",
+            "--tag",
+            "backup",
+            "--host",
+            "db",
+            "--host",
+            "web",
+            "--command",
+            "restic backup /srv",
+            "--parallel",
+            "8",
+            "--timeout",
+            "60",
+            "--dry-run",
+            "--workset",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.action,
+            CliAction::Exec(ExecArgs {
+                tags: vec!["backup".into()],
+                hosts: vec!["db".into(), "web".into()],
+                filters: Vec::new(),
+                command: "restic backup /srv".into(),
+                parallel: 8,
+                timeout: Some(Duration::from_secs(60)),
+                dry_run: true,
+                workset: true,
+            })
+        );
+
+        let CliAction::Exec(exec) = Cli::parse(["exec", "--filter", "prod", "--command", "w"])
+            .unwrap()
+            .action
+        else {
+            panic!("expected exec");
+        };
+        assert_eq!(exec.parallel, DEFAULT_PARALLEL);
+        assert_eq!(exec.timeout, None);
+
+        assert!(Cli::parse(["exec", "--tag", "x"]).is_err());
+        assert!(Cli::parse(["exec", "--command"]).is_err());
+        assert!(Cli::parse(["exec", "--command", "w", "--parallel", "0"]).is_err());
+        assert!(Cli::parse(["exec", "--command", "w", "--basic"]).is_err());
+    }
+
+    #[test]
+    fn parses_export() {
+        assert_eq!(
+            Cli::parse(["export"]).unwrap().action,
+            CliAction::Export {
+                inline_aliases: false,
+                workset: false,
+            }
+        );
+        assert_eq!(
+            Cli::parse(["export", "--inline-aliases", "--workset"])
+                .unwrap()
+                .action,
+            CliAction::Export {
+                inline_aliases: true,
+                workset: true,
+            }
+        );
+        assert!(Cli::parse(["export", "--json"]).is_err());
+    }
+
+    #[test]
+    fn usage_continuation_lines_stay_under_the_description() {
+        let lines: Vec<&str> = usage().lines().collect();
+        let column = |line: &str| line.len() - line.trim_start().len();
+        for (flag, description) in [
+            ("--overlay", "merge"),
+            ("--log-file", "write"),
+            ("--log-redact", "keep"),
+        ] {
+            let at = lines.iter().position(|l| l.contains(flag)).unwrap();
+            let description = lines[at].find(description).unwrap();
+            assert_eq!(column(lines[at + 1]), description, "{}", lines[at + 1]);
+        }
+    }
+
+    #[test]
+    fn parses_connect() {
+        assert_eq!(
+            Cli::parse(["connect", "prod-web"]).unwrap().action,
+            CliAction::Connect {
+                host: "prod-web".into(),
+                profile: None,
+            }
+        );
+        let cli = Cli::parse(["connect", "eu/db", "--config", "c.toml"]).unwrap();
+        assert_eq!(
+            cli.action,
+            CliAction::Connect {
+                host: "eu/db".into(),
+                profile: None,
+            }
+        );
+        assert_eq!(cli.config, Some(PathBuf::from("c.toml")));
+        for args in [
+            &["connect", "nas", "--profile", "home"][..],
+            &["connect", "nas", "--profile=home"],
+        ] {
+            assert_eq!(
+                Cli::parse(args.iter().copied()).unwrap().action,
+                CliAction::Connect {
+                    host: "nas".into(),
+                    profile: Some("home".into()),
+                }
+            );
+        }
+        for bad in [
+            &["connect"][..],
+            &["connect", "--basic"],
+            &["connect", "a", "b"],
+            &["connect", "a", "--profile"],
+        ] {
+            assert!(Cli::parse(bad.iter().copied()).is_err(), "{bad:?}");
+        }
+    }
+
+    fn assert_no_files_created(action: CliAction) -> String {
+        let _guard = crate::ENV_LOCK.lock().unwrap();
+        let home = tempdir().unwrap();
+        let old_home = std::env::var_os("HOME");
+        let old_xdg = std::env::var_os("XDG_CONFIG_HOME");
+        unsafe {
+            std::env::set_var("HOME", home.path());
+            std::env::remove_var("XDG_CONFIG_HOME");
+        }
+        let out = Cli {
+            action,
+            basic: false,
+            launcher: false,
+            config: None,
+            overlays: Vec::new(),
+            log_file: None,
+            log_redact: false,
+        }
+        .info_output();
+        unsafe {
+            match old_home {
+                Some(v) => std::env::set_var("HOME", v),
+                None => std::env::remove_var("HOME"),
+            }
+            if let Some(v) = old_xdg {
+                std::env::set_var("XDG_CONFIG_HOME", v);
+            }
+        }
+        assert_eq!(std::fs::read_dir(home.path()).unwrap().count(), 0);
+        let out = out.unwrap().unwrap();
+        assert!(!out.is_empty());
+        out
+    }
+
+    #[test]
+    fn version_creates_no_files() {
+        let out = assert_no_files_created(CliAction::PrintVersion);
+        assert!(out.starts_with(&format!("sshdb {VERSION}")));
+    }
+
+    #[test]
+    fn config_path_creates_no_files() {
+        let out = assert_no_files_created(CliAction::PrintConfigPath);
+        assert!(out.ends_with("config.toml"));
+    }
+
+    #[test]
+    fn default_config_creates_no_files_and_parses() {
+        let out = assert_no_files_created(CliAction::PrintDefaultConfig);
+        let cfg: Config = toml::from_str(&out).unwrap();
+        assert!(cfg.hosts.is_empty());
+    }
+}
@@ -1,11 +1,21 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
 
-use std::io::Write;
+use std::io::{ErrorKind, Write};
 use std::process::{Command, Stdio};
 
 use anyhow::{anyhow, Context, Result};
 
+/// None of the clipboard programs for this platform is installed, as
+/// opposed to one that ran and failed.
+#[derive(Debug, thiserror::Error)]
+#[error("no clipboard program found (tried {})", .tried.join(", "))]
+pub struct NoClipboard {
+    pub tried: Vec<&'static str>,
+}
+
+/// Copies `text` with the first clipboard program that is installed;
+/// fails with `NoClipboard` when there is none.
 pub fn copy_text(text: &str) -> Result<()> {
     let commands: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
         &[("pbcopy", &[])]
@@ -23,11 +33,23 @@ pub fn copy_text(text: &str) -> Result<()> {
     for (program, args) in commands {
         match copy_with(program, args, text) {
             Ok(()) => return Ok(()),
+            Err(err) if is_not_found(&err) => {}
             Err(err) => last_err = Some(err),
         }
     }
 
-    Err(last_err.unwrap_or_else(|| anyhow!("no clipboard command available")))
+    Err(last_err.unwrap_or_else(|| {
+        NoClipboard {
+            tried: commands.iter().map(|(program, _)| *program).collect(),
+        }
+        .into()
+    }))
+}
+
+fn is_not_found(err: &anyhow::Error) -> bool {
+    err.root_cause()
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|err| err.kind() == ErrorKind::NotFound)
 }
 
 fn copy_with(program: &str, args: &[&str], text: &str) -> Result<()> {
@@ -59,3 +81,18 @@ fn copy_with(program: &str, args: &[&str], text: &str) -> Result<()> {
         Err(anyhow!("{program} failed: {stderr}"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_missing_program_counts_as_no_clipboard() {
+        let err = copy_with("sshdb-no-such-clipboard", &[], "x").unwrap_err();
+        assert!(is_not_found(&err), "{err:#}");
+        if cfg!(unix) {
+            let err = copy_with("false", &[], "x").unwrap_err();
+            assert!(!is_not_found(&err), "{err:#}");
+        }
+    }
+}
@@ -1,80 +1,340 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
 
+use std::cell::RefCell;
 use std::env;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use directories::ProjectDirs;
 
+use crate::config_edit::reconcile;
 use crate::model::Config;
 
+/// Schema version written by this build. Bump it together with a new entry
+/// in `MIGRATIONS`.
+pub const CURRENT_VERSION: u8 = 2;
+
+type Migration = fn(&mut toml::Value) -> Result<()>;
+
+/// `MIGRATIONS[i]` upgrades a document from version `i + 1` to `i + 2`.
+const MIGRATIONS: &[Migration] = &[migrate_v1_to_v2];
+
+/// How `ConfigStore::save` wrote the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SaveOutcome {
+    /// Existing comments and layout were kept (or there was no file yet).
+    Preserved,
+    /// The file could not be reconciled and was re-serialized; carries a
+    /// user-facing warning.
+    Rewritten(String),
+}
+
+/// What `load_or_init` did to bring an older file up to date.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MigrationReport {
+    pub from: u8,
+    pub to: u8,
+    pub backup: PathBuf,
+}
+
+impl MigrationReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "Migrated config from v{} to v{} (backup: {}).",
+            self.from,
+            self.to,
+            self.backup.display()
+        )
+    }
+}
+
 pub struct ConfigStore {
     path: PathBuf,
+    /// Set for `in_memory` stores: the document text, never written to disk.
+    memory: Option<RefCell<String>>,
 }
 
 impl ConfigStore {
-    pub fn new() -> Result<Self> {
-        let path = config_path();
-        if let Some(dir) = path.parent() {
-            fs::create_dir_all(dir)
-                .with_context(|| format!("failed to create config dir {}", dir.display()))?;
-        }
-        Ok(Self { path })
+    /// Resolves the config location only; directories are created lazily on
+    /// the first save so startup never waits on the filesystem.
+    // Not `Default`: the location comes from the environment.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self::at(config_path())
     }
 
-    #[cfg(test)]
     pub fn at(path: PathBuf) -> Self {
-        Self { path }
+        Self { path, memory: None }
+    }
+
+    /// A store that keeps the document in memory with the same load/save
+    /// behaviour, for tests and embedding. Starts out with no file.
+    #[cfg_attr(not(test), allow(dead_code))] // no embedder in the binary itself
+    pub fn in_memory() -> Self {
+        Self {
+            path: PathBuf::from("(in memory)"),
+            memory: Some(RefCell::new(String::new())),
+        }
     }
 
     pub fn path(&self) -> &Path {
         &self.path
     }
 
-    pub fn load_or_init(&self) -> Result<Config> {
+    pub fn is_in_memory(&self) -> bool {
+        self.memory.is_some()
+    }
+
+    /// Checks that saving can work: the directory exists (or can be created)
+    /// and accepts new files, and an existing config can be opened for
+    /// writing. Errors name the path and the io error kind.
+    pub fn check_writable(&self) -> Result<()> {
+        if self.is_in_memory() {
+            return Ok(());
+        }
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir).map_err(|err| io_error("create", dir, &err))?;
+        let probe = dir.join(".sshdb-write-test");
+        fs::File::create(&probe).map_err(|err| io_error("write to", dir, &err))?;
+        let _ = fs::remove_file(&probe);
         if self.path.exists() {
-            let content =
-                fs::read_to_string(&self.path).with_context(|| "failed to read config file")?;
-            let cfg: Config = toml::from_str(&content)
+            fs::OpenOptions::new()
+                .append(true)
+                .open(&self.path)
+                .map_err(|err| io_error("write", &self.path, &err))?;
+        }
+        Ok(())
+    }
+
+    /// Current document text; `None` when nothing has been saved yet.
+    fn read(&self) -> Result<Option<String>> {
+        if let Some(memory) = &self.memory {
+            let text = memory.borrow();
+            return Ok((!text.is_empty()).then(|| text.clone()));
+        }
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        fs::read_to_string(&self.path)
+            .map(Some)
+            .with_context(|| "failed to read config file")
+    }
+
+    /// Replaces the document with `text` verbatim.
+    pub fn write(&self, text: &str) -> Result<()> {
+        if let Some(memory) = &self.memory {
+            *memory.borrow_mut() = text.to_string();
+            return Ok(());
+        }
+        let mut f =
+            fs::File::create(&self.path).map_err(|err| io_error("write", &self.path, &err))?;
+        f.write_all(text.as_bytes())
+            .map_err(|err| io_error("write", &self.path, &err))
+    }
+
+    pub fn load_or_init(&self) -> Result<(Config, Option<MigrationReport>)> {
+        if let Some(content) = self.read()? {
+            let mut doc: toml::Value = toml::from_str(&content)
                 .with_context(|| "failed to parse config; fix or remove the file")?;
-            return Ok(cfg);
+            let from = document_version(&doc)?;
+            if from == CURRENT_VERSION {
+                let cfg: Config = doc
+                    .try_into()
+                    .with_context(|| "failed to parse config; fix or remove the file")?;
+                return Ok((cfg, None));
+            }
+
+            migrate(&mut doc, from)?;
+            let cfg: Config = doc
+                .try_into()
+                .with_context(|| format!("failed to parse config migrated from v{from}"))?;
+            let backup = self.path.with_extension(format!("v{from}.toml.bak"));
+            if !self.is_in_memory() {
+                fs::write(&backup, &content)
+                    .with_context(|| format!("failed to write backup {}", backup.display()))?;
+            }
+            self.save(&cfg)?;
+            return Ok((
+                cfg,
+                Some(MigrationReport {
+                    from,
+                    to: CURRENT_VERSION,
+                    backup,
+                }),
+            ));
         }
 
         let cfg = Config::default();
         self.save(&cfg)?;
-        Ok(cfg)
+        Ok((cfg, None))
     }
 
-    pub fn save(&self, config: &Config) -> Result<()> {
-        if let Some(dir) = self.path.parent() {
-            fs::create_dir_all(dir)
-                .with_context(|| format!("failed to create config dir {}", dir.display()))?;
+    /// Reads the config without creating, migrating or rewriting the file,
+    /// for non-interactive commands. Older versions are upgraded in memory.
+    pub fn load_read_only(&self) -> Result<Config> {
+        let Some(content) = self.read()? else {
+            bail!("no config at {}", self.path.display());
+        };
+        parse_document(&content).map(|(config, _)| config)
+    }
+
+    /// Writes `config`, editing the existing file in place so comments and
+    /// formatting of untouched hosts survive.
+    pub fn save(&self, config: &Config) -> Result<SaveOutcome> {
+        if !self.is_in_memory() {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir).map_err(|err| io_error("create", dir, &err))?;
+            }
         }
-        if self.path.exists() {
+        let existing = self.read().ok().flatten();
+        if existing.is_some() && !self.is_in_memory() {
             let backup = self.path.with_extension("toml.bak");
             fs::copy(&self.path, &backup).ok();
         }
 
-        let toml =
-            toml::to_string_pretty(config).with_context(|| "failed to serialize config to toml")?;
-        let mut f = fs::File::create(&self.path)
-            .with_context(|| format!("failed to open config {}", self.path.display()))?;
-        f.write_all(toml.as_bytes())
-            .with_context(|| "failed to write config")?;
-        Ok(())
+        let (toml, outcome) = match existing.as_deref().map(|text| reconcile(text, config)) {
+            Some(Ok(text)) => (text, SaveOutcome::Preserved),
+            Some(Err(err)) => (
+                serialize(config)?,
+                SaveOutcome::Rewritten(format!(
+                    "Config rewritten without its formatting ({err:#})."
+                )),
+            ),
+            None => (serialize(config)?, SaveOutcome::Preserved),
+        };
+        self.write(&toml)?;
+        Ok(outcome)
+    }
+
+    /// The config as pretty-printed JSON, for tools that don't speak TOML.
+    pub fn export_json(config: &Config) -> Result<String> {
+        serde_json::to_string_pretty(config).with_context(|| "failed to serialize config to json")
+    }
+
+    /// Reads a config written by `export_json`. Only the current schema is
+    /// accepted: older TOML files are migrated, exported JSON never needs to.
+    pub fn import_json(text: &str) -> Result<Config> {
+        let config: Config =
+            serde_json::from_str(text).with_context(|| "failed to parse the json config")?;
+        if config.version != CURRENT_VERSION {
+            bail!(
+                "json config has schema version {}, this sshdb reads {CURRENT_VERSION}",
+                config.version
+            );
+        }
+        Ok(config)
     }
 }
 
-fn config_path() -> PathBuf {
+/// `cannot <action> <path>: <kind>`, the form every filesystem error in
+/// here takes, so messages are actionable on one status line.
+fn io_error(action: &str, path: &Path, err: &std::io::Error) -> anyhow::Error {
+    anyhow::anyhow!("cannot {action} {}: {}", path.display(), err.kind())
+}
+
+/// Parses config text of any supported version, migrating in memory.
+/// Returns the config and the version the text was written in.
+pub fn parse_document(content: &str) -> Result<(Config, u8)> {
+    let mut doc: toml::Value = toml::from_str(content)
+        .with_context(|| "failed to parse config; fix or remove the file")?;
+    let from = document_version(&doc)?;
+    if from != CURRENT_VERSION {
+        migrate(&mut doc, from)?;
+    }
+    let config = doc
+        .try_into()
+        .with_context(|| "failed to parse config; fix or remove the file")?;
+    Ok((config, from))
+}
+
+fn serialize(config: &Config) -> Result<String> {
+    toml::to_string_pretty(config).with_context(|| "failed to serialize config to toml")
+}
+
+/// Files written before versioning was enforced may lack the field; they are v1.
+fn document_version(doc: &toml::Value) -> Result<u8> {
+    let version = match doc.get("version") {
+        None => 1,
+        Some(value) => value
+            .as_integer()
+            .and_then(|v| u8::try_from(v).ok())
+            .with_context(|| "config `version` must be a small positive integer")?,
+    };
+    if version == 0 {
+        bail!("config version 0 is invalid");
+    }
+    if version > CURRENT_VERSION {
+        bail!(
+            "config version {version} is newer than this sshdb supports (v{CURRENT_VERSION}); \
+             upgrade sshdb instead of editing the file"
+        );
+    }
+    Ok(version)
+}
+
+fn migrate(doc: &mut toml::Value, from: u8) -> Result<()> {
+    for (idx, step) in MIGRATIONS.iter().enumerate().skip(usize::from(from) - 1) {
+        step(doc).with_context(|| format!("migration v{} -> v{} failed", idx + 1, idx + 2))?;
+    }
+    if let Some(table) = doc.as_table_mut() {
+        table.insert(
+            "version".into(),
+            toml::Value::Integer(i64::from(CURRENT_VERSION)),
+        );
+    }
+    Ok(())
+}
+
+/// v2 stores identity files as a `key_paths` list instead of `key_path`.
+fn migrate_v1_to_v2(doc: &mut toml::Value) -> Result<()> {
+    let Some(hosts) = doc.get_mut("hosts").and_then(|h| h.as_array_mut()) else {
+        return Ok(());
+    };
+    for host in hosts.iter_mut().filter_map(|h| h.as_table_mut()) {
+        let Some(legacy) = host.remove("key_path") else {
+            continue;
+        };
+        if host.contains_key("key_paths") {
+            continue;
+        }
+        let paths = match legacy {
+            toml::Value::Array(items) => items,
+            other => vec![other],
+        };
+        host.insert("key_paths".into(), toml::Value::Array(paths));
+    }
+    Ok(())
+}
+
+/// Environment variable that overrides the config location.
+pub const CONFIG_ENV: &str = "SSHDB_CONFIG";
+
+pub fn config_path() -> PathBuf {
+    if let Some(path) = env::var_os(CONFIG_ENV).filter(|p| !p.is_empty()) {
+        // Quoted in a shell profile or set by a service manager, the
+        // tilde is still there.
+        return match path.to_str() {
+            Some(text) => PathBuf::from(crate::ssh::expand_tilde(text)),
+            None => PathBuf::from(path),
+        };
+    }
     if let Some(proj) = ProjectDirs::from("", "", "sshdb") {
         return proj.config_dir().join("config.toml");
     }
     dirs_fallback()
 }
 
+/// Writable places to continue from when the configured one is read-only,
+/// most conventional first.
+pub fn fallback_paths() -> Vec<PathBuf> {
+    let mut paths = vec![dirs_fallback()];
+    paths.push(env::temp_dir().join("sshdb").join("config.toml"));
+    paths
+}
+
 fn dirs_fallback() -> PathBuf {
     env::var("HOME")
         .map(PathBuf::from)
@@ -92,11 +352,196 @@ mod tests {
     fn saves_and_loads_config() {
         let dir = tempdir().unwrap();
         let path = dir.path().join("config.toml");
-        let store = ConfigStore { path };
+        let store = ConfigStore::at(path);
         let cfg = Config::sample();
         store.save(&cfg).unwrap();
-        let loaded = store.load_or_init().unwrap();
+        let (loaded, migration) = store.load_or_init().unwrap();
         assert_eq!(loaded.hosts.len(), cfg.hosts.len());
         assert_eq!(loaded.version, cfg.version);
+        assert!(migration.is_none());
+    }
+
+    #[test]
+    fn save_keeps_comments_and_falls_back_when_needed() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let store = ConfigStore::at(path.clone());
+        let original = "# my hosts\nversion = 2\n\n[[hosts]]\nname = \"a\"  # keep\nhost = \"a\"\n";
+        fs::write(&path, original).unwrap();
+        let (mut cfg, _) = store.load_or_init().unwrap();
+        cfg.hosts[0].port = Some(2222);
+        assert_eq!(store.save(&cfg).unwrap(), SaveOutcome::Preserved);
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.starts_with("# my hosts\n"));
+        assert!(saved.contains("name = \"a\"  # keep\n"));
+        assert!(saved.contains("port = 2222"));
+
+        fs::write(
+            &path,
+            "version = 2\nhosts = [{ name = \"a\", host = \"a\" }]\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            store.save(&cfg).unwrap(),
+            SaveOutcome::Rewritten(_)
+        ));
+        assert_eq!(store.load_or_init().unwrap().0, cfg);
+    }
+
+    const V1_FIXTURE: &str = r#"
+version = 1
+default_key = "~/.ssh/id_ed25519"
+
+[[hosts]]
+name = "legacy"
+host = "10.0.0.1"
+key_path = "~/.ssh/legacy"
+
+[[hosts]]
+name = "plain"
+host = "10.0.0.2"
+"#;
+
+    #[test]
+    fn migrates_v1_fixture_with_backup() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, V1_FIXTURE).unwrap();
+        let store = ConfigStore::at(path.clone());
+
+        let (cfg, report) = store.load_or_init().unwrap();
+        let report = report.expect("migration ran");
+        assert_eq!((report.from, report.to), (1, CURRENT_VERSION));
+        assert_eq!(cfg.version, CURRENT_VERSION);
+        assert_eq!(cfg.hosts[0].key_paths, vec!["~/.ssh/legacy".to_string()]);
+        assert!(cfg.hosts[1].key_paths.is_empty());
+
+        assert_eq!(fs::read_to_string(&report.backup).unwrap(), V1_FIXTURE);
+        assert!(report.backup.ends_with("config.v1.toml.bak"));
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("version = 2"));
+        assert!(!saved.contains("key_path ="));
+
+        // Second load is a no-op.
+        assert!(store.load_or_init().unwrap().1.is_none());
+    }
+
+    #[test]
+    fn read_only_load_never_writes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let store = ConfigStore::at(path.clone());
+        assert!(store.load_read_only().is_err());
+        assert!(!path.exists());
+
+        fs::write(&path, V1_FIXTURE).unwrap();
+        let cfg = store.load_read_only().unwrap();
+        assert_eq!(cfg.hosts[0].key_paths, vec!["~/.ssh/legacy".to_string()]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), V1_FIXTURE);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn unwritable_location_names_path_and_error_kind() {
+        let dir = tempdir().unwrap();
+        let blocker = dir.path().join("blocker");
+        fs::write(&blocker, "not a directory").unwrap();
+        let store = ConfigStore::at(blocker.join("config.toml"));
+        let err = store.check_writable().unwrap_err().to_string();
+        assert!(err.starts_with("cannot create "), "{err}");
+        assert!(err.contains(&blocker.display().to_string()), "{err}");
+        let err = store.save(&Config::default()).unwrap_err().to_string();
+        assert!(err.contains(&blocker.display().to_string()), "{err}");
+
+        assert!(ConfigStore::at(dir.path().join("config.toml"))
+            .check_writable()
+            .is_ok());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_directory_is_detected() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(locked.join("config.toml"), "version = 2\n").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+        // Permission bits don't bind root; nothing to simulate then.
+        let enforced = fs::write(locked.join("probe"), "").is_err();
+        let result = ConfigStore::at(locked.join("config.toml")).check_writable();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        if enforced {
+            let err = result.unwrap_err().to_string();
+            assert!(err.contains("permission denied"), "{err}");
+            assert!(err.contains(&locked.display().to_string()), "{err}");
+        }
+    }
+
+    #[test]
+    fn treats_missing_version_as_v1() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "[[hosts]]\nname = \"a\"\nhost = \"a\"\n").unwrap();
+        let store = ConfigStore::at(path);
+        let (cfg, report) = store.load_or_init().unwrap();
+        assert_eq!(report.unwrap().from, 1);
+        assert_eq!(cfg.hosts.len(), 1);
+    }
+
+    #[test]
+    fn loads_current_version_untouched() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let content = "version = 2\n\n[[hosts]]\nname = \"a\"\nhost = \"a\"\nkey_paths = [\"k\"]\n";
+        fs::write(&path, content).unwrap();
+        let store = ConfigStore::at(path.clone());
+        let (cfg, report) = store.load_or_init().unwrap();
+        assert!(report.is_none());
+        assert_eq!(cfg.hosts[0].key_paths, vec!["k".to_string()]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn refuses_future_versions_without_touching_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "version = 99\n").unwrap();
+        let store = ConfigStore::at(path.clone());
+        let err = store.load_or_init().unwrap_err();
+        assert!(err.to_string().contains("newer than this sshdb supports"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "version = 99\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn in_memory_store_round_trips_without_disk() {
+        let store = ConfigStore::in_memory();
+        let (cfg, _) = store.load_or_init().unwrap();
+        assert!(cfg.hosts.is_empty());
+
+        let sample = Config::sample();
+        store.save(&sample).unwrap();
+        let (loaded, migration) = store.load_or_init().unwrap();
+        assert_eq!(loaded, sample);
+        assert!(migration.is_none());
+        assert!(!store.path().exists());
+    }
+
+    #[test]
+    fn json_round_trips_and_checks_the_version() {
+        let mut sample = Config::sample();
+        sample.hosts[0].last_connected = Some(1_700_000_000);
+        sample.hosts[0].connect_count = 3;
+        let json = ConfigStore::export_json(&sample).unwrap();
+        assert!(json.contains("\"name\": \"prod-web\""), "{json}");
+        assert_eq!(ConfigStore::import_json(&json).unwrap(), sample);
+
+        let old = json.replacen("\"version\": 2", "\"version\": 1", 1);
+        let err = ConfigStore::import_json(&old).unwrap_err();
+        assert!(err.to_string().contains("schema version 1"), "{err}");
+        assert!(ConfigStore::import_json("[1, 2]").is_err());
     }
 }
@@ -0,0 +1,364 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Applies a `Config` onto the existing config.toml text so that comments,
+//! key order and formatting of everything that did not change survive a save.
+
+use std::collections::HashSet;
+
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table, Value};
+
+use crate::model::{Config, Host, Profile};
+
+/// Returns the updated document text, or an error describing why the file
+/// can't be reconciled (the caller then falls back to a full rewrite).
+pub fn reconcile(existing: &str, config: &Config) -> Result<String> {
+    let mut doc: DocumentMut = existing
+        .parse()
+        .with_context(|| "existing config is not valid TOML")?;
+    let fresh: DocumentMut = toml::to_string_pretty(config)
+        .with_context(|| "failed to serialize config to toml")?
+        .parse()
+        .with_context(|| "failed to re-read serialized config")?;
+
+    let mut fresh_root = fresh.as_table().clone();
+    let fresh_hosts = match fresh_root.remove("hosts") {
+        Some(Item::ArrayOfTables(hosts)) => hosts,
+        Some(_) => bail!("serialized hosts are not an array of tables"),
+        None => ArrayOfTables::new(),
+    };
+
+    let fresh_profiles = match fresh_root.remove("profiles") {
+        Some(Item::ArrayOfTables(profiles)) => profiles,
+        Some(_) => bail!("serialized profiles are not an array of tables"),
+        None => ArrayOfTables::new(),
+    };
+
+    let old_hosts = match doc.remove("hosts") {
+        Some(Item::ArrayOfTables(hosts)) => hosts,
+        Some(_) => bail!("`hosts` is not written as [[hosts]] tables"),
+        None => ArrayOfTables::new(),
+    };
+    let old_profiles = match doc.remove("profiles") {
+        Some(Item::ArrayOfTables(profiles)) => profiles,
+        Some(_) => bail!("`profiles` is not written as [[profiles]] tables"),
+        None => ArrayOfTables::new(),
+    };
+    update_table::<Config>(doc.as_table_mut(), &fresh_root);
+    let hosts = reconcile_hosts(old_hosts, fresh_hosts)?;
+    if !hosts.is_empty() {
+        doc.insert("hosts", Item::ArrayOfTables(hosts));
+    }
+    let profiles = reconcile_profiles(old_profiles, fresh_profiles)?;
+    if !profiles.is_empty() {
+        doc.insert("profiles", Item::ArrayOfTables(profiles));
+    }
+    Ok(doc.to_string())
+}
+
+/// Pairs old and new profile tables by name and reconciles the hosts of
+/// each like the top-level ones.
+fn reconcile_profiles(old: ArrayOfTables, fresh: ArrayOfTables) -> Result<ArrayOfTables> {
+    let mut old: Vec<Option<Table>> = old.into_iter().map(Some).collect();
+    let mut out = ArrayOfTables::new();
+    for mut new in fresh {
+        let name = new.get("name").and_then(Item::as_str).map(String::from);
+        let mut table = old
+            .iter()
+            .position(|t| {
+                t.as_ref()
+                    .and_then(|t| t.get("name"))
+                    .and_then(Item::as_str)
+                    == name.as_deref()
+            })
+            .and_then(|pos| old[pos].take())
+            .unwrap_or_default();
+        let fresh_hosts = match new.remove("hosts") {
+            Some(Item::ArrayOfTables(hosts)) => hosts,
+            Some(_) => bail!("serialized profile hosts are not an array of tables"),
+            None => ArrayOfTables::new(),
+        };
+        let old_hosts = match table.remove("hosts") {
+            Some(Item::ArrayOfTables(hosts)) => hosts,
+            Some(_) => bail!("hosts of a profile are not written as [[profiles.hosts]] tables"),
+            None => ArrayOfTables::new(),
+        };
+        update_table::<Profile>(&mut table, &new);
+        let hosts = reconcile_hosts(old_hosts, fresh_hosts)?;
+        if !hosts.is_empty() {
+            table.insert("hosts", Item::ArrayOfTables(hosts));
+        }
+        out.push(table);
+    }
+    Ok(out)
+}
+
+/// Pairs old and new host tables by canonical id (`group/name`). An unmatched
+/// host that sits where an unmatched old table sat is treated as a rename, so
+/// its comments survive.
+fn reconcile_hosts(old: ArrayOfTables, fresh: ArrayOfTables) -> Result<ArrayOfTables> {
+    let mut old: Vec<Option<Table>> = old.into_iter().map(Some).collect();
+    let mut seen = HashSet::new();
+    for id in old.iter().flatten().filter_map(host_id) {
+        if !seen.insert(id.clone()) {
+            bail!("duplicate host '{id}' in config");
+        }
+    }
+
+    let fresh: Vec<Table> = fresh.into_iter().collect();
+    let mut matched: Vec<Option<Table>> = fresh
+        .iter()
+        .map(|table| {
+            let id = host_id(table)?;
+            let pos = old
+                .iter()
+                .position(|t| t.as_ref().and_then(host_id).as_ref() == Some(&id))?;
+            old[pos].take()
+        })
+        .collect();
+    let fresh_ids: HashSet<String> = fresh.iter().filter_map(host_id).collect();
+    for (slot, old) in matched.iter_mut().zip(old.iter_mut()) {
+        let renamed = old
+            .as_ref()
+            .and_then(host_id)
+            .is_some_and(|id| !fresh_ids.contains(&id));
+        if slot.is_none() && renamed {
+            *slot = old.take();
+        }
+    }
+
+    let mut out = ArrayOfTables::new();
+    for (slot, new) in matched.into_iter().zip(&fresh) {
+        let table = match slot {
+            Some(mut table) => {
+                update_table::<Host>(&mut table, new);
+                table
+            }
+            None => {
+                let mut table = Table::new();
+                update_table::<Host>(&mut table, new);
+                table
+            }
+        };
+        out.push(table);
+    }
+    // toml_edit prints tables by the position they were parsed at, so hand
+    // the old positions out again in the new order or a reordered list
+    // would be written back as it was.
+    let mut positions: Vec<usize> = out.iter().filter_map(Table::position).collect();
+    positions.sort_unstable();
+    let mut positions = positions.into_iter();
+    for table in out.iter_mut().filter(|t| t.position().is_some()) {
+        if let Some(pos) = positions.next() {
+            table.set_position(pos);
+        }
+    }
+    Ok(out)
+}
+
+/// Mirrors `Host::id`.
+fn host_id(table: &Table) -> Option<String> {
+    let name = table.get("name").and_then(Item::as_str)?;
+    Some(match table.get("group").and_then(Item::as_str) {
+        Some(group) => format!("{group}/{name}"),
+        None => name.to_string(),
+    })
+}
+
+/// Updates `target` in place to hold exactly the values of `fresh`, keeping
+/// the decoration (comments, spacing) of keys whose value did not change.
+/// Keys the file omits stay omitted while `T` would decode to the same thing.
+fn update_table<T: DeserializeOwned + PartialEq>(target: &mut Table, fresh: &Table) {
+    let stale: Vec<String> = target
+        .iter()
+        .filter(|(key, _)| !fresh.contains_key(key))
+        .map(|(key, _)| key.to_string())
+        .collect();
+    for key in stale {
+        target.remove(&key);
+    }
+    let mut inserted = Vec::new();
+    for (key, new) in fresh.iter() {
+        match target.get_mut(key) {
+            Some(Item::Value(old)) => {
+                let Some(new) = new.as_value() else {
+                    target.insert(key, new.clone());
+                    continue;
+                };
+                if !same_value(old, new) {
+                    let decor = old.decor().clone();
+                    *old = new.clone();
+                    *old.decor_mut() = decor;
+                }
+            }
+            Some(old) => *old = new.clone(),
+            None => {
+                target.insert(key, new.clone());
+                inserted.push(key.to_string());
+            }
+        }
+    }
+    for key in inserted {
+        let with = decode::<T>(target);
+        let Some(item) = target.remove(&key) else {
+            continue;
+        };
+        if with.is_none() || decode::<T>(target) != with {
+            target.insert(&key, item);
+        }
+    }
+}
+
+fn decode<T: DeserializeOwned>(table: &Table) -> Option<T> {
+    toml::from_str(&DocumentMut::from(table.clone()).to_string()).ok()
+}
+
+/// Semantic comparison, so `'x'` and `"x"` or differently wrapped arrays
+/// don't count as edits.
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(x), Value::String(y)) => x.value() == y.value(),
+        (Value::Integer(x), Value::Integer(y)) => x.value() == y.value(),
+        (Value::Float(x), Value::Float(y)) => x.value() == y.value(),
+        (Value::Boolean(x), Value::Boolean(y)) => x.value() == y.value(),
+        (Value::Array(x), Value::Array(y)) => {
+            x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| same_value(a, b))
+        }
+        (Value::InlineTable(x), Value::InlineTable(y)) => {
+            x.len() == y.len()
+                && x.iter()
+                    .all(|(key, a)| y.get(key).is_some_and(|b| same_value(a, b)))
+        }
+        _ => a.to_string().trim() == b.to_string().trim(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = r#"# sshdb hosts — hand maintained
+version = 2
+default_key   = "~/.ssh/id_ed25519"   # shared key
+warn_local_addresses = true
+
+# Production web tier.
+[[hosts]]
+name = "web"
+host = "10.0.0.1"
+user = 'deploy'
+options = [ "-o", "ServerAliveInterval=30" ]  # keepalive for flaky LB
+tags = ["prod"]
+
+# Legacy box: needs old ciphers, see ticket.
+[[hosts]]
+name="legacy"
+host="10.0.0.2"
+options = ["-oCiphers=aes128-cbc"]
+tags = []
+
+[[hosts]]
+name = "db"    # primary
+host = "10.0.0.3"
+port = 2222
+tags = ["prod", "db"]
+"#;
+
+    fn load(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn unchanged_config_round_trips_byte_for_byte() {
+        let cfg = load(FIXTURE);
+        assert_eq!(reconcile(FIXTURE, &cfg).unwrap(), FIXTURE);
+    }
+
+    #[test]
+    fn editing_one_host_only_touches_that_line() {
+        let mut cfg = load(FIXTURE);
+        cfg.hosts[2].port = Some(2200);
+        let out = reconcile(FIXTURE, &cfg).unwrap();
+        assert_eq!(out, FIXTURE.replace("port = 2222", "port = 2200"));
+    }
+
+    #[test]
+    fn adds_and_removes_hosts_without_disturbing_others() {
+        let mut cfg = load(FIXTURE);
+        cfg.hosts.remove(0);
+        cfg.hosts.push(Host {
+            name: "new".into(),
+            address: "10.0.0.9".into(),
+            ..Default::default()
+        });
+        let out = reconcile(FIXTURE, &cfg).unwrap();
+        assert!(!out.contains("Production web tier"));
+        assert!(out.contains("# Legacy box: needs old ciphers, see ticket."));
+        assert!(out.contains("name = \"db\"    # primary"));
+        assert!(out.ends_with("\n[[hosts]]\nname = \"new\"\nhost = \"10.0.0.9\"\n"));
+        assert_eq!(load(&out).hosts.len(), 3);
+    }
+
+    #[test]
+    fn renamed_host_keeps_its_comments() {
+        let mut cfg = load(FIXTURE);
+        cfg.hosts[1].name = "legacy-old".into();
+        let out = reconcile(FIXTURE, &cfg).unwrap();
+        assert!(out.contains(
+            "# Legacy box: needs old ciphers, see ticket.\n[[hosts]]\nname=\"legacy-old\""
+        ));
+    }
+
+    #[test]
+    fn same_name_in_different_groups_is_not_a_duplicate() {
+        let text = "version = 2\n\n[[hosts]]\nname = \"db1\"\ngroup = \"a\"\nhost = \"1\"\n\n# team b\n[[hosts]]\nname = \"db1\"\ngroup = \"b\"\nhost = \"2\"\n";
+        let mut cfg = load(text);
+        cfg.hosts[1].address = "3".into();
+        let out = reconcile(text, &cfg).unwrap();
+        assert_eq!(out, text.replace("host = \"2\"", "host = \"3\""));
+    }
+
+    #[test]
+    fn profile_hosts_keep_their_comments() {
+        let text = "version = 2\n\n[[hosts]]\nname = \"a\"\nhost = \"1\"\n\n[[profiles]]\nname = \"work\"\n\n# the build box\n[[profiles.hosts]]\nname = \"ci\"\nhost = \"2\"\n";
+        let mut cfg = load(text);
+        assert_eq!(reconcile(text, &cfg).unwrap(), text);
+        cfg.profiles[0].hosts[0].address = "3".into();
+        let out = reconcile(text, &cfg).unwrap();
+        assert_eq!(out, text.replace("host = \"2\"", "host = \"3\""));
+
+        cfg.profiles.push(Profile {
+            name: "home".into(),
+            hosts: vec![Host {
+                name: "nas".into(),
+                address: "4".into(),
+                ..Default::default()
+            }],
+        });
+        let out = reconcile(text, &cfg).unwrap();
+        assert!(out.contains("# the build box\n[[profiles.hosts]]"), "{out}");
+        assert_eq!(load(&out).profiles, cfg.profiles);
+    }
+
+    #[test]
+    fn reordered_hosts_are_written_in_the_new_order() {
+        let text = "version = 2\n\n# first\n[[hosts]]\nname = \"a\"\nhost = \"1\"\n\n# second\n[[hosts]]\nname = \"b\"\nhost = \"2\"\n";
+        let mut cfg = load(text);
+        cfg.hosts.swap(0, 1);
+        let out = reconcile(text, &cfg).unwrap();
+        assert_eq!(
+            out,
+            "version = 2\n\n# second\n[[hosts]]\nname = \"b\"\nhost = \"2\"\n\n# first\n[[hosts]]\nname = \"a\"\nhost = \"1\"\n"
+        );
+    }
+
+    #[test]
+    fn refuses_inline_host_arrays_and_duplicates() {
+        let inline = "version = 2\nhosts = [{ name = \"a\", host = \"a\" }]\n";
+        assert!(reconcile(inline, &load(inline)).is_err());
+        let dup = "[[hosts]]\nname = \"a\"\nhost = \"a\"\n[[hosts]]\nname = \"a\"\nhost = \"b\"\n";
+        assert!(reconcile(dup, &Config::default()).is_err());
+    }
+}
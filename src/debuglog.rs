@@ -0,0 +1,522 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Opt-in troubleshooting log. `SSHDB_LOG=debug` (or `info`, `warn`,
+//! `error`) or `--log-file PATH` appends one timestamped, logfmt-style line
+//! per event: mode changes and actions, config loads and saves, the argv of
+//! every spawned command, background tasks and errors.
+//!
+//! The TUI owns the terminal, so nothing here writes to stdout or stderr:
+//! a line that can't be written is dropped. With `SSHDB_LOG_REDACT=1` or
+//! `--log-redact`, commands keep their options but lose destinations, users,
+//! key paths and remote commands, and IPv4 addresses in messages are masked.
+
+use std::fmt::{self, Display};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::privacy::Masker;
+
+/// Where `SSHDB_LOG` writes without `--log-file`: next to the config.
+pub const LOG_FILE_NAME: &str = "sshdb.log";
+
+/// Lines of the log `sshdb doctor --collect` includes.
+const COLLECT_LINES: usize = 200;
+
+/// Stands in for whatever redaction removed.
+const REDACTED: &str = "<redacted>";
+
+/// ssh options that take a value, from ssh(1).
+const SSH_VALUE_OPTIONS: &str = "BbcDEeFIiJLlmOoPpQRSWw";
+
+/// ssh options whose values say nothing about the destination or the user.
+const SSH_HARMLESS_VALUES: &str = "ceOpQm";
+
+/// `-o` settings that name a destination, user, file or command.
+const SSH_SENSITIVE_SETTINGS: &[&str] = &[
+    "certificatefile",
+    "hostname",
+    "identityfile",
+    "localcommand",
+    "proxycommand",
+    "proxyjump",
+    "remotecommand",
+    "setenv",
+    "user",
+    "userknownhostsfile",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    /// An `SSHDB_LOG` value; `None` for `off`.
+    pub fn parse(value: &str) -> Result<Option<Self>> {
+        Ok(Some(match value.trim().to_ascii_lowercase().as_str() {
+            "" | "off" | "0" => return Ok(None),
+            "error" => Level::Error,
+            "warn" => Level::Warn,
+            "info" => Level::Info,
+            "debug" | "1" => Level::Debug,
+            other => bail!("SSHDB_LOG must be off, error, warn, info or debug, not '{other}'"),
+        }))
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+/// What to log, where, and whether to redact.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogSettings {
+    pub path: PathBuf,
+    pub level: Level,
+    pub redact: bool,
+}
+
+impl LogSettings {
+    /// Settings from `SSHDB_LOG` and `SSHDB_LOG_REDACT` plus the command
+    /// line; `None` when logging is off.
+    pub fn from_env(
+        log_file: Option<&Path>,
+        redact: bool,
+        config_path: &Path,
+    ) -> Result<Option<Self>> {
+        Self::resolve(
+            std::env::var("SSHDB_LOG").ok().as_deref(),
+            std::env::var("SSHDB_LOG_REDACT").ok().as_deref(),
+            log_file,
+            redact,
+            config_path,
+        )
+    }
+
+    /// `--log-file` alone logs everything; `SSHDB_LOG` alone logs to
+    /// `default_path`; `SSHDB_LOG=off` wins over both.
+    pub fn resolve(
+        env_level: Option<&str>,
+        env_redact: Option<&str>,
+        log_file: Option<&Path>,
+        redact: bool,
+        config_path: &Path,
+    ) -> Result<Option<Self>> {
+        let level = match (env_level, log_file) {
+            (Some(value), _) => Level::parse(value)?,
+            (None, Some(_)) => Some(Level::Debug),
+            (None, None) => None,
+        };
+        let Some(level) = level else {
+            return Ok(None);
+        };
+        let redact = redact
+            || env_redact.is_some_and(|value| {
+                matches!(
+                    value.trim().to_ascii_lowercase().as_str(),
+                    "1" | "true" | "yes" | "on"
+                )
+            });
+        Ok(Some(Self {
+            path: log_file.map_or_else(|| default_path(config_path), Path::to_path_buf),
+            level,
+            redact,
+        }))
+    }
+}
+
+/// `sshdb.log` in the config's directory.
+pub fn default_path(config_path: &Path) -> PathBuf {
+    config_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(LOG_FILE_NAME)
+}
+
+struct Logger {
+    file: File,
+    level: Level,
+    redact: bool,
+}
+
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+
+/// Opens (appending to) the log file and starts logging. Errors come back
+/// to the caller, which reports them before the TUI takes the terminal.
+pub fn init(settings: &LogSettings) -> Result<()> {
+    if let Some(dir) = settings.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("cannot create log directory {}", dir.display()))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&settings.path)
+        .with_context(|| format!("cannot open log file {}", settings.path.display()))?;
+    *lock() = Some(Logger {
+        file,
+        level: settings.level,
+        redact: settings.redact,
+    });
+    info(
+        "log",
+        "logging started",
+        &[
+            ("version", &crate::cli::version_string()),
+            ("level", &settings.level.label()),
+            ("redact", &settings.redact),
+            ("pid", &std::process::id()),
+        ],
+    );
+    Ok(())
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<Logger>> {
+    LOGGER.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Whether an event at `level` would be written.
+pub fn enabled(level: Level) -> bool {
+    lock().as_ref().is_some_and(|logger| level <= logger.level)
+}
+
+/// Writes one event. `fields` are `key=value` pairs after the message.
+pub fn event(level: Level, target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    let mut guard = lock();
+    let Some(logger) = guard.as_mut().filter(|logger| level <= logger.level) else {
+        return;
+    };
+    let fields: Vec<(&str, String)> = fields
+        .iter()
+        .map(|(key, value)| (*key, value.to_string()))
+        .collect();
+    let mut line = format_line(SystemTime::now(), level, target, message, &fields);
+    if logger.redact {
+        line = Masker::default().mask(&line).into_owned();
+    }
+    line.push('\n');
+    // Dropped on failure: there is nowhere else to report it.
+    let _ = logger.file.write_all(line.as_bytes());
+}
+
+pub fn error(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    event(Level::Error, target, message, fields);
+}
+
+pub fn warn(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    event(Level::Warn, target, message, fields);
+}
+
+pub fn info(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    event(Level::Info, target, message, fields);
+}
+
+pub fn debug(target: &str, message: &str, fields: &[(&str, &dyn Display)]) {
+    event(Level::Debug, target, message, fields);
+}
+
+/// Logs the argv of a command about to be spawned, redacted when asked.
+pub fn spawn(target: &str, argv: &[String]) {
+    if !enabled(Level::Debug) {
+        return;
+    }
+    let redact = lock().as_ref().is_some_and(|logger| logger.redact);
+    let shown = if redact {
+        redact_argv(argv)
+    } else {
+        argv.to_vec()
+    };
+    debug(target, "spawn", &[("argv", &shown.join(" "))]);
+}
+
+/// `2026-10-16T09:12:03.481Z INFO app: mode changed from=Normal to=Form`;
+/// values with spaces, quotes or `=` are quoted.
+pub fn format_line(
+    at: SystemTime,
+    level: Level,
+    target: &str,
+    message: &str,
+    fields: &[(&str, String)],
+) -> String {
+    let since = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let stamp = DateTime::<Utc>::from_timestamp(since.as_secs() as i64, since.subsec_nanos())
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ");
+    let mut line = format!("{stamp} {} {target}: {}", level.label(), one_line(message));
+    for (key, value) in fields {
+        line.push_str(&format!(" {key}={}", Quoted(value)));
+    }
+    line
+}
+
+fn one_line(text: &str) -> String {
+    text.replace(['\n', '\r'], " ")
+}
+
+struct Quoted<'a>(&'a str);
+
+impl Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plain = !self.0.is_empty()
+            && !self
+                .0
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '"' | '=' | '\\'));
+        if plain {
+            f.write_str(self.0)
+        } else {
+            write!(f, "{:?}", self.0)
+        }
+    }
+}
+
+/// Keeps the program and the options but drops what identifies the target.
+/// For ssh that is the destination, the remote command and the values of
+/// options naming hosts, users or files; other programs keep only their
+/// flags.
+pub fn redact_argv(argv: &[String]) -> Vec<String> {
+    let Some((program, args)) = argv.split_first() else {
+        return Vec::new();
+    };
+    let mut out = vec![program.clone()];
+    let is_ssh = Path::new(program)
+        .file_name()
+        .is_some_and(|name| name == "ssh");
+    if !is_ssh {
+        out.extend(args.iter().map(|arg| {
+            if arg.starts_with('-') {
+                arg.clone()
+            } else {
+                REDACTED.to_string()
+            }
+        }));
+        return out;
+    }
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(flags) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+            // The destination; whatever follows is the remote command.
+            out.push(REDACTED.to_string());
+            if args.next().is_some() {
+                out.push(REDACTED.to_string());
+            }
+            break;
+        };
+        let Some(at) = flags.find(|c| SSH_VALUE_OPTIONS.contains(c)) else {
+            out.push(arg.clone());
+            continue;
+        };
+        let option = flags[at..].chars().next().unwrap_or_default();
+        let inline = &flags[at + option.len_utf8()..];
+        let value = if inline.is_empty() {
+            args.next().cloned()
+        } else {
+            Some(inline.to_string())
+        };
+        let value = value.map(|value| redact_option(option, &value));
+        out.push(format!("-{}", &flags[..at + option.len_utf8()]));
+        out.extend(value);
+    }
+    out
+}
+
+fn redact_option(option: char, value: &str) -> String {
+    if option == 'o' {
+        let key = value.split(['=', ' ']).next().unwrap_or_default();
+        if SSH_SENSITIVE_SETTINGS.contains(&key.to_ascii_lowercase().as_str()) {
+            return format!("{key}={REDACTED}");
+        }
+        return value.to_string();
+    }
+    if SSH_HARMLESS_VALUES.contains(option) {
+        value.to_string()
+    } else {
+        REDACTED.to_string()
+    }
+}
+
+/// What `sshdb doctor` prints: version, platform, config and log
+/// locations, and with `collect` the end of the log for a bug report.
+pub fn doctor_report(
+    config_path: &Path,
+    settings: Option<&LogSettings>,
+    collect: bool,
+) -> Result<String> {
+    let log_path = settings.map_or_else(|| default_path(config_path), |s| s.path.clone());
+    let mut out = format!(
+        "{}\nplatform: {} {}\nconfig: {}{}\n",
+        crate::cli::version_string(),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        config_path.display(),
+        if config_path.exists() {
+            ""
+        } else {
+            " (missing)"
+        },
+    );
+    let log_size = fs::metadata(&log_path).ok().map(|m| m.len());
+    out.push_str(&format!(
+        "log: {}{}\n",
+        log_path.display(),
+        match log_size {
+            Some(bytes) => format!(" ({bytes} bytes)"),
+            None => " (missing)".into(),
+        }
+    ));
+    out.push_str(&match settings {
+        Some(s) => format!(
+            "logging: {}{}\n",
+            s.level.label().to_ascii_lowercase(),
+            if s.redact { ", redacted" } else { "" }
+        ),
+        None => "logging: off (set SSHDB_LOG=debug or pass --log-file PATH)\n".into(),
+    });
+    if !collect {
+        return Ok(out.trim_end().to_string());
+    }
+    let Some(_) = log_size else {
+        bail!(
+            "no log at {}; run sshdb with SSHDB_LOG=debug, reproduce the problem, then collect",
+            log_path.display()
+        );
+    };
+    let text = fs::read_to_string(&log_path)
+        .with_context(|| format!("cannot read log {}", log_path.display()))?;
+    let lines: Vec<&str> = text.lines().collect();
+    let tail = &lines[lines.len().saturating_sub(COLLECT_LINES)..];
+    out.push_str(&format!(
+        "\n--- last {} of {} log lines ---\n{}",
+        tail.len(),
+        lines.len(),
+        tail.join("\n")
+    ));
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn lines_are_timestamped_logfmt() {
+        let at = UNIX_EPOCH + Duration::from_millis(1_791_000_000_123);
+        let line = format_line(
+            at,
+            Level::Info,
+            "app",
+            "mode changed",
+            &[
+                ("from", "Normal".into()),
+                ("key", "Char('a')".into()),
+                ("error", "cannot write\nconfig: denied".into()),
+                ("empty", String::new()),
+            ],
+        );
+        assert_eq!(
+            line,
+            "2026-10-03T04:00:00.123Z INFO app: mode changed from=Normal key=Char('a') \
+             error=\"cannot write\\nconfig: denied\" empty=\"\""
+        );
+    }
+
+    #[test]
+    fn settings_come_from_the_flag_or_the_environment() {
+        let config = Path::new("/home/u/.config/sshdb/config.toml");
+        assert_eq!(
+            LogSettings::resolve(None, None, None, false, config).unwrap(),
+            None
+        );
+        let from_env = LogSettings::resolve(Some("info"), Some("1"), None, false, config)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            from_env,
+            LogSettings {
+                path: "/home/u/.config/sshdb/sshdb.log".into(),
+                level: Level::Info,
+                redact: true,
+            }
+        );
+        let from_flag = LogSettings::resolve(None, None, Some(Path::new("x.log")), true, config)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (from_flag.path, from_flag.level, from_flag.redact),
+            ("x.log".into(), Level::Debug, true)
+        );
+        let off = LogSettings::resolve(Some("off"), None, Some(Path::new("x.log")), false, config);
+        assert_eq!(off.unwrap(), None);
+        assert!(LogSettings::resolve(Some("verbose"), None, None, false, config).is_err());
+    }
+
+    #[test]
+    fn redaction_keeps_options_but_not_targets() {
+        let ssh = argv(&[
+            "ssh",
+            "-p",
+            "2222",
+            "-i",
+            "/home/alice/.ssh/prod_id_ed25519",
+            "-oIdentitiesOnly=yes",
+            "-o",
+            "ProxyJump=ops@52.17.9.3",
+            "-J52.17.9.3",
+            "-tt",
+            "deploy@52.14.33.10",
+            "sudo systemctl restart api",
+        ]);
+        assert_eq!(
+            redact_argv(&ssh).join(" "),
+            "ssh -p 2222 -i <redacted> -o IdentitiesOnly=yes -o ProxyJump=<redacted> \
+             -J <redacted> -tt <redacted> <redacted>"
+        );
+        assert_eq!(
+            redact_argv(&argv(&["tsh", "ssh", "--proxy", "root@node"])).join(" "),
+            "tsh <redacted> --proxy <redacted>"
+        );
+        assert!(redact_argv(&[]).is_empty());
+    }
+
+    #[test]
+    fn doctor_collects_the_end_of_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("config.toml");
+        let err = doctor_report(&config, None, true).unwrap_err().to_string();
+        assert!(err.contains("SSHDB_LOG=debug"), "{err}");
+
+        let lines: Vec<String> = (0..250).map(|i| format!("line {i}")).collect();
+        fs::write(dir.path().join(LOG_FILE_NAME), lines.join("\n")).unwrap();
+        let report = doctor_report(&config, None, false).unwrap();
+        assert!(
+            report.starts_with(&crate::cli::version_string()),
+            "{report}"
+        );
+        assert!(report.contains("(missing)") && report.contains("logging: off"));
+        assert!(!report.contains("line 249"));
+
+        let report = doctor_report(&config, None, true).unwrap();
+        assert!(report.contains("--- last 200 of 250 log lines ---"));
+        assert!(report.ends_with("line 249") && !report.contains("line 49\n"));
+    }
+}
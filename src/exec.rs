@@ -0,0 +1,459 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! `sshdb exec`: runs one remote command across a set of hosts without the
+//! TUI, for CI and cron jobs.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+
+use crate::app::search_haystack;
+use crate::config::ConfigStore;
+use crate::debuglog;
+use crate::hostkey;
+use crate::model::{Config, Host};
+use crate::ssh;
+use crate::state::{self, SearchScope};
+
+pub const DEFAULT_PARALLEL: usize = 4;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExecArgs {
+    pub tags: Vec<String>,
+    pub hosts: Vec<String>,
+    pub filters: Vec<String>,
+    pub command: String,
+    pub parallel: usize,
+    pub timeout: Option<Duration>,
+    pub dry_run: bool,
+    /// Also run on the workset saved by the TUI (`persist_workset`).
+    pub workset: bool,
+}
+
+/// How one host's run ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Exit code; `None` when killed by a signal.
+    Exited(Option<i32>),
+    TimedOut,
+    /// The command could not be started.
+    Failed(String),
+}
+
+impl Outcome {
+    pub fn success(&self) -> bool {
+        *self == Outcome::Exited(Some(0))
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Outcome::Exited(Some(0)) => "ok".into(),
+            Outcome::Exited(Some(code)) => format!("exit {code}"),
+            Outcome::Exited(None) => "killed".into(),
+            Outcome::TimedOut => "timeout".into(),
+            Outcome::Failed(err) => format!("error: {err}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Job {
+    /// Host id, used to prefix output lines.
+    pub label: String,
+    pub argv: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobResult {
+    pub label: String,
+    pub outcome: Outcome,
+    pub took: Duration,
+}
+
+/// Hosts matching any `--host`, `--tag` or `--filter`, in config order and
+/// without duplicates. Unknown `--host` names are an error rather than
+/// silently shrinking the set.
+pub fn select_hosts<'a>(config: &'a Config, args: &ExecArgs) -> Result<Vec<&'a Host>> {
+    if args.hosts.is_empty() && args.tags.is_empty() && args.filters.is_empty() {
+        bail!("no hosts selected; pass --host, --tag or --filter");
+    }
+    let mut ids = Vec::new();
+    for reference in &args.hosts {
+        match config.resolve_host(reference, None)? {
+            Some(host) => ids.push(host.id()),
+            None => bail!("no host named '{reference}'"),
+        }
+    }
+    let matcher = SkimMatcherV2::default();
+    let selected: Vec<&Host> = config
+        .hosts
+        .iter()
+        .filter(|host| {
+            ids.contains(&host.id())
+                || args.tags.iter().any(|tag| host.tags.contains(tag))
+                || args.filters.iter().any(|query| {
+                    matcher
+                        .fuzzy_match(&search_haystack(host, SearchScope::Targets), query)
+                        .is_some()
+                })
+        })
+        .collect();
+    if selected.is_empty() {
+        bail!("no hosts match the given --tag/--filter");
+    }
+    Ok(selected)
+}
+
+/// The argv for one host: never prompts, and gives up connecting after the
+/// timeout.
+pub fn batch_argv(host: &Host, config: &Config, args: &ExecArgs) -> Result<Vec<String>> {
+    let mut host = host.clone();
+    host.options.push("-oBatchMode=yes".into());
+    if let Some(timeout) = args.timeout {
+        host.options
+            .push(format!("-oConnectTimeout={}", timeout.as_secs().max(1)));
+    }
+    ssh::build_argv(
+        &host,
+        config,
+        config.default_key.as_deref(),
+        Some(&args.command),
+    )
+}
+
+/// Runs `jobs` at most `parallel` at a time, writing each output line to
+/// `out` as `[label] line`. Results come back in job order.
+///
+/// `execute` runs one argv with the timeout, feeding it output lines; it is
+/// injected so tests don't spawn ssh.
+pub fn run_jobs<E, W>(
+    jobs: &[Job],
+    parallel: usize,
+    timeout: Option<Duration>,
+    execute: &E,
+    out: &Mutex<W>,
+) -> Vec<JobResult>
+where
+    E: Fn(&[String], Option<Duration>, &mut dyn FnMut(&str)) -> Outcome + Sync,
+    W: Write + Send,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; jobs.len()]);
+    thread::scope(|scope| {
+        for _ in 0..parallel.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::SeqCst);
+                let Some(job) = jobs.get(idx) else {
+                    break;
+                };
+                let started = Instant::now();
+                let outcome = execute(&job.argv, timeout, &mut |line| {
+                    if let Ok(mut out) = out.lock() {
+                        let _ = writeln!(out, "[{}] {line}", job.label);
+                    }
+                });
+                results.lock().unwrap()[idx] = Some(JobResult {
+                    label: job.label.clone(),
+                    outcome,
+                    took: started.elapsed(),
+                });
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Table of per-host results followed by a one-line tally.
+pub fn summary_table(results: &[JobResult]) -> String {
+    let width = results
+        .iter()
+        .map(|r| r.label.len())
+        .chain(std::iter::once("HOST".len()))
+        .max()
+        .unwrap_or_default();
+    let mut out = format!("{:<width$}  {:>7}  RESULT\n", "HOST", "TIME");
+    for result in results {
+        out.push_str(&format!(
+            "{:<width$}  {:>6.1}s  {}\n",
+            result.label,
+            result.took.as_secs_f64(),
+            result.outcome.label()
+        ));
+    }
+    let failed = results.iter().filter(|r| !r.outcome.success()).count();
+    out.push_str(&format!(
+        "{} host{}: {} ok, {} failed",
+        results.len(),
+        if results.len() == 1 { "" } else { "s" },
+        results.len() - failed,
+        failed
+    ));
+    out
+}
+
+/// Entry point for `sshdb exec`; returns the process exit code.
+pub fn run(args: &ExecArgs, store: &ConfigStore) -> Result<i32> {
+    let config = store.load_read_only()?;
+    let mut args = args.clone();
+    if args.workset {
+        args.hosts
+            .extend(state::saved_workset(store.path(), &config)?);
+    }
+    let args = &args;
+    let hosts = select_hosts(&config, args)?;
+    let mut jobs = hosts
+        .iter()
+        .map(|host| {
+            Ok(Job {
+                label: host.id(),
+                argv: batch_argv(host, &config, args)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if args.dry_run {
+        for job in &jobs {
+            let line: Vec<String> = job.argv.iter().map(|a| ssh::shell_quote(a)).collect();
+            println!("[{}] {}", job.label, line.join(" "));
+        }
+        return Ok(0);
+    }
+
+    // Pinned host keys are resolved only for real runs; the files stay
+    // until every job is done.
+    let mut known_hosts = Vec::new();
+    for (job, host) in jobs.iter_mut().zip(&hosts) {
+        known_hosts.push(hostkey::pin(host, &mut job.argv)?);
+    }
+
+    let out = Mutex::new(std::io::stdout());
+    let results = run_jobs(&jobs, args.parallel, args.timeout, &execute, &out);
+    println!("{}", summary_table(&results));
+    Ok(if results.iter().all(|r| r.outcome.success()) {
+        0
+    } else {
+        1
+    })
+}
+
+/// Spawns `argv` with stdin closed, forwarding stdout and stderr lines as
+/// they arrive and killing the process once `timeout` has passed.
+fn execute(argv: &[String], timeout: Option<Duration>, on_line: &mut dyn FnMut(&str)) -> Outcome {
+    let Some((program, args)) = argv.split_first() else {
+        return Outcome::Failed("empty command".into());
+    };
+    debuglog::spawn("exec", argv);
+    let mut child = match Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => return Outcome::Failed(format!("failed to start {program}: {err}")),
+    };
+    let (tx, rx) = mpsc::channel();
+    let readers = [
+        child.stdout.take().map(|s| forward_lines(s, tx.clone())),
+        child.stderr.take().map(|s| forward_lines(s, tx.clone())),
+    ];
+    drop(tx);
+
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let mut timed_out = false;
+    loop {
+        let wait = deadline.map_or(Duration::from_millis(100), |d| {
+            d.saturating_duration_since(Instant::now())
+                .min(Duration::from_millis(100))
+        });
+        match rx.recv_timeout(wait) {
+            Ok(line) => on_line(&line),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    timed_out = true;
+                    let _ = child.kill();
+                    break;
+                }
+            }
+        }
+    }
+    let status = child.wait();
+    if timed_out {
+        // Grandchildren may still hold the pipes open; leave the readers be.
+        return Outcome::TimedOut;
+    }
+    for reader in readers.into_iter().flatten() {
+        let _ = reader.join();
+    }
+    match status {
+        Ok(status) => Outcome::Exited(status.code()),
+        Err(err) => Outcome::Failed(err.to_string()),
+    }
+}
+
+fn forward_lines(
+    stream: impl Read + Send + 'static,
+    tx: mpsc::Sender<String>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &str) -> ExecArgs {
+        ExecArgs {
+            command: command.into(),
+            parallel: DEFAULT_PARALLEL,
+            ..Default::default()
+        }
+    }
+
+    fn ids(hosts: &[&Host]) -> Vec<String> {
+        hosts.iter().map(|h| h.id()).collect()
+    }
+
+    #[test]
+    fn selects_union_of_hosts_tags_and_filters_in_config_order() {
+        let config = Config::sample();
+        let mut a = args("uptime");
+        a.tags = vec!["jump".into()];
+        a.hosts = vec!["prod-web".into()];
+        assert_eq!(
+            ids(&select_hosts(&config, &a).unwrap()),
+            vec!["prod-web", "jump-eu"]
+        );
+
+        let mut a = args("uptime");
+        a.filters = vec!["staging".into()];
+        a.hosts = vec!["staging-db".into()];
+        assert_eq!(ids(&select_hosts(&config, &a).unwrap()), vec!["staging-db"]);
+    }
+
+    #[test]
+    fn rejects_empty_and_unknown_selections() {
+        let config = Config::sample();
+        assert!(select_hosts(&config, &args("uptime")).is_err());
+
+        let mut a = args("uptime");
+        a.hosts = vec!["nope".into()];
+        assert!(select_hosts(&config, &a)
+            .unwrap_err()
+            .to_string()
+            .contains("no host named 'nope'"));
+
+        let mut a = args("uptime");
+        a.tags = vec!["no-such-tag".into()];
+        assert!(select_hosts(&config, &a).is_err());
+    }
+
+    #[test]
+    fn batch_argv_never_prompts_and_bounds_connecting() {
+        let config = Config::default();
+        let host = Host {
+            name: "b".into(),
+            address: "10.0.0.9".into(),
+            key_paths: vec!["/keys/b".into()],
+            ..Default::default()
+        };
+        let mut a = args("restic backup /srv");
+        a.timeout = Some(Duration::from_secs(30));
+        assert_eq!(
+            batch_argv(&host, &config, &a).unwrap(),
+            vec![
+                "ssh",
+                "-i",
+                "/keys/b",
+                "-oBatchMode=yes",
+                "-oConnectTimeout=30",
+                "10.0.0.9",
+                "restic backup /srv"
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_jobs_with_prefixed_output_and_ordered_results() {
+        let jobs: Vec<Job> = ["a", "b", "c"]
+            .iter()
+            .map(|label| Job {
+                label: label.to_string(),
+                argv: vec!["true".into(), label.to_string()],
+            })
+            .collect();
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let execute = |argv: &[String], _: Option<Duration>, on_line: &mut dyn FnMut(&str)| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            on_line(&format!("hello from {}", argv[1]));
+            running.fetch_sub(1, Ordering::SeqCst);
+            match argv[1].as_str() {
+                "b" => Outcome::Exited(Some(3)),
+                _ => Outcome::Exited(Some(0)),
+            }
+        };
+        let out = Mutex::new(Vec::new());
+        let results = run_jobs(&jobs, 2, None, &execute, &out);
+
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        let labels: Vec<&str> = results.iter().map(|r| r.label.as_str()).collect();
+        assert_eq!(labels, vec!["a", "b", "c"]);
+        assert_eq!(results[1].outcome, Outcome::Exited(Some(3)));
+        let text = String::from_utf8(out.into_inner().unwrap()).unwrap();
+        let mut lines: Vec<&str> = text.lines().collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec!["[a] hello from a", "[b] hello from b", "[c] hello from c"]
+        );
+    }
+
+    #[test]
+    fn formats_summary_table() {
+        let result = |label: &str, outcome, ms| JobResult {
+            label: label.into(),
+            outcome,
+            took: Duration::from_millis(ms),
+        };
+        let table = summary_table(&[
+            result("prod-web", Outcome::Exited(Some(0)), 1200),
+            result("team-a/db", Outcome::Exited(Some(2)), 300),
+            result("x", Outcome::TimedOut, 30000),
+            result("y", Outcome::Failed("failed to start ssh".into()), 0),
+        ]);
+        assert_eq!(
+            table,
+            "HOST          TIME  RESULT\n\
+             prod-web      1.2s  ok\n\
+             team-a/db     0.3s  exit 2\n\
+             x            30.0s  timeout\n\
+             y             0.0s  error: failed to start ssh\n\
+             4 hosts: 1 ok, 3 failed"
+        );
+    }
+}
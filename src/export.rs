@@ -0,0 +1,585 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! `sshdb export`: the host database as TOML for sharing, optionally with
+//! ssh_config aliases replaced by what they stand for. `export_ssh_config`
+//! writes it as ssh_config instead, for plain `ssh` and other tools.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::config::ConfigStore;
+use crate::model::{Config, Host, IpFamily};
+use crate::net;
+use crate::problems;
+use crate::ssh;
+use crate::ssh_config::{self, Parsed};
+use crate::state;
+
+/// Prints the config read-only; with `inline_aliases`, hosts whose address
+/// is an ssh_config alias get the values from `~/.ssh/config` instead, and
+/// with `workset` only the saved workset is kept (see `only`). Notes about
+/// what was (not) inlined go to stderr.
+pub fn run(store: &ConfigStore, inline_aliases: bool, workset: bool) -> Result<String> {
+    let mut config = store.load_read_only()?;
+    if workset {
+        config = only(&config, &state::saved_workset(store.path(), &config)?);
+    }
+    if inline_aliases {
+        let parsed = user_ssh_config()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|text| ssh_config::parse(&text))
+            .unwrap_or_default();
+        let (inlined, notes) = inline(&config, &parsed, |host| {
+            host.alias_ok
+                || (problems::alias_candidate(host)
+                    && net::needs_lookup(&host.address)
+                    && !net::resolves_now(&host.address))
+        });
+        for note in notes {
+            eprintln!("sshdb export: {note}");
+        }
+        config = inlined;
+    }
+    toml::to_string_pretty(&config).with_context(|| "failed to serialize config")
+}
+
+/// `config` with just the hosts in `ids` plus every bastion they reach
+/// through, so the result still loads on its own.
+pub fn only(config: &Config, ids: &[String]) -> Config {
+    let mut keep: Vec<String> = ids.to_vec();
+    let mut next = 0;
+    while let Some(id) = keep.get(next).cloned() {
+        next += 1;
+        let Some(host) = config.find_host(&id) else {
+            continue;
+        };
+        let bastion = host
+            .bastion
+            .as_deref()
+            .and_then(|bastion| config.resolve_host(bastion, host.group.as_deref()).ok())
+            .flatten();
+        if let Some(bastion) = bastion {
+            if !keep.contains(&bastion.id()) {
+                keep.push(bastion.id());
+            }
+        }
+    }
+    let mut out = config.clone();
+    out.hosts.retain(|host| keep.contains(&host.id()));
+    out
+}
+
+fn user_ssh_config() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".ssh").join("config"))
+}
+
+/// Copies `config`, replacing the address of every host `is_alias` picks
+/// with its ssh_config `HostName`, and filling user, port, keys and bastion
+/// (or proxy command) from ssh_config where the host leaves them unset.
+/// Returns one note per alias host.
+pub fn inline(
+    config: &Config,
+    ssh_config: &Parsed,
+    is_alias: impl Fn(&Host) -> bool,
+) -> (Config, Vec<String>) {
+    let mut out = config.clone();
+    let mut notes = Vec::new();
+    for host in out.hosts.iter_mut().filter(|host| is_alias(host)) {
+        let alias = host.address.clone();
+        let directives = ssh_config::lookup(ssh_config, &alias);
+        let first = |keyword: &str| {
+            directives
+                .iter()
+                .find(|(k, _)| k == keyword)
+                .map(|(_, v)| v.clone())
+        };
+        let Some(hostname) = first("hostname") else {
+            notes.push(format!(
+                "kept '{alias}' for {}: no HostName for it in ssh_config",
+                config.display_name(host)
+            ));
+            continue;
+        };
+        let mut filled = vec!["HostName"];
+        host.address = hostname.replace("%h", &alias);
+        host.alias_ok = false;
+        if host.user.is_none() {
+            host.user = first("user");
+            filled.extend(host.user.as_ref().map(|_| "User"));
+        }
+        if host.port.is_none() {
+            host.port = first("port").and_then(|p| p.parse().ok());
+            filled.extend(host.port.map(|_| "Port"));
+        }
+        if host.key_paths.is_empty() {
+            host.key_paths = directives
+                .iter()
+                .filter(|(k, _)| k == "identityfile")
+                .map(|(_, v)| v.clone())
+                .collect();
+            if !host.key_paths.is_empty() {
+                filled.push("IdentityFile");
+            }
+        }
+        if host.bastion.is_none() {
+            host.bastion = first("proxyjump").filter(|jump| !jump.eq_ignore_ascii_case("none"));
+            filled.extend(host.bastion.as_ref().map(|_| "ProxyJump"));
+        }
+        if host.bastion.is_none() && host.proxy_command.is_none() {
+            host.proxy_command =
+                first("proxycommand").filter(|command| !command.eq_ignore_ascii_case("none"));
+            filled.extend(host.proxy_command.as_ref().map(|_| "ProxyCommand"));
+        }
+        notes.push(format!(
+            "inlined '{alias}' for {} ({})",
+            config.display_name(host),
+            filled.join(", ")
+        ));
+    }
+    (out, notes)
+}
+
+/// ssh_config(5) keywords as the man page spells them, so `-o` options
+/// come out in their usual case.
+const KEYWORDS: [&str; 56] = [
+    "AddKeysToAgent",
+    "AddressFamily",
+    "BatchMode",
+    "BindAddress",
+    "BindInterface",
+    "CanonicalizeHostname",
+    "CertificateFile",
+    "CheckHostIP",
+    "Ciphers",
+    "ClearAllForwardings",
+    "Compression",
+    "ConnectionAttempts",
+    "ConnectTimeout",
+    "ControlMaster",
+    "ControlPath",
+    "ControlPersist",
+    "DynamicForward",
+    "EscapeChar",
+    "ExitOnForwardFailure",
+    "ForwardAgent",
+    "ForwardX11",
+    "ForwardX11Trusted",
+    "GatewayPorts",
+    "GlobalKnownHostsFile",
+    "HashKnownHosts",
+    "HostKeyAlgorithms",
+    "HostKeyAlias",
+    "HostName",
+    "IdentitiesOnly",
+    "IdentityAgent",
+    "IdentityFile",
+    "IPQoS",
+    "KbdInteractiveAuthentication",
+    "KexAlgorithms",
+    "LocalCommand",
+    "LocalForward",
+    "LogLevel",
+    "MACs",
+    "PasswordAuthentication",
+    "PermitLocalCommand",
+    "Port",
+    "PreferredAuthentications",
+    "ProxyCommand",
+    "ProxyJump",
+    "PubkeyAcceptedAlgorithms",
+    "PubkeyAuthentication",
+    "RemoteCommand",
+    "RemoteForward",
+    "RequestTTY",
+    "SendEnv",
+    "ServerAliveCountMax",
+    "ServerAliveInterval",
+    "SetEnv",
+    "StrictHostKeyChecking",
+    "UserKnownHostsFile",
+    "VisualHostKey",
+];
+
+/// The `Host` alias a host is exported under: its canonical id, with
+/// whitespace and the pattern characters `*?!,` turned into `-` so the
+/// line names exactly one host.
+fn ssh_alias(host: &Host) -> String {
+    host.id()
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() || "*?!,".contains(c) {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Why `host` can't be written as ssh_config; `None` when it can.
+pub fn unexportable(config: &Config, host: &Host) -> Option<String> {
+    if host.command_template.is_some() {
+        return Some("uses a command template".into());
+    }
+    // The first host keeps an alias two names map to.
+    let alias = ssh_alias(host);
+    if let Some(other) = config
+        .hosts
+        .iter()
+        .take_while(|other| other.id() != host.id())
+        .find(|other| ssh_alias(other) == alias)
+    {
+        return Some(format!(
+            "its alias '{alias}' would be the same as {}'s",
+            other.id()
+        ));
+    }
+    if let Some(bastion) = &host.bastion {
+        if let Err(err) = config.resolve_host(bastion, host.group.as_deref()) {
+            return Some(format!("{err:#}"));
+        }
+    }
+    if let Some(command) = &host.remote_command {
+        if let Err(err) = ssh::substitute_vars(command, &host.vars) {
+            return Some(format!("{err:#}"));
+        }
+    }
+    None
+}
+
+/// Every host as an ssh_config `Host` block under `ssh_alias`, with what
+/// connecting from sshdb would pass: `HostName`, `User`, `Port`, the keys
+/// (`default_key` for hosts without their own), `ProxyJump` to the
+/// bastion's block or the `ProxyCommand`, and the options as directives.
+/// Hosts that can't be expressed are listed as comments.
+pub fn export_ssh_config(config: &Config) -> String {
+    let mut out = String::from("# Written by sshdb export; changes here are not read back.\n");
+    for host in &config.hosts {
+        out.push('\n');
+        if let Some(reason) = unexportable(config, host) {
+            out.push_str(&format!("# {} not exported: {reason}\n", host.id()));
+            continue;
+        }
+        out.push_str(&host_block(config, host));
+    }
+    out
+}
+
+fn host_block(config: &Config, host: &Host) -> String {
+    let mut lines = vec![format!("Host {}", ssh_alias(host))];
+    let mut directive = |keyword: &str, value: &str| lines.push(format!("  {keyword} {value}"));
+    directive("HostName", &host.address);
+    if let Some(user) = &host.user {
+        directive("User", user);
+    }
+    if let Some(port) = host.port {
+        directive("Port", &port.to_string());
+    }
+    let keys: Vec<&str> = match (host.key_paths.is_empty(), config.default_key.as_deref()) {
+        (false, _) => host.key_paths.iter().map(String::as_str).collect(),
+        (true, Some(key)) if key != "agent" => vec![key],
+        _ => Vec::new(),
+    };
+    for key in keys {
+        directive("IdentityFile", &quote(key));
+    }
+    if let Some(bastion) = &host.bastion {
+        let jump = match config.resolve_host(bastion, host.group.as_deref()) {
+            Ok(Some(bastion)) => ssh_alias(bastion),
+            _ => bastion.clone(),
+        };
+        directive("ProxyJump", &jump);
+    }
+    if let Some(command) = &host.proxy_command {
+        directive("ProxyCommand", command);
+    }
+    if let Some(family) = host.ip_family {
+        let family = match family {
+            IpFamily::V4 => "inet",
+            IpFamily::V6 => "inet6",
+        };
+        directive("AddressFamily", family);
+    }
+    match host.request_tty {
+        Some(true) => directive("RequestTTY", "yes"),
+        Some(false) => directive("RequestTTY", "no"),
+        None => {}
+    }
+    let mut comments = Vec::new();
+    let mut options = ssh::effective_options(host);
+    options.extend(ssh::set_env_args(host));
+    options.extend(ssh::multiplex_args(host));
+    options.extend(ssh::forwarding_args(host));
+    options.extend(ssh::forward_args(host));
+    for option in option_directives(&options) {
+        match option {
+            Ok((keyword, value)) => directive(&keyword, &value),
+            Err(flag) => comments.push(format!("  # not expressible in ssh_config: {flag}")),
+        }
+    }
+    if let Some(command) = &host.remote_command {
+        // `unexportable` already checked the placeholders.
+        let command = ssh::substitute_vars(command, &host.vars).unwrap_or_default();
+        directive("RemoteCommand", &command);
+    }
+    if host.connect_with.as_deref() == Some("mosh") {
+        comments.push("  # connect_with = \"mosh\" is only used when connecting from sshdb".into());
+    }
+    if host.password_command.is_some() {
+        comments.push("  # password_command is only run when connecting from sshdb".into());
+    }
+    if host.pinned_fingerprint.is_some() || host.pinned_hostkey.is_some() {
+        comments.push("  # the pinned host key is only enforced when connecting from sshdb".into());
+    }
+    lines.extend(comments);
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// `value` in double quotes when it has spaces, as ssh_config reads it.
+fn quote(value: &str) -> String {
+    if value.contains(char::is_whitespace) {
+        format!("\"{value}\"")
+    } else {
+        value.to_string()
+    }
+}
+
+/// The directives ssh options stand for, in order; `Err` carries a flag
+/// ssh_config has no directive for.
+fn option_directives(options: &[String]) -> Vec<Result<(String, String), String>> {
+    let mut out = Vec::new();
+    let mut args = options.iter();
+    while let Some(arg) = args.next() {
+        let Some((flag, attached)) = arg.strip_prefix('-').and_then(|rest| {
+            let flag = rest.chars().next()?;
+            Some((flag, &rest[flag.len_utf8()..]))
+        }) else {
+            out.push(Err(arg.clone()));
+            continue;
+        };
+        let value = match flag {
+            'o' | 'L' | 'R' | 'D' if attached.is_empty() => args.next().cloned(),
+            'o' | 'L' | 'R' | 'D' => Some(attached.to_string()),
+            _ => None,
+        };
+        let written = match &value {
+            Some(value) if attached.is_empty() => format!("{arg} {value}"),
+            _ => arg.clone(),
+        };
+        let directive = match (flag, value) {
+            ('o', Some(option)) => option
+                .split_once('=')
+                .or_else(|| option.split_once(char::is_whitespace))
+                .map(|(keyword, value)| (canonical(keyword.trim()), value.trim().to_string())),
+            ('L', Some(spec)) => forward(&spec).map(|v| ("LocalForward".into(), v)),
+            ('R', Some(spec)) => forward(&spec).map(|v| ("RemoteForward".into(), v)),
+            ('D', Some(port)) => Some(("DynamicForward".into(), port)),
+            _ if !attached.is_empty() => None,
+            ('A', _) => Some(("ForwardAgent".into(), "yes".into())),
+            ('a', _) => Some(("ForwardAgent".into(), "no".into())),
+            ('C', _) => Some(("Compression".into(), "yes".into())),
+            ('X', _) => Some(("ForwardX11".into(), "yes".into())),
+            ('x', _) => Some(("ForwardX11".into(), "no".into())),
+            ('Y', _) => Some(("ForwardX11Trusted".into(), "yes".into())),
+            ('q', _) => Some(("LogLevel".into(), "QUIET".into())),
+            _ => None,
+        };
+        out.push(directive.ok_or(written));
+    }
+    out
+}
+
+fn canonical(keyword: &str) -> String {
+    KEYWORDS
+        .iter()
+        .find(|known| known.eq_ignore_ascii_case(keyword))
+        .map_or_else(|| keyword.to_string(), |known| known.to_string())
+}
+
+/// `-L [bind:]port:host:hostport` as ssh_config writes it:
+/// `[bind:]port host:hostport`.
+fn forward(spec: &str) -> Option<String> {
+    let parts: Vec<&str> = spec.split(':').collect();
+    match parts.as_slice() {
+        [port, host, hostport] => Some(format!("{port} {host}:{hostport}")),
+        [bind, port, host, hostport] => Some(format!("{bind}:{port} {host}:{hostport}")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SSH_CONFIG: &str = "\
+Host corp-jump
+  HostName jump.corp.example
+  User ops
+  Port 2222
+  IdentityFile ~/.ssh/corp
+Host corp-db
+  HostName %h.internal.example
+  ProxyJump corp-jump
+";
+
+    fn host(name: &str, address: &str) -> Host {
+        Host {
+            name: name.into(),
+            address: address.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn workset_export_keeps_the_bastions_it_needs() {
+        let config = Config::sample();
+        let names = |config: &Config| {
+            config
+                .hosts
+                .iter()
+                .map(|h| h.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&only(&config, &["staging-db".into()])),
+            ["staging-db", "jump-eu"]
+        );
+        assert_eq!(names(&only(&config, &["prod-web".into()])), ["prod-web"]);
+    }
+
+    #[test]
+    fn inlines_alias_hosts_from_ssh_config() {
+        let mut db = host("db", "corp-db");
+        db.user = Some("postgres".into());
+        let config = Config {
+            hosts: vec![
+                host("jump", "corp-jump"),
+                db,
+                host("web", "web.example.com"),
+                host("lab", "corp-lab"),
+            ],
+            ..Default::default()
+        };
+        let (out, notes) = inline(&config, &ssh_config::parse(SSH_CONFIG), |h| {
+            !h.address.contains('.')
+        });
+
+        let jump = &out.hosts[0];
+        assert_eq!(jump.address, "jump.corp.example");
+        assert_eq!(jump.user.as_deref(), Some("ops"));
+        assert_eq!(jump.port, Some(2222));
+        assert_eq!(jump.key_paths, vec!["~/.ssh/corp".to_string()]);
+
+        let db = &out.hosts[1];
+        assert_eq!(db.address, "corp-db.internal.example");
+        assert_eq!(db.user.as_deref(), Some("postgres"));
+        assert_eq!(db.bastion.as_deref(), Some("corp-jump"));
+
+        assert_eq!(out.hosts[2], config.hosts[2]);
+        assert_eq!(out.hosts[3], config.hosts[3]);
+        assert_eq!(
+            notes,
+            vec![
+                "inlined 'corp-jump' for jump (HostName, User, Port, IdentityFile)",
+                "inlined 'corp-db' for db (HostName, ProxyJump)",
+                "kept 'corp-lab' for lab: no HostName for it in ssh_config",
+            ]
+        );
+    }
+
+    #[test]
+    fn inlining_clears_the_alias_acknowledgement() {
+        let mut jump = host("jump", "corp-jump");
+        jump.alias_ok = true;
+        let config = Config {
+            hosts: vec![jump],
+            ..Default::default()
+        };
+        let (out, _) = inline(&config, &ssh_config::parse(SSH_CONFIG), |h| h.alias_ok);
+        assert!(!out.hosts[0].alias_ok);
+        assert!(!toml::to_string(&out).unwrap().contains("alias_ok"));
+    }
+
+    #[test]
+    fn writes_hosts_as_ssh_config_blocks() {
+        let mut config = Config::sample();
+        config.hosts[0].options = [
+            "-o",
+            "stricthostkeychecking=no",
+            "-oServerAliveInterval=30",
+            "-L",
+            "8080:localhost:80",
+            "-A",
+            "-E",
+        ]
+        .map(String::from)
+        .to_vec();
+        config.hosts[0].ip_family = Some(IpFamily::V6);
+        config.hosts[0].remote_command = Some("tail -f /var/log/{{svc}}.log".into());
+        config.hosts[0].vars = [("svc".to_string(), "nginx".to_string())].into();
+        config.hosts[1].prefer_public_key_auth = true;
+        config.hosts[2].key_paths.clear();
+        let text = export_ssh_config(&config);
+        let blocks: Vec<&str> = text.split("\n\n").collect();
+        assert_eq!(
+            blocks[1],
+            "Host prod-web\n  HostName 52.14.33.10\n  User deploy\n  Port 22\n  \
+             IdentityFile ~/.ssh/prod_id_ed25519\n  AddressFamily inet6\n  \
+             StrictHostKeyChecking no\n  ServerAliveInterval 30\n  \
+             LocalForward 8080 localhost:80\n  ForwardAgent yes\n  \
+             RemoteCommand tail -f /var/log/nginx.log\n  \
+             # not expressible in ssh_config: -E"
+        );
+        assert!(blocks[2].starts_with("Host staging-db\n  HostName 35.12.2.4\n"));
+        assert!(blocks[2].contains("\n  ProxyJump jump-eu\n"));
+        assert!(blocks[2].contains("\n  PreferredAuthentications publickey"));
+        // No key of its own: default_key applies, as when sshdb connects.
+        assert!(blocks[3].contains("\n  IdentityFile ~/.ssh/id_ed25519"));
+
+        // A flag that isn't ASCII is kept as a comment, not split mid-character.
+        config.hosts[0].options = vec!["-ü".into(), "-oü=1".into()];
+        let text = export_ssh_config(&config);
+        assert!(
+            text.contains("  # not expressible in ssh_config: -ü\n"),
+            "{text}"
+        );
+        assert!(text.contains("  ü 1\n"), "{text}");
+
+        // mosh is sshdb's doing; the block is plain ssh.
+        config.hosts[0].options.clear();
+        config.hosts[0].connect_with = Some("mosh".into());
+        let text = export_ssh_config(&config);
+        assert!(
+            text.contains("  # connect_with = \"mosh\" is only used when connecting from sshdb\n"),
+            "{text}"
+        );
+
+        // Names that would be patterns or several aliases get one word.
+        let mut odd = Config::sample();
+        odd.hosts[0].name = "db 1".into();
+        odd.hosts[1].name = "web*".into();
+        odd.hosts[1].bastion = Some("jump eu".into());
+        odd.hosts[2].name = "jump eu".into();
+        let text = export_ssh_config(&odd);
+        assert!(text.contains("Host db-1\n"), "{text}");
+        assert!(text.contains("Host web-\n"), "{text}");
+        assert!(text.contains("  ProxyJump jump-eu\n"), "{text}");
+        assert!(text.contains("Host jump-eu\n"), "{text}");
+        // Two hosts can't share an alias; the second is left out.
+        odd.hosts[0].name = "web?".into();
+        let text = export_ssh_config(&odd);
+        assert!(
+            text.contains("# web* not exported: its alias 'web-' would be the same as web?'s"),
+            "{text}"
+        );
+        assert!(text.contains("Host web-\n"), "{text}");
+
+        config.hosts[0].remote_command = Some("{{missing}}".into());
+        let text = export_ssh_config(&config);
+        assert!(!text.contains("Host prod-web"));
+        assert!(text.contains("# prod-web not exported: "), "{text}");
+    }
+}
@@ -0,0 +1,366 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Why an ssh session failed, read from the last lines ssh wrote to stderr.
+//! With `-J` chains OpenSSH names the address it could not reach or log in
+//! to; matching that against the route tells which hop broke.
+
+use std::fmt;
+
+/// One machine a connection passes through: the jump hosts in the order ssh
+/// visits them, then the target.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    /// Host id, or the bastion reference as written for a free-text hop.
+    pub label: String,
+    /// What ssh is told to connect to; OpenSSH quotes it in its errors.
+    pub address: String,
+    pub bastion: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cause {
+    ConnectionRefused,
+    TimedOut,
+    NoRoute,
+    NetworkUnreachable,
+    Unresolved,
+    /// The methods the server offered, e.g. `publickey,password`.
+    PermissionDenied(String),
+    HostKeyVerification,
+    /// A jump host refused to forward (`AllowTcpForwarding no`).
+    ForwardingProhibited,
+    /// The connection closed before the ssh handshake, typically because a
+    /// jump connection or `ProxyCommand` died.
+    ClosedBeforeHandshake,
+    /// Anything else, as ssh worded it.
+    Other(String),
+}
+
+impl Cause {
+    fn from_reason(reason: &str) -> Self {
+        let lower = reason.to_ascii_lowercase();
+        if lower.contains("connection refused") {
+            Cause::ConnectionRefused
+        } else if lower.contains("timed out") {
+            Cause::TimedOut
+        } else if lower.contains("no route to host") {
+            Cause::NoRoute
+        } else if lower.contains("network is unreachable") {
+            Cause::NetworkUnreachable
+        } else if lower.contains("administratively prohibited") {
+            Cause::ForwardingProhibited
+        } else if [
+            "name or service not known",
+            "nodename nor servname",
+            "no address associated",
+            "temporary failure in name resolution",
+        ]
+        .iter()
+        .any(|sig| lower.contains(sig))
+        {
+            Cause::Unresolved
+        } else {
+            Cause::Other(reason.trim().trim_end_matches('.').to_string())
+        }
+    }
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cause::ConnectionRefused => f.write_str("connection refused"),
+            Cause::TimedOut => f.write_str("connection timed out"),
+            Cause::NoRoute => f.write_str("no route to host"),
+            Cause::NetworkUnreachable => f.write_str("network unreachable"),
+            Cause::Unresolved => f.write_str("name does not resolve"),
+            Cause::PermissionDenied(methods) if methods.is_empty() => {
+                f.write_str("permission denied")
+            }
+            Cause::PermissionDenied(methods) => write!(f, "permission denied: {methods}"),
+            Cause::HostKeyVerification => f.write_str("host key verification failed"),
+            Cause::ForwardingProhibited => f.write_str("forwarding not allowed"),
+            Cause::ClosedBeforeHandshake => f.write_str("connection closed before the handshake"),
+            Cause::Other(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// Where along the route it broke.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum At {
+    /// Connecting or logging in to this hop failed.
+    Hop {
+        label: String,
+        bastion: bool,
+    },
+    /// A jump host could not open the connection onward. Both ends are
+    /// known only with a single jump host; deeper chains report the same
+    /// message from every hop.
+    Forward {
+        from: Option<String>,
+        to: Option<String>,
+    },
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub at: At,
+    pub cause: Cause,
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cause = &self.cause;
+        match &self.at {
+            At::Hop {
+                label,
+                bastion: true,
+            } => write!(f, "failed at bastion {label} ({cause})"),
+            At::Hop { label, .. } => write!(f, "failed at {label} ({cause})"),
+            At::Forward {
+                from: Some(from),
+                to: Some(to),
+            } => write!(f, "bastion {from} could not reach {to} ({cause})"),
+            At::Forward { .. } => write!(f, "a jump host could not reach the next hop ({cause})"),
+            At::Unknown => write!(f, "{cause}"),
+        }
+    }
+}
+
+/// Reads the first failure OpenSSH reported in `stderr` (later lines are
+/// usually consequences of it), placing it on `route` where the message
+/// names an address. `None` when no known signature is present.
+pub fn diagnose(stderr: &[String], route: &[Endpoint]) -> Option<Diagnosis> {
+    let hop = |address: &str| {
+        route
+            .iter()
+            .find(|endpoint| endpoint.address.eq_ignore_ascii_case(address))
+            .map_or(At::Unknown, |endpoint| At::Hop {
+                label: endpoint.label.clone(),
+                bastion: endpoint.bastion,
+            })
+    };
+    for line in stderr.iter().map(|line| line.trim()) {
+        // ssh: connect to host 52.17.9.3 port 22: Connection timed out
+        if let Some((address, reason)) = line
+            .strip_prefix("ssh: connect to host ")
+            .and_then(|rest| rest.split_once(" port "))
+        {
+            let reason = reason.split_once(": ").map_or(reason, |(_, reason)| reason);
+            return Some(Diagnosis {
+                at: hop(address),
+                cause: Cause::from_reason(reason),
+            });
+        }
+        // ssh: Could not resolve hostname jump-eu: Name or service not known
+        if let Some(rest) = line.strip_prefix("ssh: Could not resolve hostname ") {
+            let address = rest.split_once(':').map_or(rest, |(address, _)| address);
+            return Some(Diagnosis {
+                at: hop(address),
+                cause: Cause::Unresolved,
+            });
+        }
+        // ops@52.17.9.3: Permission denied (publickey).
+        if let Some(start) = line.find("Permission denied (") {
+            let methods = line[start + "Permission denied (".len()..]
+                .split(')')
+                .next()
+                .unwrap_or_default();
+            let prefix = line[..start].trim_end().trim_end_matches(':');
+            let address = prefix.rsplit_once('@').map_or(prefix, |(_, host)| host);
+            return Some(Diagnosis {
+                at: hop(address),
+                cause: Cause::PermissionDenied(methods.to_string()),
+            });
+        }
+        // channel 0: open failed: connect failed: Connection refused
+        if line.starts_with("channel ") && line.contains(": open failed: ") {
+            let reason = line.rsplit(": ").next().unwrap_or(line);
+            let reason = if line.contains("administratively prohibited") {
+                "administratively prohibited"
+            } else {
+                reason
+            };
+            let bastions: Vec<&Endpoint> = route.iter().filter(|e| e.bastion).collect();
+            let at = match (bastions.as_slice(), route.last()) {
+                ([from], Some(to)) if !to.bastion => At::Forward {
+                    from: Some(from.label.clone()),
+                    to: Some(to.label.clone()),
+                },
+                _ => At::Forward {
+                    from: None,
+                    to: None,
+                },
+            };
+            return Some(Diagnosis {
+                at,
+                cause: Cause::from_reason(reason),
+            });
+        }
+        if line == "Host key verification failed." {
+            // Named on an earlier line, e.g. "Host key for 52.17.9.3 has
+            // changed" or "No ED25519 host key is known for 52.17.9.3".
+            let at = route
+                .iter()
+                .find(|endpoint| {
+                    stderr.iter().any(|line| {
+                        line.to_ascii_lowercase().contains("host key")
+                            && line
+                                .split(|c: char| c.is_whitespace() || c == '\'' || c == '"')
+                                .any(|word| word.eq_ignore_ascii_case(&endpoint.address))
+                    })
+                })
+                .map_or(At::Unknown, |endpoint| hop(&endpoint.address));
+            return Some(Diagnosis {
+                at,
+                cause: Cause::HostKeyVerification,
+            });
+        }
+        if line.ends_with("Connection closed by remote host")
+            || line.starts_with("Connection closed by UNKNOWN")
+        {
+            return Some(Diagnosis {
+                at: At::Unknown,
+                cause: Cause::ClosedBeforeHandshake,
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route() -> Vec<Endpoint> {
+        vec![
+            Endpoint {
+                label: "jump-eu".into(),
+                address: "52.17.9.3".into(),
+                bastion: true,
+            },
+            Endpoint {
+                label: "staging-db".into(),
+                address: "35.12.2.4".into(),
+                bastion: false,
+            },
+        ]
+    }
+
+    fn diagnosed(fixture: &str) -> String {
+        let lines: Vec<String> = fixture.lines().map(str::to_string).collect();
+        diagnose(&lines, &route()).unwrap().to_string()
+    }
+
+    // Captured from OpenSSH 9.x with `-J ops@52.17.9.3 db@35.12.2.4 -p 2222`.
+    const JUMP_TIMED_OUT: &str = "\
+ssh: connect to host 52.17.9.3 port 22: Connection timed out
+Connection closed by UNKNOWN port 65535
+";
+    const TARGET_REFUSED_FROM_JUMP: &str = "\
+channel 0: open failed: connect failed: Connection refused
+stdio forwarding failed
+kex_exchange_identification: Connection closed by remote host
+Connection closed by UNKNOWN port 65535
+";
+    const JUMP_DENIED: &str = "\
+ops@52.17.9.3: Permission denied (publickey).
+Connection closed by UNKNOWN port 65535
+";
+    const TARGET_DENIED: &str = "db@35.12.2.4: Permission denied (publickey,password).\n";
+    const JUMP_UNRESOLVED: &str = "\
+ssh: Could not resolve hostname 52.17.9.3: Name or service not known
+Connection closed by UNKNOWN port 65535
+";
+    const FORWARDING_OFF: &str = "\
+channel 0: open failed: administratively prohibited: open failed
+stdio forwarding failed
+";
+    const HOST_KEY_CHANGED: &str = "\
+@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@
+@    WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!     @
+@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@
+Offending ED25519 key in /home/me/.ssh/known_hosts:12
+Host key for 35.12.2.4 has changed and you have requested strict checking.
+Host key verification failed.
+";
+
+    #[test]
+    fn failures_are_placed_on_the_hop_they_name() {
+        assert_eq!(
+            diagnosed(JUMP_TIMED_OUT),
+            "failed at bastion jump-eu (connection timed out)"
+        );
+        assert_eq!(
+            diagnosed(JUMP_DENIED),
+            "failed at bastion jump-eu (permission denied: publickey)"
+        );
+        assert_eq!(
+            diagnosed(TARGET_DENIED),
+            "failed at staging-db (permission denied: publickey,password)"
+        );
+        assert_eq!(
+            diagnosed(JUMP_UNRESOLVED),
+            "failed at bastion jump-eu (name does not resolve)"
+        );
+        assert_eq!(
+            diagnosed(HOST_KEY_CHANGED),
+            "failed at staging-db (host key verification failed)"
+        );
+    }
+
+    #[test]
+    fn forwarding_failures_name_both_ends_of_a_single_jump() {
+        assert_eq!(
+            diagnosed(TARGET_REFUSED_FROM_JUMP),
+            "bastion jump-eu could not reach staging-db (connection refused)"
+        );
+        assert_eq!(
+            diagnosed(FORWARDING_OFF),
+            "bastion jump-eu could not reach staging-db (forwarding not allowed)"
+        );
+
+        let mut deeper = route();
+        deeper.insert(
+            0,
+            Endpoint {
+                label: "edge".into(),
+                address: "edge.example.com".into(),
+                bastion: true,
+            },
+        );
+        let lines: Vec<String> = TARGET_REFUSED_FROM_JUMP
+            .lines()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(
+            diagnose(&lines, &deeper).unwrap().to_string(),
+            "a jump host could not reach the next hop (connection refused)"
+        );
+    }
+
+    #[test]
+    fn unknown_addresses_and_unrecognised_output() {
+        let lines = vec!["ssh: connect to host 10.1.1.1 port 22: No route to host".to_string()];
+        assert_eq!(
+            diagnose(&lines, &route()),
+            Some(Diagnosis {
+                at: At::Unknown,
+                cause: Cause::NoRoute
+            })
+        );
+        let lines = vec![
+            "kex_exchange_identification: Connection closed by remote host".to_string(),
+            "Connection closed by 52.17.9.3 port 22".to_string(),
+        ];
+        assert_eq!(
+            diagnose(&lines, &route()).unwrap().to_string(),
+            "connection closed before the handshake"
+        );
+        let lines = vec!["Connection to 35.12.2.4 closed.".to_string()];
+        assert_eq!(diagnose(&lines, &route()), None);
+        assert_eq!(diagnose(&[], &route()), None);
+    }
+}
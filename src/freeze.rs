@@ -0,0 +1,356 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Change-freeze windows (`freeze_window` on a host): weekly weekday and
+//! `HH:MM` ranges evaluated in the configured timezone.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+
+const DAY: u32 = 24 * 60;
+const WEEK: u32 = 7 * DAY;
+const DAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// A weekly range in minutes since Monday 00:00, end exclusive. `end <=
+/// start` wraps over the end of the week.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Window {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/// Parses comma-separated ranges:
+/// - `Fri 18:00-Sun 24:00`: one span across days;
+/// - `Mon-Fri 22:00-06:00` or `Sat 10:00-12:00`: the same hours on each day,
+///   running past midnight when the end is earlier than the start;
+/// - `Sat-Sun` or `Sun`: whole days.
+///
+/// Day names may be abbreviated to three letters; en dashes work as `-`.
+pub fn parse(spec: &str) -> Result<Vec<Window>> {
+    let mut windows = Vec::new();
+    for item in spec.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        windows.extend(parse_item(item).with_context(|| format!("freeze window '{item}'"))?);
+    }
+    if windows.is_empty() {
+        bail!("freeze window is empty");
+    }
+    Ok(windows)
+}
+
+fn parse_item(item: &str) -> Result<Vec<Window>> {
+    let normalized = item.replace(['–', '—'], "-");
+    let joined = normalized
+        .split('-')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("-");
+    let words: Vec<&str> = joined.split_whitespace().collect();
+    match words.as_slice() {
+        [days] => Ok(day_range(days)?
+            .into_iter()
+            .map(|day| Window {
+                start: day * DAY,
+                end: (day + 1) * DAY % WEEK,
+            })
+            .collect()),
+        [days, hours] => {
+            let (from, to) = hours
+                .split_once('-')
+                .context("expected HH:MM-HH:MM after the days")?;
+            let (from, to) = (time(from, false)?, time(to, true)?);
+            let length = if to > from {
+                to - from
+            } else {
+                to + DAY - from
+            };
+            Ok(day_range(days)?
+                .into_iter()
+                .map(|day| Window {
+                    start: day * DAY + from,
+                    end: (day * DAY + from + length) % WEEK,
+                })
+                .collect())
+        }
+        [start_day, middle, end_time] => {
+            let (start_time, end_day) = middle
+                .split_once('-')
+                .context("expected DAY HH:MM-DAY HH:MM")?;
+            let start = day(start_day)? * DAY + time(start_time, false)?;
+            let end = (day(end_day)? * DAY + time(end_time, true)?) % WEEK;
+            if start == end {
+                bail!("start and end are the same");
+            }
+            Ok(vec![Window { start, end }])
+        }
+        _ => bail!("expected 'DAY HH:MM-DAY HH:MM' or 'DAYS HH:MM-HH:MM'"),
+    }
+}
+
+/// `Mon-Fri` (wrapping, so `Fri-Mon` is four days) or a single day.
+fn day_range(spec: &str) -> Result<Vec<u32>> {
+    let (first, last) = match spec.split_once('-') {
+        Some((first, last)) => (day(first)?, day(last)?),
+        None => (day(spec)?, day(spec)?),
+    };
+    let count = (last + 7 - first) % 7 + 1;
+    Ok((0..count).map(|offset| (first + offset) % 7).collect())
+}
+
+fn day(name: &str) -> Result<u32> {
+    let lower = name.to_ascii_lowercase();
+    DAYS.iter()
+        .position(|full| lower.len() >= 3 && full.starts_with(&lower))
+        .map(|idx| idx as u32)
+        .with_context(|| format!("unknown day '{name}'"))
+}
+
+/// Minutes since midnight; `24:00` is only valid as an end.
+fn time(text: &str, is_end: bool) -> Result<u32> {
+    let (h, m) = text
+        .split_once(':')
+        .with_context(|| format!("expected HH:MM, got '{text}'"))?;
+    let (h, m): (u32, u32) = (
+        h.parse().with_context(|| format!("bad hour in '{text}'"))?,
+        m.parse()
+            .with_context(|| format!("bad minute in '{text}'"))?,
+    );
+    if m >= 60 || h > 24 || (h == 24 && (m != 0 || !is_end)) {
+        bail!("'{text}' is not a time of day");
+    }
+    Ok(h * 60 + m)
+}
+
+/// Where freeze windows are evaluated (`freeze_timezone` in the config).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Zone {
+    /// The machine's local time, daylight saving included.
+    Local,
+    /// A fixed offset from UTC in seconds (`UTC`, `+02:00`, `-0530`).
+    Fixed(i64),
+    /// An IANA zone such as `Asia/Tokyo` (a host's `timezone`).
+    Named(Tz),
+}
+
+impl Zone {
+    pub fn parse(spec: Option<&str>) -> Result<Self> {
+        let Some(spec) = spec.map(str::trim).filter(|s| !s.is_empty()) else {
+            return Ok(Zone::Local);
+        };
+        if spec.eq_ignore_ascii_case("local") {
+            return Ok(Zone::Local);
+        }
+        if spec.eq_ignore_ascii_case("utc") || spec == "Z" {
+            return Ok(Zone::Fixed(0));
+        }
+        let (sign, rest) = match spec.as_bytes()[0] {
+            b'+' => (1, &spec[1..]),
+            b'-' => (-1, &spec[1..]),
+            _ => bail!("freeze_timezone must be 'local', 'UTC' or an offset like +02:00"),
+        };
+        let digits = rest.replace(':', "");
+        let (h, m) = match digits.len() {
+            2 => (&digits[..2], "0"),
+            4 => (&digits[..2], &digits[2..]),
+            _ => bail!("freeze_timezone offset '{spec}' should look like +02:00"),
+        };
+        let (h, m): (i64, i64) = (
+            h.parse().context("freeze_timezone hours")?,
+            m.parse().context("freeze_timezone minutes")?,
+        );
+        if h > 14 || m >= 60 {
+            bail!("freeze_timezone offset '{spec}' is out of range");
+        }
+        Ok(Zone::Fixed(sign * (h * 3600 + m * 60)))
+    }
+
+    /// Seconds east of UTC at `unix` seconds.
+    pub fn offset_at(self, unix: i64) -> i64 {
+        match self {
+            Zone::Fixed(offset) => offset,
+            Zone::Local => local_offset(unix),
+            Zone::Named(tz) => DateTime::from_timestamp(unix, 0).map_or(0, |utc| {
+                i64::from(
+                    tz.offset_from_utc_datetime(&utc.naive_utc())
+                        .fix()
+                        .local_minus_utc(),
+                )
+            }),
+        }
+    }
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // time_t and c_long are 32 bits on some targets
+fn local_offset(unix: i64) -> i64 {
+    let time = unix as libc::time_t;
+    // SAFETY: localtime_r only writes into the tm we pass.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+#[cfg(not(unix))]
+fn local_offset(_unix: i64) -> i64 {
+    0
+}
+
+/// Minutes since Monday 00:00 local time, for `unix` seconds at `offset`
+/// seconds east of UTC.
+pub fn week_minute(unix: i64, offset: i64) -> u32 {
+    let local = unix + offset;
+    let minutes = local.div_euclid(60);
+    // 1970-01-01 was a Thursday, three days after a Monday.
+    (minutes + 3 * i64::from(DAY)).rem_euclid(i64::from(WEEK)) as u32
+}
+
+/// Whether `unix` falls inside any of `windows`, with `offset_at` giving the
+/// zone's UTC offset at that moment.
+pub fn active_at(windows: &[Window], unix: i64, offset_at: impl Fn(i64) -> i64) -> bool {
+    let minute = week_minute(unix, offset_at(unix));
+    windows.iter().any(|window| window.contains(minute))
+}
+
+/// Whether `spec` is in effect at `now` in `zone`.
+pub fn active_at_time(spec: &str, zone: Zone, now: SystemTime) -> Result<bool> {
+    let windows = parse(spec)?;
+    let now = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    Ok(active_at(&windows, now, |unix| zone.offset_at(unix)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monday 2024-03-25 00:00 UTC.
+    const MONDAY: i64 = 1_711_324_800;
+    const HOUR: i64 = 3600;
+
+    fn at(spec: &str, unix: i64, offset: i64) -> bool {
+        active_at(&parse(spec).unwrap(), unix, |_| offset)
+    }
+
+    #[test]
+    fn parses_spans_daily_ranges_and_whole_days() {
+        assert_eq!(
+            parse("Fri 18:00–Sun 24:00").unwrap(),
+            vec![Window {
+                start: 4 * DAY + 18 * 60,
+                end: 0
+            }]
+        );
+        let daily = parse("Mon-Wed 22:00 - 02:00").unwrap();
+        assert_eq!(daily.len(), 3);
+        assert_eq!(daily[2].start, 2 * DAY + 22 * 60);
+        assert_eq!(daily[2].end, 3 * DAY + 2 * 60);
+        assert_eq!(parse("friday-monday").unwrap().len(), 4);
+        assert_eq!(
+            parse("Sun, Sat 09:00-10:00").unwrap(),
+            vec![
+                Window {
+                    start: 6 * DAY,
+                    end: 0
+                },
+                Window {
+                    start: 5 * DAY + 9 * 60,
+                    end: 5 * DAY + 10 * 60
+                },
+            ]
+        );
+
+        for bad in [
+            "",
+            "Someday",
+            "Mo 10:00-11:00",
+            "Fri 25:00-26:00",
+            "Mon 24:00-Tue 01:00",
+            "Mon 10:00-Mon 10:00",
+            "Mon 10:00",
+        ] {
+            assert!(parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn evaluates_windows_including_week_wraparound() {
+        let spec = "Fri 18:00-Mon 06:00";
+        assert!(!at(spec, MONDAY + 4 * 24 * HOUR + 17 * HOUR, 0));
+        assert!(at(spec, MONDAY + 4 * 24 * HOUR + 18 * HOUR, 0));
+        assert!(at(spec, MONDAY + 7 * 24 * HOUR + 5 * HOUR, 0));
+        assert!(!at(spec, MONDAY + 7 * 24 * HOUR + 6 * HOUR, 0));
+        // Monday 01:00 UTC is Sunday 21:00 at -04:00.
+        assert!(at("Sun 20:00-22:00", MONDAY + HOUR, -4 * HOUR));
+        assert!(at(
+            "Mon-Fri 22:00-02:00",
+            MONDAY + 4 * 24 * HOUR + 25 * HOUR,
+            0
+        ));
+    }
+
+    /// Europe/Berlin in 2024: CET (+1) switches to CEST (+2) at 01:00 UTC on
+    /// 31 March and back at 01:00 UTC on 27 October.
+    fn berlin(unix: i64) -> i64 {
+        if (1_711_846_800..1_729_990_800).contains(&unix) {
+            2 * HOUR
+        } else {
+            HOUR
+        }
+    }
+
+    #[test]
+    fn follows_daylight_saving_transitions() {
+        let windows = parse("Sun 02:00-03:00").unwrap();
+        // Spring forward: local 02:00-03:00 never happens that night.
+        let spring = 1_711_846_800;
+        for unix in (spring - 2 * HOUR..spring + 2 * HOUR).step_by(60) {
+            assert!(!active_at(&windows, unix, berlin), "{unix}");
+        }
+        // Fall back: the local hour happens twice, so the window lasts two.
+        let fall = 1_729_990_800;
+        assert!(!active_at(&windows, fall - HOUR - 60, berlin));
+        assert!(active_at(&windows, fall - HOUR, berlin));
+        assert!(active_at(&windows, fall - 60, berlin));
+        assert!(active_at(&windows, fall, berlin));
+        assert!(active_at(&windows, fall + HOUR - 60, berlin));
+        assert!(!active_at(&windows, fall + HOUR, berlin));
+
+        let windows = parse("Sun 03:00-04:00").unwrap();
+        assert!(active_at(&windows, spring, berlin));
+        assert!(!active_at(&windows, spring - 60, berlin));
+    }
+
+    #[test]
+    fn parses_timezones() {
+        assert_eq!(Zone::parse(None).unwrap(), Zone::Local);
+        assert_eq!(Zone::parse(Some("local")).unwrap(), Zone::Local);
+        assert_eq!(Zone::parse(Some("UTC")).unwrap(), Zone::Fixed(0));
+        assert_eq!(Zone::parse(Some("+02:00")).unwrap(), Zone::Fixed(2 * HOUR));
+        assert_eq!(Zone::parse(Some("-0530")).unwrap(), Zone::Fixed(-19800));
+        assert!(Zone::parse(Some("Europe/Berlin")).is_err());
+        assert!(Zone::parse(Some("+25:00")).is_err());
+    }
+}
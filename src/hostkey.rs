@@ -0,0 +1,423 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Host key pinning. A host can carry the exact public key
+//! (`pinned_hostkey`) or its SHA256 fingerprint (`pinned_fingerprint`).
+//! Connecting then writes that key to a private known_hosts file which is
+//! the only one ssh consults for that invocation, with strict checking on,
+//! so whatever is in the user's known_hosts doesn't matter.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use crate::model::Host;
+
+/// Stands in for the temporary known_hosts file in argv previews; the real
+/// path only exists while connecting.
+pub const KNOWN_HOSTS_PLACEHOLDER: &str = "<pinned-known-hosts>";
+
+const KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ssh-dss",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+/// Seconds `ssh-keyscan` may take per host.
+const SCAN_TIMEOUT_SECS: &str = "5";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostKey {
+    /// Key type, e.g. `ssh-ed25519`.
+    pub kind: String,
+    /// The key in ssh wire format.
+    pub blob: Vec<u8>,
+}
+
+impl HostKey {
+    /// `SHA256:…`, as printed by `ssh-keygen -l` and ssh itself.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "SHA256:{}",
+            STANDARD_NO_PAD.encode(Sha256::digest(&self.blob))
+        )
+    }
+
+    /// The known_hosts line accepting this key for `pattern`.
+    fn known_hosts_line(&self, pattern: &str) -> String {
+        format!("{pattern} {} {}\n", self.kind, STANDARD.encode(&self.blob))
+    }
+}
+
+/// Parses a public key line: `type base64 [comment]`, optionally preceded by
+/// a host field as in known_hosts files and `ssh-keyscan` output.
+pub fn parse_key(line: &str) -> Result<HostKey> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let at = words
+        .iter()
+        .take(2)
+        .position(|word| KEY_TYPES.contains(word))
+        .ok_or_else(|| anyhow!("pinned host key must look like 'ssh-ed25519 AAAA…'"))?;
+    let kind = words[at];
+    let data = words
+        .get(at + 1)
+        .ok_or_else(|| anyhow!("pinned host key is missing the base64 key after {kind}"))?;
+    let blob = STANDARD
+        .decode(data)
+        .map_err(|_| anyhow!("pinned host key is not valid base64"))?;
+    // The blob starts with its own type as a length-prefixed string.
+    let embedded = blob
+        .get(..4)
+        .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+        .and_then(|len| blob.get(4..4 + len));
+    if embedded != Some(kind.as_bytes()) {
+        bail!("pinned host key data does not match its type {kind}");
+    }
+    Ok(HostKey {
+        kind: kind.to_string(),
+        blob,
+    })
+}
+
+/// Checks a `SHA256:…` fingerprint (43 base64 characters, `=` padding
+/// optional).
+pub fn validate_fingerprint(fingerprint: &str) -> Result<()> {
+    let digest = fingerprint
+        .strip_prefix("SHA256:")
+        .ok_or_else(|| anyhow!("pinned fingerprint must start with SHA256:"))?;
+    match STANDARD_NO_PAD.decode(digest.trim_end_matches('=')) {
+        Ok(bytes) if bytes.len() == 32 => Ok(()),
+        _ => bail!("pinned fingerprint must be SHA256: followed by 43 base64 characters"),
+    }
+}
+
+fn same_fingerprint(a: &str, b: &str) -> bool {
+    a.trim_end_matches('=') == b.trim_end_matches('=')
+}
+
+/// Whether the host pins its key in either form.
+pub fn is_pinned(host: &Host) -> bool {
+    host.pinned_hostkey.is_some() || host.pinned_fingerprint.is_some()
+}
+
+/// Validates the pin fields: both well-formed, agreeing with each other
+/// when both are set, and only on hosts connected through OpenSSH.
+pub fn validate(host: &Host) -> Result<()> {
+    let key = host.pinned_hostkey.as_deref().map(parse_key).transpose()?;
+    if let Some(fingerprint) = &host.pinned_fingerprint {
+        validate_fingerprint(fingerprint)?;
+        if let Some(key) = &key {
+            if !same_fingerprint(&key.fingerprint(), fingerprint) {
+                bail!(
+                    "pinned fingerprint {fingerprint} does not match the pinned host key ({})",
+                    key.fingerprint()
+                );
+            }
+        }
+    }
+    if is_pinned(host) && host.command_template.is_some() {
+        bail!("a pinned host key needs ssh; it can't be combined with a command template");
+    }
+    Ok(())
+}
+
+/// How known_hosts names the host: the address, with the port in brackets
+/// when it isn't 22.
+pub fn known_hosts_pattern(host: &Host) -> String {
+    match host.port {
+        Some(port) if port != 22 => format!("[{}]:{port}", host.address),
+        _ => host.address.clone(),
+    }
+}
+
+/// The scanned key matching `fingerprint`, or an error listing what the
+/// host offered instead.
+fn matching_key(host: &Host, scanned: Vec<HostKey>, fingerprint: &str) -> Result<HostKey> {
+    if scanned.is_empty() {
+        bail!(
+            "could not fetch the host key of '{}' to check its pinned fingerprint",
+            host.name
+        );
+    }
+    let offered: Vec<String> = scanned.iter().map(HostKey::fingerprint).collect();
+    scanned
+        .into_iter()
+        .find(|key| same_fingerprint(&key.fingerprint(), fingerprint))
+        .ok_or_else(|| {
+            anyhow!(
+                "refusing to connect: '{}' offered {}, not the pinned {fingerprint}",
+                host.name,
+                offered.join(", ")
+            )
+        })
+}
+
+/// Asks the host for its keys with `ssh-keyscan`. The scan goes straight to
+/// the address, so it can't see hosts only reachable through a bastion.
+fn scan(host: &Host) -> Result<Vec<HostKey>> {
+    let mut cmd = Command::new("ssh-keyscan");
+    cmd.args(["-T", SCAN_TIMEOUT_SECS]);
+    if let Some(port) = host.port {
+        cmd.args(["-p", &port.to_string()]);
+    }
+    let out = cmd
+        .arg(&host.address)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .context("failed to run ssh-keyscan")?;
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| parse_key(line).ok())
+        .collect())
+}
+
+/// The key ssh must see: the pinned key itself, or the scanned key that
+/// matches the pinned fingerprint. `None` when nothing is pinned.
+fn resolve(host: &Host) -> Result<Option<HostKey>> {
+    validate(host)?;
+    if let Some(line) = &host.pinned_hostkey {
+        return parse_key(line).map(Some);
+    }
+    let Some(fingerprint) = &host.pinned_fingerprint else {
+        return Ok(None);
+    };
+    if host.bastion.is_some() {
+        bail!(
+            "can't check the pinned fingerprint of '{}' through its bastion; pin the full key with pinned_hostkey",
+            host.name
+        );
+    }
+    matching_key(host, scan(host)?, fingerprint).map(Some)
+}
+
+/// A private known_hosts file holding one pinned key, removed on drop.
+#[derive(Debug)]
+pub struct KnownHostsFile {
+    path: PathBuf,
+}
+
+impl KnownHostsFile {
+    fn write(dir: &Path, host: &Host, key: &HostKey) -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = dir.join(format!(
+            "sshdb-known-hosts-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        // Owned from here on, so a failed write still removes the file.
+        let pinned = Self { path };
+        file.write_all(key.known_hosts_line(&known_hosts_pattern(host)).as_bytes())
+            .with_context(|| format!("failed to write {}", pinned.path.display()))?;
+        Ok(pinned)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for KnownHostsFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Prepares a pinned host's argv for running: writes its known_hosts file
+/// and puts the real path where `build_argv` left the placeholder. The file
+/// has to outlive the ssh process.
+pub fn pin(host: &Host, argv: &mut [String]) -> Result<Option<KnownHostsFile>> {
+    pin_in(&std::env::temp_dir(), host, argv, resolve)
+}
+
+fn pin_in(
+    dir: &Path,
+    host: &Host,
+    argv: &mut [String],
+    resolve: impl Fn(&Host) -> Result<Option<HostKey>>,
+) -> Result<Option<KnownHostsFile>> {
+    let Some(key) = resolve(host)? else {
+        return Ok(None);
+    };
+    let file = KnownHostsFile::write(dir, host, &key)?;
+    let placeholder = format!("UserKnownHostsFile={KNOWN_HOSTS_PLACEHOLDER}");
+    let path = file.path().display().to_string();
+    let path = if path.contains(char::is_whitespace) {
+        format!("\"{path}\"")
+    } else {
+        path
+    };
+    // Matched inside words too: mosh carries ssh's options in `--ssh=…`.
+    for arg in argv.iter_mut().filter(|arg| arg.contains(&placeholder)) {
+        *arg = arg.replace(&placeholder, &format!("UserKnownHostsFile={path}"));
+    }
+    Ok(Some(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIDivEachRsVlR+hRghtml286It6zmvTRqafHWfy7or0a root@vm";
+    const FINGERPRINT: &str = "SHA256:3h6xuW2xYTVydvu/kjnVk62DBTWaShkBYixW27T5BrU";
+
+    fn pinned(key: Option<&str>, fingerprint: Option<&str>) -> Host {
+        Host {
+            name: "vault".into(),
+            address: "10.0.0.5".into(),
+            pinned_hostkey: key.map(Into::into),
+            pinned_fingerprint: fingerprint.map(Into::into),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_keys_and_computes_ssh_keygen_fingerprints() {
+        let key = parse_key(KEY).unwrap();
+        assert_eq!(key.kind, "ssh-ed25519");
+        assert_eq!(key.fingerprint(), FINGERPRINT);
+        // ssh-keyscan / known_hosts lines carry the host first.
+        assert_eq!(parse_key(&format!("10.0.0.5 {KEY}")).unwrap(), key);
+
+        assert!(parse_key("AAAAC3NzaC1lZDI1NTE5").is_err());
+        assert!(parse_key("ssh-ed25519").is_err());
+        assert!(parse_key("ssh-ed25519 not*base64").is_err());
+        // Valid base64, but the blob says it's an RSA key.
+        let rsa_blob = STANDARD.encode(b"\0\0\0\x07ssh-rsa");
+        assert!(parse_key(&format!("ssh-ed25519 {rsa_blob}")).is_err());
+    }
+
+    #[test]
+    fn validates_fingerprints() {
+        validate_fingerprint(FINGERPRINT).unwrap();
+        validate_fingerprint(&format!("{FINGERPRINT}=")).unwrap();
+        assert!(validate_fingerprint("3h6xuW2xYTVydvu/kjnVk62DBTWaShkBYixW27T5BrU").is_err());
+        assert!(validate_fingerprint("SHA256:tooshort").is_err());
+        assert!(validate_fingerprint("MD5:3h:6x").is_err());
+    }
+
+    #[test]
+    fn validate_checks_that_key_and_fingerprint_agree() {
+        validate(&pinned(Some(KEY), Some(FINGERPRINT))).unwrap();
+        validate(&pinned(Some(KEY), None)).unwrap();
+        validate(&pinned(None, None)).unwrap();
+        let other = "SHA256:AAAxuW2xYTVydvu/kjnVk62DBTWaShkBYixW27T5BrU";
+        let err = validate(&pinned(Some(KEY), Some(other))).unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{err}");
+
+        let mut templated = pinned(None, Some(FINGERPRINT));
+        templated.command_template = Some("tsh ssh {address}".into());
+        assert!(validate(&templated).is_err());
+    }
+
+    #[test]
+    fn known_hosts_pattern_brackets_non_default_ports() {
+        let mut host = pinned(None, None);
+        assert_eq!(known_hosts_pattern(&host), "10.0.0.5");
+        host.port = Some(22);
+        assert_eq!(known_hosts_pattern(&host), "10.0.0.5");
+        host.port = Some(2222);
+        assert_eq!(known_hosts_pattern(&host), "[10.0.0.5]:2222");
+    }
+
+    #[test]
+    fn scanned_keys_must_match_the_fingerprint() {
+        let host = pinned(None, Some(FINGERPRINT));
+        let key = parse_key(KEY).unwrap();
+        let other = HostKey {
+            kind: "ssh-rsa".into(),
+            blob: b"\0\0\0\x07ssh-rsa".to_vec(),
+        };
+        let found = matching_key(&host, vec![other.clone(), key.clone()], FINGERPRINT).unwrap();
+        assert_eq!(found, key);
+
+        let err = matching_key(&host, vec![other.clone()], FINGERPRINT).unwrap_err();
+        assert!(err.to_string().contains("refusing to connect"), "{err}");
+        assert!(err.to_string().contains(&other.fingerprint()), "{err}");
+        assert!(matching_key(&host, Vec::new(), FINGERPRINT).is_err());
+    }
+
+    #[test]
+    fn pin_writes_a_private_known_hosts_file_and_removes_it_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut host = pinned(Some(KEY), None);
+        host.port = Some(2222);
+        let mut argv: Vec<String> = [
+            "ssh",
+            "-o",
+            &format!("UserKnownHostsFile={KNOWN_HOSTS_PLACEHOLDER}"),
+            "10.0.0.5",
+        ]
+        .map(String::from)
+        .to_vec();
+
+        let file = pin_in(dir.path(), &host, &mut argv, resolve)
+            .unwrap()
+            .unwrap();
+        let path = file.path().to_path_buf();
+        assert_eq!(argv[2], format!("UserKnownHostsFile={}", path.display()));
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            format!("[10.0.0.5]:2222 {}\n", KEY.trim_end_matches(" root@vm"))
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        drop(file);
+        assert!(!path.exists());
+
+        // Nothing pinned: nothing written, argv untouched.
+        let before = argv.clone();
+        assert!(pin_in(dir.path(), &pinned(None, None), &mut argv, resolve)
+            .unwrap()
+            .is_none());
+        assert_eq!(argv, before);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn fingerprint_mismatch_refuses_before_writing_anything() {
+        let dir = tempfile::tempdir().unwrap();
+        let host = pinned(None, Some(FINGERPRINT));
+        let mut argv = vec!["ssh".to_string()];
+        let scanned = |host: &Host| {
+            let other = HostKey {
+                kind: "ssh-rsa".into(),
+                blob: b"\0\0\0\x07ssh-rsa".to_vec(),
+            };
+            matching_key(host, vec![other], FINGERPRINT).map(Some)
+        };
+        assert!(pin_in(dir.path(), &host, &mut argv, scanned).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let mut behind_bastion = host.clone();
+        behind_bastion.bastion = Some("jump".into());
+        let err = resolve(&behind_bastion).unwrap_err();
+        assert!(err.to_string().contains("pinned_hostkey"), "{err}");
+    }
+}
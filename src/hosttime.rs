@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! The time where a host is: its `timezone` (an IANA name, checked against
+//! the tz database bundled with the binary) and its `quiet_hours`, weekly
+//! ranges in the `freeze_window` syntax evaluated on that zone's clock.
+
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use chrono_tz::{Tz, TZ_VARIANTS};
+
+use crate::freeze::{self, Zone};
+use crate::text::distance;
+
+/// Names offered when a timezone isn't known.
+const SUGGESTIONS: usize = 3;
+
+/// Parses an IANA zone name; unknown names are refused with the closest
+/// known ones.
+pub fn parse_zone(name: &str) -> Result<Tz> {
+    let name = name.trim();
+    if let Ok(tz) = Tz::from_str(name) {
+        return Ok(tz);
+    }
+    let close = suggestions(name);
+    if close.is_empty() {
+        bail!("unknown timezone '{name}'; use an IANA name such as Europe/Berlin");
+    }
+    bail!(
+        "unknown timezone '{name}' (did you mean {}?)",
+        close.join(", ")
+    )
+}
+
+/// Known zones that differ from `name` only in case, share its city, or
+/// are a couple of typos away.
+fn suggestions(name: &str) -> Vec<&'static str> {
+    let lower = name.to_ascii_lowercase();
+    let city = lower.rsplit('/').next().unwrap_or(&lower);
+    let mut scored: Vec<(usize, &'static str)> = TZ_VARIANTS
+        .iter()
+        .map(|tz| tz.name())
+        .filter_map(|known| {
+            let known_lower = known.to_ascii_lowercase();
+            let known_city = known_lower.rsplit('/').next().unwrap_or(&known_lower);
+            let score = if known_lower == lower || known_city == city {
+                0
+            } else {
+                distance(&known_lower, &lower).min(distance(known_city, city))
+            };
+            (score <= 2).then_some((score, known))
+        })
+        .collect();
+    scored.sort();
+    scored
+        .into_iter()
+        .take(SUGGESTIONS)
+        .map(|(_, known)| known)
+        .collect()
+}
+
+/// `HH:MM` on the wall clock of `tz` at `unix` seconds.
+pub fn local_time(tz: Tz, unix: i64) -> String {
+    let minute = freeze::week_minute(unix, Zone::Named(tz).offset_at(unix)) % (24 * 60);
+    format!("{:02}:{:02}", minute / 60, minute % 60)
+}
+
+/// Whether `unix` falls inside the `quiet_hours` ranges `spec`, read in
+/// `tz` or, without one, in the machine's local time.
+pub fn in_quiet_hours(spec: &str, tz: Option<Tz>, unix: i64) -> Result<bool> {
+    let zone = tz.map_or(Zone::Local, Zone::Named);
+    let windows = freeze::parse(spec)?;
+    Ok(freeze::active_at(&windows, unix, |at| zone.offset_at(at)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-03-10 07:00 UTC: New York springs from 02:00 EST to 03:00 EDT.
+    const NEW_YORK_SPRING: i64 = 1_710_054_000;
+    /// 2024-10-27 01:00 UTC: Berlin falls back from 03:00 CEST to 02:00 CET.
+    const BERLIN_AUTUMN: i64 = 1_729_990_800;
+    const MINUTE: i64 = 60;
+
+    #[test]
+    fn local_time_follows_dst_transitions() {
+        let new_york = parse_zone("America/New_York").unwrap();
+        assert_eq!(local_time(new_york, NEW_YORK_SPRING - MINUTE), "01:59");
+        assert_eq!(local_time(new_york, NEW_YORK_SPRING), "03:00");
+
+        let berlin = parse_zone("Europe/Berlin").unwrap();
+        assert_eq!(local_time(berlin, BERLIN_AUTUMN - MINUTE), "02:59");
+        assert_eq!(local_time(berlin, BERLIN_AUTUMN), "02:00");
+        assert_eq!(
+            local_time(parse_zone("Asia/Tokyo").unwrap(), BERLIN_AUTUMN),
+            "10:00"
+        );
+    }
+
+    #[test]
+    fn quiet_hours_are_read_on_the_hosts_clock() {
+        let berlin = Some(parse_zone("Europe/Berlin").unwrap());
+        // 02:30 happens twice that night, and both are quiet.
+        for unix in [BERLIN_AUTUMN - 30 * MINUTE, BERLIN_AUTUMN + 30 * MINUTE] {
+            assert!(in_quiet_hours("Sun 02:30-02:45", berlin, unix).unwrap());
+        }
+        assert!(!in_quiet_hours("Sun 02:30-02:45", berlin, BERLIN_AUTUMN).unwrap());
+
+        // The skipped hour never comes; 03:00 EDT is already past it.
+        let new_york = Some(parse_zone("America/New_York").unwrap());
+        assert!(!in_quiet_hours("Sun 02:00-03:00", new_york, NEW_YORK_SPRING).unwrap());
+        assert!(in_quiet_hours("Mon-Sun 22:00-07:00", new_york, NEW_YORK_SPRING).unwrap());
+        assert!(in_quiet_hours("Someday", new_york, NEW_YORK_SPRING).is_err());
+    }
+
+    #[test]
+    fn unknown_zones_are_refused_with_suggestions() {
+        let err = parse_zone("Asia/Tokio").unwrap_err().to_string();
+        assert!(err.contains("did you mean Asia/Tokyo"), "{err}");
+        let err = parse_zone("europe/berlin").unwrap_err().to_string();
+        assert!(err.contains("Europe/Berlin"), "{err}");
+        let err = parse_zone("Tokyo").unwrap_err().to_string();
+        assert!(err.contains("Asia/Tokyo"), "{err}");
+        let err = parse_zone("Nowhere/Qqqqqqq").unwrap_err().to_string();
+        assert!(err.contains("IANA name"), "{err}");
+    }
+}
@@ -0,0 +1,200 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Unix job control for ssh sessions. The child runs in its own process
+//! group and owns the terminal for the duration of the session, so Ctrl+C,
+//! Ctrl+Z (`~^Z` in ssh) and SIGWINCH reach ssh instead of sshdb, and
+//! signalling sshdb's group no longer takes a live session down with it.
+//!
+//! Manual checks (none of this is observable without a real terminal):
+//! - `~^Z` inside a session suspends sshdb as one job; `fg` resumes ssh
+//!   with the right window size, and quitting ssh returns to the TUI.
+//! - Ctrl+C inside a session only reaches ssh; sshdb keeps running.
+//! - Resizing during a session is picked up by ssh, and the TUI redraws at
+//!   the new size afterwards.
+//! - `kill -TSTP <sshdb pid>` during a session leaves ssh usable; once sshdb
+//!   is continued it returns to the TUI when ssh exits.
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus};
+
+use anyhow::{Context, Result};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WaitOutcome {
+    Finished(ExitStatus),
+    Stopped,
+}
+
+/// The controlling terminal as seen from sshdb's own process group.
+pub struct TtyOwner {
+    fd: RawFd,
+    pgrp: libc::pid_t,
+}
+
+impl TtyOwner {
+    /// `None` unless `fd` is a terminal whose foreground group is ours
+    /// (pipes, background jobs and test runners fall back to a plain spawn).
+    pub fn foreground(fd: RawFd) -> Option<Self> {
+        unsafe {
+            if libc::isatty(fd) != 1 {
+                return None;
+            }
+            let pgrp = libc::getpgrp();
+            (libc::tcgetpgrp(fd) == pgrp).then_some(Self { fd, pgrp })
+        }
+    }
+
+    fn give_to(&self, pgrp: libc::pid_t) -> io::Result<()> {
+        set_foreground(self.fd, pgrp)
+    }
+
+    fn reclaim(&self) -> io::Result<()> {
+        set_foreground(self.fd, self.pgrp)
+    }
+}
+
+/// `tcsetpgrp` from a background group raises SIGTTOU; block it for the call.
+/// Only uses async-signal-safe calls, so it is usable from `pre_exec`.
+fn set_foreground(fd: RawFd, pgrp: libc::pid_t) -> io::Result<()> {
+    unsafe {
+        let mut block = MaybeUninit::<libc::sigset_t>::uninit();
+        let mut previous = MaybeUninit::<libc::sigset_t>::uninit();
+        libc::sigemptyset(block.as_mut_ptr());
+        libc::sigaddset(block.as_mut_ptr(), libc::SIGTTOU);
+        libc::pthread_sigmask(libc::SIG_BLOCK, block.as_ptr(), previous.as_mut_ptr());
+        let rc = libc::tcsetpgrp(fd, pgrp);
+        let err = io::Error::last_os_error();
+        libc::pthread_sigmask(libc::SIG_SETMASK, previous.as_ptr(), std::ptr::null_mut());
+        if rc == 0 {
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+}
+
+/// Makes the child lead a new process group and, when `tty` is given, take
+/// the terminal before exec. The parent repeats both steps after spawning,
+/// so neither side races the other.
+pub fn place_in_own_group(cmd: &mut Command, tty: Option<RawFd>) {
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(fd) = tty {
+                set_foreground(fd, libc::getpid())?;
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Waits for `pid` to exit or stop, retrying on EINTR.
+pub fn wait_child(pid: libc::pid_t) -> io::Result<WaitOutcome> {
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) } == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if libc::WIFSTOPPED(status) {
+            return Ok(WaitOutcome::Stopped);
+        }
+        return Ok(WaitOutcome::Finished(ExitStatus::from_raw(status)));
+    }
+}
+
+/// Runs `cmd` as the terminal's foreground job and hands the terminal back
+/// to sshdb afterwards, whatever happened to the child.
+pub fn run_in_foreground(mut cmd: Command, tty: &TtyOwner) -> Result<ExitStatus> {
+    place_in_own_group(&mut cmd, Some(tty.fd));
+    let child = cmd.spawn().with_context(|| "failed to start ssh")?;
+    let pid = child.id() as libc::pid_t;
+    unsafe {
+        // Fails with EACCES once the child has exec'd; it did this itself then.
+        libc::setpgid(pid, pid);
+    }
+    let _ = tty.give_to(pid);
+    let result = supervise(tty, pid);
+    tty.reclaim()
+        .with_context(|| "failed to take the terminal back from ssh")?;
+    result
+}
+
+fn supervise(tty: &TtyOwner, pid: libc::pid_t) -> Result<ExitStatus> {
+    loop {
+        match wait_child(pid).with_context(|| "failed to wait for ssh")? {
+            WaitOutcome::Finished(status) => return Ok(status),
+            WaitOutcome::Stopped => {
+                // ssh suspended itself: stop sshdb too so the shell sees one
+                // job, then resume ssh when the shell continues us.
+                tty.reclaim()?;
+                unsafe {
+                    libc::raise(libc::SIGSTOP);
+                }
+                tty.give_to(pid)?;
+                unsafe {
+                    libc::kill(-pid, libc::SIGCONT);
+                    // The window may have changed while ssh was stopped.
+                    libc::kill(-pid, libc::SIGWINCH);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pipes_are_not_a_foreground_terminal() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        assert!(TtyOwner::foreground(fds[0]).is_none());
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+
+    #[test]
+    fn child_leads_its_own_group_and_reports_exit_code() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "sleep 0.2; exit 3"]);
+        place_in_own_group(&mut cmd, None);
+        // Reaped through `wait_child`, not `Child::wait`.
+        let pid = cmd.spawn().unwrap().id() as libc::pid_t;
+        assert_eq!(unsafe { libc::getpgid(pid) }, pid);
+        assert_ne!(pid, unsafe { libc::getpgrp() });
+        match wait_child(pid).unwrap() {
+            WaitOutcome::Finished(status) => assert_eq!(status.code(), Some(3)),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn reports_stopped_child_and_resumes_it() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "kill -STOP $$; exit 0"]);
+        place_in_own_group(&mut cmd, None);
+        // Reaped through `wait_child`, not `Child::wait`.
+        let pid = cmd.spawn().unwrap().id() as libc::pid_t;
+        assert_eq!(wait_child(pid).unwrap(), WaitOutcome::Stopped);
+        unsafe {
+            libc::kill(-pid, libc::SIGCONT);
+        }
+        match wait_child(pid).unwrap() {
+            WaitOutcome::Finished(status) => assert!(status.success()),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! The host database, the TUI state machine (`app`) and its rendering
+//! (`ui`). The `sshdb` binary is a thin terminal frontend over them;
+//! `script` drives the same code from tests and demos.
+
+pub mod app;
+pub mod autotag;
+pub mod bulk;
+pub mod bundle;
+pub mod cli;
+pub mod clipboard;
+pub mod config;
+pub mod config_edit;
+pub mod debuglog;
+pub mod exec;
+pub mod export;
+pub mod failure;
+pub mod freeze;
+pub mod hostkey;
+pub mod hosttime;
+#[cfg(unix)]
+pub mod job;
+pub mod model;
+pub mod net;
+pub mod overlay;
+pub mod preflight;
+pub mod privacy;
+pub mod problems;
+pub mod program;
+pub mod prompt;
+pub mod runloop;
+pub mod script;
+pub mod series;
+pub mod ssh;
+pub mod ssh_config;
+pub mod state;
+pub mod status;
+pub mod term;
+pub mod text;
+pub mod ui;
+pub mod watch;
+
+/// Serializes tests that mutate process-wide environment variables.
+#[cfg(test)]
+pub(crate) static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
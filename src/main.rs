@@ -1,52 +1,271 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
 
-mod app;
-mod clipboard;
-mod config;
-mod model;
-mod ssh;
-mod ui;
-
-use std::io;
-use std::time::Duration;
-
-use anyhow::Result;
-use app::{App, AppAction, StatusKind, StatusLine};
-use config::ConfigStore;
-use crossterm::event::{
-    self, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
-};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use crossterm::event;
 use crossterm::execute;
-use crossterm::terminal::{
-    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
-};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
+use sshdb::app::{App, AppAction, Mode};
+use sshdb::cli::{Cli, CliAction};
+use sshdb::config::ConfigStore;
+use sshdb::model::Host;
+use sshdb::runloop::{LoopConfig, Pacer};
+use sshdb::status::{StatusKind, StatusLine};
+use sshdb::term::{self, TerminalFeatures};
+use sshdb::{bundle, debuglog, exec, prompt, ssh, ui};
 
 fn main() {
-    if let Err(e) = start() {
+    // Started by ssh as SSH_ASKPASS for a host with a password_command.
+    if let Some(command) = ssh::askpass_request() {
+        let prompt = std::env::args().nth(1).unwrap_or_default();
+        let code = ssh::answer_askpass(
+            &command,
+            &prompt,
+            &mut ssh::ask_tty,
+            &mut std::io::stdout().lock(),
+        );
+        std::process::exit(code);
+    }
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("sshdb: {e}");
+            std::process::exit(2);
+        }
+    };
+    // `doctor` reads the log; it doesn't add to it.
+    if !matches!(cli.action, CliAction::Doctor { .. }) {
+        if let Err(e) = start_logging(&cli) {
+            eprintln!("sshdb: {e:#}");
+            std::process::exit(2);
+        }
+    }
+    if let CliAction::Exec(args) = &cli.action {
+        match exec::run(args, &cli.store()) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                debuglog::error("main", "exec failed", &[("error", &format!("{e:#}"))]);
+                eprintln!("sshdb exec: {e:#}");
+                std::process::exit(2);
+            }
+        }
+    }
+    if let CliAction::Connect { host, profile } = &cli.action {
+        match connect(host, profile.as_deref(), &cli.store()) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                debuglog::error("main", "connect failed", &[("error", &format!("{e:#}"))]);
+                eprintln!("sshdb connect: {e:#}");
+                std::process::exit(2);
+            }
+        }
+    }
+    if let CliAction::Bundle(command) = &cli.action {
+        match bundle::run(command, &cli.store()) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                debuglog::error("main", "bundle failed", &[("error", &format!("{e:#}"))]);
+                eprintln!("sshdb bundle: {e:#}");
+                std::process::exit(2);
+            }
+        }
+    }
+    match cli.info_output() {
+        Ok(Some(text)) => {
+            println!("{text}");
+            return;
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("sshdb error: {e:?}");
+            std::process::exit(1);
+        }
+    }
+    if let Err(e) = start(&cli) {
+        debuglog::error("main", "exiting on error", &[("error", &format!("{e:#}"))]);
         eprintln!("sshdb error: {e:?}");
         std::process::exit(1);
     }
+    debuglog::info("main", "exit", &[]);
 }
 
-fn start() -> Result<()> {
-    let mut guard = TerminalGuard::new()?;
-    let res = run_loop(guard.terminal());
-    guard.restore()?;
-    res
+/// `sshdb connect NAME`: builds the command the TUI would and runs it here,
+/// looking in `profile` (`None`: the default one).
+/// Returns ssh's exit status; a failed session also gets the diagnosis.
+fn connect(name: &str, profile: Option<&str>, store: &ConfigStore) -> Result<i32> {
+    let mut config = store.load_read_only()?;
+    // Where else the host is, for the error, before the profiles move.
+    let has = |hosts: &[Host]| hosts.iter().any(|h| h.id() == name || h.name == name);
+    let mut elsewhere: Vec<String> = config
+        .profiles
+        .iter()
+        .filter(|p| Some(p.name.as_str()) != profile && has(&p.hosts))
+        .map(|p| format!("--profile {}", p.name))
+        .collect();
+    if profile.is_some() && has(&config.hosts) {
+        elsewhere.insert(0, "without --profile".to_string());
+    }
+    config.switch_profile(profile)?;
+    let host = config.resolve_host(name, None)?.with_context(|| {
+        let mut message = format!("no host named '{name}' in {}", store.path().display());
+        if let Some(profile) = profile {
+            message.push_str(&format!(" (profile '{profile}')"));
+        }
+        if !elsewhere.is_empty() {
+            message.push_str(&format!("; try {}", elsewhere.join(" or ")));
+        }
+        message
+    })?;
+    let cmd = ssh::build_command(host, &config, config.default_key.as_deref(), None)?;
+    match ssh::run_command(cmd) {
+        Ok(()) => Ok(0),
+        Err(err) => match err.downcast_ref::<ssh::SessionFailed>() {
+            Some(failed) => {
+                eprintln!("sshdb connect: {failed}");
+                Ok(failed.status.code().unwrap_or(255))
+            }
+            None => Err(err),
+        },
+    }
+}
+
+/// Starts the debug log when asked for, and logs panics to it before the
+/// default hook prints them.
+fn start_logging(cli: &Cli) -> Result<()> {
+    let Some(settings) = cli.log_settings()? else {
+        return Ok(());
+    };
+    debuglog::init(&settings)?;
+    debuglog::debug("main", "start", &[("action", &format!("{:?}", cli.action))]);
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        debuglog::error("main", "panic", &[("info", info)]);
+        default_hook(info);
+    }));
+    Ok(())
 }
 
+fn start(cli: &Cli) -> Result<()> {
+    let store = cli.store();
+    let mut frontend: Box<dyn Frontend> = if cli.basic {
+        debuglog::info("main", "frontend", &[("kind", &"basic")]);
+        Box::new(BasicFrontend)
+    } else {
+        match TerminalGuard::new() {
+            Ok(guard) => {
+                debuglog::info("main", "frontend", &[("kind", &"full-screen")]);
+                Box::new(FullScreenFrontend {
+                    guard,
+                    launcher: cli.launcher,
+                })
+            }
+            Err(err) => {
+                let _ = disable_raw_mode();
+                debuglog::warn(
+                    "main",
+                    "full-screen terminal unavailable, using basic mode",
+                    &[("error", &format!("{err:#}"))],
+                );
+                eprintln!("sshdb: full-screen terminal unavailable ({err}); using basic mode");
+                Box::new(BasicFrontend)
+            }
+        }
+    };
+    frontend.run(store, &cli.overlays)
+}
+
+/// A way of driving the UI on the current terminal.
+trait Frontend {
+    fn run(&mut self, store: ConfigStore, overlays: &[PathBuf]) -> Result<()>;
+}
+
+/// The regular ratatui interface on the alternate screen.
+struct FullScreenFrontend {
+    guard: TerminalGuard,
+    launcher: bool,
+}
+
+impl Frontend for FullScreenFrontend {
+    fn run(&mut self, store: ConfigStore, overlays: &[PathBuf]) -> Result<()> {
+        // Show something before touching the config so slow home directories
+        // don't leave a blank terminal.
+        let path = store.path().to_path_buf();
+        self.guard
+            .terminal()
+            .draw(|f| ui::render_loading(f, &path))?;
+        let res = App::new(store).and_then(|mut app| {
+            app.load_overlays(overlays)?;
+            if self.launcher {
+                app.mode = Mode::Launcher;
+            }
+            app.check_ssh_binary();
+            // Only asks in the normal list; the launcher stays a picker.
+            app.suggest_jump_hosts();
+            // Housekeeping runs on its own thread, so the first frame is not delayed.
+            app.start_housekeeping();
+            run_loop(self.guard.terminal(), &mut app)
+        });
+        self.guard.restore()?;
+        res
+    }
+}
+
+/// Prints into the scrollback and reads commands line by line.
+struct BasicFrontend;
+
+impl Frontend for BasicFrontend {
+    fn run(&mut self, store: ConfigStore, overlays: &[PathBuf]) -> Result<()> {
+        let mut app = App::new(store)?;
+        app.load_overlays(overlays)?;
+        let app = &mut app;
+        let stdin = io::stdin();
+        let mut stdout = io::stdout();
+        let mut session = prompt::PromptSession::default();
+        let mut out = String::new();
+        prompt::render_list(app, &mut out);
+        if !app.check_ssh_binary() {
+            if let Some(message) = &app.ssh_unavailable {
+                out.push_str(&format!("error: {message}\n"));
+            }
+        }
+        out.push_str("Type ? for help.\n");
+        loop {
+            write!(stdout, "{out}{}", session.prompt())?;
+            stdout.flush()?;
+            out.clear();
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            match session.handle_line(app, &line, &mut out)? {
+                prompt::PromptOutcome::Continue => {}
+                prompt::PromptOutcome::Quit => return Ok(()),
+                prompt::PromptOutcome::Run(cmd) => {
+                    let host = cmd.host_id().to_string();
+                    let result = ssh::run_command(cmd);
+                    if let Some(message) = app.session_ended(&host, &result) {
+                        out.push_str(&format!("{message}\n"));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Terminal modes the full-screen UI turns on; restored the same way after
+/// every ssh session.
+const FEATURES: TerminalFeatures = TerminalFeatures::FULL_SCREEN;
+
 fn setup_terminal() -> Result<Terminal<CrosstermBackend<io::Stdout>>> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(
-        stdout,
-        EnterAlternateScreen,
-        // Keep kitty keyboard protocol scoped to the TUI session.
-        PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
-    )?;
+    FEATURES.enter(&mut stdout)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     terminal.clear()?;
@@ -55,13 +274,17 @@ fn setup_terminal() -> Result<Terminal<CrosstermBackend<io::Stdout>>> {
 
 fn restore_terminal(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<()> {
     disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        // Pop before leaving the alternate screen to avoid leaking CSI u sequences.
-        PopKeyboardEnhancementFlags,
-        LeaveAlternateScreen
-    )?;
-    terminal.show_cursor()?;
+    FEATURES.leave(terminal.backend_mut())?;
+    Ok(())
+}
+
+/// Takes the terminal back from ssh: modes on again, cursor and screen reset,
+/// and the next frame painted in full so nothing from the session shows
+/// through.
+fn resume_terminal(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<()> {
+    enable_raw_mode()?;
+    FEATURES.resume(terminal.backend_mut())?;
+    term::force_full_redraw(terminal)?;
     Ok(())
 }
 
@@ -97,47 +320,103 @@ impl Drop for TerminalGuard {
     }
 }
 
-fn run_loop(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<()> {
-    let mut app = App::new(ConfigStore::new()?)?;
+fn run_loop(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut App) -> Result<()> {
+    let mut pacer = Pacer::default();
     loop {
-        terminal.draw(|f| ui::render(f, &app))?;
-        if event::poll(Duration::from_millis(80))? {
+        app.tick();
+        // Read every pass, so a reload picks up new settings.
+        let timing = LoopConfig::from_config(&app.config);
+        if pacer.draw_now(&timing, Instant::now()) {
+            terminal.draw(|f| ui::render(f, app))?;
+        }
+        if app.take_bell() {
+            execute!(terminal.backend_mut(), crossterm::style::Print('\x07'))?;
+        }
+        if event::poll(pacer.timeout(&timing, Instant::now()))? {
             let evt = event::read()?;
+            if let event::Event::Resize(..) = evt {
+                // Laid out again for the new size now, even inside a
+                // `max_fps` hold, so no frame for the old size lingers.
+                terminal.autoresize()?;
+                terminal.draw(|f| ui::render(f, app))?;
+                pacer.drawn(Instant::now());
+            }
             if let Some(action) = app.on_event(evt)? {
                 match action {
                     AppAction::Quit => break,
                     AppAction::RunSsh(cmd) => {
-                        run_ssh(terminal, &mut app, cmd)?;
+                        remember_selection(app);
+                        let launched = matches!(app.mode, Mode::Launcher);
+                        // The launcher is done once a session ends cleanly;
+                        // on failure it stays up to show the error.
+                        if run_ssh(terminal, app, cmd)? && launched {
+                            break;
+                        }
                     }
                 }
             }
         }
     }
+    remember_selection(app);
     Ok(())
 }
 
+/// Not worth failing a quit or a connect over; the log says why.
+fn remember_selection(app: &mut App) {
+    if let Err(err) = app.remember_selection() {
+        debuglog::warn("app", "selection not remembered", &[("error", &err)]);
+    }
+}
+
 fn run_ssh(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     app: &mut App,
-    cmd: std::process::Command,
-) -> Result<()> {
+    cmd: ssh::SshCommand,
+) -> Result<bool> {
+    let host = cmd.host_id().to_string();
+    if let Some(terminal_command) = app.config.terminal_command.clone() {
+        // Nothing to say about a session still running elsewhere.
+        app.take_session_note();
+        let opened = if cmd.is_transfer() {
+            format!("Copying to {host} in a new terminal window.")
+        } else {
+            format!("Opened {host} in a new terminal window.")
+        };
+        let (ok, status) = match ssh::run_in_terminal(cmd, &terminal_command) {
+            Ok(()) => (
+                true,
+                StatusLine {
+                    text: opened,
+                    kind: StatusKind::Info,
+                },
+            ),
+            Err(err) => (
+                false,
+                StatusLine {
+                    text: format!("Couldn't open a terminal window (terminal_command): {err:#}"),
+                    kind: StatusKind::Error,
+                },
+            ),
+        };
+        app.status.set(status);
+        return Ok(ok);
+    }
     restore_terminal(terminal)?;
     let result = ssh::run_command(cmd);
-    *terminal = setup_terminal()?;
+    resume_terminal(terminal)?;
 
-    match result {
-        Ok(_) => {
-            app.status = Some(StatusLine {
-                text: "ssh session ended".into(),
-                kind: StatusKind::Info,
-            });
-        }
-        Err(err) => {
-            app.status = Some(StatusLine {
-                text: format!("ssh failed: {err}"),
-                kind: StatusKind::Error,
-            });
-        }
-    }
-    Ok(())
+    let ok = result.is_ok();
+    let note = app.take_session_note();
+    let status = match app.session_ended(&host, &result) {
+        None => StatusLine {
+            text: note.unwrap_or_else(|| "ssh session ended".into()),
+            kind: StatusKind::Info,
+        },
+        Some(message) => StatusLine {
+            text: message,
+            kind: StatusKind::Error,
+        },
+    };
+    app.status.set(status);
+    Ok(ok)
 }
@@ -1,12 +1,23 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
 
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+
+use anyhow::{bail, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use serde::de::Deserializer;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+use crate::autotag::AutoTagRule;
+use crate::config::CURRENT_VERSION;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
 pub struct Host {
     pub name: String,
+    /// Namespace for `name`: hosts in different groups may share a name.
+    #[serde(default)]
+    pub group: Option<String>,
     #[serde(rename = "host")]
     pub address: String,
     pub user: Option<String>,
@@ -22,16 +33,243 @@ pub struct Host {
     pub tags: Vec<String>,
     #[serde(default)]
     pub options: Vec<String>,
+    /// `-L` specs such as `8080:localhost:80`, one per forward.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local_forwards: Vec<String>,
+    /// `-R` specs, like `local_forwards`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remote_forwards: Vec<String>,
     #[serde(default)]
     pub remote_command: Option<String>,
+    /// Commands the connect modal offers with Up/Down as its remote
+    /// command; never run on their own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command_presets: Vec<String>,
+    /// `KEY=value` pairs, passed together as one `-o SetEnv=…`; the
+    /// server's `AcceptEnv` decides which it takes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub set_env: Vec<String>,
+    /// Per-host values for `{{name}}` placeholders in commands (see
+    /// `ssh::substitute_vars`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, String>,
     #[serde(default)]
     pub bastion: Option<String>,
+    /// Passed as `-o ProxyCommand=…`; can't be combined with `bastion`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_command: Option<String>,
+    /// Run when ssh asks for the password, which is its first line of
+    /// output; see `ssh::build_command`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_command: Option<String>,
+    /// What the host is for; unset means `normal`. See `Host::role`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<HostRole>,
     #[serde(default)]
     pub prefer_public_key_auth: bool,
+    /// Shares one connection between sessions through a ControlMaster
+    /// socket in `~/.ssh/sshdb-cm`; see `ssh::multiplex_args`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub multiplex: bool,
+    /// Passes `-A`: the local ssh agent answers for keys used on the host.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forward_agent: bool,
+    /// Passes `-X`, so X11 programs started on the host open locally.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forward_x11: bool,
+    /// `Some(true)` passes `-t`, `Some(false)` passes `-T`, `None` leaves it to ssh.
+    #[serde(default)]
+    pub request_tty: Option<bool>,
+    /// Passed as `-4` or `-6`; unset leaves the choice to ssh.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_family: Option<IpFamily>,
+    /// Replaces the OpenSSH argv entirely (e.g. `tsh ssh {user}@{address}`).
+    #[serde(default)]
+    pub command_template: Option<String>,
+    /// `mosh` connects with mosh instead of ssh; unset or `ssh` means ssh.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_with: Option<String>,
+    /// Acknowledges that `address` only works through the owner's
+    /// ssh_config, silencing the problems-panel warning about it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub alias_ok: bool,
+    /// High-stakes host: connecting always goes through the confirm modal
+    /// and its pre-flight checklist. Hosts tagged `prod` count as well.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub protected: bool,
+    /// Listed above the other hosts while nothing is searched; `*` toggles it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Free-text maintenance note shown in the details pane.
+    #[serde(default)]
+    pub maintenance: Option<String>,
+    /// Weekly change-freeze window (see `freeze::parse`); connecting inside
+    /// it takes an extra confirmation.
+    #[serde(default)]
+    pub freeze_window: Option<String>,
+    /// IANA zone the host lives in (`Asia/Tokyo`); the details pane shows
+    /// its local time. See `hosttime`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Weekly ranges in the `freeze_window` syntax, read in `timezone`,
+    /// during which connecting shows a warning.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quiet_hours: Option<String>,
+    /// `SHA256:…` fingerprint the host key must match (see `hostkey`).
+    #[serde(default)]
+    pub pinned_fingerprint: Option<String>,
+    /// Public key line the host must present, regardless of known_hosts.
+    #[serde(default)]
+    pub pinned_hostkey: Option<String>,
     pub description: Option<String>,
+    /// When sshdb last started a session to the host, in unix seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_connected: Option<i64>,
+    /// Sessions sshdb has started to the host; dry runs don't count.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub connect_count: u32,
+    /// Overlay file this host came from (`--overlay`); such hosts live for
+    /// the session only and are never written to the config.
+    #[serde(skip)]
+    pub overlay: Option<String>,
+}
+
+/// How a host is used. Jump hosts (`bastion`) are listed in their own
+/// section and offered first as bastions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostRole {
+    #[default]
+    Normal,
+    Bastion,
+}
+
+impl HostRole {
+    pub fn label(self) -> &'static str {
+        match self {
+            HostRole::Normal => "normal",
+            HostRole::Bastion => "bastion",
+        }
+    }
+
+    pub fn parse(input: &str) -> Result<Option<Self>> {
+        Ok(match input.trim().to_ascii_lowercase().as_str() {
+            "" => None,
+            "normal" => Some(HostRole::Normal),
+            "bastion" | "jump" => Some(HostRole::Bastion),
+            other => bail!("role must be normal or bastion, not '{other}'"),
+        })
+    }
+}
+
+/// The address family ssh is restricted to, for dual-stack hosts that only
+/// answer on one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    pub fn flag(self) -> &'static str {
+        match self {
+            IpFamily::V4 => "-4",
+            IpFamily::V6 => "-6",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            IpFamily::V4 => "IPv4",
+            IpFamily::V6 => "IPv6",
+        }
+    }
+
+    /// The DNS record that carries addresses of this family.
+    pub fn record(self) -> &'static str {
+        match self {
+            IpFamily::V4 => "A",
+            IpFamily::V6 => "AAAA",
+        }
+    }
+
+    pub fn matches(self, ip: IpAddr) -> bool {
+        match self {
+            IpFamily::V4 => ip.is_ipv4(),
+            IpFamily::V6 => ip.is_ipv6(),
+        }
+    }
+
+    /// `v4`, `ipv4`, `4`, `-4` or `inet` (and the v6 forms); empty, `any`
+    /// or `auto` for none.
+    pub fn parse(input: &str) -> Result<Option<Self>> {
+        let word = input.split_whitespace().next().unwrap_or("");
+        Ok(match word.to_ascii_lowercase().as_str() {
+            "" | "any" | "auto" => None,
+            "v4" | "ipv4" | "4" | "-4" | "inet" => Some(IpFamily::V4),
+            "v6" | "ipv6" | "6" | "-6" | "inet6" => Some(IpFamily::V6),
+            other => bail!("IP family must be any, v4 or v6, not '{other}'"),
+        })
+    }
+}
+
+/// Whether `name` can be used as a `{{name}}` variable: ASCII letters,
+/// digits, `_` and `-`.
+pub fn is_var_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Parses the form's `key=value,key2=value2` into host vars.
+pub fn parse_vars(input: &str) -> Result<BTreeMap<String, String>> {
+    let mut vars = BTreeMap::new();
+    for pair in input.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let Some((key, value)) = pair.split_once('=') else {
+            bail!("vars must be key=value pairs, not '{pair}'");
+        };
+        let key = key.trim();
+        if !is_var_name(key) {
+            bail!("'{key}' is not a variable name (use letters, digits, _ and -)");
+        }
+        vars.insert(key.to_string(), value.trim().to_string());
+    }
+    Ok(vars)
+}
+
+/// The inverse of `parse_vars`.
+pub fn format_vars(vars: &BTreeMap<String, String>) -> String {
+    vars.iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(",")
 }
 
 impl Host {
+    /// The configured role, `Normal` when unset.
+    pub fn role(&self) -> HostRole {
+        self.role.unwrap_or_default()
+    }
+
+    pub fn is_jump_host(&self) -> bool {
+        self.role() == HostRole::Bastion
+    }
+
+    /// `protected`, or tagged `prod`.
+    pub fn is_protected(&self) -> bool {
+        self.protected || self.tags.iter().any(|tag| tag == "prod")
+    }
+
+    /// Canonical identifier: `group/name` when a group is set, else `name`.
+    pub fn id(&self) -> String {
+        match &self.group {
+            Some(group) => format!("{group}/{}", self.name),
+            None => self.name.clone(),
+        }
+    }
+
     pub fn display_label(&self) -> String {
         if let Some(user) = &self.user {
             format!("{user}@{}", self.address)
@@ -39,36 +277,677 @@ impl Host {
             self.address.clone()
         }
     }
+
+    /// Fields that differ in `other`, in config order. Values are formatted
+    /// for display; an unset value is empty. `last_connected` and
+    /// `connect_count` record use, not an edit, and are left out.
+    pub fn diff(&self, other: &Host) -> Vec<FieldChange> {
+        fn text(value: &Option<String>) -> String {
+            value.clone().unwrap_or_default()
+        }
+        fn port(value: Option<u16>) -> String {
+            value.map(|p| p.to_string()).unwrap_or_default()
+        }
+        fn flag(value: bool) -> String {
+            if value { "yes" } else { "no" }.to_string()
+        }
+        fn family(value: Option<IpFamily>) -> String {
+            value.map_or("any", IpFamily::label).to_string()
+        }
+        fn tty(value: Option<bool>) -> String {
+            match value {
+                None => "auto",
+                Some(true) => "yes (-t)",
+                Some(false) => "no (-T)",
+            }
+            .to_string()
+        }
+        let (a, b) = (self, other);
+        let fields = [
+            ("name", a.name != b.name, a.name.clone(), b.name.clone()),
+            ("group", a.group != b.group, text(&a.group), text(&b.group)),
+            (
+                "host",
+                a.address != b.address,
+                a.address.clone(),
+                b.address.clone(),
+            ),
+            ("user", a.user != b.user, text(&a.user), text(&b.user)),
+            ("port", a.port != b.port, port(a.port), port(b.port)),
+            (
+                "key_paths",
+                a.key_paths != b.key_paths,
+                a.key_paths.join(", "),
+                b.key_paths.join(", "),
+            ),
+            ("tags", a.tags != b.tags, a.tags.join(","), b.tags.join(",")),
+            (
+                "options",
+                a.options != b.options,
+                a.options.join(" "),
+                b.options.join(" "),
+            ),
+            (
+                "local_forwards",
+                a.local_forwards != b.local_forwards,
+                a.local_forwards.join(", "),
+                b.local_forwards.join(", "),
+            ),
+            (
+                "remote_forwards",
+                a.remote_forwards != b.remote_forwards,
+                a.remote_forwards.join(", "),
+                b.remote_forwards.join(", "),
+            ),
+            (
+                "remote_command",
+                a.remote_command != b.remote_command,
+                text(&a.remote_command),
+                text(&b.remote_command),
+            ),
+            (
+                "command_presets",
+                a.command_presets != b.command_presets,
+                a.command_presets.join(", "),
+                b.command_presets.join(", "),
+            ),
+            (
+                "set_env",
+                a.set_env != b.set_env,
+                a.set_env.join(" "),
+                b.set_env.join(" "),
+            ),
+            (
+                "vars",
+                a.vars != b.vars,
+                format_vars(&a.vars),
+                format_vars(&b.vars),
+            ),
+            (
+                "bastion",
+                a.bastion != b.bastion,
+                text(&a.bastion),
+                text(&b.bastion),
+            ),
+            (
+                "proxy_command",
+                a.proxy_command != b.proxy_command,
+                text(&a.proxy_command),
+                text(&b.proxy_command),
+            ),
+            (
+                "password_command",
+                a.password_command != b.password_command,
+                text(&a.password_command),
+                text(&b.password_command),
+            ),
+            (
+                "role",
+                a.role != b.role,
+                a.role.map(|r| r.label().to_string()).unwrap_or_default(),
+                b.role.map(|r| r.label().to_string()).unwrap_or_default(),
+            ),
+            (
+                "prefer_public_key_auth",
+                a.prefer_public_key_auth != b.prefer_public_key_auth,
+                flag(a.prefer_public_key_auth),
+                flag(b.prefer_public_key_auth),
+            ),
+            (
+                "multiplex",
+                a.multiplex != b.multiplex,
+                flag(a.multiplex),
+                flag(b.multiplex),
+            ),
+            (
+                "forward_agent",
+                a.forward_agent != b.forward_agent,
+                flag(a.forward_agent),
+                flag(b.forward_agent),
+            ),
+            (
+                "forward_x11",
+                a.forward_x11 != b.forward_x11,
+                flag(a.forward_x11),
+                flag(b.forward_x11),
+            ),
+            (
+                "request_tty",
+                a.request_tty != b.request_tty,
+                tty(a.request_tty),
+                tty(b.request_tty),
+            ),
+            (
+                "ip_family",
+                a.ip_family != b.ip_family,
+                family(a.ip_family),
+                family(b.ip_family),
+            ),
+            (
+                "command_template",
+                a.command_template != b.command_template,
+                text(&a.command_template),
+                text(&b.command_template),
+            ),
+            (
+                "connect_with",
+                a.connect_with != b.connect_with,
+                text(&a.connect_with),
+                text(&b.connect_with),
+            ),
+            (
+                "alias_ok",
+                a.alias_ok != b.alias_ok,
+                flag(a.alias_ok),
+                flag(b.alias_ok),
+            ),
+            (
+                "protected",
+                a.protected != b.protected,
+                flag(a.protected),
+                flag(b.protected),
+            ),
+            (
+                "pinned",
+                a.pinned != b.pinned,
+                flag(a.pinned),
+                flag(b.pinned),
+            ),
+            (
+                "maintenance",
+                a.maintenance != b.maintenance,
+                text(&a.maintenance),
+                text(&b.maintenance),
+            ),
+            (
+                "freeze_window",
+                a.freeze_window != b.freeze_window,
+                text(&a.freeze_window),
+                text(&b.freeze_window),
+            ),
+            (
+                "timezone",
+                a.timezone != b.timezone,
+                text(&a.timezone),
+                text(&b.timezone),
+            ),
+            (
+                "quiet_hours",
+                a.quiet_hours != b.quiet_hours,
+                text(&a.quiet_hours),
+                text(&b.quiet_hours),
+            ),
+            (
+                "pinned_fingerprint",
+                a.pinned_fingerprint != b.pinned_fingerprint,
+                text(&a.pinned_fingerprint),
+                text(&b.pinned_fingerprint),
+            ),
+            (
+                "pinned_hostkey",
+                a.pinned_hostkey != b.pinned_hostkey,
+                text(&a.pinned_hostkey),
+                text(&b.pinned_hostkey),
+            ),
+            (
+                "description",
+                a.description != b.description,
+                text(&a.description),
+                text(&b.description),
+            ),
+        ];
+        fields
+            .into_iter()
+            .filter(|(_, differs, _, _)| *differs)
+            .map(|(field, _, old, new)| FieldChange { field, old, new })
+            .collect()
+    }
+}
+
+/// One field that differs between two versions of a host (see `Host::diff`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldChange {
+    /// Config key, e.g. `user` or `key_paths`.
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Config {
     pub version: u8,
     pub default_key: Option<String>,
+    #[serde(default = "default_true")]
+    pub warn_local_addresses: bool,
+    /// One-line command preview above the status bar.
+    #[serde(default)]
+    pub show_preview_bar: bool,
+    /// Ring the terminal bell when an error is shown.
+    #[serde(default)]
+    pub bell_on_error: bool,
+    /// Seconds between runs in watch mode.
+    #[serde(default = "default_watch_interval")]
+    pub watch_interval_secs: u64,
+    /// Open in the compact launcher, as with `--launcher`.
+    #[serde(default)]
+    pub launcher: bool,
+    /// Timezone for `freeze_window`: `local` (default), `UTC` or an offset
+    /// such as `+02:00`.
+    #[serde(default)]
+    pub freeze_timezone: Option<String>,
+    /// Drop the undo history on reload instead of asking before an undo
+    /// would go back past it.
+    #[serde(default)]
+    pub clear_undo_on_reload: bool,
+    /// Keep the workset (`w`) in the state file, so a restart mid-incident
+    /// doesn't lose it and `sshdb exec --workset` can use it.
+    #[serde(default)]
+    pub persist_workset: bool,
+    /// Program run for connections instead of `ssh` from `PATH`: a name
+    /// looked up on `PATH` or a path such as `/opt/openssh/bin/ssh`.
+    #[serde(default)]
+    pub ssh_binary: Option<String>,
+    /// Opens sessions in a new window by running this, then the ssh argv,
+    /// e.g. `alacritty -e`; unset takes over sshdb's own terminal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terminal_command: Option<String>,
+    /// Most bastions one host may chain through; a longer chain is refused
+    /// when the config is saved.
+    #[serde(default = "default_max_bastion_depth")]
+    pub max_bastion_depth: usize,
+    /// How many steps undo and redo each keep.
+    #[serde(default = "default_history_limit")]
+    pub history_limit: usize,
+    /// Start with addresses, key paths and (with `privacy_pseudonyms`) host
+    /// names masked on screen, for sharing it. `P` toggles it at runtime.
+    #[serde(default)]
+    pub privacy_mode: bool,
+    /// In privacy mode, also replace host names with stable pseudonyms such
+    /// as `host-a3f2`.
+    #[serde(default)]
+    pub privacy_pseudonyms: bool,
+    /// Plain Enter on a host with a `remote_command` shows the command and
+    /// asks before running it.
+    #[serde(default = "default_true")]
+    pub confirm_remote_command: bool,
+    /// Look up the selected host in the background and list its A and
+    /// AAAA records in the details pane.
+    #[serde(default = "default_true")]
+    pub show_resolved_addresses: bool,
+    /// Milliseconds the UI waits for input before ticking again; see
+    /// `runloop::LoopConfig` for the bounds.
+    #[serde(default = "default_poll_ms")]
+    pub poll_ms: u64,
+    /// Most frames drawn per second; unset draws after every event.
+    #[serde(default)]
+    pub max_fps: Option<u32>,
+    /// Pre-flight checks left out of the connect confirm for protected
+    /// hosts: `bastion`, `keys`, `port`, `window`, `dry_run`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preflight_skip: Vec<String>,
+    /// Tags for hosts added by quick connect or the Add form; see `autotag`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auto_tags: Vec<AutoTagRule>,
+    /// `[keys]`: list-view action name to key, e.g. `connect = "o"` or
+    /// `delete = "ctrl+d"`. Read through `KeyMap::new`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, String>,
+    /// `[theme]`: colours replacing the built-in ones.
+    #[serde(default, skip_serializing_if = "ThemeColors::is_empty")]
+    pub theme: ThemeColors,
+    /// `[[profiles]]`: named host sets besides the top-level `hosts`, which
+    /// are the default profile.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<Profile>,
+    /// Profile shown this session, `None` for the default one. Its hosts
+    /// are swapped into `hosts`; see `Config::switch_profile`.
+    #[serde(skip)]
+    pub active_profile: Option<String>,
     #[serde(default)]
     pub hosts: Vec<Host>,
 }
 
+/// A named set of hosts in the same file, shown instead of the top-level
+/// hosts while active. Bastions resolve among the profile's own hosts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub hosts: Vec<Host>,
+}
+
+/// What `Config::rename_host` changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenameReport {
+    pub old_id: String,
+    pub new_id: String,
+    /// Hosts whose bastion reference was rewritten.
+    pub bastions: Vec<String>,
+}
+
+impl RenameReport {
+    pub fn summary(&self) -> String {
+        let mut text = format!("Renamed {} to {}", self.old_id, self.new_id);
+        if !self.bastions.is_empty() {
+            text.push_str(&format!(
+                "; updated bastion of {}",
+                self.bastions.join(", ")
+            ));
+        }
+        text.push('.');
+        text
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_zero(count: &u32) -> bool {
+    *count == 0
+}
+
+fn default_watch_interval() -> u64 {
+    5
+}
+
+fn default_max_bastion_depth() -> usize {
+    5
+}
+
+fn default_history_limit() -> usize {
+    20
+}
+
+fn default_poll_ms() -> u64 {
+    crate::runloop::DEFAULT_POLL_MS
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
-            version: 1,
+            version: CURRENT_VERSION,
             default_key: None,
+            warn_local_addresses: true,
+            show_preview_bar: false,
+            bell_on_error: false,
+            watch_interval_secs: default_watch_interval(),
+            launcher: false,
+            freeze_timezone: None,
+            clear_undo_on_reload: false,
+            persist_workset: false,
+            ssh_binary: None,
+            terminal_command: None,
+            max_bastion_depth: default_max_bastion_depth(),
+            history_limit: default_history_limit(),
+            privacy_mode: false,
+            privacy_pseudonyms: false,
+            confirm_remote_command: true,
+            show_resolved_addresses: true,
+            poll_ms: default_poll_ms(),
+            max_fps: None,
+            preflight_skip: Vec::new(),
+            auto_tags: Vec::new(),
+            keys: BTreeMap::new(),
+            theme: ThemeColors::default(),
+            profiles: Vec::new(),
+            active_profile: None,
             hosts: Vec::new(),
         }
     }
 }
 
 impl Config {
-    pub fn find_host(&self, name: &str) -> Option<&Host> {
-        self.hosts.iter().find(|h| h.name == name)
+    /// The program connections run: `ssh_binary`, or `ssh` from `PATH`.
+    pub fn ssh_binary(&self) -> &str {
+        self.ssh_binary
+            .as_deref()
+            .filter(|binary| !binary.trim().is_empty())
+            .unwrap_or(crate::program::DEFAULT_SSH)
+    }
+
+    /// Shows profile `name` (`None`: the default one) in `hosts`. The
+    /// hosts shown so far are swapped back first, so while a profile is
+    /// active its slot in `profiles` holds the default hosts.
+    pub fn switch_profile(&mut self, name: Option<&str>) -> Result<()> {
+        if name == self.active_profile.as_deref() {
+            return Ok(());
+        }
+        if let Some(name) = name {
+            if !self.profiles.iter().any(|p| p.name == name) {
+                bail!("no profile named '{name}'");
+            }
+        }
+        if let Some(active) = self.active_profile.take() {
+            if let Some(profile) = self.profiles.iter_mut().find(|p| p.name == active) {
+                std::mem::swap(&mut self.hosts, &mut profile.hosts);
+            }
+        }
+        if let Some(name) = name {
+            if let Some(profile) = self.profiles.iter_mut().find(|p| p.name == name) {
+                std::mem::swap(&mut self.hosts, &mut profile.hosts);
+            }
+            self.active_profile = Some(name.to_string());
+        }
+        Ok(())
+    }
+
+    /// The profile after the active one, wrapping to the default profile
+    /// after the last.
+    pub fn next_profile(&self) -> Option<&str> {
+        let at = match &self.active_profile {
+            None => 0,
+            Some(active) => self.profiles.iter().position(|p| &p.name == active)? + 1,
+        };
+        self.profiles.get(at).map(|p| p.name.as_str())
+    }
+
+    /// The config as the file holds it: top-level hosts back in `hosts`.
+    pub fn with_default_profile(&self) -> Config {
+        let mut config = self.clone();
+        // Switching to the default profile can't fail.
+        let _ = config.switch_profile(None);
+        config
+    }
+
+    /// Looks a host up by canonical id, or by short name when only one host
+    /// has it. Ambiguous references find nothing; see `resolve_host`.
+    pub fn find_host(&self, reference: &str) -> Option<&Host> {
+        self.resolve_host(reference, None).ok().flatten()
+    }
+
+    /// Resolves a reference written by a host in `group` (e.g. a bastion).
+    /// An exact canonical id always wins; otherwise a short name must match
+    /// one host, or exactly one among several in `group`. `Ok(None)` means
+    /// no host matches, which bastions treat as a raw address.
+    pub fn resolve_host(&self, reference: &str, group: Option<&str>) -> Result<Option<&Host>> {
+        if let Some(host) = self.hosts.iter().find(|h| h.id() == reference) {
+            return Ok(Some(host));
+        }
+        let named: Vec<&Host> = self.hosts.iter().filter(|h| h.name == reference).collect();
+        match named.as_slice() {
+            [] => Ok(None),
+            [host] => Ok(Some(host)),
+            _ => {
+                if let Some(host) = named.iter().find(|h| h.group.as_deref() == group) {
+                    return Ok(Some(host));
+                }
+                let ids: Vec<String> = named.iter().map(|h| h.id()).collect();
+                bail!(
+                    "host name '{reference}' is ambiguous ({}); use the group/name form",
+                    ids.join(", ")
+                )
+            }
+        }
+    }
+
+    /// Up to three host ids a couple of typos away from `reference`, the
+    /// closest first, for did-you-mean messages.
+    pub fn similar_hosts(&self, reference: &str) -> Vec<String> {
+        let wanted = reference.to_ascii_lowercase();
+        let mut scored: Vec<(usize, String)> = self
+            .hosts
+            .iter()
+            .map(|host| {
+                let score = [host.id(), host.name.clone()]
+                    .iter()
+                    .map(|name| crate::text::distance(&name.to_ascii_lowercase(), &wanted))
+                    .min()
+                    .unwrap_or(usize::MAX);
+                (score, host.id())
+            })
+            .filter(|(score, _)| *score <= 2)
+            .collect();
+        scored.sort();
+        scored.into_iter().take(3).map(|(_, id)| id).collect()
+    }
+
+    /// Every tag some host carries, sorted, each once.
+    pub fn tags(&self) -> Vec<String> {
+        let tags: BTreeSet<&String> = self.hosts.iter().flat_map(|h| &h.tags).collect();
+        tags.into_iter().cloned().collect()
+    }
+
+    /// Indices of hosts whose bastion resolves to the host with canonical `id`.
+    pub fn bastion_referrers(&self, id: &str) -> Vec<usize> {
+        self.hosts
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| {
+                h.bastion.as_deref().is_some_and(|reference| {
+                    self.resolve_host(reference, h.group.as_deref())
+                        .ok()
+                        .flatten()
+                        .is_some_and(|target| target.id() == id)
+                })
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Hosts used as someone's bastion whose role was never set, as
+    /// candidates for the jump-host role. Hosts explicitly set to `normal`
+    /// are left out, so declining is remembered in the config itself.
+    pub fn jump_host_candidates(&self) -> Vec<usize> {
+        self.hosts
+            .iter()
+            .enumerate()
+            .filter(|(_, host)| host.role.is_none())
+            .filter(|(_, host)| !self.bastion_referrers(&host.id()).is_empty())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Renames `hosts[index]` and repoints bastion references at it, keeping
+    /// each in the form it was written (short name or `group/name`) unless
+    /// the short form would no longer resolve to it. Every rename, from the
+    /// rename prompt or the edit form, goes through here.
+    pub fn rename_host(&mut self, index: usize, new_name: &str) -> Result<RenameReport> {
+        let new_name = new_name.trim();
+        if new_name.is_empty() {
+            bail!("Name cannot be empty.");
+        }
+        if new_name.contains('/') {
+            bail!("Name cannot contain '/'.");
+        }
+        let Some(host) = self.hosts.get(index) else {
+            bail!("No host to rename.");
+        };
+        let old_id = host.id();
+        let new_id = Host {
+            name: new_name.to_string(),
+            group: host.group.clone(),
+            ..Default::default()
+        }
+        .id();
+        if self
+            .hosts
+            .iter()
+            .enumerate()
+            .any(|(i, h)| i != index && h.id() == new_id)
+        {
+            match &host.group {
+                Some(group) => {
+                    bail!("A host named '{new_name}' already exists in group '{group}'.")
+                }
+                None => bail!("A host named '{new_name}' already exists."),
+            }
+        }
+
+        // Resolve references before renaming, so they are read as written.
+        let referrers = self.bastion_referrers(&old_id);
+        self.hosts[index].name = new_name.to_string();
+        let mut bastions = Vec::new();
+        for i in referrers {
+            let qualified = self.hosts[i]
+                .bastion
+                .as_deref()
+                .is_some_and(|b| b.contains('/'));
+            let group = self.hosts[i].group.as_deref();
+            let short_resolves = self
+                .resolve_host(new_name, group)
+                .ok()
+                .flatten()
+                .is_some_and(|h| h.id() == new_id);
+            self.hosts[i].bastion = Some(if short_resolves && !qualified {
+                new_name.to_string()
+            } else {
+                new_id.clone()
+            });
+            bastions.push(self.hosts[i].id());
+        }
+        Ok(RenameReport {
+            old_id,
+            new_id,
+            bastions,
+        })
+    }
+
+    /// The host's name, qualified with its group only when another host
+    /// shares the name.
+    pub fn display_name(&self, host: &Host) -> String {
+        let shared = self
+            .hosts
+            .iter()
+            .filter(|h| h.name == host.name)
+            .nth(1)
+            .is_some();
+        if shared {
+            host.id()
+        } else {
+            host.name.clone()
+        }
     }
 
     #[cfg(test)]
     pub fn sample() -> Self {
         Self {
-            version: 1,
+            version: CURRENT_VERSION,
             default_key: Some("~/.ssh/id_ed25519".to_string()),
+            warn_local_addresses: true,
+            show_preview_bar: false,
+            bell_on_error: false,
+            watch_interval_secs: default_watch_interval(),
+            launcher: false,
+            freeze_timezone: None,
+            clear_undo_on_reload: false,
+            persist_workset: false,
+            ssh_binary: None,
+            terminal_command: None,
+            max_bastion_depth: default_max_bastion_depth(),
+            history_limit: default_history_limit(),
+            privacy_mode: false,
+            privacy_pseudonyms: false,
+            confirm_remote_command: true,
+            show_resolved_addresses: true,
+            poll_ms: default_poll_ms(),
+            max_fps: None,
+            preflight_skip: Vec::new(),
+            auto_tags: Vec::new(),
+            keys: BTreeMap::new(),
+            theme: ThemeColors::default(),
+            profiles: Vec::new(),
+            active_profile: None,
             hosts: vec![
                 Host {
                     name: "prod-web".to_string(),
@@ -82,6 +961,7 @@ impl Config {
                     description: Some("Payment frontend".into()),
                     bastion: None,
                     prefer_public_key_auth: false,
+                    ..Default::default()
                 },
                 Host {
                     name: "staging-db".to_string(),
@@ -95,6 +975,7 @@ impl Config {
                     description: Some("Staging database".into()),
                     bastion: Some("jump-eu".into()),
                     prefer_public_key_auth: false,
+                    ..Default::default()
                 },
                 Host {
                     name: "jump-eu".to_string(),
@@ -108,12 +989,366 @@ impl Config {
                     description: Some("Jump host EU".into()),
                     bastion: None,
                     prefer_public_key_auth: false,
+                    ..Default::default()
                 },
             ],
         }
     }
 }
 
+/// `#rrggbb` strings for the UI colours; see `ui::Theme::from_config`.
+/// Unset or malformed ones keep the built-in colour.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ThemeColors {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bg: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub panel: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warn: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub muted: Option<String>,
+}
+
+impl ThemeColors {
+    pub fn is_empty(&self) -> bool {
+        self.entries().iter().all(|(_, value)| value.is_none())
+    }
+
+    /// Each colour under its `[theme]` key.
+    pub fn entries(&self) -> [(&'static str, Option<&str>); 7] {
+        [
+            ("accent", self.accent.as_deref()),
+            ("bg", self.bg.as_deref()),
+            ("panel", self.panel.as_deref()),
+            ("warn", self.warn.as_deref()),
+            ("error", self.error.as_deref()),
+            ("text", self.text.as_deref()),
+            ("muted", self.muted.as_deref()),
+        ]
+    }
+
+    /// One line per colour that isn't `#rrggbb`, for the problems panel.
+    pub fn problems(&self) -> Vec<String> {
+        self.entries()
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let value = value?;
+                parse_hex_color(value).is_none().then(|| {
+                    format!("theme.{key} = \"{value}\" is not a #rrggbb colour; using the default")
+                })
+            })
+            .collect()
+    }
+}
+
+/// Red, green and blue of a `#rrggbb` colour.
+pub fn parse_hex_color(input: &str) -> Option<(u8, u8, u8)> {
+    let hex = input.trim().strip_prefix('#')?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// What a key does in the host list. `[keys]` names them as in
+/// `KEY_ACTIONS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyAction {
+    Quit,
+    Help,
+    About,
+    Search,
+    QuickConnect,
+    Down,
+    Up,
+    New,
+    Edit,
+    Delete,
+    Duplicate,
+    DuplicateNext,
+    Rename,
+    Undo,
+    Redo,
+    Reload,
+    Connect,
+    ConnectForce,
+    ConnectCommand,
+    ConnectBastion,
+    ConnectAs,
+    CopyFile,
+    CopyConnection,
+    Ping,
+    HealthSweep,
+    HealthFilter,
+    LinkBastion,
+    MoveHostUp,
+    MoveHostDown,
+    Pin,
+    CloseControl,
+    Explain,
+    FullCommand,
+    Problems,
+    Bundle,
+    ExportSshConfig,
+    ExportJson,
+    ImportJson,
+    SwitchProfile,
+    SortRecent,
+    JumpHosts,
+    GroupByTag,
+    Workset,
+    WorksetView,
+    WorksetClear,
+    TagFilter,
+    Mark,
+    BulkEdit,
+    WritableCopy,
+    Privacy,
+    AutoTagRules,
+    PreviewBar,
+    DryRun,
+    Settings,
+}
+
+/// Every action with its `[keys]` name and default keys. The arrow keys
+/// always move the selection as well.
+pub const KEY_ACTIONS: &[(KeyAction, &str, &[&str])] = &[
+    (KeyAction::Quit, "quit", &["q"]),
+    (KeyAction::Help, "help", &["?", "h"]),
+    (KeyAction::About, "about", &["a"]),
+    (KeyAction::Search, "search", &["/"]),
+    (KeyAction::QuickConnect, "quick_connect", &["g"]),
+    (KeyAction::Down, "down", &["j"]),
+    (KeyAction::Up, "up", &["k"]),
+    (KeyAction::New, "new", &["n"]),
+    (KeyAction::Edit, "edit", &["e"]),
+    (KeyAction::Delete, "delete", &["d"]),
+    (KeyAction::Duplicate, "duplicate", &["y"]),
+    (KeyAction::DuplicateNext, "duplicate_next", &["Y"]),
+    (KeyAction::Rename, "rename", &["R"]),
+    (KeyAction::Undo, "undo", &["u"]),
+    (KeyAction::Redo, "redo", &["ctrl+r"]),
+    (KeyAction::Reload, "reload", &["r"]),
+    (KeyAction::Connect, "connect", &["enter"]),
+    (KeyAction::ConnectForce, "connect_force", &["G"]),
+    (KeyAction::ConnectCommand, "connect_command", &["c"]),
+    (KeyAction::ConnectBastion, "connect_bastion", &["b"]),
+    (KeyAction::ConnectAs, "connect_as", &["U"]),
+    (KeyAction::CopyFile, "copy_file", &["f"]),
+    (KeyAction::CopyConnection, "copy", &["x"]),
+    (KeyAction::Ping, "ping", &["o"]),
+    (KeyAction::HealthSweep, "health_sweep", &["H"]),
+    (KeyAction::HealthFilter, "health_filter", &["A"]),
+    (KeyAction::LinkBastion, "link_bastion", &["l"]),
+    (KeyAction::MoveHostUp, "move_host_up", &["alt+k"]),
+    (KeyAction::MoveHostDown, "move_host_down", &["alt+j"]),
+    (KeyAction::Pin, "pin", &["*"]),
+    (KeyAction::CloseControl, "close_control", &["X"]),
+    (KeyAction::Explain, "explain", &["i"]),
+    (KeyAction::FullCommand, "full_command", &["V"]),
+    (KeyAction::Problems, "problems", &["!"]),
+    (KeyAction::Bundle, "bundle", &["B"]),
+    (KeyAction::ExportSshConfig, "export_ssh_config", &["S"]),
+    (KeyAction::ExportJson, "export_json", &["D"]),
+    (KeyAction::ImportJson, "import_json", &["I"]),
+    (KeyAction::SwitchProfile, "switch_profile", &["F"]),
+    (KeyAction::SortRecent, "sort", &["s"]),
+    (KeyAction::JumpHosts, "jump_hosts", &["J"]),
+    (KeyAction::GroupByTag, "group_by_tag", &["z"]),
+    (KeyAction::Workset, "workset", &["w"]),
+    (KeyAction::WorksetView, "workset_view", &["v"]),
+    (KeyAction::WorksetClear, "workset_clear", &["ctrl+w"]),
+    (KeyAction::TagFilter, "tag_filter", &["t"]),
+    (KeyAction::Mark, "mark", &["space"]),
+    (KeyAction::BulkEdit, "bulk_edit", &["E"]),
+    (KeyAction::WritableCopy, "writable_copy", &["W"]),
+    (KeyAction::Privacy, "privacy", &["P"]),
+    (KeyAction::AutoTagRules, "auto_tag_rules", &["T"]),
+    (KeyAction::PreviewBar, "preview_bar", &["p"]),
+    (KeyAction::DryRun, "dry_run", &["C"]),
+    (KeyAction::Settings, "settings", &["O"]),
+];
+
+/// A key as `[keys]` writes it: one character (`x`, `G`, `/`), a named
+/// key (`enter`, `space`, `tab`, `f1` to `f12`), optionally after
+/// `ctrl+` and/or `alt+`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyBinding {
+    code: KeyCode,
+    ctrl: bool,
+    alt: bool,
+}
+
+impl KeyBinding {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut rest = input.trim();
+        let (mut ctrl, mut alt) = (false, false);
+        loop {
+            let lower = rest.to_ascii_lowercase();
+            if lower.starts_with("ctrl+") && rest.len() > 5 {
+                ctrl = true;
+                rest = &rest[5..];
+            } else if lower.starts_with("alt+") && rest.len() > 4 {
+                alt = true;
+                rest = &rest[4..];
+            } else {
+                break;
+            }
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            // Terminals send Ctrl/Alt with a letter in lower case.
+            (Some(c), None) if ctrl || alt => KeyCode::Char(c.to_ascii_lowercase()),
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "enter" => KeyCode::Enter,
+                "space" => KeyCode::Char(' '),
+                "tab" => KeyCode::Tab,
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                name => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => bail!("'{input}' is not a key (try \"x\", \"ctrl+d\" or \"enter\")"),
+                },
+            },
+        };
+        Ok(Self { code, ctrl, alt })
+    }
+
+    /// The key as help and status hints show it: `x`, `Enter`, `Ctrl+W`.
+    pub fn label(&self) -> String {
+        let key = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            // The letter terminals send is lower case; help shows Ctrl+W.
+            KeyCode::Char(c) if self.ctrl => c.to_ascii_uppercase().to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::Enter => "Enter".into(),
+            KeyCode::Tab => "Tab".into(),
+            KeyCode::Backspace => "Backspace".into(),
+            KeyCode::Delete => "Delete".into(),
+            KeyCode::Home => "Home".into(),
+            KeyCode::End => "End".into(),
+            KeyCode::PageUp => "PgUp".into(),
+            KeyCode::PageDown => "PgDn".into(),
+            KeyCode::F(n) => format!("F{n}"),
+            other => format!("{other:?}"),
+        };
+        let ctrl = if self.ctrl { "Ctrl+" } else { "" };
+        let alt = if self.alt { "Alt+" } else { "" };
+        format!("{ctrl}{alt}{key}")
+    }
+
+    /// Shift is not compared: it is already in the character.
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        key.code == self.code
+            && key.modifiers.contains(KeyModifiers::CONTROL) == self.ctrl
+            && key.modifiers.contains(KeyModifiers::ALT) == self.alt
+    }
+}
+
+/// Which action each key runs in the host list: the `[keys]` bindings,
+/// then the defaults of the actions `[keys]` leaves alone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyMap {
+    bindings: Vec<(KeyBinding, KeyAction)>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::new(&BTreeMap::new()).0
+    }
+}
+
+impl KeyMap {
+    /// The map for a `[keys]` table, with one warning per entry that was
+    /// ignored: an unknown action, a key that doesn't parse or one already
+    /// taken by another entry.
+    pub fn new(keys: &BTreeMap<String, String>) -> (Self, Vec<String>) {
+        let mut bindings: Vec<(KeyBinding, KeyAction)> = Vec::new();
+        let mut warnings = Vec::new();
+        let mut rebound = Vec::new();
+        for (name, key) in keys {
+            let Some(&(action, _, _)) = KEY_ACTIONS.iter().find(|(_, n, _)| n == name) else {
+                warnings.push(format!("unknown action '{name}'"));
+                continue;
+            };
+            let binding = match KeyBinding::parse(key) {
+                Ok(binding) => binding,
+                Err(err) => {
+                    warnings.push(format!("{name}: {err}"));
+                    continue;
+                }
+            };
+            if let Some((_, taken)) = bindings.iter().find(|(b, _)| *b == binding) {
+                warnings.push(format!(
+                    "{name}: '{key}' is already bound to {}",
+                    action_name(*taken)
+                ));
+                continue;
+            }
+            bindings.push((binding, action));
+            rebound.push(action);
+        }
+        for (action, _, defaults) in KEY_ACTIONS {
+            if rebound.contains(action) {
+                continue;
+            }
+            for key in *defaults {
+                let binding = KeyBinding::parse(key).expect("default keys parse");
+                bindings.push((binding, *action));
+            }
+        }
+        (Self { bindings }, warnings)
+    }
+
+    /// The first binding matching `key` wins, so `[keys]` entries take
+    /// their key from whichever default had it.
+    pub fn action(&self, key: &KeyEvent) -> Option<KeyAction> {
+        self.bindings
+            .iter()
+            .find(|(binding, _)| binding.matches(key))
+            .map(|(_, action)| *action)
+    }
+
+    /// The keys that run `action`, as help shows them (`?/h`); a default
+    /// key a `[keys]` entry took is left out. "unbound" when none is left.
+    pub fn label(&self, action: KeyAction) -> String {
+        let keys: Vec<String> = self
+            .bindings
+            .iter()
+            .enumerate()
+            .filter(|(at, (binding, bound))| {
+                *bound == action && !self.bindings[..*at].iter().any(|(b, _)| b == binding)
+            })
+            .map(|(_, (binding, _))| binding.label())
+            .collect();
+        if keys.is_empty() {
+            "unbound".into()
+        } else {
+            keys.join("/")
+        }
+    }
+}
+
+fn action_name(action: KeyAction) -> &'static str {
+    KEY_ACTIONS
+        .iter()
+        .find(|(a, _, _)| *a == action)
+        .map_or("", |(_, name, _)| name)
+}
+
 #[derive(Deserialize)]
 #[serde(untagged)]
 enum KeyPathsField {
@@ -137,6 +1372,75 @@ where
 mod tests {
     use super::*;
 
+    #[test]
+    fn keys_table_rebinds_actions_and_reports_what_it_ignored() {
+        let config: Config = toml::from_str(
+            r#"
+version = 2
+
+[keys]
+delete = "ctrl+d"
+new = "x"
+edit = "Ctrl+D"
+quit = "hyper+q"
+frobnicate = "z"
+"#,
+        )
+        .unwrap();
+        let (keys, warnings) = KeyMap::new(&config.keys);
+        let key = |code, modifiers| keys.action(&KeyEvent::new(code, modifiers));
+        let plain = |c| key(KeyCode::Char(c), KeyModifiers::NONE);
+
+        assert_eq!(
+            key(KeyCode::Char('d'), KeyModifiers::CONTROL),
+            Some(KeyAction::Delete)
+        );
+        assert_eq!(plain('d'), None);
+        // A rebound key leaves the action that had it by default.
+        assert_eq!(plain('x'), Some(KeyAction::New));
+        assert_eq!(plain('n'), None);
+        // Ignored entries keep their defaults.
+        assert_eq!(plain('e'), Some(KeyAction::Edit));
+        assert_eq!(plain('q'), Some(KeyAction::Quit));
+        assert_eq!(
+            warnings,
+            [
+                "edit: 'Ctrl+D' is already bound to delete",
+                "unknown action 'frobnicate'",
+                "quit: 'hyper+q' is not a key (try \"x\", \"ctrl+d\" or \"enter\")",
+            ]
+        );
+        // Labels follow the bindings, leaving out keys taken by another.
+        assert_eq!(keys.label(KeyAction::Delete), "Ctrl+D");
+        assert_eq!(keys.label(KeyAction::New), "x");
+        assert_eq!(keys.label(KeyAction::CopyConnection), "unbound");
+        assert_eq!(keys.label(KeyAction::Help), "?/h");
+
+        let defaults = KeyMap::default();
+        let key = |code, modifiers| defaults.action(&KeyEvent::new(code, modifiers));
+        assert_eq!(
+            key(KeyCode::Char('G'), KeyModifiers::SHIFT),
+            Some(KeyAction::ConnectForce)
+        );
+        assert_eq!(
+            key(KeyCode::Char('r'), KeyModifiers::CONTROL),
+            Some(KeyAction::Redo)
+        );
+        assert_eq!(
+            key(KeyCode::Char('r'), KeyModifiers::NONE),
+            Some(KeyAction::Reload)
+        );
+        assert_eq!(
+            key(KeyCode::Char(' '), KeyModifiers::NONE),
+            Some(KeyAction::Mark)
+        );
+        assert_eq!(
+            key(KeyCode::Enter, KeyModifiers::NONE),
+            Some(KeyAction::Connect)
+        );
+        assert_eq!(key(KeyCode::Char('x'), KeyModifiers::CONTROL), None);
+    }
+
     #[test]
     fn loads_legacy_key_path_into_key_paths() {
         let host: Host = toml::from_str(
@@ -150,4 +1454,326 @@ key_path = "~/.ssh/legacy"
 
         assert_eq!(host.key_paths, vec!["~/.ssh/legacy".to_string()]);
     }
+
+    fn host(group: Option<&str>, name: &str, address: &str) -> Host {
+        Host {
+            name: name.into(),
+            group: group.map(Into::into),
+            address: address.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Two teams with a `db1` each, plus hosts whose names are unique.
+    fn teams() -> Config {
+        Config {
+            hosts: vec![
+                host(Some("team-a"), "db1", "10.0.1.1"),
+                host(Some("team-b"), "db1", "10.0.2.1"),
+                host(Some("team-a"), "web", "10.0.1.2"),
+                host(None, "jump", "10.0.0.1"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn address(found: Option<&Host>) -> Option<&str> {
+        found.map(|h| h.address.as_str())
+    }
+
+    #[test]
+    fn diff_lists_changed_fields_in_config_order() {
+        let old = Config::sample().hosts[0].clone();
+        assert!(old.diff(&old).is_empty());
+
+        let mut new = old.clone();
+        new.address = "10.0.0.9".into();
+        new.user = None;
+        new.port = Some(2222);
+        new.key_paths.push("~/.ssh/extra".into());
+        new.options = vec!["-o".into(), "ServerAliveInterval=30".into()];
+        new.prefer_public_key_auth = !old.prefer_public_key_auth;
+        new.request_tty = Some(false);
+        new.description = Some("moved".into());
+        let changes = old.diff(&new);
+        let fields: Vec<_> = changes.iter().map(|c| c.field).collect();
+        assert_eq!(
+            fields,
+            [
+                "host",
+                "user",
+                "port",
+                "key_paths",
+                "options",
+                "prefer_public_key_auth",
+                "request_tty",
+                "description"
+            ]
+        );
+
+        let change = |field: &str| changes.iter().find(|c| c.field == field).unwrap();
+        assert_eq!(change("host").new, "10.0.0.9");
+        // Unset values are empty.
+        assert_eq!(change("user").new, "");
+        assert_eq!(change("port").new, "2222");
+        assert_eq!(change("key_paths").new, {
+            let mut keys = old.key_paths.clone();
+            keys.push("~/.ssh/extra".into());
+            keys.join(", ")
+        });
+        assert_eq!(change("options").new, "-o ServerAliveInterval=30");
+        assert_eq!(change("request_tty").new, "no (-T)");
+        assert_eq!(change("description").new, "moved");
+    }
+
+    #[test]
+    fn diff_compares_lists_and_options_exactly() {
+        let mut old = host(None, "web", "10.0.0.1");
+        old.options = vec!["-o".into(), "A=1".into()];
+        let mut new = old.clone();
+        // Same text once joined, but not the same argv.
+        new.options = vec!["-o A=1".into()];
+        new.group = Some("prod".into());
+        new.tags = vec!["a".into()];
+        new.alias_ok = true;
+        let changes = old.diff(&new);
+        assert_eq!(
+            changes,
+            [
+                FieldChange {
+                    field: "group",
+                    old: "".into(),
+                    new: "prod".into()
+                },
+                FieldChange {
+                    field: "tags",
+                    old: "".into(),
+                    new: "a".into()
+                },
+                FieldChange {
+                    field: "options",
+                    old: "-o A=1".into(),
+                    new: "-o A=1".into()
+                },
+                FieldChange {
+                    field: "alias_ok",
+                    old: "no".into(),
+                    new: "yes".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn canonical_id_includes_group_only_when_set() {
+        assert_eq!(host(Some("team-a"), "db1", "x").id(), "team-a/db1");
+        assert_eq!(host(None, "db1", "x").id(), "db1");
+    }
+
+    #[test]
+    fn finds_hosts_by_canonical_id_or_unambiguous_name() {
+        let config = teams();
+        assert_eq!(address(config.find_host("team-a/db1")), Some("10.0.1.1"));
+        assert_eq!(address(config.find_host("team-b/db1")), Some("10.0.2.1"));
+        assert_eq!(address(config.find_host("web")), Some("10.0.1.2"));
+        assert_eq!(address(config.find_host("team-a/web")), Some("10.0.1.2"));
+        assert_eq!(address(config.find_host("jump")), Some("10.0.0.1"));
+        assert!(config.find_host("db1").is_none());
+        assert!(config.find_host("team-c/db1").is_none());
+        assert!(config.find_host("team-b/web").is_none());
+    }
+
+    #[test]
+    fn ambiguous_short_name_is_an_error_naming_the_candidates() {
+        let config = teams();
+        let err = config.resolve_host("db1", None).unwrap_err().to_string();
+        assert!(err.contains("ambiguous"), "{err}");
+        assert!(err.contains("team-a/db1, team-b/db1"), "{err}");
+        let err = config.resolve_host("db1", Some("team-c")).unwrap_err();
+        assert!(err.to_string().contains("ambiguous"));
+        assert!(config.resolve_host("nope", None).unwrap().is_none());
+    }
+
+    #[test]
+    fn short_name_prefers_the_referring_group() {
+        let config = teams();
+        let found = config.resolve_host("db1", Some("team-b")).unwrap();
+        assert_eq!(address(found), Some("10.0.2.1"));
+        // The canonical form is never reinterpreted relative to a group.
+        let found = config.resolve_host("team-a/db1", Some("team-b")).unwrap();
+        assert_eq!(address(found), Some("10.0.1.1"));
+    }
+
+    #[test]
+    fn ungrouped_host_owns_its_bare_name() {
+        let mut config = teams();
+        config.hosts.push(host(None, "db1", "10.0.0.9"));
+        assert_eq!(address(config.find_host("db1")), Some("10.0.0.9"));
+        let found = config.resolve_host("db1", Some("team-a")).unwrap();
+        assert_eq!(address(found), Some("10.0.0.9"));
+        assert_eq!(address(config.find_host("team-a/db1")), Some("10.0.1.1"));
+    }
+
+    fn with_bastion(mut host: Host, bastion: &str) -> Host {
+        host.bastion = Some(bastion.into());
+        host
+    }
+
+    #[test]
+    fn rename_rewrites_bastion_references_in_their_written_form() {
+        let mut config = teams();
+        config
+            .hosts
+            .push(with_bastion(host(Some("team-a"), "app", "x"), "web"));
+        config
+            .hosts
+            .push(with_bastion(host(None, "ci", "x"), "team-a/web"));
+        config
+            .hosts
+            .push(with_bastion(host(None, "other", "x"), "jump"));
+
+        let report = config.rename_host(2, "frontend").unwrap();
+        assert_eq!(report.old_id, "team-a/web");
+        assert_eq!(report.new_id, "team-a/frontend");
+        assert_eq!(report.bastions, vec!["team-a/app", "ci"]);
+        assert_eq!(config.hosts[2].name, "frontend");
+        assert_eq!(config.hosts[4].bastion.as_deref(), Some("frontend"));
+        assert_eq!(config.hosts[5].bastion.as_deref(), Some("team-a/frontend"));
+        assert_eq!(config.hosts[6].bastion.as_deref(), Some("jump"));
+        assert_eq!(
+            report.summary(),
+            "Renamed team-a/web to team-a/frontend; updated bastion of team-a/app, ci."
+        );
+    }
+
+    #[test]
+    fn role_is_optional_and_only_written_when_set() {
+        let host: Host = toml::from_str("name = \"jump\"\nhost = \"10.0.0.1\"\n").unwrap();
+        assert_eq!(host.role, None);
+        assert_eq!(host.role(), HostRole::Normal);
+        assert!(!toml::to_string(&host).unwrap().contains("role"));
+
+        let host: Host =
+            toml::from_str("name = \"jump\"\nhost = \"10.0.0.1\"\nrole = \"bastion\"\n").unwrap();
+        assert!(host.is_jump_host());
+        assert!(toml::to_string(&host)
+            .unwrap()
+            .contains("role = \"bastion\""));
+        assert!(
+            toml::from_str::<Host>("name = \"j\"\nhost = \"x\"\nrole = \"gateway\"\n").is_err()
+        );
+
+        assert_eq!(HostRole::parse(" Jump ").unwrap(), Some(HostRole::Bastion));
+        assert_eq!(HostRole::parse("").unwrap(), None);
+        assert!(HostRole::parse("gateway").is_err());
+    }
+
+    #[test]
+    fn vars_round_trip_through_the_config_and_the_form() {
+        let mut config = Config::default();
+        let mut host = Host {
+            name: "api".into(),
+            address: "10.0.0.2".into(),
+            remote_command: Some("systemctl status {{svc}}".into()),
+            ..Default::default()
+        };
+        config.hosts.push(host.clone());
+        assert!(!toml::to_string(&config).unwrap().contains("vars"));
+
+        host.vars = parse_vars(" svc = api ,dir=/srv/a=b,").unwrap();
+        assert_eq!(format_vars(&host.vars), "dir=/srv/a=b,svc=api");
+        config.hosts[0] = host.clone();
+        let text = toml::to_string_pretty(&config).unwrap();
+        let back: Config = toml::from_str(&text).unwrap();
+        assert_eq!(back.hosts[0], host);
+
+        assert!(parse_vars("svc").is_err());
+        assert!(parse_vars("my var=1").is_err());
+        assert!(parse_vars("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn bastions_without_a_role_are_jump_host_candidates() {
+        let mut config = teams();
+        config
+            .hosts
+            .push(with_bastion(host(Some("team-a"), "app", "x"), "web"));
+        config
+            .hosts
+            .push(with_bastion(host(None, "ci", "x"), "jump"));
+        assert_eq!(config.jump_host_candidates(), vec![2, 3]);
+
+        // Either answer to the suggestion is a decision; only unset roles ask.
+        config.hosts[2].role = Some(HostRole::Normal);
+        config.hosts[3].role = Some(HostRole::Bastion);
+        assert!(config.jump_host_candidates().is_empty());
+
+        // A bastion nobody uses any more is not suggested.
+        config.hosts[2].role = None;
+        config.hosts[4].bastion = None;
+        assert!(config.jump_host_candidates().is_empty());
+    }
+
+    #[test]
+    fn rename_qualifies_references_that_would_become_ambiguous() {
+        let mut config = teams();
+        config
+            .hosts
+            .push(with_bastion(host(None, "ci", "x"), "web"));
+        config.hosts.push(host(Some("team-b"), "db2", "y"));
+        // A bare "db2" from the ungrouped `ci` would match both teams.
+        let report = config.rename_host(2, "db2").unwrap();
+        assert_eq!(report.bastions, vec!["ci"]);
+        assert_eq!(config.hosts[4].bastion.as_deref(), Some("team-a/db2"));
+        assert_eq!(
+            config
+                .resolve_host("team-a/db2", None)
+                .unwrap()
+                .unwrap()
+                .address,
+            "10.0.1.2"
+        );
+    }
+
+    #[test]
+    fn rename_validates_the_new_name() {
+        let mut config = teams();
+        assert!(config.rename_host(0, "  ").is_err());
+        assert!(config.rename_host(0, "a/b").is_err());
+        let err = config.rename_host(0, "web").unwrap_err().to_string();
+        assert_eq!(err, "A host named 'web' already exists in group 'team-a'.");
+        // Same name in another group is fine.
+        config.rename_host(1, "web").unwrap();
+        assert_eq!(config.hosts[1].id(), "team-b/web");
+    }
+
+    #[test]
+    fn display_name_is_qualified_only_when_shared() {
+        let config = teams();
+        assert_eq!(config.display_name(&config.hosts[0]), "team-a/db1");
+        assert_eq!(config.display_name(&config.hosts[1]), "team-b/db1");
+        assert_eq!(config.display_name(&config.hosts[2]), "web");
+        assert_eq!(config.display_name(&config.hosts[3]), "jump");
+    }
+
+    #[test]
+    fn ip_family_accepts_ssh_and_config_spellings() {
+        for word in ["v4", "IPv4", "4", "-4", "inet"] {
+            assert_eq!(IpFamily::parse(word).unwrap(), Some(IpFamily::V4), "{word}");
+        }
+        for word in ["v6", "ipv6", "6", "-6", "inet6"] {
+            assert_eq!(IpFamily::parse(word).unwrap(), Some(IpFamily::V6), "{word}");
+        }
+        assert_eq!(IpFamily::parse("any").unwrap(), None);
+        assert_eq!(IpFamily::parse("").unwrap(), None);
+        assert!(IpFamily::parse("v5").is_err());
+
+        let host: Host =
+            toml::from_str("name = \"db\"\nhost = \"db.example.com\"\nip_family = \"v6\"\n")
+                .unwrap();
+        assert_eq!(host.ip_family, Some(IpFamily::V6));
+        assert!(toml::to_string(&host)
+            .unwrap()
+            .contains("ip_family = \"v6\""));
+    }
 }
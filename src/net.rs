@@ -0,0 +1,569 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Why an address looks like it points back at this machine (or its link).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LocalKind {
+    Loopback,
+    LinkLocal,
+    Unspecified,
+}
+
+impl LocalKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            LocalKind::Loopback => "loopback",
+            LocalKind::LinkLocal => "link-local",
+            LocalKind::Unspecified => "unspecified",
+        }
+    }
+}
+
+pub fn classify_ip(ip: IpAddr) -> Option<LocalKind> {
+    if ip.is_loopback() {
+        return Some(LocalKind::Loopback);
+    }
+    if ip.is_unspecified() {
+        return Some(LocalKind::Unspecified);
+    }
+    let link_local = match ip {
+        IpAddr::V4(v4) => v4.is_link_local(),
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+    };
+    if link_local {
+        return Some(LocalKind::LinkLocal);
+    }
+    None
+}
+
+/// Classifies an address literal without touching the network. Accepts bare
+/// IPs as well as bracketed IPv6 (`[::1]`) and `localhost`.
+pub fn classify_literal(address: &str) -> Option<LocalKind> {
+    let trimmed = address.trim().trim_start_matches('[').trim_end_matches(']');
+    if trimmed.eq_ignore_ascii_case("localhost") {
+        return Some(LocalKind::Loopback);
+    }
+    trimmed.parse::<IpAddr>().ok().and_then(classify_ip)
+}
+
+pub fn is_ip_literal(address: &str) -> bool {
+    let trimmed = address.trim().trim_start_matches('[').trim_end_matches(']');
+    trimmed.parse::<IpAddr>().is_ok()
+}
+
+/// How an address relates to name resolution on this machine, for spotting
+/// ssh_config aliases stored as addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressClass {
+    IpLiteral,
+    /// Contains a dot, so most likely a DNS name.
+    Hostname,
+    /// A single label that resolved here (`/etc/hosts`, search domains).
+    Resolvable,
+    /// A single label that did not resolve: probably only meaningful through
+    /// the owner's ssh_config.
+    PossibleAlias,
+    /// A single label whose lookup hasn't finished.
+    Unchecked,
+}
+
+/// Whether telling a hostname from an alias needs a lookup: single-label
+/// names that aren't IP literals or `localhost`.
+pub fn needs_lookup(address: &str) -> bool {
+    let address = address.trim();
+    !address.is_empty()
+        && !address.contains('.')
+        && !is_ip_literal(address)
+        && !address.eq_ignore_ascii_case("localhost")
+}
+
+/// `resolves` is the lookup result, `None` while unknown.
+pub fn classify_address(address: &str, resolves: Option<bool>) -> AddressClass {
+    if is_ip_literal(address) {
+        return AddressClass::IpLiteral;
+    }
+    if !needs_lookup(address) {
+        return AddressClass::Hostname;
+    }
+    match resolves {
+        Some(true) => AddressClass::Resolvable,
+        Some(false) => AddressClass::PossibleAlias,
+        None => AddressClass::Unchecked,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Lookup {
+    Pending,
+    Failed,
+    Resolved {
+        local: Option<LocalKind>,
+        /// Each address once, in resolver order.
+        addresses: Vec<IpAddr>,
+    },
+}
+
+/// Where a background lookup stands, for display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution<'a> {
+    Pending,
+    Failed,
+    Resolved(&'a [IpAddr]),
+}
+
+/// Resolves hostnames off the UI thread and remembers what they resolved
+/// to and whether any of their records are local. Each name is looked up
+/// once per session. Lookups are best-effort: failures count as "not
+/// local".
+pub struct LocalResolver {
+    results: HashMap<String, Lookup>,
+    tx: Sender<(String, Lookup)>,
+    rx: Receiver<(String, Lookup)>,
+    lookup: fn(&str) -> Lookup,
+}
+
+impl Default for LocalResolver {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            results: HashMap::new(),
+            tx,
+            rx,
+            lookup,
+        }
+    }
+}
+
+impl LocalResolver {
+    /// Starts a background lookup unless one already ran or is running.
+    pub fn request(&mut self, address: &str) {
+        if is_ip_literal(address) || self.results.contains_key(address) {
+            return;
+        }
+        self.results.insert(address.to_string(), Lookup::Pending);
+        let tx = self.tx.clone();
+        let address = address.to_string();
+        let lookup = self.lookup;
+        thread::spawn(move || {
+            let lookup = lookup(&address);
+            let _ = tx.send((address, lookup));
+        });
+    }
+
+    pub fn poll(&mut self) {
+        while let Ok((address, lookup)) = self.rx.try_recv() {
+            self.results.insert(address, lookup);
+        }
+    }
+
+    /// Local classification for an address, from the literal or a finished lookup.
+    pub fn local_kind(&self, address: &str) -> Option<LocalKind> {
+        classify_literal(address).or_else(|| match self.results.get(address) {
+            Some(Lookup::Resolved { local, .. }) => *local,
+            _ => None,
+        })
+    }
+
+    /// Whether a finished lookup found any address; `None` while unknown.
+    pub fn resolves(&self, address: &str) -> Option<bool> {
+        match self.results.get(address)? {
+            Lookup::Pending => None,
+            Lookup::Failed => Some(false),
+            Lookup::Resolved { .. } => Some(true),
+        }
+    }
+
+    /// The lookup of `address`; `None` if it was never requested.
+    pub fn resolution(&self, address: &str) -> Option<Resolution<'_>> {
+        Some(match self.results.get(address)? {
+            Lookup::Pending => Resolution::Pending,
+            Lookup::Failed => Resolution::Failed,
+            Lookup::Resolved { addresses, .. } => Resolution::Resolved(addresses),
+        })
+    }
+
+    pub fn classify(&self, address: &str) -> AddressClass {
+        classify_address(address, self.resolves(address))
+    }
+
+    /// Stands in for a finished lookup.
+    #[cfg(test)]
+    pub fn record(&mut self, address: &str, addresses: &[IpAddr]) {
+        let lookup = if addresses.is_empty() {
+            Lookup::Failed
+        } else {
+            resolved(addresses.to_vec())
+        };
+        self.results.insert(address.to_string(), lookup);
+    }
+}
+
+/// Blocking lookup, for callers without a UI to keep responsive.
+pub fn resolves_now(address: &str) -> bool {
+    matches!(lookup(address), Lookup::Resolved { .. })
+}
+
+fn lookup(address: &str) -> Lookup {
+    let Ok(addrs) = (address, 22).to_socket_addrs() else {
+        return Lookup::Failed;
+    };
+    let mut addresses: Vec<IpAddr> = Vec::new();
+    for ip in addrs.map(|addr| addr.ip()) {
+        if !addresses.contains(&ip) {
+            addresses.push(ip);
+        }
+    }
+    if addresses.is_empty() {
+        return Lookup::Failed;
+    }
+    resolved(addresses)
+}
+
+fn resolved(addresses: Vec<IpAddr>) -> Lookup {
+    Lookup::Resolved {
+        local: addresses.iter().find_map(|ip| classify_ip(*ip)),
+        addresses,
+    }
+}
+
+/// How long a probe waits for each address to accept the connection.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long a finished probe is trusted before it counts as unknown again.
+pub const PROBE_FRESH: Duration = Duration::from_secs(300);
+/// At most this many probes connect at once; the others wait their turn, so
+/// a health sweep over a hundred hosts doesn't open a hundred sockets.
+pub const MAX_RUNNING_PROBES: usize = 16;
+
+/// The outcome of a TCP connect to an address and port.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PortProbe {
+    Pending,
+    /// With how long the connect took.
+    Open(Duration),
+    Closed(String),
+}
+
+type ProbeKey = (String, u16);
+
+/// Checks off the UI thread whether a port accepts TCP connections, and
+/// remembers each answer with the time it came in. A probe is started again
+/// once its answer is older than `PROBE_FRESH`.
+pub struct PortProber {
+    results: HashMap<ProbeKey, (Instant, PortProbe)>,
+    /// Pending probes beyond `MAX_RUNNING_PROBES`, started as others end.
+    queued: VecDeque<ProbeKey>,
+    running: usize,
+    tx: Sender<(ProbeKey, Instant, PortProbe)>,
+    rx: Receiver<(ProbeKey, Instant, PortProbe)>,
+    probe: fn(&str, u16) -> PortProbe,
+}
+
+impl Default for PortProber {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            results: HashMap::new(),
+            queued: VecDeque::new(),
+            running: 0,
+            tx,
+            rx,
+            probe,
+        }
+    }
+}
+
+impl PortProber {
+    /// Starts a background probe unless one is running or a fresh answer is
+    /// at hand.
+    pub fn request(&mut self, address: &str, port: u16, now: Instant) {
+        let key = (address.to_string(), port);
+        match self.results.get(&key) {
+            Some((_, PortProbe::Pending)) => return,
+            Some((at, _)) if now.saturating_duration_since(*at) < PROBE_FRESH => return,
+            _ => {}
+        }
+        self.results.insert(key.clone(), (now, PortProbe::Pending));
+        if self.running < MAX_RUNNING_PROBES {
+            self.start(key);
+        } else {
+            self.queued.push_back(key);
+        }
+    }
+
+    fn start(&mut self, key: ProbeKey) {
+        self.running += 1;
+        let tx = self.tx.clone();
+        let probe = self.probe;
+        thread::spawn(move || {
+            let result = probe(&key.0, key.1);
+            let _ = tx.send((key, Instant::now(), result));
+        });
+    }
+
+    /// Like `request`, but also replaces a fresh answer: for when the user
+    /// asks for a new check. A running probe is left to finish.
+    pub fn recheck(&mut self, address: &str, port: u16, now: Instant) {
+        let key = (address.to_string(), port);
+        if !matches!(self.results.get(&key), Some((_, PortProbe::Pending))) {
+            self.results.remove(&key);
+        }
+        self.request(address, port, now);
+    }
+
+    pub fn poll(&mut self) {
+        while let Ok((key, at, result)) = self.rx.try_recv() {
+            self.running -= 1;
+            self.results.insert(key, (at, result));
+        }
+        while self.running < MAX_RUNNING_PROBES {
+            let Some(key) = self.queued.pop_front() else {
+                break;
+            };
+            self.start(key);
+        }
+    }
+
+    /// The last answer for `address:port` and its age at `now`; `None` if
+    /// it was never probed or the answer is stale.
+    pub fn status(&self, address: &str, port: u16, now: Instant) -> Option<(&PortProbe, Duration)> {
+        let (at, result) = self.results.get(&(address.to_string(), port))?;
+        let age = now.saturating_duration_since(*at);
+        (*result == PortProbe::Pending || age < PROBE_FRESH).then_some((result, age))
+    }
+
+    /// A prober that runs `probe` instead of connecting.
+    #[cfg(test)]
+    pub fn with_probe(probe: fn(&str, u16) -> PortProbe) -> Self {
+        Self {
+            probe,
+            ..Default::default()
+        }
+    }
+
+    /// Stands in for a probe that finished at `at`.
+    #[cfg(test)]
+    pub fn record(&mut self, address: &str, port: u16, at: Instant, result: PortProbe) {
+        self.results
+            .insert((address.to_string(), port), (at, result));
+    }
+}
+
+fn probe(address: &str, port: u16) -> PortProbe {
+    let address = address.trim().trim_start_matches('[').trim_end_matches(']');
+    let addrs = match (address, port).to_socket_addrs() {
+        Ok(addrs) => addrs,
+        Err(_) => return PortProbe::Closed("does not resolve".into()),
+    };
+    let mut last = "no address".to_string();
+    for addr in addrs {
+        let started = Instant::now();
+        match TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
+            Ok(_) => return PortProbe::Open(started.elapsed()),
+            Err(err) => last = err.to_string(),
+        }
+    }
+    PortProbe::Closed(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_local_literals() {
+        assert_eq!(classify_literal("127.0.0.1"), Some(LocalKind::Loopback));
+        assert_eq!(classify_literal("127.8.9.10"), Some(LocalKind::Loopback));
+        assert_eq!(classify_literal("::1"), Some(LocalKind::Loopback));
+        assert_eq!(classify_literal("[::1]"), Some(LocalKind::Loopback));
+        assert_eq!(classify_literal("localhost"), Some(LocalKind::Loopback));
+        assert_eq!(classify_literal("169.254.1.1"), Some(LocalKind::LinkLocal));
+        assert_eq!(classify_literal("fe80::1"), Some(LocalKind::LinkLocal));
+        assert_eq!(classify_literal("0.0.0.0"), Some(LocalKind::Unspecified));
+    }
+
+    #[test]
+    fn leaves_remote_addresses_alone() {
+        assert_eq!(classify_literal("52.14.33.10"), None);
+        assert_eq!(classify_literal("10.0.0.1"), None);
+        assert_eq!(classify_literal("2001:db8::1"), None);
+        assert_eq!(classify_literal("prod.example.com"), None);
+    }
+
+    #[test]
+    fn classifies_alias_candidates() {
+        assert_eq!(classify_address("10.0.0.1", None), AddressClass::IpLiteral);
+        assert_eq!(classify_address("[::1]", None), AddressClass::IpLiteral);
+        assert_eq!(
+            classify_address("jump.example.com", Some(false)),
+            AddressClass::Hostname
+        );
+        assert_eq!(classify_address("localhost", None), AddressClass::Hostname);
+        assert_eq!(
+            classify_address("corp-jump", Some(false)),
+            AddressClass::PossibleAlias
+        );
+        assert_eq!(
+            classify_address("nas", Some(true)),
+            AddressClass::Resolvable
+        );
+        assert_eq!(classify_address("nas", None), AddressClass::Unchecked);
+        assert!(!needs_lookup("db.internal"));
+        assert!(needs_lookup("corp-jump"));
+    }
+
+    #[test]
+    fn resolver_looks_each_name_up_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::{Duration, Instant};
+
+        static LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+        fn fake(_: &str) -> Lookup {
+            LOOKUPS.fetch_add(1, Ordering::SeqCst);
+            resolved(vec![
+                "192.0.2.7".parse().unwrap(),
+                "2001:db8::7".parse().unwrap(),
+            ])
+        }
+        let mut resolver = LocalResolver {
+            lookup: fake,
+            ..Default::default()
+        };
+        assert_eq!(resolver.resolution("db.example.com"), None);
+        resolver.request("db.example.com");
+        resolver.request("db.example.com");
+        assert_eq!(
+            resolver.resolution("db.example.com"),
+            Some(Resolution::Pending)
+        );
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while resolver.resolution("db.example.com") == Some(Resolution::Pending) {
+            assert!(Instant::now() < deadline, "lookup never finished");
+            std::thread::sleep(Duration::from_millis(5));
+            resolver.poll();
+        }
+        resolver.request("db.example.com");
+        resolver.poll();
+        assert_eq!(LOOKUPS.load(Ordering::SeqCst), 1);
+        let Some(Resolution::Resolved(addresses)) = resolver.resolution("db.example.com") else {
+            panic!("not resolved");
+        };
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(resolver.resolves("db.example.com"), Some(true));
+    }
+
+    #[test]
+    fn prober_keeps_a_bounded_number_of_probes_running() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static RUNNING: AtomicUsize = AtomicUsize::new(0);
+        static MOST: AtomicUsize = AtomicUsize::new(0);
+        fn slow(_: &str, _: u16) -> PortProbe {
+            let now = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            MOST.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+            PortProbe::Open(Duration::from_millis(20))
+        }
+        let mut prober = PortProber {
+            probe: slow,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let hosts = MAX_RUNNING_PROBES * 3;
+        for i in 0..hosts {
+            prober.request(&format!("192.0.2.{i}"), 22, start);
+        }
+        assert_eq!(prober.queued.len(), hosts - MAX_RUNNING_PROBES);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while (0..hosts).any(|i| {
+            matches!(
+                prober.status(&format!("192.0.2.{i}"), 22, start),
+                Some((PortProbe::Pending, _))
+            )
+        }) {
+            assert!(Instant::now() < deadline, "probes never finished");
+            std::thread::sleep(Duration::from_millis(5));
+            prober.poll();
+        }
+        assert!(MOST.load(Ordering::SeqCst) <= MAX_RUNNING_PROBES);
+        assert_eq!(prober.running, 0);
+    }
+
+    #[test]
+    fn prober_reuses_fresh_answers_and_retries_stale_ones() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static PROBES: AtomicUsize = AtomicUsize::new(0);
+        fn fake(_: &str, port: u16) -> PortProbe {
+            PROBES.fetch_add(1, Ordering::SeqCst);
+            if port == 22 {
+                PortProbe::Open(Duration::from_millis(12))
+            } else {
+                PortProbe::Closed("connection refused".into())
+            }
+        }
+        let mut prober = PortProber {
+            probe: fake,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        assert_eq!(prober.status("192.0.2.7", 22, start), None);
+        prober.request("192.0.2.7", 22, start);
+        prober.request("192.0.2.7", 22, start);
+        assert_eq!(
+            prober
+                .status("192.0.2.7", 22, start)
+                .map(|(p, _)| p.clone()),
+            Some(PortProbe::Pending)
+        );
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while let Some((PortProbe::Pending, _)) = prober.status("192.0.2.7", 22, start) {
+            assert!(Instant::now() < deadline, "probe never finished");
+            std::thread::sleep(Duration::from_millis(5));
+            prober.poll();
+        }
+        let now = Instant::now();
+        assert_eq!(
+            prober.status("192.0.2.7", 22, now).map(|(p, _)| p.clone()),
+            Some(PortProbe::Open(Duration::from_millis(12)))
+        );
+        prober.request("192.0.2.7", 22, now);
+        assert_eq!(PROBES.load(Ordering::SeqCst), 1);
+        // Asking again on purpose probes even though the answer is fresh.
+        prober.recheck("192.0.2.7", 22, now);
+        assert_eq!(
+            prober.status("192.0.2.7", 22, now).map(|(p, _)| p.clone()),
+            Some(PortProbe::Pending)
+        );
+
+        prober.record(
+            "192.0.2.7",
+            2222,
+            now,
+            PortProbe::Closed("connection refused".into()),
+        );
+        let later = now + PROBE_FRESH;
+        assert_eq!(prober.status("192.0.2.7", 2222, later), None);
+        prober.request("192.0.2.7", 2222, later);
+        assert_eq!(
+            prober
+                .status("192.0.2.7", 2222, later)
+                .map(|(p, _)| p.clone()),
+            Some(PortProbe::Pending)
+        );
+    }
+
+    #[test]
+    fn resolver_skips_literals() {
+        let mut resolver = LocalResolver::default();
+        resolver.request("127.0.0.1");
+        assert!(resolver.results.is_empty());
+        assert_eq!(resolver.local_kind("127.0.0.1"), Some(LocalKind::Loopback));
+    }
+}
@@ -0,0 +1,166 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Overlay files (`--overlay FILE`): hosts from extra config files merged
+//! over the main config for one session. Overlay hosts carry their source
+//! in `Host::overlay` and `persisted` drops them again before every save.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::config;
+use crate::model::{Config, Host};
+
+/// The hosts in the overlay file at `path`, parsed like the main config.
+pub fn read(path: &Path) -> Result<Vec<Host>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed to read overlay {}", path.display()))?;
+    let (overlay, _) = config::parse_document(&content)
+        .with_context(|| format!("invalid overlay {}", path.display()))?;
+    Ok(overlay.hosts)
+}
+
+/// Adds `hosts` from `source` to `config`. A host whose canonical id is
+/// already taken replaces that host in place; replaced main-config hosts
+/// are pushed onto `shadowed` with their index so saves can put them back.
+/// Returns the ids that collided.
+pub fn merge(
+    config: &mut Config,
+    source: &str,
+    hosts: Vec<Host>,
+    shadowed: &mut Vec<(usize, Host)>,
+) -> Vec<String> {
+    let mut replaced = Vec::new();
+    for mut host in hosts {
+        host.overlay = Some(source.to_string());
+        let id = host.id();
+        match config.hosts.iter().position(|h| h.id() == id) {
+            Some(idx) => {
+                let previous = std::mem::replace(&mut config.hosts[idx], host);
+                if previous.overlay.is_none() {
+                    shadowed.push((idx, previous));
+                }
+                replaced.push(id);
+            }
+            None => config.hosts.push(host),
+        }
+    }
+    shadowed.sort_by_key(|(idx, _)| *idx);
+    replaced
+}
+
+/// `config` as it should be written: overlay hosts left out and the
+/// main-config hosts they shadowed back at their old positions (unless a
+/// host with the same id has been added since).
+pub fn persisted(config: &Config, shadowed: &[(usize, Host)]) -> Config {
+    let mut out = config.clone();
+    out.hosts.retain(|host| host.overlay.is_none());
+    for (idx, host) in shadowed {
+        let id = host.id();
+        if out.hosts.iter().any(|h| h.id() == id) {
+            continue;
+        }
+        let at = (*idx).min(out.hosts.len());
+        out.hosts.insert(at, host.clone());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(name: &str, address: &str) -> Host {
+        Host {
+            name: name.into(),
+            address: address.into(),
+            ..Default::default()
+        }
+    }
+
+    fn names(config: &Config) -> Vec<&str> {
+        config.hosts.iter().map(|h| h.name.as_str()).collect()
+    }
+
+    #[test]
+    fn overlay_hosts_are_added_or_replace_by_id() {
+        let mut config = Config {
+            hosts: vec![host("a", "a.main"), host("b", "b.main")],
+            ..Default::default()
+        };
+        let mut shadowed = Vec::new();
+        let replaced = merge(
+            &mut config,
+            "one.toml",
+            vec![host("b", "b.one"), host("c", "c.one")],
+            &mut shadowed,
+        );
+        assert_eq!(replaced, ["b"]);
+        assert_eq!(names(&config), ["a", "b", "c"]);
+        assert_eq!(config.hosts[1].address, "b.one");
+        assert_eq!(config.hosts[2].overlay.as_deref(), Some("one.toml"));
+        assert!(config.hosts[0].overlay.is_none());
+
+        // A later overlay wins over an earlier one; only main hosts are shadowed.
+        let replaced = merge(
+            &mut config,
+            "two.toml",
+            vec![host("c", "c.two")],
+            &mut shadowed,
+        );
+        assert_eq!(replaced, ["c"]);
+        assert_eq!(config.hosts[2].address, "c.two");
+        assert_eq!(shadowed.len(), 1);
+        assert_eq!(shadowed[0].1.address, "b.main");
+    }
+
+    #[test]
+    fn persisted_config_drops_overlays_and_restores_shadowed_hosts() {
+        let main = Config {
+            hosts: vec![
+                host("a", "a.main"),
+                host("b", "b.main"),
+                host("c", "c.main"),
+            ],
+            ..Default::default()
+        };
+        let mut config = main.clone();
+        let mut shadowed = Vec::new();
+        merge(
+            &mut config,
+            "o.toml",
+            vec![host("b", "b.o"), host("d", "d.o")],
+            &mut shadowed,
+        );
+        assert_eq!(persisted(&config, &shadowed), main);
+
+        // Edits to main hosts are kept; the shadowed host goes back in place.
+        config.hosts[0].user = Some("root".into());
+        config.hosts.push(host("e", "e.main"));
+        let saved = persisted(&config, &shadowed);
+        assert_eq!(names(&saved), ["a", "b", "c", "e"]);
+        assert_eq!(saved.hosts[0].user.as_deref(), Some("root"));
+        assert_eq!(saved.hosts[1].address, "b.main");
+    }
+
+    #[test]
+    fn reads_overlays_with_the_config_parser() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("project.toml");
+        fs::write(
+            &path,
+            "[[hosts]]\nname = \"ci\"\nhost = \"10.0.0.5\"\nkey_path = \"~/.ssh/ci\"\n",
+        )
+        .unwrap();
+        let hosts = read(&path).unwrap();
+        assert_eq!(hosts[0].name, "ci");
+        assert_eq!(hosts[0].key_paths, ["~/.ssh/ci"]);
+
+        fs::write(&path, "[[hosts]]\nname = \"ci\"\n").unwrap();
+        let err = format!("{:#}", read(&path).unwrap_err());
+        assert!(err.contains("invalid overlay"), "{err}");
+        assert!(read(&dir.path().join("missing.toml")).is_err());
+    }
+}
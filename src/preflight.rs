@@ -0,0 +1,470 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! The pre-flight checklist the connect confirm shows for protected hosts.
+//! Every check is computed from the config and from answers the app already
+//! has (cached probes, file metadata, the clock), so building the list never
+//! blocks: anything still in flight shows as pending.
+
+use std::time::Duration;
+
+use crate::model::{Config, Host};
+use crate::ssh;
+
+/// One line of the checklist, in display order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    Bastion,
+    Keys,
+    Port,
+    Window,
+    DryRun,
+}
+
+impl Check {
+    pub const ALL: [Check; 5] = [
+        Check::Bastion,
+        Check::Keys,
+        Check::Port,
+        Check::Window,
+        Check::DryRun,
+    ];
+
+    /// The name used in `preflight_skip`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::Bastion => "bastion",
+            Check::Keys => "keys",
+            Check::Port => "port",
+            Check::Window => "window",
+            Check::DryRun => "dry_run",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|check| check.name() == name.trim())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+    /// Not known (yet): a pending probe, or nothing to check against.
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckResult {
+    pub check: Check,
+    pub outcome: Outcome,
+    pub detail: String,
+}
+
+/// What the app knows about a TCP port right now.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PortState {
+    /// Never probed, or the last probe is too old to trust.
+    Unknown,
+    Pending,
+    Open {
+        age: Duration,
+    },
+    Closed {
+        reason: String,
+        age: Duration,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyFile {
+    Missing,
+    /// `mode` holds the permission bits where the platform has them.
+    Found {
+        mode: Option<u32>,
+    },
+}
+
+/// The answers the checks need from outside the config. The app provides
+/// them from its caches; none of them may block.
+pub trait Providers {
+    /// Metadata of a key path as configured (`~` not yet expanded).
+    fn key_file(&self, path: &str) -> KeyFile;
+    fn port(&self, address: &str, port: u16) -> PortState;
+    /// The freeze window or quiet hours `host` is inside now, described.
+    fn active_window(&self, host: &Host) -> Option<String>;
+    fn dry_run(&self) -> bool;
+}
+
+/// The checklist for connecting to `host`, without the checks named in
+/// `preflight_skip`.
+pub fn run(config: &Config, host: &Host, providers: &dyn Providers) -> Vec<CheckResult> {
+    Check::ALL
+        .into_iter()
+        .filter(|check| !skips(config, *check))
+        .map(|check| {
+            let (outcome, detail) = match check {
+                Check::Bastion => bastion(config, host),
+                Check::Keys => keys(config, host, providers),
+                Check::Port => port(host, providers),
+                Check::Window => window(host, providers),
+                Check::DryRun => dry_run(providers),
+            };
+            CheckResult {
+                check,
+                outcome,
+                detail,
+            }
+        })
+        .collect()
+}
+
+/// Whether `preflight_skip` leaves `check` out.
+pub fn skips(config: &Config, check: Check) -> bool {
+    config
+        .preflight_skip
+        .iter()
+        .any(|name| Check::parse(name) == Some(check))
+}
+
+/// `preflight_skip` entries that name no check.
+pub fn unknown_skips(config: &Config) -> Vec<&str> {
+    config
+        .preflight_skip
+        .iter()
+        .map(String::as_str)
+        .filter(|name| Check::parse(name).is_none())
+        .collect()
+}
+
+fn bastion(config: &Config, host: &Host) -> (Outcome, String) {
+    match ssh::bastion_chain(config, host) {
+        Ok(hops) if hops.is_empty() => (Outcome::Pass, "direct connection".into()),
+        Ok(hops) => match hops.iter().find(|hop| hop.id.is_none()) {
+            Some(hop) => (
+                Outcome::Unknown,
+                format!("{} is free text, not a host in the list", hop.reference),
+            ),
+            None => (
+                Outcome::Pass,
+                format!(
+                    "bastion chain resolves: {}",
+                    hops.iter()
+                        .filter_map(|hop| hop.id.as_deref())
+                        .collect::<Vec<_>>()
+                        .join(" → ")
+                ),
+            ),
+        },
+        Err(err) => (Outcome::Fail, err.to_string()),
+    }
+}
+
+fn keys(config: &Config, host: &Host, providers: &dyn Providers) -> (Outcome, String) {
+    let paths: Vec<&str> = if !host.key_paths.is_empty() {
+        host.key_paths.iter().map(String::as_str).collect()
+    } else {
+        match config.default_key.as_deref() {
+            Some("agent") => return (Outcome::Pass, "left to the ssh agent".into()),
+            Some(key) => vec![key],
+            None => {
+                return (
+                    Outcome::Unknown,
+                    "no key configured; ssh tries the agent and its defaults".into(),
+                )
+            }
+        }
+    };
+    for path in &paths {
+        match providers.key_file(path) {
+            KeyFile::Missing => return (Outcome::Fail, format!("{path} does not exist")),
+            KeyFile::Found { mode: Some(mode) } if mode & 0o077 != 0 => {
+                return (
+                    Outcome::Fail,
+                    format!("{path} is readable by others ({:04o})", mode & 0o7777),
+                )
+            }
+            KeyFile::Found { .. } => {}
+        }
+    }
+    (Outcome::Pass, format!("{} present", paths.join(", ")))
+}
+
+fn port(host: &Host, providers: &dyn Providers) -> (Outcome, String) {
+    if host.command_template.is_some() {
+        return (Outcome::Unknown, "not probed: command template".into());
+    }
+    if let Some(bastion) = &host.bastion {
+        return (
+            Outcome::Unknown,
+            format!("not probed: reached through {bastion}"),
+        );
+    }
+    let number = host.port.unwrap_or(22);
+    let target = format!("{}:{number}", host.address);
+    match providers.port(&host.address, number) {
+        PortState::Unknown => (Outcome::Unknown, format!("{target} not probed")),
+        PortState::Pending => (Outcome::Unknown, format!("{target} probe pending")),
+        PortState::Open { age } => (
+            Outcome::Pass,
+            format!("{target} reachable ({})", checked(age)),
+        ),
+        PortState::Closed { reason, age } => (
+            Outcome::Fail,
+            format!("{target} unreachable: {reason} ({})", checked(age)),
+        ),
+    }
+}
+
+/// How old a probe's answer is, for the checklist and the details pane.
+pub fn checked(age: Duration) -> String {
+    match age.as_secs() {
+        0 => "checked just now".into(),
+        secs if secs < 60 => format!("checked {secs}s ago"),
+        secs => format!("checked {}m ago", secs / 60),
+    }
+}
+
+fn window(host: &Host, providers: &dyn Providers) -> (Outcome, String) {
+    if let Some(active) = providers.active_window(host) {
+        return (Outcome::Fail, active);
+    }
+    match (&host.freeze_window, &host.quiet_hours) {
+        (None, None) => (Outcome::Pass, "no freeze window or quiet hours".into()),
+        (Some(spec), _) => (Outcome::Pass, format!("outside the freeze window ({spec})")),
+        (None, Some(spec)) => (Outcome::Pass, format!("outside the quiet hours ({spec})")),
+    }
+}
+
+fn dry_run(providers: &dyn Providers) -> (Outcome, String) {
+    let detail = if providers.dry_run() {
+        "dry-run ON: the command is only shown"
+    } else {
+        "dry-run off: this connects for real"
+    };
+    (Outcome::Pass, detail.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Mock {
+        missing: Vec<&'static str>,
+        mode: Option<u32>,
+        port: Option<PortState>,
+        window: Option<String>,
+        dry_run: bool,
+    }
+
+    impl Providers for Mock {
+        fn key_file(&self, path: &str) -> KeyFile {
+            if self.missing.contains(&path) {
+                KeyFile::Missing
+            } else {
+                KeyFile::Found { mode: self.mode }
+            }
+        }
+
+        fn port(&self, _: &str, _: u16) -> PortState {
+            self.port.clone().unwrap_or(PortState::Unknown)
+        }
+
+        fn active_window(&self, _: &Host) -> Option<String> {
+            self.window.clone()
+        }
+
+        fn dry_run(&self) -> bool {
+            self.dry_run
+        }
+    }
+
+    fn result(config: &Config, name: &str, mock: &Mock, check: Check) -> (Outcome, String) {
+        let host = config.hosts.iter().find(|h| h.name == name).unwrap();
+        let found = run(config, host, mock)
+            .into_iter()
+            .find(|r| r.check == check)
+            .unwrap();
+        (found.outcome, found.detail)
+    }
+
+    #[test]
+    fn bastion_chain_must_resolve() {
+        let mut config = Config::sample();
+        let mock = Mock::default();
+        assert_eq!(
+            result(&config, "prod-web", &mock, Check::Bastion),
+            (Outcome::Pass, "direct connection".into())
+        );
+        assert_eq!(
+            result(&config, "staging-db", &mock, Check::Bastion),
+            (Outcome::Pass, "bastion chain resolves: jump-eu".into())
+        );
+        config.hosts.retain(|h| h.name != "jump-eu");
+        config.hosts[1].bastion = Some("jump-eu".into());
+        assert_eq!(
+            result(&config, "staging-db", &mock, Check::Bastion),
+            (
+                Outcome::Unknown,
+                "jump-eu is free text, not a host in the list".into()
+            )
+        );
+        config.hosts[1].bastion = Some("staging-db".into());
+        assert_eq!(
+            result(&config, "staging-db", &mock, Check::Bastion).0,
+            Outcome::Fail
+        );
+    }
+
+    #[test]
+    fn key_files_must_exist_and_stay_private() {
+        let mut config = Config::sample();
+        let ok = Mock {
+            mode: Some(0o100600),
+            ..Mock::default()
+        };
+        assert_eq!(
+            result(&config, "prod-web", &ok, Check::Keys),
+            (Outcome::Pass, "~/.ssh/prod_id_ed25519 present".into())
+        );
+        let missing = Mock {
+            missing: vec!["~/.ssh/prod_id_ed25519"],
+            ..Mock::default()
+        };
+        assert_eq!(
+            result(&config, "prod-web", &missing, Check::Keys),
+            (
+                Outcome::Fail,
+                "~/.ssh/prod_id_ed25519 does not exist".into()
+            )
+        );
+        let open = Mock {
+            mode: Some(0o100644),
+            ..Mock::default()
+        };
+        assert_eq!(
+            result(&config, "prod-web", &open, Check::Keys),
+            (
+                Outcome::Fail,
+                "~/.ssh/prod_id_ed25519 is readable by others (0644)".into()
+            )
+        );
+
+        config.hosts[0].key_paths.clear();
+        assert_eq!(
+            result(&config, "prod-web", &ok, Check::Keys),
+            (Outcome::Pass, "~/.ssh/id_ed25519 present".into())
+        );
+        config.default_key = Some("agent".into());
+        assert_eq!(
+            result(&config, "prod-web", &ok, Check::Keys).0,
+            Outcome::Pass
+        );
+        config.default_key = None;
+        assert_eq!(
+            result(&config, "prod-web", &ok, Check::Keys).0,
+            Outcome::Unknown
+        );
+    }
+
+    #[test]
+    fn port_reports_each_probe_state() {
+        let config = Config::sample();
+        let cases = [
+            (None, Outcome::Unknown, "52.14.33.10:22 not probed"),
+            (
+                Some(PortState::Pending),
+                Outcome::Unknown,
+                "52.14.33.10:22 probe pending",
+            ),
+            (
+                Some(PortState::Open {
+                    age: Duration::from_secs(42),
+                }),
+                Outcome::Pass,
+                "52.14.33.10:22 reachable (checked 42s ago)",
+            ),
+            (
+                Some(PortState::Closed {
+                    reason: "connection refused".into(),
+                    age: Duration::from_secs(130),
+                }),
+                Outcome::Fail,
+                "52.14.33.10:22 unreachable: connection refused (checked 2m ago)",
+            ),
+        ];
+        for (state, outcome, detail) in cases {
+            let mock = Mock {
+                port: state,
+                ..Mock::default()
+            };
+            assert_eq!(
+                result(&config, "prod-web", &mock, Check::Port),
+                (outcome, detail.to_string())
+            );
+        }
+        let open = Mock {
+            port: Some(PortState::Open {
+                age: Duration::ZERO,
+            }),
+            ..Mock::default()
+        };
+        assert_eq!(
+            result(&config, "staging-db", &open, Check::Port),
+            (
+                Outcome::Unknown,
+                "not probed: reached through jump-eu".into()
+            )
+        );
+    }
+
+    #[test]
+    fn window_and_dry_run_are_reported() {
+        let mut config = Config::sample();
+        let mock = Mock::default();
+        assert_eq!(
+            result(&config, "prod-web", &mock, Check::Window),
+            (Outcome::Pass, "no freeze window or quiet hours".into())
+        );
+        config.hosts[0].freeze_window = Some("Fri 16:00-Mon 08:00".into());
+        assert_eq!(
+            result(&config, "prod-web", &mock, Check::Window),
+            (
+                Outcome::Pass,
+                "outside the freeze window (Fri 16:00-Mon 08:00)".into()
+            )
+        );
+        let inside = Mock {
+            window: Some("inside the freeze window (Fri 16:00-Mon 08:00)".into()),
+            dry_run: true,
+            ..Mock::default()
+        };
+        assert_eq!(
+            result(&config, "prod-web", &inside, Check::Window).0,
+            Outcome::Fail
+        );
+        assert_eq!(
+            result(&config, "prod-web", &inside, Check::DryRun),
+            (
+                Outcome::Pass,
+                "dry-run ON: the command is only shown".into()
+            )
+        );
+    }
+
+    #[test]
+    fn skipped_checks_are_left_out() {
+        let mut config = Config::sample();
+        config.preflight_skip = vec!["port".into(), " dry_run".into(), "keyz".into()];
+        let host = config.hosts[0].clone();
+        let checks: Vec<Check> = run(&config, &host, &Mock::default())
+            .into_iter()
+            .map(|r| r.check)
+            .collect();
+        assert_eq!(checks, [Check::Bastion, Check::Keys, Check::Window]);
+        assert_eq!(unknown_skips(&config), ["keyz"]);
+    }
+}
@@ -0,0 +1,224 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Privacy mode: what the screen shows while it is being shared. Addresses
+//! become `10.x.x.x` or `█████.example`, key paths their file name and,
+//! optionally, host names stable pseudonyms such as `host-a3f2`. Only text
+//! on its way to the screen goes through here; the config, and the
+//! commands built from it, never do.
+
+use std::borrow::Cow;
+
+use sha2::{Digest, Sha256};
+
+use crate::model::Config;
+
+/// Stands in for the hidden part of a hostname.
+const BLOCKED: &str = "█████";
+
+/// Replaces the configured addresses, key paths and (with `pseudonyms`)
+/// host names wherever they appear as whole words, plus any other IPv4
+/// address.
+#[derive(Clone, Debug, Default)]
+pub struct Masker {
+    /// (real, shown) pairs, longest first so `db.example.com` wins over
+    /// `example.com`.
+    pairs: Vec<(String, String)>,
+}
+
+impl Masker {
+    pub fn new(config: &Config, pseudonyms: bool) -> Self {
+        let mut pairs: Vec<(String, String)> = Vec::new();
+        let mut add = |real: &str, shown: String| {
+            if !real.is_empty() && !pairs.iter().any(|(r, _)| r == real) {
+                pairs.push((real.to_string(), shown));
+            }
+        };
+        let keys = config
+            .hosts
+            .iter()
+            .flat_map(|h| &h.key_paths)
+            .chain(&config.default_key);
+        for key in keys {
+            add(key, basename(key));
+            add(&crate::ssh::expand_tilde(key), basename(key));
+        }
+        for host in &config.hosts {
+            add(&host.address, mask_address(&host.address));
+        }
+        if pseudonyms {
+            for host in &config.hosts {
+                add(&host.name, pseudonym(&host.name));
+            }
+        }
+        pairs.sort_by_key(|(real, _)| std::cmp::Reverse(real.len()));
+        Self { pairs }
+    }
+
+    pub fn mask<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut out = String::new();
+        let mut copied = 0;
+        let mut prev: Option<char> = None;
+        let mut iter = text.char_indices();
+        while let Some((i, c)) = iter.next() {
+            let found = if starts_word(prev) {
+                self.pairs
+                    .iter()
+                    .find(|(real, _)| {
+                        text[i..].starts_with(real.as_str()) && ends_word(text, i + real.len())
+                    })
+                    .map(|(real, shown)| (real.len(), shown.clone()))
+                    .or_else(|| {
+                        ipv4_at(text, i).map(|(len, first)| (len, format!("{first}.x.x.x")))
+                    })
+            } else {
+                None
+            };
+            match found {
+                Some((len, shown)) => {
+                    out.push_str(&text[copied..i]);
+                    out.push_str(&shown);
+                    copied = i + len;
+                    prev = text[..copied].chars().next_back();
+                    while iter.offset() < copied {
+                        iter.next();
+                    }
+                }
+                None => prev = Some(c),
+            }
+        }
+        if copied == 0 {
+            return Cow::Borrowed(text);
+        }
+        out.push_str(&text[copied..]);
+        Cow::Owned(out)
+    }
+}
+
+/// `52.14.33.10` → `52.x.x.x`, `db.eu.example.com` → `█████.com`; IPv6
+/// keeps its first group.
+pub fn mask_address(address: &str) -> String {
+    if let Some((first, _)) = address.split_once(':') {
+        return format!("{first}:…");
+    }
+    if let Some((len, first)) = ipv4_at(address, 0) {
+        if len == address.len() {
+            return format!("{first}.x.x.x");
+        }
+    }
+    match address.rsplit_once('.') {
+        Some((_, last)) => format!("{BLOCKED}.{last}"),
+        None => BLOCKED.to_string(),
+    }
+}
+
+/// The same `host-xxxx` for the same name, every time.
+pub fn pseudonym(name: &str) -> String {
+    let digest = Sha256::digest(name.as_bytes());
+    format!("host-{:02x}{:02x}", digest[0], digest[1])
+}
+
+fn basename(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+fn starts_word(prev: Option<char>) -> bool {
+    !prev.is_some_and(|c| is_word(c) || c == '.')
+}
+
+/// A full stop ends a word; a dot followed by more of the name doesn't.
+fn ends_word(text: &str, end: usize) -> bool {
+    let mut rest = text[end..].chars();
+    match rest.next() {
+        None => true,
+        Some('.') => !rest.next().is_some_and(char::is_alphanumeric),
+        Some(c) => !is_word(c),
+    }
+}
+
+/// A dotted IPv4 address starting at `start`: its length and first octet.
+fn ipv4_at(text: &str, start: usize) -> Option<(usize, u8)> {
+    let bytes = text.as_bytes();
+    let mut pos = start;
+    let mut first = None;
+    for octet in 0..4 {
+        if octet > 0 {
+            if bytes.get(pos) != Some(&b'.') {
+                return None;
+            }
+            pos += 1;
+        }
+        let digits = bytes[pos.min(bytes.len())..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if !(1..=3).contains(&digits) {
+            return None;
+        }
+        let value: u8 = text[pos..pos + digits].parse().ok()?;
+        first.get_or_insert(value);
+        pos += digits;
+    }
+    ends_word(text, pos).then(|| (pos - start, first.unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_addresses_keys_and_names_as_whole_words() {
+        let config = Config::sample();
+        let masker = Masker::new(&config, false);
+        assert_eq!(
+            masker.mask("ssh -i ~/.ssh/prod_id_ed25519 -p 22 deploy@52.14.33.10"),
+            "ssh -i prod_id_ed25519 -p 22 deploy@52.x.x.x"
+        );
+        // Unknown IPv4 addresses go too; other numbers and names stay.
+        assert_eq!(
+            masker.mask("prod-web via 10.0.0.7, port 2222, v1.2.3."),
+            "prod-web via 10.x.x.x, port 2222, v1.2.3."
+        );
+        assert_eq!(masker.mask("nothing here"), "nothing here");
+        assert!(matches!(masker.mask("nothing here"), Cow::Borrowed(_)));
+
+        let masker = Masker::new(&config, true);
+        let name = pseudonym("prod-web");
+        assert_eq!(name, pseudonym("prod-web"));
+        assert!(name.starts_with("host-") && name.len() == 9, "{name}");
+        assert_eq!(
+            masker.mask("Connecting to prod-web."),
+            format!("Connecting to {name}.")
+        );
+        assert_eq!(masker.mask("prod-web-2"), "prod-web-2");
+    }
+
+    #[test]
+    fn address_masks_keep_only_the_coarse_part() {
+        assert_eq!(mask_address("10.1.2.3"), "10.x.x.x");
+        assert_eq!(mask_address("db.eu.example.com"), "█████.com");
+        assert_eq!(mask_address("bastion"), "█████");
+        assert_eq!(mask_address("2001:db8::1"), "2001:…");
+
+        let mut config = Config::default();
+        config.hosts.push(crate::model::Host {
+            name: "a".into(),
+            address: "example.com".into(),
+            ..Default::default()
+        });
+        config.hosts.push(crate::model::Host {
+            name: "b".into(),
+            address: "db.example.com".into(),
+            ..Default::default()
+        });
+        let masker = Masker::new(&config, false);
+        assert_eq!(
+            masker.mask("db.example.com and example.com"),
+            "█████.com and █████.com"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Checks over the whole host database, listed in the problems panel (`!`).
+
+use crate::model::{Config, Host};
+use crate::net::AddressClass;
+use crate::preflight;
+use crate::runloop::LoopConfig;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// The address only works through the owner's ssh_config; silenced by
+    /// `alias_ok`.
+    SshConfigAlias,
+    /// The ssh binary is missing or can't be executed; not tied to a host.
+    SshUnavailable,
+    /// An `[[auto_tags]]` rule that can never apply; not tied to a host.
+    AutoTagRule,
+    /// A setting out of bounds and clamped, such as `poll_ms = 0`, or one
+    /// naming something that doesn't exist.
+    Setting,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    /// Index into `Config::hosts`; `None` for problems with the setup.
+    pub index: Option<usize>,
+    pub kind: ProblemKind,
+    pub message: String,
+}
+
+/// All problems, in host order. `classify` reports how each address
+/// resolves (normally `LocalResolver::classify`).
+pub fn find(config: &Config, classify: impl Fn(&str) -> AddressClass) -> Vec<Problem> {
+    let rules = config
+        .auto_tags
+        .iter()
+        .enumerate()
+        .filter_map(|(number, rule)| {
+            let err = rule.check().err()?;
+            Some(Problem {
+                index: None,
+                kind: ProblemKind::AutoTagRule,
+                message: format!("auto_tags rule {}: {err:#}", number + 1),
+            })
+        });
+    let skips = preflight::unknown_skips(config).into_iter().map(|name| {
+        format!("preflight_skip: no check named '{name}' (bastion, keys, port, window, dry_run)")
+    });
+    let settings = LoopConfig::problems(config)
+        .into_iter()
+        .chain(skips)
+        .chain(config.theme.problems())
+        .map(|message| Problem {
+            index: None,
+            kind: ProblemKind::Setting,
+            message,
+        });
+    let aliases = config
+        .hosts
+        .iter()
+        .enumerate()
+        .filter(|(_, host)| alias_candidate(host))
+        .filter(|(_, host)| classify(&host.address) == AddressClass::PossibleAlias)
+        .map(|(index, host)| Problem {
+            index: Some(index),
+            kind: ProblemKind::SshConfigAlias,
+            message: format!(
+                "{}: '{}' does not resolve here; it may be an ssh_config alias that won't work for anyone else",
+                config.display_name(host),
+                host.address
+            ),
+        });
+    settings.chain(rules).chain(aliases).collect()
+}
+
+/// Hosts whose address is worth checking for being an alias. Templates hand
+/// the address to another tool, and behind a bastion the name is resolved
+/// on the jump host, so neither says anything about local resolution.
+pub fn alias_candidate(host: &Host) -> bool {
+    !host.alias_ok && host.command_template.is_none() && host.bastion.is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(name: &str, address: &str) -> Host {
+        Host {
+            name: name.into(),
+            address: address.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flags_unresolvable_single_label_addresses_only() {
+        let mut config = Config {
+            hosts: vec![
+                host("jump", "corp-jump"),
+                host("web", "web.example.com"),
+                host("ip", "10.0.0.1"),
+                host("nas", "nas"),
+                host("acked", "corp-db"),
+                host("tsh", "i-0abc"),
+                host("inner", "db1"),
+            ],
+            ..Default::default()
+        };
+        config.hosts[4].alias_ok = true;
+        config.hosts[5].command_template = Some("tsh ssh {address}".into());
+        config.hosts[6].bastion = Some("jump".into());
+
+        let problems = find(&config, |address| {
+            crate::net::classify_address(address, Some(address == "nas"))
+        });
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].index, Some(0));
+        assert_eq!(problems[0].kind, ProblemKind::SshConfigAlias);
+        assert!(problems[0].message.contains("'corp-jump'"));
+        assert!(problems[0].message.contains("ssh_config alias"));
+    }
+
+    #[test]
+    fn broken_auto_tag_rules_are_problems() {
+        let config = Config {
+            auto_tags: vec![crate::autotag::AutoTagRule {
+                address_regex: Some("[".into()),
+                tags: vec!["lan".into()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let problems = find(&config, |_| AddressClass::Hostname);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].kind, ProblemKind::AutoTagRule);
+        assert!(problems[0]
+            .message
+            .starts_with("auto_tags rule 1: address_regex '['"));
+    }
+
+    #[test]
+    fn clamped_settings_are_problems() {
+        let config = Config {
+            poll_ms: 0,
+            ..Default::default()
+        };
+        let problems = find(&config, |_| AddressClass::Hostname);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].kind, ProblemKind::Setting);
+        assert_eq!(problems[0].message, "poll_ms = 0 is below 10; using 10");
+
+        let config = Config {
+            preflight_skip: vec!["port".into(), "ports".into()],
+            ..Default::default()
+        };
+        let problems = find(&config, |_| AddressClass::Hostname);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].kind, ProblemKind::Setting);
+        assert!(problems[0]
+            .message
+            .starts_with("preflight_skip: no check named 'ports'"));
+
+        let mut config = Config::default();
+        config.theme.accent = Some("#46b9c8".into());
+        config.theme.muted = Some("grey".into());
+        let problems = find(&config, |_| AddressClass::Hostname);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            problems[0].message,
+            "theme.muted = \"grey\" is not a #rrggbb colour; using the default"
+        );
+    }
+
+    #[test]
+    fn pending_lookups_are_not_problems_yet() {
+        let config = Config {
+            hosts: vec![host("jump", "corp-jump")],
+            ..Default::default()
+        };
+        let pending = |address: &str| crate::net::classify_address(address, None);
+        assert!(find(&config, pending).is_empty());
+    }
+}
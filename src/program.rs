@@ -0,0 +1,197 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Finding the ssh binary (`ssh_binary`, default `ssh`) the way the OS
+//! would before spawning it, so a missing or unusable one is reported as
+//! such instead of as a bare "No such file or directory".
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Program run for connections when `ssh_binary` is not set.
+pub const DEFAULT_SSH: &str = "ssh";
+
+/// Why a program can't be started.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Unavailable {
+    /// Not in any of the `searched` directories (empty when `PATH` is
+    /// unset), or, for a path, not there at all.
+    NotFound { searched: Vec<PathBuf> },
+    /// The file exists but can't be executed.
+    NotExecutable(PathBuf),
+}
+
+impl Unavailable {
+    /// One sentence for `program` saying where it was looked for; callers
+    /// add what to do about it.
+    pub fn message(&self, program: &str) -> String {
+        match self {
+            Unavailable::NotFound { .. } if is_path(program) => {
+                format!("{program} does not exist.")
+            }
+            Unavailable::NotFound { searched } => {
+                let dirs = if searched.is_empty() {
+                    "PATH is not set".to_string()
+                } else {
+                    format!(
+                        "searched PATH: {}",
+                        searched
+                            .iter()
+                            .map(|dir| dir.display().to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                };
+                format!("'{program}' not found ({dirs}).")
+            }
+            Unavailable::NotExecutable(path) => format!("{} is not executable.", path.display()),
+        }
+    }
+}
+
+/// Where `program` would be run from with `path` as `PATH`. Names with a
+/// directory part are checked as given.
+pub fn find(program: &str, path: Option<&OsStr>) -> Result<PathBuf, Unavailable> {
+    if is_path(program) {
+        let program = Path::new(program);
+        return match candidates(program).into_iter().find(|p| p.is_file()) {
+            Some(found) if is_executable(&found) => Ok(found),
+            Some(found) => Err(Unavailable::NotExecutable(found)),
+            None => Err(Unavailable::NotFound {
+                searched: Vec::new(),
+            }),
+        };
+    }
+    let searched: Vec<PathBuf> = path
+        .map(|path| std::env::split_paths(path).collect())
+        .unwrap_or_default();
+    let mut not_executable = None;
+    for dir in &searched {
+        for candidate in candidates(&dir.join(program)) {
+            if !candidate.is_file() {
+                continue;
+            }
+            if is_executable(&candidate) {
+                return Ok(candidate);
+            }
+            not_executable.get_or_insert(candidate);
+        }
+    }
+    Err(match not_executable {
+        Some(found) => Unavailable::NotExecutable(found),
+        None => Unavailable::NotFound { searched },
+    })
+}
+
+/// `find` with the current `PATH`.
+pub fn find_on_path(program: &str) -> Result<PathBuf, Unavailable> {
+    find(program, std::env::var_os("PATH").as_deref())
+}
+
+fn is_path(program: &str) -> bool {
+    Path::new(program).components().count() > 1
+}
+
+#[cfg(unix)]
+fn candidates(path: &Path) -> Vec<PathBuf> {
+    vec![path.to_path_buf()]
+}
+
+/// Without an extension Windows tries each of `PATHEXT` in turn.
+#[cfg(not(unix))]
+fn candidates(path: &Path) -> Vec<PathBuf> {
+    if path.extension().is_some() {
+        return vec![path.to_path_buf()];
+    }
+    let exts = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+    exts.split(';')
+        .filter(|ext| !ext.is_empty())
+        .map(|ext| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(ext);
+            PathBuf::from(name)
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|meta| meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn file(path: &Path, mode: u32) {
+        fs::write(path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn finds_the_first_executable_on_path() {
+        let dir = tempdir().unwrap();
+        let (first, second) = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir(&first).unwrap();
+        fs::create_dir(&second).unwrap();
+        file(&first.join("ssh"), 0o644);
+        file(&second.join("ssh"), 0o755);
+        let path = std::env::join_paths([&first, &second]).unwrap();
+        assert_eq!(find("ssh", Some(&path)), Ok(second.join("ssh")));
+
+        let explicit = second.join("ssh");
+        assert_eq!(find(explicit.to_str().unwrap(), None), Ok(explicit));
+    }
+
+    #[test]
+    fn missing_programs_name_the_directories_searched() {
+        let dir = tempdir().unwrap();
+        let path = std::env::join_paths([dir.path()]).unwrap();
+        let err = find("ssh", Some(&path)).unwrap_err();
+        assert_eq!(
+            err,
+            Unavailable::NotFound {
+                searched: vec![dir.path().to_path_buf()]
+            }
+        );
+        assert!(err
+            .message("ssh")
+            .contains(&format!("searched PATH: {}", dir.path().display())));
+        assert!(find("ssh", None)
+            .unwrap_err()
+            .message("ssh")
+            .contains("PATH is not set"));
+
+        let missing = dir.path().join("bin/ssh");
+        let err = find(missing.to_str().unwrap(), Some(&path)).unwrap_err();
+        assert!(err
+            .message(missing.to_str().unwrap())
+            .contains("does not exist"));
+    }
+
+    #[test]
+    fn files_without_execute_permission_are_reported() {
+        let dir = tempdir().unwrap();
+        file(&dir.path().join("ssh"), 0o644);
+        let path = std::env::join_paths([dir.path()]).unwrap();
+        let err = find("ssh", Some(&path)).unwrap_err();
+        assert_eq!(err, Unavailable::NotExecutable(dir.path().join("ssh")));
+        assert!(err.message("ssh").contains("is not executable"));
+
+        let explicit = dir.path().join("ssh");
+        assert_eq!(
+            find(explicit.to_str().unwrap(), Some(&path)),
+            Err(Unavailable::NotExecutable(explicit))
+        );
+    }
+}
@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Line-based fallback UI for terminals where raw mode or the alternate
+//! screen misbehave. Everything is printed into the scrollback and input is
+//! read a line at a time.
+
+use std::fmt::Write as _;
+
+use anyhow::Result;
+
+use crate::app::{App, AppAction};
+use crate::ssh;
+use crate::status::StatusKind;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PromptState {
+    Main,
+    ConfirmConnect,
+}
+
+pub enum PromptOutcome {
+    Continue,
+    Run(ssh::SshCommand),
+    Quit,
+}
+
+pub struct PromptSession {
+    state: PromptState,
+}
+
+impl Default for PromptSession {
+    fn default() -> Self {
+        Self {
+            state: PromptState::Main,
+        }
+    }
+}
+
+impl PromptSession {
+    pub fn prompt(&self) -> &'static str {
+        match self.state {
+            PromptState::Main => "sshdb> ",
+            PromptState::ConfirmConnect => "connect? [Y/n] ",
+        }
+    }
+
+    /// Handles one input line, appending anything to show to `out`.
+    pub fn handle_line(
+        &mut self,
+        app: &mut App,
+        line: &str,
+        out: &mut String,
+    ) -> Result<PromptOutcome> {
+        let line = line.trim();
+        match self.state {
+            PromptState::ConfirmConnect => {
+                self.state = PromptState::Main;
+                if line.is_empty() || line.eq_ignore_ascii_case("y") {
+                    let action = app.connect(None)?;
+                    write_status(app, out);
+                    if let Some(AppAction::RunSsh(cmd)) = action {
+                        return Ok(PromptOutcome::Run(cmd));
+                    }
+                } else {
+                    out.push_str("cancelled\n");
+                }
+                Ok(PromptOutcome::Continue)
+            }
+            PromptState::Main => self.handle_main(app, line, out),
+        }
+    }
+
+    fn handle_main(
+        &mut self,
+        app: &mut App,
+        line: &str,
+        out: &mut String,
+    ) -> Result<PromptOutcome> {
+        if line == "q" || line == "quit" {
+            return Ok(PromptOutcome::Quit);
+        }
+        if line.is_empty() {
+            render_list(app, out);
+            return Ok(PromptOutcome::Continue);
+        }
+        if line == "?" || line == "help" {
+            out.push_str(HELP);
+            return Ok(PromptOutcome::Continue);
+        }
+        if line == "C" {
+            app.dry_run = !app.dry_run;
+            let _ = writeln!(out, "dry-run {}", if app.dry_run { "on" } else { "off" });
+            return Ok(PromptOutcome::Continue);
+        }
+        if let Some(query) = line.strip_prefix('/') {
+            app.set_filter(query.trim());
+            render_list(app, out);
+            return Ok(PromptOutcome::Continue);
+        }
+        if let Some(spec) = line.strip_prefix("g ") {
+            let action = app.quick_connect_str(spec)?;
+            write_status(app, out);
+            if let Some(AppAction::RunSsh(cmd)) = action {
+                return Ok(PromptOutcome::Run(cmd));
+            }
+            return Ok(PromptOutcome::Continue);
+        }
+        if let Ok(n) = line.parse::<usize>() {
+            if n == 0 || n > app.filtered_indices.len() {
+                let _ = writeln!(out, "no host #{n}");
+                return Ok(PromptOutcome::Continue);
+            }
+            app.selected = n - 1;
+            if let Some(host) = app.current_host() {
+                let preview = ssh::command_preview(
+                    host,
+                    &app.config,
+                    app.config.default_key.as_deref(),
+                    None,
+                );
+                let _ = writeln!(out, "{preview}");
+                self.state = PromptState::ConfirmConnect;
+            }
+            return Ok(PromptOutcome::Continue);
+        }
+        let _ = writeln!(out, "unknown command '{line}' (? for help)");
+        Ok(PromptOutcome::Continue)
+    }
+}
+
+const HELP: &str = "  <n>        connect to host number n\n  \
+                    /<query>   filter hosts (/ alone clears)\n  \
+                    g <ssh>    quick connect (ssh user@host)\n  \
+                    C          toggle dry-run\n  \
+                    <empty>    list hosts\n  \
+                    q          quit\n";
+
+pub fn render_list(app: &App, out: &mut String) {
+    if !app.filter.is_empty() {
+        let _ = writeln!(out, "filter: {}", app.filter);
+    }
+    if app.filtered_indices.is_empty() {
+        out.push_str("(no hosts)\n");
+    }
+    for (pos, idx) in app.filtered_indices.iter().enumerate() {
+        let host = &app.config.hosts[*idx];
+        let _ = writeln!(
+            out,
+            "{:>3}  {:<24} {}",
+            pos + 1,
+            app.config.display_name(host),
+            host.display_label()
+        );
+    }
+}
+
+fn write_status(app: &App, out: &mut String) {
+    if let Some(status) = app.status.current() {
+        let prefix = match status.kind {
+            StatusKind::Info => "",
+            StatusKind::Warn => "warning: ",
+            StatusKind::Error => "error: ",
+        };
+        let _ = writeln!(out, "{prefix}{}", status.text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::tests::test_app;
+
+    #[test]
+    fn lists_and_filters_hosts() {
+        let mut app = test_app();
+        let mut session = PromptSession::default();
+        let mut out = String::new();
+        session.handle_line(&mut app, "", &mut out).unwrap();
+        assert!(out.contains("prod-web"));
+        assert!(out.contains("jump-eu"));
+
+        out.clear();
+        session.handle_line(&mut app, "/staging", &mut out).unwrap();
+        assert!(out.contains("staging-db"));
+        assert!(!out.contains("prod-web"));
+    }
+
+    #[test]
+    fn number_then_confirm_runs_ssh() {
+        let mut app = test_app();
+        let mut session = PromptSession::default();
+        let mut out = String::new();
+        session.handle_line(&mut app, "1", &mut out).unwrap();
+        assert_eq!(session.prompt(), "connect? [Y/n] ");
+        assert!(out.contains("deploy@52.14.33.10"));
+
+        let outcome = session.handle_line(&mut app, "y", &mut out).unwrap();
+        assert!(matches!(outcome, PromptOutcome::Run(_)));
+        assert_eq!(session.prompt(), "sshdb> ");
+    }
+
+    #[test]
+    fn declining_confirm_returns_to_main() {
+        let mut app = test_app();
+        let mut session = PromptSession::default();
+        let mut out = String::new();
+        session.handle_line(&mut app, "2", &mut out).unwrap();
+        let outcome = session.handle_line(&mut app, "n", &mut out).unwrap();
+        assert!(matches!(outcome, PromptOutcome::Continue));
+        assert!(out.contains("cancelled"));
+    }
+
+    #[test]
+    fn rejects_out_of_range_and_quits() {
+        let mut app = test_app();
+        let mut session = PromptSession::default();
+        let mut out = String::new();
+        session.handle_line(&mut app, "99", &mut out).unwrap();
+        assert!(out.contains("no host #99"));
+        let outcome = session.handle_line(&mut app, "q", &mut out).unwrap();
+        assert!(matches!(outcome, PromptOutcome::Quit));
+    }
+
+    #[test]
+    fn quick_connect_respects_dry_run() {
+        let mut app = test_app();
+        app.dry_run = true;
+        let mut session = PromptSession::default();
+        let mut out = String::new();
+        let outcome = session
+            .handle_line(&mut app, "g ops@10.9.9.9", &mut out)
+            .unwrap();
+        assert!(matches!(outcome, PromptOutcome::Continue));
+        assert!(out.contains("Dry-run"));
+    }
+}
@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Pacing of the full-screen loop: how long it waits for input (`poll_ms`)
+//! and how often it may redraw (`max_fps`). Values outside the bounds are
+//! clamped, so `poll_ms = 0` polls every 10 ms instead of spinning, and
+//! listed in the problems panel.
+
+use std::time::{Duration, Instant};
+
+use crate::model::Config;
+
+pub const DEFAULT_POLL_MS: u64 = 80;
+const POLL_MS: (u64, u64) = (10, 1000);
+const FPS: (u32, u32) = (1, 240);
+
+/// The loop's timing, as read from the config.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoopConfig {
+    /// How long to wait for input before ticking again.
+    pub poll: Duration,
+    /// Shortest time between two frames; `None` draws on every pass.
+    pub min_frame: Option<Duration>,
+}
+
+impl Default for LoopConfig {
+    fn default() -> Self {
+        Self::from_config(&Config::default())
+    }
+}
+
+impl LoopConfig {
+    pub fn from_config(config: &Config) -> Self {
+        let poll_ms = config.poll_ms.clamp(POLL_MS.0, POLL_MS.1);
+        Self {
+            poll: Duration::from_millis(poll_ms),
+            min_frame: config
+                .max_fps
+                .map(|fps| Duration::from_secs(1) / fps.clamp(FPS.0, FPS.1)),
+        }
+    }
+
+    /// The settings that had to be clamped, e.g. `poll_ms = 0 is below 10;
+    /// using 10`.
+    pub fn problems(config: &Config) -> Vec<String> {
+        let mut problems = Vec::new();
+        let (low, high) = POLL_MS;
+        match config.poll_ms {
+            ms if ms < low => problems.push(format!("poll_ms = {ms} is below {low}; using {low}")),
+            ms if ms > high => {
+                problems.push(format!("poll_ms = {ms} is above {high}; using {high}"))
+            }
+            _ => {}
+        }
+        let (low, high) = FPS;
+        match config.max_fps {
+            Some(fps) if fps < low => {
+                problems.push(format!("max_fps = {fps} is below {low}; using {low}"))
+            }
+            Some(fps) if fps > high => {
+                problems.push(format!("max_fps = {fps} is above {high}; using {high}"))
+            }
+            _ => {}
+        }
+        problems
+    }
+}
+
+/// Decides, pass by pass, whether to draw and how long to wait for input.
+/// A frame held back by `max_fps` shortens the wait so it still shows
+/// once its time comes.
+#[derive(Clone, Debug, Default)]
+pub struct Pacer {
+    last_frame: Option<Instant>,
+    held: bool,
+}
+
+impl Pacer {
+    /// Whether to draw at `now`; a `true` counts as the frame drawn.
+    pub fn draw_now(&mut self, timing: &LoopConfig, now: Instant) -> bool {
+        let due = match (timing.min_frame, self.last_frame) {
+            (Some(min), Some(last)) => now.saturating_duration_since(last) >= min,
+            _ => true,
+        };
+        self.held = !due;
+        if due {
+            self.last_frame = Some(now);
+        }
+        due
+    }
+
+    /// Counts a frame drawn outside `draw_now`, such as the one a terminal
+    /// resize gets straight away.
+    pub fn drawn(&mut self, now: Instant) {
+        self.last_frame = Some(now);
+        self.held = false;
+    }
+
+    /// How long to wait for input after a pass at `now`.
+    pub fn timeout(&self, timing: &LoopConfig, now: Instant) -> Duration {
+        match (self.held, timing.min_frame, self.last_frame) {
+            (true, Some(min), Some(last)) => {
+                (last + min).saturating_duration_since(now).min(timing.poll)
+            }
+            _ => timing.poll,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(poll_ms: u64, max_fps: Option<u32>) -> Config {
+        Config {
+            poll_ms,
+            max_fps,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn settings_are_clamped_and_reported() {
+        assert_eq!(
+            LoopConfig::default(),
+            LoopConfig {
+                poll: Duration::from_millis(80),
+                min_frame: None
+            }
+        );
+        assert!(LoopConfig::problems(&Config::default()).is_empty());
+
+        let busy = config(0, Some(0));
+        let timing = LoopConfig::from_config(&busy);
+        assert_eq!(timing.poll, Duration::from_millis(10));
+        assert_eq!(timing.min_frame, Some(Duration::from_secs(1)));
+        assert_eq!(
+            LoopConfig::problems(&busy),
+            [
+                "poll_ms = 0 is below 10; using 10",
+                "max_fps = 0 is below 1; using 1"
+            ]
+        );
+
+        let slow = config(60_000, Some(1000));
+        let timing = LoopConfig::from_config(&slow);
+        assert_eq!(timing.poll, Duration::from_secs(1));
+        assert_eq!(timing.min_frame, Some(Duration::from_secs(1) / 240));
+        assert_eq!(LoopConfig::problems(&slow).len(), 2);
+
+        let parsed: Config = toml::from_str("version = 2\npoll_ms = 250\nmax_fps = 30\n").unwrap();
+        let timing = LoopConfig::from_config(&parsed);
+        assert_eq!(timing.poll, Duration::from_millis(250));
+        assert_eq!(timing.min_frame, Some(Duration::from_secs(1) / 30));
+    }
+
+    #[test]
+    fn capped_frames_are_held_back_and_still_drawn() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        let uncapped = LoopConfig::from_config(&config(50, None));
+        let mut pacer = Pacer::default();
+        assert!(pacer.draw_now(&uncapped, start));
+        assert!(pacer.draw_now(&uncapped, start + ms(1)));
+        assert_eq!(pacer.timeout(&uncapped, start + ms(1)), ms(50));
+
+        // 10 fps: a frame every 100 ms, and the poll never waits past it.
+        let capped = LoopConfig::from_config(&config(500, Some(10)));
+        let mut pacer = Pacer::default();
+        assert!(pacer.draw_now(&capped, start));
+        assert_eq!(pacer.timeout(&capped, start), ms(500));
+        assert!(!pacer.draw_now(&capped, start + ms(30)));
+        assert_eq!(pacer.timeout(&capped, start + ms(30)), ms(70));
+        assert!(pacer.draw_now(&capped, start + ms(100)));
+        assert_eq!(pacer.timeout(&capped, start + ms(100)), ms(500));
+
+        // A resize frame drawn in between resets the cap.
+        assert!(!pacer.draw_now(&capped, start + ms(130)));
+        pacer.drawn(start + ms(140));
+        assert_eq!(pacer.timeout(&capped, start + ms(140)), ms(500));
+        assert!(!pacer.draw_now(&capped, start + ms(200)));
+        assert!(pacer.draw_now(&capped, start + ms(240)));
+    }
+}
@@ -0,0 +1,144 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Scripted sessions: feeds terminal events to an `App` the way the
+//! full-screen loop does (event, tick, draw) and renders every step into a
+//! `TestBackend`, so integration tests and demo recordings can drive the
+//! real UI and assert on what is on screen.
+//!
+//! Nothing here starts ssh: actions the app returns are collected in
+//! `Session::actions`. Freeze windows follow `App::set_clock`; hostname
+//! lookups still run in the background, so scripts that assert on local
+//! address warnings should use IP addresses.
+
+use anyhow::Result;
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use ratatui::backend::TestBackend;
+use ratatui::buffer::Buffer;
+use ratatui::Terminal;
+
+use crate::app::{App, AppAction};
+use crate::ui;
+
+pub struct Session {
+    pub app: App,
+    terminal: Terminal<TestBackend>,
+    /// Every rendered frame, oldest first; the first is the initial screen.
+    pub frames: Vec<String>,
+    /// What the app asked the frontend to do, in order.
+    pub actions: Vec<AppAction>,
+}
+
+impl Session {
+    /// A session on a `width`×`height` terminal, with the first frame drawn.
+    pub fn new(app: App, width: u16, height: u16) -> Result<Self> {
+        let mut session = Self {
+            app,
+            terminal: Terminal::new(TestBackend::new(width, height))?,
+            frames: Vec::new(),
+            actions: Vec::new(),
+        };
+        session.draw()?;
+        Ok(session)
+    }
+
+    /// Feeds one event, then ticks and draws like the main loop.
+    pub fn event(&mut self, event: Event) -> Result<&mut Self> {
+        if let Some(action) = self.app.on_event(event)? {
+            self.actions.push(action);
+        }
+        self.draw()?;
+        Ok(self)
+    }
+
+    pub fn events(&mut self, events: impl IntoIterator<Item = Event>) -> Result<&mut Self> {
+        for event in events {
+            self.event(event)?;
+        }
+        Ok(self)
+    }
+
+    pub fn key(&mut self, code: KeyCode) -> Result<&mut Self> {
+        self.event(Event::Key(KeyEvent::from(code)))
+    }
+
+    pub fn ctrl(&mut self, c: char) -> Result<&mut Self> {
+        self.event(Event::Key(KeyEvent::new(
+            KeyCode::Char(c),
+            KeyModifiers::CONTROL,
+        )))
+    }
+
+    /// Types `text` one key press per character.
+    pub fn type_text(&mut self, text: &str) -> Result<&mut Self> {
+        self.events(text.chars().map(|c| {
+            let modifiers = if c.is_ascii_uppercase() {
+                KeyModifiers::SHIFT
+            } else {
+                KeyModifiers::NONE
+            };
+            Event::Key(KeyEvent::new(KeyCode::Char(c), modifiers))
+        }))
+    }
+
+    /// A bracketed paste of `text`.
+    pub fn paste(&mut self, text: &str) -> Result<&mut Self> {
+        self.event(Event::Paste(text.into()))
+    }
+
+    /// The last rendered frame.
+    pub fn screen(&self) -> &str {
+        self.frames.last().map_or("", String::as_str)
+    }
+
+    /// Where the last frame left the cursor.
+    pub fn cursor(&mut self) -> Result<(u16, u16)> {
+        use ratatui::backend::Backend;
+        Ok(self.terminal.backend_mut().get_cursor()?)
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        self.app.tick();
+        let app = &self.app;
+        let frame = self.terminal.draw(|f| ui::render(f, app))?;
+        self.frames.push(buffer_to_string(frame.buffer));
+        Ok(())
+    }
+}
+
+/// The buffer's text, one line per row with trailing blanks trimmed. Wide
+/// glyphs appear once, as on screen.
+pub fn buffer_to_string(buffer: &Buffer) -> String {
+    let area = buffer.area;
+    (area.top()..area.bottom())
+        .map(|y| {
+            let mut line = String::new();
+            let mut skip = 0;
+            for x in area.left()..area.right() {
+                let symbol = buffer.get(x, y).symbol();
+                if skip > 0 {
+                    skip -= 1;
+                    continue;
+                }
+                skip = crate::text::width(symbol).saturating_sub(1);
+                line.push_str(symbol);
+            }
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::layout::Rect;
+
+    #[test]
+    fn buffer_text_trims_rows_and_shows_wide_glyphs_once() {
+        let mut buffer = Buffer::empty(Rect::new(0, 0, 8, 2));
+        buffer.set_string(0, 0, "東京 ok", ratatui::style::Style::default());
+        buffer.set_string(1, 1, "x", ratatui::style::Style::default());
+        assert_eq!(buffer_to_string(&buffer), "東京 ok\n x");
+    }
+}
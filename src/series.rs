@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Numbered host series (`web-02`, `web-03`, …): the trailing number of a
+//! name and where the same number shows up in the address, for `Y`
+//! (duplicate as the next host of the series).
+
+use std::net::{IpAddr, Ipv4Addr};
+
+/// The trailing run of ASCII digits in `s`, split off as `(prefix, digits)`.
+fn trailing_number(s: &str) -> Option<(&str, &str)> {
+    let start = s.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    (start < s.len()).then(|| s.split_at(start))
+}
+
+/// `digits` plus `step`, zero-padded to at least the original width.
+fn bump(digits: &str, step: u64) -> Option<String> {
+    let next = digits.parse::<u64>().ok()?.checked_add(step)?;
+    Some(format!("{next:0width$}", width = digits.len()))
+}
+
+/// The value of `s`'s trailing number, if it has one.
+pub fn number(s: &str) -> Option<u64> {
+    trailing_number(s)?.1.parse().ok()
+}
+
+/// `s` with its trailing number raised by `step`, keeping zero padding:
+/// `web-09` → `web-10`, `host2` → `host3`.
+pub fn increment(s: &str, step: u64) -> Option<String> {
+    let (prefix, digits) = trailing_number(s)?;
+    Some(format!("{prefix}{}", bump(digits, step)?))
+}
+
+/// `address` with the one digit run whose value is `number` raised by
+/// `step` (`web-02.prod` → `web-03.prod` for 2). IP addresses and names
+/// where the number is missing or appears more than once are left alone.
+pub fn increment_hostname(address: &str, number: u64, step: u64) -> Option<String> {
+    if address.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (at, c) in address.char_indices().chain([(address.len(), ' ')]) {
+        match (c.is_ascii_digit(), start) {
+            (true, None) => start = Some(at),
+            (false, Some(from)) => {
+                runs.push(from..at);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    let mut matching = runs
+        .into_iter()
+        .filter(|run| address[run.clone()].parse::<u64>().ok() == Some(number));
+    let run = matching.next()?;
+    if matching.next().is_some() {
+        return None;
+    }
+    Some(format!(
+        "{}{}{}",
+        &address[..run.start],
+        bump(&address[run.clone()], step)?,
+        &address[run.end..]
+    ))
+}
+
+/// An IPv4 address with its last octet raised by `step`; `None` for
+/// anything else or when the octet would overflow. IPv6 is never touched.
+pub fn increment_ipv4(address: &str, step: u64) -> Option<String> {
+    let ip = address.parse::<Ipv4Addr>().ok()?;
+    let [a, b, c, d] = ip.octets();
+    let d = u8::try_from(u64::from(d).checked_add(step)?).ok()?;
+    Some(Ipv4Addr::new(a, b, c, d).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increments_the_trailing_number_keeping_padding() {
+        assert_eq!(increment("web-09", 1).as_deref(), Some("web-10"));
+        assert_eq!(increment("web-02", 3).as_deref(), Some("web-05"));
+        assert_eq!(increment("host2", 1).as_deref(), Some("host3"));
+        assert_eq!(increment("db-099", 1).as_deref(), Some("db-100"));
+        assert_eq!(increment("node-99", 1).as_deref(), Some("node-100"));
+        assert_eq!(increment("42", 1).as_deref(), Some("43"));
+        assert_eq!(increment("web", 1), None);
+        assert_eq!(increment("web-2a", 1), None);
+        assert_eq!(number("web-007"), Some(7));
+        assert_eq!(number("web"), None);
+    }
+
+    #[test]
+    fn hostnames_follow_the_name_only_when_the_number_is_there_once() {
+        assert_eq!(
+            increment_hostname("web-02.prod", 2, 1).as_deref(),
+            Some("web-03.prod")
+        );
+        assert_eq!(
+            increment_hostname("web2.eu-1.example", 2, 1).as_deref(),
+            Some("web3.eu-1.example")
+        );
+        assert_eq!(increment_hostname("web.prod", 2, 1), None);
+        assert_eq!(increment_hostname("web-2.az2.example", 2, 1), None);
+        assert_eq!(increment_hostname("10.0.0.2", 2, 1), None);
+    }
+
+    #[test]
+    fn only_ipv4_addresses_are_incremented() {
+        assert_eq!(increment_ipv4("10.0.0.12", 1).as_deref(), Some("10.0.0.13"));
+        assert_eq!(increment_ipv4("10.0.0.255", 1), None);
+        assert_eq!(increment_ipv4("2001:db8::12", 1), None);
+        assert_eq!(increment_hostname("2001:db8::12", 12, 1), None);
+        assert_eq!(increment_ipv4("web-02.prod", 1), None);
+    }
+}
@@ -1,306 +1,2986 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
 
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Read, Write};
+use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 
-use crate::model::{Config, Host};
+use crate::debuglog;
+use crate::failure::{self, Diagnosis, Endpoint};
+use crate::hostkey::{self, KnownHostsFile};
+use crate::model::{is_var_name, Config, Host, IpFamily};
+use crate::program;
 
+/// Program file transfers (`f`) run.
+pub const SCP: &str = "scp";
+
+/// Set on ssh when the host has a `password_command`: the command that
+/// sshdb, started again by ssh as its `SSH_ASKPASS`, runs for the answer.
+pub const ASKPASS_COMMAND_ENV: &str = "SSHDB_PASSWORD_COMMAND";
+
+/// What previews show in place of the password a `password_command` gives,
+/// quoted so a pasted preview is still one plain command.
+const PASSWORD_PLACEHOLDER: &str = "SSH_ASKPASS='<from password_command>'";
+
+/// A command ready to spawn, plus the pinned known_hosts file it reads.
+/// The file is removed when this is dropped, so keep it until ssh exits.
+#[derive(Debug)]
+pub struct SshCommand {
+    // Boxed: a `Command` is large and this travels inside `AppAction`.
+    cmd: Box<Command>,
+    known_hosts: Option<KnownHostsFile>,
+    /// Jump hosts, then the target; for telling which hop a failure hit.
+    route: Vec<Endpoint>,
+    /// An scp copy (`f`) rather than a session.
+    transfer: bool,
+}
+
+impl SshCommand {
+    pub fn into_parts(self) -> (Command, Option<KnownHostsFile>) {
+        (*self.cmd, self.known_hosts)
+    }
+
+    /// Id of the host this connects to.
+    pub fn host_id(&self) -> &str {
+        self.route.last().map_or("", |target| target.label.as_str())
+    }
+
+    /// Whether this copies a file with scp instead of opening a session.
+    pub fn is_transfer(&self) -> bool {
+        self.transfer
+    }
+}
+
+/// A session that ended with a failure status, with what its stderr says
+/// went wrong when that could be read.
+#[derive(Debug, thiserror::Error)]
+#[error("{}", match .diagnosis {
+    Some(diagnosis) => diagnosis.to_string(),
+    None => format!("ssh exited with status {}", .status),
+})]
+pub struct SessionFailed {
+    pub status: ExitStatus,
+    pub diagnosis: Option<Diagnosis>,
+}
+
+/// How many of ssh's last stderr lines are kept for `failure::diagnose`.
+const STDERR_TAIL_LINES: usize = 50;
+
+impl Deref for SshCommand {
+    type Target = Command;
+
+    fn deref(&self) -> &Command {
+        &self.cmd
+    }
+}
+
+impl DerefMut for SshCommand {
+    fn deref_mut(&mut self) -> &mut Command {
+        &mut self.cmd
+    }
+}
+
+/// Builds the command for connecting to `host`. A pinned host key is
+/// resolved here (for a fingerprint, by scanning the host), so this fails
+/// rather than connect to a host presenting any other key.
+///
+/// A `password_command` is not run here: ssh gets this sshdb binary as
+/// `SSH_ASKPASS` (forced, so a terminal doesn't take over) and the command
+/// in `ASKPASS_COMMAND_ENV`, and `answer_askpass` runs it only when ssh
+/// asks for a password, putting any other prompt to the user. Its output
+/// goes straight to ssh, so it never reaches the log, the previews or the
+/// screen.
+/// Hosts reached through a bastion, a proxy or a command template are
+/// refused one, as the ssh processes those start would ask it as well.
 pub fn build_command(
     host: &Host,
     config: &Config,
     default_key: Option<&str>,
     extra_command: Option<&str>,
-) -> Result<Command> {
-    let mut cmd = Command::new("ssh");
+) -> Result<SshCommand> {
+    let mut argv = build_argv(host, config, default_key, extra_command)?;
+    let known_hosts = hostkey::pin(host, &mut argv)?;
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("command template for '{}' is empty", host.name))?;
+    if host.multiplex {
+        create_control_socket_dir()?;
+    }
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    if let Some(command) = &host.password_command {
+        let askpass = std::env::current_exe().map_err(|err| {
+            anyhow::anyhow!("can't find sshdb to answer ssh's password prompt: {err}")
+        })?;
+        cmd.env("SSH_ASKPASS", askpass)
+            .env("SSH_ASKPASS_REQUIRE", "force")
+            .env(ASKPASS_COMMAND_ENV, command);
+    }
+    Ok(SshCommand {
+        cmd: Box::new(cmd),
+        known_hosts,
+        route: route(host, config),
+        transfer: false,
+    })
+}
+
+/// The endpoints connecting to `host` passes through. Templated hosts
+/// bypass `-J`, so only the target is listed for them.
+fn route(host: &Host, config: &Config) -> Vec<Endpoint> {
+    let mut route = Vec::new();
+    if let (Some(bastion), None) = (&host.bastion, &host.command_template) {
+        for hop in bastion_hops(config, host, bastion, &mut Vec::new()).unwrap_or_default() {
+            let address = match hop.id.as_deref().and_then(|id| config.find_host(id)) {
+                Some(bastion) => bastion.address.clone(),
+                None => hop_address(&hop.target).to_string(),
+            };
+            route.push(Endpoint {
+                label: hop.id.unwrap_or(hop.reference),
+                address,
+                bastion: true,
+            });
+        }
+    }
+    route.push(Endpoint {
+        label: host.id(),
+        address: host.address.clone(),
+        bastion: false,
+    });
+    route
+}
+
+/// The address in a free-text `-J` hop like `user@host:port`.
+fn hop_address(target: &str) -> &str {
+    let address = target
+        .rsplit_once('@')
+        .map_or(target, |(_, address)| address);
+    if let Some(bracketed) = address.strip_prefix('[') {
+        return bracketed.split(']').next().unwrap_or(bracketed);
+    }
+    match address.split_once(':') {
+        Some((host, port)) if !port.contains(':') => host,
+        _ => address,
+    }
+}
+
+/// The full argv (program first) that connecting to `host` runs. This is the
+/// single source for both `build_command` and `command_preview`.
+pub fn build_argv(
+    host: &Host,
+    config: &Config,
+    default_key: Option<&str>,
+    extra_command: Option<&str>,
+) -> Result<Vec<String>> {
+    trace_argv(host, config, default_key, extra_command, &mut Vec::new())
+}
+
+/// One decision `build_argv` made, recorded as it builds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// `None` for OpenSSH; a template replaces the whole invocation.
+    Program {
+        template: Option<String>,
+    },
+    /// `connect_with = "mosh"`: ssh's flags go into mosh's `--ssh`.
+    Mosh,
+    /// Hops in the order ssh visits them.
+    Bastion(Vec<Hop>),
+    /// `proxy_command`, passed as `-o ProxyCommand=…`.
+    ProxyCommand(String),
+    Port(Option<u16>),
+    /// Only when the host sets `ip_family`.
+    IpFamily(IpFamily),
+    Keys {
+        keys: Vec<String>,
+        source: KeySource,
+    },
+    Options {
+        configured: Vec<String>,
+        applied: Vec<String>,
+        prefer_public_key: bool,
+    },
+    /// Only when the host sets `set_env`.
+    SetEnv(Vec<String>),
+    /// Only when the host sets `multiplex`; the ControlPath used.
+    Multiplex(String),
+    /// Only when the host sets `password_command`.
+    PasswordCommand,
+    /// Only when the host sets `forward_agent` or `forward_x11`.
+    Forwarding {
+        agent: bool,
+        x11: bool,
+    },
+    /// Only when the host sets `local_forwards` or `remote_forwards`.
+    Forwards {
+        local: Vec<String>,
+        remote: Vec<String>,
+    },
+    /// Only the pinned key is trusted; `full_key` is false when just the
+    /// fingerprint is pinned.
+    PinnedHostKey {
+        full_key: bool,
+        fingerprint: Option<String>,
+    },
+    Tty(Option<bool>),
+    Target(String),
+    Command {
+        command: Option<String>,
+        source: CommandSource,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hop {
+    /// The bastion reference as written on the host it came from.
+    pub reference: String,
+    /// Canonical id of the host it resolved to; `None` for a free-text hop.
+    pub id: Option<String>,
+    /// The `user@address:port` passed to `-J`.
+    pub target: String,
+}
+
+/// Why these keys (or none) are passed with `-i`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeySource {
+    Host,
+    DefaultKey,
+    /// `default_key = "agent"`.
+    AgentConfigured,
+    /// No key configured and `SSH_AUTH_SOCK` is set.
+    AgentDetected,
+    /// No key configured and no agent; `exists` tells whether the fallback
+    /// file was found.
+    Fallback {
+        exists: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandSource {
+    /// Given for this connection (the `c` prompt, watch, exec).
+    Extra,
+    /// The host's `remote_command`.
+    Host,
+    /// Interactive shell.
+    None,
+}
+
+/// Every decision behind a connection, plus the argv it produced (or why it
+/// could not be built).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Explanation {
+    pub steps: Vec<Step>,
+    pub argv: std::result::Result<Vec<String>, String>,
+}
+
+impl Explanation {
+    /// `(topic, detail)` rows for display, in argv order.
+    pub fn lines(&self) -> Vec<(&'static str, String)> {
+        let mut lines: Vec<(&'static str, String)> = self.steps.iter().map(Step::line).collect();
+        lines.push(match &self.argv {
+            Ok(argv) => (
+                "argv",
+                argv.iter()
+                    .map(|arg| shell_quote(arg))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            Err(err) => ("error", err.clone()),
+        });
+        lines
+    }
+}
+
+impl Step {
+    fn line(&self) -> (&'static str, String) {
+        match self {
+            Step::Program { template: None } => ("program", "ssh (OpenSSH)".into()),
+            Step::Program {
+                template: Some(template),
+            } => (
+                "program",
+                format!("command template `{template}`; bastion, port, IP family, options and tty settings are not applied"),
+            ),
+            Step::Mosh => (
+                "mosh",
+                "connect_with = mosh; ssh only starts the session, with the flags below passed in --ssh, and tty settings are not applied".into(),
+            ),
+            Step::Bastion(hops) => (
+                "bastion",
+                hops.iter()
+                    .map(|hop| match &hop.id {
+                        Some(id) if *id == hop.reference => format!("{} (host {id})", hop.target),
+                        Some(id) => format!("{} (host {id}, written '{}')", hop.target, hop.reference),
+                        None => format!("{} (free text, not a known host)", hop.target),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" → "),
+            ),
+            Step::ProxyCommand(command) => ("proxy", format!("ProxyCommand {command}")),
+            Step::Port(Some(port)) => ("port", format!("{port} from the host")),
+            Step::Port(None) => ("port", "not set; ssh_config or 22 applies".into()),
+            Step::IpFamily(IpFamily::V4) => ("family", "-4: ip_family = v4, IPv4 addresses only".into()),
+            Step::IpFamily(IpFamily::V6) => ("family", "-6: ip_family = v6, IPv6 addresses only".into()),
+            Step::Keys { keys, source } => {
+                let keys = keys.join(", ");
+                let detail = match source {
+                    KeySource::Host => format!("{keys} from the host's key_paths"),
+                    KeySource::DefaultKey => format!("{keys} from default_key (host has none)"),
+                    KeySource::AgentConfigured => {
+                        "none passed; default_key = \"agent\" leaves it to the ssh agent".into()
+                    }
+                    KeySource::AgentDetected => {
+                        "none passed; no key configured and SSH_AUTH_SOCK is set, so the agent is used"
+                            .into()
+                    }
+                    KeySource::Fallback { exists: true } => {
+                        format!("{keys}: no key configured and no agent, first existing fallback")
+                    }
+                    KeySource::Fallback { exists: false } => {
+                        format!("{keys}: no key configured, no agent and no fallback key found")
+                    }
+                };
+                ("keys", detail)
+            }
+            Step::Options {
+                configured,
+                applied,
+                prefer_public_key,
+            } => {
+                let mut detail = if applied.is_empty() {
+                    "none".to_string()
+                } else {
+                    applied.join(" ")
+                };
+                if *prefer_public_key {
+                    detail.push_str("; prefer_public_key_auth puts PreferredAuthentications=publickey first");
+                    if configured.len() + 2 != applied.len() {
+                        detail.push_str(" and drops the host's own PreferredAuthentications");
+                    }
+                }
+                ("options", detail)
+            }
+            Step::SetEnv(vars) => (
+                "env",
+                format!(
+                    "{} sent with SetEnv; the server's AcceptEnv decides which it takes",
+                    vars.join(" ")
+                ),
+            ),
+            Step::Multiplex(path) => (
+                "multiplex",
+                format!("ControlMaster=auto through {path}, kept open 60s after the last session"),
+            ),
+            Step::PasswordCommand => (
+                "password",
+                "answered by password_command through SSH_ASKPASS; its output is never shown"
+                    .to_string(),
+            ),
+            Step::Forwarding { agent, x11 } => (
+                "forwarding",
+                [(*agent, "ssh agent (-A)"), (*x11, "X11 (-X)")]
+                    .iter()
+                    .filter(|(on, _)| *on)
+                    .map(|(_, what)| *what)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            Step::Forwards { local, remote } => (
+                "forwards",
+                local
+                    .iter()
+                    .map(|spec| format!("-L {spec}"))
+                    .chain(remote.iter().map(|spec| format!("-R {spec}")))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            Step::PinnedHostKey {
+                full_key,
+                fingerprint,
+            } => (
+                "host key",
+                match (full_key, fingerprint) {
+                    (true, _) => "pinned_hostkey is the only accepted key; known_hosts is ignored and it is written to a temporary file while connecting".into(),
+                    (false, Some(fingerprint)) => format!("must match {fingerprint}; fetched with ssh-keyscan and checked before connecting, then trusted alone"),
+                    (false, None) => "pinned".into(),
+                },
+            ),
+            Step::Tty(Some(true)) => ("tty", "-t: request_tty = true".into()),
+            Step::Tty(Some(false)) => ("tty", "-T: request_tty = false".into()),
+            Step::Tty(None) => ("tty", "not set; ssh allocates one only without a command".into()),
+            Step::Target(target) => ("target", target.clone()),
+            Step::Command { command, source } => (
+                "command",
+                match (command, source) {
+                    (Some(command), CommandSource::Extra) => format!("{command} (given for this connection)"),
+                    (Some(command), _) => format!("{command} (the host's remote_command)"),
+                    (None, _) => "none; interactive shell".into(),
+                },
+            ),
+        }
+    }
+}
+
+/// Builds the argv the way `build_argv` does, recording each decision.
+pub fn explain(
+    host: &Host,
+    config: &Config,
+    default_key: Option<&str>,
+    extra_command: Option<&str>,
+) -> Explanation {
+    let mut steps = Vec::new();
+    let argv = trace_argv(host, config, default_key, extra_command, &mut steps)
+        .map_err(|err| err.to_string());
+    Explanation { steps, argv }
+}
+
+/// The command to run on `host`: `extra` when given for this connection,
+/// else its `remote_command`, with the host's vars substituted.
+fn remote_command(host: &Host, extra: Option<&str>) -> Result<(Option<String>, CommandSource)> {
+    let (command, source) = match (extra, &host.remote_command) {
+        (Some(extra), _) => (extra, CommandSource::Extra),
+        (None, Some(remote)) => (remote.as_str(), CommandSource::Host),
+        (None, None) => return Ok((None, CommandSource::None)),
+    };
+    Ok((Some(substitute_vars(command, &host.vars)?), source))
+}
+
+fn trace_argv(
+    host: &Host,
+    config: &Config,
+    default_key: Option<&str>,
+    extra_command: Option<&str>,
+    steps: &mut Vec<Step>,
+) -> Result<Vec<String>> {
+    let (command, command_source) = remote_command(host, extra_command)?;
+    check_password_command(host)?;
+
+    if let Some(template) = &host.command_template {
+        hostkey::validate(host)?;
+        steps.push(Step::Program {
+            template: Some(template.clone()),
+        });
+        let (keys, source) = choose_keys(&host.key_paths, default_key);
+        steps.push(Step::Keys { keys, source });
+        steps.push(Step::Command {
+            command,
+            source: command_source,
+        });
+        return render_template(template, host, default_key, extra_command);
+    }
+
+    steps.push(Step::Program { template: None });
+    let mosh = uses_mosh(host)?;
+    if mosh {
+        steps.push(Step::Mosh);
+    }
+    let mut argv = vec![config.ssh_binary().to_string()];
 
     if let Some(bastion_name) = &host.bastion {
-        let bastion_str = build_bastion_string(config, bastion_name, default_key, &mut Vec::new())?;
-        cmd.arg("-J").arg(bastion_str);
+        if mosh {
+            anyhow::bail!(
+                "'{}' connects with mosh, which can't go through bastion '{bastion_name}'; unset bastion or connect_with",
+                host.name
+            );
+        }
+        let hops = bastion_hops(config, host, bastion_name, &mut Vec::new())?;
+        argv.push("-J".into());
+        argv.push(
+            hops.iter()
+                .map(|hop| hop.target.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        steps.push(Step::Bastion(hops));
+    }
+    if let Some(proxy) = &host.proxy_command {
+        if let Some(bastion_name) = &host.bastion {
+            anyhow::bail!(
+                "'{}' sets both bastion '{bastion_name}' and a proxy_command; keep one",
+                host.name
+            );
+        }
+        argv.push("-o".into());
+        argv.push(format!("ProxyCommand={proxy}"));
+        steps.push(Step::ProxyCommand(proxy.clone()));
     }
 
+    steps.push(Step::Port(host.port));
     if let Some(port) = host.port {
-        cmd.arg("-p").arg(port.to_string());
+        argv.push("-p".into());
+        argv.push(port.to_string());
+    }
+    if let Some(family) = host.ip_family {
+        argv.push(family.flag().into());
+        steps.push(Step::IpFamily(family));
+    }
+
+    let (keys, source) = choose_keys(&host.key_paths, default_key);
+    for key in &keys {
+        argv.push("-i".into());
+        argv.push(key.clone());
+    }
+    steps.push(Step::Keys { keys, source });
+
+    let applied = effective_options(host);
+    argv.extend(applied.iter().cloned());
+    steps.push(Step::Options {
+        configured: host.options.clone(),
+        applied,
+        prefer_public_key: host.prefer_public_key_auth,
+    });
+    if !host.set_env.is_empty() {
+        argv.extend(set_env_args(host));
+        steps.push(Step::SetEnv(host.set_env.clone()));
+    }
+    if host.multiplex {
+        argv.extend(multiplex_args(host));
+        steps.push(Step::Multiplex(control_path()));
+    }
+    if host.password_command.is_some() {
+        steps.push(Step::PasswordCommand);
+    }
+    if host.forward_agent || host.forward_x11 {
+        argv.extend(forwarding_args(host));
+        steps.push(Step::Forwarding {
+            agent: host.forward_agent,
+            x11: host.forward_x11,
+        });
+    }
+
+    if !host.local_forwards.is_empty() || !host.remote_forwards.is_empty() {
+        if mosh {
+            anyhow::bail!(
+                "'{}' connects with mosh, which can't forward ports",
+                host.name
+            );
+        }
+        argv.extend(forward_args(host));
+        steps.push(Step::Forwards {
+            local: host.local_forwards.clone(),
+            remote: host.remote_forwards.clone(),
+        });
+    }
+
+    if hostkey::is_pinned(host) {
+        hostkey::validate(host)?;
+        argv.extend(pinned_key_options());
+        steps.push(Step::PinnedHostKey {
+            full_key: host.pinned_hostkey.is_some(),
+            fingerprint: host.pinned_fingerprint.clone(),
+        });
+    }
+
+    if !mosh {
+        steps.push(Step::Tty(host.request_tty));
+        if let Some(flag) = tty_flag(host.request_tty) {
+            argv.push(flag.into());
+        }
+    }
+
+    let target = match &host.user {
+        Some(user) => format!("{user}@{}", host.address),
+        None => host.address.clone(),
+    };
+    steps.push(Step::Target(target.clone()));
+    if mosh {
+        let argv = mosh_argv(&argv, target, command.as_deref())?;
+        steps.push(Step::Command {
+            command,
+            source: command_source,
+        });
+        return Ok(argv);
+    }
+    argv.push(target);
+
+    argv.extend(command.clone());
+    steps.push(Step::Command {
+        command,
+        source: command_source,
+    });
+
+    Ok(argv)
+}
+
+/// Options making ssh trust only the pinned key, written to the file
+/// `hostkey::pin` puts in place of the placeholder.
+fn pinned_key_options() -> [String; 6] {
+    [
+        "-o".into(),
+        format!("UserKnownHostsFile={}", hostkey::KNOWN_HOSTS_PLACEHOLDER),
+        "-o".into(),
+        "GlobalKnownHostsFile=/dev/null".into(),
+        "-o".into(),
+        "StrictHostKeyChecking=yes".into(),
+    ]
+}
+
+/// `-L spec` and `-R spec` for the host's forwards, local ones first.
+pub(crate) fn forward_args(host: &Host) -> Vec<String> {
+    let local = host.local_forwards.iter().map(|spec| ("-L", spec));
+    let remote = host.remote_forwards.iter().map(|spec| ("-R", spec));
+    local
+        .chain(remote)
+        .flat_map(|(flag, spec)| [flag.to_string(), spec.clone()])
+        .collect()
+}
+
+/// Where multiplexed hosts keep their master socket: a directory of
+/// sshdb's own, so the startup cleanup and `X` never touch anything else.
+fn control_path() -> String {
+    expand_tilde(&format!(
+        "~/.ssh/{CONTROL_SOCKET_DIR}/{CONTROL_SOCKET_PREFIX}%r@%h:%p"
+    ))
+}
+
+/// The ControlMaster options for a host with `multiplex` set.
+pub(crate) fn multiplex_args(host: &Host) -> Vec<String> {
+    if !host.multiplex {
+        return Vec::new();
     }
+    [
+        "ControlMaster=auto".to_string(),
+        format!("ControlPath={}", control_path()),
+        "ControlPersist=60s".to_string(),
+    ]
+    .into_iter()
+    .flat_map(|option| ["-o".to_string(), option])
+    .collect()
+}
+
+/// `-A` and `-X` for the host's `forward_agent` and `forward_x11`.
+pub(crate) fn forwarding_args(host: &Host) -> Vec<String> {
+    [(host.forward_agent, "-A"), (host.forward_x11, "-X")]
+        .into_iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, flag)| flag.to_string())
+        .collect()
+}
+
+/// `-o SetEnv=…` for the host's `set_env`, all in one option: ssh only
+/// takes the first SetEnv it is given.
+pub(crate) fn set_env_args(host: &Host) -> Vec<String> {
+    if host.set_env.is_empty() {
+        return Vec::new();
+    }
+    let vars: Vec<String> = host
+        .set_env
+        .iter()
+        .map(|var| {
+            if var.contains(char::is_whitespace) {
+                format!("\"{var}\"")
+            } else {
+                var.clone()
+            }
+        })
+        .collect();
+    vec!["-o".into(), format!("SetEnv={}", vars.join(" "))]
+}
 
-    for key in select_keys(&host.key_paths, default_key) {
-        cmd.arg("-i").arg(key);
+/// Whether `host` connects with mosh (`connect_with`) rather than ssh.
+fn uses_mosh(host: &Host) -> Result<bool> {
+    match host.connect_with.as_deref() {
+        None | Some("ssh") => Ok(false),
+        Some("mosh") => Ok(true),
+        Some(other) => anyhow::bail!(
+            "connect_with for '{}' is '{other}'; expected ssh or mosh",
+            host.name
+        ),
     }
+}
 
-    for opt in effective_options(host) {
-        cmd.arg(opt);
+/// `mosh [--ssh=…] target [-- command]`, with `ssh_argv` (program and
+/// flags, no target) as the ssh mosh starts the session with. mosh runs
+/// the command itself rather than through a shell, so it is split here.
+fn mosh_argv(ssh_argv: &[String], target: String, command: Option<&str>) -> Result<Vec<String>> {
+    let mut argv = vec!["mosh".to_string()];
+    if ssh_argv.len() > 1 || ssh_argv[0] != program::DEFAULT_SSH {
+        let ssh = ssh_argv
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        argv.push(format!("--ssh={ssh}"));
+    }
+    argv.push(target);
+    if let Some(command) = command {
+        argv.push("--".into());
+        argv.extend(shell_split(command)?);
     }
+    Ok(argv)
+}
+
+/// `scp` copying `local` to `remote` on `host`, with the same user, port,
+/// IP family, keys, bastion (`-J`), proxy command, pinned host key and
+/// `-o` options connecting would use. Other ssh flags (`-A`, `-X`, …)
+/// mean nothing to scp and are left out; an empty `remote` is the remote
+/// home directory.
+pub fn build_scp_command(
+    host: &Host,
+    config: &Config,
+    default_key: Option<&str>,
+    local: &str,
+    remote: &str,
+) -> Result<SshCommand> {
+    let mut argv = build_scp_argv(host, config, default_key, local, remote)?;
+    let known_hosts = hostkey::pin(host, &mut argv)?;
+    let mut cmd = Command::new(&argv[0]);
+    cmd.args(&argv[1..]);
+    Ok(SshCommand {
+        cmd: Box::new(cmd),
+        known_hosts,
+        route: route(host, config),
+        transfer: true,
+    })
+}
 
-    let target = if let Some(user) = &host.user {
-        format!("{user}@{}", host.address)
+/// The argv `build_scp_command` runs.
+pub fn build_scp_argv(
+    host: &Host,
+    config: &Config,
+    default_key: Option<&str>,
+    local: &str,
+    remote: &str,
+) -> Result<Vec<String>> {
+    if host.command_template.is_some() {
+        anyhow::bail!(
+            "'{}' connects through a command template, which scp can't use",
+            host.name
+        );
+    }
+    if host.bastion.is_some() && host.proxy_command.is_some() {
+        anyhow::bail!(
+            "'{}' sets both a bastion and a proxy_command; keep one",
+            host.name
+        );
+    }
+    hostkey::validate(host)?;
+    let mut argv = vec![SCP.to_string()];
+    if config.ssh_binary() != program::DEFAULT_SSH {
+        argv.extend(["-S".to_string(), config.ssh_binary().to_string()]);
+    }
+    if let Some(bastion) = &host.bastion {
+        let hops = bastion_hops(config, host, bastion, &mut Vec::new())?;
+        argv.push("-J".into());
+        argv.push(
+            hops.iter()
+                .map(|hop| hop.target.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+    }
+    if let Some(proxy) = &host.proxy_command {
+        argv.extend(["-o".to_string(), format!("ProxyCommand={proxy}")]);
+    }
+    if let Some(port) = host.port {
+        argv.extend(["-P".to_string(), port.to_string()]);
+    }
+    if let Some(family) = host.ip_family {
+        argv.push(family.flag().into());
+    }
+    for key in choose_keys(&host.key_paths, default_key).0 {
+        argv.extend(["-i".to_string(), key]);
+    }
+    let mut options = effective_options(host).into_iter();
+    while let Some(option) = options.next() {
+        if option == "-o" {
+            argv.push(option);
+            argv.extend(options.next());
+        } else if option.starts_with("-o") {
+            argv.push(option);
+        }
+    }
+    if hostkey::is_pinned(host) {
+        argv.extend(pinned_key_options());
+    }
+    // An IPv6 literal needs brackets, or its colons read as the path.
+    let address = if host.address.contains(':') {
+        format!("[{}]", host.address)
     } else {
         host.address.clone()
     };
-    cmd.arg(target);
+    let target = match &host.user {
+        Some(user) => format!("{user}@{address}:{remote}"),
+        None => format!("{address}:{remote}"),
+    };
+    argv.extend(["--".to_string(), expand_tilde(local), target]);
+    Ok(argv)
+}
+
+/// The scp command line, quoted for display; errors are shown inline as
+/// in `command_preview`.
+pub fn scp_preview(
+    host: &Host,
+    config: &Config,
+    default_key: Option<&str>,
+    local: &str,
+    remote: &str,
+) -> String {
+    match build_scp_argv(host, config, default_key, local, remote) {
+        Ok(argv) => argv
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Err(err) => format!("<error: {err}>"),
+    }
+}
+
+/// Runs the session on this terminal. A failure status comes back as
+/// `SessionFailed`, with the cause read from ssh's stderr.
+pub fn run_command(cmd: SshCommand) -> Result<()> {
+    let SshCommand {
+        cmd,
+        // Dropped after the session: removes the pinned known_hosts file.
+        known_hosts: _known_hosts,
+        route,
+        ..
+    } = cmd;
+    let mut cmd = *cmd;
+    let (reader, writer) = std::io::pipe()?;
+    cmd.stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(writer);
+    let stderr = StderrTee::spawn(reader, std::io::stderr(), STDERR_TAIL_LINES);
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let argv: Vec<String> = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    debuglog::spawn("ssh", &argv);
+    // `run_session` drops `cmd`, and with it our copy of the pipe's write
+    // end, so the tee sees EOF once ssh is gone.
+    let status = run_session(cmd).map_err(|err| explain_spawn_error(&program, err));
+    match &status {
+        Ok(status) => debuglog::info("ssh", "session ended", &[("status", status)]),
+        Err(err) => debuglog::error(
+            "ssh",
+            "session failed to start",
+            &[("error", &format!("{err:#}"))],
+        ),
+    }
+    let status = status?;
+    if !status.success() {
+        let lines = stderr.finish();
+        return Err(SessionFailed {
+            status,
+            diagnosis: failure::diagnose(&lines, &route),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Starts the session in a new window through `terminal_command` (such as
+/// `alacritty -e`, followed by the ssh argv) and returns once it is
+/// spawned, leaving this terminal to the TUI. A host with a pinned key is
+/// refused: many terminals hand the window to a server and exit at once,
+/// so nothing could tell when ssh is done with the pinned known_hosts file.
+pub fn run_in_terminal(cmd: SshCommand, terminal_command: &str) -> Result<()> {
+    let wrapper = shell_split(terminal_command)?;
+    let Some((program, args)) = wrapper.split_first() else {
+        anyhow::bail!("terminal_command is empty");
+    };
+    if cmd.known_hosts.is_some() {
+        anyhow::bail!(
+            "'{}' has a pinned host key, which is only checked in sshdb's own terminal; unset terminal_command to connect",
+            cmd.host_id()
+        );
+    }
+    let SshCommand { cmd, .. } = cmd;
+    let mut window = Command::new(program);
+    window
+        .args(args)
+        .arg(cmd.get_program())
+        .args(cmd.get_args())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => window.env(key, value),
+            None => window.env_remove(key),
+        };
+    }
+    let argv: Vec<String> = std::iter::once(window.get_program())
+        .chain(window.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    debuglog::spawn("ssh", &argv);
+    let mut child = window
+        .spawn()
+        .map_err(|err| explain_spawn_error(program, err.into()))?;
+    // Reaped so a wrapper that stays up doesn't linger as a zombie.
+    std::thread::spawn(move || {
+        let _ = child.wait();
+    });
+    Ok(())
+}
+
+/// Refuses a `password_command` that is empty, or on a host whose
+/// connection starts other ssh processes (a bastion, a proxy or a command
+/// template): they inherit `SSH_ASKPASS`, so a jump host's password prompt
+/// would be answered with this host's secret.
+fn check_password_command(host: &Host) -> Result<()> {
+    let Some(command) = &host.password_command else {
+        return Ok(());
+    };
+    if command.trim().is_empty() {
+        anyhow::bail!("password_command of '{}' is empty", host.name);
+    }
+    let proxied_by_option = host.options.iter().any(|option| {
+        let option = option.to_ascii_lowercase();
+        option.starts_with("-j") || option.contains("proxyjump") || option.contains("proxycommand")
+    });
+    let through = if let Some(bastion) = &host.bastion {
+        format!("bastion '{bastion}'")
+    } else if host.proxy_command.is_some() || proxied_by_option {
+        "a proxy".to_string()
+    } else if host.command_template.is_some() {
+        "a command template".to_string()
+    } else {
+        return Ok(());
+    };
+    anyhow::bail!(
+        "password_command of '{}' can't be used through {through}: its ssh would be sent the same password",
+        host.name
+    )
+}
+
+/// The `password_command` to answer with when this process was started by
+/// ssh as its `SSH_ASKPASS`: both variables `build_command` sets must be
+/// there, with `SSH_ASKPASS` naming this very binary.
+pub fn askpass_request() -> Option<String> {
+    let command = std::env::var(ASKPASS_COMMAND_ENV).ok()?;
+    let askpass = std::env::var_os("SSH_ASKPASS")?;
+    let this = std::env::current_exe().ok()?;
+    let same = |path: &Path| std::fs::canonicalize(path).ok();
+    (same(Path::new(&askpass))? == same(&this)?).then_some(command)
+}
+
+/// sshdb started by ssh as its `SSH_ASKPASS`: runs `command` (split like a
+/// shell line, without a shell) and prints the first line it outputs for
+/// ssh to read. Only password prompts are answered from it; anything else
+/// (a host key question, a key passphrase, a one-time code) goes to `ask`,
+/// which puts it to the user, so none of them is sent the password.
+/// Returns the exit code.
+pub fn answer_askpass(
+    command: &str,
+    prompt: &str,
+    ask: &mut dyn FnMut(&str) -> std::io::Result<String>,
+    out: &mut dyn Write,
+) -> i32 {
+    if !prompt.to_ascii_lowercase().contains("password") {
+        let answer = match ask(prompt) {
+            Ok(answer) => answer,
+            Err(err) => {
+                eprintln!("sshdb: can't ask \"{}\": {err}", prompt.trim());
+                return 1;
+            }
+        };
+        return match writeln!(out, "{answer}") {
+            Ok(()) => 0,
+            Err(err) => {
+                eprintln!("sshdb: {err}");
+                1
+            }
+        };
+    }
+    let words = match shell_split(command) {
+        Ok(words) if !words.is_empty() => words,
+        Ok(_) => {
+            eprintln!("sshdb: password_command is empty");
+            return 1;
+        }
+        Err(err) => {
+            eprintln!("sshdb: password_command: {err:#}");
+            return 1;
+        }
+    };
+    let output = Command::new(&words[0])
+        .args(&words[1..])
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            eprintln!("sshdb: password_command failed ({})", output.status);
+            return 1;
+        }
+        Err(err) => {
+            eprintln!(
+                "sshdb: password_command: {:#}",
+                explain_spawn_error(&words[0], err.into())
+            );
+            return 1;
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.lines().next().filter(|line| !line.is_empty()) {
+        Some(password) => match writeln!(out, "{password}") {
+            Ok(()) => 0,
+            Err(err) => {
+                eprintln!("sshdb: password_command: {err}");
+                1
+            }
+        },
+        None => {
+            eprintln!("sshdb: password_command printed nothing");
+            1
+        }
+    }
+}
+
+/// Puts an ssh prompt `answer_askpass` doesn't answer to the user on
+/// `/dev/tty` and reads their line. Only a yes/no question is echoed, as
+/// ssh's own prompts do.
+pub fn ask_tty(prompt: &str) -> std::io::Result<String> {
+    use std::io::BufRead;
+
+    let mut tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")?;
+    tty.write_all(prompt.as_bytes())?;
+    tty.flush()?;
+    let echo = prompt.contains("(yes/no");
+    let hidden = if echo { None } else { hide_input(&tty) };
+    let mut line = String::new();
+    let read = std::io::BufReader::new(&tty).read_line(&mut line);
+    if let Some(restore) = hidden {
+        restore(&tty);
+        let _ = tty.write_all(b"\n");
+    }
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Turns off echo on `tty`, returning what turns it back on; `None` when
+/// that can't be done.
+#[cfg(unix)]
+fn hide_input(tty: &std::fs::File) -> Option<impl FnOnce(&std::fs::File)> {
+    use std::os::fd::AsRawFd;
+
+    unsafe {
+        let mut saved = std::mem::MaybeUninit::<libc::termios>::uninit();
+        if libc::tcgetattr(tty.as_raw_fd(), saved.as_mut_ptr()) != 0 {
+            return None;
+        }
+        let saved = saved.assume_init();
+        let mut hidden = saved;
+        hidden.c_lflag &= !libc::ECHO;
+        if libc::tcsetattr(tty.as_raw_fd(), libc::TCSAFLUSH, &hidden) != 0 {
+            return None;
+        }
+        Some(move |tty: &std::fs::File| {
+            libc::tcsetattr(tty.as_raw_fd(), libc::TCSAFLUSH, &saved);
+        })
+    }
+}
+
+#[cfg(not(unix))]
+fn hide_input(_tty: &std::fs::File) -> Option<fn(&std::fs::File)> {
+    None
+}
+
+/// Copies ssh's stderr to ours as it arrives, so the session looks the same,
+/// while keeping its last lines.
+struct StderrTee {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    done: mpsc::Receiver<()>,
+}
+
+impl StderrTee {
+    /// How long `finish` waits for EOF. Anything ssh left running with our
+    /// stderr (a `ProxyCommand` that ignored the hangup) keeps the pipe open,
+    /// and must not keep the TUI from coming back.
+    const DRAIN_TIMEOUT: Duration = Duration::from_millis(300);
+
+    fn spawn(
+        mut reader: impl Read + Send + 'static,
+        mut out: impl Write + Send + 'static,
+        keep: usize,
+    ) -> Self {
+        let lines = Arc::new(Mutex::new(VecDeque::with_capacity(keep)));
+        let (done_tx, done) = mpsc::channel();
+        let tail = Arc::clone(&lines);
+        std::thread::spawn(move || {
+            allow_background_tty_writes();
+            let mut partial = Vec::new();
+            let mut buf = [0u8; 4096];
+            let push = |bytes: &[u8]| {
+                let mut tail = tail.lock().unwrap_or_else(|err| err.into_inner());
+                if tail.len() == keep {
+                    tail.pop_front();
+                }
+                let line = String::from_utf8_lossy(bytes);
+                tail.push_back(line.trim_end_matches('\r').to_string());
+            };
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                let _ = out.write_all(&buf[..n]);
+                let _ = out.flush();
+                partial.extend_from_slice(&buf[..n]);
+                while let Some(end) = partial.iter().position(|&b| b == b'\n') {
+                    push(&partial[..end]);
+                    partial.drain(..=end);
+                }
+            }
+            if !partial.is_empty() {
+                push(&partial);
+            }
+            let _ = done_tx.send(());
+        });
+        Self { lines, done }
+    }
+
+    /// The kept lines, once the pipe closed or the drain timeout passed.
+    fn finish(self) -> Vec<String> {
+        let _ = self.done.recv_timeout(Self::DRAIN_TIMEOUT);
+        let lines = self.lines.lock().unwrap_or_else(|err| err.into_inner());
+        lines.iter().cloned().collect()
+    }
+}
+
+/// ssh owns the terminal during the session, so sshdb is a background job
+/// and, with `stty tostop`, writing ssh's stderr would stop it with SIGTTOU.
+/// Blocking the signal in the tee thread lets the write through.
+#[cfg(unix)]
+fn allow_background_tty_writes() {
+    unsafe {
+        let mut block = std::mem::MaybeUninit::<libc::sigset_t>::uninit();
+        libc::sigemptyset(block.as_mut_ptr());
+        libc::sigaddset(block.as_mut_ptr(), libc::SIGTTOU);
+        libc::pthread_sigmask(libc::SIG_BLOCK, block.as_ptr(), std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+fn allow_background_tty_writes() {}
+
+/// A program that could not be started because it is missing or not
+/// executable is explained by `program::find_on_path` instead of the raw
+/// "No such file or directory (os error 2)".
+fn explain_spawn_error(program: &str, err: anyhow::Error) -> anyhow::Error {
+    let kind = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .map(std::io::Error::kind);
+    match kind {
+        Some(std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied) => {
+            match program::find_on_path(program) {
+                Err(unavailable) => anyhow::anyhow!(unavailable.message(program)),
+                Ok(_) => err,
+            }
+        }
+        _ => err,
+    }
+}
+
+/// On an interactive terminal the session becomes the foreground job; see `job`.
+#[cfg(unix)]
+fn run_session(mut cmd: Command) -> Result<ExitStatus> {
+    match crate::job::TtyOwner::foreground(libc::STDIN_FILENO) {
+        Some(tty) => crate::job::run_in_foreground(cmd, &tty),
+        None => Ok(cmd.status()?),
+    }
+}
+
+#[cfg(not(unix))]
+fn run_session(mut cmd: Command) -> Result<ExitStatus> {
+    Ok(cmd.status()?)
+}
+
+/// Directory under `~/.ssh` holding the ControlMaster sockets sshdb manages.
+pub const CONTROL_SOCKET_DIR: &str = "sshdb-cm";
+
+/// File-name prefix of those sockets.
+pub const CONTROL_SOCKET_PREFIX: &str = "cm-";
+
+/// Upper bound for a single `ssh -O` probe, so housekeeping can't hang.
+const CONTROL_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn control_socket_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".ssh").join(CONTROL_SOCKET_DIR))
+}
+
+/// Creates the socket directory, private like `~/.ssh`: ssh doesn't
+/// create a ControlPath's directory itself.
+fn create_control_socket_dir() -> Result<()> {
+    let Some(dir) = control_socket_dir() else {
+        anyhow::bail!("HOME is not set; nowhere to keep the multiplexing socket");
+    };
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder
+        .create(&dir)
+        .map_err(|err| anyhow::anyhow!("could not create {}: {err}", dir.display()))
+}
+
+/// Lists sshdb-managed control sockets (`cm-*`) in `dir`; anything that
+/// isn't a socket is left out, so it can never be removed.
+pub fn find_control_sockets(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut sockets: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(CONTROL_SOCKET_PREFIX)
+        })
+        .filter(|entry| entry.file_type().is_ok_and(|kind| is_socket(&kind)))
+        .map(|entry| entry.path())
+        .collect();
+    sockets.sort();
+    sockets
+}
+
+#[cfg(unix)]
+fn is_socket(kind: &std::fs::FileType) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    kind.is_socket()
+}
+
+/// No ControlMaster without Unix sockets.
+#[cfg(not(unix))]
+fn is_socket(_kind: &std::fs::FileType) -> bool {
+    false
+}
+
+/// Removes sockets whose master no longer answers `is_alive`. Returns how
+/// many were removed.
+pub fn remove_stale_sockets(sockets: &[PathBuf], is_alive: impl Fn(&Path) -> bool) -> usize {
+    sockets
+        .iter()
+        .filter(|path| !is_alive(path))
+        .filter(|path| std::fs::remove_file(path).is_ok())
+        .count()
+}
+
+/// Asks every live master to exit. Returns (closed, failed).
+pub fn close_control_sockets(
+    sockets: &[PathBuf],
+    exit: impl Fn(&Path) -> Result<()>,
+) -> (usize, usize) {
+    sockets
+        .iter()
+        .fold((0, 0), |(closed, failed), path| match exit(path) {
+            Ok(()) => (closed + 1, failed),
+            Err(_) => (closed, failed + 1),
+        })
+}
+
+/// `ssh -O check`: true when a master is listening on `socket`.
+pub fn control_master_alive(socket: &Path) -> bool {
+    control_command(socket, "check").unwrap_or(false)
+}
+
+/// `ssh -O exit`: asks the master on `socket` to shut down.
+pub fn control_master_exit(socket: &Path) -> Result<()> {
+    if control_command(socket, "exit")? {
+        Ok(())
+    } else {
+        anyhow::bail!("no master on {}", socket.display())
+    }
+}
+
+fn control_command(socket: &Path, op: &str) -> Result<bool> {
+    let mut child = Command::new("ssh")
+        .arg("-O")
+        .arg(op)
+        .arg("-S")
+        .arg(socket)
+        .arg("sshdb-control")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status.success());
+        }
+        if started.elapsed() > CONTROL_PROBE_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// `build_argv` joined for display, quoted so it can be pasted into a shell.
+pub fn command_preview(
+    host: &Host,
+    config: &Config,
+    default_key: Option<&str>,
+    extra: Option<&str>,
+) -> String {
+    match command_lines(host, config, default_key, extra) {
+        Ok(lines) => lines.join(" "),
+        Err(err) => format!("<error: {err}>"),
+    }
+}
+
+/// ssh flags whose value is the next argument.
+const VALUE_FLAGS: [&str; 20] = [
+    "-B", "-b", "-c", "-D", "-E", "-e", "-F", "-I", "-i", "-J", "-L", "-l", "-m", "-O", "-o", "-p",
+    "-Q", "-R", "-S", "-W",
+];
+
+/// `command_preview` one argument per line, a flag on the line of its
+/// value (`-i key`, `-o Option=…`), for reading long commands.
+pub fn command_lines(
+    host: &Host,
+    config: &Config,
+    default_key: Option<&str>,
+    extra: Option<&str>,
+) -> Result<Vec<String>> {
+    let argv = build_argv(host, config, default_key, extra)?;
+    let mut lines = Vec::new();
+    if host.password_command.is_some() {
+        lines.push(PASSWORD_PLACEHOLDER.to_string());
+    }
+    let mut args = argv.iter().map(|arg| shell_quote(arg));
+    while let Some(arg) = args.next() {
+        let line = match args.clone().next() {
+            Some(value) if VALUE_FLAGS.contains(&arg.as_str()) => {
+                args.next();
+                format!("{arg} {value}")
+            }
+            _ => arg,
+        };
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+/// Renders a per-host command template into argv.
+///
+/// The template is split shell-style first, then `{user}`, `{address}`,
+/// `{port}`, `{name}`, `{key}` and `{remote_command}` are substituted inside
+/// each word, so substituted values never introduce extra words. Words that
+/// only held placeholders with no value are dropped.
+pub fn render_template(
+    template: &str,
+    host: &Host,
+    default_key: Option<&str>,
+    extra: Option<&str>,
+) -> Result<Vec<String>> {
+    let key = choose_keys(&host.key_paths, default_key)
+        .0
+        .into_iter()
+        .next();
+    let (remote, _) = remote_command(host, extra)?;
+    let port = host.port.map(|p| p.to_string());
+    let values: [(&str, Option<&str>); 6] = [
+        ("user", host.user.as_deref()),
+        ("address", Some(host.address.as_str())),
+        ("port", port.as_deref()),
+        ("name", Some(host.name.as_str())),
+        ("key", key.as_deref()),
+        ("remote_command", remote.as_deref()),
+    ];
+
+    let mut argv = Vec::new();
+    for word in shell_split(template)? {
+        let mut out = String::new();
+        let mut had_placeholder = false;
+        let mut rest = word.as_str();
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let placeholder =
+                after
+                    .find('}')
+                    .map(|end| (&after[..end], end))
+                    .and_then(|(name, end)| {
+                        values
+                            .iter()
+                            .find(|(key, _)| *key == name)
+                            .map(|(_, value)| (*value, end))
+                    });
+            match placeholder {
+                Some((value, end)) => {
+                    had_placeholder = true;
+                    out.push_str(value.unwrap_or_default());
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    out.push('{');
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        if had_placeholder && out.is_empty() {
+            continue;
+        }
+        argv.push(out);
+    }
+    Ok(argv)
+}
+
+/// Replaces each `{{name}}` in `text` with `vars[name]`; spaces inside the
+/// braces are ignored. Values go in as written and are not expanded again.
+/// `\{{` stands for a literal `{{`. A placeholder naming no var is an error
+/// rather than reaching the shell as braces.
+pub fn substitute_vars(text: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        if rest[..start].ends_with('\\') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str("{{");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            anyhow::bail!("unterminated {{{{ in `{text}`");
+        };
+        let name = after[..end].trim();
+        if !is_var_name(name) {
+            anyhow::bail!(
+                "{{{{{}}}}} in `{text}` is not a variable (names use letters, digits, _ and -)",
+                &after[..end]
+            );
+        }
+        let Some(value) = vars.get(name) else {
+            anyhow::bail!(
+                "variable '{name}' is not set for `{text}`; add {name}=… to the host's vars"
+            );
+        };
+        out.push_str(value);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Splits a command line into words following POSIX shell quoting rules
+/// (single quotes, double quotes with `\` escapes, backslash outside quotes).
+pub fn shell_split(input: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(ch) => current.push(ch),
+                        None => anyhow::bail!("unterminated single quote in '{input}'"),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(ch @ ('"' | '\\' | '$' | '`')) => current.push(ch),
+                            Some(ch) => {
+                                current.push('\\');
+                                current.push(ch);
+                            }
+                            None => anyhow::bail!("unterminated double quote in '{input}'"),
+                        },
+                        Some(ch) => current.push(ch),
+                        None => anyhow::bail!("unterminated double quote in '{input}'"),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(ch) = chars.next() {
+                    current.push(ch);
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    Ok(words)
+}
+
+/// Quotes a word for display so that `shell_split` would read it back unchanged.
+pub fn shell_quote(word: &str) -> String {
+    let safe = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:@%+=,~".contains(c));
+    if safe {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// The jump hosts connecting to `host` passes through, in the order ssh
+/// visits them; empty without a bastion.
+pub fn bastion_chain(config: &Config, host: &Host) -> Result<Vec<Hop>> {
+    match &host.bastion {
+        Some(bastion) => bastion_hops(config, host, bastion, &mut Vec::new()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// `bastion_name` is resolved relative to `from`'s group; `visited` holds
+/// the canonical ids already on the chain. Hops come back in the order ssh
+/// visits them, so the innermost bastion's own bastion is first.
+fn bastion_hops(
+    config: &Config,
+    from: &Host,
+    bastion_name: &str,
+    visited: &mut Vec<String>,
+) -> Result<Vec<Hop>> {
+    let Some(bastion) = config.resolve_host(bastion_name, from.group.as_deref())? else {
+        return Ok(vec![Hop {
+            reference: bastion_name.to_string(),
+            id: None,
+            target: bastion_name.to_string(),
+        }]);
+    };
+    let id = bastion.id();
+    if id == from.id() || visited.contains(&id) {
+        anyhow::bail!("circular bastion reference detected: {}", id);
+    }
+    visited.push(id.clone());
+
+    let mut hops = match &bastion.bastion {
+        Some(nested) => bastion_hops(config, bastion, nested, visited)?,
+        None => Vec::new(),
+    };
+
+    let mut target = if let Some(user) = &bastion.user {
+        format!("{user}@{}", bastion.address)
+    } else {
+        bastion.address.clone()
+    };
+    if let Some(port) = bastion.port {
+        target.push_str(&format!(":{}", port));
+    }
+    hops.push(Hop {
+        reference: bastion_name.to_string(),
+        id: Some(id),
+        target,
+    });
+    Ok(hops)
+}
+
+pub(crate) fn choose_keys(
+    host_keys: &[String],
+    default_key: Option<&str>,
+) -> (Vec<String>, KeySource) {
+    const FALLBACKS: [&str; 2] = ["~/.ssh/id_ed25519", "~/.ssh/id_rsa"];
+    if !host_keys.is_empty() {
+        let keys = host_keys.iter().map(|key| expand_tilde(key)).collect();
+        return (keys, KeySource::Host);
+    }
+    if let Some(k) = default_key {
+        if k == "agent" {
+            return (Vec::new(), KeySource::AgentConfigured);
+        }
+        return (vec![expand_tilde(k)], KeySource::DefaultKey);
+    }
+
+    let agent_available = std::env::var("SSH_AUTH_SOCK")
+        .map(|v| !v.is_empty())
+        .unwrap_or(false);
+    if agent_available {
+        return (Vec::new(), KeySource::AgentDetected);
+    }
+
+    // fall back to common keys when no agent is present; prefer an existing one
+    for cand in FALLBACKS {
+        let expanded = expand_tilde(cand);
+        if Path::new(&expanded).exists() {
+            return (vec![expanded], KeySource::Fallback { exists: true });
+        }
+    }
+    (
+        vec![expand_tilde(FALLBACKS[0])],
+        KeySource::Fallback { exists: false },
+    )
+}
+
+fn tty_flag(request_tty: Option<bool>) -> Option<&'static str> {
+    request_tty.map(|force| if force { "-t" } else { "-T" })
+}
+
+/// Whether `command` runs something that usually prompts on a terminal
+/// (`sudo`, `doas`), in any of its `;`, `&&` or `|` separated parts.
+pub fn command_wants_tty(command: &str) -> bool {
+    command
+        .split([';', '&', '|'])
+        .filter_map(|part| part.split_whitespace().next())
+        .any(|program| matches!(program, "sudo" | "doas"))
+}
+
+/// True when running `command` on `host` will likely fail for lack of a TTY:
+/// ssh only allocates one for commands when asked with `-t`.
+pub fn lacks_needed_tty(host: &Host, command: Option<&str>) -> bool {
+    let command = command
+        .filter(|c| !c.trim().is_empty())
+        .or(host.remote_command.as_deref());
+    host.command_template.is_none()
+        && host.request_tty != Some(true)
+        && command.is_some_and(command_wants_tty)
+}
+
+pub(crate) fn effective_options(host: &Host) -> Vec<String> {
+    let mut options = if host.prefer_public_key_auth {
+        strip_preferred_auth_options(&host.options)
+    } else {
+        host.options.clone()
+    };
+
+    if host.prefer_public_key_auth {
+        options.splice(
+            0..0,
+            [
+                "-o".to_string(),
+                "PreferredAuthentications=publickey".to_string(),
+            ],
+        );
+    }
+
+    options
+}
+
+fn strip_preferred_auth_options(options: &[String]) -> Vec<String> {
+    let mut cleaned = Vec::new();
+    let mut i = 0;
+    while i < options.len() {
+        let current = &options[i];
+        if current == "-o" {
+            if let Some(next) = options.get(i + 1) {
+                if is_preferred_auth_option(next) {
+                    i += 2;
+                    continue;
+                }
+            }
+            cleaned.push(current.clone());
+            i += 1;
+            continue;
+        }
+
+        if current.starts_with("-o") && is_preferred_auth_option(&current[2..]) {
+            i += 1;
+            continue;
+        }
+
+        cleaned.push(current.clone());
+        i += 1;
+    }
+    cleaned
+}
+
+fn is_preferred_auth_option(option: &str) -> bool {
+    option
+        .to_ascii_lowercase()
+        .contains("preferredauthentications=")
+}
+
+pub(crate) fn expand_tilde(path: &str) -> String {
+    if let Some(stripped) = path.strip_prefix("~/") {
+        if let Ok(home) = std::env::var("HOME") {
+            return PathBuf::from(home)
+                .join(stripped)
+                .to_string_lossy()
+                .into_owned();
+        }
+    }
+    path.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ENV_LOCK;
+
+    #[test]
+    fn builds_preview_with_defaults() {
+        let config = Config::default();
+        let host = Host {
+            name: "prod".into(),
+            address: "10.0.0.1".into(),
+            user: Some("deploy".into()),
+            port: Some(2222),
+            key_paths: Vec::new(),
+            tags: vec![],
+            options: vec!["-L".into(), "8080:localhost:80".into()],
+            remote_command: None,
+            description: None,
+            bastion: None,
+            prefer_public_key_auth: false,
+            ..Default::default()
+        };
+        let preview = command_preview(&host, &config, Some("~/.ssh/id_ed25519"), Some("uptime"));
+        assert!(preview.contains("-p 2222"));
+        assert!(preview.contains("-i"));
+        assert!(preview.contains("deploy@10.0.0.1"));
+        assert!(preview.ends_with("uptime"));
+        assert!(preview.contains("-L 8080:localhost:80"));
+    }
+
+    #[test]
+    fn forward_fields_become_l_and_r_flags() {
+        let config = Config::default();
+        let mut host = Host {
+            name: "db".into(),
+            address: "10.0.0.2".into(),
+            local_forwards: vec!["5432:localhost:5432".into(), "8080:web:80".into()],
+            remote_forwards: vec!["9000:localhost:9000".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            command_preview(&host, &config, Some("agent"), None),
+            "ssh -L 5432:localhost:5432 -L 8080:web:80 -R 9000:localhost:9000 10.0.0.2"
+        );
+        let explanation = explain(&host, &config, Some("agent"), None);
+        assert!(explanation.lines().contains(&(
+            "forwards",
+            "-L 5432:localhost:5432, -L 8080:web:80, -R 9000:localhost:9000".into()
+        )));
+
+        host.connect_with = Some("mosh".into());
+        let err = build_argv(&host, &config, Some("agent"), None).unwrap_err();
+        assert!(err.to_string().contains("can't forward ports"), "{err}");
+    }
+
+    #[test]
+    fn allows_free_text_bastion() {
+        let mut config = Config::default();
+        let host = Host {
+            name: "prod".into(),
+            address: "10.0.0.1".into(),
+            user: Some("deploy".into()),
+            port: None,
+            key_paths: Vec::new(),
+            tags: vec![],
+            options: vec![],
+            remote_command: None,
+            description: None,
+            bastion: Some("proxy.example.com".into()),
+            prefer_public_key_auth: false,
+            ..Default::default()
+        };
+        config.hosts.push(host.clone());
+        let preview = command_preview(&host, &config, None, None);
+        assert!(preview.contains("-J proxy.example.com"));
+        assert!(preview.contains("deploy@10.0.0.1"));
+    }
+
+    #[test]
+    fn bastion_resolves_within_the_referring_group() {
+        let db = |group: &str, address: &str| Host {
+            name: "db1".into(),
+            group: Some(group.into()),
+            address: address.into(),
+            ..Default::default()
+        };
+        let config = Config {
+            hosts: vec![db("team-a", "10.0.1.1"), db("team-b", "10.0.2.1")],
+            ..Default::default()
+        };
+        let mut host = Host {
+            name: "web".into(),
+            group: Some("team-b".into()),
+            address: "10.0.2.2".into(),
+            bastion: Some("db1".into()),
+            ..Default::default()
+        };
+        let preview = command_preview(&host, &config, Some("agent"), None);
+        assert!(preview.contains("-J 10.0.2.1"), "{preview}");
+
+        host.bastion = Some("team-a/db1".into());
+        let preview = command_preview(&host, &config, Some("agent"), None);
+        assert!(preview.contains("-J 10.0.1.1"), "{preview}");
+
+        host.group = None;
+        host.bastion = Some("db1".into());
+        let err = build_command(&host, &config, Some("agent"), None).unwrap_err();
+        assert!(err.to_string().contains("ambiguous"));
+        let preview = command_preview(&host, &config, Some("agent"), None);
+        assert!(preview.starts_with("<error: host name 'db1' is ambiguous"));
+    }
+
+    const GOLDEN_CONFIG: &str = r#"
+version = 1
+
+[[hosts]]
+name = "plain"
+host = "10.0.0.1"
+
+[[hosts]]
+name = "edge"
+host = "edge.example.com"
+user = "jump"
+port = 2201
+
+[[hosts]]
+name = "inner"
+host = "10.1.0.1"
+bastion = "edge"
+
+[[hosts]]
+name = "app"
+host = "10.2.0.1"
+user = "deploy"
+port = 2222
+bastion = "inner"
+key_paths = ["/keys/a", "/keys/b"]
+options = ["-o", "PreferredAuthentications=password", "-L", "8080:localhost:80"]
+prefer_public_key_auth = true
+request_tty = true
+remote_command = "sudo systemctl status app"
+
+[[hosts]]
+name = "quiet"
+host = "10.3.0.1"
+request_tty = false
+remote_command = "uptime"
+
+[[hosts]]
+name = "free"
+host = "10.4.0.1"
+bastion = "gw.example.com:2022"
+
+[[hosts]]
+name = "db"
+group = "team-a"
+host = "10.5.0.1"
+
+[[hosts]]
+name = "web"
+group = "team-a"
+host = "10.5.0.2"
+bastion = "db"
+
+[[hosts]]
+name = "tele"
+host = "i-0abc"
+user = "ops"
+port = 3022
+key_paths = ["/keys/tele"]
+command_template = "tsh ssh -p {port} -i {key} {user}@{address} {remote_command}"
+"#;
+
+    #[test]
+    fn golden_argv() {
+        let config: Config = toml::from_str(GOLDEN_CONFIG).unwrap();
+        // (host id, default key, extra command, expected argv)
+        type Case<'a> = (&'a str, Option<&'a str>, Option<&'a str>, &'a [&'a str]);
+        let cases: &[Case] = &[
+            ("plain", Some("agent"), None, &["ssh", "10.0.0.1"]),
+            (
+                "plain",
+                Some("/keys/default"),
+                Some("df -h"),
+                &["ssh", "-i", "/keys/default", "10.0.0.1", "df -h"],
+            ),
+            (
+                "inner",
+                Some("agent"),
+                None,
+                &["ssh", "-J", "jump@edge.example.com:2201", "10.1.0.1"],
+            ),
+            (
+                "app",
+                Some("/keys/default"),
+                None,
+                &[
+                    "ssh",
+                    "-J",
+                    "jump@edge.example.com:2201,10.1.0.1",
+                    "-p",
+                    "2222",
+                    "-i",
+                    "/keys/a",
+                    "-i",
+                    "/keys/b",
+                    "-o",
+                    "PreferredAuthentications=publickey",
+                    "-L",
+                    "8080:localhost:80",
+                    "-t",
+                    "deploy@10.2.0.1",
+                    "sudo systemctl status app",
+                ],
+            ),
+            (
+                "quiet",
+                Some("agent"),
+                None,
+                &["ssh", "-T", "10.3.0.1", "uptime"],
+            ),
+            (
+                "quiet",
+                Some("agent"),
+                Some("w"),
+                &["ssh", "-T", "10.3.0.1", "w"],
+            ),
+            (
+                "free",
+                Some("agent"),
+                None,
+                &["ssh", "-J", "gw.example.com:2022", "10.4.0.1"],
+            ),
+            (
+                "team-a/web",
+                Some("agent"),
+                None,
+                &["ssh", "-J", "10.5.0.1", "10.5.0.2"],
+            ),
+            (
+                "tele",
+                Some("agent"),
+                Some("ls -la"),
+                &[
+                    "tsh",
+                    "ssh",
+                    "-p",
+                    "3022",
+                    "-i",
+                    "/keys/tele",
+                    "ops@i-0abc",
+                    "ls -la",
+                ],
+            ),
+            (
+                "tele",
+                Some("agent"),
+                None,
+                &["tsh", "ssh", "-p", "3022", "-i", "/keys/tele", "ops@i-0abc"],
+            ),
+        ];
+
+        for (id, default_key, extra, expected) in cases {
+            let host = config.find_host(id).unwrap();
+            let argv = build_argv(host, &config, *default_key, *extra).unwrap();
+            assert_eq!(argv, *expected, "{id} with {extra:?}");
+
+            let cmd = build_command(host, &config, *default_key, *extra).unwrap();
+            let mut from_command = vec![cmd.get_program().to_string_lossy().into_owned()];
+            from_command.extend(cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()));
+            assert_eq!(from_command, argv, "{id}: command differs from argv");
+
+            let preview = command_preview(host, &config, *default_key, *extra);
+            assert_eq!(shell_split(&preview).unwrap(), argv, "{id}: {preview}");
+        }
+    }
+
+    fn hop(reference: &str, id: Option<&str>, target: &str) -> Hop {
+        Hop {
+            reference: reference.into(),
+            id: id.map(Into::into),
+            target: target.into(),
+        }
+    }
+
+    #[test]
+    fn golden_explanations() {
+        let config: Config = toml::from_str(GOLDEN_CONFIG).unwrap();
+        let explain_host = |id: &str, default_key, extra| {
+            let host = config.find_host(id).unwrap();
+            let explanation = explain(host, &config, default_key, extra);
+            assert_eq!(
+                explanation.argv.as_ref().ok(),
+                build_argv(host, &config, default_key, extra).ok().as_ref(),
+                "{id}: explain drifted from build_argv"
+            );
+            explanation
+        };
+        let step = |explanation: &Explanation, pick: fn(&Step) -> bool| {
+            explanation.steps.iter().find(|s| pick(s)).cloned().unwrap()
+        };
+
+        let plain = explain_host("plain", Some("agent"), None);
+        assert_eq!(
+            plain.steps,
+            vec![
+                Step::Program { template: None },
+                Step::Port(None),
+                Step::Keys {
+                    keys: Vec::new(),
+                    source: KeySource::AgentConfigured
+                },
+                Step::Options {
+                    configured: Vec::new(),
+                    applied: Vec::new(),
+                    prefer_public_key: false
+                },
+                Step::Tty(None),
+                Step::Target("10.0.0.1".into()),
+                Step::Command {
+                    command: None,
+                    source: CommandSource::None
+                },
+            ]
+        );
+
+        let with_default = explain_host("plain", Some("/keys/default"), Some("df -h"));
+        assert_eq!(
+            step(&with_default, |s| matches!(s, Step::Keys { .. })),
+            Step::Keys {
+                keys: vec!["/keys/default".into()],
+                source: KeySource::DefaultKey
+            }
+        );
+        assert_eq!(
+            step(&with_default, |s| matches!(s, Step::Command { .. })),
+            Step::Command {
+                command: Some("df -h".into()),
+                source: CommandSource::Extra
+            }
+        );
+
+        let app = explain_host("app", Some("/keys/default"), None);
+        assert_eq!(
+            step(&app, |s| matches!(s, Step::Bastion(_))),
+            Step::Bastion(vec![
+                hop("edge", Some("edge"), "jump@edge.example.com:2201"),
+                hop("inner", Some("inner"), "10.1.0.1"),
+            ])
+        );
+        assert_eq!(
+            step(&app, |s| matches!(s, Step::Keys { .. })),
+            Step::Keys {
+                keys: vec!["/keys/a".into(), "/keys/b".into()],
+                source: KeySource::Host
+            }
+        );
+        assert_eq!(
+            step(&app, |s| matches!(s, Step::Tty(_))),
+            Step::Tty(Some(true))
+        );
+        assert_eq!(
+            step(&app, |s| matches!(s, Step::Command { .. })),
+            Step::Command {
+                command: Some("sudo systemctl status app".into()),
+                source: CommandSource::Host
+            }
+        );
+        let lines = app.lines();
+        let line = |topic: &str| {
+            lines
+                .iter()
+                .find(|(t, _)| *t == topic)
+                .map(|(_, detail)| detail.clone())
+                .unwrap()
+        };
+        assert_eq!(
+            line("bastion"),
+            "jump@edge.example.com:2201 (host edge) → 10.1.0.1 (host inner)"
+        );
+        assert_eq!(line("keys"), "/keys/a, /keys/b from the host's key_paths");
+        assert!(line("options").ends_with("and drops the host's own PreferredAuthentications"));
+        assert_eq!(line("tty"), "-t: request_tty = true");
+        assert_eq!(
+            line("command"),
+            "sudo systemctl status app (the host's remote_command)"
+        );
+        assert_eq!(
+            line("argv"),
+            command_preview(
+                config.find_host("app").unwrap(),
+                &config,
+                Some("/keys/default"),
+                None
+            )
+        );
+
+        let quiet = explain_host("quiet", Some("agent"), None);
+        assert_eq!(
+            step(&quiet, |s| matches!(s, Step::Tty(_))),
+            Step::Tty(Some(false))
+        );
+
+        let free = explain_host("free", Some("agent"), None);
+        assert_eq!(
+            step(&free, |s| matches!(s, Step::Bastion(_))),
+            Step::Bastion(vec![hop(
+                "gw.example.com:2022",
+                None,
+                "gw.example.com:2022"
+            )])
+        );
+        assert!(free.lines()[1].1.contains("free text"));
+
+        let web = explain_host("team-a/web", Some("agent"), None);
+        assert_eq!(
+            step(&web, |s| matches!(s, Step::Bastion(_))),
+            Step::Bastion(vec![hop("db", Some("team-a/db"), "10.5.0.1")])
+        );
+        assert_eq!(web.lines()[1].1, "10.5.0.1 (host team-a/db, written 'db')");
+
+        let tele = explain_host("tele", Some("agent"), Some("ls -la"));
+        assert_eq!(
+            tele.steps,
+            vec![
+                Step::Program {
+                    template: Some(
+                        "tsh ssh -p {port} -i {key} {user}@{address} {remote_command}".into()
+                    )
+                },
+                Step::Keys {
+                    keys: vec!["/keys/tele".into()],
+                    source: KeySource::Host
+                },
+                Step::Command {
+                    command: Some("ls -la".into()),
+                    source: CommandSource::Extra
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn explains_agent_detection_and_fallback_keys() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        let old_home = std::env::var_os("HOME");
+        let old_sock = std::env::var_os("SSH_AUTH_SOCK");
+        let host = Host {
+            name: "h".into(),
+            address: "10.0.0.1".into(),
+            ..Default::default()
+        };
+        let keys_step = || {
+            explain(&host, &Config::default(), None, None)
+                .steps
+                .into_iter()
+                .find(|s| matches!(s, Step::Keys { .. }))
+                .unwrap()
+        };
+        unsafe {
+            std::env::set_var("HOME", home.path());
+            std::env::set_var("SSH_AUTH_SOCK", "/tmp/agent.sock");
+        }
+        let detected = keys_step();
+        unsafe {
+            std::env::remove_var("SSH_AUTH_SOCK");
+        }
+        let missing = keys_step();
+        unsafe {
+            match old_home {
+                Some(v) => std::env::set_var("HOME", v),
+                None => std::env::remove_var("HOME"),
+            }
+            if let Some(v) = old_sock {
+                std::env::set_var("SSH_AUTH_SOCK", v);
+            }
+        }
+        assert_eq!(
+            detected,
+            Step::Keys {
+                keys: Vec::new(),
+                source: KeySource::AgentDetected
+            }
+        );
+        assert!(matches!(
+            missing,
+            Step::Keys {
+                source: KeySource::Fallback { exists: false },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn build_argv_reports_bastion_cycles() {
+        let mut config: Config = toml::from_str(GOLDEN_CONFIG).unwrap();
+        config.hosts[1].bastion = Some("inner".into());
+        let host = config.find_host("inner").unwrap();
+        let err = build_argv(host, &config, Some("agent"), None).unwrap_err();
+        assert!(err.to_string().contains("circular"), "{err}");
+        assert!(command_preview(host, &config, Some("agent"), None).starts_with("<error: "));
+        let explanation = explain(host, &config, Some("agent"), None);
+        assert_eq!(explanation.lines().last().unwrap().0, "error");
+    }
+
+    #[test]
+    fn emits_tty_flag_for_each_setting() {
+        let config = Config::default();
+        let mut host = Host {
+            name: "box".into(),
+            address: "10.0.0.1".into(),
+            ..Default::default()
+        };
+        for (setting, flag) in [
+            (None, None),
+            (Some(true), Some("-t")),
+            (Some(false), Some("-T")),
+        ] {
+            host.request_tty = setting;
+            let preview = command_preview(&host, &config, Some("agent"), Some("uptime"));
+            let args: Vec<String> = build_command(&host, &config, Some("agent"), Some("uptime"))
+                .unwrap()
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect();
+            match flag {
+                Some(flag) => {
+                    assert!(preview.contains(&format!(" {flag} 10.0.0.1")), "{preview}");
+                    assert_eq!(args[args.len() - 3], flag);
+                }
+                None => {
+                    assert!(!preview.contains(" -t") && !preview.contains(" -T"));
+                    assert!(!args.iter().any(|a| a == "-t" || a == "-T"));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn emits_ip_family_flag_when_set() {
+        let config = Config::default();
+        let mut host = Host {
+            name: "box".into(),
+            address: "db.example.com".into(),
+            port: Some(2222),
+            ..Default::default()
+        };
+        for (family, flag) in [(Some(IpFamily::V4), "-4"), (Some(IpFamily::V6), "-6")] {
+            host.ip_family = family;
+            let preview = command_preview(&host, &config, Some("agent"), None);
+            assert!(preview.contains(&format!("-p 2222 {flag}")), "{preview}");
+            let args: Vec<String> = build_command(&host, &config, Some("agent"), None)
+                .unwrap()
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect();
+            assert_eq!(args.iter().filter(|a| *a == flag).count(), 1, "{args:?}");
+            let explanation = explain(&host, &config, Some("agent"), None);
+            let lines = explanation.lines();
+            let family_line = lines.iter().find(|(topic, _)| *topic == "family").unwrap();
+            assert!(family_line.1.starts_with(&format!("{flag}: ip_family")));
+        }
+        host.ip_family = None;
+        let args: Vec<String> = build_command(&host, &config, Some("agent"), None)
+            .unwrap()
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert!(!args.iter().any(|a| a == "-4" || a == "-6"));
+        let explanation = explain(&host, &config, Some("agent"), None);
+        assert!(!explanation
+            .lines()
+            .iter()
+            .any(|(topic, _)| *topic == "family"));
+    }
+
+    #[test]
+    fn detects_commands_that_need_a_tty() {
+        assert!(command_wants_tty("sudo systemctl restart foo"));
+        assert!(command_wants_tty("cd /srv && doas make install"));
+        assert!(command_wants_tty("echo hi; sudo -i"));
+        assert!(!command_wants_tty("uptime"));
+        assert!(!command_wants_tty("echo sudo"));
+        assert!(!command_wants_tty("sudoedit-wrapper"));
+
+        let mut host = Host {
+            name: "box".into(),
+            address: "10.0.0.1".into(),
+            remote_command: Some("sudo journalctl -f".into()),
+            ..Default::default()
+        };
+        assert!(lacks_needed_tty(&host, None));
+        assert!(!lacks_needed_tty(&host, Some("uptime")));
+        assert!(lacks_needed_tty(&host, Some("sudo reboot")));
+        host.request_tty = Some(false);
+        assert!(lacks_needed_tty(&host, None));
+        host.request_tty = Some(true);
+        assert!(!lacks_needed_tty(&host, None));
+    }
+
+    fn template_host(template: &str) -> Host {
+        Host {
+            name: "prod".into(),
+            address: "i-0abc".into(),
+            user: Some("deploy".into()),
+            port: Some(2222),
+            key_paths: vec!["/keys/prod".into()],
+            command_template: Some(template.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn splits_shell_words() {
+        assert_eq!(
+            shell_split("tsh ssh  user@host").unwrap(),
+            vec!["tsh", "ssh", "user@host"]
+        );
+        assert_eq!(
+            shell_split(r#"aws ssm --params 'a b' "c \"d\"" e\ f"#).unwrap(),
+            vec!["aws", "ssm", "--params", "a b", "c \"d\"", "e f"]
+        );
+        assert_eq!(shell_split("a '' b").unwrap(), vec!["a", "", "b"]);
+        assert!(shell_split("echo 'open").is_err());
+        assert!(shell_split("echo \"open").is_err());
+        assert!(shell_split("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn renders_all_placeholders() {
+        let host = template_host("tsh ssh -p {port} -i {key} {user}@{address} # {name}");
+        let argv =
+            render_template(host.command_template.as_deref().unwrap(), &host, None, None).unwrap();
+        assert_eq!(
+            argv,
+            vec![
+                "tsh",
+                "ssh",
+                "-p",
+                "2222",
+                "-i",
+                "/keys/prod",
+                "deploy@i-0abc",
+                "#",
+                "prod"
+            ]
+        );
+    }
+
+    #[test]
+    fn template_drops_missing_values() {
+        let mut host = template_host("aws ssm start-session --target {address} {remote_command}");
+        host.user = None;
+        let argv =
+            render_template(host.command_template.as_deref().unwrap(), &host, None, None).unwrap();
+        assert_eq!(
+            argv,
+            vec!["aws", "ssm", "start-session", "--target", "i-0abc"]
+        );
+    }
+
+    #[test]
+    fn template_keeps_substituted_values_as_one_word() {
+        let host = template_host("tsh ssh {user}@{address} \"{remote_command}\"");
+        let argv = render_template(
+            host.command_template.as_deref().unwrap(),
+            &host,
+            None,
+            Some("uptime -p"),
+        )
+        .unwrap();
+        assert_eq!(argv, vec!["tsh", "ssh", "deploy@i-0abc", "uptime -p"]);
+    }
+
+    #[test]
+    fn template_leaves_unknown_braces_alone() {
+        let host = template_host("run {unknown} {address}{");
+        let argv =
+            render_template(host.command_template.as_deref().unwrap(), &host, None, None).unwrap();
+        assert_eq!(argv, vec!["run", "{unknown}", "i-0abc{"]);
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn substitutes_vars_once_and_keeps_escaped_braces() {
+        let vars = vars(&[("svc", "api"), ("dir", "/srv/{{svc}}")]);
+        assert_eq!(
+            substitute_vars("systemctl status {{svc}} {{ svc }}", &vars).unwrap(),
+            "systemctl status api api"
+        );
+        // Values are not expanded again.
+        assert_eq!(
+            substitute_vars("ls {{dir}}", &vars).unwrap(),
+            "ls /srv/{{svc}}"
+        );
+        assert_eq!(
+            substitute_vars(r"echo \{{svc}} {svc} }}", &vars).unwrap(),
+            "echo {{svc}} {svc} }}"
+        );
+        assert_eq!(
+            substitute_vars("uptime", &BTreeMap::new()).unwrap(),
+            "uptime"
+        );
+    }
+
+    #[test]
+    fn unknown_or_malformed_placeholders_are_errors() {
+        let vars = vars(&[("svc", "api")]);
+        let err = substitute_vars("journalctl -u {{unit}}", &vars).unwrap_err();
+        assert!(
+            err.to_string().contains("variable 'unit' is not set"),
+            "{err}"
+        );
+        // Placeholders don't nest.
+        let err = substitute_vars("echo {{a{{svc}}}}", &vars).unwrap_err();
+        assert!(err.to_string().contains("is not a variable"), "{err}");
+        assert!(substitute_vars("echo {{svc", &vars).is_err());
+        assert!(substitute_vars("echo {{}}", &vars).is_err());
+    }
+
+    #[test]
+    fn vars_are_substituted_in_every_command_source() {
+        let config = Config::default();
+        let host = Host {
+            name: "app".into(),
+            address: "10.0.0.5".into(),
+            remote_command: Some("systemctl status {{svc}}".into()),
+            vars: vars(&[("svc", "api")]),
+            ..Default::default()
+        };
+        let argv = build_argv(&host, &config, Some("agent"), None).unwrap();
+        assert_eq!(argv.last().unwrap(), "systemctl status api");
+        let argv = build_argv(
+            &host,
+            &config,
+            Some("agent"),
+            Some("tail /var/log/{{svc}}.log"),
+        )
+        .unwrap();
+        assert_eq!(argv.last().unwrap(), "tail /var/log/api.log");
+        assert!(
+            command_preview(&host, &config, Some("agent"), None).contains("'systemctl status api'")
+        );
+
+        let mut templated = template_host("tsh ssh {address} {remote_command}");
+        templated.remote_command = host.remote_command.clone();
+        templated.vars = host.vars.clone();
+        assert_eq!(
+            build_argv(&templated, &config, None, None).unwrap(),
+            vec!["tsh", "ssh", "i-0abc", "systemctl status api"]
+        );
+
+        let preview = command_preview(&host, &config, Some("agent"), Some("cat {{missing}}"));
+        assert!(
+            preview.starts_with("<error: variable 'missing'"),
+            "{preview}"
+        );
+    }
+
+    #[test]
+    fn template_bypasses_openssh_flags_in_preview_and_command() {
+        let config = Config::default();
+        let host = template_host("tsh ssh {user}@{address} {remote_command}");
+        let preview = command_preview(&host, &config, None, Some("ls -la"));
+        assert_eq!(preview, "tsh ssh deploy@i-0abc 'ls -la'");
+
+        let cmd = build_command(&host, &config, None, Some("ls -la")).unwrap();
+        assert_eq!(cmd.get_program(), "tsh");
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(args, vec!["ssh", "deploy@i-0abc", "ls -la"]);
+    }
+
+    #[test]
+    fn mosh_hosts_pass_the_ssh_flags_through_its_ssh_option() {
+        let config = Config::default();
+        let mut host = Host {
+            name: "far".into(),
+            address: "203.0.113.8".into(),
+            user: Some("ops".into()),
+            port: Some(2222),
+            connect_with: Some("mosh".into()),
+            request_tty: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            command_preview(
+                &host,
+                &config,
+                Some("agent"),
+                Some("tmux attach -t 'main work'")
+            ),
+            "mosh '--ssh=ssh -p 2222' ops@203.0.113.8 -- tmux attach -t 'main work'"
+        );
+        let cmd = build_command(&host, &config, Some("agent"), None).unwrap();
+        assert_eq!(cmd.get_program(), "mosh");
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(args, ["--ssh=ssh -p 2222", "ops@203.0.113.8"]);
 
-    if let Some(extra) = extra_command {
-        cmd.arg(extra);
-    } else if let Some(remote) = &host.remote_command {
-        cmd.arg(remote);
-    }
+        // Nothing to pass on: mosh's own default ssh.
+        host.port = None;
+        host.user = None;
+        assert_eq!(
+            build_argv(&host, &config, Some("agent"), None).unwrap(),
+            ["mosh", "203.0.113.8"]
+        );
 
-    Ok(cmd)
-}
+        host.bastion = Some("jump-eu".into());
+        let err = build_command(&host, &Config::sample(), None, None).unwrap_err();
+        assert!(
+            err.to_string().contains("can't go through bastion"),
+            "{err}"
+        );
 
-pub fn run_command(mut cmd: Command) -> Result<()> {
-    cmd.stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
-    let status = cmd.status()?;
-    if !status.success() {
-        anyhow::bail!("ssh exited with status {status}");
+        host.bastion = None;
+        host.connect_with = Some("et".into());
+        assert!(command_preview(&host, &config, None, None).contains("expected ssh or mosh"));
+        host.connect_with = Some("ssh".into());
+        assert_eq!(
+            build_argv(&host, &config, Some("agent"), None).unwrap(),
+            ["ssh", "-t", "203.0.113.8"]
+        );
     }
-    Ok(())
-}
 
-pub fn command_preview(
-    host: &Host,
-    config: &Config,
-    default_key: Option<&str>,
-    extra: Option<&str>,
-) -> String {
-    let mut parts: Vec<String> = vec!["ssh".to_string()];
+    #[test]
+    fn proxy_command_is_passed_as_an_option_and_excludes_a_bastion() {
+        let mut host = Host {
+            name: "inner".into(),
+            address: "10.1.0.4".into(),
+            proxy_command: Some("nc -X 5 -x socks:1080 %h %p".into()),
+            ..Default::default()
+        };
+        let config = Config::sample();
+        assert_eq!(
+            command_preview(&host, &config, Some("agent"), None),
+            "ssh -o 'ProxyCommand=nc -X 5 -x socks:1080 %h %p' 10.1.0.4"
+        );
+        let steps = explain(&host, &config, Some("agent"), None).steps;
+        assert!(steps.contains(&Step::ProxyCommand("nc -X 5 -x socks:1080 %h %p".into())));
 
-    if let Some(bastion_name) = &host.bastion {
-        match build_bastion_string(config, bastion_name, default_key, &mut Vec::new()) {
-            Ok(b_str) => {
-                parts.push("-J".into());
-                parts.push(b_str);
-            }
-            Err(_) => {
-                parts.push(format!("-J <error: bastion '{}' not found>", bastion_name));
-            }
-        }
+        host.bastion = Some("jump-eu".into());
+        let err = build_command(&host, &config, None, None).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("both bastion 'jump-eu' and a proxy_command"),
+            "{err}"
+        );
     }
 
-    if let Some(port) = host.port {
-        parts.push("-p".into());
-        parts.push(port.to_string());
+    #[test]
+    fn set_env_goes_in_a_single_option() {
+        let host = Host {
+            name: "app".into(),
+            address: "10.1.0.5".into(),
+            set_env: vec!["LANG=C.UTF-8".into(), "APP_ENV=prod".into()],
+            ..Default::default()
+        };
+        let config = Config::sample();
+        // ssh ignores every SetEnv after the first.
+        assert_eq!(
+            command_preview(&host, &config, Some("agent"), None),
+            "ssh -o 'SetEnv=LANG=C.UTF-8 APP_ENV=prod' 10.1.0.5"
+        );
+        let steps = explain(&host, &config, Some("agent"), None).steps;
+        assert!(steps.contains(&Step::SetEnv(host.set_env.clone())));
     }
 
-    for key in select_keys(&host.key_paths, default_key) {
-        parts.push("-i".into());
-        parts.push(key);
+    #[test]
+    fn command_lines_keep_flags_with_their_values() {
+        let config: Config = toml::from_str(GOLDEN_CONFIG).unwrap();
+        let host = config.find_host("inner").unwrap();
+        let preview = command_preview(host, &config, Some("agent"), None);
+        let lines = command_lines(host, &config, Some("agent"), None).unwrap();
+        assert_eq!(lines.join(" "), preview);
+        assert_eq!(lines[0], "ssh");
+        assert!(
+            lines.iter().any(|line| line.starts_with("-J ")),
+            "{lines:?}"
+        );
+        assert!(lines
+            .iter()
+            .skip(1)
+            .all(|line| !line.starts_with('-') || line.contains(' ') || line.len() == 2));
     }
 
-    for opt in effective_options(host) {
-        parts.push(opt);
-    }
+    #[test]
+    fn password_command_goes_through_askpass_and_stays_out_of_previews() {
+        let host = Host {
+            name: "vault".into(),
+            address: "10.1.0.9".into(),
+            password_command: Some("vault read -field=pw secret/ssh".into()),
+            ..Default::default()
+        };
+        let config = Config::sample();
+        assert_eq!(
+            command_preview(&host, &config, Some("agent"), None),
+            "SSH_ASKPASS='<from password_command>' ssh 10.1.0.9"
+        );
+        assert_eq!(
+            shell_split(&command_preview(&host, &config, Some("agent"), None)).unwrap(),
+            ["SSH_ASKPASS=<from password_command>", "ssh", "10.1.0.9"]
+        );
+        let cmd = build_command(&host, &config, Some("agent"), None).unwrap();
+        let envs: Vec<_> = cmd.get_envs().collect();
+        assert!(envs.contains(&(
+            std::ffi::OsStr::new(ASKPASS_COMMAND_ENV),
+            Some(std::ffi::OsStr::new("vault read -field=pw secret/ssh"))
+        )));
+        assert!(envs.contains(&(
+            std::ffi::OsStr::new("SSH_ASKPASS_REQUIRE"),
+            Some(std::ffi::OsStr::new("force"))
+        )));
+        assert!(!cmd
+            .get_args()
+            .any(|arg| arg.to_string_lossy().contains("vault")));
 
-    if let Some(user) = &host.user {
-        parts.push(format!("{user}@{}", host.address));
-    } else {
-        parts.push(host.address.clone());
+        let empty = Host {
+            password_command: Some("  ".into()),
+            ..host
+        };
+        let err = build_command(&empty, &config, Some("agent"), None).unwrap_err();
+        assert_eq!(err.to_string(), "password_command of 'vault' is empty");
     }
 
-    if let Some(extra_cmd) = extra {
-        parts.push(extra_cmd.to_string());
-    } else if let Some(remote) = &host.remote_command {
-        parts.push(remote.clone());
+    #[test]
+    fn password_command_is_refused_where_other_ssh_processes_would_ask() {
+        let host = Host {
+            name: "vault".into(),
+            address: "10.1.0.9".into(),
+            password_command: Some("vault read -field=pw secret/ssh".into()),
+            ..Default::default()
+        };
+        let config = Config::sample();
+        let cases = [
+            (
+                Host {
+                    bastion: Some("jump-eu".into()),
+                    ..host.clone()
+                },
+                "bastion 'jump-eu'",
+            ),
+            (
+                Host {
+                    proxy_command: Some("nc -X 5 -x proxy:1080 %h %p".into()),
+                    ..host.clone()
+                },
+                "a proxy",
+            ),
+            (
+                Host {
+                    options: vec!["-oProxyJump=ops@52.17.9.3".into()],
+                    ..host.clone()
+                },
+                "a proxy",
+            ),
+            (
+                Host {
+                    command_template: Some("ssh {host}".into()),
+                    ..host.clone()
+                },
+                "a command template",
+            ),
+        ];
+        for (host, through) in cases {
+            let err = build_command(&host, &config, Some("agent"), None).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "password_command of 'vault' can't be used through {through}: its ssh would be sent the same password"
+                )
+            );
+        }
     }
 
-    parts.join(" ")
-}
-
-#[allow(clippy::only_used_in_recursion)]
-fn build_bastion_string(
-    config: &Config,
-    bastion_name: &str,
-    default_key: Option<&str>,
-    visited: &mut Vec<String>,
-) -> Result<String> {
-    if visited.contains(&bastion_name.to_string()) {
-        anyhow::bail!("circular bastion reference detected: {}", bastion_name);
+    /// `answer_askpass` with `ask` answering "yes"; the code, what it
+    /// printed and the prompts `ask` was given.
+    fn answer(command: &str, prompt: &str) -> (i32, String, Vec<String>) {
+        let mut asked = Vec::new();
+        let mut out = Vec::new();
+        let mut ask = |prompt: &str| {
+            asked.push(prompt.to_string());
+            Ok("yes".to_string())
+        };
+        let code = answer_askpass(command, prompt, &mut ask, &mut out);
+        (code, String::from_utf8(out).unwrap(), asked)
     }
-    visited.push(bastion_name.to_string());
-
-    let Some(bastion) = config.find_host(bastion_name) else {
-        return Ok(bastion_name.to_string());
-    };
 
-    let mut chains = Vec::new();
-    if let Some(nested) = &bastion.bastion {
-        let nested_str = build_bastion_string(config, nested, default_key, visited)?;
-        chains.push(nested_str);
+    #[test]
+    fn askpass_asks_the_user_prompts_that_are_not_for_a_password() {
+        let prompt = "Are you sure you want to continue connecting (yes/no)? ";
+        // The command is not run, so it doesn't matter that it can't be.
+        assert_eq!(
+            answer("/nonexistent/secret-tool", prompt),
+            (0, "yes\n".into(), vec![prompt.to_string()])
+        );
+        assert_eq!(answer("", "Password: "), (1, String::new(), Vec::new()));
     }
 
-    let mut bastion_str = if let Some(user) = &bastion.user {
-        format!("{user}@{}", bastion.address)
-    } else {
-        bastion.address.clone()
-    };
-    if let Some(port) = bastion.port {
-        bastion_str.push_str(&format!(":{}", port));
+    #[cfg(unix)]
+    #[test]
+    fn askpass_answers_password_prompts_with_a_working_command() {
+        assert_eq!(
+            answer("echo s3cret", "ops@10.1.0.9's password: "),
+            (0, "s3cret\n".into(), Vec::new())
+        );
+        assert_eq!(
+            answer("false", "Password: "),
+            (1, String::new(), Vec::new())
+        );
+        assert_eq!(answer("true", "Password: "), (1, String::new(), Vec::new()));
     }
 
-    if !chains.is_empty() {
-        chains.push(bastion_str);
-        Ok(chains.join(","))
-    } else {
-        Ok(bastion_str)
+    #[test]
+    fn forwarding_toggles_pass_a_and_x() {
+        let host = Host {
+            name: "app".into(),
+            address: "10.1.0.5".into(),
+            forward_agent: true,
+            forward_x11: true,
+            ..Default::default()
+        };
+        let config = Config::sample();
+        assert_eq!(
+            command_preview(&host, &config, Some("agent"), None),
+            "ssh -A -X 10.1.0.5"
+        );
+        let steps = explain(&host, &config, Some("agent"), None).steps;
+        assert!(steps.contains(&Step::Forwarding {
+            agent: true,
+            x11: true
+        }));
     }
-}
 
-fn select_keys(host_keys: &[String], default_key: Option<&str>) -> Vec<String> {
-    const FALLBACKS: [&str; 2] = ["~/.ssh/id_ed25519", "~/.ssh/id_rsa"];
-    if !host_keys.is_empty() {
-        return host_keys.iter().map(|key| expand_tilde(key)).collect();
-    }
-    if let Some(k) = default_key {
-        if k == "agent" {
-            return Vec::new();
+    #[test]
+    fn multiplex_adds_control_master_options_under_home() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let old_home = std::env::var_os("HOME");
+        unsafe {
+            std::env::set_var("HOME", "/home/ops");
         }
-        return vec![expand_tilde(k)];
+        let host = Host {
+            name: "app".into(),
+            address: "10.1.0.5".into(),
+            multiplex: true,
+            ..Default::default()
+        };
+        let config = Config::sample();
+        let preview = command_preview(&host, &config, Some("agent"), None);
+        let steps = explain(&host, &config, Some("agent"), None).steps;
+        unsafe {
+            match old_home {
+                Some(home) => std::env::set_var("HOME", home),
+                None => std::env::remove_var("HOME"),
+            }
+        }
+        assert_eq!(
+            preview,
+            "ssh -o ControlMaster=auto -o ControlPath=/home/ops/.ssh/sshdb-cm/cm-%r@%h:%p \
+             -o ControlPersist=60s 10.1.0.5"
+        );
+        assert!(steps.contains(&Step::Multiplex(
+            "/home/ops/.ssh/sshdb-cm/cm-%r@%h:%p".into()
+        )));
     }
 
-    let agent_available = std::env::var("SSH_AUTH_SOCK")
-        .map(|v| !v.is_empty())
-        .unwrap_or(false);
-    if agent_available {
-        return Vec::new();
-    }
+    #[test]
+    fn terminal_command_wraps_the_session_or_names_a_missing_program() {
+        let host = Host {
+            name: "app".into(),
+            address: "10.1.0.5".into(),
+            ..Default::default()
+        };
+        let config = Config::sample();
+        let cmd = || build_command(&host, &config, Some("agent"), None).unwrap();
+        run_in_terminal(cmd(), "true -e").unwrap();
 
-    // fall back to common keys when no agent is present; prefer an existing one
-    for cand in FALLBACKS {
-        let expanded = expand_tilde(cand);
-        if Path::new(&expanded).exists() {
-            return vec![expanded];
-        }
-    }
-    FALLBACKS
-        .first()
-        .map(|cand| vec![expand_tilde(cand)])
-        .unwrap_or_default()
-}
+        let err = run_in_terminal(cmd(), "/nonexistent/sshdb-term -e").unwrap_err();
+        assert_eq!(err.to_string(), "/nonexistent/sshdb-term does not exist.");
+        let err = run_in_terminal(cmd(), "  ").unwrap_err();
+        assert_eq!(err.to_string(), "terminal_command is empty");
 
-fn effective_options(host: &Host) -> Vec<String> {
-    let mut options = if host.prefer_public_key_auth {
-        strip_preferred_auth_options(&host.options)
-    } else {
-        host.options.clone()
-    };
+        let pinned = Host {
+            pinned_hostkey: Some(PINNED_KEY.into()),
+            ..host.clone()
+        };
+        let cmd = build_command(&pinned, &config, Some("agent"), None).unwrap();
+        let err = run_in_terminal(cmd, "true -e").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'app' has a pinned host key, which is only checked in sshdb's own terminal; unset terminal_command to connect"
+        );
+    }
 
-    if host.prefer_public_key_auth {
-        options.splice(
-            0..0,
+    #[test]
+    fn scp_reuses_the_connection_flags() {
+        let mut host = Host {
+            name: "v6".into(),
+            address: "2001:db8::7".into(),
+            user: Some("ops".into()),
+            port: Some(2200),
+            key_paths: vec!["/keys/ops".into()],
+            options: vec!["-A".into(), "-o".into(), "ServerAliveInterval=30".into()],
+            proxy_command: Some("nc %h %p".into()),
+            ..Default::default()
+        };
+        let mut config = Config::default();
+        assert_eq!(
+            build_scp_argv(&host, &config, None, "./a b.txt", "").unwrap(),
             [
-                "-o".to_string(),
-                "PreferredAuthentications=publickey".to_string(),
-            ],
+                "scp",
+                "-o",
+                "ProxyCommand=nc %h %p",
+                "-P",
+                "2200",
+                "-i",
+                "/keys/ops",
+                "-o",
+                "ServerAliveInterval=30",
+                "--",
+                "./a b.txt",
+                "ops@[2001:db8::7]:",
+            ]
         );
+
+        config.ssh_binary = Some("/opt/ssh/bin/ssh".into());
+        host.command_template = Some("tsh ssh {address}".into());
+        assert!(scp_preview(&host, &config, None, "a", "b").contains("command template"));
+        host.command_template = None;
+        assert!(scp_preview(&host, &config, None, "a", "b").starts_with("scp -S /opt/ssh/bin/ssh "));
     }
 
-    options
-}
+    const PINNED_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIDivEachRsVlR+hRghtml286It6zmvTRqafHWfy7or0a";
 
-fn strip_preferred_auth_options(options: &[String]) -> Vec<String> {
-    let mut cleaned = Vec::new();
-    let mut i = 0;
-    while i < options.len() {
-        let current = &options[i];
-        if current == "-o" {
-            if let Some(next) = options.get(i + 1) {
-                if is_preferred_auth_option(next) {
-                    i += 2;
-                    continue;
-                }
-            }
-            cleaned.push(current.clone());
-            i += 1;
-            continue;
-        }
+    #[test]
+    fn pinned_key_replaces_known_hosts_for_one_invocation() {
+        let config = Config::default();
+        let host = Host {
+            name: "vault".into(),
+            address: "10.0.0.5".into(),
+            key_paths: vec!["/keys/vault".into()],
+            pinned_hostkey: Some(PINNED_KEY.into()),
+            ..Default::default()
+        };
+        // The preview names the file symbolically.
+        assert_eq!(
+            command_preview(&host, &config, None, None),
+            "ssh -i /keys/vault -o 'UserKnownHostsFile=<pinned-known-hosts>' \
+             -o GlobalKnownHostsFile=/dev/null -o StrictHostKeyChecking=yes 10.0.0.5"
+        );
+        let explanation = explain(&host, &config, None, None);
+        assert!(explanation.steps.contains(&Step::PinnedHostKey {
+            full_key: true,
+            fingerprint: None
+        }));
 
-        if current.starts_with("-o") && is_preferred_auth_option(&current[2..]) {
-            i += 1;
-            continue;
-        }
+        let cmd = build_command(&host, &config, None, None).unwrap();
+        let args: Vec<String> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        let path = args[3].strip_prefix("UserKnownHostsFile=").unwrap();
+        assert_ne!(path, hostkey::KNOWN_HOSTS_PLACEHOLDER);
+        let path = PathBuf::from(path);
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("10.0.0.5 ssh-ed25519 "));
+        drop(cmd);
+        assert!(!path.exists());
 
-        cleaned.push(current.clone());
-        i += 1;
+        let mut templated = host.clone();
+        templated.command_template = Some("tsh ssh {address}".into());
+        assert!(build_argv(&templated, &config, None, None).is_err());
+        let mut broken = host;
+        broken.pinned_hostkey = Some("ssh-ed25519 nope".into());
+        assert!(command_preview(&broken, &config, None, None).starts_with("<error: "));
     }
-    cleaned
-}
-
-fn is_preferred_auth_option(option: &str) -> bool {
-    option
-        .to_ascii_lowercase()
-        .contains("preferredauthentications=")
-}
 
-fn expand_tilde(path: &str) -> String {
-    if let Some(stripped) = path.strip_prefix("~/") {
-        if let Ok(home) = std::env::var("HOME") {
-            return PathBuf::from(home)
-                .join(stripped)
-                .to_string_lossy()
-                .into_owned();
+    #[test]
+    fn quoted_preview_splits_back_to_argv() {
+        for word in ["plain", "two words", "it's", "", "$HOME"] {
+            assert_eq!(shell_split(&shell_quote(word)).unwrap(), vec![word]);
         }
     }
-    path.to_string()
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::Mutex;
+    #[cfg(unix)]
+    #[test]
+    fn finds_only_managed_sockets() {
+        use std::os::unix::net::UnixListener;
 
-    static ENV_LOCK: Mutex<()> = Mutex::new(());
+        let dir = tempfile::tempdir().unwrap();
+        let _listeners: Vec<UnixListener> = ["cm-deploy@prod:22", "cm-ops@jump:22", "agent"]
+            .iter()
+            .map(|name| UnixListener::bind(dir.path().join(name)).unwrap())
+            .collect();
+        for name in ["cm-notes", "known_hosts", "id_ed25519"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        std::fs::create_dir(dir.path().join("cm-dir")).unwrap();
+        let sockets = find_control_sockets(dir.path());
+        let names: Vec<_> = sockets
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["cm-deploy@prod:22", "cm-ops@jump:22"]);
+    }
 
+    #[cfg(unix)]
     #[test]
-    fn builds_preview_with_defaults() {
-        let config = Config::default();
-        let host = Host {
-            name: "prod".into(),
-            address: "10.0.0.1".into(),
-            user: Some("deploy".into()),
-            port: Some(2222),
-            key_paths: Vec::new(),
-            tags: vec![],
-            options: vec!["-L".into(), "8080:localhost:80".into()],
-            remote_command: None,
-            description: None,
-            bastion: None,
-            prefer_public_key_auth: false,
-        };
-        let preview = command_preview(&host, &config, Some("~/.ssh/id_ed25519"), Some("uptime"));
-        assert!(preview.contains("-p 2222"));
-        assert!(preview.contains("-i"));
-        assert!(preview.contains("deploy@10.0.0.1"));
-        assert!(preview.ends_with("uptime"));
-        assert!(preview.contains("-L 8080:localhost:80"));
+    fn removes_only_dead_sockets() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = tempfile::tempdir().unwrap();
+        let live = dir.path().join("cm-live");
+        let dead = dir.path().join("cm-dead");
+        let notes = dir.path().join("cm-notes");
+        let _live = UnixListener::bind(&live).unwrap();
+        drop(UnixListener::bind(&dead).unwrap());
+        std::fs::write(&notes, "keep me").unwrap();
+
+        let removed = remove_stale_sockets(&find_control_sockets(dir.path()), |p| p == live);
+        assert_eq!(removed, 1);
+        assert!(live.exists());
+        assert!(!dead.exists());
+        // Not a socket, so never a candidate, alive or not.
+        assert_eq!(std::fs::read_to_string(&notes).unwrap(), "keep me");
     }
 
     #[test]
-    fn allows_free_text_bastion() {
-        let mut config = Config::default();
-        let host = Host {
-            name: "prod".into(),
-            address: "10.0.0.1".into(),
-            user: Some("deploy".into()),
-            port: None,
-            key_paths: Vec::new(),
-            tags: vec![],
-            options: vec![],
-            remote_command: None,
-            description: None,
-            bastion: Some("proxy.example.com".into()),
-            prefer_public_key_auth: false,
-        };
-        config.hosts.push(host.clone());
-        let preview = command_preview(&host, &config, None, None);
-        assert!(preview.contains("-J proxy.example.com"));
-        assert!(preview.contains("deploy@10.0.0.1"));
+    fn counts_closed_and_failed_control_masters() {
+        let sockets = vec![PathBuf::from("cm-a"), PathBuf::from("cm-b")];
+        let (closed, failed) = close_control_sockets(&sockets, |p| {
+            if p.ends_with("cm-a") {
+                Ok(())
+            } else {
+                anyhow::bail!("gone")
+            }
+        });
+        assert_eq!((closed, failed), (1, 1));
     }
 
     #[test]
     fn expands_tilde() {
+        let _guard = ENV_LOCK.lock().unwrap();
         let out = expand_tilde("~/abc");
         if let Ok(home) = std::env::var("HOME") {
             assert!(out.contains(&home));
@@ -325,6 +3005,7 @@ mod tests {
             description: None,
             bastion: None,
             prefer_public_key_auth: false,
+            ..Default::default()
         };
         let old = std::env::var("SSH_AUTH_SOCK").ok();
         unsafe { std::env::remove_var("SSH_AUTH_SOCK") };
@@ -351,6 +3032,7 @@ mod tests {
             description: None,
             bastion: None,
             prefer_public_key_auth: false,
+            ..Default::default()
         };
         let old = std::env::var("SSH_AUTH_SOCK").ok();
         unsafe {
@@ -380,6 +3062,7 @@ mod tests {
             description: None,
             bastion: None,
             prefer_public_key_auth: true,
+            ..Default::default()
         };
 
         let preview = command_preview(&host, &config, None, None);
@@ -404,6 +3087,7 @@ mod tests {
             description: None,
             bastion: None,
             prefer_public_key_auth: true,
+            ..Default::default()
         };
 
         let preview = command_preview(&host, &config, None, None);
@@ -430,10 +3114,138 @@ mod tests {
             description: None,
             bastion: None,
             prefer_public_key_auth: true,
+            ..Default::default()
         };
 
         let preview = command_preview(&host, &config, None, None);
         assert!(preview.contains("PreferredAuthentications=publickey"));
         assert!(!preview.contains("PreferredAuthentications=password"));
     }
+
+    #[test]
+    fn route_lists_jump_hosts_then_the_target() {
+        let mut config = Config::default();
+        let edge = Host {
+            name: "edge".into(),
+            address: "edge.example.com".into(),
+            ..Default::default()
+        };
+        let jump = Host {
+            name: "jump".into(),
+            address: "52.17.9.3".into(),
+            user: Some("ops".into()),
+            port: Some(2200),
+            bastion: Some("edge".into()),
+            ..Default::default()
+        };
+        let db = Host {
+            name: "db".into(),
+            group: Some("eu".into()),
+            address: "35.12.2.4".into(),
+            bastion: Some("jump".into()),
+            ..Default::default()
+        };
+        config.hosts = vec![edge, jump, db.clone()];
+        let route = route(&db, &config);
+        let labels: Vec<(&str, &str, bool)> = route
+            .iter()
+            .map(|e| (e.label.as_str(), e.address.as_str(), e.bastion))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ("edge", "edge.example.com", true),
+                ("jump", "52.17.9.3", true),
+                ("eu/db", "35.12.2.4", false)
+            ]
+        );
+
+        assert_eq!(hop_address("ops@10.0.0.9:2222"), "10.0.0.9");
+        assert_eq!(hop_address("[fe80::1]:22"), "fe80::1");
+        assert_eq!(hop_address("fe80::1"), "fe80::1");
+        assert_eq!(hop_address("corp-jump"), "corp-jump");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_sessions_are_diagnosed_from_their_stderr() {
+        let mut cmd = Command::new("sh");
+        cmd.args([
+            "-c",
+            "echo 'ssh: connect to host 52.17.9.3 port 22: Connection timed out' >&2; exit 255",
+        ]);
+        let cmd = SshCommand {
+            cmd: Box::new(cmd),
+            known_hosts: None,
+            route: vec![
+                Endpoint {
+                    label: "jump-eu".into(),
+                    address: "52.17.9.3".into(),
+                    bastion: true,
+                },
+                Endpoint {
+                    label: "staging-db".into(),
+                    address: "35.12.2.4".into(),
+                    bastion: false,
+                },
+            ],
+            transfer: false,
+        };
+        assert_eq!(cmd.host_id(), "staging-db");
+        let err = run_command(cmd).unwrap_err();
+        let failed = err.downcast_ref::<SessionFailed>().unwrap();
+        assert_eq!(failed.status.code(), Some(255));
+        assert_eq!(
+            err.to_string(),
+            "failed at bastion jump-eu (connection timed out)"
+        );
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "exit 3"]);
+        let cmd = SshCommand {
+            cmd: Box::new(cmd),
+            known_hosts: None,
+            route: Vec::new(),
+            transfer: false,
+        };
+        let err = run_command(cmd).unwrap_err();
+        assert!(
+            err.to_string().starts_with("ssh exited with status"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn stderr_tee_keeps_only_the_last_lines() {
+        let input: String = (1..=60)
+            .map(|n| format!("line {n}\r\n"))
+            .collect::<String>()
+            + "tail";
+        let lines = StderrTee::spawn(
+            std::io::Cursor::new(input.into_bytes()),
+            std::io::sink(),
+            50,
+        )
+        .finish();
+        assert_eq!(lines.len(), 50);
+        assert_eq!(lines[0], "line 12");
+        assert_eq!(lines[48], "line 60");
+        assert_eq!(lines[49], "tail");
+    }
+
+    #[test]
+    fn spawning_a_missing_program_explains_the_lookup() {
+        let cmd = SshCommand {
+            cmd: Box::new(Command::new("sshdb-test-no-such-ssh")),
+            known_hosts: None,
+            route: Vec::new(),
+            transfer: false,
+        };
+        let err = run_command(cmd).unwrap_err().to_string();
+        assert!(
+            err.starts_with("'sshdb-test-no-such-ssh' not found"),
+            "{err}"
+        );
+        assert!(!err.contains("os error"), "{err}");
+    }
 }
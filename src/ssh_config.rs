@@ -0,0 +1,270 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! A forgiving reader for ssh_config text (`Host foo` / `HostName …` stanzas).
+//! Lines it can't make sense of are collected rather than treated as errors,
+//! since input is often pasted from wikis and chat.
+
+/// One `Host` block. Directives seen before any `Host` line land in a block
+/// with no patterns.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stanza {
+    pub patterns: Vec<String>,
+    /// `(keyword, value)` in file order; keywords keep their original case.
+    pub directives: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Parsed {
+    pub stanzas: Vec<Stanza>,
+    /// Lines that were neither a directive nor a comment.
+    pub skipped: Vec<String>,
+}
+
+/// Keywords that make text look like ssh_config rather than an ssh command.
+const SIGNATURE_KEYWORDS: [&str; 6] = [
+    "host",
+    "hostname",
+    "user",
+    "port",
+    "identityfile",
+    "proxyjump",
+];
+
+pub fn parse(text: &str) -> Parsed {
+    let mut parsed = Parsed::default();
+    let mut current: Option<Stanza> = None;
+    for raw in text.lines() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((keyword, value)) = split_directive(line) else {
+            parsed.skipped.push(line.to_string());
+            continue;
+        };
+        if keyword.eq_ignore_ascii_case("host") {
+            parsed.stanzas.extend(current.take());
+            current = Some(Stanza {
+                patterns: value.split_whitespace().map(unquote).collect(),
+                directives: Vec::new(),
+            });
+        } else {
+            current
+                .get_or_insert_with(Stanza::default)
+                .directives
+                .push((keyword.to_string(), unquote(value)));
+        }
+    }
+    parsed.stanzas.extend(current);
+    parsed
+}
+
+/// True when every meaningful line is `Keyword value` (or `Keyword=value`)
+/// and at least one keyword is a core ssh_config one, so `ssh -p 22 host` or
+/// `deploy@host` never qualify.
+pub fn looks_like_config(text: &str) -> bool {
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+    if lines.peek().is_none() {
+        return false;
+    }
+    let mut signature = false;
+    for line in lines {
+        let Some((keyword, _)) = split_directive(line) else {
+            return false;
+        };
+        if keyword.eq_ignore_ascii_case("ssh") {
+            return false;
+        }
+        signature |= SIGNATURE_KEYWORDS
+            .iter()
+            .any(|known| keyword.eq_ignore_ascii_case(known));
+    }
+    signature
+}
+
+/// The directives ssh would apply to `alias`: matching stanzas are read in
+/// order and the first value of each keyword wins, except `IdentityFile`,
+/// which accumulates. Keywords come back lowercased.
+pub fn lookup(parsed: &Parsed, alias: &str) -> Vec<(String, String)> {
+    let mut effective: Vec<(String, String)> = Vec::new();
+    for stanza in parsed
+        .stanzas
+        .iter()
+        .filter(|stanza| host_matches(&stanza.patterns, alias))
+    {
+        for (keyword, value) in &stanza.directives {
+            let keyword = keyword.to_ascii_lowercase();
+            if keyword == "identityfile" || !effective.iter().any(|(k, _)| *k == keyword) {
+                effective.push((keyword, value.clone()));
+            }
+        }
+    }
+    effective
+}
+
+/// `Host` pattern list semantics: any positive match and no negated one.
+/// Directives before the first `Host` line (no patterns) apply to all.
+fn host_matches(patterns: &[String], alias: &str) -> bool {
+    if patterns.is_empty() {
+        return true;
+    }
+    let alias = alias.to_ascii_lowercase();
+    let mut matched = false;
+    for pattern in patterns {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix('!') {
+            Some(negated) if glob_match(negated, &alias) => return false,
+            Some(_) => {}
+            None => matched |= glob_match(&pattern, &alias),
+        }
+    }
+    matched
+}
+
+/// `*` and `?` wildcards, as in ssh_config patterns.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, from)) => {
+                    p = star + 1;
+                    t = from + 1;
+                    backtrack = Some((star, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Splits `Keyword value`, `Keyword=value` or `Keyword = value`. Keywords
+/// are plain words; anything else is not a directive.
+fn split_directive(line: &str) -> Option<(&str, &str)> {
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let keyword = &line[..end];
+    if keyword.len() < 2 || !keyword.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest).trim();
+    if rest.is_empty() {
+        return None;
+    }
+    Some((keyword, rest))
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values<'a>(stanza: &'a Stanza, keyword: &str) -> Vec<&'a str> {
+        stanza
+            .directives
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(keyword))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn parses_stanzas_and_tolerates_junk() {
+        let parsed = parse(
+            "# from the wiki\nHost web web.prod\n  HostName 10.0.0.1\n  User=deploy\n  \
+             IdentityFile \"~/.ssh/a key\"\n  IdentityFile ~/.ssh/b\n???\n\nHost db\n  Port = 2222\n",
+        );
+        assert_eq!(parsed.stanzas.len(), 2);
+        let web = &parsed.stanzas[0];
+        assert_eq!(web.patterns, vec!["web", "web.prod"]);
+        assert_eq!(values(web, "hostname"), vec!["10.0.0.1"]);
+        assert_eq!(values(web, "USER"), vec!["deploy"]);
+        assert_eq!(
+            values(web, "IdentityFile"),
+            vec!["~/.ssh/a key", "~/.ssh/b"]
+        );
+        assert_eq!(values(&parsed.stanzas[1], "Port"), vec!["2222"]);
+        assert_eq!(parsed.skipped, vec!["???"]);
+    }
+
+    #[test]
+    fn directives_before_any_host_form_an_anonymous_stanza() {
+        let parsed = parse("HostName 10.0.0.1\nUser root\n");
+        assert_eq!(parsed.stanzas.len(), 1);
+        assert!(parsed.stanzas[0].patterns.is_empty());
+        assert_eq!(values(&parsed.stanzas[0], "user"), vec!["root"]);
+    }
+
+    #[test]
+    fn looks_up_effective_directives_like_ssh() {
+        let parsed = parse(
+            "User everyone\nHost corp-* !corp-lab\n  HostName jump.corp.example\n  \
+             IdentityFile ~/.ssh/a\nHost corp-jump\n  HostName ignored\n  Port 2222\n  \
+             IdentityFile ~/.ssh/b\nHost *\n  User fallback\n",
+        );
+        assert_eq!(
+            lookup(&parsed, "Corp-Jump"),
+            vec![
+                ("user".to_string(), "everyone".to_string()),
+                ("hostname".to_string(), "jump.corp.example".to_string()),
+                ("identityfile".to_string(), "~/.ssh/a".to_string()),
+                ("port".to_string(), "2222".to_string()),
+                ("identityfile".to_string(), "~/.ssh/b".to_string()),
+            ]
+        );
+        assert_eq!(
+            lookup(&parsed, "corp-lab"),
+            vec![("user".to_string(), "everyone".to_string())]
+        );
+        assert!(glob_match("db?.prod*", "db1.prod.example"));
+        assert!(!glob_match("db?", "db12"));
+    }
+
+    #[test]
+    fn detects_config_shape() {
+        assert!(looks_like_config(
+            "Host foo\n  HostName 1.2.3.4\n  User deploy"
+        ));
+        assert!(looks_like_config("  hostname 1.2.3.4\n  port 2222\n"));
+        assert!(looks_like_config("Host=foo\n# comment\nHostName=1.2.3.4"));
+        assert!(looks_like_config("Host foo"));
+    }
+
+    #[test]
+    fn ssh_commands_and_targets_are_not_config() {
+        assert!(!looks_like_config(""));
+        assert!(!looks_like_config("ssh deploy@10.0.0.1"));
+        assert!(!looks_like_config("ssh -p 2222 deploy@10.0.0.1 uptime"));
+        assert!(!looks_like_config("deploy@10.0.0.1"));
+        assert!(!looks_like_config("example.com"));
+        assert!(!looks_like_config("myhost -p 2222"));
+        assert!(!looks_like_config("Host foo\nssh foo"));
+        assert!(!looks_like_config("ForwardAgent yes\nCompression yes"));
+    }
+}
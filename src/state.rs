@@ -0,0 +1,175 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::model::{Config, Host};
+
+/// Which host fields the main search matches against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchScope {
+    Names,
+    #[default]
+    Targets,
+    Everything,
+}
+
+impl SearchScope {
+    pub fn next(self) -> Self {
+        match self {
+            SearchScope::Names => SearchScope::Targets,
+            SearchScope::Targets => SearchScope::Everything,
+            SearchScope::Everything => SearchScope::Names,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SearchScope::Names => "names",
+            SearchScope::Targets => "names+targets+tags",
+            SearchScope::Everything => "everything",
+        }
+    }
+}
+
+/// The order of the host list while the search is empty.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostOrder {
+    /// As in the config file.
+    #[default]
+    Config,
+    /// Most recently connected first; never-connected hosts last.
+    Recent,
+}
+
+impl HostOrder {
+    pub fn toggle(self) -> Self {
+        match self {
+            HostOrder::Config => HostOrder::Recent,
+            HostOrder::Recent => HostOrder::Config,
+        }
+    }
+}
+
+/// UI state that survives restarts but does not belong in the host database.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppState {
+    #[serde(default)]
+    pub search_scope: SearchScope,
+    #[serde(default)]
+    pub host_order: HostOrder,
+    /// Canonical ids of the workset hosts, kept only with `persist_workset`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub workset: Vec<String>,
+    /// Canonical id of the host last moved to, selected again on start.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_selected: Option<String>,
+}
+
+pub struct StateStore {
+    path: PathBuf,
+    /// Set for `in_memory` stores, which keep state for the session only.
+    memory: Option<RefCell<AppState>>,
+}
+
+impl StateStore {
+    /// Keeps the state file next to the config it belongs to.
+    pub fn beside(config_path: &Path) -> Self {
+        Self {
+            path: config_path.with_file_name("state.toml"),
+            memory: None,
+        }
+    }
+
+    /// Never touches disk; pairs with `ConfigStore::in_memory`.
+    pub fn in_memory() -> Self {
+        Self {
+            path: PathBuf::new(),
+            memory: Some(RefCell::new(AppState::default())),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load(&self) -> Result<AppState> {
+        if let Some(memory) = &self.memory {
+            return Ok(memory.borrow().clone());
+        }
+        if !self.path.exists() {
+            return Ok(AppState::default());
+        }
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read state file {}", self.path.display()))?;
+        toml::from_str(&content).with_context(|| "failed to parse state file")
+    }
+
+    pub fn save(&self, state: &AppState) -> Result<()> {
+        if let Some(memory) = &self.memory {
+            *memory.borrow_mut() = state.clone();
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create state dir {}", dir.display()))?;
+        }
+        let toml =
+            toml::to_string_pretty(state).with_context(|| "failed to serialize state to toml")?;
+        fs::write(&self.path, toml)
+            .with_context(|| format!("failed to write state file {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// The saved workset of the config at `config_path`, for `--workset` on the
+/// command line: ids of hosts still in `config`, in config order.
+pub fn saved_workset(config_path: &Path, config: &Config) -> Result<Vec<String>> {
+    let saved = StateStore::beside(config_path).load()?.workset;
+    let ids: Vec<String> = config
+        .hosts
+        .iter()
+        .map(Host::id)
+        .filter(|id| saved.contains(id))
+        .collect();
+    if ids.is_empty() {
+        bail!(
+            "no saved workset; add hosts with w in sshdb and set persist_workset = true to keep it"
+        );
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn saves_and_loads_state() {
+        let dir = tempdir().unwrap();
+        let store = StateStore::beside(&dir.path().join("config.toml"));
+        assert_eq!(store.load().unwrap(), AppState::default());
+
+        let state = AppState {
+            search_scope: SearchScope::Everything,
+            workset: vec!["prod-web".into(), "team-a/db1".into()],
+            host_order: HostOrder::Recent,
+            last_selected: Some("team-a/db1".into()),
+        };
+        store.save(&state).unwrap();
+        assert_eq!(store.load().unwrap(), state);
+
+        let config = Config::sample();
+        let workset = saved_workset(&dir.path().join("config.toml"), &config).unwrap();
+        assert_eq!(workset, ["prod-web"]);
+        assert!(saved_workset(&dir.path().join("other/config.toml"), &config).is_err());
+    }
+}
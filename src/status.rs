@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! The status bar message and the rules for replacing it. Errors stay until
+//! dismissed; anything less severe that arrives meanwhile waits its turn.
+
+use std::collections::VecDeque;
+
+use crate::debuglog;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusKind {
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatusLine {
+    pub text: String,
+    pub kind: StatusKind,
+}
+
+/// Messages queued behind an error beyond this are dropped, oldest first.
+const MAX_QUEUED: usize = 8;
+
+#[derive(Debug, Default)]
+pub struct StatusManager {
+    current: Option<StatusLine>,
+    queued: VecDeque<StatusLine>,
+    bell: bool,
+}
+
+impl StatusManager {
+    /// Shows `line`, unless an error is on screen and `line` is not one: then
+    /// it is queued until the error is dismissed.
+    pub fn set(&mut self, line: StatusLine) {
+        match line.kind {
+            StatusKind::Error => debuglog::error("status", &line.text, &[]),
+            StatusKind::Warn => debuglog::warn("status", &line.text, &[]),
+            StatusKind::Info => debuglog::debug("status", &line.text, &[]),
+        }
+        if line.kind == StatusKind::Error {
+            self.bell = true;
+        } else if self.has_error() {
+            if self.queued.len() == MAX_QUEUED {
+                self.queued.pop_front();
+            }
+            self.queued.push_back(line);
+            return;
+        }
+        self.current = Some(line);
+    }
+
+    /// Clears a transient message; errors need `dismiss`.
+    pub fn clear(&mut self) {
+        if !self.has_error() {
+            self.current = None;
+        }
+    }
+
+    /// Drops the shown error and brings up the most recent queued message,
+    /// i.e. what would be showing had the error never been there. Returns
+    /// false when there was no error to dismiss.
+    pub fn dismiss(&mut self) -> bool {
+        if !self.has_error() {
+            return false;
+        }
+        self.current = self.queued.pop_back();
+        self.queued.clear();
+        true
+    }
+
+    pub fn current(&self) -> Option<&StatusLine> {
+        self.current.as_ref()
+    }
+
+    pub fn has_error(&self) -> bool {
+        self.current
+            .as_ref()
+            .is_some_and(|line| line.kind == StatusKind::Error)
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// True once per newly shown error.
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str, kind: StatusKind) -> StatusLine {
+        StatusLine {
+            text: text.into(),
+            kind,
+        }
+    }
+
+    fn shown(status: &StatusManager) -> &str {
+        &status.current().unwrap().text
+    }
+
+    #[test]
+    fn info_and_warn_replace_each_other() {
+        let mut status = StatusManager::default();
+        status.set(line("a", StatusKind::Info));
+        status.set(line("b", StatusKind::Warn));
+        assert_eq!(shown(&status), "b");
+        status.clear();
+        assert!(status.current().is_none());
+        assert!(!status.take_bell());
+    }
+
+    #[test]
+    fn error_holds_until_dismissed_and_queues_the_rest() {
+        let mut status = StatusManager::default();
+        status.set(line("boom", StatusKind::Error));
+        assert!(status.take_bell());
+        assert!(!status.take_bell());
+
+        status.set(line("saved", StatusKind::Info));
+        status.set(line("careful", StatusKind::Warn));
+        status.clear();
+        assert_eq!(shown(&status), "boom");
+        assert_eq!(status.queued(), 2);
+
+        assert!(status.dismiss());
+        assert_eq!(shown(&status), "careful");
+        assert_eq!(status.queued(), 0);
+        assert!(!status.dismiss());
+        assert_eq!(shown(&status), "careful");
+    }
+
+    #[test]
+    fn newer_error_replaces_error_and_rings_again() {
+        let mut status = StatusManager::default();
+        status.set(line("first", StatusKind::Error));
+        status.take_bell();
+        status.set(line("queued", StatusKind::Info));
+        status.set(line("second", StatusKind::Error));
+        assert_eq!(shown(&status), "second");
+        assert!(status.take_bell());
+        status.dismiss();
+        assert_eq!(shown(&status), "queued");
+    }
+
+    #[test]
+    fn queue_is_bounded() {
+        let mut status = StatusManager::default();
+        status.set(line("boom", StatusKind::Error));
+        for n in 0..20 {
+            status.set(line(&n.to_string(), StatusKind::Info));
+        }
+        assert_eq!(status.queued(), MAX_QUEUED);
+        status.dismiss();
+        assert_eq!(shown(&status), "19");
+    }
+}
@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Terminal modes of the full-screen UI, switched off while ssh has the
+//! terminal and back on afterwards, plus the repaint that follows.
+//!
+//! Some terminals restore their own state lazily when a program leaves the
+//! alternate screen (conhost and older Windows Terminal builds, GNU screen
+//! before 4.9), so text from the ssh session could show through cells the
+//! first frame left blank. Resuming therefore resets the cursor, clears,
+//! and makes the next frame write every cell.
+//!
+//! Manual check after changing this, on each of those terminals plus tmux:
+//! connect, run `ls -la /` and `top` (then `q`), log out, and make sure the
+//! list comes back with no leftover text, a hidden cursor, and a
+//! multi-line paste into the Add form still arriving as one paste.
+
+use std::io::{self, Write};
+
+use crossterm::cursor::{Hide, SetCursorStyle, Show};
+use crossterm::event::{
+    DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+    KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::queue;
+use crossterm::terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::Backend;
+use ratatui::Terminal;
+
+/// Which modes are on, so leaving and resuming apply the same set. Raw mode
+/// is not an escape sequence and is switched separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TerminalFeatures {
+    pub alternate_screen: bool,
+    /// Multi-line pastes (ssh_config stanzas) arrive as one event.
+    pub bracketed_paste: bool,
+    /// Kitty keyboard protocol, kept to the TUI session.
+    pub keyboard_enhancement: bool,
+    pub mouse_capture: bool,
+}
+
+impl TerminalFeatures {
+    /// What the full-screen UI uses.
+    pub const FULL_SCREEN: Self = Self {
+        alternate_screen: true,
+        bracketed_paste: true,
+        keyboard_enhancement: true,
+        mouse_capture: false,
+    };
+
+    /// Turns the modes on, outermost (the alternate screen) first.
+    pub fn enter(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.alternate_screen {
+            queue!(out, EnterAlternateScreen)?;
+        }
+        if self.bracketed_paste {
+            queue!(out, EnableBracketedPaste)?;
+        }
+        if self.keyboard_enhancement {
+            queue!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+            )?;
+        }
+        if self.mouse_capture {
+            queue!(out, EnableMouseCapture)?;
+        }
+        out.flush()
+    }
+
+    /// Turns the modes off in reverse order. The keyboard flags are popped
+    /// before leaving the alternate screen so no CSI u sequences leak into
+    /// the shell.
+    pub fn leave(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.mouse_capture {
+            queue!(out, DisableMouseCapture)?;
+        }
+        if self.keyboard_enhancement {
+            queue!(out, PopKeyboardEnhancementFlags)?;
+        }
+        if self.bracketed_paste {
+            queue!(out, DisableBracketedPaste)?;
+        }
+        if self.alternate_screen {
+            queue!(out, LeaveAlternateScreen)?;
+        }
+        queue!(out, Show)?;
+        out.flush()
+    }
+
+    /// After another program had the terminal: turns the modes back on, then
+    /// resets what that program may have left behind (cursor shape and
+    /// visibility, screen contents).
+    pub fn resume(&self, out: &mut impl Write) -> io::Result<()> {
+        self.enter(out)?;
+        queue!(
+            out,
+            SetCursorStyle::DefaultUserShape,
+            Hide,
+            Clear(ClearType::All)
+        )?;
+        out.flush()
+    }
+}
+
+/// A symbol no frame contains, so every cell differs from it.
+const STALE: &str = "\u{e000}";
+
+/// Clears the screen and makes the next `draw` write every cell, not just
+/// the ones that changed since the last frame.
+pub fn force_full_redraw<B: Backend>(terminal: &mut Terminal<B>) -> io::Result<()> {
+    terminal.clear()?;
+    for cell in &mut terminal.current_buffer_mut().content {
+        cell.set_symbol(STALE);
+    }
+    // The filled buffer becomes the "previous frame" the next draw diffs
+    // against.
+    terminal.swap_buffers();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::buffer::Cell;
+    use ratatui::widgets::Paragraph;
+
+    fn written(f: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut out = Vec::new();
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn resume_reapplies_what_leave_turned_off() {
+        let features = TerminalFeatures::FULL_SCREEN;
+        let leave = written(|out| features.leave(out));
+        let resume = written(|out| features.resume(out));
+        for (on, off) in [
+            ("\x1b[?1049h", "\x1b[?1049l"),
+            ("\x1b[?2004h", "\x1b[?2004l"),
+        ] {
+            assert!(leave.contains(off) && !leave.contains(on), "{leave:?}");
+            assert!(resume.contains(on) && !resume.contains(off), "{resume:?}");
+        }
+        assert!(resume.contains("\x1b[>1u"), "{resume:?}");
+        let pop = leave.find("\x1b[<1u").unwrap();
+        assert!(pop < leave.find("\x1b[?1049l").unwrap());
+        // Cursor shape and visibility reset, then the screen cleared.
+        assert!(resume.ends_with("\x1b[0 q\x1b[?25l\x1b[2J"), "{resume:?}");
+        assert!(!resume.contains("\x1b[?1000h"));
+
+        let mouse = TerminalFeatures {
+            mouse_capture: true,
+            ..TerminalFeatures::FULL_SCREEN
+        };
+        assert!(written(|out| mouse.resume(out)).contains("\x1b[?1000h"));
+        assert!(written(|out| mouse.leave(out)).contains("\x1b[?1000l"));
+    }
+
+    #[test]
+    fn full_redraw_overwrites_text_left_on_screen() {
+        let mut terminal = Terminal::new(TestBackend::new(12, 2)).unwrap();
+        let frame = |f: &mut ratatui::Frame| f.render_widget(Paragraph::new("hosts"), f.size());
+        terminal.draw(frame).unwrap();
+
+        // The terminal repaints the ssh session's text after we cleared.
+        let leftovers = |terminal: &mut Terminal<TestBackend>| {
+            let mut cell = Cell::default();
+            cell.set_symbol("$");
+            terminal
+                .backend_mut()
+                .draw([(8, 0, &cell), (3, 1, &cell)].into_iter())
+                .unwrap();
+        };
+
+        terminal.clear().unwrap();
+        leftovers(&mut terminal);
+        terminal.draw(frame).unwrap();
+        terminal
+            .backend()
+            .assert_buffer(&ratatui::buffer::Buffer::with_lines(vec![
+                "hosts   $   ",
+                "   $        ",
+            ]));
+
+        force_full_redraw(&mut terminal).unwrap();
+        leftovers(&mut terminal);
+        terminal.draw(frame).unwrap();
+        terminal
+            .backend()
+            .assert_buffer(&ratatui::buffer::Buffer::with_lines(vec![
+                "hosts       ",
+                "            ",
+            ]));
+    }
+}
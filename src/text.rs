@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Display-width measurement and truncation for terminal cells. Everything
+//! works on grapheme clusters, so wide (CJK, emoji) and combining characters
+//! are counted the way the terminal draws them and never split.
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Columns `text` occupies on screen.
+pub fn width(text: &str) -> usize {
+    text.graphemes(true).map(UnicodeWidthStr::width).sum()
+}
+
+/// Shortens `text` to at most `max` columns, ending in `…` when something
+/// was cut.
+pub fn truncate_end(text: &str, max: usize) -> String {
+    if width(text) <= max {
+        return text.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+    let mut out = take_width(text.graphemes(true), max - 1);
+    out.push('…');
+    out
+}
+
+/// Shortens `text` to at most `max` columns, starting with `…` when
+/// something was cut, so the end of what is being typed stays in view.
+pub fn truncate_start(text: &str, max: usize) -> String {
+    if width(text) <= max {
+        return text.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+    let mut tail = Vec::new();
+    let mut used = 0;
+    for g in text.graphemes(true).rev() {
+        used += g.width();
+        if used > max - 1 {
+            break;
+        }
+        tail.push(g);
+    }
+    let mut out = String::from("…");
+    out.extend(tail.into_iter().rev());
+    out
+}
+
+/// Shortens `text` to at most `max` columns by replacing its middle with `…`,
+/// so both the target near the start and a trailing remote command stay
+/// visible.
+pub fn truncate_middle(text: &str, max: usize) -> String {
+    if width(text) <= max {
+        return text.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+    let budget = max - 1;
+    let tail_budget = budget / 2;
+    let head_budget = budget - tail_budget;
+
+    let mut head = take_width(text.graphemes(true), head_budget);
+    let tail: Vec<&str> = {
+        let mut tail = Vec::new();
+        let mut used = 0;
+        for g in text.graphemes(true).rev() {
+            used += g.width();
+            if used > tail_budget {
+                break;
+            }
+            tail.push(g);
+        }
+        tail
+    };
+    head.push('…');
+    head.extend(tail.into_iter().rev());
+    head
+}
+
+/// Levenshtein distance, for did-you-mean suggestions.
+pub fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + usize::from(ca != *cb))
+                .min(row[j] + 1)
+                .min(above + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+fn take_width<'a>(graphemes: impl Iterator<Item = &'a str>, max: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    for g in graphemes {
+        used += g.width();
+        if used > max {
+            break;
+        }
+        out.push_str(g);
+    }
+    out
+}
+
+/// Byte offset of the grapheme boundary before `at` (0 at the start).
+pub fn prev_boundary(text: &str, at: usize) -> usize {
+    text[..at]
+        .grapheme_indices(true)
+        .next_back()
+        .map_or(0, |(idx, _)| idx)
+}
+
+/// Byte offset of the grapheme boundary after `at` (`text.len()` at the end).
+pub fn next_boundary(text: &str, at: usize) -> usize {
+    at + text[at..].graphemes(true).next().map_or(0, str::len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Combining ("e" + acute accent), CJK and emoji characters.
+    const MIXED: &str = "cafe\u{301} 東京 🚀 db";
+
+    #[test]
+    fn measures_display_width() {
+        assert_eq!(width("plain"), 5);
+        assert_eq!(width("東京"), 4);
+        assert_eq!(width("e\u{301}"), 1);
+        assert_eq!(width("🚀"), 2);
+        assert_eq!(width(MIXED), 4 + 1 + 4 + 1 + 2 + 1 + 2);
+    }
+
+    #[test]
+    fn truncates_the_start_on_grapheme_boundaries() {
+        assert_eq!(truncate_start(MIXED, 30), MIXED);
+        assert_eq!(truncate_start(MIXED, 3), "…db");
+        // A wide character that doesn't fit is dropped, not split.
+        assert_eq!(truncate_start(MIXED, 5), "… db");
+        assert_eq!(truncate_start("東京", 1), "…");
+        assert_eq!(truncate_start("abc", 0), "");
+        for max in 0..=width(MIXED) {
+            assert!(width(&truncate_start(MIXED, max)) <= max, "{max}");
+        }
+    }
+
+    #[test]
+    fn truncates_on_grapheme_boundaries() {
+        assert_eq!(truncate_end(MIXED, 30), MIXED);
+        assert_eq!(truncate_end(MIXED, 5), "cafe\u{301}…");
+        assert_eq!(truncate_end(MIXED, 4), "caf…");
+        // A wide character that doesn't fit is dropped, not split.
+        assert_eq!(truncate_end(MIXED, 8), "cafe\u{301} 東…");
+        assert_eq!(truncate_end(MIXED, 7), "cafe\u{301} …");
+        assert_eq!(truncate_end("東京", 1), "…");
+        assert_eq!(truncate_end("abc", 0), "");
+        for max in 0..=width(MIXED) {
+            assert!(width(&truncate_end(MIXED, max)) <= max, "{max}");
+        }
+    }
+
+    #[test]
+    fn truncate_middle_keeps_both_ends() {
+        assert_eq!(truncate_middle("ssh host", 20), "ssh host");
+        assert_eq!(truncate_middle("ssh deploy@host uptime", 11), "ssh d…ptime");
+        assert_eq!(truncate_middle("abc", 0), "");
+        assert_eq!(truncate_middle("abc", 1), "…");
+        assert_eq!(width(&truncate_middle("abcdef", 5)), 5);
+    }
+
+    #[test]
+    fn truncate_middle_respects_wide_and_combining_chars() {
+        let out = truncate_middle("ssh 東京東京東京 ls", 10);
+        assert!(width(&out) <= 10);
+        assert!(out.starts_with("ssh "));
+        assert!(out.ends_with(" ls"));
+
+        assert_eq!(truncate_middle(MIXED, 9), "cafe\u{301}… db");
+        for max in 0..=width(MIXED) {
+            assert!(width(&truncate_middle(MIXED, max)) <= max, "{max}");
+        }
+    }
+
+    #[test]
+    fn boundaries_step_over_whole_graphemes() {
+        let text = "ae\u{301}東";
+        assert_eq!(next_boundary(text, 0), 1);
+        assert_eq!(next_boundary(text, 1), 4);
+        assert_eq!(next_boundary(text, 4), text.len());
+        assert_eq!(next_boundary(text, text.len()), text.len());
+        assert_eq!(prev_boundary(text, text.len()), 4);
+        assert_eq!(prev_boundary(text, 4), 1);
+        assert_eq!(prev_boundary(text, 0), 0);
+    }
+}
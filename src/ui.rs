@@ -1,17 +1,37 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 // SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
 
-use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Margin, Rect};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span, Text};
-use ratatui::widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::widgets::{
+    Block, Borders, Cell, Clear, List, ListItem, ListState, Paragraph, Row, Table, TableState, Wrap,
+};
 use ratatui::Frame;
+use unicode_width::UnicodeWidthStr;
 
-use crate::app::{App, ConfirmKind, FormKind, Mode, StatusKind};
-use crate::model::{Config, Host};
+use crate::app::{App, ConfirmKind, FormKind, ListRow, Mode, UnmaskedAction};
+use crate::model::{parse_hex_color, Config, FieldChange, Host};
+use crate::net::{PortProbe, Resolution};
+use crate::preflight::{self, Outcome};
+use crate::status::StatusKind;
+use crate::text;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Below this size the preview bar is dropped to leave room for the list.
+const PREVIEW_BAR_MIN_WIDTH: u16 = 50;
+const PREVIEW_BAR_MIN_HEIGHT: u16 = 18;
+
+/// Host table columns: name, target, tags.
+const LIST_COLUMNS: [Constraint; 3] = [
+    Constraint::Percentage(30),
+    Constraint::Percentage(45),
+    Constraint::Percentage(25),
+];
+const LIST_HIGHLIGHT: &str = "□ ";
+const LIST_SPACING: u16 = 2;
+
 #[derive(Clone, Copy)]
 pub struct Theme {
     pub bg: Color,
@@ -20,6 +40,8 @@ pub struct Theme {
     pub accent_dim: Color,
     pub warn: Color,
     pub error: Color,
+    /// Status bar background while an error is shown.
+    pub error_bg: Color,
     pub text: Color,
     pub muted: Color,
 }
@@ -33,22 +55,54 @@ impl Default for Theme {
             accent_dim: Color::Rgb(60, 150, 140),
             warn: Color::Rgb(230, 185, 90),
             error: Color::Rgb(230, 110, 110),
+            error_bg: Color::Rgb(58, 22, 28),
             text: Color::Gray,
             muted: Color::DarkGray,
         }
     }
 }
 
+impl Theme {
+    /// The built-in colours with the config's `[theme]` over them; a
+    /// missing or malformed entry keeps its default.
+    pub fn from_config(config: &Config) -> Theme {
+        let mut theme = Theme::default();
+        let colors = &config.theme;
+        for (slot, value) in [
+            (&mut theme.accent, &colors.accent),
+            (&mut theme.bg, &colors.bg),
+            (&mut theme.panel, &colors.panel),
+            (&mut theme.warn, &colors.warn),
+            (&mut theme.error, &colors.error),
+            (&mut theme.text, &colors.text),
+            (&mut theme.muted, &colors.muted),
+        ] {
+            if let Some((r, g, b)) = value.as_deref().and_then(parse_hex_color) {
+                *slot = Color::Rgb(r, g, b);
+            }
+        }
+        theme
+    }
+}
+
 pub fn render(frame: &mut Frame, app: &App) {
-    let theme = Theme::default();
+    let theme = Theme::from_config(&app.config);
     let size = frame.size();
+    if matches!(app.mode, Mode::Launcher) {
+        render_launcher(frame, size, app, theme);
+        return;
+    }
 
+    let preview_bar = app.show_preview_bar
+        && size.width >= PREVIEW_BAR_MIN_WIDTH
+        && size.height >= PREVIEW_BAR_MIN_HEIGHT;
     let outer = Layout::default()
         .direction(Direction::Vertical)
         .constraints(
             [
                 Constraint::Length(3),
                 Constraint::Min(10),
+                Constraint::Length(if preview_bar { 1 } else { 0 }),
                 Constraint::Length(2),
             ]
             .as_ref(),
@@ -57,29 +111,266 @@ pub fn render(frame: &mut Frame, app: &App) {
 
     render_header(frame, outer[0], app, theme);
     render_body(frame, outer[1], app, theme);
-    render_status(frame, outer[2], app, theme);
+    if preview_bar {
+        render_preview_bar(frame, outer[2], app, theme);
+    }
+    render_status(frame, outer[3], app, theme);
 
     if let Some(confirm) = app.confirm.clone() {
         render_modal_confirm(frame, app, confirm, theme);
     }
 
     if let Some(form) = app.form.as_ref() {
-        render_modal_form(frame, form, &app.config, theme);
+        render_modal_form(frame, form, app, theme);
     }
 
     if app.show_help {
-        render_help(frame, theme);
+        render_help(frame, app, theme);
     }
 
     if matches!(app.mode, Mode::QuickConnect) {
         render_quickconnect(frame, app, theme);
     }
 
+    if matches!(app.mode, Mode::Watch) {
+        if let Some(watcher) = app.watch.as_ref() {
+            render_watch(frame, watcher, theme);
+        }
+    }
+
+    if matches!(app.mode, Mode::Problems) {
+        render_problems(frame, app, theme);
+    }
+
+    if matches!(app.mode, Mode::Bundle) {
+        render_bundle(frame, app, theme);
+    }
+
+    if matches!(app.mode, Mode::Bulk) {
+        render_bulk(frame, app, theme);
+    }
+
+    if matches!(app.mode, Mode::Explain) {
+        render_explain(frame, app, theme);
+    }
+
+    if matches!(app.mode, Mode::FullCommand) {
+        render_full_command(frame, app, theme);
+    }
+
+    if matches!(app.mode, Mode::Rules) {
+        render_rules(frame, app, theme);
+    }
+
+    if matches!(app.mode, Mode::TagFilter) {
+        render_tag_filter(frame, app, theme);
+    }
+
+    if matches!(app.mode, Mode::Transfer) {
+        render_transfer(frame, app, theme);
+    }
+
+    if matches!(app.mode, Mode::Settings) {
+        render_settings(frame, app, theme);
+    }
+
     if app.show_about {
         render_about(frame, theme);
     }
 }
 
+/// Compact pick-and-connect view: a full-width search box, the matches
+/// beneath it and one line for status or key hints.
+fn render_launcher(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .split(area);
+
+    let search = Paragraph::new(Line::from(vec![
+        Span::styled("> ", Style::default().fg(theme.accent)),
+        Span::styled(app.filter.as_str(), Style::default().fg(theme.text)),
+    ]))
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.accent))
+            .style(Style::default().bg(theme.panel)),
+    );
+    frame.render_widget(search, chunks[0]);
+    frame.set_cursor(
+        chunks[0].x + 3 + text::width(&app.filter) as u16,
+        chunks[0].y + 1,
+    );
+
+    let items: Vec<ListItem> = app
+        .filtered_indices
+        .iter()
+        .map(|idx| {
+            let host = &app.config.hosts[*idx];
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    app.display(&app.config.display_name(host)).into_owned(),
+                    Style::default().fg(theme.text).add_modifier(Modifier::BOLD),
+                ),
+                Span::raw("  "),
+                Span::styled(
+                    app.display(&host.display_label()).into_owned(),
+                    Style::default().fg(theme.muted),
+                ),
+            ]))
+        })
+        .collect();
+    let mut state = ListState::default();
+    if !app.filtered_indices.is_empty() {
+        state.select(Some(app.selected));
+    }
+    let list = List::new(items)
+        .style(Style::default().bg(theme.bg))
+        .highlight_style(
+            Style::default()
+                .fg(theme.accent)
+                .add_modifier(Modifier::BOLD),
+        )
+        .highlight_symbol("▸ ");
+    frame.render_stateful_widget(list, chunks[1], &mut state);
+
+    let (text, color) = match app.status.current() {
+        Some(status) if status.kind != StatusKind::Info => (
+            app.display(&status.text).into_owned(),
+            if status.kind == StatusKind::Error {
+                theme.error
+            } else {
+                theme.warn
+            },
+        ),
+        _ => (
+            "Enter connect · Alt+Enter full view · Esc quit".to_string(),
+            theme.muted,
+        ),
+    };
+    frame.render_widget(
+        Paragraph::new(text).style(Style::default().fg(color).bg(theme.bg)),
+        chunks[2],
+    );
+}
+
+/// Full-screen pane showing one run of the watched command.
+fn render_watch(frame: &mut Frame, watcher: &crate::watch::Watcher, theme: Theme) {
+    let area = frame.size();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .title(format!(
+            "watch · every {}s · {}",
+            watcher.interval.as_secs(),
+            watcher.label
+        ))
+        .style(Style::default().bg(theme.bg));
+
+    let mut header = Vec::new();
+    let body = match watcher.current() {
+        Some(run) => {
+            let (status, color) = match &run.result {
+                Ok(out) => match out.exit {
+                    Some(0) => ("exit 0".to_string(), theme.accent),
+                    Some(code) => (format!("exit {code}"), theme.warn),
+                    None => ("killed by signal".to_string(), theme.error),
+                },
+                Err(_) => ("failed".to_string(), theme.error),
+            };
+            header.push(Span::styled(
+                format!("{} UTC", crate::watch::clock(run.started)),
+                Style::default().fg(theme.text),
+            ));
+            header.push(Span::styled(" · ", Style::default().fg(theme.muted)));
+            header.push(Span::styled(
+                status,
+                Style::default().fg(color).add_modifier(Modifier::BOLD),
+            ));
+            header.push(Span::styled(
+                format!(
+                    " · {:.1}s · run {}/{}",
+                    run.took.as_secs_f32(),
+                    watcher.history_len() - watcher.offset,
+                    watcher.history_len()
+                ),
+                Style::default().fg(theme.muted),
+            ));
+            match &run.result {
+                Ok(out) => out.output.clone(),
+                Err(err) => err.clone(),
+            }
+        }
+        None => String::new(),
+    };
+    if watcher.is_running() {
+        header.push(Span::styled(
+            if header.is_empty() {
+                "first run…"
+            } else {
+                " · running…"
+            },
+            Style::default().fg(theme.accent_dim),
+        ));
+    }
+    if watcher.skipped > 0 {
+        header.push(Span::styled(
+            format!(" · {} skipped (still running)", watcher.skipped),
+            Style::default().fg(theme.warn),
+        ));
+    }
+
+    let mut lines = vec![
+        Line::from(header),
+        Line::from(Span::styled(
+            "j/k older/newer • +/- interval • Enter connect • Esc stop",
+            Style::default().fg(theme.muted),
+        )),
+        Line::from(""),
+    ];
+    lines.extend(
+        body.lines()
+            .map(|line| Line::from(Span::styled(line.to_string(), theme.text))),
+    );
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(Text::from(lines)).block(block), area);
+}
+
+/// First frame, drawn before the config has been read.
+pub fn render_loading(frame: &mut Frame, config_path: &std::path::Path) {
+    let theme = Theme::default();
+    let area = frame.size();
+    let text = Text::from(vec![
+        Line::from(Span::styled(
+            format!("sshdb v{VERSION}"),
+            Style::default()
+                .fg(theme.accent)
+                .add_modifier(Modifier::BOLD),
+        )),
+        Line::from(Span::styled(
+            format!("loading {}…", config_path.display()),
+            Style::default().fg(theme.muted),
+        )),
+    ]);
+    let paragraph = Paragraph::new(text)
+        .alignment(Alignment::Center)
+        .style(Style::default().bg(theme.bg));
+    let y = area.y + area.height.saturating_sub(2) / 2;
+    frame.render_widget(
+        paragraph,
+        Rect {
+            y,
+            height: area.height.saturating_sub(y - area.y).min(2),
+            ..area
+        },
+    );
+}
+
 fn render_header(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
     let header = Paragraph::new(Text::from(vec![Line::from(vec![
         Span::styled(
@@ -94,6 +385,35 @@ fn render_header(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
             format!("{} hosts", app.config.hosts.len()),
             Style::default().fg(theme.muted),
         ),
+        Span::styled(
+            match app.problems().len() {
+                0 => String::new(),
+                n => format!("  ! {n} problem{}", if n == 1 { "" } else { "s" }),
+            },
+            Style::default().fg(theme.warn),
+        ),
+        Span::styled(
+            if app.read_only.is_some() {
+                "  READ-ONLY (W: writable copy)"
+            } else {
+                ""
+            },
+            Style::default().fg(theme.warn).add_modifier(Modifier::BOLD),
+        ),
+        Span::styled(
+            if app.privacy { "  PRIVATE (P)" } else { "" },
+            Style::default().fg(theme.warn).add_modifier(Modifier::BOLD),
+        ),
+        Span::styled(
+            if app.tag_filter.is_empty() {
+                String::new()
+            } else {
+                format!("  tags: {} (Esc clears)", app.tag_filter.join(" + "))
+            },
+            Style::default()
+                .fg(theme.accent)
+                .add_modifier(Modifier::BOLD),
+        ),
         Span::raw("    "),
         Span::styled(
             "Enter",
@@ -193,8 +513,33 @@ fn render_list(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
                 })
                 .bg(theme.panel),
         )
-        .title("search");
+        .title({
+            let mode = if app.search_regex { " (regex)" } else { "" };
+            let mut title = format!("search{mode} · {}", app.search_scope.label());
+            if let Some(profile) = &app.config.active_profile {
+                title.push_str(&format!(" · profile {profile}"));
+            }
+            if app.workset_only {
+                title.push_str(&format!(" · workset ({})", app.workset.len()));
+            }
+            match app.health_filter {
+                Some(true) => title.push_str(" · reachable"),
+                Some(false) => title.push_str(" · unreachable"),
+                None => {}
+            }
+            if !app.filter.is_empty() {
+                let count = app.filtered_indices.len();
+                title.push_str(&format!(
+                    " · {count} {}",
+                    if count == 1 { "match" } else { "matches" }
+                ));
+            }
+            title
+        });
 
+    // Borders, "/ " and a cell for the cursor after the text.
+    let room = usize::from(inner[0].width.saturating_sub(5));
+    let filter = text::truncate_start(&app.filter, room);
     let search_text = Paragraph::new(Line::from(vec![
         Span::styled("/", Style::default().fg(theme.muted)),
         Span::raw(" "),
@@ -202,7 +547,7 @@ fn render_list(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
             if app.filter.is_empty() {
                 "type to filter".to_string()
             } else {
-                app.filter.clone()
+                filter.clone()
             },
             Style::default().fg(theme.text),
         ),
@@ -211,33 +556,91 @@ fn render_list(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
     .block(search_block);
     frame.render_widget(search_text, inner[0]);
     if matches!(app.mode, Mode::Search) {
-        let cursor_x = inner[0].x + 1 + 2 + app.filter.len() as u16;
+        let cursor_x = inner[0].x + 1 + 2 + text::width(&filter) as u16;
         let cursor_y = inner[0].y + 1;
         frame.set_cursor(cursor_x, cursor_y);
     }
 
-    let rows: Vec<Row> = app
-        .filtered_indices
-        .iter()
-        .map(|idx| {
-            let host = &app.config.hosts[*idx];
-            let tags = if host.tags.is_empty() {
-                "∙".to_string()
-            } else {
-                host.tags.join(" ")
-            };
-            Row::new(vec![
-                Cell::from(host.name.clone())
-                    .style(Style::default().fg(theme.text).add_modifier(Modifier::BOLD)),
-                Cell::from(host.display_label()).style(Style::default().fg(theme.muted)),
-                Cell::from(tags).style(Style::default().fg(theme.accent_dim)),
+    let widths = list_column_widths(inner[1].width.saturating_sub(2));
+    let fit = |text: String, column: usize| {
+        text::truncate_end(&app.display(&text), usize::from(widths[column]))
+    };
+    let host_row = |idx: usize| {
+        let host = &app.config.hosts[idx];
+        let tags = if host.tags.is_empty() {
+            "∙".to_string()
+        } else {
+            host.tags.join(" ")
+        };
+        let mut name = app.config.display_name(host);
+        if host.is_jump_host() {
+            name = format!("⇢ {name}");
+        }
+        if host.overlay.is_some() {
+            name = format!("◇ {name}");
+        }
+        if host
+            .remote_command
+            .as_deref()
+            .is_some_and(|command| !command.trim().is_empty())
+        {
+            name = format!("» {name}");
+        }
+        if host.pinned {
+            name = format!("★ {name}");
+        }
+        if app.workset.contains(&host.id()) {
+            name = format!("⚑ {name}");
+        }
+        if app.marked.contains(&host.id()) {
+            name = format!("● {name}");
+        }
+        Row::new(vec![
+            Cell::from(fit(name, 0))
+                .style(Style::default().fg(theme.text).add_modifier(Modifier::BOLD)),
+            Cell::from(fit(host.display_label(), 1)).style(Style::default().fg(theme.muted)),
+            Cell::from(fit(tags, 2)).style(Style::default().fg(theme.accent_dim)),
+        ])
+    };
+
+    // Only the rows on screen are built: inside the borders, below the
+    // header and its margin.
+    let height = usize::from(inner[1].height.saturating_sub(4));
+    app.set_list_height(height);
+    let offset = app.list_window(height);
+    // Headings (the jump hosts divider, the tag groups) are rows of their
+    // own that can't be selected.
+    let headings = app.list_headings();
+    let total = app.filtered_indices.len() + headings.len();
+    let rows: Vec<Row> = (offset..total.min(offset + height))
+        .map(|row| match app.list_row(&headings, row) {
+            ListRow::Host(idx) => host_row(idx),
+            ListRow::JumpHosts(count) => {
+                let (arrow, hint) = if app.jump_hosts_expanded {
+                    ("▾", "J to hide")
+                } else {
+                    ("▸", "J to show")
+                };
+                Row::new(vec![
+                    Cell::from(fit(format!("{arrow} jump hosts"), 0)),
+                    Cell::from(fit(format!("({count}) {hint}"), 1)),
+                ])
+                .style(Style::default().fg(theme.muted))
+            }
+            ListRow::Tag(tag, count) => Row::new(vec![
+                Cell::from(fit(
+                    format!("▾ {}", tag.as_deref().unwrap_or("(no tags)")),
+                    0,
+                )),
+                Cell::from(fit(format!("({count})"), 1)),
             ])
+            .style(Style::default().fg(theme.muted)),
         })
         .collect();
 
     let mut state = TableState::default();
-    if !app.filtered_indices.is_empty() {
-        state.select(Some(app.selected));
+    if !rows.is_empty() && !app.filtered_indices.is_empty() {
+        state.select(Some(app.selected_row() - offset));
     }
 
     let header = Row::new(vec![
@@ -253,36 +656,64 @@ fn render_list(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
     )
     .bottom_margin(1);
 
-    let table = Table::new(
-        rows,
-        [
-            Constraint::Percentage(30),
-            Constraint::Percentage(45),
-            Constraint::Percentage(25),
-        ],
-    )
-    .header(header)
-    .block(
-        Block::default()
-            .borders(Borders::ALL)
-            .title("hosts")
-            .border_style(Style::default().fg(theme.accent_dim))
-            .style(Style::default().bg(theme.panel)),
-    )
-    .highlight_style(
-        Style::default()
-            .fg(theme.accent)
-            .add_modifier(Modifier::BOLD),
-    )
-    .highlight_symbol("□ ")
-    .column_spacing(2);
+    let table = Table::new(rows, LIST_COLUMNS)
+        .header(header)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("hosts")
+                .border_style(Style::default().fg(theme.accent_dim))
+                .style(Style::default().bg(theme.panel)),
+        )
+        .highlight_style(
+            Style::default()
+                .fg(theme.accent)
+                .add_modifier(Modifier::BOLD),
+        )
+        .highlight_symbol(LIST_HIGHLIGHT)
+        .column_spacing(LIST_SPACING);
 
     frame.render_stateful_widget(table, inner[1], &mut state);
+
+    // Tells a search that matches nothing apart from an empty config.
+    if app.filtered_indices.is_empty() && !app.filter.is_empty() {
+        let body = inner[1].inner(&Margin {
+            horizontal: 1,
+            vertical: 1,
+        });
+        let row = Rect {
+            y: body.y + body.height / 2,
+            height: body.height.min(1),
+            ..body
+        };
+        frame.render_widget(
+            Paragraph::new(format!("no hosts match '{}'", app.filter))
+                .style(Style::default().fg(theme.muted))
+                .alignment(Alignment::Center),
+            row,
+        );
+    }
+}
+
+/// Widths of the host table columns inside `width` columns, split the way
+/// the table widget splits them, so cells can be cut to fit beforehand.
+fn list_column_widths(width: u16) -> Vec<u16> {
+    let [_, columns] = Layout::horizontal([
+        Constraint::Length(LIST_HIGHLIGHT.width() as u16),
+        Constraint::Fill(0),
+    ])
+    .areas(Rect::new(0, 0, width, 1));
+    Layout::horizontal(LIST_COLUMNS)
+        .spacing(LIST_SPACING)
+        .split(columns)
+        .iter()
+        .map(|rect| rect.width)
+        .collect()
 }
 
 fn render_details(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
     let content = if let Some(host) = app.current_host() {
-        build_details(host, app, theme)
+        build_details(host, app, theme, area)
     } else {
         Paragraph::new("No host selected")
             .style(Style::default().fg(theme.muted))
@@ -298,28 +729,78 @@ fn render_details(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
     frame.render_widget(content, area);
 }
 
-fn build_details<'a>(host: &'a Host, app: &'a App, theme: Theme) -> Paragraph<'a> {
+fn build_details<'a>(host: &'a Host, app: &'a App, theme: Theme, area: Rect) -> Paragraph<'a> {
     let mut lines: Vec<Line> = Vec::new();
+    let warnings = [app.local_address_warning(host), app.ip_family_warning(host)];
+    for warning in warnings.into_iter().flatten() {
+        lines.push(Line::from(Span::styled(
+            format!("⚠ {warning}"),
+            Style::default().fg(theme.warn).add_modifier(Modifier::BOLD),
+        )));
+    }
+    // A one-line description sits next to the name; longer notes get a
+    // block of their own below it.
+    let notes = host
+        .description
+        .as_deref()
+        .filter(|description| description.contains('\n'));
     lines.push(Line::from(vec![
         Span::styled(
-            &host.name,
+            app.config.display_name(host),
             Style::default()
                 .fg(theme.accent)
                 .add_modifier(Modifier::BOLD),
         ),
         Span::raw("  "),
         Span::styled(
-            host.description
-                .clone()
-                .unwrap_or_else(|| "no description".into()),
+            match (notes, &host.description) {
+                (Some(_), _) => String::new(),
+                (None, Some(description)) => description.clone(),
+                (None, None) => "no description".into(),
+            },
             Style::default().fg(theme.text),
         ),
     ]));
+    if let Some(notes) = notes {
+        lines.extend(
+            notes
+                .lines()
+                .map(|line| Line::from(Span::styled(line, Style::default().fg(theme.text)))),
+        );
+        lines.push(Line::from(""));
+    }
     lines.push(Line::from(vec![
         Span::styled("host", Style::default().fg(theme.muted)),
         Span::raw(": "),
         Span::styled(&host.address, Style::default().fg(theme.text)),
     ]));
+    if let Some(resolution) = app.resolution(host) {
+        let (value, color) = match resolution {
+            Resolution::Pending => ("resolving…".to_string(), theme.muted),
+            Resolution::Failed => ("does not resolve here".to_string(), theme.muted),
+            Resolution::Resolved(addresses) => {
+                let mut addresses = addresses.to_vec();
+                addresses.sort_by_key(|ip| ip.is_ipv6());
+                let shown: Vec<String> = addresses.iter().map(ToString::to_string).collect();
+                (shown.join(", "), theme.text)
+            }
+        };
+        lines.push(Line::from(vec![
+            Span::styled("resolves to", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(value, Style::default().fg(color)),
+        ]));
+    }
+    if let Some(family) = host.ip_family {
+        lines.push(Line::from(vec![
+            Span::styled("ip family", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(
+                format!("{} only ({})", family.label(), family.flag()),
+                Style::default().fg(theme.text),
+            ),
+        ]));
+    }
     if let Some(user) = &host.user {
         lines.push(Line::from(vec![
             Span::styled("user", Style::default().fg(theme.muted)),
@@ -334,16 +815,57 @@ fn build_details<'a>(host: &'a Host, app: &'a App, theme: Theme) -> Paragraph<'a
             Span::styled(port.to_string(), Style::default().fg(theme.text)),
         ]));
     }
+    if let Some((probe, age)) = app.reachability(host) {
+        let (color, value) = match probe {
+            PortProbe::Pending => (theme.muted, "checking…".to_string()),
+            PortProbe::Open(took) => (
+                theme.accent,
+                format!(
+                    "reachable in {}ms ({})",
+                    took.as_millis(),
+                    preflight::checked(age)
+                ),
+            ),
+            PortProbe::Closed(reason) => (
+                theme.error,
+                format!("unreachable: {reason} ({})", preflight::checked(age)),
+            ),
+        };
+        lines.push(Line::from(vec![
+            Span::styled("ssh port", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled("● ", Style::default().fg(color)),
+            Span::styled(value, Style::default().fg(theme.text)),
+        ]));
+    }
     let key_display = if !host.key_paths.is_empty() {
-        Some(host.key_paths.join(", "))
+        Some(host.key_paths.clone())
     } else {
-        app.config.default_key.clone()
+        app.config.default_key.clone().map(|key| vec![key])
     };
-    if let Some(key) = key_display {
+    if let Some(keys) = key_display {
+        // A hint only: ssh reports the missing file itself on connect.
+        let missing = app.missing_keys(host);
+        let key = keys
+            .into_iter()
+            .map(|key| {
+                if missing.contains(&key) {
+                    format!("{key} (missing)")
+                } else {
+                    key
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let color = if missing.is_empty() {
+            theme.text
+        } else {
+            theme.warn
+        };
         lines.push(Line::from(vec![
             Span::styled("keys", Style::default().fg(theme.muted)),
             Span::raw(": "),
-            Span::styled(key, Style::default().fg(theme.text)),
+            Span::styled(key, Style::default().fg(color)),
         ]));
     }
     if host.prefer_public_key_auth {
@@ -356,11 +878,36 @@ fn build_details<'a>(host: &'a Host, app: &'a App, theme: Theme) -> Paragraph<'a
             ),
         ]));
     }
+    if host.multiplex {
+        lines.push(Line::from(vec![
+            Span::styled("multiplex", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(
+                "on (ControlMaster=auto, persists 60s)",
+                Style::default().fg(theme.text),
+            ),
+        ]));
+    }
+    if host.forward_agent || host.forward_x11 {
+        let forwarded: Vec<&str> = [
+            (host.forward_agent, "agent (-A)"),
+            (host.forward_x11, "X11 (-X)"),
+        ]
+        .into_iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, what)| what)
+        .collect();
+        lines.push(Line::from(vec![
+            Span::styled("forwarding", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(forwarded.join(", "), Style::default().fg(theme.text)),
+        ]));
+    }
     if let Some(bastion) = &host.bastion {
-        let bastion_display = if let Some(bh) = app.config.find_host(bastion) {
-            format!("{} ({})", bastion, bh.display_label())
-        } else {
-            format!("{} (not found)", bastion)
+        let bastion_display = match app.config.resolve_host(bastion, host.group.as_deref()) {
+            Ok(Some(bh)) => format!("{} ({})", bastion, bh.display_label()),
+            Ok(None) => format!("{} (not found)", bastion),
+            Err(_) => format!("{} (ambiguous)", bastion),
         };
         lines.push(Line::from(vec![
             Span::styled("bastion", Style::default().fg(theme.muted)),
@@ -368,63 +915,327 @@ fn build_details<'a>(host: &'a Host, app: &'a App, theme: Theme) -> Paragraph<'a
             Span::styled(bastion_display, Style::default().fg(theme.accent_dim)),
         ]));
     }
-    if let Some(rc) = &host.remote_command {
+    if host.is_jump_host() {
+        let used_by = app.config.bastion_referrers(&host.id()).len();
         lines.push(Line::from(vec![
-            Span::styled("remote", Style::default().fg(theme.muted)),
+            Span::styled("role", Style::default().fg(theme.muted)),
             Span::raw(": "),
-            Span::styled(rc, Style::default().fg(theme.text)),
+            Span::styled(
+                format!("jump host (bastion of {used_by})"),
+                Style::default().fg(theme.accent_dim),
+            ),
         ]));
     }
-    if !host.tags.is_empty() {
+    if let Some(last) = app.last_errors.get(&host.id()) {
         lines.push(Line::from(vec![
-            Span::styled("tags", Style::default().fg(theme.muted)),
+            Span::styled("last error", Style::default().fg(theme.muted)),
             Span::raw(": "),
-            Span::styled(host.tags.join(", "), Style::default().fg(theme.accent_dim)),
+            Span::styled(
+                last.diagnosis
+                    .as_ref()
+                    .map_or_else(|| last.message.clone(), ToString::to_string),
+                Style::default().fg(theme.error),
+            ),
         ]));
     }
-
-    Paragraph::new(Text::from(lines))
-        .style(Style::default().bg(theme.panel))
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .border_style(Style::default().fg(theme.accent))
-                .title("details"),
-        )
-}
-
-fn render_status(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
-    let (text, color) = match &app.status {
-        Some(status) => {
-            let c = match status.kind {
-                StatusKind::Info => theme.accent,
-                StatusKind::Warn => theme.warn,
-                StatusKind::Error => theme.error,
-            };
-            (status.text.clone(), c)
-        }
-        None => ("Ready".into(), theme.muted),
-    };
-
-    let msg = format!(
-        "{}   config: {}   dry-run: {}",
-        text,
-        app.config_path.display(),
-        if app.dry_run { "on" } else { "off" }
-    );
-
-    let paragraph = Paragraph::new(msg)
-        .alignment(Alignment::Left)
-        .style(Style::default().fg(color).bg(theme.bg))
-        .block(Block::default().borders(Borders::NONE));
-    frame.render_widget(paragraph, area);
+    if let Some(source) = &host.overlay {
+        lines.push(Line::from(vec![
+            Span::styled("source", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(
+                format!("overlay {source} (this session only, not saved)"),
+                Style::default().fg(theme.warn),
+            ),
+        ]));
+    }
+    if let Some(note) = &host.maintenance {
+        lines.push(Line::from(vec![
+            Span::styled("maintenance", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(note, Style::default().fg(theme.text)),
+        ]));
+    }
+    if let Some(window) = &host.freeze_window {
+        let valid = crate::freeze::parse(window)
+            .and_then(|_| crate::freeze::Zone::parse(app.config.freeze_timezone.as_deref()));
+        let (state, color) = match valid {
+            Err(err) => (format!(" (invalid: {err:#})"), theme.error),
+            Ok(_) if app.active_freeze(host).is_some() => (" (in effect now)".into(), theme.warn),
+            Ok(_) => (String::new(), theme.text),
+        };
+        lines.push(Line::from(vec![
+            Span::styled("freeze", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(format!("{window}{state}"), Style::default().fg(color)),
+        ]));
+    }
+    if let Some(time) = app.host_local_time(host) {
+        let (label, value, color) = match time {
+            Ok(time) => ("local time", time, theme.text),
+            Err(err) => ("timezone", format!("invalid: {err:#}"), theme.error),
+        };
+        lines.push(Line::from(vec![
+            Span::styled(label, Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(value, Style::default().fg(color)),
+        ]));
+    }
+    if let Some(hours) = &host.quiet_hours {
+        let (state, color) = match crate::freeze::parse(hours) {
+            Err(err) => (format!(" (invalid: {err:#})"), theme.error),
+            Ok(_) if app.quiet_hours_warning(host).is_some() => (" (now)".into(), theme.warn),
+            Ok(_) => (String::new(), theme.text),
+        };
+        lines.push(Line::from(vec![
+            Span::styled("quiet hours", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(format!("{hours}{state}"), Style::default().fg(color)),
+        ]));
+    }
+    if crate::hostkey::is_pinned(host) {
+        let pinned = match crate::hostkey::validate(host) {
+            Err(err) => (format!("invalid: {err:#}"), theme.error),
+            Ok(()) => match (&host.pinned_hostkey, &host.pinned_fingerprint) {
+                (Some(key), _) => match crate::hostkey::parse_key(key) {
+                    Ok(key) => (format!("{} {}", key.kind, key.fingerprint()), theme.text),
+                    Err(err) => (format!("invalid: {err:#}"), theme.error),
+                },
+                (None, Some(fingerprint)) => (
+                    format!("{fingerprint} (checked via ssh-keyscan)"),
+                    theme.text,
+                ),
+                (None, None) => (String::new(), theme.text),
+            },
+        };
+        lines.push(Line::from(vec![
+            Span::styled("host key", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(pinned.0, Style::default().fg(pinned.1)),
+        ]));
+    }
+    if let Some(template) = &host.command_template {
+        lines.push(Line::from(vec![
+            Span::styled("template", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(template, Style::default().fg(theme.text)),
+        ]));
+    }
+    if let Some(request_tty) = host.request_tty {
+        lines.push(Line::from(vec![
+            Span::styled("tty", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(
+                if request_tty {
+                    "forced (-t)"
+                } else {
+                    "off (-T)"
+                },
+                Style::default().fg(theme.text),
+            ),
+        ]));
+    }
+    if let Some(rc) = &host.remote_command {
+        lines.push(Line::from(vec![
+            Span::styled("remote", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(rc, Style::default().fg(theme.text)),
+        ]));
+    }
+    if !host.vars.is_empty() {
+        lines.push(Line::from(vec![
+            Span::styled("vars", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(
+                host.vars
+                    .iter()
+                    .map(|(key, value)| format!("{key}={value}"))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                Style::default().fg(theme.text),
+            ),
+        ]));
+    }
+    if !host.tags.is_empty() {
+        lines.push(Line::from(vec![
+            Span::styled("tags", Style::default().fg(theme.muted)),
+            Span::raw(": "),
+            Span::styled(host.tags.join(", "), Style::default().fg(theme.accent_dim)),
+        ]));
+    }
+    lines.push(Line::from(vec![
+        Span::styled("connections", Style::default().fg(theme.muted)),
+        Span::raw(": "),
+        Span::styled(
+            host.connect_count.to_string(),
+            Style::default().fg(theme.text),
+        ),
+    ]));
+
+    let lines = masked(app, lines);
+    // Rows once wrapped, counting a line that doesn't fit as cut at the
+    // width; word wrapping can take a row more, never fewer.
+    let width = usize::from(area.width.saturating_sub(2)).max(1);
+    let rows: usize = lines
+        .iter()
+        .map(|line| line.width().div_ceil(width).max(1))
+        .sum();
+    let height = usize::from(area.height.saturating_sub(2));
+    app.set_details_max_scroll(u16::try_from(rows.saturating_sub(height)).unwrap_or(u16::MAX));
+    Paragraph::new(Text::from(lines))
+        .style(Style::default().bg(theme.panel))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(theme.accent))
+                .title("details"),
+        )
+        .wrap(Wrap { trim: false })
+        .scroll((app.details_offset(), 0))
+}
+
+/// `lines` with every span passed through `App::display`.
+fn masked<'a>(app: &App, lines: Vec<Line<'a>>) -> Vec<Line<'a>> {
+    if !app.privacy {
+        return lines;
+    }
+    lines
+        .into_iter()
+        .map(|mut line| {
+            for span in &mut line.spans {
+                if let std::borrow::Cow::Owned(text) = app.display(&span.content) {
+                    span.content = text.into();
+                }
+            }
+            line
+        })
+        .collect()
+}
+
+/// Said next to every masked command preview.
+const MASKED_PREVIEW: &str = "⚠ privacy mode: the command run uses the real values, not these";
+
+fn render_preview_bar(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
+    let Some(preview) = app.selected_preview() else {
+        return;
+    };
+    let preview = app.display(&preview);
+    let prefix = if app.privacy { "▸ (masked) " } else { "▸ " };
+    let room = usize::from(area.width).saturating_sub(prefix.width());
+    let line = Line::from(vec![
+        Span::styled(prefix, Style::default().fg(theme.muted)),
+        Span::styled(
+            text::truncate_middle(&preview, room),
+            Style::default().fg(theme.accent_dim),
+        ),
+    ]);
+    frame.render_widget(
+        Paragraph::new(line).style(Style::default().bg(theme.bg)),
+        area,
+    );
+}
+
+fn render_status(frame: &mut Frame, area: Rect, app: &App, theme: Theme) {
+    let (text, color, bg) = match app.status.current() {
+        Some(status) => match status.kind {
+            StatusKind::Info => (
+                app.display(&status.text).into_owned(),
+                theme.accent,
+                theme.bg,
+            ),
+            StatusKind::Warn => (app.display(&status.text).into_owned(), theme.warn, theme.bg),
+            StatusKind::Error => {
+                let mark = if unicode_glyphs() { "✗" } else { "!" };
+                let mut text = format!("{mark} {}  (Esc to dismiss)", app.display(&status.text));
+                if app.status.queued() > 0 {
+                    text.push_str(&format!(" +{} more", app.status.queued()));
+                }
+                (text, theme.error, theme.error_bg)
+            }
+        },
+        None => ("Ready".into(), theme.muted, theme.bg),
+    };
+
+    let generation = match app.generation {
+        0 => String::new(),
+        n => format!(" (gen {n})"),
+    };
+    let msg = format!(
+        "{}   config: {}{}   undo: {} available   dry-run: {}",
+        text,
+        app.config_path.display(),
+        generation,
+        app.history.len(),
+        if app.dry_run { "on" } else { "off" }
+    );
+    let msg = text::truncate_end(&msg, usize::from(area.width));
+
+    let paragraph = Paragraph::new(msg)
+        .alignment(Alignment::Left)
+        .style(Style::default().fg(color).bg(bg))
+        .block(Block::default().borders(Borders::NONE));
+    frame.render_widget(paragraph, area);
+}
+
+/// Whether the locale promises UTF-8; otherwise markers fall back to ASCII.
+fn unicode_glyphs() -> bool {
+    static UTF8: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *UTF8.get_or_init(|| {
+        ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .map(|value| {
+                let value = value.to_ascii_lowercase();
+                value.contains("utf-8") || value.contains("utf8")
+            })
+            // No locale at all is usually a modern terminal with defaults.
+            .unwrap_or(true)
+    })
 }
 
 fn render_modal_confirm(frame: &mut Frame, app: &App, confirm: ConfirmKind, theme: Theme) {
-    let area = centered_rect_clamped(68, 9, frame.size());
+    let freeze = match confirm {
+        ConfirmKind::Connect { .. } => app.current_freeze(),
+        _ => None,
+    };
+    let quiet =
+        matches!(confirm, ConfirmKind::Connect { .. }) && app.current_quiet_hours().is_some();
+    let checklist = match confirm {
+        ConfirmKind::Connect { .. } => app.preflight().unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    let presets = match confirm {
+        ConfirmKind::Connect { .. } => app.current_host().map_or(0, |h| h.command_presets.len()),
+        _ => 0,
+    };
+    let height = match &confirm {
+        ConfirmKind::UndoPastReload { changes } => {
+            8 + changes.len().clamp(1, UNDO_PREVIEW_ROWS + 1) as u16
+        }
+        ConfirmKind::PickBastion { hops, .. } => 6 + hops.len() as u16,
+        ConfirmKind::Delete { dependents } if !dependents.is_empty() => {
+            8 + dependents.len().min(UNDO_PREVIEW_ROWS + 1) as u16
+        }
+        _ => {
+            11 + 2 * u16::from(freeze.is_some())
+                + u16::from(app.privacy)
+                + u16::from(quiet)
+                + u16::from(presets > 0)
+                + preflight_height(&checklist)
+        }
+    };
+    let area = modal_area(68, height, CONFIRM_MIN, frame.size());
     let title = match &confirm {
-        ConfirmKind::Delete => "delete host?",
+        ConfirmKind::Delete { .. } => "delete host?",
         ConfirmKind::Connect { .. } => "connect with optional remote cmd",
+        ConfirmKind::ConnectAs { .. } => "connect once as user",
+        ConfirmKind::Rename { .. } => "rename host",
+        ConfirmKind::UndoPastReload { .. } => "undo past reload?",
+        ConfirmKind::SeriesAddress { .. } => "next address too?",
+        ConfirmKind::SuggestJumpHosts { .. } => "mark as jump hosts?",
+        ConfirmKind::Unmasked(_) => "use real values?",
+        ConfirmKind::RemoteCommand { .. } => "run the stored remote command?",
+        ConfirmKind::PickBastion { .. } => "connect to which bastion?",
     };
     let block = Block::default()
         .borders(Borders::ALL)
@@ -433,13 +1244,72 @@ fn render_modal_confirm(frame: &mut Frame, app: &App, confirm: ConfirmKind, them
         .style(Style::default().bg(theme.panel));
 
     let content = match confirm {
-        ConfirmKind::Delete => Paragraph::new("Press y/Enter to delete, Esc to cancel.")
-            .style(Style::default().fg(theme.warn))
+        ConfirmKind::Delete { dependents } if dependents.is_empty() => {
+            Paragraph::new("Press y/Enter to delete, Esc to cancel.")
+                .style(Style::default().fg(theme.warn))
+                .block(block)
+                .alignment(Alignment::Center)
+        }
+        ConfirmKind::Delete { dependents } => delete_bastion_modal(dependents, block, theme),
+        ConfirmKind::ConnectAs { user } => connect_as_modal(app, user, block, theme),
+        ConfirmKind::Rename { name } => rename_modal(app, name, block, theme),
+        ConfirmKind::UndoPastReload { changes } => undo_modal(changes, block, theme),
+        ConfirmKind::SeriesAddress { next, address } => {
+            series_address_modal(app, &next, address, block, theme)
+        }
+        ConfirmKind::SuggestJumpHosts { ids } => suggest_jump_hosts_modal(app, ids, block, theme),
+        ConfirmKind::PickBastion { hops, selected } => {
+            pick_bastion_modal(app, &hops, selected, block, theme)
+        }
+        ConfirmKind::RemoteCommand { command } => Paragraph::new(vec![
+            Line::from(vec![
+                Span::styled("will run: ", Style::default().fg(theme.warn)),
+                Span::styled(
+                    app.display(&command).into_owned(),
+                    Style::default().fg(theme.text).add_modifier(Modifier::BOLD),
+                ),
+            ]),
+            Line::from(""),
+            Line::from(Span::styled(
+                "Enter to proceed, e to connect without it, Esc to cancel",
+                Style::default().fg(theme.muted),
+            )),
+        ])
+        .wrap(Wrap { trim: true })
+        .block(block)
+        .alignment(Alignment::Center),
+        ConfirmKind::Unmasked(action) => {
+            let what = match action {
+                UnmaskedAction::CopyConnection => "The copied connection string",
+                UnmaskedAction::ExportBundle => "The exported bundle",
+            };
+            Paragraph::new(vec![
+                Line::from(Span::styled(
+                    format!("Privacy mode is on. {what} has the real addresses and key paths."),
+                    Style::default().fg(theme.warn),
+                )),
+                Line::from(""),
+                Line::from(Span::styled(
+                    "Press y/Enter to go ahead, Esc to cancel.",
+                    Style::default().fg(theme.muted),
+                )),
+            ])
+            .wrap(Wrap { trim: true })
             .block(block)
-            .alignment(Alignment::Center),
-        ConfirmKind::Connect { extra_cmd } => {
-            let preview = app
-                .current_host()
+            .alignment(Alignment::Center)
+        }
+        ConfirmKind::Connect {
+            extra_cmd,
+            request_tty,
+            freeze_armed,
+        } => {
+            let host = app.current_host().map(|h| {
+                let mut host = h.clone();
+                host.request_tty = request_tty;
+                host
+            });
+            let preview = host
+                .as_ref()
                 .map(|h| {
                     crate::ssh::command_preview(
                         h,
@@ -449,23 +1319,88 @@ fn render_modal_confirm(frame: &mut Frame, app: &App, confirm: ConfirmKind, them
                     )
                 })
                 .unwrap_or_else(|| "ssh ...".to_string());
-            let lines = vec![
+            let needs_tty = host
+                .as_ref()
+                .is_some_and(|h| crate::ssh::lacks_needed_tty(h, Some(&extra_cmd)));
+            let tty = match request_tty {
+                None => "auto",
+                Some(true) => "-t",
+                Some(false) => "-T",
+            };
+            let mut lines = Vec::new();
+            if let Some(window) = freeze {
+                lines.push(Line::from(Span::styled(
+                    format!("⚠ inside change freeze window ({window})"),
+                    Style::default().fg(theme.warn).add_modifier(Modifier::BOLD),
+                )));
+                lines.push(Line::from(Span::styled(
+                    if freeze_armed {
+                        "  Enter again connects anyway"
+                    } else {
+                        "  Connecting takes Enter twice"
+                    },
+                    Style::default().fg(theme.warn),
+                )));
+            }
+            if let Some(warning) = app.current_quiet_hours() {
+                lines.push(Line::from(Span::styled(
+                    format!("⚠ {warning}"),
+                    Style::default().fg(theme.warn).add_modifier(Modifier::BOLD),
+                )));
+            }
+            let warnings = app
+                .current_host()
+                .map(|h| [app.local_address_warning(h), app.ip_family_warning(h)]);
+            for warning in warnings.into_iter().flatten().flatten() {
+                lines.push(Line::from(Span::styled(
+                    format!("⚠ {warning}"),
+                    Style::default().fg(theme.warn).add_modifier(Modifier::BOLD),
+                )));
+            }
+            lines.extend(preflight_lines(app, &checklist, theme));
+            lines.push(Line::from(vec![
+                Span::styled(
+                    "Remote command (optional): ",
+                    Style::default().fg(theme.muted),
+                ),
+                Span::styled(extra_cmd, Style::default().fg(theme.text)),
+            ]));
+            if presets > 0 {
+                lines.push(Line::from(Span::styled(
+                    format!("  ↑/↓ picks one of {presets} command presets"),
+                    Style::default().fg(theme.muted),
+                )));
+            }
+            lines.extend([
                 Line::from(vec![
-                    Span::styled(
-                        "Remote command (optional): ",
-                        Style::default().fg(theme.muted),
-                    ),
-                    Span::styled(extra_cmd, Style::default().fg(theme.text)),
+                    Span::styled("TTY: ", Style::default().fg(theme.muted)),
+                    Span::styled(tty, Style::default().fg(theme.text)),
+                    Span::styled(" (Ctrl+T)", Style::default().fg(theme.muted)),
                 ]),
                 Line::from(vec![
                     Span::styled("Preview: ", Style::default().fg(theme.muted)),
-                    Span::styled(preview, Style::default().fg(theme.accent)),
+                    Span::styled(
+                        app.display(&preview).into_owned(),
+                        Style::default().fg(theme.accent),
+                    ),
                 ]),
-                Line::from(vec![Span::styled(
-                    "Enter to connect, Esc to cancel",
-                    Style::default().fg(theme.muted),
-                )]),
-            ];
+            ]);
+            if app.privacy {
+                lines.push(Line::from(Span::styled(
+                    MASKED_PREVIEW,
+                    Style::default().fg(theme.warn),
+                )));
+            }
+            if needs_tty {
+                lines.push(Line::from(Span::styled(
+                    "⚠ sudo/doas usually needs a TTY; press Ctrl+T to add -t",
+                    Style::default().fg(theme.warn),
+                )));
+            }
+            lines.extend([Line::from(vec![Span::styled(
+                "Enter to connect, Tab to watch (re-run every few seconds), Esc to cancel",
+                Style::default().fg(theme.muted),
+            )])]);
             Paragraph::new(Text::from(lines))
                 .wrap(Wrap { trim: true })
                 .block(block)
@@ -475,19 +1410,314 @@ fn render_modal_confirm(frame: &mut Frame, app: &App, confirm: ConfirmKind, them
     frame.render_widget(content, area);
 }
 
-fn render_modal_form(
-    frame: &mut Frame,
-    form: &crate::app::FormState,
-    config: &Config,
+fn preflight_height(checklist: &[preflight::CheckResult]) -> u16 {
+    if checklist.is_empty() {
+        0
+    } else {
+        checklist.len() as u16 + 2
+    }
+}
+
+/// The protected-host checklist: a marker, the check's name and its detail.
+fn preflight_lines(
+    app: &App,
+    checklist: &[preflight::CheckResult],
+    theme: Theme,
+) -> Vec<Line<'static>> {
+    if checklist.is_empty() {
+        return Vec::new();
+    }
+    let unicode = unicode_glyphs();
+    let mut lines = vec![Line::from(Span::styled(
+        "Pre-flight (protected host):",
+        Style::default().fg(theme.text).add_modifier(Modifier::BOLD),
+    ))];
+    for result in checklist {
+        let (mark, style) = match result.outcome {
+            Outcome::Pass => (
+                if unicode { "✓" } else { "+" },
+                Style::default().fg(theme.accent),
+            ),
+            Outcome::Fail => (if unicode { "✗" } else { "x" }, error_style(theme)),
+            Outcome::Unknown => ("?", Style::default().fg(theme.warn)),
+        };
+        lines.push(Line::from(vec![
+            Span::styled(format!(" {mark} "), style),
+            Span::styled(
+                format!("{:<8} ", result.check.name()),
+                Style::default().fg(theme.muted),
+            ),
+            Span::styled(
+                app.display(&result.detail).into_owned(),
+                Style::default().fg(theme.text),
+            ),
+        ]));
+    }
+    lines.push(Line::from(""));
+    lines
+}
+
+fn connect_as_modal<'a>(app: &App, user: String, block: Block<'a>, theme: Theme) -> Paragraph<'a> {
+    let preview = app
+        .current_host()
+        .map(|h| {
+            let mut host = h.clone();
+            if !user.trim().is_empty() {
+                host.user = Some(user.trim().to_string());
+            }
+            crate::ssh::command_preview(&host, &app.config, app.config.default_key.as_deref(), None)
+        })
+        .unwrap_or_else(|| "ssh ...".to_string());
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled("User: ", Style::default().fg(theme.muted)),
+            Span::styled(user, Style::default().fg(theme.text)),
+        ]),
+        Line::from(vec![
+            Span::styled("Preview: ", Style::default().fg(theme.muted)),
+            Span::styled(
+                app.display(&preview).into_owned(),
+                Style::default().fg(theme.accent),
+            ),
+        ]),
+    ];
+    if app.privacy {
+        lines.push(Line::from(Span::styled(
+            MASKED_PREVIEW,
+            Style::default().fg(theme.warn),
+        )));
+    }
+    lines.push(Line::from(vec![Span::styled(
+        "Tab completes known users • Enter to connect, Esc to cancel (nothing is saved)",
+        Style::default().fg(theme.muted),
+    )]));
+    Paragraph::new(Text::from(lines))
+        .wrap(Wrap { trim: true })
+        .block(block)
+}
+
+/// Reverted hosts listed before the rest are summarized.
+const UNDO_PREVIEW_ROWS: usize = 8;
+
+/// Deleting a host others connect through: who loses their bastion.
+fn delete_bastion_modal(dependents: Vec<String>, block: Block<'_>, theme: Theme) -> Paragraph<'_> {
+    let mut lines = vec![Line::from(Span::styled(
+        "It is the bastion of these hosts; deleting it clears their bastion, so they connect directly:",
+        Style::default().fg(theme.warn),
+    ))];
+    for name in dependents.iter().take(UNDO_PREVIEW_ROWS) {
+        lines.push(Line::from(Span::styled(
+            format!("  {name}"),
+            Style::default().fg(theme.text),
+        )));
+    }
+    if dependents.len() > UNDO_PREVIEW_ROWS {
+        lines.push(Line::from(Span::styled(
+            format!("  … and {} more", dependents.len() - UNDO_PREVIEW_ROWS),
+            Style::default().fg(theme.muted),
+        )));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "y/Enter: delete and clear their bastion • Esc: cancel",
+        Style::default().fg(theme.muted),
+    )));
+    Paragraph::new(Text::from(lines))
+        .wrap(Wrap { trim: true })
+        .block(block)
+}
+
+fn undo_modal(changes: Vec<String>, block: Block<'_>, theme: Theme) -> Paragraph<'_> {
+    let mut lines = vec![Line::from(Span::styled(
+        "The config was reloaded since this change. Undoing it also discards changes made outside this session:",
+        Style::default().fg(theme.warn),
+    ))];
+    if changes.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "  (no host differences)",
+            Style::default().fg(theme.muted),
+        )));
+    }
+    for change in changes.iter().take(UNDO_PREVIEW_ROWS) {
+        lines.push(Line::from(Span::styled(
+            format!("  {change}"),
+            Style::default().fg(theme.text),
+        )));
+    }
+    if changes.len() > UNDO_PREVIEW_ROWS {
+        lines.push(Line::from(Span::styled(
+            format!("  … and {} more", changes.len() - UNDO_PREVIEW_ROWS),
+            Style::default().fg(theme.muted),
+        )));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "y/Enter: undo anyway • c: forget older undo steps • Esc: cancel",
+        Style::default().fg(theme.muted),
+    )));
+    Paragraph::new(Text::from(lines))
+        .wrap(Wrap { trim: true })
+        .block(block)
+}
+
+fn series_address_modal<'a>(
+    app: &App,
+    next: &crate::model::Host,
+    address: String,
+    block: Block<'a>,
     theme: Theme,
-) {
-    let base_height = 20;
+) -> Paragraph<'a> {
+    let current = app
+        .current_host()
+        .map(|host| host.address.clone())
+        .unwrap_or_default();
+    let lines = vec![
+        Line::from(vec![
+            Span::styled("New host: ", Style::default().fg(theme.muted)),
+            Span::styled(next.name.clone(), Style::default().fg(theme.text)),
+        ]),
+        Line::from(vec![
+            Span::styled("Address: ", Style::default().fg(theme.muted)),
+            Span::styled(
+                format!("{current} → {address}"),
+                Style::default().fg(theme.accent),
+            ),
+        ]),
+        Line::from(Span::styled(
+            "IP addresses don't always follow the host numbering; check it's the right machine.",
+            Style::default().fg(theme.warn),
+        )),
+        Line::from(Span::styled(
+            format!("y/Enter: use {address} • n: keep {current} • Esc: cancel"),
+            Style::default().fg(theme.muted),
+        )),
+    ];
+    Paragraph::new(Text::from(lines))
+        .wrap(Wrap { trim: true })
+        .block(block)
+}
+
+fn suggest_jump_hosts_modal<'a>(
+    app: &App,
+    ids: Vec<String>,
+    block: Block<'a>,
+    theme: Theme,
+) -> Paragraph<'a> {
+    let used_by = |id: &String| app.config.bastion_referrers(id).len();
+    let hosts = ids
+        .iter()
+        .map(|id| match used_by(id) {
+            1 => format!("{id} (bastion of 1 host)"),
+            n => format!("{id} (bastion of {n} hosts)"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let lines = vec![
+        Line::from(Span::styled(hosts, Style::default().fg(theme.text))),
+        Line::from(Span::styled(
+            "Jump hosts move to a collapsed section at the bottom of the list (J) and come first in the bastion picker.",
+            Style::default().fg(theme.muted),
+        )),
+        Line::from(Span::styled(
+            "y/Enter: mark as jump hosts • n: keep as normal hosts • Esc: ask later",
+            Style::default().fg(theme.muted),
+        )),
+    ];
+    Paragraph::new(Text::from(lines))
+        .wrap(Wrap { trim: true })
+        .block(block)
+}
+
+/// The jump hosts of the selected host, first hop at the top.
+fn pick_bastion_modal<'a>(
+    app: &App,
+    hops: &[String],
+    selected: usize,
+    block: Block<'a>,
+    theme: Theme,
+) -> Paragraph<'a> {
+    let mut lines: Vec<Line> = hops
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let active = i == selected;
+            let address = app
+                .config
+                .find_host(id)
+                .map(|host| app.display(&host.display_label()).into_owned())
+                .unwrap_or_default();
+            Line::from(vec![
+                Span::styled(
+                    format!("{}{}. ", if active { "► " } else { "  " }, i + 1),
+                    Style::default().fg(if active { theme.accent } else { theme.muted }),
+                ),
+                Span::styled(
+                    app.display(id).into_owned(),
+                    Style::default()
+                        .fg(if active { theme.accent } else { theme.text })
+                        .add_modifier(if active {
+                            Modifier::BOLD
+                        } else {
+                            Modifier::empty()
+                        }),
+                ),
+                Span::styled(format!("  ({address})"), Style::default().fg(theme.muted)),
+            ])
+        })
+        .collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "↑↓ choose • Enter or 1-9 connect • Esc cancel",
+        Style::default().fg(theme.muted),
+    )));
+    Paragraph::new(Text::from(lines)).block(block)
+}
+
+fn rename_modal<'a>(app: &App, name: String, block: Block<'a>, theme: Theme) -> Paragraph<'a> {
+    let referrers = app
+        .current_host()
+        .map_or(0, |host| app.config.bastion_referrers(&host.id()).len());
+    let lines = vec![
+        Line::from(vec![
+            Span::styled("New name: ", Style::default().fg(theme.muted)),
+            Span::styled(name, Style::default().fg(theme.text)),
+        ]),
+        Line::from(Span::styled(
+            format!("{referrers} host(s) use this as bastion and will follow the rename."),
+            Style::default().fg(theme.muted),
+        )),
+        Line::from(Span::styled(
+            "Enter to rename, Esc to cancel",
+            Style::default().fg(theme.muted),
+        )),
+    ];
+    Paragraph::new(Text::from(lines))
+        .wrap(Wrap { trim: true })
+        .block(block)
+}
+
+fn render_modal_form(frame: &mut Frame, form: &crate::app::FormState, app: &App, theme: Theme) {
+    let config = &app.config;
+    let changes = form.changes();
+    let error_field = form.error.as_ref().and_then(|error| error.field);
+    let form_error_line = form
+        .error
+        .as_ref()
+        .filter(|error| error.field.is_none())
+        .map(|error| app.display(&error.message).into_owned());
+    let base_height = 8
+        + form.fields.len() as u16
+        + form.paste_warnings.len() as u16
+        + changes.len() as u16
+        + u16::from(app.privacy)
+        + u16::from(!form.auto_tags.is_empty())
+        + u16::from(form_error_line.is_some());
     let overlay_height = if form.bastion_dropdown.is_some() || form.key_selector.is_some() {
         10
     } else {
         0
     };
-    let area = centered_rect_clamped(78, base_height + overlay_height, frame.size());
+    let area = modal_area(78, base_height + overlay_height, FORM_MIN, frame.size());
     let title = match form.kind {
         FormKind::Add => "new host",
         FormKind::Edit => "edit host",
@@ -499,7 +1729,10 @@ fn render_modal_form(
         .style(Style::default().bg(theme.panel));
 
     let mut rows: Vec<Line> = Vec::new();
-    let mut cursor: Option<(u16, u16)> = None;
+    // Cursor column and line, and the line after the focused field's hints
+    // and pickers, so the paragraph can be scrolled to keep them in view.
+    let mut cursor: Option<(u16, usize)> = None;
+    let mut focus_end: usize = 0;
     let has_command = matches!(form.kind, FormKind::Add);
     let mut line_no: usize = 0;
 
@@ -516,11 +1749,15 @@ fn render_modal_form(
             rows.push(Line::from(vec![
                 Span::styled(
                     format!("{:>16}", f.label),
-                    Style::default().fg(if active {
-                        theme.accent
+                    if error_field == Some(f.label) {
+                        error_style(theme)
                     } else {
-                        theme.accent_dim
-                    }),
+                        Style::default().fg(if active {
+                            theme.accent
+                        } else {
+                            theme.accent_dim
+                        })
+                    },
                 ),
                 Span::raw("  "),
                 Span::styled(
@@ -537,12 +1774,21 @@ fn render_modal_form(
                 ),
             ]));
             if active {
-                let x = area.x + 1 + 16 + 2 + f.cursor as u16;
-                let y = area.y + 1 + line_no as u16;
-                cursor = Some((x, y));
+                let x = area.x + 1 + 16 + 2 + text::width(&f.value[..f.cursor]) as u16;
+                cursor = Some((x, line_no));
             }
             line_no += 1;
         }
+        for warning in &form.paste_warnings {
+            rows.push(Line::from(Span::styled(
+                format!("{:>16}  ⚠ {warning}", ""),
+                Style::default().fg(theme.warn),
+            )));
+            line_no += 1;
+        }
+        if form.index == 0 {
+            focus_end = line_no;
+        }
         rows.push(Line::from(Span::styled(
             "─────────────────────────",
             Style::default().fg(theme.muted),
@@ -579,45 +1825,125 @@ fn render_modal_form(
     let key_field_idx = form
         .fields
         .iter()
-        .position(|field| field.label == "SSH keys")
+        .position(|field| field.label == "SSH keys")
+        .unwrap_or(usize::MAX);
+    let toggle_idxs: Vec<usize> = form
+        .fields
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| {
+            [
+                "Prefer publickey",
+                "Multiplex",
+                "Forward agent",
+                "Forward X11",
+            ]
+            .contains(&field.label)
+        })
+        .map(|(idx, _)| idx)
+        .collect();
+    let ip_family_idx = form
+        .fields
+        .iter()
+        .position(|field| field.label == "IP family")
+        .unwrap_or(usize::MAX);
+    let role_idx = form
+        .fields
+        .iter()
+        .position(|field| field.label == "Role")
+        .unwrap_or(usize::MAX);
+    let tags_idx = form
+        .fields
+        .iter()
+        .position(|field| field.label == "Tags (comma)")
         .unwrap_or(usize::MAX);
-    let prefer_public_key_idx = form
+    let port_idx = form
         .fields
         .iter()
-        .position(|field| field.label == "Prefer publickey")
+        .position(|field| field.label == "Port")
         .unwrap_or(usize::MAX);
     for (local_idx, f) in form.fields.iter().enumerate().skip(start_idx) {
         let active = form.index == local_idx;
         let prefix = if active { "▌" } else { " " };
+        let change = crate::app::field_diff_key(f.label)
+            .and_then(|key| changes.iter().find(|change| change.field == key));
+        let bad_port = local_idx == port_idx
+            && !f.value.trim().is_empty()
+            && f.value.trim().parse::<u16>().is_err();
+        let value_style = Style::default()
+            .fg(if bad_port { theme.error } else { theme.text })
+            .add_modifier(if active {
+                Modifier::UNDERLINED
+            } else {
+                Modifier::empty()
+            });
+        let mut value_lines = f.value.split('\n');
+        let first_line = value_lines.next().unwrap_or_default();
         rows.push(Line::from(vec![
             Span::styled(
                 format!("{prefix}{:>width$}", f.label, width = field_label_width),
-                Style::default().fg(if active {
-                    theme.accent
+                if error_field == Some(f.label) {
+                    error_style(theme)
                 } else {
-                    theme.accent_dim
-                }),
+                    Style::default().fg(if change.is_some() {
+                        theme.warn
+                    } else if active {
+                        theme.accent
+                    } else {
+                        theme.accent_dim
+                    })
+                },
             ),
             Span::raw("  "),
             Span::styled(
                 if f.value.is_empty() {
                     " ".into()
                 } else {
-                    f.value.clone()
+                    first_line.to_string()
                 },
-                Style::default().fg(theme.text).add_modifier(if active {
-                    Modifier::UNDERLINED
+                value_style,
+            ),
+            Span::styled(
+                if form.manual.contains(f.label) {
+                    "  manual"
                 } else {
-                    Modifier::empty()
-                }),
+                    ""
+                },
+                Style::default().fg(theme.muted),
             ),
         ]));
+        // Only Description takes newlines; its further lines line up under
+        // the first.
+        for line in value_lines {
+            rows.push(Line::from(vec![
+                Span::raw(" ".repeat(field_label_width + 3)),
+                Span::styled(line.to_string(), value_style),
+            ]));
+        }
         if active {
-            let x = area.x + 1 + 1 + field_label_width as u16 + 2 + f.cursor as u16;
-            let y = area.y + 1 + line_no as u16;
-            cursor = Some((x, y));
+            let before = &f.value[..f.cursor];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            let x = area.x
+                + 1
+                + 1
+                + field_label_width as u16
+                + 2
+                + text::width(&before[line_start..]) as u16;
+            cursor = Some((x, line_no + before.matches('\n').count()));
+        }
+        line_no += f.value.split('\n').count();
+        if let Some(change) = change {
+            rows.push(Line::from(Span::styled(
+                format!(
+                    " {:>width$}  was: {}",
+                    "",
+                    unset_or(&change.old),
+                    width = field_label_width
+                ),
+                Style::default().fg(theme.muted).add_modifier(Modifier::DIM),
+            )));
+            line_no += 1;
         }
-        line_no += 1;
 
         if local_idx == key_field_idx && form.key_selector.is_some() {
             if let Some(selector) = &form.key_selector {
@@ -730,7 +2056,7 @@ fn render_modal_form(
                                     }),
                                 ),
                                 Span::styled(
-                                    host.name.clone(),
+                                    config.display_name(host),
                                     Style::default()
                                         .fg(if is_selected {
                                             theme.accent
@@ -786,80 +2112,851 @@ fn render_modal_form(
             )]));
             line_no += 1;
         }
-        if local_idx == prefer_public_key_idx && active {
+        if toggle_idxs.contains(&local_idx) && active {
             rows.push(Line::from(vec![Span::styled(
                 "  (Press Space to toggle, or type y/n)",
                 Style::default().fg(theme.muted),
             )]));
             line_no += 1;
         }
+        if local_idx == ip_family_idx && active {
+            rows.push(Line::from(vec![Span::styled(
+                "  (Press Space to cycle any/IPv4/IPv6, or type a/4/6)",
+                Style::default().fg(theme.muted),
+            )]));
+            line_no += 1;
+        }
+        if local_idx == role_idx && active {
+            rows.push(Line::from(vec![Span::styled(
+                "  (Press Space for normal/bastion; bastions are listed as jump hosts)",
+                Style::default().fg(theme.muted),
+            )]));
+            line_no += 1;
+        }
+        if local_idx == tags_idx && !form.auto_tags.is_empty() {
+            rows.push(Line::from(vec![
+                Span::styled("  auto: ", Style::default().fg(theme.muted)),
+                Span::styled(
+                    form.auto_tags
+                        .iter()
+                        .map(|tag| format!("[{tag}]"))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    Style::default().fg(theme.accent_dim),
+                ),
+                Span::styled(
+                    " from auto_tags rules; delete from Tags to drop",
+                    Style::default().fg(theme.muted),
+                ),
+            ]));
+            line_no += 1;
+        }
+        if active {
+            focus_end = line_no;
+        }
+    }
+
+    if !has_command {
+        rows.push(Line::from(Span::raw("")));
+        let preview = form
+            .build_host()
+            .ok()
+            .map(|h| crate::ssh::command_preview(&h, config, None, None))
+            .unwrap_or_else(|| "fill required fields for preview".into());
+        rows.push(Line::from(Span::styled(
+            "Command preview:",
+            Style::default().fg(theme.muted),
+        )));
+        rows.push(Line::from(Span::styled(
+            app.display(&preview).into_owned(),
+            Style::default().fg(theme.accent_dim),
+        )));
+        if app.privacy {
+            rows.push(Line::from(Span::styled(
+                MASKED_PREVIEW,
+                Style::default().fg(theme.warn),
+            )));
+        }
+    }
+
+    // The save error keeps the bottom line, outside the scrolled fields.
+    let mut body = block.inner(area);
+    let error_area = form_error_line.as_ref().map(|_| {
+        body.height = body.height.saturating_sub(1);
+        Rect {
+            y: body.y + body.height,
+            height: 1,
+            ..body
+        }
+    });
+    let visible = usize::from(body.height);
+    let scroll = cursor.map_or(0, |(_, line)| focus_end.saturating_sub(visible).min(line));
+    let paragraph = Paragraph::new(Text::from(rows))
+        .wrap(Wrap { trim: false })
+        .scroll((scroll as u16, 0));
+    frame.render_widget(Clear, area);
+    frame.render_widget(block, area);
+    frame.render_widget(paragraph, body);
+    if let (Some(message), Some(error_area)) = (form_error_line, error_area) {
+        let mark = if unicode_glyphs() { "✗" } else { "!" };
+        let message = format!("{mark} {message}");
+        frame.render_widget(
+            Paragraph::new(Line::from(Span::styled(
+                text::truncate_end(&message, usize::from(error_area.width)),
+                error_style(theme),
+            ))),
+            error_area,
+        );
+    }
+    if let Some(review) = &form.review {
+        render_form_review(frame, review, theme);
+    } else if let Some((x, line)) = cursor {
+        frame.set_cursor(x, area.y + 1 + (line - scroll) as u16);
+    }
+}
+
+/// A form field or line a failed save points at.
+fn error_style(theme: Theme) -> Style {
+    Style::default()
+        .fg(theme.error)
+        .add_modifier(Modifier::BOLD)
+}
+
+fn unset_or(value: &str) -> &str {
+    if value.is_empty() {
+        "(unset)"
+    } else {
+        value
+    }
+}
+
+/// Summary of an edit's changes, shown before saving it.
+fn render_form_review(frame: &mut Frame, changes: &[FieldChange], theme: Theme) {
+    let area = modal_area(68, 5 + changes.len() as u16, REVIEW_MIN, frame.size());
+    let inner = area.width.saturating_sub(2) as usize;
+    let key_width = changes
+        .iter()
+        .map(|change| change.field.len())
+        .max()
+        .unwrap_or(0);
+    let mut lines: Vec<Line> = changes
+        .iter()
+        .map(|change| {
+            let values = format!("{} → {}", unset_or(&change.old), unset_or(&change.new));
+            Line::from(vec![
+                Span::styled(
+                    format!("{:>key_width$}  ", change.field),
+                    Style::default().fg(theme.warn),
+                ),
+                Span::styled(
+                    text::truncate_end(&values, inner.saturating_sub(key_width + 2)),
+                    Style::default().fg(theme.text),
+                ),
+            ])
+        })
+        .collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "Enter/y save • Esc/n back to the form",
+        Style::default().fg(theme.muted),
+    )));
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.warn))
+        .title("review changes")
+        .style(Style::default().bg(theme.panel));
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// Smallest size at which a modal is still usable; see `modal_area`.
+#[derive(Clone, Copy)]
+struct MinSize {
+    width: u16,
+    height: u16,
+}
+
+const CONFIRM_MIN: MinSize = MinSize {
+    width: 40,
+    height: 9,
+};
+const FORM_MIN: MinSize = MinSize {
+    width: 50,
+    height: 14,
+};
+const REVIEW_MIN: MinSize = MinSize {
+    width: 40,
+    height: 6,
+};
+const HELP_MIN: MinSize = MinSize {
+    width: 40,
+    height: 10,
+};
+const PROBLEMS_MIN: MinSize = MinSize {
+    width: 50,
+    height: 6,
+};
+const BUNDLE_MIN: MinSize = MinSize {
+    width: 50,
+    height: 8,
+};
+const BULK_MIN: MinSize = MinSize {
+    width: 50,
+    height: 10,
+};
+const EXPLAIN_MIN: MinSize = MinSize {
+    width: 50,
+    height: 10,
+};
+const QUICK_CONNECT_MIN: MinSize = MinSize {
+    width: 40,
+    height: 6,
+};
+const TAG_FILTER_MIN: MinSize = MinSize {
+    width: 30,
+    height: 7,
+};
+const TRANSFER_MIN: MinSize = MinSize {
+    width: 40,
+    height: 8,
+};
+const SETTINGS_MIN: MinSize = MinSize {
+    width: 40,
+    height: 8,
+};
+const ABOUT_MIN: MinSize = MinSize {
+    width: 40,
+    height: 8,
+};
+
+/// Where a modal of the preferred `width`×`height` goes: centered in `r`
+/// when it still gets `min` (or its preferred size, if smaller), otherwise
+/// over all of `r`, hiding the list until it closes, rather than as a sliver.
+fn modal_area(width: u16, height: u16, min: MinSize, r: Rect) -> Rect {
+    let area = centered_rect_clamped(width, height, r);
+    if area.width < min.width.min(width) || area.height < min.height.min(height) {
+        r
+    } else {
+        area
+    }
+}
+
+fn centered_rect_clamped(width: u16, height: u16, r: Rect) -> Rect {
+    let w = width.min(r.width.saturating_sub(2));
+    let h = height.min(r.height.saturating_sub(2));
+    let x = r.x + (r.width.saturating_sub(w)) / 2;
+    let y = r.y + (r.height.saturating_sub(h)) / 2;
+    Rect {
+        x,
+        y,
+        width: w,
+        height: h,
+    }
+}
+
+fn render_help(frame: &mut Frame, app: &App, theme: Theme) {
+    let area = modal_area(78, 16, HELP_MIN, frame.size());
+    let items: Vec<Line> = app
+        .help_entries()
+        .into_iter()
+        .map(|(k, v)| {
+            Line::from(vec![
+                Span::styled(format!("{:>15}", k), Style::default().fg(theme.accent)),
+                Span::raw("  "),
+                Span::styled(v, Style::default().fg(theme.text)),
+            ])
+        })
+        .collect();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .title("keys");
+    let paragraph = Paragraph::new(Text::from(items))
+        .style(Style::default().bg(theme.panel))
+        .block(block);
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+}
+
+fn render_problems(frame: &mut Frame, app: &App, theme: Theme) {
+    let problems = app.problems();
+    let area = modal_area(96, problems.len() as u16 + 4, PROBLEMS_MIN, frame.size());
+    let mut lines: Vec<Line> = problems
+        .iter()
+        .enumerate()
+        .map(|(i, problem)| {
+            let selected = i == app.problem_selected;
+            Line::from(vec![
+                Span::styled(
+                    if selected { "▸ " } else { "  " },
+                    Style::default().fg(theme.accent),
+                ),
+                Span::styled(
+                    problem.message.as_str(),
+                    if selected {
+                        Style::default().fg(theme.warn).add_modifier(Modifier::BOLD)
+                    } else {
+                        Style::default().fg(theme.text)
+                    },
+                ),
+            ])
+        })
+        .collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "Enter: show host  a: acknowledge alias (alias_ok)  Esc: close",
+        Style::default().fg(theme.muted),
+    )));
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.warn))
+        .title("problems");
+    let paragraph = Paragraph::new(Text::from(lines))
+        .style(Style::default().bg(theme.panel))
+        .block(block)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+}
+
+/// Tags the `t` picker shows at once; the list scrolls with the selection.
+const TAG_FILTER_ROWS: usize = 12;
+
+/// The `t` picker: known tags narrowed by what was typed, with the ones
+/// already filtering the list checked.
+fn render_tag_filter(frame: &mut Frame, app: &App, theme: Theme) {
+    let Some(picker) = &app.tag_picker else {
+        return;
+    };
+    let choices = app.tag_choices();
+    let shown = choices.len().clamp(1, TAG_FILTER_ROWS);
+    let area = modal_area(50, shown as u16 + 6, TAG_FILTER_MIN, frame.size());
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled("tag: ", Style::default().fg(theme.muted)),
+            Span::styled(
+                if picker.query.is_empty() {
+                    " "
+                } else {
+                    picker.query.as_str()
+                },
+                Style::default()
+                    .fg(theme.text)
+                    .add_modifier(Modifier::UNDERLINED),
+            ),
+        ]),
+        Line::from(""),
+    ];
+    if choices.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "No tag matches.",
+            Style::default().fg(theme.muted),
+        )));
+    }
+    let check = if unicode_glyphs() { "✓" } else { "*" };
+    let start = picker.selected.saturating_sub(TAG_FILTER_ROWS - 1);
+    for (i, tag) in choices.iter().enumerate().skip(start).take(TAG_FILTER_ROWS) {
+        let selected = i == picker.selected;
+        let active = app.tag_filter.contains(tag);
+        lines.push(Line::from(vec![
+            Span::styled(
+                if selected { "▸ " } else { "  " },
+                Style::default().fg(theme.accent),
+            ),
+            Span::styled(
+                format!("{} ", if active { check } else { " " }),
+                Style::default().fg(theme.accent),
+            ),
+            Span::styled(
+                tag.as_str(),
+                if selected {
+                    Style::default()
+                        .fg(theme.accent)
+                        .add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(theme.text)
+                },
+            ),
+        ]));
     }
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "Enter: add or remove  Esc: close",
+        Style::default().fg(theme.muted),
+    )));
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .title("filter by tag");
+    let paragraph = Paragraph::new(Text::from(lines))
+        .style(Style::default().bg(theme.panel))
+        .block(block);
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+    let typed = text::width(&picker.query) as u16;
+    frame.set_cursor(area.x + 1 + 5 + typed, area.y + 1);
+}
+
+/// The `f` prompt: the two paths, the active one underlined, and the scp
+/// command Enter would run.
+fn render_transfer(frame: &mut Frame, app: &App, theme: Theme) {
+    let (Some(host), Some(transfer)) = (app.current_host(), &app.transfer) else {
+        return;
+    };
+    let area = modal_area(80, 9, TRANSFER_MIN, frame.size());
+    let path = |label: &'static str, value: &str, active: bool| {
+        let style = if active {
+            Style::default()
+                .fg(theme.text)
+                .add_modifier(Modifier::UNDERLINED)
+        } else {
+            Style::default().fg(theme.text)
+        };
+        Line::from(vec![
+            Span::styled(
+                label,
+                Style::default().fg(if active { theme.accent } else { theme.muted }),
+            ),
+            Span::styled(
+                if value.is_empty() {
+                    " ".to_string()
+                } else {
+                    value.to_string()
+                },
+                style,
+            ),
+        ])
+    };
+    let preview = app.transfer_preview().unwrap_or_default();
+    let lines = vec![
+        path("local:  ", &transfer.local, !transfer.editing_remote),
+        path("remote: ", &transfer.remote, transfer.editing_remote),
+        Line::from(Span::styled(
+            "(empty remote: the home directory)",
+            Style::default().fg(theme.muted),
+        )),
+        Line::from(""),
+        Line::from(Span::styled(
+            app.display(&preview).into_owned(),
+            Style::default().fg(if preview.starts_with("<error") {
+                theme.error
+            } else {
+                theme.muted
+            }),
+        )),
+        Line::from(""),
+        Line::from(Span::styled(
+            if app.dry_run {
+                "Tab: other path  Enter: show command (dry-run)  Esc: cancel"
+            } else {
+                "Tab: other path  Enter: copy  Esc: cancel"
+            },
+            Style::default().fg(theme.muted),
+        )),
+    ];
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .title(format!(
+            "copy to {}",
+            app.display(&app.config.display_name(host))
+        ));
+    let paragraph = Paragraph::new(Text::from(lines))
+        .style(Style::default().bg(theme.panel))
+        .block(block)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+    let (row, typed) = if transfer.editing_remote {
+        (1, &transfer.remote)
+    } else {
+        (0, &transfer.local)
+    };
+    let cursor_x = area.x + 1 + 8 + text::width(typed) as u16;
+    frame.set_cursor(
+        cursor_x.min(area.right().saturating_sub(2)),
+        area.y + 1 + row,
+    );
+}
+
+/// The `O` modal: the default key being edited, with where the config
+/// lives and whether dry-run is on for reference.
+fn render_settings(frame: &mut Frame, app: &App, theme: Theme) {
+    let Some(settings) = &app.settings else {
+        return;
+    };
+    let area = modal_area(80, 10, SETTINGS_MIN, frame.size());
+    let key = settings.default_key.trim();
+    let meaning = match key {
+        "" => "ssh picks keys itself (its defaults and ssh_config)",
+        "agent" => "keys are left to the ssh agent",
+        _ => "passed as -i to hosts without keys of their own",
+    };
+    let info = |label: &'static str, value: String| {
+        Line::from(vec![
+            Span::styled(label, Style::default().fg(theme.muted)),
+            Span::styled(value, Style::default().fg(theme.text)),
+        ])
+    };
+    let lines = vec![
+        Line::from(vec![
+            Span::styled("default key: ", Style::default().fg(theme.accent)),
+            Span::styled(
+                app.display(&settings.default_key).into_owned(),
+                Style::default()
+                    .fg(theme.text)
+                    .add_modifier(Modifier::UNDERLINED),
+            ),
+        ]),
+        Line::from(Span::styled(
+            format!("({meaning})"),
+            Style::default().fg(theme.muted),
+        )),
+        Line::from(""),
+        info("config:  ", app.config_path.display().to_string()),
+        info(
+            "dry-run: ",
+            if app.dry_run { "on (C)" } else { "off (C)" }.into(),
+        ),
+        Line::from(""),
+        Line::from(Span::styled(
+            "Tab: key path / agent  Enter: save  Esc: cancel",
+            Style::default().fg(theme.muted),
+        )),
+    ];
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .title("settings");
+    let paragraph = Paragraph::new(Text::from(lines))
+        .style(Style::default().bg(theme.panel))
+        .block(block)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+    let cursor_x = area.x + 1 + 13 + text::width(&settings.default_key) as u16;
+    frame.set_cursor(cursor_x.min(area.right().saturating_sub(2)), area.y + 1);
+}
 
-    if !has_command {
-        rows.push(Line::from(Span::raw("")));
-        let preview = form
-            .build_host()
-            .ok()
-            .map(|h| crate::ssh::command_preview(&h, config, None, None))
-            .unwrap_or_else(|| "fill required fields for preview".into());
-        rows.push(Line::from(Span::styled(
-            "Command preview:",
+/// The `T` view: each `[[auto_tags]]` rule with the existing hosts it
+/// matches. Nothing is changed; rules only tag hosts as they are added.
+fn render_rules(frame: &mut Frame, app: &App, theme: Theme) {
+    let rules = &app.config.auto_tags;
+    let mut lines: Vec<Line> = Vec::new();
+    if rules.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "No [[auto_tags]] rules in the config.",
             Style::default().fg(theme.muted),
         )));
-        rows.push(Line::from(Span::styled(
-            preview,
-            Style::default().fg(theme.accent_dim),
+    }
+    for (number, rule) in rules.iter().enumerate() {
+        lines.push(Line::from(vec![
+            Span::styled(
+                format!("{}. ", number + 1),
+                Style::default().fg(theme.muted),
+            ),
+            Span::styled(rule.describe(), Style::default().fg(theme.text)),
+            Span::styled(" → ", Style::default().fg(theme.muted)),
+            Span::styled(rule.tags.join(", "), Style::default().fg(theme.accent_dim)),
+        ]));
+        let (text, color) = match rule.check() {
+            Err(err) => (format!("invalid: {err:#}"), theme.error),
+            Ok(()) => {
+                let matched: Vec<String> = app
+                    .config
+                    .hosts
+                    .iter()
+                    .filter(|host| rule.matches(host))
+                    .map(|host| app.display(&app.config.display_name(host)).into_owned())
+                    .collect();
+                if matched.is_empty() {
+                    ("matches no host".into(), theme.muted)
+                } else {
+                    (
+                        format!("matches {}: {}", matched.len(), matched.join(", ")),
+                        theme.text,
+                    )
+                }
+            }
+        };
+        lines.push(Line::from(Span::styled(
+            format!("   {text}"),
+            Style::default().fg(color),
         )));
     }
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "Rules tag hosts added by quick connect or the Add form; existing hosts keep their tags. Esc: close",
+        Style::default().fg(theme.muted),
+    )));
+    let area = modal_area(96, lines.len() as u16 + 2, PROBLEMS_MIN, frame.size());
+    let paragraph = Paragraph::new(Text::from(lines))
+        .style(Style::default().bg(theme.panel))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(theme.accent))
+                .title("auto_tags rules"),
+        )
+        .wrap(Wrap { trim: false });
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+}
 
-    let paragraph = Paragraph::new(Text::from(rows))
+/// The `i` report: one row per decision `ssh::explain` recorded.
+fn render_bundle(frame: &mut Frame, app: &App, theme: Theme) {
+    let path = crate::bundle::default_path();
+    let key = |key: &'static str, text: String| {
+        Line::from(vec![
+            Span::styled(
+                key,
+                Style::default()
+                    .fg(theme.accent)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(text, Style::default().fg(theme.text)),
+        ])
+    };
+    let lines: Vec<Line> = match &app.bundle_import {
+        None => vec![
+            key(
+                "e  ",
+                format!("export config, state and backups to {}", path.display()),
+            ),
+            key("i  ", format!("import from {}", path.display())),
+            Line::from(""),
+            Line::from(Span::styled("Esc: close", Style::default().fg(theme.muted))),
+        ],
+        Some((_, plan)) => plan
+            .iter()
+            .map(|line| Line::from(Span::styled(line.as_str(), Style::default().fg(theme.text))))
+            .chain([
+                Line::from(""),
+                Line::from(Span::styled(
+                    "y: import  Esc: cancel (nothing is written until y)",
+                    Style::default().fg(theme.warn),
+                )),
+            ])
+            .collect(),
+    };
+    let area = modal_area(96, lines.len() as u16 + 6, BUNDLE_MIN, frame.size());
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .title("bundle");
+    let paragraph = Paragraph::new(Text::from(lines))
+        .style(Style::default().bg(theme.panel))
         .block(block)
         .wrap(Wrap { trim: false });
     frame.render_widget(Clear, area);
     frame.render_widget(paragraph, area);
-    if let Some((x, y)) = cursor {
-        frame.set_cursor(x, y);
-    }
 }
 
-fn centered_rect_clamped(width: u16, height: u16, r: Rect) -> Rect {
-    let w = width.min(r.width.saturating_sub(2));
-    let h = height.min(r.height.saturating_sub(2));
-    let x = r.x + (r.width.saturating_sub(w)) / 2;
-    let y = r.y + (r.height.saturating_sub(h)) / 2;
-    Rect {
-        x,
-        y,
-        width: w,
-        height: h,
-    }
-}
+/// Rows of before → after shown in the bulk-edit preview.
+const BULK_PREVIEW_ROWS: usize = 12;
 
-fn render_help(frame: &mut Frame, theme: Theme) {
-    let area = centered_rect_clamped(78, 16, frame.size());
-    let items: Vec<Line> = crate::app::App::help_entries()
+fn render_bulk(frame: &mut Frame, app: &App, theme: Theme) {
+    let Some(edit) = &app.bulk else {
+        return;
+    };
+    let field = edit.field();
+    let fields: Vec<Span> = crate::bulk::BulkField::ALL
         .iter()
-        .map(|(k, v)| {
-            Line::from(vec![
-                Span::styled(format!("{:>15}", k), Style::default().fg(theme.accent)),
-                Span::raw("  "),
-                Span::styled(*v, Style::default().fg(theme.text)),
-            ])
+        .flat_map(|f| {
+            let style = if *f == field {
+                Style::default()
+                    .fg(theme.accent)
+                    .add_modifier(Modifier::BOLD | Modifier::REVERSED)
+            } else {
+                Style::default().fg(theme.muted)
+            };
+            [
+                Span::styled(format!(" {} ", f.label()), style),
+                Span::raw(" "),
+            ]
         })
         .collect();
+    let mut lines = vec![
+        Line::from(fields),
+        Line::from(""),
+        Line::from(vec![
+            Span::styled(
+                format!("{}: ", field.label()),
+                Style::default().fg(theme.accent),
+            ),
+            Span::styled(edit.value.as_str(), Style::default().fg(theme.text)),
+        ]),
+        Line::from(Span::styled(
+            field.empty_means(),
+            Style::default().fg(theme.muted),
+        )),
+        Line::from(""),
+    ];
+    let preview = app.bulk_preview();
+    match &preview {
+        Err(err) => lines.push(Line::from(Span::styled(
+            format!("{err:#}"),
+            Style::default().fg(theme.error),
+        ))),
+        Ok(changes) if changes.is_empty() => lines.push(Line::from(Span::styled(
+            "No marked host changes.",
+            Style::default().fg(theme.muted),
+        ))),
+        Ok(changes) => {
+            for change in changes.iter().take(BULK_PREVIEW_ROWS) {
+                lines.push(Line::from(vec![
+                    Span::styled(
+                        format!("{}  ", change.id),
+                        Style::default().fg(theme.text).add_modifier(Modifier::BOLD),
+                    ),
+                    Span::styled(
+                        if change.before.is_empty() {
+                            "(unset)"
+                        } else {
+                            &change.before
+                        },
+                        Style::default().fg(theme.muted),
+                    ),
+                    Span::styled(" → ", Style::default().fg(theme.muted)),
+                    Span::styled(
+                        if change.after.is_empty() {
+                            "(unset)"
+                        } else {
+                            &change.after
+                        },
+                        Style::default().fg(theme.warn),
+                    ),
+                ]));
+            }
+            if changes.len() > BULK_PREVIEW_ROWS {
+                lines.push(Line::from(Span::styled(
+                    format!("… and {} more", changes.len() - BULK_PREVIEW_ROWS),
+                    Style::default().fg(theme.muted),
+                )));
+            }
+        }
+    }
+    let changing = preview.as_ref().map_or(0, Vec::len);
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        format!("Tab: field  Enter: apply to {changing} host(s)  Esc: cancel"),
+        Style::default().fg(theme.muted),
+    )));
+
+    let area = modal_area(96, lines.len() as u16 + 2, BULK_MIN, frame.size());
     let block = Block::default()
         .borders(Borders::ALL)
         .border_style(Style::default().fg(theme.accent))
-        .title("keys");
-    let paragraph = Paragraph::new(Text::from(items))
+        .title(format!("bulk edit ({} marked)", app.marked.len()));
+    let value_line = area.y + 3;
+    let cursor_x = area.x + 1 + (field.label().len() + 2 + text::width(&edit.value)) as u16;
+    let paragraph = Paragraph::new(Text::from(lines))
         .style(Style::default().bg(theme.panel))
-        .block(block);
+        .block(block)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+    frame.set_cursor(cursor_x.min(area.right().saturating_sub(2)), value_line);
+}
+
+fn render_explain(frame: &mut Frame, app: &App, theme: Theme) {
+    let (Some(host), Some(explanation)) = (app.current_host(), app.current_explanation()) else {
+        return;
+    };
+    let area = modal_area(100, 20, EXPLAIN_MIN, frame.size());
+    let mut lines = vec![
+        Line::from(Span::styled(
+            if app.dry_run {
+                "dry-run is ON: Enter only shows this command"
+            } else {
+                "dry-run is off: Enter runs this command"
+            },
+            Style::default().fg(theme.muted),
+        )),
+        Line::from(""),
+    ];
+    lines.extend(explanation.lines().into_iter().map(|(topic, detail)| {
+        Line::from(vec![
+            Span::styled(format!("{topic:>8}  "), Style::default().fg(theme.accent)),
+            Span::styled(
+                detail,
+                Style::default().fg(if topic == "error" {
+                    theme.warn
+                } else {
+                    theme.text
+                }),
+            ),
+        ])
+    }));
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .title(format!(
+            "explain {} (j/k scroll, Esc close)",
+            app.display(&app.config.display_name(host))
+        ));
+    let paragraph = Paragraph::new(Text::from(masked(app, lines)))
+        .style(Style::default().bg(theme.panel))
+        .block(block)
+        .wrap(Wrap { trim: false })
+        .scroll((app.explain_scroll, 0));
+    frame.render_widget(Clear, area);
+    frame.render_widget(paragraph, area);
+}
+
+/// The command `Enter` would run, one argument per line with shell
+/// continuations, so it still pastes as one command.
+fn render_full_command(frame: &mut Frame, app: &App, theme: Theme) {
+    let (Some(host), Some(command)) = (app.current_host(), app.current_command_lines()) else {
+        return;
+    };
+    let mut lines = vec![
+        Line::from(Span::styled(
+            if app.dry_run {
+                "dry-run is ON: Enter only shows this command"
+            } else {
+                "dry-run is off: Enter runs this command"
+            },
+            Style::default().fg(theme.muted),
+        )),
+        Line::from(""),
+    ];
+    match command {
+        Ok(args) => {
+            let last = args.len().saturating_sub(1);
+            lines.extend(args.into_iter().enumerate().map(|(i, arg)| {
+                let indent = if i == 0 { "" } else { "  " };
+                let continued = if i == last { "" } else { " \\" };
+                Line::from(Span::styled(
+                    format!("{indent}{arg}{continued}"),
+                    Style::default().fg(theme.text),
+                ))
+            }));
+        }
+        Err(err) => lines.push(Line::from(Span::styled(
+            format!("{err:#}"),
+            Style::default().fg(theme.warn),
+        ))),
+    }
+    let area = modal_area(100, lines.len() as u16 + 2, EXPLAIN_MIN, frame.size());
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.accent))
+        .title(format!(
+            "command for {} (j/k scroll, Esc close)",
+            app.display(&app.config.display_name(host))
+        ));
+    let paragraph = Paragraph::new(Text::from(masked(app, lines)))
+        .style(Style::default().bg(theme.panel))
+        .block(block)
+        .wrap(Wrap { trim: false })
+        .scroll((app.explain_scroll, 0));
     frame.render_widget(Clear, area);
     frame.render_widget(paragraph, area);
 }
 
 fn render_quickconnect(frame: &mut Frame, app: &App, theme: Theme) {
-    let area = centered_rect_clamped(70, 8, frame.size());
+    let area = modal_area(70, 8, QUICK_CONNECT_MIN, frame.size());
     let block = Block::default()
         .borders(Borders::ALL)
         .border_style(Style::default().fg(theme.accent))
@@ -868,7 +2965,8 @@ fn render_quickconnect(frame: &mut Frame, app: &App, theme: Theme) {
     let content_start_x = area.x + 1;
     let content_start_y = area.y + 1;
     let prefix_len = 4u16; // "ssh "
-    let cursor_x = content_start_x + prefix_len + app.quick_cursor.min(input.len()) as u16;
+    let typed = input.get(..app.quick_cursor).unwrap_or(&input);
+    let cursor_x = content_start_x + prefix_len + text::width(typed) as u16;
     let cursor_y = content_start_y + 2;
 
     let lines = vec![
@@ -901,7 +2999,7 @@ fn render_quickconnect(frame: &mut Frame, app: &App, theme: Theme) {
 }
 
 fn render_about(frame: &mut Frame, theme: Theme) {
-    let area = centered_rect_clamped(70, 10, frame.size());
+    let area = modal_area(70, 10, ABOUT_MIN, frame.size());
     let lines = vec![
         Line::from(Span::styled(
             format!("sshdb v{}", VERSION),
@@ -941,3 +3039,778 @@ fn render_about(frame: &mut Frame, theme: Theme) {
     frame.render_widget(Clear, area);
     frame.render_widget(paragraph, area);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::tests::{press, select, test_app};
+    use crate::app::FormState;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use std::time::{Duration, Instant};
+
+    fn draw(app: &App, width: u16, height: u16) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| render(f, app)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| buffer.get(x, y).symbol())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn wide_host_names_stay_inside_their_column() {
+        let mut app = test_app();
+        app.config.hosts[0].name = "東京-サーバー-🚀-primary-web".into();
+        let screen = draw(&app, 100, 20);
+        // A wide glyph fills its cell and blanks the next, so the drawn text
+        // reads "東 京"; columns are compared in cells, not bytes.
+        let row = |needle: &str| {
+            screen
+                .lines()
+                .find(|line| line.contains(needle))
+                .unwrap()
+                .chars()
+                .collect::<Vec<_>>()
+        };
+        let column = |cells: &[char], needle: &str| {
+            let needle: Vec<char> = needle.chars().collect();
+            cells.windows(needle.len()).position(|w| w == needle)
+        };
+        let wide = row("□ 東 京");
+        let plain = row("staging-db");
+        assert!(wide.contains(&'…'));
+        assert!(column(&wide, "primary").is_none());
+        assert_eq!(column(&wide, "deploy@"), column(&plain, "db@"));
+        assert_eq!(column(&wide, "web blue"), column(&plain, "db green"));
+    }
+
+    #[test]
+    fn edit_form_shows_old_values_and_the_review() {
+        let mut app = test_app();
+        let host = app.config.hosts[0].clone();
+        let mut form = FormState::new(FormKind::Edit, Some(&host), &app.config);
+        form.fields[3].value = "root".into();
+        app.form = Some(form);
+        let screen = draw(&app, 100, 40);
+        assert!(screen.contains("User  root"));
+        assert!(screen.contains("was: deploy"));
+
+        app.form.as_mut().unwrap().review = Some(app.form.as_ref().unwrap().changes());
+        let screen = draw(&app, 100, 40);
+        assert!(screen.contains("review changes"));
+        assert!(screen.contains("user  deploy → root"));
+    }
+
+    #[test]
+    fn form_marks_the_field_a_save_failed_on() {
+        use crate::app::FormError;
+
+        let mut app = test_app();
+        let host = app.config.hosts[0].clone();
+        let mut form = FormState::new(FormKind::Edit, Some(&host), &app.config);
+        form.error = Some(FormError {
+            field: Some("Port"),
+            message: "port must be numeric".into(),
+        });
+        app.form = Some(form);
+        let mut terminal = Terminal::new(TestBackend::new(100, 40)).unwrap();
+        terminal.draw(|f| render(f, &app)).unwrap();
+        let buffer = terminal.backend().buffer().clone();
+        let label_color = |label: &str| {
+            (0..40).find_map(|y| {
+                let row: String = (0..100).map(|x| buffer.get(x, y).symbol()).collect();
+                let x = row.find(&format!("{label}  "))?;
+                let x = row[..x].chars().count() as u16;
+                Some(buffer.get(x, y).fg)
+            })
+        };
+        let theme = Theme::default();
+        assert_eq!(label_color("Port"), Some(theme.error));
+        assert_ne!(label_color("User"), Some(theme.error));
+        assert!(!draw(&app, 100, 40).contains("port must be numeric"));
+
+        app.form.as_mut().unwrap().error = Some(FormError {
+            field: None,
+            message: "Circular bastion reference detected involving 'jump-eu'.".into(),
+        });
+        let screen = draw(&app, 100, 40);
+        assert!(screen.contains("Circular bastion reference detected involving 'jump-eu'."));
+        // It stays on screen when the fields scroll.
+        assert!(draw(&app, 100, 12).contains("Circular bastion"));
+    }
+
+    #[test]
+    fn long_lists_scroll_with_the_selection() {
+        let mut app = test_app();
+        app.config.hosts = (0..200)
+            .map(|i| Host {
+                name: format!("host-{i:03}"),
+                address: format!("10.0.{}.{}", i / 100, i % 100),
+                ..Default::default()
+            })
+            .collect();
+        app.set_filter("");
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("host-000"));
+        assert!(!screen.contains("host-199"));
+
+        // Up from the top wraps to the last host and the window follows.
+        press(&mut app, 'k');
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("host-199"));
+        assert!(!screen.contains("host-000"));
+        let offset = app.list_offset;
+        assert!(offset > 150, "{offset}");
+
+        // Moving inside the window leaves it where it is.
+        press(&mut app, 'k');
+        assert_eq!(app.list_offset, offset);
+        press(&mut app, 'j');
+        press(&mut app, 'j');
+        assert_eq!(app.list_offset, 0);
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("host-000"));
+    }
+
+    #[test]
+    fn details_show_the_last_port_check() {
+        let mut app = test_app();
+        select(&mut app, "prod-web");
+        assert!(!draw(&app, 120, 40).contains("ssh port"));
+        app.probes.record(
+            "52.14.33.10",
+            22,
+            Instant::now(),
+            PortProbe::Open(Duration::from_millis(12)),
+        );
+        let screen = draw(&app, 120, 40);
+        assert!(
+            screen.contains("ssh port: ● reachable in 12ms (checked just now)"),
+            "{screen}"
+        );
+    }
+
+    #[test]
+    fn details_flag_key_files_that_are_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let present = dir.path().join("id_a").display().to_string();
+        let absent = dir.path().join("id_b").display().to_string();
+        std::fs::write(&present, "").unwrap();
+        let mut app = test_app();
+        app.config.hosts[0].key_paths = vec![present.clone(), absent.clone()];
+        select(&mut app, "prod-web");
+        let screen = draw(&app, 160, 40);
+        assert!(
+            screen.contains(&format!("keys: {present}, {absent} (missing)")),
+            "{screen}"
+        );
+
+        // The lookup is kept until the config changes.
+        std::fs::write(&absent, "").unwrap();
+        assert!(draw(&app, 160, 40).contains("(missing)"));
+        app.config.hosts[0].key_paths = vec![absent.clone()];
+        assert!(!draw(&app, 160, 40).contains("(missing)"));
+    }
+
+    #[test]
+    fn search_shows_its_match_count_and_says_when_nothing_matches() {
+        let mut app = test_app();
+        assert!(!draw(&app, 100, 30).contains("match"));
+        press(&mut app, '/');
+        for c in "prod".chars() {
+            press(&mut app, c);
+        }
+        assert!(draw(&app, 100, 30).contains("· 1 match"));
+        for c in "xyz".chars() {
+            press(&mut app, c);
+        }
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("· 0 matches"), "{screen}");
+        assert!(screen.contains("no hosts match 'prodxyz'"), "{screen}");
+
+        // An empty config isn't a search that found nothing.
+        let mut app = test_app();
+        app.config.hosts.clear();
+        app.set_filter("");
+        assert!(!draw(&app, 100, 30).contains("no hosts match"));
+    }
+
+    #[test]
+    fn settings_modal_shows_the_config_path_and_dry_run() {
+        let mut app = test_app();
+        app.config_path = "/tmp/work/config.toml".into();
+        app.dry_run = true;
+        press(&mut app, 'O');
+        app.settings.as_mut().unwrap().default_key = "agent".into();
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("default key: agent"), "{screen}");
+        assert!(screen.contains("left to the ssh agent"), "{screen}");
+        assert!(
+            screen.contains("config:  /tmp/work/config.toml"),
+            "{screen}"
+        );
+        assert!(screen.contains("dry-run: on"), "{screen}");
+    }
+
+    #[test]
+    fn grouped_list_draws_a_heading_per_tag() {
+        let mut app = test_app();
+        app.config.hosts[0].tags.clear();
+        app.set_filter("");
+        press(&mut app, 'z');
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("▾ db"), "{screen}");
+        assert!(screen.contains("▾ jump"), "{screen}");
+        assert!(screen.contains("▾ (no tags)"), "{screen}");
+        assert!(!screen.contains("jump hosts"), "{screen}");
+    }
+
+    #[test]
+    fn theme_colours_come_from_the_config() {
+        let mut app = test_app();
+        app.config.theme.accent = Some("#FF8800".into());
+        app.config.theme.muted = Some("#12345".into());
+        let theme = Theme::from_config(&app.config);
+        assert_eq!(theme.accent, Color::Rgb(255, 136, 0));
+        assert_eq!(theme.muted, Theme::default().muted);
+        assert_eq!(theme.bg, Theme::default().bg);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|f| render(f, &app)).unwrap();
+        let buffer = terminal.backend().buffer();
+        assert!(buffer.content.iter().any(|cell| cell.fg == theme.accent));
+        let default_accent = Theme::default().accent;
+        assert!(!buffer.content.iter().any(|cell| cell.fg == default_accent));
+    }
+
+    #[test]
+    fn form_shows_an_out_of_range_port_in_red() {
+        let mut app = test_app();
+        let host = app.config.hosts[0].clone();
+        app.form = Some(FormState::new(FormKind::Edit, Some(&host), &app.config));
+        let port_color = |app: &App, port: &str| {
+            let mut terminal = Terminal::new(TestBackend::new(100, 40)).unwrap();
+            terminal.draw(|f| render(f, app)).unwrap();
+            let buffer = terminal.backend().buffer();
+            (0..40).find_map(|y| {
+                let row: String = (0..100).map(|x| buffer.get(x, y).symbol()).collect();
+                let x = row.find(&format!("Port  {port}"))? + "Port  ".len();
+                let x = row[..x].chars().count() as u16;
+                Some(buffer.get(x, y).fg)
+            })
+        };
+        let set_port = |app: &mut App, port: &str| {
+            let form = app.form.as_mut().unwrap();
+            let field = form.fields.iter_mut().find(|f| f.label == "Port").unwrap();
+            field.value = port.into();
+            field.cursor = 0;
+        };
+        let theme = Theme::default();
+        set_port(&mut app, "65535");
+        assert_eq!(port_color(&app, "65535"), Some(theme.text));
+        set_port(&mut app, "65536");
+        assert_eq!(port_color(&app, "65536"), Some(theme.error));
+    }
+
+    #[test]
+    fn tag_filter_shows_in_the_picker_and_the_header() {
+        let mut app = test_app();
+        app.tag_filter = vec!["web".into()];
+        app.tag_picker = Some(crate::app::TagPicker {
+            query: "b".into(),
+            selected: 2,
+        });
+        app.mode = Mode::TagFilter;
+        let screen = draw(&app, 140, 30);
+        assert!(screen.contains("tags: web (Esc clears)"), "{screen}");
+        assert!(screen.contains("filter by tag"));
+        assert!(screen.contains("tag: b"));
+        let check = if unicode_glyphs() { "✓" } else { "*" };
+        assert!(screen.contains("│    blue"), "{screen}");
+        assert!(screen.contains("│    db"));
+        assert!(screen.contains(&format!("▸ {check} web")), "{screen}");
+        assert!(!screen.contains(" green"));
+    }
+
+    #[test]
+    fn bastion_picker_lists_the_hops() {
+        let mut app = test_app();
+        app.mode = Mode::Confirm;
+        app.confirm = Some(ConfirmKind::PickBastion {
+            hops: vec!["prod-web".into(), "jump-eu".into()],
+            selected: 1,
+        });
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("connect to which bastion?"), "{screen}");
+        assert!(screen.contains("  1. prod-web  (deploy@52.14.33.10)"));
+        assert!(screen.contains("► 2. jump-eu  (ops@52.17.9.3)"));
+    }
+
+    #[test]
+    fn modals_take_over_short_terminals() {
+        use ratatui::backend::Backend;
+
+        let mut app = test_app();
+        let host = app.config.hosts[0].clone();
+        let mut form = FormState::new(FormKind::Edit, Some(&host), &app.config);
+        form.index = form.fields.len() - 1;
+        form.fields[form.index].value = "web".into();
+        form.fields[form.index].cursor = 3;
+        app.form = Some(form);
+        let mut terminal = Terminal::new(TestBackend::new(80, 10)).unwrap();
+        terminal.draw(|f| render(f, &app)).unwrap();
+        let buffer = terminal.backend().buffer().clone();
+        let rows: Vec<String> = (0..10)
+            .map(|y| (0..80).map(|x| buffer.get(x, y).symbol()).collect())
+            .collect();
+        // The form's border is the frame's border and the list is hidden.
+        assert!(rows[0].starts_with("┌edit host"), "{}", rows[0]);
+        assert!(!rows.iter().any(|row| row.contains("hosts")));
+        // Scrolled so the focused last field is on screen, cursor after its value.
+        let (x, y) = terminal.backend_mut().get_cursor().unwrap();
+        assert!(y > 0 && y < 9, "cursor row {y}");
+        assert!(rows[usize::from(y)].contains("Description  web"));
+        let row = &rows[usize::from(y)];
+        let value_col = row[..row.find("web").unwrap()].chars().count();
+        assert_eq!(usize::from(x), value_col + 3);
+
+        // A roomy terminal keeps the floating modal.
+        app.form = None;
+        app.confirm = Some(ConfirmKind::Delete {
+            dependents: Vec::new(),
+        });
+        assert!(draw(&app, 80, 10)
+            .lines()
+            .next()
+            .unwrap()
+            .starts_with("┌delete host?"));
+        assert!(!draw(&app, 80, 30)
+            .lines()
+            .next()
+            .unwrap()
+            .contains("delete host?"));
+    }
+
+    #[test]
+    fn undo_past_reload_lists_what_it_reverts() {
+        let mut app = test_app();
+        assert!(!draw(&app, 120, 30).contains("(gen "));
+        app.generation = 2;
+        app.mode = Mode::Confirm;
+        app.confirm = Some(ConfirmKind::UndoPastReload {
+            changes: vec!["staging-db: reverts user".into()],
+        });
+        let screen = draw(&app, 120, 30);
+        assert!(screen.contains("undo past reload?"));
+        assert!(screen.contains("staging-db: reverts user"));
+        assert!(screen.contains("c: forget older undo steps"));
+        assert!(screen.contains("(gen 2)"));
+    }
+
+    #[test]
+    fn status_line_counts_the_undo_steps() {
+        let mut app = test_app();
+        assert!(draw(&app, 160, 30).contains("undo: 0 available"));
+        crate::app::tests::select(&mut app, "prod-web");
+        crate::app::tests::press(&mut app, 'y');
+        assert!(draw(&app, 160, 30).contains("undo: 1 available"));
+    }
+
+    #[test]
+    fn deleting_a_bastion_lists_the_hosts_behind_it() {
+        let mut app = test_app();
+        crate::app::tests::select(&mut app, "jump-eu");
+        crate::app::tests::press(&mut app, 'd');
+        let screen = draw(&app, 100, 30);
+        assert!(
+            screen.contains("It is the bastion of these hosts"),
+            "{screen}"
+        );
+        assert!(screen.contains("  staging-db"));
+        assert!(screen.contains("y/Enter: delete and clear their bastion"));
+    }
+
+    #[test]
+    fn bulk_edit_previews_before_and_after() {
+        let mut app = test_app();
+        app.marked = ["staging-db".to_string()].into();
+        assert!(draw(&app, 100, 30).contains("● staging-db"));
+
+        app.mode = Mode::Bulk;
+        app.bulk = Some(crate::bulk::BulkEdit {
+            field: 1,
+            value: "2200".into(),
+        });
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("bulk edit (1 marked)"));
+        assert!(screen.contains("staging-db  2222 → 2200"));
+        assert!(screen.contains("Enter: apply to 1 host(s)"));
+    }
+
+    #[test]
+    fn jump_hosts_are_listed_in_their_own_section() {
+        let mut app = test_app();
+        app.config.hosts[2].role = Some(crate::model::HostRole::Bastion);
+        app.set_filter("");
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("▸ jump hosts"));
+        assert!(screen.contains("(1) J to show"));
+        assert!(!screen.contains("⇢ jump-eu"));
+
+        app.jump_hosts_expanded = true;
+        app.set_filter("");
+        select(&mut app, "jump-eu");
+        let screen = draw(&app, 100, 30);
+        let rows: Vec<&str> = screen.lines().collect();
+        let divider = rows
+            .iter()
+            .position(|row| row.contains("▾ jump hosts"))
+            .unwrap();
+        assert!(rows[divider - 1].contains("staging-db"));
+        // The selection marks the host below the divider, not the divider.
+        assert!(rows[divider + 1].contains(&format!("{LIST_HIGHLIGHT}⇢ jump-eu")));
+    }
+
+    #[test]
+    fn full_command_puts_each_argument_on_a_line() {
+        let mut app = test_app();
+        crate::app::tests::select(&mut app, "staging-db");
+        app.mode = Mode::FullCommand;
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("command for staging-db"), "{screen}");
+        assert!(screen.contains("  -J ops@52.17.9.3 \\"), "{screen}");
+        assert!(screen.contains("  -p 2222 \\"), "{screen}");
+        let target = screen
+            .lines()
+            .find(|line| line.contains("db@35.12.2.4"))
+            .unwrap();
+        assert!(!target.contains('\\'), "{target}");
+    }
+
+    #[test]
+    fn modals_are_laid_out_again_after_a_resize() {
+        let mut app = test_app();
+        app.show_help = true;
+        let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+        terminal.draw(|f| render(f, &app)).unwrap();
+        terminal.backend_mut().resize(30, 10);
+        let frame = terminal.draw(|f| render(f, &app)).unwrap();
+        assert_eq!(frame.area, Rect::new(0, 0, 30, 10));
+        // Too small for the centered box, so help takes the whole frame.
+        let buffer = terminal.backend().buffer();
+        assert_eq!(buffer.get(0, 0).symbol(), "┌");
+        assert_eq!(buffer.get(29, 9).symbol(), "┘");
+    }
+
+    #[test]
+    fn pinned_hosts_are_starred_at_the_top() {
+        let mut app = test_app();
+        app.config.hosts[1].pinned = true;
+        app.set_filter("");
+        let screen = draw(&app, 100, 30);
+        let rows: Vec<&str> = screen.lines().collect();
+        let starred = rows.iter().position(|row| row.contains("★ staging-db"));
+        let prod = rows.iter().position(|row| row.contains("prod-web"));
+        assert!(starred.unwrap() < prod.unwrap(), "{screen}");
+    }
+
+    #[test]
+    fn workset_hosts_are_flagged_and_the_view_is_named() {
+        let mut app = test_app();
+        app.workset.insert("jump-eu".into());
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("⚑ jump-eu"));
+        assert!(!screen.contains("⚑ prod-web"));
+
+        app.workset_only = true;
+        app.set_filter("");
+        let screen = draw(&app, 100, 30);
+        assert!(screen.contains("search · names+targets+tags · workset (1)"));
+        assert!(!screen.contains("prod-web"));
+    }
+
+    #[test]
+    fn overlay_hosts_are_badged_and_explained() {
+        let mut app = test_app();
+        app.config.hosts[1].overlay = Some("project.toml".into());
+        select(&mut app, "staging-db");
+        let screen = draw(&app, 120, 30);
+        assert!(screen.contains("◇ staging-db"));
+        assert!(!screen.contains("◇ prod-web"));
+        assert!(screen.contains("overlay project.toml (this session only, not saved)"));
+    }
+
+    #[test]
+    fn long_searches_keep_their_end_and_the_cursor_in_view() {
+        use ratatui::backend::Backend;
+
+        let mut app = test_app();
+        press(&mut app, '/');
+        let filter = format!("{}end", "x".repeat(150));
+        for c in filter.chars() {
+            press(&mut app, c);
+        }
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|f| render(f, &app)).unwrap();
+        let (x, y) = terminal.backend_mut().get_cursor().unwrap();
+        let screen = draw(&app, 100, 30);
+        let row: Vec<char> = screen
+            .lines()
+            .nth(usize::from(y))
+            .unwrap()
+            .chars()
+            .collect();
+        assert!(row.contains(&'…'));
+        let before: String = row[usize::from(x) - 3..usize::from(x)].iter().collect();
+        assert_eq!(before, "end");
+    }
+
+    #[test]
+    fn launcher_shows_only_search_and_results() {
+        let mut app = test_app();
+        app.mode = Mode::Launcher;
+        app.filter = "jump".into();
+        app.filtered_indices = vec![2];
+        let screen = draw(&app, 80, 12);
+        assert!(screen.contains("> jump"));
+        assert!(screen.contains("▸ jump-eu  ops@52.17.9.3"));
+        assert!(screen.contains("Esc quit"));
+        assert!(!screen.contains("details"));
+        assert!(!screen.contains("dry-run"));
+    }
+
+    #[test]
+    fn stored_remote_commands_are_flagged_and_confirmed() {
+        let mut app = test_app();
+        app.config.hosts[0].remote_command = Some("reboot".into());
+        let screen = draw(&app, 120, 30);
+        assert!(screen.contains("» prod-web"));
+        assert!(!screen.contains("» staging-db"));
+
+        app.mode = Mode::Confirm;
+        app.confirm = Some(ConfirmKind::RemoteCommand {
+            command: "reboot".into(),
+        });
+        let screen = draw(&app, 120, 30);
+        assert!(screen.contains("will run: reboot"));
+        assert!(screen.contains("Enter to proceed, e to connect without it, Esc to cancel"));
+    }
+
+    #[test]
+    fn connect_confirm_shows_the_preflight_checklist_for_protected_hosts() {
+        let mut app = test_app();
+        app.mode = Mode::Confirm;
+        app.confirm = Some(ConfirmKind::Connect {
+            extra_cmd: String::new(),
+            request_tty: None,
+            freeze_armed: false,
+        });
+        assert!(!draw(&app, 120, 40).contains("Pre-flight"));
+
+        app.config.hosts[0].protected = true;
+        app.config.hosts[0].key_paths = vec!["/nonexistent/prod_key".into()];
+        app.probes.record(
+            "52.14.33.10",
+            22,
+            Instant::now(),
+            PortProbe::Open(Duration::ZERO),
+        );
+        let screen = draw(&app, 120, 40);
+        assert!(screen.contains("Pre-flight (protected host):"), "{screen}");
+        let line = |name: &str| {
+            screen
+                .lines()
+                .find(|l| l.contains(&format!(" {name} ")))
+                .unwrap_or_else(|| panic!("no {name} line in\n{screen}"))
+                .to_string()
+        };
+        let mark = |pass: &str, ascii: &str| {
+            if unicode_glyphs() {
+                pass.to_string()
+            } else {
+                ascii.to_string()
+            }
+        };
+        let bastion = line("bastion");
+        assert!(
+            bastion.contains(&format!("{} bastion  direct connection", mark("✓", "+"))),
+            "{bastion}"
+        );
+        assert!(
+            line("keys").contains(&format!(
+                "{} keys     /nonexistent/prod_key does not exist",
+                mark("✗", "x")
+            )),
+            "{screen}"
+        );
+        assert!(line("port").contains("52.14.33.10:22 reachable (checked"));
+        assert!(line("dry_run").contains("dry-run off: this connects for real"));
+        assert!(screen.contains("Enter to connect"), "{screen}");
+    }
+
+    #[test]
+    fn rules_view_lists_what_each_rule_matches() {
+        use crate::autotag::AutoTagRule;
+
+        let mut app = test_app();
+        app.mode = Mode::Rules;
+        assert!(draw(&app, 120, 30).contains("No [[auto_tags]] rules"));
+        app.config.auto_tags = vec![
+            AutoTagRule {
+                address: Some("52.".into()),
+                tags: vec!["aws".into()],
+                ..Default::default()
+            },
+            AutoTagRule {
+                name: Some("nothing-*".into()),
+                tags: vec!["x".into()],
+                ..Default::default()
+            },
+            AutoTagRule {
+                address_regex: Some("(".into()),
+                tags: vec!["x".into()],
+                ..Default::default()
+            },
+        ];
+        let screen = draw(&app, 120, 30);
+        assert!(screen.contains("1. address contains 52. → aws"));
+        assert!(screen.contains("matches 2: prod-web, jump-eu"));
+        assert!(screen.contains("matches no host"));
+        assert!(screen.contains("invalid: address_regex '('"));
+    }
+
+    #[test]
+    fn details_show_the_hosts_local_time() {
+        let mut app = test_app();
+        app.set_clock(Some(
+            std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_729_990_800),
+        ));
+        app.config.hosts[0].timezone = Some("Asia/Tokyo".into());
+        app.config.hosts[0].quiet_hours = Some("Sun".into());
+        let screen = draw(&app, 120, 30);
+        assert!(
+            screen.contains("local time: 10:00 (Asia/Tokyo)"),
+            "{screen}"
+        );
+        assert!(screen.contains("quiet hours: Sun (now)"));
+
+        app.config.hosts[0].timezone = Some("Mars/Olympus".into());
+        assert!(draw(&app, 120, 30).contains("timezone: invalid: unknown timezone"));
+    }
+
+    #[test]
+    fn details_show_how_often_the_host_was_connected() {
+        let mut app = test_app();
+        assert!(draw(&app, 120, 30).contains("connections: 0"));
+        app.config.hosts[0].connect_count = 12;
+        assert!(draw(&app, 120, 30).contains("connections: 12"));
+    }
+
+    #[test]
+    fn multiline_descriptions_are_a_block_in_the_details() {
+        let mut app = test_app();
+        app.config.hosts[0].description = Some("Deploy:\n  make ship\nOn call: ana".into());
+        let out = draw(&app, 120, 40);
+        let rows: Vec<&str> = out.lines().collect();
+        let deploy = rows.iter().position(|r| r.contains("Deploy:")).unwrap();
+        assert!(rows[deploy + 1].contains("  make ship"));
+        assert!(rows[deploy + 2].contains("On call: ana"));
+        assert!(!rows[deploy].contains("prod-web"));
+    }
+
+    #[test]
+    fn page_down_stops_at_the_end_of_the_details() {
+        use crossterm::event::{Event, KeyCode, KeyEvent};
+
+        let mut app = test_app();
+        let notes: Vec<String> = (1..=40).map(|n| format!("note {n}")).collect();
+        app.config.hosts[0].description = Some(notes.join("\n"));
+        let key = |code| Event::Key(KeyEvent::from(code));
+        draw(&app, 120, 30);
+        for _ in 0..50 {
+            app.on_event(key(KeyCode::PageDown)).unwrap();
+        }
+        let end = app.details_offset();
+        assert!(end > 0);
+        // The last line of the pane is on screen, not a blank box.
+        assert!(draw(&app, 120, 30).contains("connections: 0"));
+        app.on_event(key(KeyCode::PageUp)).unwrap();
+        assert_eq!(app.details_offset(), end - 5);
+        assert!(!draw(&app, 120, 30).contains("connections: 0"));
+    }
+
+    #[test]
+    fn details_show_resolved_addresses_and_family_warnings() {
+        let mut app = test_app();
+        app.config.hosts[0].address = "db.example.com".into();
+        app.config.hosts[0].ip_family = Some(crate::model::IpFamily::V6);
+        app.resolver.record(
+            "db.example.com",
+            &["2001:db8::7".parse().unwrap(), "192.0.2.7".parse().unwrap()],
+        );
+        let screen = draw(&app, 120, 30);
+        assert!(
+            screen.contains("resolves to: 192.0.2.7, 2001:db8::7"),
+            "{screen}"
+        );
+        assert!(screen.contains("ip family: IPv6 only (-6)"));
+
+        app.resolver
+            .record("db.example.com", &["192.0.2.7".parse().unwrap()]);
+        let screen = draw(&app, 120, 30);
+        assert!(screen.contains("has no AAAA (IPv6) record"), "{screen}");
+    }
+
+    #[test]
+    fn privacy_mode_masks_the_list_details_and_previews() {
+        let mut app = test_app();
+        app.config.privacy_pseudonyms = true;
+        app.privacy = true;
+        app.show_preview_bar = true;
+        app.set_filter("");
+        let screen = draw(&app, 140, 30);
+        let name = crate::privacy::pseudonym("prod-web");
+        assert!(screen.contains("PRIVATE"));
+        assert!(screen.contains(&name), "{screen}");
+        assert!(screen.contains("deploy@52.x.x.x"));
+        assert!(screen.contains("keys: prod_id_ed25519"));
+        assert!(screen.contains("▸ (masked) ssh"));
+        for real in ["52.14.33.10", "prod-web", "~/.ssh/", "35.12.2.4"] {
+            assert!(!screen.contains(real), "{real} shown:\n{screen}");
+        }
+
+        app.mode = Mode::Confirm;
+        app.confirm = Some(ConfirmKind::Connect {
+            extra_cmd: String::new(),
+            request_tty: None,
+            freeze_armed: false,
+        });
+        let screen = draw(&app, 140, 30);
+        assert!(screen.contains("privacy mode: the command run uses the real values"));
+        assert!(!screen.contains("52.14.33.10"));
+    }
+
+    #[test]
+    fn preview_bar_follows_selection_and_collapses_when_narrow() {
+        let mut app = test_app();
+        app.show_preview_bar = true;
+        let screen = draw(&app, 120, 30);
+        assert!(screen.contains("▸ ssh"));
+        assert!(screen.contains("deploy@52.14.33.10"));
+
+        app.selected = 1;
+        let screen = draw(&app, 120, 30);
+        let bar = screen.lines().find(|l| l.contains("▸ ssh")).unwrap();
+        assert!(!bar.contains("deploy@52.14.33.10"));
+
+        assert!(!draw(&app, 40, 30).contains("▸ ssh"));
+        app.show_preview_bar = false;
+        assert!(!draw(&app, 120, 30).contains("▸ ssh"));
+    }
+}
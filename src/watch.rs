@@ -0,0 +1,337 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! Watch mode: re-runs a remote command on an interval and keeps the most
+//! recent outputs around, for "is the deploy done yet" checks.
+
+use std::collections::VecDeque;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::debuglog;
+use crate::hostkey::KnownHostsFile;
+
+/// How many past runs stay available for scroll-back.
+pub const WATCH_HISTORY: usize = 20;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunOutput {
+    /// `None` when the process was killed by a signal.
+    pub exit: Option<i32>,
+    pub output: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct WatchRun {
+    pub started: SystemTime,
+    pub took: Duration,
+    /// `Err` when the command could not be started at all.
+    pub result: Result<RunOutput, String>,
+}
+
+/// Executes one run; swapped out in tests so no real ssh is needed.
+pub type Runner = Arc<dyn Fn() -> Result<RunOutput, String> + Send + Sync>;
+
+/// Runs `program args…` with stdin closed and captures stdout and stderr.
+/// A pinned known_hosts file is kept for as long as the runner lives.
+pub fn command_runner(
+    program: String,
+    args: Vec<String>,
+    known_hosts: Option<KnownHostsFile>,
+) -> Runner {
+    Arc::new(move || {
+        let _ = &known_hosts;
+        debuglog::spawn(
+            "watch",
+            &[std::slice::from_ref(&program), &args[..]].concat(),
+        );
+        let out = Command::new(&program)
+            .args(&args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|err| format!("failed to start {program}: {err}"))?;
+        let mut output = String::from_utf8_lossy(&out.stdout).into_owned();
+        output.push_str(&String::from_utf8_lossy(&out.stderr));
+        Ok(RunOutput {
+            exit: out.status.code(),
+            output,
+        })
+    })
+}
+
+/// Fixed-size history of runs, newest first.
+pub struct OutputRing {
+    runs: VecDeque<WatchRun>,
+    cap: usize,
+}
+
+impl OutputRing {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            runs: VecDeque::with_capacity(cap),
+            cap: cap.max(1),
+        }
+    }
+
+    pub fn push(&mut self, run: WatchRun) {
+        if self.runs.len() == self.cap {
+            self.runs.pop_back();
+        }
+        self.runs.push_front(run);
+    }
+
+    /// `back` counts from the newest run (0).
+    pub fn get(&self, back: usize) -> Option<&WatchRun> {
+        self.runs.get(back)
+    }
+
+    pub fn len(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+}
+
+pub struct Watcher {
+    /// What is being watched, e.g. `deploy@web: systemctl is-active app`.
+    pub label: String,
+    pub interval: Duration,
+    /// Runs that were due while the previous one was still going.
+    pub skipped: usize,
+    /// Scroll-back position; 0 shows the newest run.
+    pub offset: usize,
+    runner: Runner,
+    ring: OutputRing,
+    running: bool,
+    last_start: Option<Instant>,
+    tx: Sender<WatchRun>,
+    rx: Receiver<WatchRun>,
+}
+
+impl Watcher {
+    pub fn new(label: String, interval: Duration, runner: Runner) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            label,
+            interval,
+            skipped: 0,
+            offset: 0,
+            runner,
+            ring: OutputRing::new(WATCH_HISTORY),
+            running: false,
+            last_start: None,
+            tx,
+            rx,
+        }
+    }
+
+    /// Collects finished runs and starts the next one when it is due. A run
+    /// that comes due while the previous one is still going is skipped.
+    pub fn tick(&mut self, now: Instant) {
+        while let Ok(run) = self.rx.try_recv() {
+            self.running = false;
+            self.ring.push(run);
+            if self.offset > 0 {
+                // Stay on the run being looked at.
+                self.offset = (self.offset + 1).min(self.ring.len() - 1);
+            }
+        }
+        let due = self
+            .last_start
+            .is_none_or(|last| now.duration_since(last) >= self.interval);
+        if !due {
+            return;
+        }
+        self.last_start = Some(now);
+        if self.running {
+            self.skipped += 1;
+            return;
+        }
+        self.running = true;
+        let runner = self.runner.clone();
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            debuglog::debug("task", "watch run started", &[]);
+            let started = SystemTime::now();
+            let clock = Instant::now();
+            let result = runner();
+            debuglog::debug(
+                "task",
+                "watch run finished",
+                &[
+                    ("ms", &clock.elapsed().as_millis()),
+                    ("ok", &result.is_ok()),
+                ],
+            );
+            let _ = tx.send(WatchRun {
+                started,
+                took: clock.elapsed(),
+                result,
+            });
+        });
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn current(&self) -> Option<&WatchRun> {
+        self.ring.get(self.offset)
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn older(&mut self) {
+        if self.offset + 1 < self.ring.len() {
+            self.offset += 1;
+        }
+    }
+
+    pub fn newer(&mut self) {
+        self.offset = self.offset.saturating_sub(1);
+    }
+}
+
+/// `HH:MM:SS` in UTC; good enough to tell runs apart without a tz database.
+pub fn clock(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+        % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    fn run(output: &str) -> WatchRun {
+        WatchRun {
+            started: UNIX_EPOCH,
+            took: Duration::ZERO,
+            result: Ok(RunOutput {
+                exit: Some(0),
+                output: output.into(),
+            }),
+        }
+    }
+
+    fn output(run: Option<&WatchRun>) -> String {
+        run.unwrap().result.clone().unwrap().output
+    }
+
+    #[test]
+    fn ring_keeps_newest_runs_first() {
+        let mut ring = OutputRing::new(3);
+        for n in 1..=5 {
+            ring.push(run(&n.to_string()));
+        }
+        assert_eq!(ring.len(), 3);
+        assert_eq!(output(ring.get(0)), "5");
+        assert_eq!(output(ring.get(2)), "3");
+        assert!(ring.get(3).is_none());
+    }
+
+    fn wait_for(watcher: &mut Watcher, now: Instant, runs: usize) {
+        for _ in 0..200 {
+            watcher.tick(now);
+            if watcher.history_len() >= runs {
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("runner never finished");
+    }
+
+    #[test]
+    fn scheduler_runs_on_interval_and_skips_overlaps() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let gate = Arc::new(Mutex::new(()));
+        let held = gate.lock().unwrap();
+        let runner: Runner = {
+            let calls = calls.clone();
+            let gate = gate.clone();
+            Arc::new(move || {
+                let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                let _wait = gate.lock().unwrap();
+                Ok(RunOutput {
+                    exit: Some(0),
+                    output: format!("run {n}"),
+                })
+            })
+        };
+        let interval = Duration::from_secs(5);
+        let mut watcher = Watcher::new("t".into(), interval, runner);
+        let t0 = Instant::now();
+
+        watcher.tick(t0);
+        assert!(watcher.is_running());
+        watcher.tick(t0 + Duration::from_secs(1));
+        watcher.tick(t0 + interval);
+        assert_eq!(watcher.skipped, 1);
+
+        drop(held);
+        wait_for(&mut watcher, t0 + interval, 1);
+        assert!(!watcher.is_running());
+        assert_eq!(output(watcher.current()), "run 1");
+
+        // Not yet due again: the skipped slot reset the clock.
+        watcher.tick(t0 + interval + Duration::from_secs(1));
+        assert!(!watcher.is_running());
+        wait_for(&mut watcher, t0 + interval * 2, 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(output(watcher.current()), "run 2");
+    }
+
+    #[test]
+    fn failures_are_kept_and_scrollback_is_stable() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let runner: Runner = {
+            let calls = calls.clone();
+            Arc::new(move || match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Err("failed to start ssh".into()),
+                n => Ok(RunOutput {
+                    exit: Some(1),
+                    output: format!("run {}", n + 1),
+                }),
+            })
+        };
+        let interval = Duration::from_secs(1);
+        let mut watcher = Watcher::new("t".into(), interval, runner);
+        let t0 = Instant::now();
+        wait_for(&mut watcher, t0, 1);
+        assert_eq!(
+            watcher.current().unwrap().result,
+            Err("failed to start ssh".into())
+        );
+        wait_for(&mut watcher, t0 + interval, 2);
+        watcher.older();
+        assert!(watcher.current().unwrap().result.is_err());
+        // A new run arriving keeps the older entry in view.
+        wait_for(&mut watcher, t0 + interval * 2, 3);
+        assert!(watcher.current().unwrap().result.is_err());
+        watcher.newer();
+        watcher.newer();
+        assert_eq!(output(watcher.current()), "run 3");
+    }
+
+    #[test]
+    fn formats_utc_clock() {
+        assert_eq!(clock(UNIX_EPOCH + Duration::from_secs(3_725)), "01:02:05");
+    }
+}
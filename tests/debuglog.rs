@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! The debug log end to end. A test binary of its own, because it points
+//! this process's stdout and stderr at files to prove that logging never
+//! writes to the terminal the TUI owns.
+
+#![cfg(unix)]
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+
+use crossterm::event::KeyCode;
+use sshdb::app::App;
+use sshdb::config::ConfigStore;
+use sshdb::debuglog::{self, Level, LogSettings};
+use sshdb::script::Session;
+
+const CONFIG: &str = r#"version = 2
+
+[[hosts]]
+name = "prod-web"
+host = "52.14.33.10"
+user = "deploy"
+"#;
+
+/// Points `fd` at `file` until dropped.
+struct Redirect {
+    fd: i32,
+    saved: i32,
+}
+
+impl Redirect {
+    fn new(fd: i32, file: &File) -> Self {
+        let saved = unsafe { libc::dup(fd) };
+        assert!(saved >= 0);
+        assert!(unsafe { libc::dup2(file.as_raw_fd(), fd) } >= 0);
+        Self { fd, saved }
+    }
+}
+
+impl Drop for Redirect {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dup2(self.saved, self.fd);
+            libc::close(self.saved);
+        }
+    }
+}
+
+#[test]
+fn events_go_to_the_log_file_and_never_to_the_terminal() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    fs::write(&config, CONFIG).unwrap();
+    let log = dir.path().join("logs").join("sshdb.log");
+    let stdout = File::create(dir.path().join("stdout")).unwrap();
+    let stderr = File::create(dir.path().join("stderr")).unwrap();
+
+    io::stdout().flush().unwrap();
+    let redirects = [Redirect::new(1, &stdout), Redirect::new(2, &stderr)];
+    debuglog::init(&LogSettings {
+        path: log.clone(),
+        level: Level::Debug,
+        redact: true,
+    })
+    .unwrap();
+    let app = App::new(ConfigStore::at(config)).unwrap();
+    let mut s = Session::new(app, 110, 30).unwrap();
+    s.type_text("n").unwrap();
+    s.key(KeyCode::Tab).unwrap().type_text("build-01").unwrap();
+    s.key(KeyCode::BackTab)
+        .unwrap()
+        .type_text("ssh -p 2200 ops@10.0.0.5")
+        .unwrap();
+    s.key(KeyCode::Enter).unwrap();
+    debuglog::spawn(
+        "ssh",
+        &["ssh", "-p", "2200", "ops@10.0.0.5", "uptime"].map(String::from),
+    );
+    io::stdout().flush().unwrap();
+    io::stderr().flush().unwrap();
+    drop(redirects);
+
+    assert_eq!(fs::read_to_string(dir.path().join("stdout")).unwrap(), "");
+    assert_eq!(fs::read_to_string(dir.path().join("stderr")).unwrap(), "");
+
+    let text = fs::read_to_string(&log).unwrap();
+    for expected in [
+        "INFO log: logging started",
+        "redact=true",
+        "INFO config: loaded",
+        "hosts=1",
+        "DEBUG app: mode changed from=Normal to=Form key=Char('n')",
+        "INFO config: saved",
+        "hosts=2",
+        "DEBUG ssh: spawn argv=\"ssh -p 2200 <redacted> <redacted>\"",
+    ] {
+        assert!(text.contains(expected), "missing {expected:?} in\n{text}");
+    }
+    assert!(
+        !text.contains("10.0.0.5") && !text.contains("52.14.33.10"),
+        "{text}"
+    );
+    assert!(text
+        .lines()
+        .all(|line| line.len() > 24 && line.as_bytes()[23] == b'Z'));
+}
@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// SPDX-FileCopyrightText: 2024 Riccardo Iaconelli <riccardo@kde.org>
+
+//! End-to-end scripts: the real app and renderer driven by key events,
+//! asserting on the screen and on the config file left behind.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crossterm::event::KeyCode;
+use sshdb::app::App;
+use sshdb::config::ConfigStore;
+use sshdb::script::Session;
+use tempfile::TempDir;
+
+const CONFIG: &str = r#"version = 2
+
+[[hosts]]
+name = "prod-web"
+host = "52.14.33.10"
+user = "deploy"
+
+[[hosts]]
+name = "staging-db"
+host = "35.12.2.4"
+user = "db"
+port = 2222
+freeze_window = "Fri 18:00-Sun 24:00"
+"#;
+
+/// Monday 2024-03-25 00:00 UTC.
+const MONDAY: u64 = 1_711_324_800;
+
+fn session() -> (TempDir, PathBuf, Session) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    fs::write(&path, CONFIG).unwrap();
+    let mut app = App::new(ConfigStore::at(path.clone())).unwrap();
+    app.config.freeze_timezone = Some("UTC".into());
+    app.set_clock(Some(UNIX_EPOCH + Duration::from_secs(MONDAY)));
+    let session = Session::new(app, 110, 30).unwrap();
+    (dir, path, session)
+}
+
+#[test]
+fn add_host_through_the_form() {
+    let (_dir, path, mut s) = session();
+    s.type_text("n").unwrap();
+    assert!(s.screen().contains("new host"));
+    s.key(KeyCode::Tab).unwrap().type_text("build-01").unwrap();
+    s.key(KeyCode::BackTab)
+        .unwrap()
+        .type_text("ssh -p 2200 ops@10.0.0.5")
+        .unwrap();
+    assert!(s.screen().contains("10.0.0.5"));
+    s.key(KeyCode::Enter).unwrap();
+
+    assert!(
+        s.screen().contains("Added host build-01."),
+        "{}",
+        s.screen()
+    );
+    assert!(s.screen().contains("3 hosts"));
+    let saved = fs::read_to_string(&path).unwrap();
+    assert!(saved.contains("name = \"build-01\""), "{saved}");
+    assert!(saved.contains("host = \"10.0.0.5\""));
+    assert!(saved.contains("port = 2200"));
+}
+
+#[test]
+fn quick_connect_in_dry_run_adds_the_host_without_connecting() {
+    let (_dir, path, mut s) = session();
+    s.type_text("C").unwrap();
+    assert!(s.screen().contains("dry-run: on"));
+    s.type_text("g")
+        .unwrap()
+        .paste("ssh root@10.9.8.7")
+        .unwrap();
+    s.key(KeyCode::Enter).unwrap();
+
+    assert!(s.actions.is_empty(), "nothing may be launched in dry-run");
+    assert!(s.screen().contains("Dry-run"), "{}", s.screen());
+    assert!(s.screen().contains("root@10.9.8.7"));
+    assert!(fs::read_to_string(&path)
+        .unwrap()
+        .contains("host = \"10.9.8.7\""));
+}
+
+#[test]
+fn delete_asks_first() {
+    let (_dir, path, mut s) = session();
+    s.type_text("jd").unwrap();
+    assert!(s.screen().contains("delete host?"));
+    s.key(KeyCode::Esc).unwrap();
+    assert!(fs::read_to_string(&path).unwrap().contains("staging-db"));
+
+    s.type_text("d").unwrap().type_text("y").unwrap();
+    assert!(!s.screen().contains("db@35.12.2.4"), "{}", s.screen());
+    assert!(!fs::read_to_string(&path).unwrap().contains("staging-db"));
+    assert!(s.screen().contains("1 hosts"));
+}
+
+#[test]
+fn a_fixed_clock_makes_time_dependent_screens_stable() {
+    let (_dir, _path, mut s) = session();
+    s.type_text("j").unwrap();
+    assert!(s.screen().contains("Fri 18:00-Sun 24:00"));
+    assert!(!s.screen().contains("in effect now"));
+
+    // Saturday noon is inside the window.
+    let saturday = UNIX_EPOCH + Duration::from_secs(MONDAY + 5 * 86_400 + 12 * 3_600);
+    s.app.set_clock(Some(saturday));
+    s.type_text("kj").unwrap();
+    assert!(s.screen().contains("in effect now"), "{}", s.screen());
+    let before = s.screen().to_string();
+    s.type_text("kj").unwrap();
+    assert_eq!(s.screen(), before);
+}